@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use futures::channel::oneshot;
+
+use crate::{
+    commands::error, contact_card::ContactCard, doc_status::DocEvent, keyhive::MemberAccess,
+    network::PeerAddress, DocumentId, PeerId, Request, Response, TaskContext,
+};
+
+/// Ask the admins of `doc_id` on the other end of our established streams to
+/// give us `desired` access
+///
+/// If we don't know the members of the document every connected peer is
+/// asked and those who aren't admins refuse. Returns the peers who accepted
+/// the request, their decision arrives later as a
+/// `DocEvent::AccessRequestGranted` or `DocEvent::AccessRequestDenied`.
+pub(crate) async fn request_access<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    desired: MemberAccess,
+) -> Result<Vec<PeerId>, error::RequestDocAccess>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let keyhive = ctx.state().keyhive();
+    let known = keyhive.has_doc(&doc_id).await;
+    if known {
+        let ours = keyhive
+            .query_access(doc_id)
+            .await
+            .and_then(|access| access.get(&ctx.state().our_peer_id()).copied());
+        if ours >= Some(desired) {
+            return Err(error::RequestDocAccess::AlreadyHasAccess);
+        }
+    }
+    let contact_card = keyhive
+        .contact_card()
+        .await
+        .map_err(|e| error::RequestDocAccess::Signing(e.to_string()))?
+        .to_bytes();
+
+    let mut targets = Vec::new();
+    for stream in ctx.state().streams().established() {
+        if !known || keyhive.can_admin(stream.their_peer_id, &doc_id).await {
+            targets.push(stream);
+        }
+    }
+    // Recorded before asking so that a decision which arrives quickly isn't
+    // mistaken for one we didn't ask for
+    ctx.state().access_requests().insert_outgoing(
+        doc_id,
+        targets.iter().map(|stream| stream.their_peer_id).collect(),
+    );
+    let asks = targets.into_iter().map(|stream| {
+        let request = ctx.requests().request_access(
+            PeerAddress::Stream(stream.id),
+            doc_id,
+            desired,
+            contact_card.clone(),
+        );
+        async move {
+            match request.await {
+                Ok(()) => Some(stream.their_peer_id),
+                Err(e) => {
+                    tracing::debug!(err=?e, peer=%stream.their_peer_id, "access request refused");
+                    None
+                }
+            }
+        }
+    });
+    let accepted = futures::future::join_all(asks).await;
+    Ok(accepted
+        .into_iter()
+        .flatten()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect())
+}
+
+/// Handle a request from `from` for access to a document we administer
+pub(crate) async fn handle_request<R>(
+    ctx: &TaskContext<R>,
+    from: PeerId,
+    doc_id: DocumentId,
+    desired: MemberAccess,
+    contact_card: &[u8],
+) -> Response
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let requester = match ContactCard::from_bytes(contact_card) {
+        Ok(card) if card.peer_id() == from => card,
+        Ok(_) => return Response::Error("contact card is not the sender's".to_string()),
+        Err(e) => return Response::Error(e.to_string()),
+    };
+    if !ctx
+        .state()
+        .keyhive()
+        .can_admin(ctx.state().our_peer_id(), &doc_id)
+        .await
+    {
+        return Response::AuthorizationFailed;
+    }
+    tracing::debug!(%doc_id, %from, %desired, "access requested");
+    ctx.state()
+        .access_requests()
+        .insert_incoming(doc_id, from, desired);
+    ctx.io()
+        .new_doc_event(doc_id, DocEvent::AccessRequested { requester, desired });
+    Response::AskForAccess
+}
+
+/// Tell `member` their request for access to `doc_id` was granted, if they
+/// made one
+pub(crate) fn granted<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    member: PeerId,
+    access: MemberAccess,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    if ctx
+        .state()
+        .access_requests()
+        .take_incoming(doc_id, member)
+        .is_some()
+    {
+        send_decision(ctx, doc_id, member, Some(access));
+    }
+}
+
+/// Refuse the request of `requester` for access to `doc_id`
+pub(crate) fn deny<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    requester: PeerId,
+) -> Result<(), error::DenyDocAccess>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    ctx.state()
+        .access_requests()
+        .take_incoming(doc_id, requester)
+        .ok_or(error::DenyDocAccess::NoSuchRequest)?;
+    send_decision(ctx, doc_id, requester, None);
+    Ok(())
+}
+
+/// Handle an admin's decision on a request we made
+pub(crate) fn handle_decision<R>(
+    ctx: &TaskContext<R>,
+    from: PeerId,
+    doc_id: DocumentId,
+    granted: Option<MemberAccess>,
+) -> Response
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    if !ctx.state().access_requests().take_outgoing(doc_id, from) {
+        return Response::Error("no access request for this document".to_string());
+    }
+    let event = match granted {
+        Some(access) => DocEvent::AccessRequestGranted { by: from, access },
+        None => DocEvent::AccessRequestDenied { by: from },
+    };
+    ctx.io().new_doc_event(doc_id, event);
+    Response::AccessDecision
+}
+
+// Decisions are sent on every stream we have with the requester without
+// waiting for a reply, if there are none the requester never hears of it
+fn send_decision<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    requester: PeerId,
+    granted: Option<MemberAccess>,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let streams = ctx
+        .state()
+        .streams()
+        .established()
+        .into_iter()
+        .filter(|stream| stream.their_peer_id == requester)
+        .collect::<Vec<_>>();
+    if streams.is_empty() {
+        tracing::debug!(%doc_id, %requester, "requester isn't connected, dropping access decision");
+    }
+    for stream in streams {
+        let (tx, _) = oneshot::channel();
+        let request = Request::AccessDecision { doc_id, granted };
+        if let Err(e) =
+            ctx.state()
+                .streams()
+                .send_request(ctx.now().as_secs(), stream.id, request, tx)
+        {
+            tracing::warn!(err=?e, stream_id=?stream.id, "failed to send access decision");
+        }
+    }
+}
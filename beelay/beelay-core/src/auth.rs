@@ -47,8 +47,9 @@ pub(crate) fn receive_raw(
         .validate(now, Audience::peer(receiver))
         .map_err(|e| match e {
             MessageValidationError::Expired { .. } => ReceiveMessageError::Expired,
-            MessageValidationError::SubjectMismatch => ReceiveMessageError::ValidationFailed {
-                reason: "invalid audience".to_string(),
+            MessageValidationError::SubjectMismatch => ReceiveMessageError::AudienceMismatch {
+                expected: receive_audience.unwrap_or_else(|| Audience::peer(receiver)),
+                actual: message.audience,
             },
         })?;
 
@@ -107,6 +108,13 @@ pub(crate) enum ReceiveMessageError {
     ValidationFailed {
         reason: String,
     },
+    /// The message was addressed to someone else. If a receive audience was
+    /// provided it is reported as the expected audience, even though messages
+    /// addressed to our peer ID are also accepted.
+    AudienceMismatch {
+        expected: Audience,
+        actual: Audience,
+    },
     Expired,
 }
 
@@ -117,9 +125,50 @@ impl std::fmt::Display for ReceiveMessageError {
                 write!(f, "invalid payload ({}) from {}", reason, sender)
             }
             Self::ValidationFailed { reason } => write!(f, "validation failed: {}", reason),
+            Self::AudienceMismatch { expected, actual } => {
+                write!(f, "expected audience {} but got {}", expected, actual)
+            }
             Self::Expired => write!(f, "message expired"),
         }
     }
 }
 
 impl std::error::Error for ReceiveMessageError {}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::{message::Message, offset_seconds::OffsetSeconds, signed::Signed};
+    use crate::{serialization::Encode, Audience, PeerId, UnixTimestamp};
+
+    #[test]
+    fn audience_mismatch_reports_expected_and_actual() {
+        let sender = SigningKey::from_bytes(&[1; 32]);
+        let receiver = PeerId::from(SigningKey::from_bytes(&[2; 32]).verifying_key());
+        let now = UnixTimestamp::now();
+        let actual = Audience::service_name("other.example.com");
+        let payload = super::send(now, OffsetSeconds(0), actual, vec![1, 2, 3]);
+        let signed = Signed::<Message> {
+            signature: sender.sign(&payload.encode()),
+            verifier: sender.verifying_key(),
+            payload,
+        };
+
+        let expected = Audience::service_name("sync.example.com");
+        let err = super::receive_raw(now, signed.clone(), &receiver, Some(expected)).unwrap_err();
+        assert!(matches!(
+            err,
+            super::ReceiveMessageError::AudienceMismatch { expected: e, actual: a }
+                if e == expected && a == actual
+        ));
+
+        // Without a receive audience we expect messages addressed to our peer ID
+        let err = super::receive_raw(now, signed, &receiver, None).unwrap_err();
+        assert!(matches!(
+            err,
+            super::ReceiveMessageError::AudienceMismatch { expected: e, actual: a }
+                if e == Audience::peer(&receiver) && a == actual
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+use keyhive_core::{cgka::operation::CgkaOperation, crypto::signed::Signed};
+
+use crate::{
+    blob::BlobHash,
+    parse::{self, Parse},
+    serialization::Encode,
+    CommitHash, DocumentId, StorageKey, TaskContext,
+};
+
+// Bundles are applied in two steps: the encrypted contents are written to a
+// blob and then the stratum pointing at the blob is written. Between the two
+// the hash of the blob is recorded here, along with the CGKA operation which
+// encrypting it produced, so an `add_bundle` which was interrupted can pick
+// the blob back up instead of encrypting and writing the whole bundle again.
+fn progress_key(doc_id: &DocumentId, start: CommitHash, end: CommitHash) -> StorageKey {
+    StorageKey::sedimentree_root(doc_id)
+        .push("pending_bundles")
+        .push(format!("{}-{}", start, end))
+}
+
+/// The blob written by an earlier attempt to add the bundle from `start` to
+/// `end` which did not finish, and the CGKA operation it was encrypted with
+pub(crate) async fn load<R>(
+    ctx: &TaskContext<R>,
+    doc_id: &DocumentId,
+    start: CommitHash,
+    end: CommitHash,
+) -> Option<(BlobHash, Option<Signed<CgkaOperation>>)>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let raw = ctx.storage().load(progress_key(doc_id, start, end)).await?;
+    let input = parse::Input::new(&raw);
+    let (_, progress) = BlobHash::parse_in_ctx("blob", input)
+        .and_then(|(input, blob)| {
+            let (input, cgka_op) = Option::<Signed<CgkaOperation>>::parse_in_ctx("cgka_op", input)?;
+            Ok((input, (blob, cgka_op)))
+        })
+        .inspect_err(|e| tracing::error!(err=?e, "failed to parse stored bundle progress"))
+        .ok()?;
+    Some(progress)
+}
+
+/// Record that the contents of the bundle from `start` to `end` have been
+/// written to `blob`, encrypted with `cgka_op`
+pub(crate) async fn record<R>(
+    ctx: &TaskContext<R>,
+    doc_id: &DocumentId,
+    start: CommitHash,
+    end: CommitHash,
+    blob: BlobHash,
+    cgka_op: Option<&Signed<CgkaOperation>>,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let mut encoded = blob.encode();
+    cgka_op.cloned().encode_into(&mut encoded);
+    ctx.storage()
+        .put(progress_key(doc_id, start, end), encoded)
+        .await;
+}
+
+/// Forget the progress of a bundle which has been applied
+pub(crate) async fn clear<R>(
+    ctx: &TaskContext<R>,
+    doc_id: &DocumentId,
+    start: CommitHash,
+    end: CommitHash,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    ctx.storage().delete(progress_key(doc_id, start, end)).await;
+}
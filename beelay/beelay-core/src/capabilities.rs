@@ -0,0 +1,336 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    auth::Signed,
+    commands::error::{self, CapabilityRejected},
+    io::IoHandle,
+    keyhive::MemberAccess,
+    parse::{self, Parse},
+    serialization::{hex, Encode},
+    DocumentId, PeerId, Request, StorageKey, TaskContext, UnixTimestamp,
+};
+
+/// The identifier of a capability, used to revoke it
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub struct CapabilityId([u8; 16]);
+
+impl std::fmt::Debug for CapabilityId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl std::fmt::Display for CapabilityId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl FromStr for CapabilityId {
+    type Err = DecodeCapability;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|e| DecodeCapability(e.to_string()))?;
+        let id = bytes
+            .try_into()
+            .map_err(|_| DecodeCapability("capability IDs are 16 bytes".to_string()))?;
+        Ok(CapabilityId(id))
+    }
+}
+
+/// What a capability grants, signed by the peer who minted it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub(crate) struct CapabilityRecord {
+    pub(crate) id: CapabilityId,
+    pub(crate) doc_id: DocumentId,
+    pub(crate) access: MemberAccess,
+    pub(crate) expires_at: UnixTimestamp,
+}
+
+/// A token which lets whoever holds it make requests about one document to
+/// the peer who minted it, without being a member of the document
+///
+/// Hand the token to the holder with `to_bytes`. The holder registers an
+/// endpoint with it using `Event::register_capability_endpoint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capability(pub(crate) Signed<CapabilityRecord>);
+
+impl Capability {
+    pub fn id(&self) -> CapabilityId {
+        self.0.payload.id
+    }
+
+    pub fn doc_id(&self) -> DocumentId {
+        self.0.payload.doc_id
+    }
+
+    pub fn access(&self) -> MemberAccess {
+        self.0.payload.access
+    }
+
+    pub fn expires_at(&self) -> UnixTimestamp {
+        self.0.payload.expires_at
+    }
+
+    /// The peer who minted the capability and will accept it
+    pub fn issued_by(&self) -> PeerId {
+        PeerId::from(self.0.verifier)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.encode()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeCapability> {
+        let (input, signed) = Signed::<CapabilityRecord>::parse(parse::Input::new(bytes))
+            .map_err(|e| DecodeCapability(e.to_string()))?;
+        if !input.is_empty() {
+            return Err(DecodeCapability(
+                "trailing bytes after capability".to_string(),
+            ));
+        }
+        Ok(Capability(signed))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("failed to parse capability: {0}")]
+pub struct DecodeCapability(String);
+
+fn capabilities_prefix() -> StorageKey {
+    StorageKey::auth().push("capabilities")
+}
+
+fn capability_key(id: CapabilityId) -> StorageKey {
+    capabilities_prefix().push(id.to_string())
+}
+
+pub(crate) async fn load(io: IoHandle) -> HashMap<CapabilityId, Signed<CapabilityRecord>> {
+    let raw = crate::task_context::Storage::new(&io)
+        .load_range(capabilities_prefix())
+        .await;
+    let mut records = HashMap::new();
+    for (key, value) in raw {
+        let Some(id) = key
+            .name()
+            .and_then(|name| CapabilityId::from_str(name).ok())
+        else {
+            tracing::warn!(?key, "failed to parse capability key");
+            continue;
+        };
+        let Ok((_, record)) = Signed::<CapabilityRecord>::parse(parse::Input::new(&value))
+            .inspect_err(|e| tracing::error!(err=?e, "failed to parse stored capability"))
+        else {
+            continue;
+        };
+        if record.payload.id != id || record.verify().is_err() {
+            tracing::error!(?key, "stored capability is invalid");
+            continue;
+        }
+        records.insert(id, record);
+    }
+    records
+}
+
+/// Mint a capability granting `access` to `doc_id` until `expires_at`
+///
+/// Only admins of the document can mint capabilities for it.
+pub(crate) async fn mint<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    access: MemberAccess,
+    expires_at: UnixTimestamp,
+) -> Result<Capability, error::MintCapability>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let keyhive = ctx.state().keyhive();
+    if !keyhive.has_doc(&doc_id).await {
+        return Err(error::MintCapability::NoSuchDocument);
+    }
+    if !keyhive.can_admin(ctx.state().our_peer_id(), &doc_id).await {
+        return Err(error::MintCapability::NotAllowed);
+    }
+    let record = CapabilityRecord {
+        id: CapabilityId(ctx.state().random_bytes()),
+        doc_id,
+        access,
+        expires_at,
+    };
+    let signed = Signed::try_sign(ctx.signer(), record)
+        .await
+        .map_err(|e| error::MintCapability::Signing(e.to_string()))?;
+    let encoded = signed.encode();
+    ctx.state().capabilities().insert(signed.clone());
+    ctx.storage()
+        .put(capability_key(signed.payload.id), encoded)
+        .await;
+    Ok(Capability(signed))
+}
+
+/// Revoke the capability `id` so that requests made with it are rejected
+///
+/// Returns false if we never minted `id` or it was already revoked.
+pub(crate) async fn revoke<R>(ctx: TaskContext<R>, id: CapabilityId) -> bool
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    if !ctx.state().capabilities().remove(&id) {
+        return false;
+    }
+    ctx.storage().delete(capability_key(id)).await;
+    true
+}
+
+/// Forget every capability minted for `doc_id`, returning the storage keys
+/// they were stored under so that they can be deleted with the document
+pub(crate) fn remove_for_doc<R>(ctx: &TaskContext<R>, doc_id: DocumentId) -> Vec<StorageKey>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    ctx.state()
+        .capabilities()
+        .remove_for_doc(&doc_id)
+        .into_iter()
+        .map(capability_key)
+        .collect()
+}
+
+/// Check that `capability` allows its holder to make `request`
+///
+/// The capability must have been minted by us and not since revoked, must
+/// not have expired and must be for the document the request is about, see
+/// `permits` for which requests each access level allows.
+pub(crate) fn check<R>(
+    ctx: &TaskContext<R>,
+    capability: &Signed<CapabilityRecord>,
+    request: &Request,
+) -> Result<(), CapabilityRejected>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    if PeerId::from(capability.verifier) != ctx.state().our_peer_id() {
+        return Err(CapabilityRejected::NotOurs);
+    }
+    if capability.verify().is_err() {
+        return Err(CapabilityRejected::BadSignature);
+    }
+    let record = &capability.payload;
+    if !ctx.state().capabilities().contains(&record.id) {
+        return Err(CapabilityRejected::Revoked);
+    }
+    if record.expires_at <= ctx.now().as_secs() {
+        return Err(CapabilityRejected::Expired);
+    }
+    permits(record, request)
+}
+
+/// Check that the access `record` grants allows `request`
+///
+/// Pull and read capabilities allow fetching the document, write and admin
+/// capabilities also allow uploading commits to it. The blobs of uploaded
+/// commits travel with them, `UploadBlob` requests aren't tied to any
+/// document and so no capability allows them.
+fn permits(record: &CapabilityRecord, request: &Request) -> Result<(), CapabilityRejected> {
+    let can_write = matches!(record.access, MemberAccess::Write | MemberAccess::Admin);
+    let doc_id = match request {
+        Request::FetchSedimentree(doc_id) | Request::FetchBlob { doc_id, .. } => *doc_id,
+        Request::UploadCommits { doc, .. } if can_write => *doc,
+        _ => return Err(CapabilityRejected::NotPermitted),
+    };
+    if doc_id != record.doc_id {
+        return Err(CapabilityRejected::WrongDocument);
+    }
+    Ok(())
+}
+
+impl Encode for CapabilityId {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+impl<'a> Parse<'a> for CapabilityId {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        let (input, id) = parse::arr::<16>(input)?;
+        Ok((input, CapabilityId(id)))
+    }
+}
+
+impl Encode for CapabilityRecord {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.id.encode_into(out);
+        self.doc_id.encode_into(out);
+        self.access.encode_into(out);
+        self.expires_at.encode_into(out);
+    }
+}
+
+impl<'a> Parse<'a> for CapabilityRecord {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        input.parse_in_ctx("CapabilityRecord", |input| {
+            let (input, id) = CapabilityId::parse(input)?;
+            let (input, doc_id) = DocumentId::parse(input)?;
+            let (input, access) = MemberAccess::parse(input)?;
+            let (input, expires_at) = UnixTimestamp::parse(input)?;
+            Ok((
+                input,
+                CapabilityRecord {
+                    id,
+                    doc_id,
+                    access,
+                    expires_at,
+                },
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{permits, CapabilityId, CapabilityRecord};
+    use crate::{
+        commands::error::CapabilityRejected, keyhive::MemberAccess, DocumentId, Request,
+        UnixTimestamp,
+    };
+
+    fn record(access: MemberAccess) -> CapabilityRecord {
+        CapabilityRecord {
+            id: CapabilityId([1; 16]),
+            doc_id: DocumentId::random(&mut rand::thread_rng()),
+            access,
+            expires_at: UnixTimestamp::now(),
+        }
+    }
+
+    #[test]
+    fn write_capabilities_only_allow_uploads_to_their_document() {
+        let write = record(MemberAccess::Write);
+        let other = DocumentId::random(&mut rand::thread_rng());
+        let upload = |doc| Request::UploadCommits {
+            doc,
+            data: Vec::new(),
+        };
+        assert_eq!(permits(&write, &upload(write.doc_id)), Ok(()));
+        assert_eq!(
+            permits(&write, &upload(other)),
+            Err(CapabilityRejected::WrongDocument)
+        );
+        assert_eq!(
+            permits(&write, &Request::UploadBlob(vec![1, 2, 3])),
+            Err(CapabilityRejected::NotPermitted)
+        );
+
+        let read = record(MemberAccess::Read);
+        assert_eq!(
+            permits(&read, &Request::FetchSedimentree(read.doc_id)),
+            Ok(())
+        );
+        assert_eq!(
+            permits(&read, &upload(read.doc_id)),
+            Err(CapabilityRejected::NotPermitted)
+        );
+    }
+}
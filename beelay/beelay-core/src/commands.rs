@@ -1,22 +1,84 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
 use crate::{
-    auth::{self, offset_seconds::OffsetSeconds, Signed},
+    auth,
     blob::BlobMeta,
-    doc_status::DocStatus,
-    network::{endpoint, EndpointResponse},
+    bundle_progress,
+    commit_meta::{self, CommitMeta},
+    doc_status::{DocStatus, StreamSyncStatus},
+    ephemeral_docs, idempotency_keys, keyhive_storage,
+    network::endpoint,
+    parse::{self, Parse},
     sedimentree::{self, CommitOrStratum, LooseCommit},
-    serialization::Encode,
     state::DocUpdateBuilder,
-    streams, Audience, BundleSpec, Commit, CommitBundle, CommitOrBundle, DocumentId,
-    OutboundRequestId, Response, StorageKey, StreamId, TaskContext,
+    streams, sync_loops, Audience, BundleSpec, Commit, CommitBundle, CommitHash, CommitOrBundle,
+    DocumentId, OutboundRequestId, PeerId, StorageKey, StreamId, TaskContext, UnixTimestampMillis,
 };
 
+mod add_commit_chunk;
+use add_commit_chunk::add_commit_chunk;
 mod add_commits;
-use add_commits::add_commits;
+mod auto_bundle;
+use add_commits::{add_commits_checked, add_commits_multi, add_commits_reporting};
+pub use add_commits::{AddedCommits, CommitsAdded};
+mod compact_doc;
+use compact_doc::compact_doc;
+pub use compact_doc::Compaction;
+mod add_bundle_checked;
+use add_bundle_checked::add_bundle_checked;
+pub use add_bundle_checked::BundleProblem;
+mod create_doc_from_bundle;
+use create_doc_from_bundle::create_doc_from_bundle;
+mod handle_request;
+use handle_request::handle_request;
+mod merge_docs;
+pub use handle_request::{HandledRequest, RequestTarget};
+use merge_docs::merge_docs;
+mod delete_doc;
+pub(crate) use delete_doc::delete_doc;
+mod delete_namespace;
+use delete_namespace::delete_namespace;
+pub(crate) use delete_namespace::resume as resume_namespace_deletions;
+mod doc_graph;
+use doc_graph::doc_graph;
+pub use doc_graph::{DocGraph, GraphBundle, GraphCommit};
+mod doc_stats;
+use doc_stats::{
+    dedup_stats, diff_docs, doc_stats, find_unreachable, has_commits, pending_orphans,
+};
+pub(crate) use doc_stats::{exceeds_size_limit, known_commits};
+pub use doc_stats::{DedupStats, DocDiff, DocStats, PendingOrphan};
+mod export_bundle;
+use export_bundle::export_bundle;
+mod export_stream;
+pub use export_stream::ExportHandle;
+use export_stream::{begin_export, next_export_chunk};
+mod discover_docs;
+use discover_docs::discover_docs;
+mod estimate_sync;
+mod fetch_with_capability;
+use estimate_sync::estimate_sync;
+pub use estimate_sync::{SyncEstimate, TransferEstimate};
+use fetch_with_capability::fetch_with_capability;
+mod request_commits;
+use request_commits::request_commits;
+mod sync_with_endpoint;
+pub use request_commits::RequestedCommits;
+use sync_with_endpoint::sync_with_endpoint;
+mod verify_doc;
+use verify_doc::verify_doc;
+mod warm_docs;
+pub use verify_doc::{DocItem, DocProblem};
+use warm_docs::warm_docs;
 mod command_id;
 pub use command_id::CommandId;
 use futures::TryStreamExt;
+use keyhive_core::event::static_event::StaticEvent;
 pub mod keyhive;
-use keyhive::KeyhiveEntityId;
+use keyhive::{KeyhiveEntityId, MemberAccess};
 
 #[derive(Debug)]
 pub(crate) enum Command {
@@ -24,55 +86,397 @@ pub(crate) enum Command {
         request: auth::Signed<auth::Message>,
         receive_audience: Option<String>,
     },
+    InspectRequest(crate::SignedMessage),
     HandleResponse {
         request_id: OutboundRequestId,
         response: auth::Signed<auth::Message>,
     },
-    CreateStream(streams::StreamDirection),
+    CreateStream {
+        direction: streams::StreamDirection,
+        // Compression codecs to offer during the handshake, in order of
+        // preference. If empty the stream is never compressed.
+        codecs: Vec<streams::StreamCodec>,
+        resume_from: Option<streams::StreamResumptionToken>,
+    },
     DisconnectStream {
         stream_id: StreamId,
     },
+    DisconnectPeer(KeyhiveEntityId),
     RegisterEndpoint(Audience),
-    UnregisterEndpoints(endpoint::EndpointId),
+    RegisterEndpointMulti(Vec<Audience>),
+    RegisterDelegatedEndpoint {
+        audience: Audience,
+        on_behalf_of: crate::keyhive::KeyhiveEntityId,
+    },
+    RegisterCapabilityEndpoint {
+        audience: Audience,
+        capability: crate::Capability,
+    },
+    FetchWithCapability(endpoint::EndpointId),
+    SyncWithEndpoint {
+        endpoint_id: endpoint::EndpointId,
+        doc_id: DocumentId,
+    },
+    UnregisterEndpoints(Vec<endpoint::EndpointId>),
     AddCommits {
         doc_id: DocumentId,
         commits: Vec<Commit>,
     },
+    AddCommitsChecked {
+        doc_id: DocumentId,
+        commits: Vec<Commit>,
+        strict: bool,
+    },
+    AddCommitsMulti {
+        batches: Vec<(DocumentId, Vec<Commit>)>,
+    },
+    AddCommitChunk {
+        doc_id: DocumentId,
+        commit: CommitHash,
+        parents: Vec<CommitHash>,
+        chunk_index: u32,
+        bytes: Vec<u8>,
+        is_last: bool,
+    },
+    DiscardCommitChunks {
+        doc_id: DocumentId,
+        commit: CommitHash,
+    },
     LoadDoc {
         doc_id: DocumentId,
         decrypt: bool,
     },
+    LoadDocAt {
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    },
+    LoadCommitsFrom {
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    },
     CreateDoc {
         initial_commit: Commit,
         other_owners: Vec<KeyhiveEntityId>,
+        idempotency_key: Option<String>,
+    },
+    CreateEphemeralDoc {
+        initial_commit: Commit,
+        other_owners: Vec<KeyhiveEntityId>,
+    },
+    CreateDocWithGrants {
+        initial_commit: Commit,
+        other_owners: Vec<KeyhiveEntityId>,
+        grants: Vec<(KeyhiveEntityId, MemberAccess)>,
+    },
+    CreateDocFromBundle {
+        bundle: CommitBundle,
+        other_owners: Vec<KeyhiveEntityId>,
     },
     AddBundle {
         doc_id: DocumentId,
         bundle: CommitBundle,
     },
+    AddBundleChecked {
+        doc_id: DocumentId,
+        bundle: CommitBundle,
+        strict: bool,
+    },
+    ExportBundle {
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+        include_keys: bool,
+    },
+    BeginExport {
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    },
+    NextExportChunk(ExportHandle),
+    DeleteDoc {
+        doc_id: DocumentId,
+    },
+    DeleteNamespace(String),
+    TombstoneDoc {
+        doc_id: DocumentId,
+    },
+    SealDoc {
+        doc_id: DocumentId,
+    },
+    MintCapability {
+        doc_id: DocumentId,
+        access: crate::keyhive::MemberAccess,
+        expires_at: crate::UnixTimestamp,
+    },
+    RevokeCapability(crate::CapabilityId),
+    CompactDoc {
+        doc_id: DocumentId,
+    },
+    VerifyDoc {
+        doc_id: DocumentId,
+    },
+    DocStats {
+        doc_id: DocumentId,
+    },
+    DedupStats,
+    DocGraph {
+        doc_id: DocumentId,
+    },
+    MergeDocs {
+        into: DocumentId,
+        from: DocumentId,
+    },
+    HasCommits {
+        doc_id: DocumentId,
+        hashes: Vec<CommitHash>,
+    },
+    PendingOrphans {
+        doc_id: DocumentId,
+    },
+    FindUnreachable {
+        doc_id: DocumentId,
+    },
+    DiffDocs {
+        a: DocumentId,
+        b: DocumentId,
+    },
+    RequestDocAccess {
+        doc_id: DocumentId,
+        desired: keyhive::MemberAccess,
+    },
+    DenyDocAccess {
+        doc_id: DocumentId,
+        requester: PeerId,
+    },
+    RequestCommits {
+        doc_id: DocumentId,
+        hashes: Vec<CommitHash>,
+    },
+    EstimateSync {
+        stream_id: StreamId,
+        doc_ids: Vec<DocumentId>,
+    },
+    DiscoverDocs(StreamId),
+    DocIsMerged(DocumentId),
+    WarmDocs(Vec<DocumentId>),
+    ListDocuments {
+        access: Option<MemberAccess>,
+    },
     Keyhive(crate::keyhive::KeyhiveCommand),
     QueryStatus(DocumentId),
+    PauseDocSync(DocumentId),
+    ResumeDocSync(DocumentId),
+    PrioritizeDocSync {
+        doc_id: DocumentId,
+        priority: crate::doc_status::SyncPriority,
+    },
+    SubscribeDoc(DocumentId),
+    UnsubscribeDoc(DocumentId),
+    SubscribeAccessChanges,
+    UnsubscribeAccessChanges,
+    SetBundlingPolicy(crate::BundlingPolicy),
+    SetDocSizeLimit(crate::DocSizeLimit),
+    StreamSyncStatus {
+        stream_id: StreamId,
+        doc_id: DocumentId,
+    },
+    StreamResumptionToken(StreamId),
+    ListStreams,
+    StreamMetrics(StreamId),
+    AllStreamMetrics,
+    StreamHandshakeError(StreamId),
+    ResyncStream(StreamId),
+    ListOutboundRequests,
+    NextTickDeadline,
+    Tick,
+    Flush,
+    SetStreamRateLimit {
+        stream_id: StreamId,
+        limit: Option<streams::StreamRateLimit>,
+    },
     Stop,
 }
 
 #[derive(Debug)]
 pub enum CommandResult {
-    AddCommits(Result<Vec<BundleSpec>, error::AddCommits>),
-    AddBundle(Result<(), error::AddBundle>),
+    AddCommits(Result<CommitsAdded, error::AddCommits>),
+    AddCommitsChecked(Result<AddedCommits, error::AddCommits>),
+    /// The outcome of adding commits to each document in an `add_commits_multi` batch
+    AddCommitsMulti(HashMap<DocumentId, Result<Vec<BundleSpec>, error::AddCommits>>),
+    /// `None` if the commit is still missing chunks, otherwise the result of
+    /// adding the reassembled commit
+    AddCommitChunk(Result<Option<Vec<BundleSpec>>, error::AddCommitChunk>),
+    /// Whether there were any chunks to discard
+    DiscardCommitChunks(bool),
+    AddBundle(Result<AddedBundle, error::AddBundle>),
+    /// The problems which were found with the bundle and skipped over, always
+    /// empty in strict mode
+    AddBundleChecked(Result<Vec<BundleProblem>, error::AddBundle>),
+    /// A bundle containing the commits reachable from the heads passed to `export_bundle`
+    ExportBundle(Result<CommitBundle, error::ExportBundle>),
+    BeginExport(Result<ExportHandle, error::BeginExport>),
+    /// The next chunk of an export, or `None` once every chunk has been returned
+    NextExportChunk(Result<Option<Vec<u8>>, error::NextExportChunk>),
+    /// The number of storage keys which were removed when deleting the document
+    DeleteDoc(usize),
+    DeleteNamespace(Result<(), error::DeleteNamespace>),
+    /// The number of storage keys which were removed when purging the
+    /// document, zero if it was already tombstoned
+    TombstoneDoc(Result<usize, error::TombstoneDoc>),
+    /// The seal of the document, which is the existing one if it was already
+    /// sealed
+    SealDoc(Result<crate::Seal, error::SealDoc>),
+    MintCapability(Result<crate::Capability, error::MintCapability>),
+    /// Whether the capability was revoked, false if it is unknown or was
+    /// already revoked
+    RevokeCapability(bool),
+    /// How much was removed from storage by `compact_doc`
+    CompactDoc(Result<Compaction, error::CompactDoc>),
+    /// The problems `verify_doc` found, empty if the document is intact
+    VerifyDoc(Result<Vec<DocProblem>, error::VerifyDoc>),
+    /// `None` if the document is unknown
+    DocStats(Option<DocStats>),
+    DedupStats(DedupStats),
+    /// `None` if the document is unknown
+    DocGraph(Option<DocGraph>),
+    /// The heads of the document which was merged into
+    MergeDocs(Result<Vec<CommitHash>, error::MergeDocs>),
+    /// Whether each hash passed to `has_commits` is stored locally, in the
+    /// same order as the hashes
+    HasCommits(Vec<bool>),
+    /// Empty if every stored commit has all of its parents
+    PendingOrphans(Vec<PendingOrphan>),
+    /// Sorted, empty if every stored commit is reachable from a head
+    FindUnreachable(Vec<CommitHash>),
+    DiffDocs(Result<DocDiff, error::DiffDocs>),
+    /// The admins who accepted the request
+    RequestDocAccess(Result<Vec<PeerId>, error::RequestDocAccess>),
+    DenyDocAccess(Result<(), error::DenyDocAccess>),
+    RequestCommits(Result<RequestedCommits, error::RequestCommits>),
+    EstimateSync(Result<SyncEstimate, error::EstimateSync>),
+    DiscoverDocs(Result<Vec<DocumentId>, error::DiscoverDocs>),
+    /// Whether the document has at most one head, `None` if none of its
+    /// commits are stored locally
+    DocIsMerged(Option<bool>),
+    /// Whether each document passed to `warm_docs` was loaded into memory
+    WarmDocs(HashMap<DocumentId, Result<(), error::WarmDoc>>),
+    ListDocuments(Vec<DocumentId>),
     CreateDoc(Result<DocumentId, error::Create>),
-    LoadDoc(Option<Vec<CommitOrBundle>>),
+    /// The new document along with the peer ID of each grantee and the
+    /// access it was granted, in the order the grants were given
+    CreateDocWithGrants(
+        Result<(DocumentId, Vec<(crate::PeerId, MemberAccess)>), error::CreateDocWithGrants>,
+    ),
+    CreateDocFromBundle(Result<DocumentId, error::CreateDocFromBundle>),
+    /// The commits and bundles of the document, each paired with the metadata
+    /// recorded when it was stored. Data stored before metadata was recorded
+    /// has no metadata.
+    LoadDoc(Option<Vec<(CommitOrBundle, Option<CommitMeta>)>>),
+    /// Returned instead of `LoadDoc` when the content of the document has
+    /// been purged with `tombstone_doc`
+    Tombstoned(crate::Tombstone),
+    /// The commits and bundles reachable from the heads passed to `load_commits_from`
+    /// As for `LoadDoc`, but only the ancestors of the heads passed to
+    /// `load_doc_at`
+    LoadDocAt(Result<Vec<(CommitOrBundle, Option<CommitMeta>)>, error::LoadDocAt>),
+    LoadCommitsFrom(Result<Vec<CommitOrBundle>, error::LoadCommitsFrom>),
     CreateStream(streams::StreamId),
     DisconnectStream,
-    HandleRequest(Result<EndpointResponse, crate::error::Stopping>),
-    HandleResponse,
+    /// The number of streams which were closed
+    DisconnectPeer(usize),
+    HandleRequest(Result<HandledRequest, Box<error::HandleRequest>>),
+    InspectRequest(crate::InspectedRequest),
+    HandleResponse(Result<(), error::HandleResponse>),
     RegisterEndpoint(endpoint::EndpointId),
-    UnregisterEndpoint,
+    RegisterEndpointMulti(Result<endpoint::EndpointId, error::RegisterEndpointMulti>),
+    RegisterDelegatedEndpoint(
+        Result<endpoint::EndpointId, crate::keyhive::error::InvalidDelegation>,
+    ),
+    FetchWithCapability(Result<(), error::FetchWithCapability>),
+    SyncWithEndpoint(Result<(), error::SyncWithEndpoint>),
+    /// The endpoints which were registered and have now been removed, any
+    /// unknown ids passed to `unregister_endpoints` are omitted
+    UnregisterEndpoints(Vec<endpoint::EndpointId>),
     Keyhive(crate::keyhive::KeyhiveCommandResult),
     QueryStatus(DocStatus),
+    /// False if syncing of the document was already paused
+    PauseDocSync(bool),
+    /// False if syncing of the document wasn't paused
+    ResumeDocSync(bool),
+    /// The priority the document had before
+    PrioritizeDocSync(crate::doc_status::SyncPriority),
+    /// False if the document was already subscribed to
+    SubscribeDoc(bool),
+    /// False if the document wasn't subscribed to
+    UnsubscribeDoc(bool),
+    /// False if we were already subscribed
+    SubscribeAccessChanges(bool),
+    /// False if we weren't subscribed
+    UnsubscribeAccessChanges(bool),
+    SetBundlingPolicy,
+    SetDocSizeLimit,
+    StreamSyncStatus(Result<StreamSyncStatus, streams::StreamError>),
+    StreamResumptionToken(Result<Option<streams::StreamResumptionToken>, streams::StreamError>),
+    /// Every stream which has been created and not yet disconnected
+    ListStreams(Vec<(StreamId, streams::StreamDirection, streams::StreamState)>),
+    StreamMetrics(Result<streams::StreamMetrics, streams::StreamError>),
+    /// The metrics of every stream which has not been disconnected
+    AllStreamMetrics(HashMap<StreamId, streams::StreamMetrics>),
+    /// Why the handshake on the stream failed, `None` unless the stream is
+    /// closing
+    StreamHandshakeError(Result<Option<streams::HandshakeError>, streams::StreamError>),
+    ResyncStream(Result<(), error::ResyncStream>),
+    /// The requests sent to endpoints which are still awaiting a response,
+    /// oldest first
+    ListOutboundRequests(Vec<crate::OutboundRequest>),
+    /// When `Event::tick` should next be called, `None` if there is no time
+    /// based work pending. The deadline may already have passed.
+    NextTickDeadline(Option<UnixTimestampMillis>),
+    /// The work done by a tick requested with `Event::tick_verbose`
+    Tick(TickSummary),
+    /// Every storage write which was outstanding when the flush was requested
+    /// has completed
+    Flush,
+    SetStreamRateLimit,
     Stop,
+    /// The command was cancelled via `Event::cancel_command` before it completed
+    Cancelled,
+}
+
+/// An intermediate progress update for a long running command
+///
+/// Currently emitted by `load_doc` and `add_commits_multi`. `total` may grow
+/// as the command discovers more work, the final update for a command always
+/// has `processed == total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandProgress {
+    pub command_id: CommandId,
+    /// The number of commits or bundles processed so far
+    pub processed: usize,
+    /// The number of commits or bundles discovered so far
+    pub total: usize,
+}
+
+/// The time based work started by a tick, see `Event::tick_verbose`
+///
+/// A tick with nothing to do reports the default, empty, summary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TickSummary {
+    /// The number of expired memberships which are being revoked
+    pub expired_memberships: usize,
+    /// Outbound endpoint requests which were given up on, these also appear
+    /// in `EventResults::timed_out_requests`
+    pub timed_out_requests: Vec<crate::OutboundRequestId>,
+    /// Idle streams on which a periodic re-sync was started
+    pub resynced_streams: Vec<StreamId>,
+    /// The number of waiting commands, such as `Event::request_commits`,
+    /// whose timeout elapsed
+    pub expired_deadlines: usize,
 }
 
-pub(super) async fn handle_command<R>(mut ctx: TaskContext<R>, command: Command) -> CommandResult
+pub(super) async fn handle_command<R>(
+    mut ctx: TaskContext<R>,
+    command_id: CommandId,
+    command: Command,
+) -> CommandResult
 where
     R: rand::Rng + rand::CryptoRng + Clone + 'static,
 {
@@ -80,58 +484,32 @@ where
         Command::HandleRequest {
             request,
             receive_audience,
-        } => {
-            let recv_aud = receive_audience.map(Audience::service_name);
-            let (request, from) = match auth::receive(
-                ctx.now().as_secs(),
-                request,
-                &ctx.state().our_peer_id(),
-                recv_aud,
-            ) {
-                Ok(authed) => (authed.content, crate::PeerId::from(authed.from)),
-                Err(e) => {
-                    tracing::debug!(err=?e, "failed to authenticate incoming message");
-                    let response = Response::AuthenticationFailed;
-                    let msg = auth::send(
-                        ctx.now().as_secs(),
-                        OffsetSeconds(0),
-                        Audience::peer(&ctx.state().our_peer_id()),
-                        response.encode(),
-                    );
-                    let signed = Signed::try_sign(ctx.signer(), msg)
-                        .await
-                        .expect("should never fail");
-                    return CommandResult::HandleRequest(Ok(EndpointResponse(signed)));
-                }
-            };
-            let result =
-                crate::request_handlers::handle_request(ctx.clone(), None, request, from).await;
-            let response = auth::send(
-                ctx.now().as_secs(),
-                OffsetSeconds(0),
-                Audience::peer(&from),
-                result.encode(),
-            );
-            let signed = Signed::try_sign(ctx.signer(), response)
-                .await
-                .expect("should never fail");
-            let response = EndpointResponse(signed);
-            CommandResult::HandleRequest(Ok(response))
-        }
+        } => CommandResult::HandleRequest(handle_request(ctx, request, receive_audience).await),
+        Command::InspectRequest(request) => CommandResult::InspectRequest(request.inspect()),
         Command::HandleResponse {
             request_id,
             response,
         } => {
-            ctx.state()
-                .endpoints()
-                .handle_response(ctx.now().as_secs(), request_id, response);
-            CommandResult::HandleResponse
+            let result =
+                ctx.state()
+                    .endpoints()
+                    .handle_response(ctx.now().as_secs(), request_id, response);
+            if let Err(e) = &result {
+                tracing::debug!(?request_id, err=%e, "ignoring response");
+            }
+            CommandResult::HandleResponse(result)
         }
-        Command::CreateStream(direction) => {
-            let stream_id = ctx
-                .state()
-                .streams()
-                .new_stream(ctx.now().as_secs(), direction.clone());
+        Command::CreateStream {
+            direction,
+            codecs,
+            resume_from,
+        } => {
+            let stream_id = ctx.state().streams().new_stream(
+                ctx.now().as_secs(),
+                direction.clone(),
+                codecs,
+                resume_from,
+            );
             CommandResult::CreateStream(stream_id)
         }
         Command::DisconnectStream { stream_id } => {
@@ -140,31 +518,212 @@ where
             //     .new_inbound_stream_event(streams::IncomingStreamEvent::Disconnect(stream_id));
             CommandResult::DisconnectStream
         }
+        Command::DisconnectPeer(peer) => CommandResult::DisconnectPeer(disconnect_peer(&ctx, peer)),
         Command::RegisterEndpoint(audience) => {
-            let endpoint_id = ctx.state().endpoints().register_endpoint(audience);
+            let endpoint_id = ctx
+                .state()
+                .endpoints()
+                .register_endpoint(nonempty::NonEmpty::new(audience));
             CommandResult::RegisterEndpoint(endpoint_id)
         }
-        Command::UnregisterEndpoints(endpoint) => {
-            ctx.state().endpoints().unregister_endpoint(endpoint);
-            CommandResult::UnregisterEndpoint
+        Command::RegisterEndpointMulti(audiences) => CommandResult::RegisterEndpointMulti(
+            nonempty::NonEmpty::from_vec(audiences)
+                .map(|audiences| ctx.state().endpoints().register_endpoint(audiences))
+                .ok_or(error::RegisterEndpointMulti::NoAudiences),
+        ),
+        Command::RegisterDelegatedEndpoint {
+            audience,
+            on_behalf_of,
+        } => CommandResult::RegisterDelegatedEndpoint(
+            register_delegated_endpoint(ctx, audience, on_behalf_of).await,
+        ),
+        Command::RegisterCapabilityEndpoint {
+            audience,
+            capability,
+        } => CommandResult::RegisterEndpoint(
+            ctx.state()
+                .endpoints()
+                .register_capability_endpoint(audience, capability),
+        ),
+        Command::FetchWithCapability(endpoint_id) => {
+            CommandResult::FetchWithCapability(fetch_with_capability(ctx, endpoint_id).await)
+        }
+        Command::SyncWithEndpoint {
+            endpoint_id,
+            doc_id,
+        } => CommandResult::SyncWithEndpoint(sync_with_endpoint(ctx, endpoint_id, doc_id).await),
+        Command::UnregisterEndpoints(endpoints) => {
+            let removed = ctx.state().endpoints().unregister_endpoints(endpoints);
+            CommandResult::UnregisterEndpoints(removed)
         }
         Command::AddCommits {
             doc_id: dag_id,
             commits,
         } => {
-            let result = add_commits(ctx, dag_id, commits).await;
+            let result = add_commits_reporting(ctx, dag_id, commits).await;
             CommandResult::AddCommits(result)
         }
-        Command::LoadDoc { doc_id, decrypt } => {
-            CommandResult::LoadDoc(load_doc_commits(&mut ctx, &doc_id, decrypt).await)
+        Command::AddCommitsChecked {
+            doc_id,
+            commits,
+            strict,
+        } => CommandResult::AddCommitsChecked(
+            add_commits_checked(ctx, doc_id, commits, strict).await,
+        ),
+        Command::AddCommitChunk {
+            doc_id,
+            commit,
+            parents,
+            chunk_index,
+            bytes,
+            is_last,
+        } => CommandResult::AddCommitChunk(
+            add_commit_chunk(ctx, doc_id, commit, parents, chunk_index, bytes, is_last).await,
+        ),
+        Command::DiscardCommitChunks { doc_id, commit } => {
+            CommandResult::DiscardCommitChunks(ctx.state().commit_chunks().discard(doc_id, commit))
+        }
+        Command::AddCommitsMulti { batches } => {
+            CommandResult::AddCommitsMulti(add_commits_multi(ctx, command_id, batches).await)
+        }
+        Command::LoadDoc { doc_id, decrypt } => match ctx.state().tombstones().get(&doc_id) {
+            Some(record) => CommandResult::Tombstoned(crate::Tombstone::from(&record)),
+            None => CommandResult::LoadDoc(
+                load_doc_commits(&mut ctx, command_id, &doc_id, decrypt).await,
+            ),
+        },
+        Command::LoadDocAt { doc_id, heads } => {
+            CommandResult::LoadDocAt(load_doc_at(&mut ctx, command_id, &doc_id, heads).await)
+        }
+        Command::LoadCommitsFrom { doc_id, heads } => {
+            CommandResult::LoadCommitsFrom(load_commits_from(&mut ctx, &doc_id, heads).await)
         }
         Command::CreateDoc {
             initial_commit,
             other_owners,
-        } => CommandResult::CreateDoc(create_doc(ctx, other_owners, initial_commit).await),
+            idempotency_key: None,
+        } => CommandResult::CreateDoc(
+            create_doc(ctx, other_owners, Vec::new(), initial_commit, false)
+                .await
+                .map(|(doc_id, _)| doc_id)
+                .map_err(|e| match e {
+                    error::CreateDocWithGrants::Create(e) => e,
+                    other => error::Create(other.to_string()),
+                }),
+        ),
+        Command::CreateDoc {
+            initial_commit,
+            other_owners,
+            idempotency_key: Some(key),
+        } => CommandResult::CreateDoc(
+            create_doc_idempotent(ctx, other_owners, initial_commit, key).await,
+        ),
+        Command::CreateEphemeralDoc {
+            initial_commit,
+            other_owners,
+        } => CommandResult::CreateDoc(
+            create_doc(ctx, other_owners, Vec::new(), initial_commit, true)
+                .await
+                .map(|(doc_id, _)| doc_id)
+                .map_err(|e| match e {
+                    error::CreateDocWithGrants::Create(e) => e,
+                    other => error::Create(other.to_string()),
+                }),
+        ),
+        Command::CreateDocWithGrants {
+            initial_commit,
+            other_owners,
+            grants,
+        } => CommandResult::CreateDocWithGrants(
+            create_doc(ctx, other_owners, grants, initial_commit, false).await,
+        ),
+        Command::CreateDocFromBundle {
+            bundle,
+            other_owners,
+        } => CommandResult::CreateDocFromBundle(
+            create_doc_from_bundle(ctx, other_owners, bundle).await,
+        ),
         Command::AddBundle { doc_id, bundle } => {
             CommandResult::AddBundle(add_bundle(ctx, doc_id, bundle).await)
         }
+        Command::AddBundleChecked {
+            doc_id,
+            bundle,
+            strict,
+        } => CommandResult::AddBundleChecked(add_bundle_checked(ctx, doc_id, bundle, strict).await),
+        Command::ExportBundle {
+            doc_id,
+            heads,
+            include_keys,
+        } => {
+            CommandResult::ExportBundle(export_bundle(&mut ctx, doc_id, heads, include_keys).await)
+        }
+        Command::BeginExport { doc_id, heads } => {
+            CommandResult::BeginExport(begin_export(ctx, doc_id, heads).await)
+        }
+        Command::NextExportChunk(handle) => {
+            CommandResult::NextExportChunk(next_export_chunk(ctx, handle).await)
+        }
+        Command::DeleteDoc { doc_id } => CommandResult::DeleteDoc(delete_doc(ctx, doc_id).await),
+        Command::DeleteNamespace(namespace) => {
+            CommandResult::DeleteNamespace(delete_namespace(ctx, namespace).await)
+        }
+        Command::TombstoneDoc { doc_id } => {
+            CommandResult::TombstoneDoc(crate::tombstones::tombstone(ctx, doc_id).await)
+        }
+        Command::SealDoc { doc_id } => {
+            CommandResult::SealDoc(crate::seals::seal(ctx, doc_id).await)
+        }
+        Command::MintCapability {
+            doc_id,
+            access,
+            expires_at,
+        } => CommandResult::MintCapability(
+            crate::capabilities::mint(ctx, doc_id, access, expires_at).await,
+        ),
+        Command::RevokeCapability(id) => {
+            CommandResult::RevokeCapability(crate::capabilities::revoke(ctx, id).await)
+        }
+        Command::CompactDoc { doc_id } => CommandResult::CompactDoc(compact_doc(ctx, doc_id).await),
+        Command::VerifyDoc { doc_id } => CommandResult::VerifyDoc(verify_doc(ctx, doc_id).await),
+        Command::DocStats { doc_id } => CommandResult::DocStats(doc_stats(ctx, doc_id).await),
+        Command::DedupStats => CommandResult::DedupStats(dedup_stats(ctx)),
+        Command::DocGraph { doc_id } => CommandResult::DocGraph(doc_graph(ctx, doc_id).await),
+        Command::MergeDocs { into, from } => {
+            CommandResult::MergeDocs(merge_docs(ctx, into, from).await)
+        }
+        Command::HasCommits { doc_id, hashes } => {
+            CommandResult::HasCommits(has_commits(ctx, doc_id, hashes))
+        }
+        Command::PendingOrphans { doc_id } => {
+            CommandResult::PendingOrphans(pending_orphans(ctx, doc_id))
+        }
+        Command::FindUnreachable { doc_id } => {
+            CommandResult::FindUnreachable(find_unreachable(ctx, doc_id))
+        }
+        Command::DiffDocs { a, b } => CommandResult::DiffDocs(diff_docs(ctx, a, b)),
+        Command::RequestDocAccess { doc_id, desired } => CommandResult::RequestDocAccess(
+            crate::access_requests::request_access(ctx, doc_id, desired).await,
+        ),
+        Command::DenyDocAccess { doc_id, requester } => {
+            CommandResult::DenyDocAccess(crate::access_requests::deny(&ctx, doc_id, requester))
+        }
+        Command::RequestCommits { doc_id, hashes } => {
+            CommandResult::RequestCommits(request_commits(ctx, doc_id, hashes).await)
+        }
+        Command::EstimateSync { stream_id, doc_ids } => {
+            CommandResult::EstimateSync(estimate_sync(ctx, stream_id, doc_ids).await)
+        }
+        Command::DiscoverDocs(stream_id) => {
+            CommandResult::DiscoverDocs(discover_docs(ctx, stream_id).await)
+        }
+        Command::DocIsMerged(doc_id) => {
+            CommandResult::DocIsMerged(ctx.state().docs().is_merged(&doc_id))
+        }
+        Command::WarmDocs(doc_ids) => CommandResult::WarmDocs(warm_docs(ctx, doc_ids).await),
+        Command::ListDocuments { access } => {
+            CommandResult::ListDocuments(list_documents(ctx, access).await)
+        }
         Command::Keyhive(keyhive_command) => {
             let result = crate::keyhive::handle_keyhive_command(ctx, keyhive_command).await;
             CommandResult::Keyhive(result)
@@ -173,6 +732,138 @@ where
             let status = ctx.state().docs().doc_status(doc_id);
             CommandResult::QueryStatus(status)
         }
+        Command::PauseDocSync(doc_id) => {
+            let paused = ctx.state().docs().pause_sync(doc_id);
+            CommandResult::PauseDocSync(paused)
+        }
+        Command::ResumeDocSync(doc_id) => {
+            let resumed = ctx.state().docs().resume_sync(doc_id);
+            if resumed {
+                // Catch up on whatever we missed whilst paused rather than
+                // waiting for the next periodic sync
+                for stream in ctx.state().streams().established() {
+                    ctx.state().streams().mark_received_sync_needed(stream.id);
+                }
+            }
+            CommandResult::ResumeDocSync(resumed)
+        }
+        Command::PrioritizeDocSync { doc_id, priority } => {
+            let previous = ctx.state().docs().set_sync_priority(doc_id, priority);
+            if priority > previous {
+                // Start syncing the document now rather than waiting for the
+                // next periodic sync
+                for stream in ctx.state().streams().established() {
+                    ctx.state().streams().mark_received_sync_needed(stream.id);
+                }
+            }
+            CommandResult::PrioritizeDocSync(previous)
+        }
+        Command::SubscribeDoc(doc_id) => {
+            CommandResult::SubscribeDoc(ctx.state().docs().subscribe(doc_id))
+        }
+        Command::UnsubscribeDoc(doc_id) => {
+            CommandResult::UnsubscribeDoc(ctx.state().docs().unsubscribe(doc_id))
+        }
+        Command::SubscribeAccessChanges => {
+            let current = ctx.state().keyhive().all_access().await;
+            CommandResult::SubscribeAccessChanges(ctx.state().access_changes().subscribe(current))
+        }
+        Command::UnsubscribeAccessChanges => {
+            CommandResult::UnsubscribeAccessChanges(ctx.state().access_changes().unsubscribe())
+        }
+        Command::SetBundlingPolicy(policy) => {
+            ctx.state().set_bundling_policy(policy);
+            CommandResult::SetBundlingPolicy
+        }
+        Command::SetDocSizeLimit(limit) => {
+            ctx.state().set_doc_size_limit(limit);
+            CommandResult::SetDocSizeLimit
+        }
+        Command::StreamSyncStatus { stream_id, doc_id } => {
+            let status =
+                ctx.state()
+                    .streams()
+                    .remote_heads(stream_id, &doc_id)
+                    .map(|remote_heads| {
+                        let Some(remote_heads) = remote_heads else {
+                            return StreamSyncStatus::Unknown;
+                        };
+                        let mut local_heads = ctx
+                            .state()
+                            .docs()
+                            .doc_status(doc_id)
+                            .local_heads
+                            .unwrap_or_default();
+                        local_heads.sort();
+                        let mut remote_heads = remote_heads;
+                        remote_heads.sort();
+                        StreamSyncStatus::Known {
+                            in_sync: local_heads == remote_heads,
+                            local_heads,
+                            remote_heads,
+                        }
+                    });
+            CommandResult::StreamSyncStatus(status)
+        }
+        Command::StreamResumptionToken(stream_id) => {
+            let token = ctx.state().streams().resumption_token(stream_id);
+            CommandResult::StreamResumptionToken(token)
+        }
+        Command::ListStreams => CommandResult::ListStreams(ctx.state().streams().list()),
+        Command::StreamMetrics(stream_id) => {
+            CommandResult::StreamMetrics(ctx.state().streams().metrics(stream_id))
+        }
+        Command::AllStreamMetrics => {
+            CommandResult::AllStreamMetrics(ctx.state().streams().all_metrics())
+        }
+        Command::StreamHandshakeError(stream_id) => {
+            CommandResult::StreamHandshakeError(ctx.state().streams().handshake_error(stream_id))
+        }
+        Command::ResyncStream(stream_id) => {
+            CommandResult::ResyncStream(resync_stream(&ctx, stream_id).await)
+        }
+        Command::ListOutboundRequests => CommandResult::ListOutboundRequests(
+            ctx.state().endpoints().outbound_requests(ctx.now()),
+        ),
+        Command::NextTickDeadline => {
+            let membership_expiry = ctx
+                .state()
+                .membership_expiries()
+                .next_expiry()
+                .map(UnixTimestampMillis::from);
+            let session_expiry = ctx.state().sessions().next_expiry();
+            let sync = sync_loops::next_sync_deadline(&ctx);
+            let request_timeout = ctx.state().endpoints().next_request_deadline();
+            let deadline = ctx.state().deadlines().next_deadline();
+            CommandResult::NextTickDeadline(
+                [
+                    membership_expiry,
+                    session_expiry,
+                    sync,
+                    request_timeout,
+                    deadline,
+                ]
+                .into_iter()
+                .flatten()
+                .min(),
+            )
+        }
+        Command::Tick => {
+            // Ticks are handled by the event loop and never dispatched here
+            tracing::warn!("tick command reached the command handler");
+            CommandResult::Tick(TickSummary::default())
+        }
+        Command::Flush => {
+            // Flushes are handled by the driver and never dispatched here
+            tracing::warn!("flush command reached the event loop");
+            CommandResult::Flush
+        }
+        Command::SetStreamRateLimit { .. } => {
+            // Rate limits are enforced by the driver before messages reach
+            // the event loop
+            tracing::warn!("set stream rate limit command reached the event loop");
+            CommandResult::SetStreamRateLimit
+        }
         Command::Stop => {
             // The actual stop is handled in `run_inner`
             ctx.stopping().await;
@@ -185,17 +876,24 @@ where
 async fn create_doc<R>(
     ctx: TaskContext<R>,
     other_owners: Vec<KeyhiveEntityId>,
+    grants: Vec<(KeyhiveEntityId, MemberAccess)>,
     initial_commit: Commit,
-) -> Result<DocumentId, error::Create>
+    ephemeral: bool,
+) -> Result<(DocumentId, Vec<(crate::PeerId, MemberAccess)>), error::CreateDocWithGrants>
 where
     R: rand::Rng + rand::CryptoRng + Clone + 'static,
 {
     let heads = nonempty::NonEmpty::new(initial_commit.hash());
-    let doc_id = ctx
+    // The grants are applied before the initial commit is encrypted so that
+    // the first key is already shared with every grantee
+    let (doc_id, granted) = ctx
         .state()
         .keyhive()
-        .create_keyhive_doc(other_owners, heads)
-        .await;
+        .create_keyhive_doc_with_grants(other_owners, grants, heads)
+        .await?;
+    if ephemeral {
+        ephemeral_docs::make_ephemeral(&ctx, doc_id).await;
+    }
 
     let (encrypted, cgka_op) = ctx
         .state()
@@ -211,32 +909,78 @@ where
     tracing::trace!(?doc_id, "creating doc");
 
     let init_blob = BlobMeta::new(&encrypted);
-    let blob_key = StorageKey::blob(init_blob.hash());
-    ctx.storage().put(blob_key, encrypted).await;
+    ctx.storage()
+        .put_doc_blob(&doc_id, init_blob.hash(), encrypted)
+        .await;
 
     let initial_loose = LooseCommit::new(initial_commit.hash(), vec![], init_blob);
     let tree = sedimentree::Sedimentree::new(Vec::new(), vec![initial_loose]);
 
     let storage = ctx.storage().doc_storage(doc_id);
-    sedimentree::storage::update(storage, None, &tree).await?;
+    sedimentree::storage::update(storage, None, &tree)
+        .await
+        .map_err(error::Create::from)?;
+    commit_meta::record_commit(
+        &ctx,
+        doc_id,
+        initial_commit.hash(),
+        ctx.state().our_peer_id(),
+    )
+    .await;
 
     ctx.state().docs().add_doc(doc_id, tree, cgka_op);
 
+    Ok((doc_id, granted))
+}
+
+// Create a document unless one has already been created with `key`, in which
+// case that document is returned and `initial_commit` is dropped
+#[tracing::instrument(skip(ctx, initial_commit, key))]
+async fn create_doc_idempotent<R>(
+    ctx: TaskContext<R>,
+    other_owners: Vec<KeyhiveEntityId>,
+    initial_commit: Commit,
+    key: String,
+) -> Result<DocumentId, error::Create>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let lock = ctx.state().idempotent_creates();
+    let _guard = lock.lock().await;
+    if let Some(doc_id) = idempotency_keys::lookup(&ctx, &key).await {
+        tracing::debug!(
+            ?doc_id,
+            "document already created with this idempotency key"
+        );
+        return Ok(doc_id);
+    }
+    let (doc_id, _) = create_doc(ctx.clone(), other_owners, Vec::new(), initial_commit, false)
+        .await
+        .map_err(|e| match e {
+            error::CreateDocWithGrants::Create(e) => e,
+            other => error::Create(other.to_string()),
+        })?;
+    idempotency_keys::record(&ctx, &key, doc_id).await;
     Ok(doc_id)
 }
 
 #[tracing::instrument(skip(ctx))]
 async fn load_doc_commits<R>(
     ctx: &mut TaskContext<R>,
+    command_id: CommandId,
     doc_id: &DocumentId,
     decrypt: bool,
-) -> Option<Vec<CommitOrBundle>>
+) -> Option<Vec<(CommitOrBundle, Option<CommitMeta>)>>
 where
     R: rand::Rng + rand::CryptoRng + Clone + 'static,
 {
     let tree = match ctx.state().docs().sedimentree(doc_id) {
         Some(tree) => tree,
         None => {
+            if ctx.state().docs().is_vanished(doc_id) {
+                tracing::debug!("document was ephemeral and did not survive a restart");
+                return None;
+            }
             if ctx.state().keyhive().has_doc(doc_id).await {
                 // It's possible that the document arrived since we started the has_doc check
                 if let Some(tree) = ctx.state().docs().sedimentree(doc_id) {
@@ -251,86 +995,410 @@ where
             }
         }
     };
+    let cached = decrypt
+        .then(|| ctx.state().warm_docs().get(doc_id, &tree))
+        .flatten();
+    let items = match cached {
+        Some(items) => {
+            tracing::trace!("loaded from warm cache");
+            items
+        }
+        None => {
+            let items = load_tree_data(ctx, doc_id, tree.clone(), decrypt, Some(command_id))
+                .await
+                .inspect_err(|e| tracing::error!(err=?e, "error loading tree data"))
+                .ok()?;
+            // Keep warmed documents warm as they change
+            if decrypt && ctx.state().warm_docs().is_warm(doc_id) {
+                warm_docs::cache_if_complete(ctx, *doc_id, tree, items.clone());
+            }
+            items
+        }
+    };
+    Some(with_meta(ctx, doc_id, items).await)
+}
+
+/// Load a document as it was when its heads were `heads`
+///
+/// Only the commits and bundles which are ancestors of `heads` are loaded. A
+/// bundle can't be split without decrypting it, so a bundle which one of the
+/// heads is in the middle of is returned whole, including the commits in it
+/// which follow the head.
+#[tracing::instrument(skip(ctx))]
+async fn load_doc_at<R>(
+    ctx: &mut TaskContext<R>,
+    command_id: CommandId,
+    doc_id: &DocumentId,
+    heads: Vec<CommitHash>,
+) -> Result<Vec<(CommitOrBundle, Option<CommitMeta>)>, error::LoadDocAt>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    if ctx.state().tombstones().contains(doc_id) {
+        return Err(error::LoadDocAt::Tombstoned);
+    }
+    let tree = match ctx.state().docs().sedimentree(doc_id) {
+        Some(tree) => tree,
+        None if ctx.state().keyhive().has_doc(doc_id).await => {
+            ctx.state().docs().sedimentree(doc_id).unwrap_or_default()
+        }
+        None => return Err(error::LoadDocAt::NoSuchDocument),
+    };
+    let subtree = tree
+        .ancestors_of(&heads)
+        .map_err(error::LoadDocAt::NotACommit)?;
+    let items = load_tree_data(ctx, doc_id, subtree, true, Some(command_id))
+        .await
+        .map_err(|e| error::LoadDocAt::Load(e.to_string()))?;
+    Ok(with_meta(ctx, doc_id, items).await)
+}
+
+// Pair each loaded commit and bundle with the metadata recorded for it
+async fn with_meta<R>(
+    ctx: &TaskContext<R>,
+    doc_id: &DocumentId,
+    items: Vec<CommitOrBundle>,
+) -> Vec<(CommitOrBundle, Option<CommitMeta>)>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let meta = commit_meta::load(ctx, doc_id).await;
+    items
+        .into_iter()
+        .map(|item| {
+            let item_meta = match &item {
+                CommitOrBundle::Commit(c) => meta.commits.get(&c.hash()),
+                CommitOrBundle::Bundle(b) => meta.bundles.get(&(b.start(), b.end())),
+            };
+            (item, item_meta.copied())
+        })
+        .collect()
+}
+
+/// Load the commits reachable from `heads` in the given document
+#[tracing::instrument(skip(ctx))]
+async fn load_commits_from<R>(
+    ctx: &mut TaskContext<R>,
+    doc_id: &DocumentId,
+    heads: Vec<CommitHash>,
+) -> Result<Vec<CommitOrBundle>, error::LoadCommitsFrom>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let tree = match ctx.state().docs().sedimentree(doc_id) {
+        Some(tree) => tree,
+        None => {
+            if ctx.state().keyhive().has_doc(doc_id).await {
+                ctx.state().docs().sedimentree(doc_id).unwrap_or_default()
+            } else {
+                return Err(error::LoadCommitsFrom::NoSuchDocument);
+            }
+        }
+    };
+    let subtree = tree
+        .ancestors_of(&heads)
+        .map_err(error::LoadCommitsFrom::MissingHead)?;
+    Ok(load_tree_data(ctx, doc_id, subtree, true, None).await?)
+}
+
+async fn load_tree_data<R>(
+    ctx: &mut TaskContext<R>,
+    doc_id: &DocumentId,
+    tree: sedimentree::Sedimentree,
+    decrypt: bool,
+    report_progress_for: Option<CommandId>,
+) -> Result<Vec<CommitOrBundle>, sedimentree::storage::LoadTreeData>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
     let tree_storage = ctx.storage().doc_storage(*doc_id);
-    let tree_data = sedimentree::storage::data(tree_storage, tree)
-        .try_filter_map(|commit_or_bundle| async {
-            let doc_id = *doc_id;
-            match commit_or_bundle {
-                (CommitOrStratum::Commit(c), data) => {
-                    let content = if decrypt {
-                        match ctx
-                            .state()
-                            .keyhive()
-                            .decrypt(doc_id, c.parents(), c.hash(), data)
-                            .await
-                        {
-                            Ok(d) => d,
-                            Err(e) => {
-                                tracing::error!(err=?e, "failed to decrypt commit");
-                                return Ok(None);
-                            }
-                        }
-                    } else {
-                        data
-                    };
-                    let commit = Commit::new(c.parents().to_vec(), content, c.hash());
-                    Ok(Some(CommitOrBundle::Commit(commit)))
-                }
-                (CommitOrStratum::Stratum(s), data) => {
-                    let content = if decrypt {
-                        match ctx
-                            .state()
-                            .keyhive()
-                            .decrypt(doc_id, &[s.start()], s.hash(), data)
-                            .await
-                        {
-                            Ok(d) => d,
-                            Err(e) => {
-                                tracing::error!(err=?e, "failed to decrypt bundle");
-                                return Ok(None);
-                            }
-                        }
-                    } else {
-                        data
-                    };
-                    let bundle = CommitBundle::builder()
-                        .start(s.start())
-                        .end(s.end())
-                        .checkpoints(s.checkpoints().to_vec())
-                        .bundled_commits(content)
-                        .build();
-                    Ok(Some(CommitOrBundle::Bundle(bundle)))
-                }
+    let total = tree.strata().count() + tree.loose_commits().count();
+    let io = ctx.io().clone();
+    let mut processed = 0;
+    sedimentree::storage::data(tree_storage, tree)
+        .inspect_ok(|_| {
+            processed += 1;
+            if let Some(command_id) = report_progress_for {
+                io.command_progress(command_id, processed, total);
             }
         })
+        .try_filter_map(|(item, data)| async {
+            Ok(decode_tree_item(ctx, *doc_id, item, data, decrypt).await)
+        })
         .try_collect::<Vec<_>>()
-        .await;
-    tree_data
-        .inspect_err(|e| tracing::error!(err=?e, "error loading tree data"))
-        .ok()
+        .await
+}
+
+/// Turn a stored commit or stratum into the form in which it is returned to
+/// the application, decrypting its contents if `decrypt` is true
+///
+/// Returns `None`, after logging, if the contents could not be decrypted.
+async fn decode_tree_item<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    item: CommitOrStratum,
+    data: Vec<u8>,
+    decrypt: bool,
+) -> Option<CommitOrBundle>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    match item {
+        CommitOrStratum::Commit(c) => {
+            let content = if decrypt {
+                match ctx
+                    .state()
+                    .keyhive()
+                    .decrypt(doc_id, c.parents(), c.hash(), data)
+                    .await
+                {
+                    Ok(d) => d,
+                    Err(e) => {
+                        tracing::error!(err=?e, "failed to decrypt commit");
+                        return None;
+                    }
+                }
+            } else {
+                data
+            };
+            let commit = Commit::new(c.parents().to_vec(), content, c.hash());
+            Some(CommitOrBundle::Commit(commit))
+        }
+        CommitOrStratum::Stratum(s) => {
+            let content = if decrypt {
+                match ctx
+                    .state()
+                    .keyhive()
+                    .decrypt(doc_id, &[s.start()], s.end(), data)
+                    .await
+                {
+                    Ok(d) => d,
+                    Err(e) => {
+                        tracing::error!(err=?e, "failed to decrypt bundle");
+                        return None;
+                    }
+                }
+            } else {
+                data
+            };
+            let bundle = CommitBundle::builder()
+                .start(s.start())
+                .end(s.end())
+                .checkpoints(s.checkpoints().to_vec())
+                .bundled_commits(content)
+                .build();
+            Some(CommitOrBundle::Bundle(bundle))
+        }
+    }
+}
+
+/// List the documents which we have stored locally
+///
+/// If `access` is provided then only documents which we have at least that level of access to in
+/// keyhive are returned.
+#[tracing::instrument(skip(ctx))]
+async fn list_documents<R>(ctx: TaskContext<R>, access: Option<MemberAccess>) -> Vec<DocumentId>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let mut docs = ctx
+        .storage()
+        .list_one_level(StorageKey::sedimentrees())
+        .await
+        .into_iter()
+        .filter_map(|key| {
+            let name = key.name()?;
+            DocumentId::from_str(name)
+                .inspect_err(|e| tracing::warn!(?key, err=?e, "failed to parse stored document id"))
+                .ok()
+        })
+        .collect::<HashSet<_>>();
+    // Documents which have been created or received but not yet written to storage
+    docs.extend(ctx.state().docs().doc_ids());
+
+    let Some(access) = access else {
+        return docs.into_iter().collect();
+    };
+    let our_peer_id = ctx.state().our_peer_id();
+    let mut accessible = Vec::new();
+    for doc_id in docs {
+        if ctx
+            .state()
+            .keyhive()
+            .can_do(our_peer_id, &doc_id, access.into())
+            .await
+        {
+            accessible.push(doc_id);
+        }
+    }
+    accessible
+}
+
+// Close every established stream whose remote authenticated as `peer`,
+// returning how many there were
+fn disconnect_peer<R>(ctx: &TaskContext<R>, peer: KeyhiveEntityId) -> usize
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let peer_id = match peer {
+        KeyhiveEntityId::Individual(card) => card.peer_id(),
+        KeyhiveEntityId::Group(group_id) => group_id,
+        KeyhiveEntityId::Doc(doc_id) => PeerId::from(*doc_id.as_key()),
+        // No stream authenticates as the public
+        KeyhiveEntityId::Public => return 0,
+    };
+    let streams = ctx
+        .state()
+        .streams()
+        .established()
+        .into_iter()
+        .filter(|stream| stream.their_peer_id == peer_id)
+        .map(|stream| stream.id)
+        .collect::<Vec<_>>();
+    for stream_id in &streams {
+        let _ = ctx.state().streams().disconnect(*stream_id);
+    }
+    tracing::debug!(%peer_id, num_streams = streams.len(), "disconnected peer");
+    streams.len()
+}
+
+// Restart the handshake on an established stream, resolving once the new
+// handshake completes
+async fn resync_stream<R>(
+    ctx: &TaskContext<R>,
+    stream_id: StreamId,
+) -> Result<(), error::ResyncStream>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let completed = ctx
+        .state()
+        .streams()
+        .restart_handshake(ctx.now().as_secs(), stream_id)
+        .map_err(|_| error::ResyncStream::NoSuchStream)?;
+    match completed.await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(reason)) => Err(error::ResyncStream::HandshakeFailed(reason)),
+        Err(_) => Err(error::ResyncStream::Closed),
+    }
+}
+
+/// Register an endpoint whose requests are made on behalf of `on_behalf_of`
+///
+/// The delegation chain is captured now, so later changes to the membership
+/// of the group are not sent with requests to the endpoint.
+async fn register_delegated_endpoint<R>(
+    ctx: TaskContext<R>,
+    audience: Audience,
+    on_behalf_of: crate::keyhive::KeyhiveEntityId,
+) -> Result<endpoint::EndpointId, crate::keyhive::error::InvalidDelegation>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let crate::keyhive::KeyhiveEntityId::Group(group_id) = on_behalf_of else {
+        return Err(crate::keyhive::error::InvalidDelegation::NotAGroup);
+    };
+    let chain = ctx.state().keyhive().delegation_chain(group_id).await?;
+    let delegation = endpoint::Delegation {
+        on_behalf_of: group_id,
+        chain,
+    };
+    Ok(ctx
+        .state()
+        .endpoints()
+        .register_delegated_endpoint(audience, delegation))
+}
+
+/// The outcome of `Event::add_bundle`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddedBundle {
+    /// Whether an earlier `add_bundle` of the same bundle was interrupted
+    /// after it had written the contents of the bundle, which were reused
+    /// rather than encrypted and written again
+    pub resumed: bool,
 }
 
 async fn add_bundle<R>(
     ctx: TaskContext<R>,
     doc_id: DocumentId,
     bundle: CommitBundle,
-) -> Result<(), error::AddBundle>
+) -> Result<AddedBundle, error::AddBundle>
 where
     R: rand::Rng + rand::CryptoRng,
 {
-    let (encrypted, cgka_op) = ctx
-        .state()
-        .keyhive()
-        .encrypt(
-            doc_id,
-            &[bundle.start()],
-            bundle.hash(),
-            bundle.bundled_commits(),
-        )
-        .await?;
+    if ctx.state().tombstones().contains(&doc_id) {
+        return Err(error::AddBundle::Tombstoned);
+    }
+    if ctx.state().seals().contains(&doc_id) {
+        return Err(error::AddBundle::Sealed);
+    }
+    if exceeds_size_limit(&ctx, doc_id, 1, bundle.bundled_commits().len() as u64) {
+        return Err(error::AddBundle::SizeLimitExceeded);
+    }
+    // Ingesting events we already have is a no-op, so the keyhive material
+    // is ingested again when resuming in case it was not all stored
+    if let Some(material) = bundle.keyhive_material() {
+        let (_, events) = Vec::<StaticEvent<CommitHash>>::parse(parse::Input::new(material))
+            .map_err(|e| error::AddBundle::KeyhiveMaterial(e.to_string()))?;
+        ctx.state()
+            .keyhive()
+            .ingest_membership_ops(events)
+            .await
+            .map_err(|e| error::AddBundle::KeyhiveMaterial(e.to_string()))?;
+    }
+    let written = match bundle_progress::load(&ctx, &doc_id, bundle.start(), bundle.end()).await {
+        Some((blob, cgka_op)) => ctx
+            .storage()
+            .load(StorageKey::blob(blob))
+            .await
+            .map(|encrypted| (encrypted, cgka_op)),
+        None => None,
+    };
+    let resumed = written.is_some();
+    let (encrypted, cgka_op) = match written {
+        Some((encrypted, cgka_op)) => {
+            tracing::debug!(%doc_id, "resuming interrupted bundle");
+            // We may have been interrupted before keyhive's copy of the
+            // operation was stored, without it the blob can't be decrypted
+            // once we are reloaded
+            if let Some(op) = &cgka_op {
+                keyhive_storage::store_event(
+                    ctx.clone(),
+                    keyhive_core::event::Event::from(std::rc::Rc::new(op.clone())),
+                    ctx.now().as_secs(),
+                )
+                .await;
+            }
+            (encrypted, cgka_op)
+        }
+        None => {
+            let (encrypted, cgka_op) = ctx
+                .state()
+                .keyhive()
+                .encrypt(
+                    doc_id,
+                    &[bundle.start()],
+                    &bundle.end(),
+                    bundle.bundled_commits(),
+                )
+                .await?;
+            let blob = BlobMeta::new(&encrypted);
+            ctx.storage()
+                .put_doc_blob(&doc_id, blob.hash(), encrypted.clone())
+                .await;
+            bundle_progress::record(
+                &ctx,
+                &doc_id,
+                bundle.start(),
+                bundle.end(),
+                blob.hash(),
+                cgka_op.as_ref(),
+            )
+            .await;
+            (encrypted, cgka_op)
+        }
+    };
     let blob = BlobMeta::new(&encrypted);
-    let blob_path = StorageKey::blob(blob.hash());
-    ctx.storage().put(blob_path, encrypted.clone()).await;
 
     let stratum = sedimentree::Stratum::new(
         bundle.start(),
@@ -342,6 +1410,7 @@ where
     sedimentree::storage::write_stratum(doc_storage, stratum)
         .await
         .map_err(|e| error::AddBundle::Storage(e.to_string()))?;
+    commit_meta::record_bundle(&ctx, doc_id, bundle.start(), bundle.end()).await;
     let mut update = DocUpdateBuilder::new(doc_id, None);
     let encrypted_bundle = CommitBundle::builder()
         .start(bundle.start())
@@ -351,13 +1420,166 @@ where
         .build();
     update.add_bundle(encrypted_bundle, cgka_op);
     ctx.state().docs().apply_doc_update(update);
-    Ok(())
+    bundle_progress::clear(&ctx, &doc_id, bundle.start(), bundle.end()).await;
+    Ok(AddedBundle { resumed })
 }
 
 pub(crate) mod error {
-    use crate::task_context;
+    use crate::{task_context, CommitHash};
 
+    pub use super::add_commit_chunk::error::AddCommitChunk;
     pub use super::add_commits::error::AddCommits;
+    pub use super::compact_doc::error::CompactDoc;
+    pub use super::create_doc_from_bundle::error::CreateDocFromBundle;
+    pub use super::delete_namespace::error::DeleteNamespace;
+    pub use super::export_bundle::error::ExportBundle;
+    pub use super::export_stream::error::{BeginExport, NextExportChunk};
+    pub use super::handle_request::error::{CapabilityRejected, HandleRequest, RequestFailure};
+    pub use super::merge_docs::error::MergeDocs;
+    pub use super::verify_doc::error::VerifyDoc;
+    pub use super::warm_docs::error::WarmDoc;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+    pub enum RegisterEndpointMulti {
+        #[error("an endpoint needs at least one audience")]
+        NoAudiences,
+    }
+
+    /// A response which didn't match any request we are waiting on
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+    pub enum HandleResponse {
+        /// No request with this ID was sent, or it completed too long ago
+        /// to be remembered
+        #[error("no such outstanding request")]
+        NoSuchRequest,
+        /// The request has already received a response
+        #[error("request already completed")]
+        AlreadyCompleted,
+        /// We gave up waiting for a response to the request, see
+        /// `Config::request_timeout`
+        #[error("request timed out")]
+        RequestTimedOut,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum RequestCommits {
+        #[error("no such document")]
+        NoSuchDocument,
+        #[error("the document has been tombstoned")]
+        Tombstoned,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum DiffDocs {
+        #[error("document a not found")]
+        NoSuchA,
+        #[error("document b not found")]
+        NoSuchB,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum EstimateSync {
+        #[error("no such established stream")]
+        NoSuchStream,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ResyncStream {
+        #[error("no such established stream")]
+        NoSuchStream,
+        #[error("the new handshake failed: {0}")]
+        HandshakeFailed(crate::streams::HandshakeError),
+        #[error("the stream was closed before the new handshake completed")]
+        Closed,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum DiscoverDocs {
+        #[error("no such established stream")]
+        NoSuchStream,
+        #[error("error asking the remote: {0}")]
+        RpcError(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum RequestDocAccess {
+        /// We already have at least the access being asked for
+        #[error("already have the requested access")]
+        AlreadyHasAccess,
+        #[error("error creating contact card: {0}")]
+        Signing(String),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+    pub enum DenyDocAccess {
+        /// The peer hasn't requested access to the document, or their
+        /// request has already been granted or denied
+        #[error("no such access request")]
+        NoSuchRequest,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum TombstoneDoc {
+        #[error("no such document")]
+        NoSuchDocument,
+        /// Only admins of a document can tombstone it
+        #[error("not allowed to tombstone this document")]
+        NotAllowed,
+        #[error("error signing tombstone: {0}")]
+        Signing(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum SealDoc {
+        #[error("no such document")]
+        NoSuchDocument,
+        /// Only admins of a document can seal it
+        #[error("not allowed to seal this document")]
+        NotAllowed,
+        #[error("error signing seal: {0}")]
+        Signing(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum MintCapability {
+        #[error("no such document")]
+        NoSuchDocument,
+        /// Only admins of a document can mint capabilities for it
+        #[error("not allowed to mint capabilities for this document")]
+        NotAllowed,
+        #[error("error signing capability: {0}")]
+        Signing(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum FetchWithCapability {
+        /// The endpoint doesn't exist or wasn't registered with a capability
+        #[error("no capability endpoint with that id")]
+        NoCapability,
+        /// The peer who minted the capability no longer accepts it
+        #[error("the capability was rejected")]
+        Rejected,
+        /// The peer who minted the capability has paused syncing the document
+        #[error("sync of the document is paused")]
+        Paused,
+        #[error("error fetching the document: {0}")]
+        Failed(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum SyncWithEndpoint {
+        /// The endpoint doesn't exist or doesn't address a peer by its key
+        #[error("no endpoint for a peer with that id")]
+        NoSuchEndpoint,
+        /// The peer refused one of the requests
+        #[error("the peer rejected the sync")]
+        Rejected,
+        /// The peer has paused syncing the document
+        #[error("sync of the document is paused")]
+        Paused,
+        #[error("error syncing the document: {0}")]
+        Failed(String),
+    }
 
     #[derive(Debug, thiserror::Error)]
     pub enum AddBundle {
@@ -365,6 +1587,16 @@ pub(crate) mod error {
         Encrypt(String),
         #[error("error writing to storage: {0}")]
         Storage(String),
+        #[error("invalid keyhive material: {0}")]
+        KeyhiveMaterial(String),
+        #[error("invalid bundle: {}", .0.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", "))]
+        Invalid(Vec<super::BundleProblem>),
+        #[error("the document has been tombstoned")]
+        Tombstoned,
+        #[error("the document has been sealed")]
+        Sealed,
+        #[error("document size limit exceeded")]
+        SizeLimitExceeded,
     }
 
     impl From<crate::state::keyhive::EncryptError> for AddBundle {
@@ -375,7 +1607,56 @@ pub(crate) mod error {
 
     #[derive(Debug, thiserror::Error)]
     #[error("error creating document: {0}")]
-    pub struct Create(String);
+    pub struct Create(pub(super) String);
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum CreateDocWithGrants {
+        #[error("member {0} not found")]
+        MemberNotFound(crate::keyhive::KeyhiveEntityId),
+        #[error("error granting access: {0}")]
+        Grant(String),
+        #[error(transparent)]
+        Create(#[from] Create),
+    }
+
+    impl From<crate::state::keyhive::GrantError> for CreateDocWithGrants {
+        fn from(err: crate::state::keyhive::GrantError) -> Self {
+            match err {
+                crate::state::keyhive::GrantError::MemberNotFound(entity) => {
+                    CreateDocWithGrants::MemberNotFound(entity)
+                }
+                crate::state::keyhive::GrantError::Grant(e) => CreateDocWithGrants::Grant(e),
+            }
+        }
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum LoadDocAt {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("the document has been tombstoned")]
+        Tombstoned,
+        #[error("{0} is not a commit in the document")]
+        NotACommit(CommitHash),
+        #[error("error loading commits: {0}")]
+        Load(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum LoadCommitsFrom {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("head {0} is not present locally")]
+        MissingHead(CommitHash),
+        #[error("error loading commits: {0}")]
+        Load(String),
+    }
+
+    impl From<crate::sedimentree::storage::LoadTreeData> for LoadCommitsFrom {
+        fn from(err: crate::sedimentree::storage::LoadTreeData) -> Self {
+            LoadCommitsFrom::Load(err.to_string())
+        }
+    }
 
     impl From<task_context::SedimentreeStorageError> for Create {
         fn from(err: task_context::SedimentreeStorageError) -> Self {
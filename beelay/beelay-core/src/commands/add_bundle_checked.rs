@@ -0,0 +1,243 @@
+use std::collections::HashSet;
+
+use keyhive_core::event::static_event::StaticEvent;
+
+use crate::{
+    parse::{self, Parse},
+    CommitBundle, CommitHash, CommitOrBundle, DocumentId, TaskContext,
+};
+
+/// Something wrong with a bundle passed to `add_bundle_checked`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BundleProblem {
+    #[error("checkpoint {0} is a boundary of the bundle")]
+    CheckpointOnBoundary(CommitHash),
+    #[error("duplicate checkpoint {0}")]
+    DuplicateCheckpoint(CommitHash),
+    /// The start or end of a bundle produced by `export_bundle` isn't one of
+    /// the commits in it
+    #[error("boundary {0} is not in the bundle")]
+    MissingBoundary(CommitHash),
+    /// A commit in a bundle produced by `export_bundle` has a parent which
+    /// isn't in the bundle
+    #[error("commit {commit} has dangling parent {parent}")]
+    DanglingParent {
+        commit: CommitHash,
+        parent: CommitHash,
+    },
+    #[error("unreadable keyhive material: {0}")]
+    UnreadableKeyhiveMaterial(String),
+    /// The keyhive event at `index` in the keyhive material is badly signed
+    #[error("keyhive event {index} has an invalid signature")]
+    BadSignature { index: usize },
+    /// The keyhive material is well formed but couldn't be applied
+    #[error("inconsistent keyhive material: {0}")]
+    InconsistentKeyhiveMaterial(String),
+    /// Neither we nor the keyhive material know about the document, so the
+    /// bundle can't be encrypted
+    #[error("document not found")]
+    UnknownDocument,
+}
+
+/// Add a bundle to a document after checking that it is well formed
+///
+/// The checkpoints of the bundle must be distinct and not be its boundaries
+/// and every event in its keyhive material must be correctly signed. If the
+/// contents of the bundle were produced by `export_bundle` the commits in it
+/// must also form a complete history from the start to the end, otherwise the
+/// contents are opaque and only the boundaries can be checked.
+///
+/// If `strict` is true any problem is returned as `error::AddBundle::Invalid`
+/// and nothing is stored. Otherwise bad checkpoints and badly signed events
+/// are dropped, the rest of the bundle is stored and the problems which were
+/// found are returned. An unknown document is an error either way.
+#[tracing::instrument(skip(ctx, bundle), fields(start=%bundle.start(), end=%bundle.end()))]
+pub(super) async fn add_bundle_checked<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    bundle: CommitBundle,
+    strict: bool,
+) -> Result<Vec<BundleProblem>, super::error::AddBundle>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let mut problems = content_problems(&bundle);
+    let events = match bundle.keyhive_material() {
+        Some(material) => {
+            let (events, event_problems) = verified_events(material);
+            problems.extend(event_problems);
+            events
+        }
+        None => Vec::new(),
+    };
+    if strict && !problems.is_empty() {
+        return Err(super::error::AddBundle::Invalid(problems));
+    }
+
+    if !events.is_empty() {
+        if let Err(e) = ctx.state().keyhive().ingest_membership_ops(events).await {
+            if strict {
+                return Err(super::error::AddBundle::KeyhiveMaterial(e.to_string()));
+            }
+            problems.push(BundleProblem::InconsistentKeyhiveMaterial(e.to_string()));
+        }
+    }
+    if !ctx.state().keyhive().has_doc(&doc_id).await {
+        problems.push(BundleProblem::UnknownDocument);
+        return Err(super::error::AddBundle::Invalid(problems));
+    }
+
+    // The keyhive material has already been applied so it is left off
+    let bundle = CommitBundle::builder()
+        .start(bundle.start())
+        .end(bundle.end())
+        .checkpoints(valid_checkpoints(&bundle))
+        .bundled_commits(bundle.into_bundled_commits())
+        .build();
+    super::add_bundle(ctx, doc_id, bundle).await?;
+    Ok(problems)
+}
+
+/// The problems with the boundaries, checkpoints and contents of `bundle`
+pub(super) fn content_problems(bundle: &CommitBundle) -> Vec<BundleProblem> {
+    let mut problems = Vec::new();
+    let mut checkpoints = HashSet::new();
+    for checkpoint in bundle.checkpoints() {
+        if *checkpoint == bundle.start() || *checkpoint == bundle.end() {
+            problems.push(BundleProblem::CheckpointOnBoundary(*checkpoint));
+        } else if !checkpoints.insert(*checkpoint) {
+            problems.push(BundleProblem::DuplicateCheckpoint(*checkpoint));
+        }
+    }
+
+    // Opaque bundle contents can't be checked any further
+    let Ok(items) = bundle.exported_items() else {
+        return problems;
+    };
+
+    // Every hash which is part of the bundle. The parents of the commits
+    // inside a nested bundle are covered by the nested bundle itself.
+    let mut known = HashSet::new();
+    for item in &items {
+        match item {
+            CommitOrBundle::Commit(commit) => {
+                known.insert(commit.hash());
+            }
+            CommitOrBundle::Bundle(nested) => {
+                known.insert(nested.start());
+                known.insert(nested.end());
+                known.extend(nested.checkpoints().iter().copied());
+            }
+        }
+    }
+    for boundary in [bundle.start(), bundle.end()] {
+        if !known.contains(&boundary) {
+            problems.push(BundleProblem::MissingBoundary(boundary));
+        }
+    }
+    for item in &items {
+        let CommitOrBundle::Commit(commit) = item else {
+            continue;
+        };
+        for parent in commit.parents().iter().filter(|p| !known.contains(p)) {
+            problems.push(BundleProblem::DanglingParent {
+                commit: commit.hash(),
+                parent: *parent,
+            });
+        }
+    }
+    problems
+}
+
+// The checkpoints of `bundle` without duplicates or boundaries
+fn valid_checkpoints(bundle: &CommitBundle) -> Vec<CommitHash> {
+    let mut seen = HashSet::new();
+    bundle
+        .checkpoints()
+        .iter()
+        .filter(|c| **c != bundle.start() && **c != bundle.end() && seen.insert(**c))
+        .copied()
+        .collect()
+}
+
+// The correctly signed events in `material`, along with the problems with the
+// rest
+fn verified_events(material: &[u8]) -> (Vec<StaticEvent<CommitHash>>, Vec<BundleProblem>) {
+    let events = match Vec::<StaticEvent<CommitHash>>::parse(parse::Input::new(material)) {
+        Ok((_, events)) => events,
+        Err(e) => {
+            return (
+                Vec::new(),
+                vec![BundleProblem::UnreadableKeyhiveMaterial(e.to_string())],
+            )
+        }
+    };
+    let mut problems = Vec::new();
+    let verified = events
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, event)| {
+            let verified = match &event {
+                StaticEvent::PrekeysExpanded(op) => op.try_verify(),
+                StaticEvent::PrekeyRotated(op) => op.try_verify(),
+                StaticEvent::CgkaOperation(op) => op.try_verify(),
+                StaticEvent::Delegated(op) => op.try_verify(),
+                StaticEvent::Revoked(op) => op.try_verify(),
+            };
+            if verified.is_err() {
+                problems.push(BundleProblem::BadSignature { index });
+                return None;
+            }
+            Some(event)
+        })
+        .collect();
+    (verified, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BundleProblem;
+    use crate::{Commit, CommitBundle, CommitHash, CommitOrBundle};
+
+    fn commit(parents: Vec<CommitHash>, hash: u8) -> Commit {
+        Commit::new(parents, vec![hash], CommitHash::from([hash; 32]))
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let root = commit(vec![], 1);
+        let child = commit(vec![root.hash()], 2);
+        let grandchild = commit(vec![child.hash()], 3);
+        let bundle = CommitBundle::builder()
+            .start(root.hash())
+            .end(grandchild.hash())
+            .checkpoints(vec![root.hash(), child.hash(), child.hash()])
+            .bundled_commits(CommitBundle::encode_exported(vec![
+                CommitOrBundle::Commit(root.clone()),
+                CommitOrBundle::Commit(grandchild.clone()),
+            ]))
+            .build();
+        assert_eq!(
+            super::content_problems(&bundle),
+            vec![
+                BundleProblem::CheckpointOnBoundary(root.hash()),
+                BundleProblem::DuplicateCheckpoint(child.hash()),
+                BundleProblem::DanglingParent {
+                    commit: grandchild.hash(),
+                    parent: child.hash(),
+                },
+            ]
+        );
+        assert_eq!(super::valid_checkpoints(&bundle), vec![child.hash()]);
+    }
+
+    #[test]
+    fn unreadable_keyhive_material_is_reported() {
+        let (events, problems) = super::verified_events(&[0xff, 0xff, 0xff]);
+        assert!(events.is_empty());
+        assert!(matches!(
+            problems.as_slice(),
+            [BundleProblem::UnreadableKeyhiveMaterial(_)]
+        ));
+    }
+}
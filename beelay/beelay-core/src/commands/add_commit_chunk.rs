@@ -0,0 +1,48 @@
+use crate::{BundleSpec, CommitHash, DocumentId, TaskContext};
+
+use super::add_commits::add_commits;
+
+/// Buffer a chunk of a commit's contents, adding the commit once it is complete
+///
+/// Returns `None` while chunks are still missing. Chunks may arrive in any
+/// order and a chunk which has already been received is ignored as long as
+/// its contents are the same.
+#[tracing::instrument(skip(ctx, parents, bytes), fields(num_bytes = bytes.len()))]
+pub(super) async fn add_commit_chunk<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    commit: CommitHash,
+    parents: Vec<CommitHash>,
+    chunk_index: u32,
+    bytes: Vec<u8>,
+    is_last: bool,
+) -> Result<Option<Vec<BundleSpec>>, error::AddCommitChunk> {
+    let Some(commit) =
+        ctx.state()
+            .commit_chunks()
+            .add(doc_id, commit, parents, chunk_index, bytes, is_last)?
+    else {
+        return Ok(None);
+    };
+    tracing::debug!("all chunks received, adding commit");
+    let bundles = add_commits(ctx, doc_id, vec![commit]).await?;
+    Ok(Some(bundles))
+}
+
+pub(crate) mod error {
+    use crate::commands::error::AddCommits;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum AddCommitChunk {
+        #[error("chunk parents differ from those of earlier chunks")]
+        ConflictingParents,
+        #[error("chunk contents differ from an earlier chunk with the same index")]
+        ConflictingChunk,
+        #[error("a different chunk was already marked as the last chunk")]
+        ConflictingLastChunk,
+        #[error("chunk index is after the last chunk")]
+        ChunkAfterLast,
+        #[error(transparent)]
+        AddCommits(#[from] AddCommits),
+    }
+}
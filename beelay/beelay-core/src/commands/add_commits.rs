@@ -1,23 +1,105 @@
+use std::collections::{HashMap, HashSet};
+
+use futures::{stream::FuturesUnordered, StreamExt};
+
 use crate::{
-    blob::BlobMeta, sedimentree, state::DocUpdateBuilder, BundleSpec, Commit, DocumentId,
-    StorageKey, TaskContext,
+    blob::BlobMeta, commit_meta, sedimentree, state::DocUpdateBuilder, BundleSpec, CommandId,
+    Commit, CommitHash, DocumentId, TaskContext,
 };
 
-#[tracing::instrument(skip(ctx, commits))]
-pub(super) async fn add_commits<R: rand::Rng + rand::CryptoRng + 'static>(
+/// The outcome of `add_commits_checked`
+#[derive(Debug, Clone)]
+pub struct AddedCommits {
+    /// Bundles which should now be created, as for `add_commits`
+    pub new_bundles: Vec<BundleSpec>,
+    /// Commits whose history is present locally
+    pub accepted: Vec<CommitHash>,
+    /// Commits which were stored but which are waiting for some of their
+    /// ancestors to arrive, either because a parent is missing or because a
+    /// parent is itself an orphan. These are reported as such until the
+    /// missing commits are added.
+    pub orphaned: Vec<CommitHash>,
+}
+
+/// The outcome of `add_commits`
+#[derive(Debug, Clone)]
+pub struct CommitsAdded {
+    /// Bundles which should now be created
+    pub new_bundles: Vec<BundleSpec>,
+    /// How many of the commits we didn't already have, re-adding commits
+    /// which are already stored counts nothing
+    pub new_commits: usize,
+    /// The heads of the document before the commits were added, sorted
+    pub previous_heads: Vec<CommitHash>,
+    /// The heads of the document afterwards, sorted
+    pub heads: Vec<CommitHash>,
+}
+
+impl CommitsAdded {
+    /// Whether adding the commits moved the heads of the document
+    pub fn heads_changed(&self) -> bool {
+        self.previous_heads != self.heads
+    }
+}
+
+pub(super) async fn add_commits<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     ctx: TaskContext<R>,
     doc_id: DocumentId,
     commits: Vec<Commit>,
 ) -> Result<Vec<BundleSpec>, error::AddCommits> {
+    add_commits_reporting(ctx, doc_id, commits)
+        .await
+        .map(|added| added.new_bundles)
+}
+
+#[tracing::instrument(skip(ctx, commits))]
+pub(super) async fn add_commits_reporting<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    commits: Vec<Commit>,
+) -> Result<CommitsAdded, error::AddCommits> {
     // TODO: This function should return an error if we are missing a chain from
     // each commit back to the last bundle boundary.
+    if ctx.state().tombstones().contains(&doc_id) {
+        return Err(error::AddCommits::Tombstoned);
+    }
+    if ctx.state().seals().contains(&doc_id) {
+        return Err(error::AddCommits::Sealed);
+    }
+    // Re-adding commits we already have doesn't grow the document
+    let known = ctx
+        .state()
+        .docs()
+        .sedimentree(&doc_id)
+        .map(|tree| super::known_commits(&tree))
+        .unwrap_or_default();
+    let mut new_hashes = HashSet::new();
+    let new_bytes = commits
+        .iter()
+        .filter(|c| !known.contains(&c.hash()) && new_hashes.insert(c.hash()))
+        .map(|c| c.contents().len() as u64)
+        .sum();
+    let new_commits = new_hashes.len();
+    if super::exceeds_size_limit(&ctx, doc_id, new_commits, new_bytes) {
+        return Err(error::AddCommits::SizeLimitExceeded);
+    }
 
     let has_commit_boundary = commits
         .iter()
         .any(|c| sedimentree::Level::from(c.hash()) <= sedimentree::TOP_STRATA_LEVEL);
 
+    // Identical contents are stored once however many documents reference
+    // them, so a stored blob only means we have the commit if this document
+    // references it
+    let doc_blobs = ctx
+        .state()
+        .docs()
+        .sedimentree(&doc_id)
+        .map(|tree| tree.blobs().map(|b| b.hash()).collect::<HashSet<_>>())
+        .unwrap_or_default();
     let save_tasks = commits.into_iter().map(|commit| {
         let ctx = ctx.clone();
+        let doc_blobs = &doc_blobs;
         async move {
             tracing::debug!(commit = %commit.hash(), "adding commit");
             let (encrypted_contents, cgka_op) = ctx
@@ -31,13 +113,13 @@ pub(super) async fn add_commits<R: rand::Rng + rand::CryptoRng + 'static>(
                 encrypted_contents.clone(),
                 commit.hash(),
             );
-            let key = StorageKey::blob(blob.hash());
-            let have_commit = ctx.storage().load(key.clone()).await.is_some();
-            if have_commit {
+            if doc_blobs.contains(&blob.hash()) {
                 tracing::debug!(hash=%commit.hash(), "commit already exists in storage");
                 return Ok::<_, error::AddCommits>(None);
             }
-            ctx.storage().put(key, encrypted_contents).await;
+            ctx.storage()
+                .put_doc_blob(&doc_id, blob.hash(), encrypted_contents)
+                .await;
 
             sedimentree::storage::write_loose_commit(
                 ctx.storage().doc_storage(doc_id),
@@ -45,6 +127,8 @@ pub(super) async fn add_commits<R: rand::Rng + rand::CryptoRng + 'static>(
             )
             .await
             .map_err(|e| error::AddCommits::Storage(e.to_string()))?;
+            commit_meta::record_commit(&ctx, doc_id, commit.hash(), ctx.state().our_peer_id())
+                .await;
 
             Ok(Some((encrypted_commit, cgka_op)))
         }
@@ -53,23 +137,154 @@ pub(super) async fn add_commits<R: rand::Rng + rand::CryptoRng + 'static>(
         .await
         .into_iter()
         .collect::<Result<Vec<_>, _>>()?;
+    let previous_heads = sorted_heads(&ctx, doc_id);
     let mut update = DocUpdateBuilder::new(doc_id, None);
     update.add_commits(commits.into_iter().flatten());
     ctx.state().docs().apply_doc_update(update);
+    super::auto_bundle::bundle_if_needed(&ctx, doc_id).await;
+    let heads = sorted_heads(&ctx, doc_id);
 
     // If any of the commits might be a bundle boundary, load the sedimentree
     // and see if any new bundles are needed
+    let mut new_bundles = Vec::new();
     if has_commit_boundary {
         let storage = ctx.storage().doc_storage(doc_id);
         let tree = sedimentree::storage::load(storage).await;
         if let Ok(Some(tree)) = tree {
-            Ok(tree.missing_bundles(doc_id))
-        } else {
-            Ok(Vec::new())
+            new_bundles = tree.missing_bundles(doc_id);
         }
-    } else {
-        Ok(Vec::new())
     }
+    Ok(CommitsAdded {
+        new_bundles,
+        new_commits,
+        previous_heads,
+        heads,
+    })
+}
+
+fn sorted_heads<R: rand::Rng + rand::CryptoRng>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+) -> Vec<CommitHash> {
+    let mut heads = ctx
+        .state()
+        .docs()
+        .doc_status(doc_id)
+        .local_heads
+        .unwrap_or_default();
+    heads.sort();
+    heads
+}
+
+/// Add commits to a document, reporting which of them are missing ancestors
+///
+/// A parent counts as present if it is in `commits`, is stored as a loose
+/// commit, or is the start, end or a checkpoint of a stored bundle. If
+/// `strict` is true and any commit would be orphaned nothing is stored and
+/// `error::AddCommits::MissingParents` is returned instead.
+#[tracing::instrument(skip(ctx, commits))]
+pub(super) async fn add_commits_checked<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    commits: Vec<Commit>,
+    strict: bool,
+) -> Result<AddedCommits, error::AddCommits> {
+    let orphans = find_orphans(&ctx, doc_id, &commits);
+    if strict && !orphans.is_empty() {
+        let mut missing = commits
+            .iter()
+            .map(|c| c.hash())
+            .filter(|h| orphans.contains(h))
+            .collect::<Vec<_>>();
+        missing.dedup();
+        return Err(error::AddCommits::MissingParents(missing));
+    }
+    let (orphaned, accepted) = commits
+        .iter()
+        .map(|c| c.hash())
+        .partition(|h| orphans.contains(h));
+    let new_bundles = add_commits(ctx, doc_id, commits).await?;
+    Ok(AddedCommits {
+        new_bundles,
+        accepted,
+        orphaned,
+    })
+}
+
+// The commits in `commits` which don't have a complete chain of ancestors in
+// either the batch or the stored sedimentree
+fn find_orphans<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    commits: &[Commit],
+) -> HashSet<CommitHash> {
+    let stored = ctx
+        .state()
+        .docs()
+        .sedimentree(&doc_id)
+        .map(|tree| super::doc_stats::known_commits(&tree))
+        .unwrap_or_default();
+    let in_batch = commits.iter().map(|c| c.hash()).collect::<HashSet<_>>();
+
+    let mut orphans = commits
+        .iter()
+        .filter(|c| {
+            c.parents()
+                .iter()
+                .any(|p| !stored.contains(p) && !in_batch.contains(p))
+        })
+        .map(|c| c.hash())
+        .collect::<HashSet<_>>();
+    // Descendants of an orphan within the batch are orphans too
+    loop {
+        let before = orphans.len();
+        for commit in commits {
+            if commit.parents().iter().any(|p| orphans.contains(p)) {
+                orphans.insert(commit.hash());
+            }
+        }
+        if orphans.len() == before {
+            return orphans;
+        }
+    }
+}
+
+/// Add commits to several documents at once
+///
+/// Each document is processed independently so a failure for one document does
+/// not prevent the commits for the others from being added. If a document
+/// appears more than once in `batches` its commits are combined.
+#[tracing::instrument(skip(ctx, batches))]
+pub(super) async fn add_commits_multi<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    command_id: CommandId,
+    batches: Vec<(DocumentId, Vec<Commit>)>,
+) -> HashMap<DocumentId, Result<Vec<BundleSpec>, error::AddCommits>> {
+    let mut by_doc: HashMap<DocumentId, Vec<Commit>> = HashMap::new();
+    for (doc_id, commits) in batches {
+        by_doc.entry(doc_id).or_default().extend(commits);
+    }
+    let total = by_doc.values().map(Vec::len).sum();
+    let mut tasks = by_doc
+        .into_iter()
+        .map(|(doc_id, commits)| {
+            let ctx = ctx.clone();
+            async move {
+                let num_commits = commits.len();
+                (doc_id, num_commits, add_commits(ctx, doc_id, commits).await)
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    // Report progress as each document completes
+    let mut processed = 0;
+    let mut results = HashMap::new();
+    while let Some((doc_id, num_commits, result)) = tasks.next().await {
+        processed += num_commits;
+        ctx.io().command_progress(command_id, processed, total);
+        results.insert(doc_id, result);
+    }
+    results
 }
 
 pub(crate) mod error {
@@ -79,6 +294,14 @@ pub(crate) mod error {
         Encrypt(String),
         #[error("error writing commit: {0}")]
         Storage(String),
+        #[error("commits are missing parents: {0:?}")]
+        MissingParents(Vec<crate::CommitHash>),
+        #[error("the document has been tombstoned")]
+        Tombstoned,
+        #[error("the document has been sealed")]
+        Sealed,
+        #[error("document size limit exceeded")]
+        SizeLimitExceeded,
     }
 
     impl From<crate::state::keyhive::EncryptError> for AddCommits {
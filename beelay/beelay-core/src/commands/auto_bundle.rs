@@ -0,0 +1,104 @@
+use std::collections::{BTreeSet, HashSet};
+
+use crate::{
+    doc_status::DocEvent, sedimentree, BundlingPolicy, CommitBundle, DocumentId, TaskContext,
+};
+
+/// Roll the loose commits of `doc_id` up into a bundle if they exceed the
+/// current bundling policy
+///
+/// The heads of the document are left as loose commits. Failures are logged
+/// rather than returned as the commits which triggered the bundling have
+/// already been stored.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn bundle_if_needed<R>(ctx: &TaskContext<R>, doc_id: DocumentId)
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let policy = ctx.state().bundling_policy();
+    let Some(tree) = ctx.state().docs().sedimentree(&doc_id) else {
+        return;
+    };
+    if !exceeds(&policy, &tree) {
+        return;
+    }
+
+    let heads = tree.heads().into_iter().collect::<HashSet<_>>();
+    let to_bundle = tree
+        .loose_commits()
+        .filter(|c| !heads.contains(&c.hash()))
+        .cloned()
+        .collect::<Vec<_>>();
+    let hashes = to_bundle.iter().map(|c| c.hash()).collect::<HashSet<_>>();
+    // The bundle starts at a commit whose parents aren't being bundled and
+    // ends at one with no children being bundled, any others are checkpoints
+    let mut starts = to_bundle
+        .iter()
+        .filter(|c| !c.parents().iter().any(|p| hashes.contains(p)))
+        .map(|c| c.hash())
+        .collect::<BTreeSet<_>>();
+    let mut ends = hashes
+        .iter()
+        .filter(|h| !to_bundle.iter().any(|c| c.parents().contains(h)))
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let (Some(start), Some(end)) = (starts.pop_first(), ends.pop_first()) else {
+        tracing::debug!("only heads are loose, nothing to bundle");
+        return;
+    };
+    let mut checkpoints = starts.into_iter().chain(ends).collect::<BTreeSet<_>>();
+    checkpoints.remove(&start);
+    checkpoints.remove(&end);
+
+    let mut load_ctx = ctx.clone();
+    let subtree = sedimentree::Sedimentree::new(Vec::new(), to_bundle);
+    let items = match super::load_tree_data(&mut load_ctx, &doc_id, subtree, true, None).await {
+        Ok(items) => items,
+        Err(e) => {
+            tracing::warn!(err=?e, "unable to load commits to bundle");
+            return;
+        }
+    };
+    if items.len() != hashes.len() {
+        // Some commits couldn't be decrypted, don't delete them
+        tracing::warn!("unable to decrypt every commit to bundle");
+        return;
+    }
+    let bundle = CommitBundle::builder()
+        .start(start)
+        .end(end)
+        .checkpoints(checkpoints.into_iter().collect())
+        .bundled_commits(CommitBundle::encode_exported(items))
+        .build();
+    if let Err(e) = super::add_bundle(ctx.clone(), doc_id, bundle).await {
+        tracing::warn!(err=?e, "unable to store automatic bundle");
+        return;
+    }
+
+    let Some(tree) = ctx.state().docs().sedimentree(&doc_id) else {
+        return;
+    };
+    super::compact_doc::delete_loose_commits(ctx, doc_id, &tree, &hashes).await;
+    tracing::debug!(num_commits = hashes.len(), "bundled loose commits");
+    ctx.io().new_doc_event(
+        doc_id,
+        DocEvent::Bundled {
+            start,
+            end,
+            num_commits: hashes.len(),
+        },
+    );
+}
+
+fn exceeds(policy: &BundlingPolicy, tree: &sedimentree::Sedimentree) -> bool {
+    let too_many = policy
+        .max_loose_commits
+        .is_some_and(|max| tree.loose_commits().count() > max);
+    let too_big = policy.max_loose_bytes.is_some_and(|max| {
+        tree.loose_commits()
+            .map(|c| c.blob().size_bytes())
+            .sum::<u64>()
+            > max
+    });
+    too_many || too_big
+}
@@ -1,4 +1,4 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::AtomicU64;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct CommandId(u64);
@@ -7,7 +7,7 @@ static LAST_COMMAND_ID: AtomicU64 = AtomicU64::new(0);
 
 impl CommandId {
     pub(crate) fn new() -> Self {
-        Self(LAST_COMMAND_ID.fetch_add(1, Ordering::Relaxed))
+        Self(crate::ids::next(&LAST_COMMAND_ID))
     }
 
     pub fn serialize(&self) -> u64 {
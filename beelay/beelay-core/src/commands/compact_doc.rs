@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+
+use crate::{commit_meta, sedimentree, CommitHash, DocumentId, StorageKey, TaskContext};
+
+/// The outcome of compacting a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Compaction {
+    /// The number of loose commits which were deleted
+    pub commits_removed: usize,
+    /// The total size of the storage values which were deleted
+    pub bytes_reclaimed: u64,
+}
+
+/// Delete the loose commits of a document which are covered by its strata
+///
+/// A commit is only removed if minimizing the sedimentree discards it, which
+/// means that every path from it back to a bundle boundary is covered by a
+/// stratum we have stored. The heads of the document are always kept.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn compact_doc<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Result<Compaction, error::CompactDoc> {
+    let tree = sedimentree::storage::load(ctx.storage().doc_storage(doc_id))
+        .await
+        .map_err(|e| error::CompactDoc::Storage(e.to_string()))?;
+    let Some(tree) = tree else {
+        return if ctx.state().keyhive().has_doc(&doc_id).await {
+            Ok(Compaction::default())
+        } else {
+            Err(error::CompactDoc::NoSuchDocument)
+        };
+    };
+
+    let minimized = tree.minimize();
+    let heads = tree.heads().into_iter().collect::<HashSet<_>>();
+    let kept = minimized
+        .loose_commits()
+        .map(|c| c.hash())
+        .chain(heads.iter().copied())
+        .collect::<HashSet<_>>();
+    let removed = tree
+        .loose_commits()
+        .map(|c| c.hash())
+        .filter(|hash| !kept.contains(hash))
+        .collect::<HashSet<_>>();
+    if removed.is_empty() {
+        tracing::debug!("no superseded commits to remove");
+        return Ok(Compaction::default());
+    }
+
+    let bytes_reclaimed = delete_loose_commits(&ctx, doc_id, &tree, &removed).await;
+    let compaction = Compaction {
+        commits_removed: removed.len(),
+        bytes_reclaimed,
+    };
+    tracing::debug!(?compaction, "compacted document");
+    Ok(compaction)
+}
+
+/// Delete the loose commits in `removed` from storage and from the in memory
+/// sedimentree of the document, returning the total size of the deleted values
+pub(super) async fn delete_loose_commits<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    tree: &sedimentree::Sedimentree,
+    removed: &HashSet<CommitHash>,
+) -> u64 {
+    // Blobs are content addressed so a blob could be shared with an item we
+    // are keeping or with another document
+    let mut live_blobs = ctx.state().docs().blobs_outside(&doc_id);
+    live_blobs.extend(
+        tree.loose_commits()
+            .filter(|c| !removed.contains(&c.hash()))
+            .map(|c| c.blob().hash())
+            .chain(tree.strata().map(|s| s.meta().blob().hash())),
+    );
+    let mut to_delete = Vec::new();
+    for commit in tree.loose_commits().filter(|c| removed.contains(&c.hash())) {
+        to_delete.push(StorageKey::sedimentree_commit(&doc_id, commit.hash()));
+        to_delete.push(commit_meta::commit_key(&doc_id, commit.hash()));
+        if !live_blobs.contains(&commit.blob().hash()) {
+            to_delete.push(StorageKey::blob(commit.blob().hash()));
+        }
+    }
+
+    let removed_hashes = removed.iter().copied().collect::<Vec<_>>();
+    ctx.state().docs().remove_commits(&doc_id, &removed_hashes);
+
+    let deletes = to_delete.into_iter().map(|key| {
+        let ctx = ctx.clone();
+        async move {
+            let size = ctx.storage().load(key.clone()).await.map(|v| v.len());
+            if size.is_some() {
+                ctx.storage().delete(key).await;
+            }
+            size.unwrap_or(0) as u64
+        }
+    });
+    futures::future::join_all(deletes).await.into_iter().sum()
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum CompactDoc {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("error loading sedimentree: {0}")]
+        Storage(String),
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    blob::BlobMeta, commit_meta, keyhive::KeyhiveEntityId, sedimentree, CommitBundle, DocumentId,
+    TaskContext,
+};
+
+/// Create a new document whose initial history is the contents of `bundle`
+///
+/// The bundle is validated before anything is written. If the contents of the
+/// bundle were produced by `export_bundle` then every commit in it must have
+/// all of its parents in the bundle, otherwise only the boundaries of the
+/// bundle can be checked.
+#[tracing::instrument(skip(ctx, bundle), fields(start=%bundle.start(), end=%bundle.end()))]
+pub(super) async fn create_doc_from_bundle<R>(
+    ctx: TaskContext<R>,
+    other_owners: Vec<KeyhiveEntityId>,
+    bundle: CommitBundle,
+) -> Result<DocumentId, error::CreateDocFromBundle>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    validate(&bundle)?;
+
+    let heads = nonempty::NonEmpty::new(bundle.end());
+    let doc_id = ctx
+        .state()
+        .keyhive()
+        .create_keyhive_doc(other_owners, heads)
+        .await;
+
+    let (encrypted, cgka_op) = ctx
+        .state()
+        .keyhive()
+        .encrypt(
+            doc_id,
+            &[bundle.start()],
+            &bundle.end(),
+            bundle.bundled_commits(),
+        )
+        .await
+        .map_err(|e| error::CreateDocFromBundle::Encrypt(e.to_string()))?;
+    tracing::trace!(?doc_id, "creating doc from bundle");
+
+    let blob = BlobMeta::new(&encrypted);
+    ctx.storage()
+        .put_doc_blob(&doc_id, blob.hash(), encrypted)
+        .await;
+
+    let stratum = sedimentree::Stratum::new(
+        bundle.start(),
+        bundle.end(),
+        bundle.checkpoints().to_vec(),
+        blob,
+    );
+    let tree = sedimentree::Sedimentree::new(vec![stratum], Vec::new());
+
+    let storage = ctx.storage().doc_storage(doc_id);
+    sedimentree::storage::update(storage, None, &tree)
+        .await
+        .map_err(|e| error::CreateDocFromBundle::Storage(e.to_string()))?;
+    commit_meta::record_bundle(&ctx, doc_id, bundle.start(), bundle.end()).await;
+
+    ctx.state().docs().add_doc(doc_id, tree, cgka_op);
+
+    Ok(doc_id)
+}
+
+fn validate(bundle: &CommitBundle) -> Result<(), error::CreateDocFromBundle> {
+    match super::add_bundle_checked::content_problems(bundle)
+        .into_iter()
+        .next()
+    {
+        Some(problem) => Err(error::CreateDocFromBundle::InvalidBundle(
+            problem.to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum CreateDocFromBundle {
+        #[error("invalid bundle: {0}")]
+        InvalidBundle(String),
+        #[error("error encrypting bundle: {0}")]
+        Encrypt(String),
+        #[error("error writing to storage: {0}")]
+        Storage(String),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Commit, CommitBundle, CommitHash, CommitOrBundle};
+
+    fn commit(parents: Vec<CommitHash>, hash: u8) -> Commit {
+        Commit::new(parents, vec![hash], CommitHash::from([hash; 32]))
+    }
+
+    fn exported(start: &Commit, end: &Commit, items: Vec<Commit>) -> CommitBundle {
+        CommitBundle::builder()
+            .start(start.hash())
+            .end(end.hash())
+            .bundled_commits(CommitBundle::encode_exported(
+                items.into_iter().map(CommitOrBundle::Commit).collect(),
+            ))
+            .build()
+    }
+
+    #[test]
+    fn consistent_bundle_is_valid() {
+        let root = commit(vec![], 1);
+        let child = commit(vec![root.hash()], 2);
+        let bundle = exported(&root, &child, vec![root.clone(), child.clone()]);
+        super::validate(&bundle).unwrap();
+    }
+
+    #[test]
+    fn dangling_parent_is_invalid() {
+        let root = commit(vec![], 1);
+        let child = commit(vec![root.hash()], 2);
+        let grandchild = commit(vec![child.hash()], 3);
+        let bundle = exported(&root, &grandchild, vec![root.clone(), grandchild.clone()]);
+        assert!(super::validate(&bundle).is_err());
+    }
+
+    #[test]
+    fn checkpoint_on_boundary_is_invalid() {
+        let root = commit(vec![], 1);
+        let child = commit(vec![root.hash()], 2);
+        let bundle = CommitBundle::builder()
+            .start(root.hash())
+            .end(child.hash())
+            .checkpoints(vec![child.hash()])
+            .bundled_commits(vec![1, 2, 3])
+            .build();
+        assert!(super::validate(&bundle).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+
+use crate::{capabilities, sedimentree, DocumentId, StorageKey, TaskContext};
+
+/// Remove a document and all of its data from local storage
+///
+/// This deletes every loose commit and stratum in the document's sedimentree
+/// along with the blobs they reference which no other document references,
+/// and drops the document from the in memory state, invalidating any exports
+/// or cached contents of it. Capabilities we minted for the document are
+/// deleted too, as they only grant access to our copy of it.
+///
+/// The document's keyhive metadata is kept. Its delegations, revocations and
+/// CGKA operations are signed events shared with every other member, and some
+/// of them may only exist inside a compacted keyhive archive which can't be
+/// pruned one document at a time. Dropping them locally would not stop other
+/// members from sending them back, but it would leave our keyhive unable to
+/// load the document or decrypt a copy which is synced back down. Membership
+/// expiries are kept for the same reason, as the members they cover still
+/// have access through the other replicas until the expiry revokes it. The
+/// seal and tombstone, which admins sign and every replica enforces, are kept
+/// so that a copy synced back is still refused writes or content.
+///
+/// Returns the number of storage keys which were deleted.
+#[tracing::instrument(skip(ctx))]
+pub(crate) async fn delete_doc<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> usize {
+    let in_memory = ctx.state().docs().remove_doc(&doc_id);
+    ctx.state().exports().invalidate_doc(&doc_id);
+    ctx.state().warm_docs().remove(&doc_id);
+    let stored = match sedimentree::storage::load(ctx.storage().doc_storage(doc_id)).await {
+        Ok(tree) => tree,
+        Err(e) => {
+            tracing::warn!(err=?e, "unable to load stored sedimentree, only deleting raw keys");
+            None
+        }
+    };
+
+    let mut to_delete = ctx
+        .storage()
+        .load_range(StorageKey::sedimentree_root(&doc_id))
+        .await
+        .into_keys()
+        .collect::<HashSet<_>>();
+    // Identical blobs are stored once, so keep any which another document
+    // still references
+    let shared = ctx.state().docs().blobs_outside(&doc_id);
+    for tree in in_memory.iter().chain(stored.iter()) {
+        to_delete.extend(
+            tree.blobs()
+                .filter(|b| !shared.contains(&b.hash()))
+                .map(|b| StorageKey::blob(b.hash())),
+        );
+    }
+
+    to_delete.extend(capabilities::remove_for_doc(&ctx, doc_id));
+
+    if to_delete.is_empty() {
+        tracing::debug!("no data found for document, nothing to delete");
+        return 0;
+    }
+
+    let num_keys = to_delete.len();
+    let deletes = to_delete.into_iter().map(|key| {
+        let ctx = ctx.clone();
+        async move { ctx.storage().delete(key).await }
+    });
+    futures::future::join_all(deletes).await;
+    tracing::debug!(num_keys, "deleted document");
+    num_keys
+}
@@ -0,0 +1,79 @@
+use crate::{io::IoHandle, task_context::Storage, StorageKey, TaskContext};
+
+// A wipe records the namespace here before deleting anything and removes the
+// record once every key is gone, so that a wipe which was interrupted is
+// finished the next time we load
+fn in_progress_prefix() -> StorageKey {
+    StorageKey::auth().push("deleting_namespaces")
+}
+
+/// Remove everything stored under `namespace`, as set by
+/// `Config::storage_namespace` on the Beelay which wrote it
+///
+/// Namespaces are only visible to a Beelay without a namespace of its own,
+/// one with a namespace gets `DeleteNamespace::Namespaced`.
+///
+/// The keys are found by listing storage one level at a time, so values
+/// encrypted with a storage key other than ours are removed too. Keys are
+/// deleted one IO task at a time and can't be removed atomically, instead
+/// the namespace is recorded in our storage before anything is deleted and
+/// a wipe which is interrupted is resumed when we are next loaded.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn delete_namespace<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    namespace: String,
+) -> Result<(), error::DeleteNamespace> {
+    if ctx.state().storage_namespaced() {
+        return Err(error::DeleteNamespace::Namespaced);
+    }
+    let root = StorageKey::namespace_root(&namespace).ok_or(error::DeleteNamespace::Invalid)?;
+    let marker = in_progress_prefix().push(&namespace);
+    ctx.storage().put(marker.clone(), Vec::new()).await;
+    wipe(ctx.storage(), root).await;
+    ctx.storage().delete(marker).await;
+    Ok(())
+}
+
+/// Finish any `delete_namespace` which was interrupted
+pub(crate) async fn resume(io: IoHandle) {
+    let storage = Storage::new(&io);
+    for marker in storage.list_one_level(in_progress_prefix()).await {
+        let Some(root) = marker.name().and_then(StorageKey::namespace_root) else {
+            tracing::warn!(?marker, "invalid namespace deletion marker");
+            continue;
+        };
+        tracing::debug!(namespace = root.namespace(), "resuming namespace deletion");
+        wipe(Storage::new(&io), root).await;
+        storage.delete(marker).await;
+    }
+}
+
+async fn wipe(storage: Storage<'_>, root: StorageKey) {
+    // Any key we list may hold a value as well as having keys below it, so
+    // every one of them is deleted
+    let mut to_delete = Vec::new();
+    let mut frontier = vec![root];
+    while let Some(prefix) = frontier.pop() {
+        let children = storage.list_one_level(prefix).await;
+        to_delete.extend(children.iter().cloned());
+        frontier.extend(children);
+    }
+
+    let num_keys = to_delete.len();
+    futures::future::join_all(to_delete.into_iter().map(|key| storage.delete(key))).await;
+    tracing::debug!(num_keys, "deleted namespace");
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum DeleteNamespace {
+        /// The namespace is empty, contains a `/` or is one of the
+        /// namespaces beelay uses itself
+        #[error("invalid storage namespace")]
+        Invalid,
+        /// This Beelay has a storage namespace of its own, so it can't see
+        /// any other namespace
+        #[error("cannot delete a namespace from a namespaced Beelay")]
+        Namespaced,
+    }
+}
@@ -0,0 +1,32 @@
+use crate::{network::PeerAddress, DocumentId, StreamId, TaskContext};
+
+/// Ask the peer on the other end of `stream_id` which of its documents we are
+/// allowed to pull
+///
+/// The remote decides what we can see from its own view of the access
+/// control, documents it has paused or tombstoned are left out.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn discover_docs<R>(
+    ctx: TaskContext<R>,
+    stream_id: StreamId,
+) -> Result<Vec<DocumentId>, super::error::DiscoverDocs>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    if !ctx
+        .state()
+        .streams()
+        .established()
+        .iter()
+        .any(|s| s.id == stream_id)
+    {
+        return Err(super::error::DiscoverDocs::NoSuchStream);
+    }
+    let docs = ctx
+        .requests()
+        .discover_docs(PeerAddress::Stream(stream_id))
+        .await
+        .map_err(|e| super::error::DiscoverDocs::RpcError(e.to_string()))?;
+    tracing::trace!(num_docs = docs.len(), "discovered docs");
+    Ok(docs)
+}
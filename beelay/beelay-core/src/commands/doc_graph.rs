@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+
+use crate::{sedimentree::Sedimentree, CommitHash, DocumentId, TaskContext};
+
+/// The shape of the history of a document which is stored locally
+///
+/// Commits which have been rolled up into a bundle only appear as the
+/// boundaries of that bundle, the interior of a bundle can't be seen without
+/// decrypting it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocGraph {
+    pub commits: Vec<GraphCommit>,
+    pub bundles: Vec<GraphBundle>,
+}
+
+/// A commit which is stored individually
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphCommit {
+    pub hash: CommitHash,
+    pub parents: Vec<CommitHash>,
+    pub is_head: bool,
+}
+
+/// A range of commits which is stored as a single bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphBundle {
+    pub start: CommitHash,
+    pub end: CommitHash,
+    /// The commits between `start` and `end` which are bundle boundaries at a
+    /// lower level
+    pub checkpoints: Vec<CommitHash>,
+    /// Whether `end` is a current head of the document
+    pub is_head: bool,
+}
+
+/// The commit graph of a document without any of the commit contents
+///
+/// Only the metadata of each commit and bundle is read, nothing is loaded
+/// from storage or decrypted. Returns `None` if the document is unknown.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn doc_graph<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Option<DocGraph> {
+    match ctx.state().docs().sedimentree(&doc_id) {
+        Some(tree) => Some(graph_of(&tree)),
+        None if ctx.state().keyhive().has_doc(&doc_id).await => Some(DocGraph {
+            commits: Vec::new(),
+            bundles: Vec::new(),
+        }),
+        None => None,
+    }
+}
+
+fn graph_of(tree: &Sedimentree) -> DocGraph {
+    let heads = tree.heads().into_iter().collect::<HashSet<_>>();
+    let commits = tree
+        .loose_commits()
+        .map(|c| GraphCommit {
+            hash: c.hash(),
+            parents: c.parents().to_vec(),
+            is_head: heads.contains(&c.hash()),
+        })
+        .collect();
+    let bundles = tree
+        .strata()
+        .map(|s| GraphBundle {
+            start: s.start(),
+            end: s.end(),
+            checkpoints: s.checkpoints().to_vec(),
+            is_head: heads.contains(&s.end()),
+        })
+        .collect();
+    DocGraph { commits, bundles }
+}
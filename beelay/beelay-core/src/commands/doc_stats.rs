@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+
+use crate::{sedimentree::Sedimentree, CommitHash, DocSizeLimit, DocumentId, TaskContext};
+
+/// How much of a document is stored locally
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DocStats {
+    /// The number of commits which are stored individually rather than in a
+    /// bundle
+    pub commits: usize,
+    pub bundles: usize,
+    /// The total size in bytes of the stored (encrypted) contents of every
+    /// commit and bundle
+    pub stored_bytes: u64,
+    pub heads: usize,
+    /// False if some of the history of the document is known to be missing
+    /// locally, for example because it has only partially synced. The other
+    /// statistics only describe what is present.
+    pub complete: bool,
+    /// The limit the document can't grow past, which applies to every
+    /// document
+    pub size_limit: DocSizeLimit,
+}
+
+/// How much storage is saved by documents referencing identical contents
+///
+/// The contents of commits and bundles are stored under their hash, so a blob
+/// which appears in several documents is only stored once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DedupStats {
+    /// The number of distinct blobs referenced by any document
+    pub blobs: usize,
+    /// The number of blobs which are referenced by more than one document
+    pub shared_blobs: usize,
+    /// The total size in bytes of the distinct blobs
+    pub stored_bytes: u64,
+    /// The extra bytes which would be stored if every document kept its own
+    /// copy of each blob
+    pub bytes_saved: u64,
+}
+
+/// The commits which one document has and another doesn't, as returned by
+/// `Event::diff_docs`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DocDiff {
+    /// Sorted
+    pub only_in_a: Vec<CommitHash>,
+    /// Sorted
+    pub only_in_b: Vec<CommitHash>,
+}
+
+/// Summarise the commits and bundles of a document which are stored locally
+///
+/// Only the metadata of each commit and bundle is read, nothing is loaded
+/// from storage or decrypted. Returns `None` if the document is unknown.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn doc_stats<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Option<DocStats> {
+    let size_limit = ctx.state().doc_size_limit();
+    match ctx.state().docs().sedimentree(&doc_id) {
+        Some(tree) => Some(stats_of(&tree, size_limit)),
+        // We know about the document but have none of its commits
+        None if ctx.state().keyhive().has_doc(&doc_id).await => Some(DocStats {
+            commits: 0,
+            bundles: 0,
+            stored_bytes: 0,
+            heads: 0,
+            complete: false,
+            size_limit,
+        }),
+        None => None,
+    }
+}
+
+/// Whether adding `commits` new commits or bundles holding `bytes` of contents
+/// to `doc_id` would take it past the document size limit
+pub(crate) fn exceeds_size_limit<R: rand::Rng + rand::CryptoRng>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    commits: usize,
+    bytes: u64,
+) -> bool {
+    let limit = ctx.state().doc_size_limit();
+    if limit == DocSizeLimit::default() {
+        return false;
+    }
+    let (stored_commits, stored_bytes) = match ctx.state().docs().sedimentree(&doc_id) {
+        Some(tree) => {
+            let stats = stats_of(&tree, limit);
+            (stats.commits + stats.bundles, stats.stored_bytes)
+        }
+        None => (0, 0),
+    };
+    limit.exceeded_by(stored_commits + commits, stored_bytes + bytes)
+}
+
+/// Count the blobs which are shared between documents and the bytes this saves
+///
+/// As with `doc_stats` this only reads the metadata of the documents we have
+/// loaded.
+#[tracing::instrument(skip(ctx))]
+pub(super) fn dedup_stats<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+) -> DedupStats {
+    let mut stats = DedupStats::default();
+    for (blob, references) in ctx.state().docs().blob_references() {
+        stats.blobs += 1;
+        stats.stored_bytes += blob.size_bytes();
+        if references > 1 {
+            stats.shared_blobs += 1;
+            stats.bytes_saved += blob.size_bytes() * (references as u64 - 1);
+        }
+    }
+    stats
+}
+
+/// Whether each of `hashes` is stored locally for `doc_id`
+///
+/// A commit is present if it is stored as a loose commit or is the start, end
+/// or a checkpoint of a stored bundle. The interior of a bundle can't be seen
+/// without decrypting it, so commits which are only contained in a bundle are
+/// reported as missing.
+#[tracing::instrument(skip(ctx, hashes), fields(num_hashes = hashes.len()))]
+pub(super) fn has_commits<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    hashes: Vec<CommitHash>,
+) -> Vec<bool> {
+    let known = ctx
+        .state()
+        .docs()
+        .sedimentree(&doc_id)
+        .map(|tree| known_commits(&tree))
+        .unwrap_or_default();
+    hashes.iter().map(|hash| known.contains(hash)).collect()
+}
+
+/// A commit which is stored locally but whose history is incomplete
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingOrphan {
+    pub commit: CommitHash,
+    /// The parents of `commit` which aren't stored locally
+    pub missing_parents: Vec<CommitHash>,
+}
+
+/// The loose commits of `doc_id` which are waiting for some of their parents
+///
+/// Only commits with a missing parent are listed, not their descendants, so
+/// the missing parents are exactly the gaps which need to be filled. As with
+/// `has_commits`, a parent which is only in the interior of a bundle is
+/// reported as missing. Empty if the document has no gaps or is unknown.
+#[tracing::instrument(skip(ctx))]
+pub(super) fn pending_orphans<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Vec<PendingOrphan> {
+    let Some(tree) = ctx.state().docs().sedimentree(&doc_id) else {
+        return Vec::new();
+    };
+    let known = known_commits(&tree);
+    let mut orphans = tree
+        .loose_commits()
+        .filter_map(|c| {
+            let mut missing_parents = c
+                .parents()
+                .iter()
+                .filter(|p| !known.contains(p))
+                .copied()
+                .collect::<Vec<_>>();
+            if missing_parents.is_empty() {
+                return None;
+            }
+            missing_parents.sort();
+            Some(PendingOrphan {
+                commit: c.hash(),
+                missing_parents,
+            })
+        })
+        .collect::<Vec<_>>();
+    orphans.sort_by_key(|o| o.commit);
+    orphans
+}
+
+/// The stored commits of `doc_id` which are not reachable from any of its
+/// heads
+///
+/// Reachability walks back through the parents of stored commits and from
+/// the end of a bundle to its start, so a stored commit which is only an
+/// interior checkpoint of a bundle is unreachable; its contents are already
+/// part of the bundle. Commits waiting for missing parents are not listed, see
+/// `pending_orphans`. Empty if the document is unknown.
+#[tracing::instrument(skip(ctx))]
+pub(super) fn find_unreachable<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Vec<CommitHash> {
+    let Some(tree) = ctx.state().docs().sedimentree(&doc_id) else {
+        return Vec::new();
+    };
+    let known = known_commits(&tree);
+    let mut frontier = tree.heads();
+    let mut reachable = HashSet::new();
+    while let Some(hash) = frontier.pop() {
+        if !reachable.insert(hash) {
+            continue;
+        }
+        if let Some(commit) = tree.loose_commits().find(|c| c.hash() == hash) {
+            frontier.extend(commit.parents().iter().copied());
+        }
+        frontier.extend(
+            tree.strata()
+                .filter(|s| s.supports_block(hash))
+                .map(|s| s.start()),
+        );
+    }
+    let mut unreachable = tree
+        .loose_commits()
+        .filter(|c| !reachable.contains(&c.hash()))
+        .filter(|c| c.parents().iter().all(|p| known.contains(p)))
+        .map(|c| c.hash())
+        .collect::<Vec<_>>();
+    unreachable.sort();
+    unreachable
+}
+
+/// The commits each of `a` and `b` has which the other doesn't
+///
+/// Commits are compared by hash using only the metadata of each document.
+/// The commits inside a bundle can't be told apart without decrypting it, so
+/// a bundle contributes its start, end and checkpoints. Two documents which
+/// hold the same commits in differently sized bundles can show differences.
+#[tracing::instrument(skip(ctx))]
+pub(super) fn diff_docs<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    a: DocumentId,
+    b: DocumentId,
+) -> Result<DocDiff, super::error::DiffDocs> {
+    let known = |doc_id| {
+        ctx.state()
+            .docs()
+            .sedimentree(&doc_id)
+            .map(|tree| known_commits(&tree))
+    };
+    let in_a = known(a).ok_or(super::error::DiffDocs::NoSuchA)?;
+    let in_b = known(b).ok_or(super::error::DiffDocs::NoSuchB)?;
+    let only = |ours: &HashSet<CommitHash>, theirs: &HashSet<CommitHash>| {
+        let mut only = ours.difference(theirs).copied().collect::<Vec<_>>();
+        only.sort();
+        only
+    };
+    Ok(DocDiff {
+        only_in_a: only(&in_a, &in_b),
+        only_in_b: only(&in_b, &in_a),
+    })
+}
+
+// The commits which are stored loose or at the boundaries of a stored stratum
+pub(crate) fn known_commits(tree: &Sedimentree) -> HashSet<CommitHash> {
+    tree.loose_commits()
+        .map(|c| c.hash())
+        .chain(tree.strata().flat_map(|s| {
+            [s.start(), s.end()]
+                .into_iter()
+                .chain(s.checkpoints().iter().copied())
+        }))
+        .collect()
+}
+
+fn stats_of(tree: &Sedimentree, size_limit: DocSizeLimit) -> DocStats {
+    let known = known_commits(tree);
+    let complete = tree
+        .loose_commits()
+        .all(|c| c.parents().iter().all(|p| known.contains(p)));
+    let stored_bytes = tree
+        .loose_commits()
+        .map(|c| c.blob().size_bytes())
+        .chain(tree.strata().map(|s| s.meta().blob().size_bytes()))
+        .sum();
+    DocStats {
+        commits: tree.loose_commits().count(),
+        bundles: tree.strata().count(),
+        stored_bytes,
+        heads: tree.heads().len(),
+        complete,
+        size_limit,
+    }
+}
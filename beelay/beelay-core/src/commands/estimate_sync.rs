@@ -0,0 +1,108 @@
+use crate::{
+    network::{messages::FetchedSedimentree, PeerAddress},
+    sedimentree, DocumentId, StreamId, TaskContext,
+};
+
+/// The outcome of `Event::estimate_sync`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncEstimate {
+    /// What we would fetch from the remote
+    pub download: TransferEstimate,
+    /// What we would send to the remote, nothing if the stream is receive only
+    pub upload: TransferEstimate,
+    /// Documents the remote wouldn't tell us about, because it doesn't have
+    /// them, has paused syncing them or won't let us read them. These aren't
+    /// included in the estimate.
+    pub unavailable: Vec<DocumentId>,
+}
+
+/// The commits and bundles one side of a sync is missing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferEstimate {
+    pub commits: usize,
+    /// The number of commits in a bundle can't be seen without decrypting
+    /// it, so bundles are counted separately
+    pub bundles: usize,
+    /// The total size of the (encrypted) contents of the commits and bundles
+    pub bytes: u64,
+}
+
+/// Estimate what syncing `doc_ids` with the peer on the other end of
+/// `stream_id` would transfer
+///
+/// Only the metadata of the remote's commits and bundles is fetched, which is
+/// compared against what we have stored to find what each side is missing.
+/// The estimate ignores messages other than commits and bundles.
+#[tracing::instrument(skip(ctx, doc_ids), fields(num_docs = doc_ids.len()))]
+pub(super) async fn estimate_sync<R>(
+    ctx: TaskContext<R>,
+    stream_id: StreamId,
+    doc_ids: Vec<DocumentId>,
+) -> Result<SyncEstimate, super::error::EstimateSync>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let Some(stream) = ctx
+        .state()
+        .streams()
+        .established()
+        .into_iter()
+        .find(|s| s.id == stream_id)
+    else {
+        return Err(super::error::EstimateSync::NoSuchStream);
+    };
+
+    let fetches = doc_ids.into_iter().map(|doc_id| {
+        let ctx = ctx.clone();
+        async move {
+            let remote = ctx
+                .requests()
+                .fetch_sedimentrees(PeerAddress::Stream(stream_id), doc_id)
+                .await;
+            (doc_id, remote)
+        }
+    });
+    let mut estimate = SyncEstimate::default();
+    for (doc_id, remote) in futures::future::join_all(fetches).await {
+        let remote = match remote {
+            Ok(FetchedSedimentree::Found(remote)) => remote,
+            Ok(FetchedSedimentree::NotFound | FetchedSedimentree::Paused) => {
+                estimate.unavailable.push(doc_id);
+                continue;
+            }
+            Err(e) => {
+                tracing::debug!(err=?e, %doc_id, "unable to fetch remote sedimentree");
+                estimate.unavailable.push(doc_id);
+                continue;
+            }
+        };
+        let local = ctx.state().docs().sedimentree(&doc_id);
+        let sedimentree::RemoteDiff {
+            remote_strata,
+            remote_commits,
+            local_strata,
+            local_commits,
+        } = match &local {
+            Some(local) => local.diff_remote(&remote),
+            None => remote.as_remote_diff(),
+        };
+
+        estimate.download.commits += remote_commits.len();
+        estimate.download.bundles += remote_strata.len();
+        estimate.download.bytes += remote_commits
+            .iter()
+            .map(|c| c.blob().size_bytes())
+            .chain(remote_strata.iter().map(|s| s.blob().size_bytes()))
+            .sum::<u64>();
+        if !stream.receive_only {
+            estimate.upload.commits += local_commits.len();
+            estimate.upload.bundles += local_strata.len();
+            estimate.upload.bytes += local_commits
+                .iter()
+                .map(|c| c.blob().size_bytes())
+                .chain(local_strata.iter().map(|s| s.meta().blob().size_bytes()))
+                .sum::<u64>();
+        }
+    }
+    Ok(estimate)
+}
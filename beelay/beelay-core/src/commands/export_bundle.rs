@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    sedimentree, serialization::Encode, CommitBundle, CommitHash, DocumentId, TaskContext,
+};
+
+/// Export the commits reachable from `heads` as a single bundle
+///
+/// The contents of the returned bundle are the decrypted commits and bundles
+/// which make up that part of the document, which the recipient can read back
+/// with [`CommitBundle::exported_items`]. The bundle starts at the root of the
+/// exported history and ends at the first of `heads`, any other heads and the
+/// boundaries of the strata which were included are recorded as checkpoints.
+///
+/// If `include_keys` is true then the keyhive events needed by a member of the
+/// document to decrypt it are attached to the bundle so that `add_bundle` can
+/// be used on a node which has never synced the document.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn export_bundle<R>(
+    ctx: &mut TaskContext<R>,
+    doc_id: DocumentId,
+    heads: Vec<CommitHash>,
+    include_keys: bool,
+) -> Result<CommitBundle, error::ExportBundle>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let Some(end) = heads.first().copied() else {
+        return Err(error::ExportBundle::NoHeads);
+    };
+    let tree = match ctx.state().docs().sedimentree(&doc_id) {
+        Some(tree) => tree,
+        None => {
+            if ctx.state().keyhive().has_doc(&doc_id).await {
+                ctx.state().docs().sedimentree(&doc_id).unwrap_or_default()
+            } else {
+                return Err(error::ExportBundle::NoSuchDocument);
+            }
+        }
+    };
+    let subtree = tree
+        .ancestors_of(&heads)
+        .map_err(error::ExportBundle::MissingHead)?;
+
+    let mut roots = roots(&subtree);
+    let start = roots.pop_first().unwrap_or(end);
+    let mut checkpoints = subtree
+        .strata()
+        .flat_map(|s| {
+            [s.start(), s.end()]
+                .into_iter()
+                .chain(s.checkpoints().iter().copied())
+        })
+        .chain(roots)
+        .chain(heads.iter().skip(1).copied())
+        .collect::<BTreeSet<_>>();
+    checkpoints.remove(&start);
+    checkpoints.remove(&end);
+
+    let items = super::load_tree_data(ctx, &doc_id, subtree, true, None)
+        .await
+        .map_err(|e| error::ExportBundle::Load(e.to_string()))?;
+
+    let builder = CommitBundle::builder()
+        .start(start)
+        .end(end)
+        .checkpoints(checkpoints.into_iter().collect())
+        .bundled_commits(CommitBundle::encode_exported(items));
+    if !include_keys {
+        return Ok(builder.build());
+    }
+    let events = ctx
+        .state()
+        .keyhive()
+        .doc_key_material(doc_id)
+        .await
+        .ok_or(error::ExportBundle::NoSuchDocument)?
+        .map_err(|e| error::ExportBundle::Keyhive(e.to_string()))?;
+    Ok(builder.keyhive_material(events.encode()).build())
+}
+
+// The commits in the tree which have no ancestors in the tree
+fn roots(tree: &sedimentree::Sedimentree) -> BTreeSet<CommitHash> {
+    let covered = |hash: CommitHash| {
+        tree.strata().any(|s| s.supports_block(hash))
+            || tree.loose_commits().any(|c| c.hash() == hash)
+    };
+    tree.loose_commits()
+        .filter(|c| c.parents().is_empty())
+        .map(|c| c.hash())
+        .chain(
+            tree.strata()
+                .map(|s| s.start())
+                .filter(|start| !covered(*start)),
+        )
+        .collect()
+}
+
+pub(crate) mod error {
+    use crate::CommitHash;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ExportBundle {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("no heads to export from")]
+        NoHeads,
+        #[error("head {0} is not present locally")]
+        MissingHead(CommitHash),
+        #[error("error loading commits: {0}")]
+        Load(String),
+        #[error("error exporting keyhive events: {0}")]
+        Keyhive(String),
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::atomic::AtomicU64;
+
+use crate::{sedimentree, CommitHash, DocumentId, TaskContext};
+
+// The size of every chunk returned by `next_export_chunk` except the last
+const EXPORT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Identifies an export started with `begin_export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExportHandle(u64);
+
+static LAST_EXPORT_HANDLE: AtomicU64 = AtomicU64::new(0);
+
+impl ExportHandle {
+    pub(crate) fn new() -> Self {
+        Self(crate::ids::next(&LAST_EXPORT_HANDLE))
+    }
+
+    pub fn serialize(&self) -> u64 {
+        self.0
+    }
+
+    pub fn from_serialized(serialized: u64) -> Self {
+        Self(serialized)
+    }
+}
+
+/// Start exporting the commits reachable from `heads`
+///
+/// Only the metadata of the commits and bundles to export is read here, their
+/// contents are loaded as chunks are requested.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn begin_export<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    heads: Vec<CommitHash>,
+) -> Result<ExportHandle, error::BeginExport>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    if heads.is_empty() {
+        return Err(error::BeginExport::NoHeads);
+    }
+    let tree = match ctx.state().docs().sedimentree(&doc_id) {
+        Some(tree) => tree,
+        None if ctx.state().keyhive().has_doc(&doc_id).await => Default::default(),
+        None => return Err(error::BeginExport::NoSuchDocument),
+    };
+    let subtree = tree
+        .ancestors_of(&heads)
+        .map_err(error::BeginExport::MissingHead)?;
+    Ok(ctx.state().exports().begin(doc_id, subtree.into_items()))
+}
+
+/// The next chunk of an export, or `None` once the export is exhausted
+///
+/// Items are loaded and decrypted until a full chunk is available, so at most
+/// one chunk plus one item is held in memory at a time. Once `None` has been
+/// returned, or any error, the handle is no longer valid.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn next_export_chunk<R>(
+    ctx: TaskContext<R>,
+    handle: ExportHandle,
+) -> Result<Option<Vec<u8>>, error::NextExportChunk>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let (doc_id, progress) = ctx
+        .state()
+        .exports()
+        .get(handle)
+        .ok_or(error::NextExportChunk::NoSuchExport)?;
+    let mut progress = progress.lock().await;
+    let storage = ctx.storage().doc_storage(doc_id);
+    while progress.buffer.len() < EXPORT_CHUNK_SIZE {
+        let Some(item) = progress.remaining.pop_front() else {
+            break;
+        };
+        let loaded = sedimentree::storage::item_data(&storage, item).await;
+        // The document may have been deleted while we were loading
+        if !ctx.state().exports().is_active(handle) {
+            return Err(error::NextExportChunk::NoSuchExport);
+        }
+        let (item, data) = loaded.map_err(|e| {
+            ctx.state().exports().finish(handle);
+            error::NextExportChunk::Load(e.to_string())
+        })?;
+        if let Some(item) = super::decode_tree_item(&ctx, doc_id, item, data, true).await {
+            item.encode_export_into(&mut progress.buffer);
+        }
+    }
+    if !ctx.state().exports().is_active(handle) {
+        return Err(error::NextExportChunk::NoSuchExport);
+    }
+    if progress.buffer.is_empty() {
+        tracing::debug!("export complete");
+        ctx.state().exports().finish(handle);
+        return Ok(None);
+    }
+    let chunk_len = progress.buffer.len().min(EXPORT_CHUNK_SIZE);
+    let rest = progress.buffer.split_off(chunk_len);
+    Ok(Some(std::mem::replace(&mut progress.buffer, rest)))
+}
+
+pub(crate) mod error {
+    use crate::CommitHash;
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum BeginExport {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("no heads to export from")]
+        NoHeads,
+        #[error("head {0} is not present locally")]
+        MissingHead(CommitHash),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum NextExportChunk {
+        /// The export has finished, failed, or its document was deleted
+        #[error("no such export")]
+        NoSuchExport,
+        #[error("error loading commits: {0}")]
+        Load(String),
+    }
+}
@@ -0,0 +1,40 @@
+use crate::{
+    network::{PeerAddress, RpcError},
+    sync::SyncSedimentreeError,
+    EndpointId, TaskContext,
+};
+
+/// Download the document the capability of `endpoint_id` is for from the
+/// peer who minted it
+///
+/// The commits are stored as they were received, without decrypting them,
+/// so unless we have been given access to the document they can only be
+/// loaded with `load_doc_encrypted`.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn fetch_with_capability<R>(
+    ctx: TaskContext<R>,
+    endpoint_id: EndpointId,
+) -> Result<(), super::error::FetchWithCapability>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let Some(capability) = ctx.state().endpoints().capability(endpoint_id) else {
+        return Err(super::error::FetchWithCapability::NoCapability);
+    };
+    match crate::sync::sync_sedimentree(
+        ctx.clone(),
+        PeerAddress::Endpoint(endpoint_id),
+        capability.issued_by(),
+        capability.doc_id(),
+        true,
+    )
+    .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(super::error::FetchWithCapability::Paused),
+        Err(SyncSedimentreeError::Rpc(RpcError::AuthorizationFailed)) => {
+            Err(super::error::FetchWithCapability::Rejected)
+        }
+        Err(e) => Err(super::error::FetchWithCapability::Failed(e.to_string())),
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    auth::{self, offset_seconds::OffsetSeconds, ReceiveMessageError, Signed},
+    capabilities,
+    keyhive::{error::InvalidDelegation, MemberAccess},
+    network::EndpointResponse,
+    serialization::Encode,
+    Audience, EndpointId, PeerId, Request, Response, TaskContext,
+};
+
+/// Which of our audiences an incoming request was received for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestTarget {
+    /// No receive audience was given so the request had to be addressed to
+    /// our peer ID
+    Peer,
+    /// The request was for `audience`, which is served by `endpoint_id`. For
+    /// endpoints with several audiences this is the audience the request was
+    /// addressed to.
+    Endpoint {
+        endpoint_id: EndpointId,
+        audience: Audience,
+    },
+    /// The request was for `audience` but no registered endpoint serves it
+    Unmatched(Audience),
+}
+
+/// A request which was served, along with the response to send back
+#[derive(Debug)]
+pub struct HandledRequest {
+    pub response: EndpointResponse,
+    pub target: RequestTarget,
+}
+
+pub(super) async fn handle_request<R>(
+    ctx: TaskContext<R>,
+    request: auth::Signed<auth::Message>,
+    receive_audience: Option<String>,
+) -> Result<HandledRequest, Box<error::HandleRequest>>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let recv_aud = receive_audience.map(|name| {
+        let audience = Audience::service_name(name);
+        // Endpoints with several audiences accept a request addressed to any
+        // of them
+        let addressed_to = request.payload.audience;
+        if ctx
+            .state()
+            .endpoints()
+            .audiences_matching(audience)
+            .contains(&addressed_to)
+        {
+            addressed_to
+        } else {
+            audience
+        }
+    });
+    let target = match recv_aud {
+        None => RequestTarget::Peer,
+        Some(audience) => match ctx.state().endpoints().endpoint_for_audience(audience) {
+            Some(endpoint_id) => RequestTarget::Endpoint {
+                endpoint_id,
+                audience,
+            },
+            None => RequestTarget::Unmatched(audience),
+        },
+    };
+    match serve(ctx, request, recv_aud).await {
+        Ok(response) => Ok(HandledRequest { response, target }),
+        Err((reason, response)) => Err(Box::new(error::HandleRequest {
+            reason,
+            response,
+            target,
+        })),
+    }
+}
+
+async fn serve<R>(
+    ctx: TaskContext<R>,
+    request: auth::Signed<auth::Message>,
+    recv_aud: Option<Audience>,
+) -> Result<EndpointResponse, (error::RequestFailure, EndpointResponse)>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let (request, reply_to) = match auth::receive::<Request>(
+        ctx.now().as_secs(),
+        request,
+        &ctx.state().our_peer_id(),
+        recv_aud,
+    ) {
+        Ok(authed) => (authed.content, PeerId::from(authed.from)),
+        Err(e) => {
+            tracing::debug!(err=?e, "failed to authenticate incoming message");
+            let reason = match e {
+                ReceiveMessageError::AudienceMismatch { expected, actual } => {
+                    error::RequestFailure::AudienceMismatch { expected, actual }
+                }
+                ReceiveMessageError::Expired => error::RequestFailure::Expired,
+                other => error::RequestFailure::AuthenticationFailed(other.to_string()),
+            };
+            let response = sign_response(
+                &ctx,
+                Audience::peer(&ctx.state().our_peer_id()),
+                Response::AuthenticationFailed,
+            )
+            .await;
+            return Err((reason, response));
+        }
+    };
+
+    // Requests for documents we have never heard of are answered before doing
+    // any keyhive or storage work. The response is the same as for a document
+    // the requestor can't access so as not to reveal which documents exist.
+    if let Some(doc_id) = request.doc_id() {
+        if !ctx.state().keyhive().has_doc(&doc_id).await {
+            tracing::debug!(%doc_id, "request for unknown document");
+            let response = sign_response(
+                &ctx,
+                Audience::peer(&reply_to),
+                Response::AuthorizationFailed,
+            )
+            .await;
+            return Err((error::RequestFailure::DocumentNotFound(doc_id), response));
+        }
+    }
+
+    // A delegated request is served as though it came from the group it was
+    // made on behalf of, once we've checked the sender belongs to the group.
+    // The group's access to the document is checked when the request is
+    // served, here we check that the sender's own access in the group also
+    // allows it, so that the sender gets the lesser of the two.
+    let (request, from, with_capability) = match request {
+        Request::Delegated {
+            on_behalf_of,
+            chain,
+            request,
+        } => {
+            let verified = match ctx
+                .state()
+                .keyhive()
+                .verify_delegation(reply_to, on_behalf_of, chain)
+                .await
+            {
+                Ok(access) if access < access_needed(&request) => {
+                    Err(InvalidDelegation::InsufficientAccess {
+                        group: on_behalf_of,
+                        access,
+                    })
+                }
+                Ok(_) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = verified {
+                tracing::debug!(err=?e, %on_behalf_of, "invalid delegation");
+                let reason = error::RequestFailure::InvalidDelegation {
+                    on_behalf_of,
+                    reason: e,
+                };
+                let response = sign_response(
+                    &ctx,
+                    Audience::peer(&reply_to),
+                    Response::AuthorizationFailed,
+                )
+                .await;
+                return Err((reason, response));
+            }
+            (*request, on_behalf_of, false)
+        }
+        // A request made with a capability is authorized by the capability
+        // alone, the sender needn't have any access to the document
+        Request::WithCapability {
+            capability,
+            request,
+        } => {
+            if let Err(reason) = capabilities::check(&ctx, &capability, &request) {
+                let id = capability.payload.id;
+                tracing::debug!(err=?reason, %id, "capability rejected");
+                let reason = error::RequestFailure::InvalidCapability { id, reason };
+                let response = sign_response(
+                    &ctx,
+                    Audience::peer(&reply_to),
+                    Response::AuthorizationFailed,
+                )
+                .await;
+                return Err((reason, response));
+            }
+            (*request, reply_to, true)
+        }
+        request => (request, reply_to, false),
+    };
+
+    let doc_id = request.doc_id();
+    let result = if with_capability {
+        crate::request_handlers::handle_capability_request(ctx.clone(), request, from).await
+    } else {
+        crate::request_handlers::handle_request(ctx.clone(), None, request, from).await
+    };
+    let reason = match (&result, doc_id) {
+        (Response::AuthorizationFailed, Some(doc_id)) => {
+            Some(error::RequestFailure::AccessDenied(doc_id))
+        }
+        (Response::Error(e), _) => Some(error::RequestFailure::Internal(e.clone())),
+        _ => None,
+    };
+    let response = sign_response(&ctx, Audience::peer(&reply_to), result).await;
+    match reason {
+        Some(reason) => Err((reason, response)),
+        None => Ok(response),
+    }
+}
+
+/// The least access within a group which lets a member make `request` on the
+/// group's behalf
+fn access_needed(request: &Request) -> MemberAccess {
+    match request {
+        Request::UploadCommits { .. } | Request::UploadBlob(_) => MemberAccess::Write,
+        _ => MemberAccess::Pull,
+    }
+}
+
+async fn sign_response<R>(
+    ctx: &TaskContext<R>,
+    audience: Audience,
+    response: Response,
+) -> EndpointResponse
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let msg = auth::send(
+        ctx.now().as_secs(),
+        OffsetSeconds(0),
+        audience,
+        response.encode(),
+    );
+    let signed = Signed::try_sign(ctx.signer(), msg)
+        .await
+        .expect("should never fail");
+    EndpointResponse(signed)
+}
+
+pub(crate) mod error {
+    use super::RequestTarget;
+    use crate::{
+        keyhive::error::InvalidDelegation, network::EndpointResponse, Audience, CapabilityId,
+        DocumentId, PeerId,
+    };
+
+    /// An incoming request which was not served
+    ///
+    /// The requestor is still owed a response, `response` is the signed
+    /// failure response which should be returned to them.
+    #[derive(Debug, thiserror::Error)]
+    #[error("{reason}")]
+    pub struct HandleRequest {
+        pub reason: RequestFailure,
+        pub response: EndpointResponse,
+        pub target: RequestTarget,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+    pub enum RequestFailure {
+        /// The request was addressed to a different audience. `expected` is
+        /// the receive audience passed to `handle_request` if there was one
+        /// and our peer ID otherwise.
+        #[error("request addressed to {actual} but expected {expected}")]
+        AudienceMismatch {
+            expected: Audience,
+            actual: Audience,
+        },
+        #[error("request expired")]
+        Expired,
+        #[error("authentication failed: {0}")]
+        AuthenticationFailed(String),
+        #[error("document {0} not found")]
+        DocumentNotFound(DocumentId),
+        #[error("access to document {0} denied")]
+        AccessDenied(DocumentId),
+        #[error("error handling request: {0}")]
+        Internal(String),
+        /// The request was made on behalf of a group but the sender could not
+        /// be shown to be a member of it
+        #[error("invalid delegation from {on_behalf_of}: {reason}")]
+        InvalidDelegation {
+            on_behalf_of: PeerId,
+            reason: InvalidDelegation,
+        },
+        /// The request was made with a capability which doesn't allow it
+        #[error("capability {id} rejected: {reason}")]
+        InvalidCapability {
+            id: CapabilityId,
+            reason: CapabilityRejected,
+        },
+    }
+
+    /// Why a capability did not authorize a request
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+    pub enum CapabilityRejected {
+        /// The capability was minted by a different peer
+        #[error("not minted by us")]
+        NotOurs,
+        #[error("bad signature")]
+        BadSignature,
+        #[error("revoked")]
+        Revoked,
+        #[error("expired")]
+        Expired,
+        /// The request is about a different document to the capability
+        #[error("for a different document")]
+        WrongDocument,
+        /// The request is not one the capability's access level allows
+        #[error("request not permitted")]
+        NotPermitted,
+    }
+}
@@ -1,22 +1,54 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use error::AddMember;
 use keyhive_core::{
+    crypto::verifiable::Verifiable,
     listener::{cgka::CgkaListener, membership::MembershipListener, prekey::PrekeyListener},
     principal::public::Public,
 };
 
-use crate::{contact_card::ContactCard, io::Signer, CommitHash, DocumentId, PeerId, TaskContext};
+use crate::{
+    contact_card::ContactCard,
+    group_metadata, identity_export,
+    io::Signer,
+    keyhive_storage, membership_expiry,
+    serialization::{parse, Encode, Parse},
+    CommitHash, DocumentId, PeerId, TaskContext, UnixTimestamp,
+};
 
 #[derive(Debug)]
 pub enum KeyhiveCommand {
     CreateGroup(Vec<KeyhiveEntityId>),
+    CreateGroupWithMembers(Vec<KeyhiveEntityId>, Vec<(KeyhiveEntityId, MemberAccess)>),
     CreateContactCard,
     AddMemberToGroup(AddMemberToGroup),
     RemoveMemberFromGroup(RemoveMemberFromGroup),
-    AddMemberToDoc(DocumentId, KeyhiveEntityId, MemberAccess),
+    AddMemberToDoc(
+        DocumentId,
+        KeyhiveEntityId,
+        MemberAccess,
+        Option<UnixTimestamp>,
+    ),
     RemoveMemberFromDoc(DocumentId, KeyhiveEntityId),
+    ChangeMemberAccess(DocumentId, KeyhiveEntityId, MemberAccess),
+    RotateDocKey(DocumentId),
     QueryAccess(DocumentId),
+    QueryAccessFor(DocumentId, KeyhiveEntityId),
+    QueryAccessMulti(Vec<DocumentId>),
+    ListDocMembers(DocumentId),
+    ExpandGroup(PeerId),
+    ListMyGroups,
+    SetGroupMetadata(PeerId, GroupMetadata),
+    GetGroupMetadata(PeerId),
+    PreviewAccessChange(AccessChange),
+    LocalIdentity,
+    ListContacts,
+    ExportIdentity(String),
+    ImportIdentity(Vec<u8>, String),
+    ExportAccessLog(DocumentId),
+    DocKeyEpochs(DocumentId),
+    ListScheduledRotations(DocumentId),
+    CancelScheduledRotation(DocumentId, RotationId),
     #[cfg(feature = "debug_events")]
     DebugEvents(keyhive_core::debug_events::Nicknames),
 }
@@ -24,12 +56,37 @@ pub enum KeyhiveCommand {
 #[derive(Debug)]
 pub enum KeyhiveCommandResult {
     CreateGroup(Result<PeerId, error::CreateGroup>),
+    CreateGroupWithMembers(Result<CreatedGroup, error::CreateGroupWithMembers>),
     CreateContactCard(Result<ContactCard, error::CreateContactCard>),
     AddMemberToGroup(Result<(), error::AddMember>),
     RemoveMemberFromGroup(Result<(), error::RemoveMember>),
     AddMemberToDoc,
     RemoveMemberFromDoc(Result<(), error::RemoveMember>),
+    /// The access the member had before the change
+    ChangeMemberAccess(Result<MemberAccess, error::ChangeMemberAccess>),
+    /// The key epoch of the document after the rotation
+    RotateDocKey(Result<u64, error::RotateDocKey>),
     QueryAccess(Result<HashMap<PeerId, MemberAccess>, error::QueryAccess>),
+    /// `None` if the entity has no access to the document
+    QueryAccessFor(Result<Option<MemberAccess>, error::QueryAccessFor>),
+    /// Our access to each document, `None` if we can't see it
+    QueryAccessMulti(HashMap<DocumentId, Option<MemberAccess>>),
+    ListDocMembers(Result<Vec<DocMember>, error::QueryAccess>),
+    ExpandGroup(Result<Vec<GroupMember>, error::ExpandGroup>),
+    ListMyGroups(Vec<GroupMembership>),
+    SetGroupMetadata(Result<(), error::SetGroupMetadata>),
+    /// The metadata of the group, which is empty if none has been set
+    GetGroupMetadata(Result<GroupMetadata, error::ExpandGroup>),
+    PreviewAccessChange(Result<AccessDiff, error::PreviewAccessChange>),
+    LocalIdentity(Box<LocalIdentity>),
+    ListContacts(Vec<Contact>),
+    /// The encrypted identity, to be passed to `import_identity`
+    ExportIdentity(Result<Vec<u8>, error::ExportIdentity>),
+    ImportIdentity(Result<(), error::ImportIdentity>),
+    ExportAccessLog(Result<Vec<AccessLogEntry>, error::ExportAccessLog>),
+    DocKeyEpochs(Result<DocKeyEpochs, error::DocKeyEpochs>),
+    ListScheduledRotations(Result<Vec<ScheduledRotation>, error::ListScheduledRotations>),
+    CancelScheduledRotation(Result<(), error::CancelScheduledRotation>),
     #[cfg(feature = "debug_events")]
     DebugEvents(keyhive_core::debug_events::DebugEventTable),
 }
@@ -41,6 +98,7 @@ pub enum Access {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
 pub enum MemberAccess {
     Pull,
     Read,
@@ -48,6 +106,43 @@ pub enum MemberAccess {
     Admin,
 }
 
+impl MemberAccess {
+    /// Every access level, from lowest to highest
+    pub const ALL: [MemberAccess; 4] = [
+        MemberAccess::Pull,
+        MemberAccess::Read,
+        MemberAccess::Write,
+        MemberAccess::Admin,
+    ];
+}
+
+impl Encode for MemberAccess {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            MemberAccess::Pull => 0,
+            MemberAccess::Read => 1,
+            MemberAccess::Write => 2,
+            MemberAccess::Admin => 3,
+        });
+    }
+}
+
+impl<'a> Parse<'a> for MemberAccess {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        input.parse_in_ctx("MemberAccess", |input| {
+            let (input, tag) = parse::u8(input)?;
+            let access = match tag {
+                0 => MemberAccess::Pull,
+                1 => MemberAccess::Read,
+                2 => MemberAccess::Write,
+                3 => MemberAccess::Admin,
+                other => return Err(input.error(format!("invalid member access: {}", other))),
+            };
+            Ok((input, access))
+        })
+    }
+}
+
 impl std::fmt::Display for MemberAccess {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -124,6 +219,201 @@ impl From<Public> for KeyhiveEntityId {
     }
 }
 
+/// A member of a document along with their effective access
+#[derive(Debug, Clone)]
+pub struct DocMember {
+    pub member: KeyhiveEntityId,
+    pub access: MemberAccess,
+    /// Whether the member was added to the document directly rather than
+    /// inheriting access through a group
+    pub direct: bool,
+}
+
+/// A transitive member of a group along with their effective access
+#[derive(Debug, Clone)]
+pub struct GroupMember {
+    pub member: KeyhiveEntityId,
+    pub access: MemberAccess,
+}
+
+/// A group created by `Event::create_group_with_members`
+#[derive(Debug, Clone)]
+pub struct CreatedGroup {
+    pub group_id: PeerId,
+    /// The access each requested member ended up with, in the order they
+    /// were requested
+    pub members: Vec<GroupMember>,
+}
+
+/// A group which the local node is a transitive member of, along with our
+/// effective access to it
+#[derive(Debug, Clone)]
+pub struct GroupMembership {
+    pub group: KeyhiveEntityId,
+    pub access: MemberAccess,
+}
+
+/// A display name and arbitrary key/value pairs attached to a group
+///
+/// Metadata is purely descriptive, it has no effect on who can access the
+/// group or anything the group is a member of. Concurrent edits are resolved
+/// by keeping the most recent one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub struct GroupMetadata {
+    pub name: Option<String>,
+    pub entries: BTreeMap<String, String>,
+}
+
+/// A change to the membership of a group which can be previewed with
+/// `Event::preview_access_change`
+#[derive(Debug)]
+pub enum AccessChange {
+    AddMember(AddMemberToGroup),
+    RemoveMember(RemoveMemberFromGroup),
+}
+
+/// The effect an `AccessChange` would have on the transitive members of a
+/// group
+///
+/// Access levels are cumulative, so an entity going from `Read` to `Admin`
+/// appears under `Write` and `Admin` in `gained` and an entity which is
+/// removed entirely appears under every level it had in `lost`.
+#[derive(Debug, Clone, Default)]
+pub struct AccessDiff {
+    pub gained: BTreeMap<MemberAccess, Vec<KeyhiveEntityId>>,
+    pub lost: BTreeMap<MemberAccess, Vec<KeyhiveEntityId>>,
+}
+
+impl AccessDiff {
+    pub fn is_empty(&self) -> bool {
+        self.gained.is_empty() && self.lost.is_empty()
+    }
+}
+
+/// The identity of the local node
+#[derive(Debug, Clone)]
+pub struct LocalIdentity {
+    /// The entity ID to use when referring to this node in keyhive commands
+    pub entity_id: KeyhiveEntityId,
+    /// The public half of the key this node signs with
+    pub verifying_key: ed25519_dalek::VerifyingKey,
+    /// The contact cards created by `create_contact_card`, in no particular order
+    pub contact_cards: Vec<ContactCard>,
+}
+
+/// Another individual known to keyhive, as returned by `Event::list_contacts`
+#[derive(Debug, Clone)]
+pub struct Contact {
+    /// The entity ID to use when referring to the contact in keyhive commands
+    pub id: KeyhiveEntityId,
+    pub peer_id: PeerId,
+    /// The public prekeys the contact has published, one of which is used
+    /// when they are added to a document
+    pub share_keys: Vec<[u8; 32]>,
+    /// Whether the contact was removed from every group and document they
+    /// were added to
+    pub revoked: bool,
+}
+
+/// A keyhive event which changed who can access a document, as returned by
+/// `Event::export_access_log`
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    /// The hash of `event`, which is the same on every node
+    pub digest: [u8; 32],
+    /// The peer which signed the event
+    pub author: PeerId,
+    /// When this node first saw the event. This is `None` for events which
+    /// were stored before event times were recorded.
+    pub recorded_at: Option<UnixTimestamp>,
+    pub change: AccessLogChange,
+    /// The signed event in the same encoding that is used to sync it, so it
+    /// can be verified independently
+    pub event: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogChange {
+    /// `member` was granted `access` to the document
+    Added {
+        member: PeerId,
+        access: MemberAccess,
+    },
+    /// A grant of `access` to `member` was revoked
+    Removed {
+        member: PeerId,
+        access: MemberAccess,
+    },
+    /// `member` was given a share of the document key
+    KeyShareAdded { member: PeerId },
+    /// `member`'s share of the document key was removed
+    KeyShareRemoved { member: PeerId },
+    /// The author of the entry rotated the document key
+    KeyRotated,
+}
+
+/// The key epochs of a document, as returned by `Event::doc_key_epochs`
+#[derive(Debug, Clone)]
+pub struct DocKeyEpochs {
+    /// The epoch commits are currently encrypted under. Epochs are numbered
+    /// from 1 and this is also the number of epochs the document has had.
+    /// This is 0 if we have none of the document's key material.
+    pub current: u64,
+    /// The epochs each individual member of the document held a share of
+    /// the document key in. This is `None` unless we are an admin of the
+    /// document.
+    pub members: Option<Vec<MemberKeyEpochs>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberKeyEpochs {
+    pub member: PeerId,
+    pub access: MemberAccess,
+    /// The epochs the member held a share of the document key in, oldest
+    /// first
+    pub epochs: Vec<u64>,
+}
+
+impl MemberKeyEpochs {
+    /// Whether the member holds no share of the document key at all, so
+    /// can't decrypt any of the document
+    pub fn locked_out(&self) -> bool {
+        self.epochs.is_empty()
+    }
+}
+
+/// A rotation of a document's key which will happen without anyone asking
+/// for it, as returned by `Event::list_scheduled_rotations`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledRotation {
+    /// Pass this to `Event::cancel_scheduled_rotation` to cancel it
+    pub id: RotationId,
+    pub at: UnixTimestamp,
+    pub reason: RotationReason,
+}
+
+/// Identifies a scheduled rotation. An ID only matches the schedule it was
+/// listed with, so it stops matching if the schedule is changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RotationId {
+    pub(crate) target: PeerId,
+    pub(crate) member: PeerId,
+    pub(crate) at: UnixTimestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationReason {
+    /// The membership of `member` expires, after which they are revoked and
+    /// the key is rotated so they can't read anything new
+    MembershipExpires {
+        member: PeerId,
+        /// The group whose membership expires, `None` if `member` is a
+        /// member of the document itself
+        via_group: Option<PeerId>,
+    },
+}
+
 pub(crate) async fn handle_keyhive_command<R>(
     ctx: TaskContext<R>,
     command: KeyhiveCommand,
@@ -132,15 +422,24 @@ where
     R: rand::Rng + rand::CryptoRng + 'static,
 {
     match command {
-        KeyhiveCommand::AddMemberToDoc(doc_id, contact_card, access) => {
-            add_member_to_doc(ctx, doc_id, contact_card, access).await;
+        KeyhiveCommand::AddMemberToDoc(doc_id, contact_card, access, expires_at) => {
+            add_member_to_doc(ctx, doc_id, contact_card, access, expires_at).await;
             KeyhiveCommandResult::AddMemberToDoc
         }
         KeyhiveCommand::RemoveMemberFromDoc(doc_id, peer_id) => {
             let result = remove_member_from_doc(ctx, doc_id, peer_id).await;
             KeyhiveCommandResult::RemoveMemberFromDoc(result)
         }
+        KeyhiveCommand::ChangeMemberAccess(doc_id, member, access) => {
+            let result = change_member_access(ctx, doc_id, member, access).await;
+            KeyhiveCommandResult::ChangeMemberAccess(result)
+        }
+        KeyhiveCommand::RotateDocKey(doc_id) => {
+            let result = rotate_doc_key(ctx, doc_id).await;
+            KeyhiveCommandResult::RotateDocKey(result)
+        }
         KeyhiveCommand::QueryAccess(doc_id) => {
+            membership_expiry::prune_expired(ctx.clone()).await;
             let result = ctx
                 .state()
                 .keyhive()
@@ -149,6 +448,112 @@ where
                 .ok_or(error::QueryAccess::NoSuchDocument);
             KeyhiveCommandResult::QueryAccess(result)
         }
+        KeyhiveCommand::QueryAccessFor(doc_id, entity) => {
+            membership_expiry::prune_expired(ctx.clone()).await;
+            let result = ctx.state().keyhive().access_for(doc_id, &entity).await;
+            KeyhiveCommandResult::QueryAccessFor(result)
+        }
+        KeyhiveCommand::QueryAccessMulti(doc_ids) => {
+            membership_expiry::prune_expired(ctx.clone()).await;
+            let result = ctx.state().keyhive().our_access_multi(doc_ids).await;
+            KeyhiveCommandResult::QueryAccessMulti(result)
+        }
+        KeyhiveCommand::ListDocMembers(doc_id) => {
+            membership_expiry::prune_expired(ctx.clone()).await;
+            let result = ctx
+                .state()
+                .keyhive()
+                .list_doc_members(doc_id)
+                .await
+                .ok_or(error::QueryAccess::NoSuchDocument);
+            KeyhiveCommandResult::ListDocMembers(result)
+        }
+        KeyhiveCommand::ExpandGroup(group_id) => {
+            membership_expiry::prune_expired(ctx.clone()).await;
+            let result = ctx
+                .state()
+                .keyhive()
+                .expand_group(group_id)
+                .await
+                .ok_or(error::ExpandGroup::NoSuchGroup);
+            KeyhiveCommandResult::ExpandGroup(result)
+        }
+        KeyhiveCommand::ListMyGroups => {
+            membership_expiry::prune_expired(ctx.clone()).await;
+            let groups = ctx.state().keyhive().groups_of_local_identity().await;
+            KeyhiveCommandResult::ListMyGroups(groups)
+        }
+        KeyhiveCommand::SetGroupMetadata(group_id, metadata) => {
+            let result = group_metadata::set_metadata(&ctx, group_id, metadata).await;
+            KeyhiveCommandResult::SetGroupMetadata(result)
+        }
+        KeyhiveCommand::GetGroupMetadata(group_id) => {
+            let result = if ctx.state().keyhive().has_group(group_id).await {
+                Ok(ctx
+                    .state()
+                    .group_metadata()
+                    .get(group_id)
+                    .map(|record| record.payload.metadata)
+                    .unwrap_or_default())
+            } else {
+                Err(error::ExpandGroup::NoSuchGroup)
+            };
+            KeyhiveCommandResult::GetGroupMetadata(result)
+        }
+        KeyhiveCommand::PreviewAccessChange(change) => {
+            let (group_id, member, access) = match change {
+                AccessChange::AddMember(add) => (add.group_id, add.member, Some(add.access)),
+                AccessChange::RemoveMember(remove) => (remove.group_id, remove.member, None),
+            };
+            let result = ctx
+                .state()
+                .keyhive()
+                .preview_access_change(group_id, member, access)
+                .await;
+            KeyhiveCommandResult::PreviewAccessChange(result)
+        }
+        KeyhiveCommand::LocalIdentity => {
+            let (entity_id, contact_cards) = ctx.state().keyhive().local_identity().await;
+            KeyhiveCommandResult::LocalIdentity(Box::new(LocalIdentity {
+                entity_id,
+                verifying_key: ctx.signer().verifying_key(),
+                contact_cards,
+            }))
+        }
+        KeyhiveCommand::ListContacts => {
+            KeyhiveCommandResult::ListContacts(ctx.state().keyhive().contacts().await)
+        }
+        KeyhiveCommand::ExportIdentity(passphrase) => {
+            let result = identity_export::export(ctx.clone(), &passphrase).await;
+            KeyhiveCommandResult::ExportIdentity(result)
+        }
+        KeyhiveCommand::ImportIdentity(blob, passphrase) => {
+            let result = identity_export::import(ctx.clone(), &blob, &passphrase).await;
+            KeyhiveCommandResult::ImportIdentity(result)
+        }
+        KeyhiveCommand::ExportAccessLog(doc_id) => {
+            let recorded = keyhive_storage::load_event_times(ctx.clone()).await;
+            let result = ctx
+                .state()
+                .keyhive()
+                .access_log(doc_id, &recorded)
+                .await
+                .ok_or(error::ExportAccessLog::NoSuchDocument)
+                .and_then(|r| r.map_err(|e| error::ExportAccessLog::Failed(e.to_string())));
+            KeyhiveCommandResult::ExportAccessLog(result)
+        }
+        KeyhiveCommand::DocKeyEpochs(doc_id) => {
+            KeyhiveCommandResult::DocKeyEpochs(doc_key_epochs(ctx, doc_id).await)
+        }
+        KeyhiveCommand::ListScheduledRotations(doc_id) => {
+            let result = membership_expiry::scheduled_rotations(ctx, doc_id).await;
+            KeyhiveCommandResult::ListScheduledRotations(result)
+        }
+        KeyhiveCommand::CancelScheduledRotation(doc_id, rotation_id) => {
+            let result =
+                membership_expiry::cancel_scheduled_rotation(ctx, doc_id, rotation_id).await;
+            KeyhiveCommandResult::CancelScheduledRotation(result)
+        }
         KeyhiveCommand::CreateGroup(other_parents) => {
             let result = ctx
                 .state()
@@ -158,6 +563,14 @@ where
                 .map_err(|e| e.into());
             KeyhiveCommandResult::CreateGroup(result)
         }
+        KeyhiveCommand::CreateGroupWithMembers(owners, members) => {
+            let result = ctx
+                .state()
+                .keyhive()
+                .create_group_with_members(owners, members)
+                .await;
+            KeyhiveCommandResult::CreateGroupWithMembers(result)
+        }
         KeyhiveCommand::AddMemberToGroup(add) => {
             let result = add_member_to_group(ctx, add).await;
             KeyhiveCommandResult::AddMemberToGroup(result)
@@ -189,13 +602,18 @@ async fn add_member_to_doc<R: rand::Rng + rand::CryptoRng + 'static>(
     doc_id: DocumentId,
     peer_to_add: KeyhiveEntityId,
     access: MemberAccess,
+    expires_at: Option<UnixTimestamp>,
 ) {
     tracing::debug!("adding member to document");
     if let Some(agent) = ctx.state().keyhive().get_agent(peer_to_add).await {
+        let member_id = PeerId::from(agent.id().0);
         ctx.state()
             .keyhive()
             .add_member_to_doc(doc_id, agent, access.into())
             .await;
+        membership_expiry::set_expiry(&ctx, PeerId::from(*doc_id.as_key()), member_id, expires_at)
+            .await;
+        crate::access_requests::granted(&ctx, doc_id, member_id, access);
     } else {
         tracing::error!("member not found");
     }
@@ -211,6 +629,20 @@ where
     R: rand::Rng + rand::CryptoRng + 'static,
 {
     tracing::debug!("removing member from document");
+    if let Some(agent) = ctx
+        .state()
+        .keyhive()
+        .get_agent(peer_to_remove.clone())
+        .await
+    {
+        membership_expiry::set_expiry(
+            &ctx,
+            PeerId::from(*doc_id.as_key()),
+            PeerId::from(agent.id().0),
+            None,
+        )
+        .await;
+    }
     ctx.state()
         .keyhive()
         .remove_member_from_doc(doc_id, peer_to_remove)
@@ -219,11 +651,67 @@ where
     Ok(())
 }
 
+#[tracing::instrument(skip(ctx, member),fields(member=%member))]
+async fn change_member_access<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    member: KeyhiveEntityId,
+    access: MemberAccess,
+) -> Result<MemberAccess, error::ChangeMemberAccess>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    tracing::debug!("changing member access");
+    ctx.state()
+        .keyhive()
+        .change_member_access(doc_id, member, access)
+        .await
+}
+
+#[tracing::instrument(skip(ctx))]
+async fn rotate_doc_key<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Result<u64, error::RotateDocKey>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    tracing::debug!("rotating document key");
+    ctx.state()
+        .keyhive()
+        .rotate_doc_key(doc_id)
+        .await
+        .map_err(error::RotateDocKey::from)
+}
+
+#[tracing::instrument(skip(ctx))]
+async fn doc_key_epochs<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Result<DocKeyEpochs, error::DocKeyEpochs>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let keyhive = ctx.state().keyhive();
+    let mut epochs = keyhive
+        .key_epochs(doc_id)
+        .await
+        .ok_or(error::DocKeyEpochs::NoSuchDocument)?
+        .map_err(|e| error::DocKeyEpochs::Failed(e.to_string()))?;
+    // Who holds which keys is only shown to those who can fix it
+    if !keyhive.can_admin(ctx.state().our_peer_id(), &doc_id).await {
+        epochs.members = None;
+    }
+    Ok(epochs)
+}
+
 #[derive(Debug)]
 pub struct AddMemberToGroup {
     pub group_id: PeerId,
     pub member: KeyhiveEntityId,
     pub access: MemberAccess,
+    /// If set, the membership is revoked once this time has passed
+    pub expires_at: Option<UnixTimestamp>,
 }
 
 #[tracing::instrument(skip(ctx),fields(group_id=%group_id, member_id=%member))]
@@ -233,6 +721,7 @@ async fn add_member_to_group<R>(
         group_id,
         member,
         access,
+        expires_at,
     }: AddMemberToGroup,
 ) -> Result<(), error::AddMember>
 where
@@ -240,10 +729,21 @@ where
 {
     tracing::debug!("adding member to group");
     if let Some(agent) = ctx.state().keyhive().get_agent(member).await {
+        if ctx
+            .state()
+            .keyhive()
+            .would_create_cycle(group_id, &agent)
+            .await
+        {
+            tracing::debug!("refusing to add member which would create a cycle");
+            return Err(AddMember::WouldCreateCycle);
+        }
+        let member_id = PeerId::from(agent.id().0);
         ctx.state()
             .keyhive()
             .add_member_to_group(group_id, agent, access.into())
             .await;
+        membership_expiry::set_expiry(&ctx, group_id, member_id, expires_at).await;
         Ok(())
     } else {
         tracing::error!("member not found");
@@ -267,6 +767,7 @@ where
 {
     tracing::debug!("removing member from group");
     if let Some(agent) = ctx.state().keyhive().get_agent(member).await {
+        membership_expiry::set_expiry(&ctx, group_id, PeerId::from(agent.id().0), None).await;
         ctx.state()
             .keyhive()
             .remove_member_from_group(group_id, agent)
@@ -279,18 +780,64 @@ where
 }
 
 pub(crate) mod error {
-    use crate::{CommitHash, Signer};
+    use super::MemberAccess;
+    use crate::{CommitHash, PeerId, Signer};
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("error exporting identity: {0}")]
+    pub struct ExportIdentity(pub(crate) String);
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ImportIdentity {
+        #[error("not an exported identity")]
+        Malformed,
+        #[error("wrong passphrase")]
+        WrongPassphrase,
+        #[error("the identity was exported by a different peer")]
+        WrongIdentity,
+        #[error("error importing identity: {0}")]
+        Ingest(String),
+    }
 
     #[derive(Debug, thiserror::Error)]
     pub enum AddMember {
         #[error("peer not found")]
         MemberNotFound,
+        #[error("the group is already a member of the member being added")]
+        WouldCreateCycle,
     }
 
     #[derive(Debug, thiserror::Error)]
     #[error("{0}")]
     pub struct RemoveMember(pub(super) String);
 
+    #[derive(Debug, thiserror::Error)]
+    pub enum ChangeMemberAccess {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("not a member of the document")]
+        NotAMember,
+        #[error("error changing access: {0}")]
+        Failed(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum RotateDocKey {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("error rotating key: {0}")]
+        Failed(String),
+    }
+
+    impl From<crate::state::keyhive::RotateKeyError> for RotateDocKey {
+        fn from(err: crate::state::keyhive::RotateKeyError) -> Self {
+            match err {
+                crate::state::keyhive::RotateKeyError::NoSuchDocument => Self::NoSuchDocument,
+                other => Self::Failed(other.to_string()),
+            }
+        }
+    }
+
     #[derive(Debug, thiserror::Error)]
     pub(crate) enum ReceiveEventError {
         #[error(transparent)]
@@ -306,6 +853,93 @@ pub(crate) mod error {
         NoSuchDocument,
     }
 
+    #[derive(Debug, thiserror::Error)]
+    pub enum QueryAccessFor {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("only admins of the document can query the access of others")]
+        NotAllowed,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ExportAccessLog {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("error reading key history: {0}")]
+        Failed(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum DocKeyEpochs {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("error reading key history: {0}")]
+        Failed(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ListScheduledRotations {
+        #[error("document not found")]
+        NoSuchDocument,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum CancelScheduledRotation {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("only admins of the document can cancel its rotations")]
+        NotAllowed,
+        #[error("the rotation has already been applied")]
+        AlreadyApplied,
+        #[error("no such scheduled rotation")]
+        NoSuchRotation,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum ExpandGroup {
+        #[error("group not found")]
+        NoSuchGroup,
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum SetGroupMetadata {
+        #[error("group not found")]
+        NoSuchGroup,
+        #[error("only members with write access can set group metadata")]
+        NotAllowed,
+        #[error("error signing metadata: {0}")]
+        Signing(String),
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    pub enum PreviewAccessChange {
+        #[error("group not found")]
+        NoSuchGroup,
+        #[error("peer not found")]
+        MemberNotFound,
+        #[error("the group is already a member of the member being added")]
+        WouldCreateCycle,
+    }
+
+    /// Why a delegation to act on behalf of a group could not be used
+    #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+    pub enum InvalidDelegation {
+        #[error("only groups can be delegated from")]
+        NotAGroup,
+        #[error("group {0} not found")]
+        NoSuchGroup(PeerId),
+        /// The delegated peer is not, or is no longer, a member of the group
+        #[error("not a member of group {0}")]
+        NotAMember(PeerId),
+        /// The delegated peer's own access in the group doesn't allow the
+        /// request, whatever access the group has
+        #[error("{access:?} access to group {group} does not allow the request")]
+        InsufficientAccess { group: PeerId, access: MemberAccess },
+        /// The delegation chain sent with a request could not be ingested
+        #[error("invalid delegation chain: {0}")]
+        InvalidChain(String),
+    }
+
     #[derive(Debug, thiserror::Error)]
     #[error("error creating group: {0}")]
     pub struct CreateGroup(String);
@@ -316,6 +950,20 @@ pub(crate) mod error {
         }
     }
 
+    #[derive(Debug, thiserror::Error)]
+    pub enum CreateGroupWithMembers {
+        /// An owner or member is a group we don't know about. Nothing was
+        /// created.
+        #[error("group not found")]
+        NoSuchGroup,
+        /// An owner or member is a document we don't know about. Nothing was
+        /// created.
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("error creating group: {0}")]
+        Failed(String),
+    }
+
     #[derive(Debug, thiserror::Error)]
     #[error("error creating contact card: {0}")]
     pub struct CreateContactCard(String);
@@ -0,0 +1,113 @@
+use crate::{
+    keyhive::MemberAccess, CommitBundle, CommitHash, CommitOrBundle, DocumentId, TaskContext,
+};
+
+/// Copy the history of `from` into `into`, returning the heads of `into`
+/// afterwards
+///
+/// The commits and bundles of `from` are decrypted and re-encrypted under the
+/// keys of `into`, so the commits keep their hashes and parents and the heads
+/// of the two documents end up side by side. Every direct member of `from` is
+/// granted at least the same access to `into`, which requires admin access to
+/// `into`. `from` itself is left as it is.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn merge_docs<R>(
+    mut ctx: TaskContext<R>,
+    into: DocumentId,
+    from: DocumentId,
+) -> Result<Vec<CommitHash>, error::MergeDocs>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    if into == from {
+        return Err(error::MergeDocs::SameDocument);
+    }
+    if !ctx.state().keyhive().has_doc(&into).await {
+        return Err(error::MergeDocs::NoSuchTarget);
+    }
+    if !ctx.state().keyhive().has_doc(&from).await {
+        return Err(error::MergeDocs::NoSuchSource);
+    }
+    let our_access = ctx
+        .state()
+        .keyhive()
+        .query_access(into)
+        .await
+        .and_then(|access| access.get(&ctx.state().our_peer_id()).copied());
+    if our_access < Some(MemberAccess::Admin) {
+        return Err(error::MergeDocs::NotAllowed);
+    }
+
+    let from_tree = ctx.state().docs().sedimentree(&from).unwrap_or_default();
+    let num_items = from_tree.strata().count() + from_tree.loose_commits().count();
+    let items = super::load_tree_data(&mut ctx, &from, from_tree, true, None)
+        .await
+        .map_err(|e| error::MergeDocs::Load(e.to_string()))?;
+    // Items which can't be decrypted are dropped by `load_tree_data`. We only
+    // hold the key epochs of `from` which we've been given, anything else
+    // can't be carried over.
+    if items.len() != num_items {
+        return Err(error::MergeDocs::Undecryptable);
+    }
+
+    let already_present = ctx
+        .state()
+        .docs()
+        .sedimentree(&into)
+        .map(|tree| super::doc_stats::known_commits(&tree))
+        .unwrap_or_default();
+    let mut commits = Vec::new();
+    let mut bundles: Vec<CommitBundle> = Vec::new();
+    for item in items {
+        match item {
+            CommitOrBundle::Commit(c) if !already_present.contains(&c.hash()) => commits.push(c),
+            CommitOrBundle::Bundle(b) if !already_present.contains(&b.end()) => bundles.push(b),
+            _ => {}
+        }
+    }
+    for bundle in bundles {
+        super::add_bundle(ctx.clone(), into, bundle)
+            .await
+            .map_err(|e| error::MergeDocs::Store(e.to_string()))?;
+    }
+    super::add_commits::add_commits(ctx.clone(), into, commits)
+        .await
+        .map_err(|e| error::MergeDocs::Store(e.to_string()))?;
+
+    ctx.state()
+        .keyhive()
+        .grant_members_of(from, into)
+        .await
+        .map_err(|e| error::MergeDocs::Grant(e.to_string()))?;
+
+    Ok(ctx
+        .state()
+        .docs()
+        .sedimentree(&into)
+        .map(|tree| tree.heads())
+        .unwrap_or_default())
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum MergeDocs {
+        #[error("cannot merge a document into itself")]
+        SameDocument,
+        #[error("target document not found")]
+        NoSuchTarget,
+        #[error("source document not found")]
+        NoSuchSource,
+        #[error("merging requires admin access to the target document")]
+        NotAllowed,
+        /// Some of the source document is encrypted under a key epoch we don't
+        /// have
+        #[error("some of the source document could not be decrypted")]
+        Undecryptable,
+        #[error("error loading source document: {0}")]
+        Load(String),
+        #[error("error storing merged commits: {0}")]
+        Store(String),
+        #[error("error granting access: {0}")]
+        Grant(String),
+    }
+}
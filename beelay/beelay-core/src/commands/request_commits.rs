@@ -0,0 +1,113 @@
+use std::collections::HashSet;
+
+use futures::FutureExt;
+
+use crate::{network::PeerAddress, CommitHash, DocumentId, TaskContext};
+
+use super::doc_stats::known_commits;
+
+/// The outcome of `Event::request_commits`
+///
+/// Each requested hash appears in exactly one of the lists, in the order it
+/// was requested.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestedCommits {
+    /// Commits which are now stored locally, including any which already were
+    pub fetched: Vec<CommitHash>,
+    /// Commits which none of the connected peers has
+    pub unfetchable: Vec<CommitHash>,
+    /// Commits which hadn't arrived when the request timed out
+    pub timed_out: Vec<CommitHash>,
+}
+
+/// Fetch each of `hashes` from the peers on the other end of our established
+/// streams
+///
+/// Streams are asked in turn until every commit has arrived. As with
+/// `has_commits` a commit is fetched if it is stored loose or is the start,
+/// end or a checkpoint of a stored bundle. If a request timeout has been
+/// configured the commits which haven't arrived once it elapses are reported
+/// as timed out.
+#[tracing::instrument(skip(ctx, hashes), fields(num_hashes = hashes.len()))]
+pub(super) async fn request_commits<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    hashes: Vec<CommitHash>,
+) -> Result<RequestedCommits, super::error::RequestCommits>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    if ctx.state().tombstones().contains(&doc_id) {
+        return Err(super::error::RequestCommits::Tombstoned);
+    }
+    if !ctx.state().keyhive().has_doc(&doc_id).await {
+        return Err(super::error::RequestCommits::NoSuchDocument);
+    }
+
+    let timeout = match ctx.state().endpoints().request_timeout() {
+        Some(timeout) => ctx
+            .state()
+            .deadlines()
+            .wait_until(ctx.now() + timeout)
+            .map(|_| ())
+            .left_future(),
+        None => futures::future::pending().right_future(),
+    };
+    let fetch = fetch_from_streams(ctx.clone(), doc_id, hashes.iter().copied().collect());
+    let timed_out = futures::select! {
+        _ = fetch.fuse() => false,
+        _ = timeout.fuse() => true,
+    };
+
+    let known = known_commits_of(&ctx, doc_id);
+    let mut result = RequestedCommits::default();
+    for hash in hashes {
+        if known.contains(&hash) {
+            result.fetched.push(hash);
+        } else if timed_out {
+            result.timed_out.push(hash);
+        } else {
+            result.unfetchable.push(hash);
+        }
+    }
+    Ok(result)
+}
+
+async fn fetch_from_streams<R>(ctx: TaskContext<R>, doc_id: DocumentId, hashes: HashSet<CommitHash>)
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    for stream in ctx.state().streams().established() {
+        let known = known_commits_of(&ctx, doc_id);
+        let wanted = hashes
+            .iter()
+            .filter(|h| !known.contains(h))
+            .copied()
+            .collect::<HashSet<_>>();
+        if wanted.is_empty() {
+            return;
+        }
+        if let Err(e) = crate::sync::fetch_commits(
+            ctx.clone(),
+            PeerAddress::Stream(stream.id),
+            stream.their_peer_id,
+            doc_id,
+            &wanted,
+        )
+        .await
+        {
+            tracing::debug!(err=?e, stream_id=?stream.id, "failed to fetch commits from stream");
+        }
+    }
+}
+
+fn known_commits_of<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+) -> HashSet<CommitHash> {
+    ctx.state()
+        .docs()
+        .sedimentree(&doc_id)
+        .map(|tree| known_commits(&tree))
+        .unwrap_or_default()
+}
@@ -0,0 +1,39 @@
+use crate::{
+    network::{PeerAddress, RpcError},
+    sync::SyncSedimentreeError,
+    DocumentId, EndpointId, TaskContext,
+};
+
+/// Sync `doc_id` with the peer `endpoint_id` addresses, through the endpoint
+///
+/// This is how a delegated endpoint's requests get made, the peer serves them
+/// with the access of the group the endpoint acts on behalf of.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn sync_with_endpoint<R>(
+    ctx: TaskContext<R>,
+    endpoint_id: EndpointId,
+    doc_id: DocumentId,
+) -> Result<(), super::error::SyncWithEndpoint>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let Some(peer_id) = ctx.state().endpoints().peer(endpoint_id) else {
+        return Err(super::error::SyncWithEndpoint::NoSuchEndpoint);
+    };
+    match crate::sync::sync_sedimentree(
+        ctx.clone(),
+        PeerAddress::Endpoint(endpoint_id),
+        peer_id,
+        doc_id,
+        false,
+    )
+    .await
+    {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(super::error::SyncWithEndpoint::Paused),
+        Err(SyncSedimentreeError::Rpc(RpcError::AuthorizationFailed)) => {
+            Err(super::error::SyncWithEndpoint::Rejected)
+        }
+        Err(e) => Err(super::error::SyncWithEndpoint::Failed(e.to_string())),
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use crate::{
+    blob::BlobMeta,
+    parse::{self, Parse},
+    sedimentree::{LooseCommit, Stratum},
+    BlobHash, CommitHash, DocumentId, StorageKey, TaskContext,
+};
+
+/// Something wrong with the stored data of a document, found by `verify_doc`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DocProblem {
+    /// A stored commit or bundle record could not be parsed
+    Unreadable { key: StorageKey, reason: String },
+    /// A record is stored under a key which doesn't match its contents
+    Misfiled {
+        key: StorageKey,
+        expected: StorageKey,
+    },
+    /// The contents of a commit or bundle are missing from storage
+    MissingBlob { item: DocItem, blob: BlobHash },
+    /// The stored contents of a commit or bundle don't hash to the hash
+    /// recorded for them
+    BlobHashMismatch {
+        item: DocItem,
+        expected: BlobHash,
+        actual: BlobHash,
+    },
+    /// The stored contents of a commit or bundle have a different length to
+    /// the one recorded for them
+    BlobSizeMismatch {
+        item: DocItem,
+        expected: u64,
+        actual: u64,
+    },
+    /// A commit refers to a parent which is neither a stored commit nor the
+    /// boundary of a stored bundle
+    DanglingParent {
+        commit: CommitHash,
+        parent: CommitHash,
+    },
+}
+
+/// A commit or bundle of a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocItem {
+    Commit(CommitHash),
+    Bundle { start: CommitHash, end: CommitHash },
+}
+
+/// Check the stored commits and bundles of a document for corruption
+///
+/// Nothing is repaired, the problems found are returned in no particular
+/// order. Bundles are opaque so a parent which is only contained in the
+/// interior of a bundle, rather than at one of its boundaries, is reported as
+/// dangling.
+#[tracing::instrument(skip(ctx))]
+pub(super) async fn verify_doc<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Result<Vec<DocProblem>, error::VerifyDoc> {
+    let raw_commits = ctx
+        .storage()
+        .load_range(StorageKey::sedimentree_commits(&doc_id))
+        .await;
+    let raw_strata = ctx
+        .storage()
+        .load_range(StorageKey::sedimentree_strata(&doc_id))
+        .await;
+    if raw_commits.is_empty()
+        && raw_strata.is_empty()
+        && !ctx.state().keyhive().has_doc(&doc_id).await
+    {
+        return Err(error::VerifyDoc::NoSuchDocument);
+    }
+
+    let mut problems = Vec::new();
+    let commits = parse_records::<LooseCommit>(raw_commits, &mut problems, |commit| {
+        StorageKey::sedimentree_commit(&doc_id, commit.hash())
+    });
+    let strata = parse_records::<Stratum>(raw_strata, &mut problems, |stratum| {
+        StorageKey::sedimentree_stratum(&doc_id, stratum.start(), stratum.end())
+    });
+
+    for commit in &commits {
+        let item = DocItem::Commit(commit.hash());
+        problems.extend(check_blob(&ctx, item, commit.blob()).await);
+    }
+    for stratum in &strata {
+        let item = DocItem::Bundle {
+            start: stratum.start(),
+            end: stratum.end(),
+        };
+        problems.extend(check_blob(&ctx, item, stratum.meta().blob()).await);
+    }
+
+    let known = commits
+        .iter()
+        .map(|c| c.hash())
+        .chain(strata.iter().flat_map(|s| {
+            [s.start(), s.end()]
+                .into_iter()
+                .chain(s.checkpoints().iter().copied())
+        }))
+        .collect::<HashSet<_>>();
+    for commit in &commits {
+        for parent in commit.parents() {
+            if !known.contains(parent) {
+                problems.push(DocProblem::DanglingParent {
+                    commit: commit.hash(),
+                    parent: *parent,
+                });
+            }
+        }
+    }
+
+    tracing::debug!(num_problems = problems.len(), "verified document");
+    Ok(problems)
+}
+
+fn parse_records<T: for<'a> Parse<'a>>(
+    raw: impl IntoIterator<Item = (StorageKey, Vec<u8>)>,
+    problems: &mut Vec<DocProblem>,
+    expected_key: impl Fn(&T) -> StorageKey,
+) -> Vec<T> {
+    let mut records = Vec::new();
+    for (key, value) in raw {
+        match T::parse(parse::Input::new(&value)) {
+            Ok((_, record)) => {
+                let expected = expected_key(&record);
+                if key != expected {
+                    problems.push(DocProblem::Misfiled { key, expected });
+                }
+                records.push(record);
+            }
+            Err(e) => problems.push(DocProblem::Unreadable {
+                key,
+                reason: e.to_string(),
+            }),
+        }
+    }
+    records
+}
+
+async fn check_blob<R: rand::Rng + rand::CryptoRng + 'static>(
+    ctx: &TaskContext<R>,
+    item: DocItem,
+    meta: &BlobMeta,
+) -> Option<DocProblem> {
+    let Some(data) = ctx.storage().load(StorageKey::blob(meta.hash())).await else {
+        return Some(DocProblem::MissingBlob {
+            item,
+            blob: meta.hash(),
+        });
+    };
+    let actual = BlobHash::hash_of(&data);
+    if actual != meta.hash() {
+        return Some(DocProblem::BlobHashMismatch {
+            item,
+            expected: meta.hash(),
+            actual,
+        });
+    }
+    if data.len() as u64 != meta.size_bytes() {
+        return Some(DocProblem::BlobSizeMismatch {
+            item,
+            expected: meta.size_bytes(),
+            actual: data.len() as u64,
+        });
+    }
+    None
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum VerifyDoc {
+        #[error("document not found")]
+        NoSuchDocument,
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use crate::{sedimentree::Sedimentree, CommitOrBundle, DocumentId, TaskContext};
+
+/// Load and decrypt each of `doc_ids` into memory so that later decrypting
+/// `load_doc` calls don't have to
+///
+/// The documents are loaded concurrently. Only local storage is read, a
+/// document which isn't stored locally is reported as missing rather than
+/// being fetched from a peer. A warmed document stays cached until it is
+/// deleted, its contents are reloaded by `load_doc` whenever it has changed.
+#[tracing::instrument(skip(ctx, doc_ids), fields(num_docs = doc_ids.len()))]
+pub(super) async fn warm_docs<R>(
+    ctx: TaskContext<R>,
+    doc_ids: Vec<DocumentId>,
+) -> HashMap<DocumentId, Result<(), error::WarmDoc>>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    futures::future::join_all(doc_ids.into_iter().map(|doc_id| {
+        let mut ctx = ctx.clone();
+        async move { (doc_id, warm_doc(&mut ctx, doc_id).await) }
+    }))
+    .await
+    .into_iter()
+    .collect()
+}
+
+async fn warm_doc<R>(ctx: &mut TaskContext<R>, doc_id: DocumentId) -> Result<(), error::WarmDoc>
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    let Some(tree) = ctx.state().docs().sedimentree(&doc_id) else {
+        // A document with no commits loads instantly anyway
+        if !ctx.state().docs().is_vanished(&doc_id) && ctx.state().keyhive().has_doc(&doc_id).await
+        {
+            return Ok(());
+        }
+        return Err(error::WarmDoc::NoSuchDocument);
+    };
+    let items = super::load_tree_data(ctx, &doc_id, tree.clone(), true, None)
+        .await
+        .map_err(|e| error::WarmDoc::Load(e.to_string()))?;
+    if !cache_if_complete(ctx, doc_id, tree, items) {
+        return Err(error::WarmDoc::Undecryptable);
+    }
+    Ok(())
+}
+
+/// Cache the decrypted contents of `doc_id`, unless some of the commits or
+/// bundles in `tree` couldn't be decrypted. Returns whether they were cached.
+pub(super) fn cache_if_complete<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    tree: Sedimentree,
+    items: Vec<CommitOrBundle>,
+) -> bool
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let expected = tree.strata().count() + tree.loose_commits().count();
+    if items.len() != expected {
+        return false;
+    }
+    ctx.state().warm_docs().insert(doc_id, tree, items);
+    true
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    pub enum WarmDoc {
+        #[error("document not found")]
+        NoSuchDocument,
+        #[error("error loading document: {0}")]
+        Load(String),
+        /// Some of the document could not be decrypted, for example because
+        /// we haven't received the keys for it yet
+        #[error("unable to decrypt the document")]
+        Undecryptable,
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use crate::{
+    parse::{self, Parse},
+    serialization::Encode,
+    CommitHash, DocumentId, PeerId, StorageKey, TaskContext, UnixTimestamp,
+};
+
+/// Information about how a commit or bundle arrived in local storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitMeta {
+    pub author: CommitAuthor,
+    /// When the commit was first written to local storage
+    pub received_at: UnixTimestamp,
+}
+
+/// Who we believe added a commit to the document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitAuthor {
+    /// The commit was added locally by this peer, or received in a message
+    /// signed by this peer. Commits relayed by a peer which did not author
+    /// them are attributed to the relaying peer.
+    Peer(PeerId),
+    /// The commit arrived as part of a bundle, which may contain commits by
+    /// several authors
+    Unknown,
+}
+
+fn meta_prefix(doc_id: &DocumentId) -> StorageKey {
+    StorageKey::sedimentree_root(doc_id).push("meta")
+}
+
+pub(crate) fn commit_key(doc_id: &DocumentId, commit: CommitHash) -> StorageKey {
+    meta_prefix(doc_id).push("commits").push(commit.to_string())
+}
+
+fn bundle_key(doc_id: &DocumentId, start: CommitHash, end: CommitHash) -> StorageKey {
+    meta_prefix(doc_id)
+        .push("bundles")
+        .push(format!("{}-{}", start, end))
+}
+
+/// The metadata which has been recorded for the commits and bundles of a document
+#[derive(Default)]
+pub(crate) struct DocMeta {
+    pub(crate) commits: HashMap<CommitHash, CommitMeta>,
+    pub(crate) bundles: HashMap<(CommitHash, CommitHash), CommitMeta>,
+}
+
+pub(crate) async fn load<R>(ctx: &TaskContext<R>, doc_id: &DocumentId) -> DocMeta
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let raw = ctx.storage().load_range(meta_prefix(doc_id)).await;
+    let mut meta = DocMeta::default();
+    for (key, value) in raw {
+        let Ok((_, parsed)) = CommitMeta::parse(parse::Input::new(&value))
+            .inspect_err(|e| tracing::error!(err=?e, ?key, "failed to parse stored commit meta"))
+        else {
+            continue;
+        };
+        match key.remaining() {
+            [_, _, kind, commit] if kind == "commits" => {
+                if let Ok(commit) = commit.parse() {
+                    meta.commits.insert(commit, parsed);
+                    continue;
+                }
+            }
+            [_, _, kind, range] if kind == "bundles" => {
+                if let Some((Ok(start), Ok(end))) = range
+                    .split_once('-')
+                    .map(|(start, end)| (start.parse(), end.parse()))
+                {
+                    meta.bundles.insert((start, end), parsed);
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        tracing::warn!(?key, "unexpected commit meta key");
+    }
+    meta
+}
+
+/// Record the author of a commit, unless we already have metadata for it
+pub(crate) async fn record_commit<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    commit: CommitHash,
+    author: PeerId,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    record(ctx, commit_key(&doc_id, commit), CommitAuthor::Peer(author)).await
+}
+
+/// Record that a bundle was received, the author of a bundle is always unknown
+pub(crate) async fn record_bundle<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+    start: CommitHash,
+    end: CommitHash,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    record(ctx, bundle_key(&doc_id, start, end), CommitAuthor::Unknown).await
+}
+
+async fn record<R>(ctx: &TaskContext<R>, key: StorageKey, author: CommitAuthor)
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    // The first time we saw a commit is the interesting one
+    if ctx.storage().load(key.clone()).await.is_some() {
+        return;
+    }
+    let meta = CommitMeta {
+        author,
+        received_at: ctx.now().as_secs(),
+    };
+    ctx.storage().put(key, meta.encode()).await;
+}
+
+impl Encode for CommitMeta {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self.author {
+            CommitAuthor::Peer(peer) => {
+                out.push(1);
+                out.extend_from_slice(peer.as_bytes());
+            }
+            CommitAuthor::Unknown => out.push(0),
+        }
+        self.received_at.encode_into(out);
+    }
+}
+
+impl Parse<'_> for CommitMeta {
+    fn parse(input: parse::Input<'_>) -> Result<(parse::Input<'_>, Self), parse::ParseError> {
+        input.parse_in_ctx("CommitMeta", |input| {
+            let (input, tag) = parse::u8(input)?;
+            let (input, author) = match tag {
+                0 => (input, CommitAuthor::Unknown),
+                1 => {
+                    let (input, bytes) = parse::arr::<32>(input)?;
+                    let peer = PeerId::try_from(bytes.as_slice())
+                        .map_err(|_| input.error("invalid author peer id"))?;
+                    (input, CommitAuthor::Peer(peer))
+                }
+                other => return Err(input.error(format!("unknown author tag {}", other))),
+            };
+            let (input, received_at) = UnixTimestamp::parse_in_ctx("received_at", input)?;
+            Ok((
+                input,
+                Self {
+                    author,
+                    received_at,
+                },
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommitAuthor, CommitMeta};
+    use crate::{
+        parse::{self, Parse},
+        serialization::Encode,
+        PeerId, UnixTimestamp,
+    };
+
+    #[test]
+    fn commit_meta_roundtrip() {
+        bolero::check!()
+            .with_arbitrary::<(Option<PeerId>, u64)>()
+            .for_each(|(author, received_at)| {
+                let meta = CommitMeta {
+                    author: author
+                        .map(CommitAuthor::Peer)
+                        .unwrap_or(CommitAuthor::Unknown),
+                    received_at: UnixTimestamp::from(*received_at),
+                };
+                let encoded = meta.encode();
+                let (_, decoded) = CommitMeta::parse(parse::Input::new(&encoded)).unwrap();
+                assert_eq!(decoded, meta);
+            });
+    }
+}
@@ -5,24 +5,228 @@ use ed25519_dalek::VerifyingKey;
 
 pub struct Config<R> {
     pub(crate) session_duration: Duration,
+    pub(crate) stream_congestion_threshold: Option<usize>,
     pub(crate) verifying_key: VerifyingKey,
     pub(crate) rng: R,
+    pub(crate) bundling_policy: BundlingPolicy,
+    pub(crate) doc_size_limit: DocSizeLimit,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) stream_rate_limit: Option<crate::StreamRateLimit>,
+    pub(crate) stream_reorder_window: usize,
+    pub(crate) storage_encryption_key: Option<crate::StorageEncryptionKey>,
+    pub(crate) storage_namespace: Option<String>,
+    #[cfg(feature = "io_observer")]
+    pub(crate) io_observer: Option<IoObserver>,
 }
 
+/// Receives a description of every IO task, see [`Config::io_observer`]
+#[cfg(feature = "io_observer")]
+pub type IoObserver = futures::channel::mpsc::UnboundedSender<crate::io::IoObservation>;
+
 impl<R: rand::Rng + rand::CryptoRng> Config<R> {
+    /// Everything beelay does at random, such as generating keys and nonces,
+    /// draws from `rng`. Tests which need reproducible runs can seed it and
+    /// enable the `insecure_deterministic` feature, see `reset_ids` for what
+    /// that does and doesn't make reproducible.
     pub fn new(rng: R, verifying_key: VerifyingKey) -> Self {
         Config {
             session_duration: Duration::from_secs(5 * 60),
+            stream_congestion_threshold: None,
             verifying_key,
             rng,
+            bundling_policy: BundlingPolicy::default(),
+            doc_size_limit: DocSizeLimit::default(),
+            request_timeout: None,
+            stream_rate_limit: None,
+            stream_reorder_window: 64,
+            storage_encryption_key: None,
+            storage_namespace: None,
+            #[cfg(feature = "io_observer")]
+            io_observer: None,
         }
     }
 
     pub fn session_duration(self, session_duration: Duration) -> Self {
         Self {
             session_duration,
-            rng: self.rng,
-            verifying_key: self.verifying_key,
+            ..self
+        }
+    }
+
+    /// Track how many bytes of outbound stream messages the transport has yet
+    /// to send and report streams whose backlog exceeds `bytes`
+    ///
+    /// Once this is set the transport must report each `StreamEvent::Send` it
+    /// hands off using `Event::stream_messages_sent`, the backlog of a stream
+    /// is then available from `Beelay::stream_queue_depth` and streams which
+    /// grow past the threshold appear in `EventResults::congested_streams`.
+    pub fn stream_congestion_threshold(self, bytes: usize) -> Self {
+        Self {
+            stream_congestion_threshold: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Drop inbound messages on a stream once the peer has sent more than
+    /// `limit` allows in the current second
+    ///
+    /// Responses to requests we sent on the stream aren't throttled, so the
+    /// initial catch-up, which is mostly answers to our own requests, isn't
+    /// slowed down. Requests from the peer are counted even while we are
+    /// waiting on it. The first message dropped on a stream in each second is
+    /// reported in `EventResults::rate_limited_streams`. The limit of a
+    /// single stream can be changed with `Event::set_stream_rate_limit`.
+    pub fn stream_rate_limit(self, limit: crate::StreamRateLimit) -> Self {
+        Self {
+            stream_rate_limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// Hold at most `messages` messages which arrive on a stream ahead of one
+    /// which hasn't arrived yet
+    ///
+    /// Streams which negotiated protocol version 3 or later number their
+    /// messages so that they can be processed in the order they were sent
+    /// when the transport reorders them. Once a message arrives `messages`
+    /// or more ahead of the oldest one still missing, the missing messages
+    /// are given up on: requests we sent on the stream which haven't been
+    /// answered fail and the stream syncs again from scratch, which is
+    /// counted in `StreamMetrics::resyncs`. A window of 0 processes messages
+    /// as they arrive and resyncs whenever one is missing. A message more
+    /// than 1024 past the end of the window closes the stream instead. The
+    /// default window is 64.
+    pub fn stream_reorder_window(self, messages: usize) -> Self {
+        Self {
+            stream_reorder_window: messages,
+            ..self
+        }
+    }
+
+    /// Automatically bundle the loose commits of documents, see
+    /// [`BundlingPolicy`]. This can be changed later with
+    /// `Event::set_bundling_policy`.
+    pub fn bundling_policy(self, bundling_policy: BundlingPolicy) -> Self {
+        Self {
+            bundling_policy,
+            ..self
+        }
+    }
+
+    /// Cap how large any single document can grow, see [`DocSizeLimit`].
+    /// This can be changed later with `Event::set_doc_size_limit`.
+    pub fn doc_size_limit(self, doc_size_limit: DocSizeLimit) -> Self {
+        Self {
+            doc_size_limit,
+            ..self
+        }
+    }
+
+    /// Give up on endpoint requests which haven't received a response within
+    /// `timeout`
+    ///
+    /// Timeouts are checked on `Event::tick`. The ids of requests which were
+    /// given up on appear in `EventResults::timed_out_requests` and a response
+    /// which arrives afterwards is rejected with
+    /// `HandleResponse::RequestTimedOut`. By default requests wait forever.
+    ///
+    /// The same timeout bounds how long `Event::request_commits` waits for
+    /// the commits it asked for.
+    pub fn request_timeout(self, timeout: Duration) -> Self {
+        Self {
+            request_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Encrypt the values written to storage with `key`
+    ///
+    /// Values are encrypted before they appear in `IoAction::Put` and
+    /// decrypted when they are loaded. Storage keys are not encrypted, so
+    /// anyone who can list storage sees document ids, commit and blob
+    /// hashes, the hashes of idempotency keys and the commit range of every
+    /// bundle being added under `pending_bundles/<start>-<end>`.
+    /// Storage must always be loaded with the key it was written with, if
+    /// anything read while loading fails to decrypt `Beelay::load` stops
+    /// with `loading::Step::Failed`.
+    pub fn storage_encryption_key(self, key: crate::StorageEncryptionKey) -> Self {
+        Self {
+            storage_encryption_key: Some(key),
+            ..self
+        }
+    }
+
+    /// Keep everything this Beelay stores under `namespace`
+    ///
+    /// Every key in an IO task is prefixed with the namespace, so that
+    /// `sedimentrees/doc123` is stored as `namespace/sedimentrees/doc123`,
+    /// and the prefix is removed again from the keys of loaded values. This
+    /// lets several Beelays share one storage backend without seeing each
+    /// other's data, and a tenant's data can be removed in bulk by deleting
+    /// the prefix, see `Event::delete_namespace`. The namespace must not be
+    /// empty, contain a `/` or be one of `sedimentrees`, `blobs` or `auth`,
+    /// otherwise `Beelay::load` fails with `Load::InvalidStorageNamespace`.
+    pub fn storage_namespace(self, namespace: impl Into<String>) -> Self {
+        Self {
+            storage_namespace: Some(namespace.into()),
+            ..self
         }
     }
+
+    /// Send a description of every IO task to `observer` as it is handed to
+    /// the application, before it has been fulfilled
+    ///
+    /// This includes the tasks issued while loading. Observations are sent
+    /// in the order the tasks appear in `EventResults::new_tasks`, a closed
+    /// observer is ignored.
+    #[cfg(feature = "io_observer")]
+    pub fn io_observer(self, observer: IoObserver) -> Self {
+        Self {
+            io_observer: Some(observer),
+            ..self
+        }
+    }
+}
+
+/// When to roll the loose commits of a document up into a bundle
+///
+/// The policy is checked whenever commits are added with `add_commits`. Once
+/// a document has more loose commits than `max_loose_commits`, or more than
+/// `max_loose_bytes` of encrypted loose commits, every loose commit other than
+/// the heads of the document is replaced with a single bundle and a
+/// `DocEvent::Bundled` notification is emitted. The contents of these bundles
+/// use the same encoding as `Event::export_bundle`, so they can be read with
+/// `CommitBundle::exported_items`.
+///
+/// The default policy never bundles automatically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BundlingPolicy {
+    pub max_loose_commits: Option<usize>,
+    pub max_loose_bytes: Option<u64>,
+}
+
+/// The largest any single document is allowed to grow to
+///
+/// The limit is checked whenever commits or bundles are added with
+/// `add_commits` or `add_bundle`, or uploaded to us by a peer. Anything which
+/// would take a document past either limit is rejected as a whole, locally
+/// with a `SizeLimitExceeded` error. Commits and bundles which are already
+/// stored are never affected, so lowering the limit only stops documents
+/// which are over it from growing any further.
+///
+/// Sizes are those reported by `Event::doc_stats`, each bundle counts as a
+/// single commit. The default has no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DocSizeLimit {
+    pub max_bytes: Option<u64>,
+    pub max_commits: Option<usize>,
+}
+
+impl DocSizeLimit {
+    /// Whether a document of `commits` commits and `bytes` bytes is over
+    /// the limit
+    pub fn exceeded_by(&self, commits: usize, bytes: u64) -> bool {
+        self.max_commits.is_some_and(|max| commits > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
 }
@@ -1,9 +1,13 @@
-use crate::{streams::SyncPhase, PeerId, UnixTimestampMillis};
+use crate::{streams::SyncPhase, PeerId, StreamCodec, UnixTimestampMillis};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConnectionInfo {
     pub peer_id: PeerId,
     pub state: ConnState,
+    /// The compression codec negotiated for the stream, if any
+    pub codec: Option<StreamCodec>,
+    /// The protocol version negotiated for the stream
+    pub protocol_version: u8,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -1,6 +1,6 @@
 use crate::{serialization::hex, PeerId};
 
-#[derive(Debug, Clone, Hash)]
+#[derive(Debug, Clone)]
 pub struct ContactCard(pub(crate) keyhive_core::contact_card::ContactCard);
 
 impl ContactCard {
@@ -29,6 +29,21 @@ impl ContactCard {
     }
 }
 
+// Contact cards are compared and hashed by their encoding
+impl PartialEq for ContactCard {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for ContactCard {}
+
+impl std::hash::Hash for ContactCard {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.to_bytes().hash(state);
+    }
+}
+
 impl std::fmt::Display for ContactCard {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.0)
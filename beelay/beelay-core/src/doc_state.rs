@@ -1,8 +1,8 @@
 use keyhive_core::{cgka::operation::CgkaOperation, crypto::signed::Signed};
 
 use crate::{
-    doc_status::DocStatus, network::messages::UploadItem, sedimentree, Commit, CommitBundle,
-    CommitHash, CommitOrBundle, PeerId,
+    network::messages::UploadItem, sedimentree, Commit, CommitBundle, CommitHash, CommitOrBundle,
+    PeerId,
 };
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -27,6 +27,12 @@ impl DocState {
         &self.tree
     }
 
+    pub(crate) fn remove_commits(&mut self, hashes: &[CommitHash]) {
+        for hash in hashes {
+            self.tree.remove_commit(*hash);
+        }
+    }
+
     pub(crate) fn add_commits<I: Iterator<Item = (Commit, Option<Signed<CgkaOperation>>)>>(
         &mut self,
         commits: I,
@@ -63,12 +69,6 @@ impl DocState {
         }
     }
 
-    pub(crate) fn status(&self) -> DocStatus {
-        DocStatus {
-            local_heads: Some(self.heads()),
-        }
-    }
-
     pub(crate) fn take_changes(&mut self) -> Vec<NewChange> {
         std::mem::take(&mut self.new_changes)
     }
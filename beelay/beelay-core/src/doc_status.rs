@@ -1,11 +1,41 @@
-use std::collections::HashMap;
-
-use crate::{commands::keyhive::MemberAccess, CommitHash, CommitOrBundle, PeerId};
+use crate::{
+    commands::keyhive::MemberAccess, contact_card::ContactCard, CommitHash, CommitOrBundle, PeerId,
+};
 
 /// The state of a one document
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DocStatus {
     pub local_heads: Option<Vec<CommitHash>>,
+    /// Whether syncing of the document has been paused with
+    /// `Event::pause_doc_sync`
+    pub sync_paused: bool,
+}
+
+/// How soon a document is synced relative to the others, see
+/// `Event::prioritize_doc_sync`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SyncPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// What we know about the state of one document on the other end of a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamSyncStatus {
+    /// The document has not been synced over this stream yet
+    Unknown,
+    Known {
+        local_heads: Vec<CommitHash>,
+        /// The heads the peer had as of the last time we synced the document
+        /// with them
+        remote_heads: Vec<CommitHash>,
+        /// Whether `local_heads` and `remote_heads` are the same. This can go
+        /// stale if the peer receives changes from elsewhere before the next
+        /// sync.
+        in_sync: bool,
+    },
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -14,7 +44,48 @@ pub enum DocEvent {
         data: CommitOrBundle,
     },
     Discovered,
+    /// The access `entity` has to the document changed, reported to
+    /// `Event::subscribe_access_changes`. `None` means no access. Changes
+    /// which happen in quick succession may be reported as one.
     AccessChanged {
-        new_access: HashMap<PeerId, MemberAccess>,
+        entity: PeerId,
+        old: Option<MemberAccess>,
+        new: Option<MemberAccess>,
+    },
+    /// The heads of a document subscribed to with `Event::subscribe_doc`
+    /// have changed
+    HeadsChanged {
+        new_heads: Vec<CommitHash>,
+    },
+    /// The document has gone from one head to several, for example because
+    /// two members wrote to it concurrently. This is only emitted again once
+    /// the heads have been merged back into one and then diverge again.
+    Diverged {
+        heads: Vec<CommitHash>,
+    },
+    /// The loose commits of a document were rolled up into a bundle according
+    /// to the `BundlingPolicy`
+    Bundled {
+        start: CommitHash,
+        end: CommitHash,
+        num_commits: usize,
+    },
+    /// A peer without enough access asked us, as an admin of the
+    /// document, for `desired` access with `Event::request_doc_access`.
+    /// Grant it with `Event::add_member_to_doc` or refuse it with
+    /// `Event::deny_doc_access`.
+    AccessRequested {
+        requester: ContactCard,
+        desired: MemberAccess,
+    },
+    /// An admin granted our request for access to the document. The
+    /// membership change itself arrives through the usual sync.
+    AccessRequestGranted {
+        by: PeerId,
+        access: MemberAccess,
+    },
+    /// An admin denied our request for access to the document
+    AccessRequestDenied {
+        by: PeerId,
     },
 }
@@ -53,6 +53,7 @@ impl CommitOrBundle {
 }
 
 pub(crate) mod error {
+    pub use super::commit_bundle::error::DecodeExportedBundle;
     pub use super::commit_hash::InvalidCommitHash;
     pub use super::document_id::error::InvalidDocumentId;
 }
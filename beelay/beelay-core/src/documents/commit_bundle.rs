@@ -1,6 +1,11 @@
-use crate::{blob::BlobMeta, sedimentree};
+use crate::{
+    blob::BlobMeta,
+    parse::{self, Parse},
+    sedimentree,
+    serialization::Encode,
+};
 
-use super::CommitHash;
+use super::{Commit, CommitHash, CommitOrBundle};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CommitBundle {
@@ -9,6 +14,7 @@ pub struct CommitBundle {
     end: CommitHash,
     checkpoints: Vec<CommitHash>,
     hash: CommitHash,
+    keyhive_material: Option<Vec<u8>>,
 }
 
 impl CommitBundle {
@@ -39,6 +45,116 @@ impl CommitBundle {
     pub fn hash(&self) -> &CommitHash {
         &self.hash
     }
+
+    /// Keyhive events which allow a member of the document to decrypt it on a
+    /// node which has not synced the document yet
+    ///
+    /// This is only set on bundles produced by `Event::export_bundle` and is
+    /// consumed by `Event::add_bundle`. It is not part of the bundle hash.
+    pub fn keyhive_material(&self) -> Option<&[u8]> {
+        self.keyhive_material.as_deref()
+    }
+
+    /// Decode the commits and bundles contained in a bundle produced by
+    /// `Event::export_bundle`
+    pub fn exported_items(&self) -> Result<Vec<CommitOrBundle>, error::DecodeExportedBundle> {
+        let input = parse::Input::new(&self.bundled_commits);
+        let (input, items) = Vec::<ExportedItem>::parse(input)
+            .map_err(|e| error::DecodeExportedBundle(e.to_string()))?;
+        if !input.is_empty() {
+            return Err(error::DecodeExportedBundle(
+                "trailing bytes after exported items".to_string(),
+            ));
+        }
+        Ok(items.into_iter().map(|ExportedItem(item)| item).collect())
+    }
+
+    /// Encode the contents of a bundle to be returned from `Event::export_bundle`
+    pub(crate) fn encode_exported(items: Vec<CommitOrBundle>) -> Vec<u8> {
+        items
+            .into_iter()
+            .map(ExportedItem)
+            .collect::<Vec<_>>()
+            .encode()
+    }
+}
+
+impl CommitOrBundle {
+    /// Decode the concatenated chunks returned by `Event::next_export_chunk`
+    pub fn decode_export(data: &[u8]) -> Result<Vec<CommitOrBundle>, error::DecodeExportedBundle> {
+        let mut input = parse::Input::new(data);
+        let mut items = Vec::new();
+        while !input.is_empty() {
+            let (rest, ExportedItem(item)) = ExportedItem::parse(input)
+                .map_err(|e| error::DecodeExportedBundle(e.to_string()))?;
+            items.push(item);
+            input = rest;
+        }
+        Ok(items)
+    }
+
+    /// Append the encoding of this item to a streaming export
+    pub(crate) fn encode_export_into(self, out: &mut Vec<u8>) {
+        ExportedItem(self).encode_into(out)
+    }
+}
+
+// The encoding of each entry in the contents of an exported bundle
+struct ExportedItem(CommitOrBundle);
+
+impl Encode for ExportedItem {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match &self.0 {
+            CommitOrBundle::Commit(commit) => {
+                out.push(0);
+                commit.parents().to_vec().encode_into(out);
+                commit.hash().encode_into(out);
+                commit.contents().to_vec().encode_into(out);
+            }
+            CommitOrBundle::Bundle(bundle) => {
+                out.push(1);
+                bundle.start().encode_into(out);
+                bundle.end().encode_into(out);
+                bundle.checkpoints().to_vec().encode_into(out);
+                bundle.bundled_commits().to_vec().encode_into(out);
+            }
+        }
+    }
+}
+
+impl<'a> Parse<'a> for ExportedItem {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        let (input, tag) = parse::u8(input)?;
+        match tag {
+            0 => {
+                let (input, parents) = Vec::<CommitHash>::parse_in_ctx("parents", input)?;
+                let (input, hash) = CommitHash::parse_in_ctx("hash", input)?;
+                let (input, contents) = parse::slice(input)?;
+                let commit = Commit::new(parents, contents.to_vec(), hash);
+                Ok((input, ExportedItem(CommitOrBundle::Commit(commit))))
+            }
+            1 => {
+                let (input, start) = CommitHash::parse_in_ctx("start", input)?;
+                let (input, end) = CommitHash::parse_in_ctx("end", input)?;
+                let (input, checkpoints) = Vec::<CommitHash>::parse_in_ctx("checkpoints", input)?;
+                let (input, contents) = parse::slice(input)?;
+                let bundle = CommitBundle::builder()
+                    .start(start)
+                    .end(end)
+                    .checkpoints(checkpoints)
+                    .bundled_commits(contents.to_vec())
+                    .build();
+                Ok((input, ExportedItem(CommitOrBundle::Bundle(bundle))))
+            }
+            other => Err(input.error(format!("unknown exported item tag: {}", other))),
+        }
+    }
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    #[error("invalid exported bundle: {0}")]
+    pub struct DecodeExportedBundle(pub(super) String);
 }
 
 pub struct Set<T>(T);
@@ -49,6 +165,7 @@ pub struct BundleBuilder<Start, End, Commits> {
     end: End,
     commits: Commits,
     checkpoints: Vec<CommitHash>,
+    keyhive_material: Option<Vec<u8>>,
 }
 
 impl BundleBuilder<UnSet, UnSet, UnSet> {
@@ -58,6 +175,7 @@ impl BundleBuilder<UnSet, UnSet, UnSet> {
             end: UnSet,
             commits: UnSet,
             checkpoints: vec![],
+            keyhive_material: None,
         }
     }
 }
@@ -69,6 +187,7 @@ impl<T, U, V> BundleBuilder<T, U, V> {
             end: self.end,
             commits: self.commits,
             checkpoints: self.checkpoints,
+            keyhive_material: self.keyhive_material,
         }
     }
 
@@ -78,6 +197,7 @@ impl<T, U, V> BundleBuilder<T, U, V> {
             end: Set(end),
             commits: self.commits,
             checkpoints: self.checkpoints,
+            keyhive_material: self.keyhive_material,
         }
     }
 
@@ -87,15 +207,23 @@ impl<T, U, V> BundleBuilder<T, U, V> {
             end: self.end,
             commits: Set(commits),
             checkpoints: self.checkpoints,
+            keyhive_material: self.keyhive_material,
         }
     }
 
     pub fn checkpoints(self, checkpoints: Vec<CommitHash>) -> Self {
         BundleBuilder {
-            start: self.start,
-            end: self.end,
-            commits: self.commits,
             checkpoints,
+            ..self
+        }
+    }
+
+    /// Attach keyhive events which a recipient needs to decrypt the bundle, see
+    /// [`CommitBundle::keyhive_material`]
+    pub fn keyhive_material(self, keyhive_material: Vec<u8>) -> Self {
+        BundleBuilder {
+            keyhive_material: Some(keyhive_material),
+            ..self
         }
     }
 }
@@ -117,6 +245,7 @@ impl BundleBuilder<Set<CommitHash>, Set<CommitHash>, Set<Vec<u8>>> {
             bundled_commits: self.commits.0,
             checkpoints: self.checkpoints,
             hash: hash.as_bytes().into(),
+            keyhive_material: self.keyhive_material,
         }
     }
 }
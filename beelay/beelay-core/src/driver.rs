@@ -1,8 +1,15 @@
-use std::{cell::RefCell, collections::HashMap, future::Future, rc::Rc, time::Duration};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    future::Future,
+    rc::Rc,
+    time::Duration,
+};
 
 use ed25519_dalek::VerifyingKey;
 use futures::{
     channel::{mpsc, oneshot},
+    future::{AbortHandle, Abortable},
     stream::FuturesUnordered,
     FutureExt, StreamExt,
 };
@@ -11,19 +18,22 @@ use keyhive_core::crypto::verifiable::Verifiable;
 mod executor;
 
 use crate::{
-    auth, commands,
+    auth, capabilities, commands,
     conn_info::ConnectionInfo,
     doc_status::DocEvent,
-    io::{IoHandle, IoResult, IoTask},
-    keyhive_storage, loading,
+    ephemeral_docs, group_metadata,
+    io::{IoAction, IoHandle, IoResult, IoResultPayload, IoTask},
+    keyhive_storage, loading, membership_expiry,
     network::EndpointRequest,
-    request_handlers,
+    request_handlers, seals,
     serialization::Encode,
     state::State,
+    storage_encryption::{self, StorageEncryptionKey},
     streams::{self, HandledMessage, UnsignedStreamEvent},
-    sync_loops, Command, CommandId, CommandResult, DocumentId, EndpointId, EventResults, IoTaskId,
-    NewRequest, OutboundRequestId, PeerId, Signer, StorageKey, StreamEvent, StreamId, TaskContext,
-    UnixTimestampMillis,
+    sync_loops, tombstones, AppendFailure, BundlingPolicy, Command, CommandId, CommandProgress,
+    CommandResult, Commit, CommitHash, DocSizeLimit, DocumentId, EndpointId, EventResults,
+    IoTaskId, NewRequest, OutboundRequestId, PeerId, Signer, StorageKey, StreamEvent, StreamId,
+    StreamQueueDepth, StreamRateLimit, TaskContext, UnixTimestampMillis,
 };
 
 pub struct SpawnArgs<R> {
@@ -36,13 +46,46 @@ pub struct SpawnArgs<R> {
 pub(crate) struct Driver {
     now: Rc<RefCell<UnixTimestampMillis>>,
     io_tasks: HashMap<IoTaskId, oneshot::Sender<IoResult>>,
+    // The subset of `io_tasks` which write to storage
+    pending_writes: HashSet<IoTaskId>,
+    // Flush commands along with the writes they are waiting for. The writes
+    // are `None` until the end of the step in which the flush was received.
+    pending_flushes: Vec<(CommandId, Option<HashSet<IoTaskId>>)>,
+    // Outbound stream messages awaiting acknowledgement from the transport,
+    // `None` unless a congestion threshold was configured
+    outbound_queues: Option<OutboundQueues>,
+    inbound_limits: InboundLimits,
+    // Commands which the driver handled itself, to be reported in the next step
+    completed_locally: Vec<(CommandId, CommandResult)>,
+    // The ids attached with `Event::with_correlation_id` to commands which
+    // are still running
+    correlation_ids: HashMap<CommandId, String>,
+    appends: Appends,
+    storage_encryption: Option<StorageEncryptionKey>,
+    // The namespace every storage key is moved inside of
+    storage_namespace: Option<String>,
+    // The keys of outstanding loads, their results are decrypted with
+    // `storage_encryption`
+    pending_loads: HashMap<IoTaskId, StorageKey>,
+    // Whether any value read from storage failed to decrypt
+    undecryptable_storage: bool,
     rx_output: mpsc::UnboundedReceiver<DriverOutput>,
     tx_input: mpsc::UnboundedSender<DriverInput>,
     executor: executor::LocalExecutor,
+    #[cfg(feature = "io_observer")]
+    io_observer: Option<crate::config::IoObserver>,
 }
 
 impl Driver {
-    pub(crate) fn start<R, F, Fut>(rng: R, now: UnixTimestampMillis, f: F) -> Self
+    pub(crate) fn start<R, F, Fut>(
+        rng: R,
+        now: UnixTimestampMillis,
+        stream_congestion_threshold: Option<usize>,
+        stream_rate_limit: Option<StreamRateLimit>,
+        storage_encryption: Option<StorageEncryptionKey>,
+        storage_namespace: Option<String>,
+        f: F,
+    ) -> Self
     where
         F: FnOnce(SpawnArgs<R>) -> Fut,
         Fut: Future<Output = ()> + 'static,
@@ -63,104 +106,699 @@ impl Driver {
         Self {
             now,
             io_tasks: HashMap::new(),
+            pending_writes: HashSet::new(),
+            pending_flushes: Vec::new(),
+            outbound_queues: stream_congestion_threshold.map(OutboundQueues::new),
+            inbound_limits: InboundLimits::new(stream_rate_limit),
+            completed_locally: Vec::new(),
+            correlation_ids: HashMap::new(),
+            appends: Appends::default(),
+            storage_encryption,
+            storage_namespace,
+            pending_loads: HashMap::new(),
+            undecryptable_storage: false,
             rx_output: rx_driver_events,
             tx_input,
             executor,
+            #[cfg(feature = "io_observer")]
+            io_observer: None,
         }
     }
 
+    #[cfg(feature = "io_observer")]
+    pub(crate) fn set_io_observer(&mut self, observer: Option<crate::config::IoObserver>) {
+        self.io_observer = observer;
+    }
+
     pub(crate) fn handle_io_complete(&mut self, io_result: IoResult) {
+        if self.pending_writes.remove(&io_result.id()) {
+            for (_, waiting_for) in self.pending_flushes.iter_mut() {
+                if let Some(waiting_for) = waiting_for {
+                    waiting_for.remove(&io_result.id());
+                }
+            }
+        }
         let Some(reply) = self.io_tasks.remove(&io_result.id()) else {
             tracing::warn!("received IO completion for unknown task");
             return;
         };
+        let io_result = self.decrypt(self.strip_namespace(io_result));
         let _ = reply.send(io_result);
     }
 
-    pub(crate) fn handle_stream_message(&mut self, stream_id: StreamId, msg: Vec<u8>) {
+    /// Whether a value read from storage couldn't be decrypted with the
+    /// storage encryption key
+    pub(crate) fn undecryptable_storage(&self) -> bool {
+        self.undecryptable_storage
+    }
+
+    // Keys outside our namespace can only come from a misbehaving storage
+    // backend, they are dropped
+    fn strip_namespace(&self, io_result: IoResult) -> IoResult {
+        let Some(namespace) = &self.storage_namespace else {
+            return io_result;
+        };
+        let id = io_result.id();
+        match io_result.take_payload() {
+            IoResultPayload::LoadRange(values) => IoResult::load_range(
+                id,
+                values
+                    .into_iter()
+                    .filter_map(|(key, data)| Some((key.without(namespace)?, data)))
+                    .collect(),
+            ),
+            IoResultPayload::ListOneLevel(keys) => IoResult::list_one_level(
+                id,
+                keys.into_iter()
+                    .filter_map(|key| key.without(namespace))
+                    .collect(),
+            ),
+            other => IoResult::from_payload(id, other),
+        }
+    }
+
+    // Values which fail to decrypt are treated as missing
+    fn decrypt(&mut self, io_result: IoResult) -> IoResult {
+        let Some(key) = &self.storage_encryption else {
+            return io_result;
+        };
+        let id = io_result.id();
+        let Some(storage_key) = self.pending_loads.remove(&id) else {
+            return io_result;
+        };
+        match io_result.take_payload() {
+            IoResultPayload::Load(data) => {
+                let decrypted = data.and_then(|data| {
+                    let decrypted = key.decrypt(&storage_key, &data);
+                    if decrypted.is_none() {
+                        tracing::error!(key=%storage_key, "failed to decrypt stored value");
+                        self.undecryptable_storage = true;
+                    }
+                    decrypted
+                });
+                IoResult::load(id, decrypted)
+            }
+            IoResultPayload::LoadRange(values) => {
+                let mut decrypted = HashMap::with_capacity(values.len());
+                for (storage_key, data) in values {
+                    match key.decrypt(&storage_key, &data) {
+                        Some(data) => {
+                            decrypted.insert(storage_key, data);
+                        }
+                        None => {
+                            tracing::error!(key=%storage_key, "failed to decrypt stored value");
+                            self.undecryptable_storage = true;
+                        }
+                    }
+                }
+                IoResult::load_range(id, decrypted)
+            }
+            other => IoResult::from_payload(id, other),
+        }
+    }
+
+    pub(crate) fn handle_stream_message(
+        &mut self,
+        now: UnixTimestampMillis,
+        stream_id: StreamId,
+        msg: Vec<u8>,
+        awaiting_responses: usize,
+    ) {
+        // Checked here rather than in the event loop so that dropped messages
+        // are never parsed or verified
+        let responses_only =
+            match self
+                .inbound_limits
+                .accept(now, stream_id, msg.len(), awaiting_responses)
+            {
+                Admission::Accept => false,
+                Admission::ResponsesOnly => true,
+                Admission::Drop => {
+                    tracing::debug!(
+                        ?stream_id,
+                        "stream is over its rate limit, dropping message"
+                    );
+                    return;
+                }
+            };
         let _ = self.tx_input.unbounded_send(DriverInput::StreamMessage {
             stream_id,
             message: msg,
+            responses_only,
         });
     }
 
+    pub(crate) fn handle_stream_messages_sent(&mut self, stream_id: StreamId, num_messages: usize) {
+        if let Some(queues) = &mut self.outbound_queues {
+            queues.sent(stream_id, num_messages);
+        }
+    }
+
+    pub(crate) fn stream_queue_depth(&self, stream_id: StreamId) -> Option<StreamQueueDepth> {
+        self.outbound_queues
+            .as_ref()
+            .map(|queues| queues.depth(stream_id))
+    }
+
+    pub(crate) fn correlate(&mut self, command_id: CommandId, correlation_id: String) {
+        self.correlation_ids.insert(command_id, correlation_id);
+    }
+
     pub(crate) fn dispatch_command(&mut self, command_id: CommandId, command: Command) {
+        // Only the driver knows which writes are outstanding so flushes never
+        // reach the event loop
+        if let Command::Flush = command {
+            self.pending_flushes.push((command_id, None));
+            return;
+        }
+        match command {
+            Command::SetStreamRateLimit { stream_id, limit } => {
+                self.inbound_limits.set_limit(stream_id, limit);
+                self.completed_locally
+                    .push((command_id, CommandResult::SetStreamRateLimit));
+                return;
+            }
+            Command::DisconnectStream { stream_id } => {
+                self.inbound_limits.remove(stream_id);
+            }
+            Command::Tick => {
+                let _ = self.tx_input.unbounded_send(DriverInput::Tick {
+                    command_id: Some(command_id),
+                });
+                return;
+            }
+            _ => {}
+        }
         let _ = self.tx_input.unbounded_send(DriverInput::Command {
             command_id,
             command: Box::new(command),
         });
     }
 
+    pub(crate) fn append_commit(&mut self, doc_id: DocumentId, commit: Commit) {
+        self.appends.push(doc_id, commit);
+    }
+
+    pub(crate) fn pending_appends(&self, doc_id: DocumentId) -> usize {
+        self.appends.pending(doc_id)
+    }
+
     pub(crate) fn tick(&mut self) {
-        let _ = self.tx_input.unbounded_send(DriverInput::Tick);
+        let _ = self
+            .tx_input
+            .unbounded_send(DriverInput::Tick { command_id: None });
+    }
+
+    pub(crate) fn cancel_command(&mut self, command_id: CommandId) {
+        let _ = self
+            .tx_input
+            .unbounded_send(DriverInput::CancelCommand { command_id });
+    }
+
+    pub(crate) fn cancel_all_commands(&mut self) {
+        let _ = self.tx_input.unbounded_send(DriverInput::CancelAllCommands);
     }
 
     pub(crate) fn step(&mut self, now: UnixTimestampMillis) -> EventResults {
         *self.now.borrow_mut() = now;
-        self.executor.run_until_stalled();
 
         let mut event_results = EventResults::default();
-        while let Ok(Some(evt)) = self.rx_output.try_next() {
-            match evt {
-                DriverOutput::CommandCompleted { command_id, result } => {
-                    event_results
-                        .completed_commands
-                        .insert(command_id, Ok(result));
-                }
-                DriverOutput::Stream { stream_id, event } => {
-                    event_results
-                        .new_stream_events
-                        .entry(stream_id)
-                        .or_default()
-                        .push(event);
-                }
-                DriverOutput::Task { task, reply } => {
-                    self.io_tasks.insert(task.id(), reply);
-                    event_results.new_tasks.push(task);
-                }
-                DriverOutput::EndpointRequest {
-                    endpoint_id,
-                    request_id,
-                    request,
-                } => {
-                    event_results
-                        .new_requests
-                        .entry(endpoint_id)
-                        .or_default()
-                        .push(NewRequest {
-                            id: request_id,
-                            request,
-                        });
-                }
-                DriverOutput::DocEvent { doc_id, event } => {
-                    event_results
-                        .notifications
-                        .entry(doc_id)
-                        .or_default()
-                        .push(event);
-                }
-                DriverOutput::PeersChanged(new_peers) => {
-                    event_results.peer_status_changes.extend(new_peers);
+        for (command_id, result) in self.completed_locally.drain(..) {
+            event_results
+                .completed_commands
+                .insert(command_id, Ok(result));
+        }
+        // A batch of appended commits which completes in this step lets the
+        // next batch for the same document start straight away
+        loop {
+            for (command_id, doc_id, commits) in self.appends.take_ready() {
+                let _ = self.tx_input.unbounded_send(DriverInput::Command {
+                    command_id,
+                    command: Box::new(Command::AddCommits { doc_id, commits }),
+                });
+            }
+            self.executor.run_until_stalled();
+
+            while let Ok(Some(evt)) = self.rx_output.try_next() {
+                match evt {
+                    DriverOutput::CommandCompleted { command_id, result } => {
+                        if let Some((doc_id, commits)) = self.appends.complete(command_id) {
+                            match result {
+                                CommandResult::AddCommits(Ok(added)) => {
+                                    if !added.new_bundles.is_empty() {
+                                        event_results
+                                            .appended_bundles
+                                            .entry(doc_id)
+                                            .or_default()
+                                            .extend(added.new_bundles);
+                                    }
+                                }
+                                CommandResult::AddCommits(Err(error)) => {
+                                    event_results.append_failures.push(AppendFailure {
+                                        doc_id,
+                                        commits,
+                                        error,
+                                    });
+                                }
+                                other => {
+                                    tracing::warn!(%doc_id, ?other, "appended commits were not added");
+                                }
+                            }
+                            continue;
+                        }
+                        event_results
+                            .completed_commands
+                            .insert(command_id, Ok(result));
+                    }
+                    DriverOutput::Stream { stream_id, event } => {
+                        if let Some(queues) = &mut self.outbound_queues {
+                            queues.queued(stream_id, &event);
+                        }
+                        if let StreamEvent::Close = event {
+                            self.inbound_limits.remove(stream_id);
+                        }
+                        event_results
+                            .new_stream_events
+                            .entry(stream_id)
+                            .or_default()
+                            .push(event);
+                    }
+                    DriverOutput::Task { mut task, reply } => {
+                        if let Some(key) = &self.storage_encryption {
+                            let id = task.id();
+                            match task.action_mut() {
+                                IoAction::Put {
+                                    key: storage_key,
+                                    data,
+                                } => {
+                                    *data = key.encrypt(storage_key, data);
+                                }
+                                IoAction::Load { key: storage_key } => {
+                                    self.pending_loads.insert(id, storage_key.clone());
+                                }
+                                IoAction::LoadRange { prefix } => {
+                                    self.pending_loads.insert(id, prefix.clone());
+                                }
+                                _ => {}
+                            }
+                        }
+                        // Encryption uses the key before it is moved into
+                        // the namespace so it doesn't depend on the namespace
+                        if let Some(namespace) = &self.storage_namespace {
+                            match task.action_mut() {
+                                IoAction::Load { key }
+                                | IoAction::Put { key, .. }
+                                | IoAction::Delete { key } => *key = key.within(namespace),
+                                IoAction::LoadRange { prefix }
+                                | IoAction::ListOneLevel { prefix } => {
+                                    *prefix = prefix.within(namespace)
+                                }
+                                IoAction::Sign { .. } => {}
+                            }
+                        }
+                        if matches!(
+                            task.action(),
+                            IoAction::Put { .. } | IoAction::Delete { .. }
+                        ) {
+                            self.pending_writes.insert(task.id());
+                        }
+                        self.io_tasks.insert(task.id(), reply);
+                        #[cfg(feature = "io_observer")]
+                        if let Some(observer) = &self.io_observer {
+                            let _ = observer.unbounded_send(crate::io::IoObservation::of(&task));
+                        }
+                        event_results.new_tasks.push(task);
+                    }
+                    DriverOutput::EndpointRequest {
+                        endpoint_id,
+                        request_id,
+                        request,
+                    } => {
+                        event_results
+                            .new_requests
+                            .entry(endpoint_id)
+                            .or_default()
+                            .push(NewRequest {
+                                id: request_id,
+                                request,
+                            });
+                    }
+                    DriverOutput::DocEvent { doc_id, event } => {
+                        event_results
+                            .notifications
+                            .entry(doc_id)
+                            .or_default()
+                            .push(event);
+                    }
+                    DriverOutput::PeersChanged(new_peers) => {
+                        event_results.peer_status_changes.extend(new_peers);
+                    }
+                    DriverOutput::CommandProgress(progress) => {
+                        event_results.command_progress.push(progress);
+                    }
+                    DriverOutput::RequestsTimedOut(request_ids) => {
+                        event_results.timed_out_requests.extend(request_ids);
+                    }
+                    DriverOutput::UnsolicitedOverLimit(stream_id) => {
+                        self.inbound_limits.unsolicited(stream_id);
+                    }
                 }
             }
+
+            if !self.appends.has_ready() || self.tx_input.is_closed() {
+                break;
+            }
+        }
+
+        if let Some(queues) = &mut self.outbound_queues {
+            event_results.congested_streams = queues.take_newly_congested();
         }
+        event_results.rate_limited_streams = self.inbound_limits.take_newly_limited();
+
+        for (_, waiting_for) in self.pending_flushes.iter_mut() {
+            waiting_for.get_or_insert_with(|| self.pending_writes.clone());
+        }
+        self.pending_flushes.retain(|(command_id, waiting_for)| {
+            if waiting_for.as_ref().is_some_and(|w| w.is_empty()) {
+                event_results
+                    .completed_commands
+                    .insert(*command_id, Ok(CommandResult::Flush));
+                false
+            } else {
+                true
+            }
+        });
 
         if self.tx_input.is_closed() {
             event_results.stopped = true;
         }
 
+        for command_id in event_results.completed_commands.keys() {
+            if let Some(correlation_id) = self.correlation_ids.remove(command_id) {
+                event_results
+                    .correlation_ids
+                    .insert(*command_id, correlation_id);
+            }
+        }
+
         event_results
     }
 }
 
+struct OutboundQueues {
+    threshold: usize,
+    queues: HashMap<StreamId, OutboundQueue>,
+}
+
+#[derive(Default)]
+struct OutboundQueue {
+    // The size of each unacknowledged message, oldest first
+    sizes: VecDeque<usize>,
+    bytes: usize,
+    // Whether we have reported this queue as congested since it last dropped
+    // back under the threshold
+    congested: bool,
+}
+
+impl OutboundQueues {
+    fn new(threshold: usize) -> Self {
+        Self {
+            threshold,
+            queues: HashMap::new(),
+        }
+    }
+
+    fn queued(&mut self, stream_id: StreamId, event: &StreamEvent) {
+        match event {
+            StreamEvent::Send(msg) => {
+                let queue = self.queues.entry(stream_id).or_default();
+                queue.sizes.push_back(msg.len());
+                queue.bytes += msg.len();
+            }
+            StreamEvent::Close => {
+                self.queues.remove(&stream_id);
+            }
+        }
+    }
+
+    fn sent(&mut self, stream_id: StreamId, num_messages: usize) {
+        let Some(queue) = self.queues.get_mut(&stream_id) else {
+            return;
+        };
+        if num_messages > queue.sizes.len() {
+            tracing::warn!(
+                ?stream_id,
+                num_messages,
+                queued = queue.sizes.len(),
+                "transport reported more stream messages sent than were queued"
+            );
+        }
+        for size in queue.sizes.drain(..num_messages.min(queue.sizes.len())) {
+            queue.bytes -= size;
+        }
+        if queue.bytes <= self.threshold {
+            queue.congested = false;
+        }
+    }
+
+    fn depth(&self, stream_id: StreamId) -> StreamQueueDepth {
+        self.queues
+            .get(&stream_id)
+            .map(|queue| StreamQueueDepth {
+                messages: queue.sizes.len(),
+                bytes: queue.bytes,
+            })
+            .unwrap_or_default()
+    }
+
+    fn take_newly_congested(&mut self) -> HashMap<StreamId, StreamQueueDepth> {
+        self.queues
+            .iter_mut()
+            .filter(|(_, queue)| !queue.congested && queue.bytes > self.threshold)
+            .map(|(stream_id, queue)| {
+                queue.congested = true;
+                (
+                    *stream_id,
+                    StreamQueueDepth {
+                        messages: queue.sizes.len(),
+                        bytes: queue.bytes,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+// Commits passed to `Event::append_commit`. Each document has at most one
+// batch being added at a time, commits appended meanwhile are queued for the
+// next batch.
+#[derive(Default)]
+struct Appends {
+    queued: HashMap<DocumentId, Vec<Commit>>,
+    // The document and commits of each batch, by the command adding it
+    in_flight: HashMap<CommandId, (DocumentId, Vec<CommitHash>)>,
+}
+
+impl Appends {
+    fn push(&mut self, doc_id: DocumentId, commit: Commit) {
+        self.queued.entry(doc_id).or_default().push(commit);
+    }
+
+    fn pending(&self, doc_id: DocumentId) -> usize {
+        let queued = self.queued.get(&doc_id).map(Vec::len).unwrap_or(0);
+        let in_flight = self
+            .in_flight
+            .values()
+            .filter(|(d, _)| *d == doc_id)
+            .map(|(_, commits)| commits.len())
+            .sum::<usize>();
+        queued + in_flight
+    }
+
+    fn is_busy(&self, doc_id: &DocumentId) -> bool {
+        self.in_flight.values().any(|(d, _)| d == doc_id)
+    }
+
+    fn has_ready(&self) -> bool {
+        self.queued.keys().any(|doc_id| !self.is_busy(doc_id))
+    }
+
+    // Start a batch for every document which has queued commits and no batch
+    // in flight
+    fn take_ready(&mut self) -> Vec<(CommandId, DocumentId, Vec<Commit>)> {
+        let ready = self
+            .queued
+            .keys()
+            .filter(|doc_id| !self.is_busy(doc_id))
+            .copied()
+            .collect::<Vec<_>>();
+        ready
+            .into_iter()
+            .filter_map(|doc_id| {
+                let commits = self.queued.remove(&doc_id)?;
+                let command_id = CommandId::new();
+                let hashes = commits.iter().map(|c| c.hash()).collect();
+                self.in_flight.insert(command_id, (doc_id, hashes));
+                Some((command_id, doc_id, commits))
+            })
+            .collect()
+    }
+
+    fn complete(&mut self, command_id: CommandId) -> Option<(DocumentId, Vec<CommitHash>)> {
+        self.in_flight.remove(&command_id)
+    }
+}
+
+struct InboundLimits {
+    // The limit of streams which haven't been given their own
+    default: Option<StreamRateLimit>,
+    streams: HashMap<StreamId, InboundBudget>,
+    newly_limited: Vec<StreamId>,
+}
+
+enum Admission {
+    Accept,
+    // The stream is over its limit but we are waiting for responses on it
+    ResponsesOnly,
+    Drop,
+}
+
+struct InboundBudget {
+    limit: Option<StreamRateLimit>,
+    window_start: Option<UnixTimestampMillis>,
+    messages: u32,
+    bytes: u64,
+    // Messages let through over the limit in the current window which turned
+    // out not to be responses
+    unsolicited: usize,
+    // Whether we have dropped a message in the current window
+    limited: bool,
+}
+
+impl InboundBudget {
+    fn new(limit: Option<StreamRateLimit>) -> Self {
+        Self {
+            limit,
+            window_start: None,
+            messages: 0,
+            bytes: 0,
+            unsolicited: 0,
+            limited: false,
+        }
+    }
+
+    fn mark_limited(&mut self, stream_id: StreamId, newly_limited: &mut Vec<StreamId>) {
+        if !self.limited {
+            self.limited = true;
+            newly_limited.push(stream_id);
+        }
+    }
+}
+
+impl InboundLimits {
+    fn new(default: Option<StreamRateLimit>) -> Self {
+        Self {
+            default,
+            streams: HashMap::new(),
+            newly_limited: Vec::new(),
+        }
+    }
+
+    fn budget(&mut self, stream_id: StreamId) -> &mut InboundBudget {
+        let default = self.default;
+        self.streams
+            .entry(stream_id)
+            .or_insert_with(|| InboundBudget::new(default))
+    }
+
+    fn set_limit(&mut self, stream_id: StreamId, limit: Option<StreamRateLimit>) {
+        let budget = self.budget(stream_id);
+        budget.limit = limit;
+        budget.window_start = None;
+    }
+
+    fn remove(&mut self, stream_id: StreamId) {
+        self.streams.remove(&stream_id);
+    }
+
+    // Whether to pass on a message of `size` bytes
+    //
+    // Responses to the `awaiting_responses` requests we sent on the stream
+    // don't count against the limit, but whether a message is a response is
+    // only known once it's parsed. Over the limit messages are passed on as
+    // `ResponsesOnly` until as many of them as we are waiting for have turned
+    // out to be something else, which bounds the parsing a peer can make us
+    // do. The cost is that a response arriving after that is dropped too.
+    fn accept(
+        &mut self,
+        now: UnixTimestampMillis,
+        stream_id: StreamId,
+        size: usize,
+        awaiting_responses: usize,
+    ) -> Admission {
+        if self.default.is_none() && !self.streams.contains_key(&stream_id) {
+            return Admission::Accept;
+        }
+        let default = self.default;
+        let budget = self
+            .streams
+            .entry(stream_id)
+            .or_insert_with(|| InboundBudget::new(default));
+        let Some(limit) = budget.limit else {
+            return Admission::Accept;
+        };
+        if budget
+            .window_start
+            .is_none_or(|start| now >= start + Duration::from_secs(1))
+        {
+            budget.window_start = Some(now);
+            budget.messages = 0;
+            budget.bytes = 0;
+            budget.unsolicited = 0;
+            budget.limited = false;
+        }
+        if budget.messages < limit.messages_per_second
+            && budget.bytes + size as u64 <= limit.bytes_per_second
+        {
+            budget.messages += 1;
+            budget.bytes += size as u64;
+            return Admission::Accept;
+        }
+        if budget.unsolicited < awaiting_responses {
+            return Admission::ResponsesOnly;
+        }
+        budget.mark_limited(stream_id, &mut self.newly_limited);
+        Admission::Drop
+    }
+
+    // A message let through by `Admission::ResponsesOnly` wasn't a response
+    fn unsolicited(&mut self, stream_id: StreamId) {
+        let Some(budget) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        budget.unsolicited += 1;
+        budget.mark_limited(stream_id, &mut self.newly_limited);
+    }
+
+    fn take_newly_limited(&mut self) -> Vec<StreamId> {
+        std::mem::take(&mut self.newly_limited)
+    }
+}
+
 pub(crate) struct DriveBeelayArgs<R: rand::Rng + rand::CryptoRng + Clone + 'static> {
     pub(crate) rng: R,
     pub(crate) now: Rc<RefCell<UnixTimestampMillis>>,
     pub(crate) tx_driver_events: mpsc::UnboundedSender<DriverOutput>,
     pub(crate) rx_input: mpsc::UnboundedReceiver<DriverInput>,
     pub(crate) verifying_key: VerifyingKey,
-    pub(crate) load_complete: oneshot::Sender<loading::LoadedParts<R>>,
+    pub(crate) load_complete: oneshot::Sender<Result<loading::LoadedParts<R>, crate::error::Load>>,
+    pub(crate) storage_encrypted: bool,
+    pub(crate) storage_namespaced: bool,
     pub(crate) session_duration: Duration,
+    pub(crate) bundling_policy: BundlingPolicy,
+    pub(crate) doc_size_limit: DocSizeLimit,
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) stream_reorder_window: usize,
 }
 
 pub(crate) async fn run<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
@@ -194,7 +832,13 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
         tx_driver_events,
         verifying_key,
         load_complete,
+        storage_encrypted,
+        storage_namespaced,
         session_duration,
+        bundling_policy,
+        doc_size_limit,
+        request_timeout,
+        stream_reorder_window,
     }: DriveBeelayArgs<R>,
 ) {
     // The tx end of this is part of `State::streams`. New messages fired by Streams::send_request
@@ -207,12 +851,40 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     let io = IoHandle::new(tx_driver_events.clone());
 
     // First load the beelay
+    if let Err(e) = storage_encryption::check(io.clone(), storage_encrypted).await {
+        let _ = load_complete.send(Err(e));
+        return;
+    }
+    commands::resume_namespace_deletions(io.clone()).await;
     let (state, mut keyhive_rx) = {
         let signer = Signer::new(verifying_key, io.clone());
 
         let load_docs = loading::load_docs(io.clone());
         let load_keyhive = loading::load_keyhive(io.clone(), rng.clone(), signer.clone());
-        let (docs, (keyhive, keyhive_rx)) = futures::future::join(load_docs, load_keyhive).await;
+        let load_expiries = membership_expiry::load(io.clone());
+        let load_ephemeral_docs = ephemeral_docs::load(io.clone());
+        let load_group_metadata = group_metadata::load(io.clone());
+        let load_tombstones = tombstones::load(io.clone());
+        let load_seals = seals::load(io.clone());
+        let load_capabilities = capabilities::load(io.clone());
+        let (
+            (docs, (keyhive, keyhive_rx), membership_expiries, vanished_docs, group_metadata),
+            tombstones,
+            seals,
+            capabilities,
+        ) = futures::future::join4(
+            futures::future::join5(
+                load_docs,
+                load_keyhive,
+                load_expiries,
+                load_ephemeral_docs,
+                load_group_metadata,
+            ),
+            load_tombstones,
+            load_seals,
+            load_capabilities,
+        )
+        .await;
         let peer_id = keyhive.active().borrow().verifying_key().into();
 
         let state = Rc::new(RefCell::new(State::new(
@@ -220,15 +892,26 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
             signer,
             keyhive,
             docs,
+            membership_expiries,
+            group_metadata,
+            tombstones,
+            seals,
+            capabilities,
+            vanished_docs,
+            storage_namespaced,
             session_duration,
+            bundling_policy,
+            doc_size_limit,
+            request_timeout,
+            stream_reorder_window,
             tx_outbound_stream_events,
             tx_outbound_endpoint_msgs,
         )));
         if load_complete
-            .send(loading::LoadedParts {
+            .send(Ok(loading::LoadedParts {
                 state: state.clone(),
                 peer_id,
-            })
+            }))
             .is_err()
         {
             tracing::warn!("load complete listener went away, stopping driver");
@@ -243,6 +926,10 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     let mut running_keyhive_event_stores = FuturesUnordered::new();
     // Commands which are waiting on IO
     let mut running_commands = FuturesUnordered::new();
+    // Tasks revoking memberships which have expired
+    let mut running_expiry_prunes = FuturesUnordered::new();
+    // Handles used to abort running commands when they are cancelled
+    let mut command_abort_handles: HashMap<CommandId, AbortHandle> = HashMap::new();
     // Requests which were sent over a stream and which are waiting on IO
     let mut inbound_stream_requests = FuturesUnordered::new();
     // Outbound stream messages which are waiting to be signed
@@ -275,6 +962,7 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
             );
             if running_commands.is_empty()
                 && running_keyhive_event_stores.is_empty()
+                && running_expiry_prunes.is_empty()
                 && ctx.state().streams().finished()
                 && signing_stream_messages.is_empty()
                 && signing_endpoint_messages.is_empty()
@@ -297,31 +985,77 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
                             }
                         } else {
                             let ctx = ctx.clone();
-                            let handler = async move {
-                                let result = commands::handle_command(ctx, *command).await;
-                                (command_id, result)
-                            };
+                            let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                            command_abort_handles.insert(command_id, abort_handle);
+                            let handler = Abortable::new(
+                                commands::handle_command(ctx, command_id, *command),
+                                abort_registration,
+                            )
+                            .map(move |result| (command_id, result));
                             running_commands.push(handler);
                         }
                     },
-                    DriverInput::StreamMessage { stream_id, message } => {
+                    DriverInput::StreamMessage { stream_id, message, responses_only } => {
                         match ctx.state().streams().handle_message(ctx.now(), stream_id, message) {
-                            Ok(Some(HandledMessage::NewRequest { from, req, id })) => {
-                                let ctx = ctx.clone();
-                                let handler = async move {
-                                    let result = request_handlers::handle_request(ctx, Some(stream_id), *req, from).await;
-                                    (stream_id, id, result)
+                            Ok(handled) => {
+                                let handled = if responses_only && !handled.is_empty() {
+                                    tracing::debug!(?stream_id, "stream is over its rate limit, dropping requests");
+                                    let _ = tx_driver_events.unbounded_send(DriverOutput::UnsolicitedOverLimit(stream_id));
+                                    Vec::new()
+                                } else {
+                                    handled
                                 };
-                                inbound_stream_requests.push(handler);
+                                for HandledMessage::NewRequest { from, req, id } in handled {
+                                    let ctx = ctx.clone();
+                                    let handler = async move {
+                                        let result = request_handlers::handle_request(ctx, Some(stream_id), *req, from).await;
+                                        (stream_id, id, result)
+                                    };
+                                    inbound_stream_requests.push(handler);
+                                }
                             },
-                            Ok(None) => {}
                             Err(e) => {
                                 tracing::error!(err=?e, "error handling stream message");
                             }
                         }
                     }
-                    DriverInput::Tick => {
+                    DriverInput::Tick { command_id } => {
                         tracing::trace!(now=?now.borrow().clone(), "tick");
+                        let mut summary = commands::TickSummary::default();
+                        if running_expiry_prunes.is_empty() {
+                            summary.expired_memberships = ctx.state().membership_expiries().num_expired(ctx.now().as_secs());
+                            if summary.expired_memberships > 0 {
+                                running_expiry_prunes.push(membership_expiry::prune_expired(ctx.clone()));
+                            }
+                        }
+                        summary.expired_deadlines = ctx.state().deadlines().fire_expired(ctx.now());
+                        // The syncs themselves are started by `reconcile` below
+                        summary.resynced_streams = sync_loops::streams_due_for_sync(&ctx, ctx.now());
+                        let timed_out = ctx.state().endpoints().expire_requests(ctx.now());
+                        if !timed_out.is_empty() {
+                            summary.timed_out_requests = timed_out.clone();
+                            let _ = tx_driver_events.unbounded_send(DriverOutput::RequestsTimedOut(timed_out));
+                        }
+                        if let Some(command_id) = command_id {
+                            let _ = tx_driver_events.unbounded_send(DriverOutput::CommandCompleted {
+                                command_id,
+                                result: CommandResult::Tick(summary),
+                            });
+                        }
+                    }
+                    DriverInput::CancelCommand { command_id } => {
+                        // The handle is removed when the command completes, so cancelling
+                        // a finished or unknown command is a no-op
+                        if let Some(handle) = command_abort_handles.get(&command_id) {
+                            tracing::debug!(?command_id, "cancelling command");
+                            handle.abort();
+                        }
+                    }
+                    DriverInput::CancelAllCommands => {
+                        tracing::debug!(num_commands = command_abort_handles.len(), "cancelling all commands");
+                        for handle in command_abort_handles.values() {
+                            handle.abort();
+                        }
                     }
                 }
             }
@@ -351,6 +1085,10 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
                             let signed = msg.sign(signer).await;
                             StreamEvent::Send(signed.encode())
                         }
+                        UnsignedStreamEvent::SendCompressed(msg, codec) => {
+                            let signed = msg.sign(signer).await;
+                            StreamEvent::Send(codec.compress(&signed.encode()))
+                        }
                     };
                     (stream_id, signed_evt)
                 })
@@ -363,14 +1101,18 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
             }
             signed = signing_stream_messages.select_next_some() => {
                 let (stream_id, event) = signed;
+                if let StreamEvent::Send(msg) = &event {
+                    ctx.state().streams().record_sent(stream_id, msg.len());
+                }
                 let _ = tx_driver_events.unbounded_send(DriverOutput::Stream { stream_id, event });
             },
             keyhive_event = keyhive_rx.select_next_some() => {
+                ctx.state().access_changes().mark_unchecked();
                 // horrible hack because at the moment keyhive events don't include secret keys
                 let ctx = ctx.clone();
                 let superceded_archives = std::mem::take(&mut current_archive_keys);
                 let task = async move {
-                    keyhive_storage::store_event(ctx.clone(), keyhive_event).await;
+                    keyhive_storage::store_event(ctx.clone(), keyhive_event, ctx.now().as_secs()).await;
                     let archive = ctx.state().keyhive().archive().await;
                     let archive_path = keyhive_storage::store_archive(ctx.clone(), archive).await;
                     let deletes = superceded_archives.into_iter().map({
@@ -391,8 +1133,13 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
             }
             finished_command = running_commands.select_next_some() => {
                 let (command_id, result) = finished_command;
+                command_abort_handles.remove(&command_id);
+                let result = result.unwrap_or(CommandResult::Cancelled);
                 let _ = tx_driver_events.unbounded_send(DriverOutput::CommandCompleted{ command_id, result });
             }
+            _ = running_expiry_prunes.select_next_some() => {
+                tracing::trace!("finished pruning expired memberships");
+            }
             stored_archive_path = running_keyhive_event_stores.select_next_some() => {
                 if let Some(path) = stored_archive_path {
                     current_archive_keys.push(path);
@@ -423,6 +1170,20 @@ async fn run_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
                 known_docs = docs_after;
             }
         }
+        for (doc_id, new_heads) in ctx.state().docs().take_heads_changes() {
+            ctx.io()
+                .new_doc_event(doc_id, DocEvent::HeadsChanged { new_heads });
+        }
+        for (doc_id, heads) in ctx.state().docs().take_divergences() {
+            ctx.io().new_doc_event(doc_id, DocEvent::Diverged { heads });
+        }
+        if ctx.state().access_changes().is_unchecked() {
+            if let Some(access) = ctx.state().keyhive().try_all_access() {
+                for (doc_id, event) in ctx.state().access_changes().take_changes(access) {
+                    ctx.io().new_doc_event(doc_id, event);
+                }
+            }
+        }
     }
 }
 
@@ -465,6 +1226,11 @@ pub(crate) enum DriverOutput {
         event: DocEvent,
     },
     PeersChanged(HashMap<PeerId, ConnectionInfo>),
+    CommandProgress(CommandProgress),
+    RequestsTimedOut(Vec<OutboundRequestId>),
+    // A stream over its rate limit sent a request rather than the response
+    // it was let through for
+    UnsolicitedOverLimit(streams::StreamId),
 }
 
 pub(crate) enum DriverInput {
@@ -475,6 +1241,16 @@ pub(crate) enum DriverInput {
     StreamMessage {
         stream_id: streams::StreamId,
         message: Vec<u8>,
+        // Set when the stream is over its rate limit, any requests in the
+        // message are dropped
+        responses_only: bool,
+    },
+    // `command_id` is set for ticks requested with `Event::tick_verbose`
+    Tick {
+        command_id: Option<CommandId>,
+    },
+    CancelCommand {
+        command_id: CommandId,
     },
-    Tick,
+    CancelAllCommands,
 }
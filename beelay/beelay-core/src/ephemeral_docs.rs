@@ -0,0 +1,36 @@
+use std::{collections::HashSet, str::FromStr};
+
+use crate::{io::IoHandle, DocumentId, StorageKey, TaskContext};
+
+// Only the fact that a document was ephemeral is stored, so that once its
+// commits are gone it can be reported as not found even though keyhive still
+// knows about it
+fn ephemeral_docs_prefix() -> StorageKey {
+    StorageKey::auth().push("ephemeral_docs")
+}
+
+/// Load the ephemeral documents created before this `Beelay` was loaded
+pub(crate) async fn load(io: IoHandle) -> HashSet<DocumentId> {
+    let keys = crate::task_context::Storage::new(&io)
+        .list_one_level(ephemeral_docs_prefix())
+        .await;
+    keys.into_iter()
+        .filter_map(|key| {
+            let doc_id = DocumentId::from_str(key.name()?)
+                .inspect_err(|e| tracing::warn!(?key, err=?e, "failed to parse ephemeral doc key"))
+                .ok()?;
+            Some(doc_id)
+        })
+        .collect()
+}
+
+/// Keep every commit of `doc_id` in memory from now on
+pub(crate) async fn make_ephemeral<R>(ctx: &TaskContext<R>, doc_id: DocumentId)
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    ctx.storage().make_ephemeral(doc_id);
+    ctx.storage()
+        .put(ephemeral_docs_prefix().push(doc_id.to_string()), Vec::new())
+        .await;
+}
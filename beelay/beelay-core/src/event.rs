@@ -3,15 +3,32 @@ use crate::{
         keyhive::{self, AddMemberToGroup, KeyhiveEntityId, MemberAccess, RemoveMemberFromGroup},
         Command,
     },
+    doc_status::SyncPriority,
     io::{self, IoResult},
-    Audience, CommandId, Commit, CommitBundle, DocumentId, EndpointId, EndpointResponse,
-    OutboundRequestId, SignedMessage, StreamDirection, StreamId,
+    Audience, Capability, CapabilityId, CommandId, Commit, CommitBundle, CommitHash, DocumentId,
+    EndpointId, EndpointResponse, ExportHandle, OutboundRequestId, PeerId, SignedMessage,
+    StreamCodec, StreamDirection, StreamId, StreamRateLimit, StreamResumptionToken, UnixTimestamp,
 };
 
 #[derive(Debug)]
 pub struct Event(pub(super) EventInner);
 
 impl Event {
+    /// Attach an ID of the caller's choosing to the command this event
+    /// begins, which is returned in `EventResults::correlation_ids` when the
+    /// command completes. The ID is not used for anything else.
+    ///
+    /// Events which don't begin a command are returned unchanged.
+    pub fn with_correlation_id(self, correlation_id: impl Into<String>) -> Event {
+        match self.0 {
+            EventInner::BeginCommand(command_id, command)
+            | EventInner::BeginCorrelatedCommand(command_id, command, _) => Event(
+                EventInner::BeginCorrelatedCommand(command_id, command, correlation_id.into()),
+            ),
+            other => Event(other),
+        }
+    }
+
     /// A storage task completed
     pub fn io_complete(result: IoResult) -> Event {
         Event(EventInner::IoComplete(result))
@@ -33,6 +50,19 @@ impl Event {
         (command_id, event)
     }
 
+    // Check the signature of a request and read who it claims to be from and
+    // who it is addressed to, without handling it. This touches neither
+    // storage nor any other state, so it is cheap enough to run on every
+    // request before deciding whether and where to handle it.
+    pub fn inspect_request(request: SignedMessage) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::InspectRequest(request)),
+        ));
+        (command_id, event)
+    }
+
     // Submit the response to an outgoing request
     pub fn handle_response(
         request_id: OutboundRequestId,
@@ -51,7 +81,8 @@ impl Event {
         )
     }
 
-    // Add some commits to a document
+    // Add some commits to a document. The result says how many of the commits
+    // were new and what the heads of the document were before and after.
     #[tracing::instrument(skip(commits))]
     pub fn add_commits(root_id: DocumentId, commits: Vec<Commit>) -> (CommandId, Event) {
         let command_id = CommandId::new();
@@ -67,6 +98,52 @@ impl Event {
         )
     }
 
+    // Append one commit to a document without waiting for it to be stored.
+    // Appended commits are batched into `add_commits` behind the scenes and
+    // there is no command to complete, commits which can't be added are
+    // reported in `EventResults::append_failures` instead. Use
+    // `Beelay::pending_appends` to see how far behind storage is.
+    #[tracing::instrument(skip(commit), fields(hash=%commit.hash()))]
+    pub fn append_commit(doc_id: DocumentId, commit: Commit) -> Event {
+        Event(EventInner::AppendCommit(doc_id, commit))
+    }
+
+    // Add some commits to a document, reporting which commits are missing
+    // ancestors. If `strict` is true commits with missing parents are an error
+    // and nothing is added.
+    #[tracing::instrument(skip(commits))]
+    pub fn add_commits_checked(
+        doc_id: DocumentId,
+        commits: Vec<Commit>,
+        strict: bool,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        (
+            command_id,
+            Event(EventInner::BeginCommand(
+                command_id,
+                Box::new(Command::AddCommitsChecked {
+                    doc_id,
+                    commits,
+                    strict,
+                }),
+            )),
+        )
+    }
+
+    // Add commits to several documents under a single command
+    #[tracing::instrument(skip(batches))]
+    pub fn add_commits_multi(batches: Vec<(DocumentId, Vec<Commit>)>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        (
+            command_id,
+            Event(EventInner::BeginCommand(
+                command_id,
+                Box::new(Command::AddCommitsMulti { batches }),
+            )),
+        )
+    }
+
     // Create a new document
     pub fn create_doc(
         initial_commit: Commit,
@@ -78,6 +155,90 @@ impl Event {
             Box::new(Command::CreateDoc {
                 initial_commit,
                 other_owners,
+                idempotency_key: None,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    /// Create a new document, or return the document already created with
+    /// `idempotency_key`
+    ///
+    /// This makes it safe to retry a create whose result was lost. The key is
+    /// stored, so it still applies after a restart. Once the document it
+    /// created has been deleted the key creates a new document again.
+    pub fn create_doc_idempotent(
+        initial_commit: Commit,
+        other_owners: Vec<KeyhiveEntityId>,
+        idempotency_key: String,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::CreateDoc {
+                initial_commit,
+                other_owners,
+                idempotency_key: Some(idempotency_key),
+            }),
+        ));
+        (command_id, event)
+    }
+
+    /// Create a new document whose commits are never written to storage
+    ///
+    /// The document is synced like any other but its commits only live in
+    /// memory, so it disappears when this `Beelay` is stopped. Loading it
+    /// after a restart returns `None` unless a peer has since synced its
+    /// commits back to us, in which case they are stored as normal.
+    pub fn create_ephemeral_doc(
+        initial_commit: Commit,
+        other_owners: Vec<KeyhiveEntityId>,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::CreateEphemeralDoc {
+                initial_commit,
+                other_owners,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    /// Create a new document which is shared with each of `grants` from the
+    /// start
+    ///
+    /// Unlike calling `add_member_to_doc` after `create_doc` there is never a
+    /// point at which the document exists but isn't shared. If any grantee
+    /// can't be found the document is not created.
+    pub fn create_doc_with_grants(
+        initial_commit: Commit,
+        other_owners: Vec<KeyhiveEntityId>,
+        grants: Vec<(KeyhiveEntityId, MemberAccess)>,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::CreateDocWithGrants {
+                initial_commit,
+                other_owners,
+                grants,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    // Create a new document whose initial history is the contents of `bundle`
+    pub fn create_doc_from_bundle(
+        bundle: CommitBundle,
+        other_owners: Vec<KeyhiveEntityId>,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::CreateDocFromBundle {
+                bundle,
+                other_owners,
             }),
         ));
         (command_id, event)
@@ -108,7 +269,33 @@ impl Event {
         (command_id, event)
     }
 
-    // Add a bundle of commits to a document
+    // Load a document as it was at `heads`, decrypting only the commits and
+    // bundles which are ancestors of them. Fails with
+    // `LoadDocAt::NotACommit` if one of the heads isn't a commit in the
+    // document, see `CommandResult::LoadDocAt`.
+    pub fn load_doc_at(doc_id: DocumentId, heads: Vec<CommitHash>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::LoadDocAt { doc_id, heads }),
+        ));
+        (command_id, event)
+    }
+
+    // Load the commits in a document which are reachable from `heads`
+    pub fn load_commits_from(doc_id: DocumentId, heads: Vec<CommitHash>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::LoadCommitsFrom { doc_id, heads }),
+        ));
+        (command_id, event)
+    }
+
+    // Add a bundle of commits to a document. If an earlier `add_bundle` of
+    // the same bundle was interrupted, for example by a crash, after it wrote
+    // the bundle's contents then those contents, and the CGKA operation they
+    // were encrypted with, are reused, which the result reports as `resumed`.
     pub fn add_bundle(doc: DocumentId, bundle: CommitBundle) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
@@ -121,161 +308,1295 @@ impl Event {
         (command_id, event)
     }
 
-    pub fn create_stream(direction: StreamDirection) -> (CommandId, Event) {
+    /// Add a bundle of commits to a document after checking that it is well
+    /// formed and that its keyhive material is correctly signed
+    ///
+    /// If `strict` is true nothing is stored unless the bundle is free of
+    /// problems. Otherwise whatever can be used is stored and the problems
+    /// which were skipped over are returned. Use this rather than `add_bundle`
+    /// for bundles from untrusted sources, such as user supplied files.
+    pub fn add_bundle_checked(
+        doc: DocumentId,
+        bundle: CommitBundle,
+        strict: bool,
+    ) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::CreateStream(direction)),
+            Box::new(Command::AddBundleChecked {
+                doc_id: doc,
+                bundle,
+                strict,
+            }),
         ));
         (command_id, event)
     }
 
-    pub fn disconnect_stream(stream_id: StreamId) -> (CommandId, Event) {
+    // Export the commits reachable from `heads` as a single bundle which can be
+    // passed to `add_bundle` on another node. If `include_keys` is true the
+    // bundle also carries the keyhive events a member needs to decrypt the
+    // document.
+    pub fn export_bundle(
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+        include_keys: bool,
+    ) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::DisconnectStream { stream_id }),
+            Box::new(Command::ExportBundle {
+                doc_id,
+                heads,
+                include_keys,
+            }),
         ));
         (command_id, event)
     }
 
-    pub fn handle_message(stream_id: StreamId, message: Vec<u8>) -> Event {
-        Event(EventInner::StreamMessage(stream_id, message))
+    /// Start a streaming export of the commits reachable from `heads`
+    ///
+    /// Use the returned `ExportHandle` with `next_export_chunk` to read the
+    /// export, which avoids holding the whole document in memory as
+    /// `export_bundle` does. The export is invalidated if the document is
+    /// deleted before it has been read to the end.
+    pub fn begin_export(doc_id: DocumentId, heads: Vec<CommitHash>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::BeginExport { doc_id, heads }),
+        ));
+        (command_id, event)
     }
 
-    pub fn register_endpoint(audience: Audience) -> (CommandId, Event) {
+    /// Read the next chunk of an export started with `begin_export`
+    ///
+    /// Every chunk is the same size except the last, after which `None` is
+    /// returned. Chunks don't line up with commits, concatenate them and pass
+    /// the result to `CommitOrBundle::decode_export` to read the export back.
+    pub fn next_export_chunk(handle: ExportHandle) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::RegisterEndpoint(audience)),
+            Box::new(Command::NextExportChunk(handle)),
         ));
         (command_id, event)
     }
 
-    pub fn unregister_endpoint(endpoint_id: EndpointId) -> (CommandId, Event) {
+    /// Add part of the contents of a commit which is too large to pass to
+    /// `add_commits` in one go
+    ///
+    /// Chunks are buffered until every chunk from zero up to the one marked
+    /// `is_last` has been received, at which point the chunks are concatenated
+    /// and the commit is added. Chunks may arrive out of order and every chunk
+    /// must be given the same parents.
+    pub fn add_commit_chunk(
+        doc_id: DocumentId,
+        commit_id: CommitHash,
+        parents: Vec<CommitHash>,
+        chunk_index: u32,
+        bytes: Vec<u8>,
+        is_last: bool,
+    ) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::UnregisterEndpoints(endpoint_id)),
+            Box::new(Command::AddCommitChunk {
+                doc_id,
+                commit: commit_id,
+                parents,
+                chunk_index,
+                bytes,
+                is_last,
+            }),
         ));
         (command_id, event)
     }
 
-    pub fn stop() -> Event {
+    // Drop the chunks received so far for a commit which will never be completed
+    pub fn discard_commit_chunks(doc_id: DocumentId, commit_id: CommitHash) -> (CommandId, Event) {
         let command_id = CommandId::new();
-        Event(EventInner::BeginCommand(
+        let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Stop),
-        ))
+            Box::new(Command::DiscardCommitChunks {
+                doc_id,
+                commit: commit_id,
+            }),
+        ));
+        (command_id, event)
     }
 
-    pub fn add_member_to_doc(
+    // Delete a document and all of its commits and bundles from local storage
+    pub fn delete_doc(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DeleteDoc { doc_id }),
+        ));
+        (command_id, event)
+    }
+
+    // Delete everything stored under another Beelay's storage namespace, see
+    // `Config::storage_namespace`. This fails with `DeleteNamespace::Namespaced`
+    // on a Beelay which has a namespace of its own.
+    pub fn delete_namespace(namespace: String) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DeleteNamespace(namespace)),
+        ));
+        (command_id, event)
+    }
+
+    /// Purge the content of a document whilst keeping a record that it existed
+    ///
+    /// Every commit and bundle of the document is deleted, as with
+    /// `delete_doc`, but its keyhive membership history is kept along with a
+    /// tombstone naming who purged it and when. `load_doc` then returns
+    /// `CommandResult::Tombstoned`, and no new content is accepted for the
+    /// document. The tombstone is synced to every peer who can pull the
+    /// document, who purge their copies too. Only admins of the document can
+    /// tombstone it.
+    pub fn tombstone_doc(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::TombstoneDoc { doc_id }),
+        ));
+        (command_id, event)
+    }
+
+    /// Permanently stop any more commits being added to a document
+    ///
+    /// After sealing, `add_commits` and `add_bundle` for the document fail
+    /// with a `Sealed` error and commits uploaded by peers are refused, even
+    /// if they come from an admin. The document can still be loaded, synced
+    /// to peers who are missing some of its content and have its access
+    /// queried. The seal is synced to every peer who can pull the document,
+    /// who enforce it too. There is no way to unseal a document. Only admins
+    /// of the document can seal it.
+    pub fn seal_doc(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SealDoc { doc_id }),
+        ));
+        (command_id, event)
+    }
+
+    /// Mint a capability which lets whoever holds it make requests about
+    /// `doc_id` to us until `expires_at`, without being a member of it
+    ///
+    /// Pull and read capabilities allow fetching the document's commits,
+    /// write and admin capabilities also allow uploading commits to it. No
+    /// keyhive access is granted, so the holder can't decrypt what they fetch
+    /// or sync the document's membership. Requests made with a capability
+    /// which has expired or been revoked with `revoke_capability` fail with
+    /// `RequestFailure::InvalidCapability`. Only admins of the document can
+    /// mint capabilities for it.
+    pub fn mint_capability(
         doc_id: DocumentId,
-        member: KeyhiveEntityId,
         access: MemberAccess,
+        expires_at: UnixTimestamp,
     ) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::AddMemberToDoc(
-                doc_id, member, access,
-            ))),
+            Box::new(Command::MintCapability {
+                doc_id,
+                access,
+                expires_at,
+            }),
         ));
         (command_id, event)
     }
 
-    pub fn remove_member_from_doc(
-        doc_id: DocumentId,
-        member: KeyhiveEntityId,
-    ) -> (CommandId, Event) {
+    // Revoke a capability minted with `mint_capability`
+    pub fn revoke_capability(id: CapabilityId) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(
-                keyhive::KeyhiveCommand::RemoveMemberFromDoc(doc_id, member),
-            )),
+            Box::new(Command::RevokeCapability(id)),
         ));
         (command_id, event)
     }
 
-    pub fn query_access(doc_id: DocumentId) -> (CommandId, Event) {
+    // Delete the loose commits of a document which are already covered by bundles
+    pub fn compact_doc(doc_id: DocumentId) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::QueryAccess(
-                doc_id,
-            ))),
+            Box::new(Command::CompactDoc { doc_id }),
         ));
         (command_id, event)
     }
 
-    pub fn create_group(other_owners: Vec<KeyhiveEntityId>) -> (CommandId, Event) {
+    // Check the stored commits and bundles of a document for corruption
+    // without attempting to repair anything
+    pub fn verify_doc(doc_id: DocumentId) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::CreateGroup(
-                other_owners,
-            ))),
+            Box::new(Command::VerifyDoc { doc_id }),
         ));
         (command_id, event)
     }
 
-    pub fn add_member_to_group(add: AddMemberToGroup) -> (CommandId, Event) {
+    // Fetch the parents of each stored commit of a document and the
+    // boundaries of each stored bundle, without loading or decrypting anything
+    pub fn doc_graph(doc_id: DocumentId) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::AddMemberToGroup(
-                add,
-            ))),
+            Box::new(Command::DocGraph { doc_id }),
         ));
         (command_id, event)
     }
 
-    pub fn remove_member_from_group(remove: RemoveMemberFromGroup) -> (CommandId, Event) {
+    // Copy the history of `from` into `into` and give the members of `from`
+    // the same access to `into`
+    pub fn merge_docs(into: DocumentId, from: DocumentId) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(
-                keyhive::KeyhiveCommand::RemoveMemberFromGroup(remove),
-            )),
+            Box::new(Command::MergeDocs { into, from }),
         ));
         (command_id, event)
     }
 
-    pub fn query_status(doc_id: DocumentId) -> (CommandId, Event) {
+    // Count the commits, bundles and heads of a document and the bytes they
+    // take up in storage, without loading or decrypting anything
+    pub fn doc_stats(doc_id: DocumentId) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::QueryStatus(doc_id)),
+            Box::new(Command::DocStats { doc_id }),
         ));
         (command_id, event)
     }
 
-    #[cfg(feature = "debug_events")]
-    pub fn log_keyhive_events(
-        nicknames: keyhive_core::debug_events::Nicknames,
-    ) -> (CommandId, Event) {
+    // Count the commits and bundles whose contents are identical to those of
+    // another document, and so are only stored once, and the bytes this saves
+    pub fn dedup_stats() -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::DebugEvents(
-                nicknames,
-            ))),
+            Box::new(Command::DedupStats),
         ));
         (command_id, event)
     }
 
-    pub fn create_contact_card() -> (CommandId, Event) {
+    // Check which of `hashes` are already stored locally, without loading or
+    // decrypting anything. Commits which are only in the interior of a stored
+    // bundle are reported as missing.
+    pub fn has_commits(doc_id: DocumentId, hashes: Vec<CommitHash>) -> (CommandId, Event) {
         let command_id = CommandId::new();
         let event = Event(EventInner::BeginCommand(
             command_id,
-            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::CreateContactCard)),
+            Box::new(Command::HasCommits { doc_id, hashes }),
         ));
         (command_id, event)
     }
 
-    pub fn tick() -> Event {
-        Event(EventInner::Tick)
+    // List the stored commits of a document whose parents haven't arrived yet,
+    // along with the parents which are missing
+    pub fn pending_orphans(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::PendingOrphans { doc_id }),
+        ));
+        (command_id, event)
+    }
+
+    // List the stored commits of a document which can't be reached by walking
+    // back from its heads, for example to decide whether to compact it
+    pub fn find_unreachable(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::FindUnreachable { doc_id }),
+        ));
+        (command_id, event)
+    }
+
+    // List the commits which each of two documents has and the other doesn't,
+    // for example to see how two forked copies differ before merging them.
+    // Only the metadata of the documents is compared, nothing is decrypted.
+    pub fn diff_docs(a: DocumentId, b: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DiffDocs { a, b }),
+        ));
+        (command_id, event)
+    }
+
+    /// Ask the peers on the other end of our established streams for each of
+    /// `hashes`, for example the missing parents reported by
+    /// `pending_orphans`
+    ///
+    /// Completes once every commit has arrived or every stream has been
+    /// asked, reporting the commits which no peer had as unfetchable. If
+    /// [`Config::request_timeout`](crate::config::Config::request_timeout)
+    /// is set the command gives up on the commits which haven't arrived once
+    /// the timeout has elapsed, which is checked on `Event::tick`.
+    /// Ask the admins of `doc_id` on the other end of our streams for
+    /// `desired` access
+    ///
+    /// Admins are notified with `DocEvent::AccessRequested` and approve the
+    /// request by adding us with `Event::add_member_to_doc`. Their decision
+    /// arrives as `DocEvent::AccessRequestGranted` or
+    /// `DocEvent::AccessRequestDenied`. If we don't know the members of the
+    /// document every connected peer is asked. Completes with the admins
+    /// who accepted the request once each peer has replied.
+    pub fn request_doc_access(doc_id: DocumentId, desired: MemberAccess) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::RequestDocAccess { doc_id, desired }),
+        ));
+        (command_id, event)
+    }
+
+    // Refuse a request for access to `doc_id` made by `requester`, who is
+    // notified if they are still connected
+    pub fn deny_doc_access(doc_id: DocumentId, requester: PeerId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DenyDocAccess { doc_id, requester }),
+        ));
+        (command_id, event)
+    }
+
+    pub fn request_commits(doc_id: DocumentId, hashes: Vec<CommitHash>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::RequestCommits { doc_id, hashes }),
+        ));
+        (command_id, event)
+    }
+
+    // Estimate how many commits and bundles, and how many bytes of them,
+    // syncing `doc_ids` over `stream_id` would transfer in each direction.
+    // Only the metadata of the remote's commits and bundles is exchanged, no
+    // commit contents are sent or received.
+    pub fn estimate_sync(stream_id: StreamId, doc_ids: Vec<DocumentId>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::EstimateSync { stream_id, doc_ids }),
+        ));
+        (command_id, event)
+    }
+
+    // Ask the peer on the other end of `stream_id` for the documents it has
+    // which we are allowed to pull. The peer checks its own access control, so
+    // documents we can't see aren't revealed. Like any command this can be
+    // cancelled with `Event::cancel_command` while waiting for the peer.
+    pub fn discover_docs(stream_id: StreamId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DiscoverDocs(stream_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Check whether a document has been merged back down to a single head
+    // since it last diverged, see `DocEvent::Diverged`
+    pub fn doc_is_merged(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DocIsMerged(doc_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Load and decrypt the given documents from local storage into memory so
+    // that loading them later is fast. Documents which aren't stored locally
+    // are reported as missing rather than fetched from peers.
+    pub fn warm_docs(doc_ids: Vec<DocumentId>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::WarmDocs(doc_ids)),
+        ));
+        (command_id, event)
+    }
+
+    // List the documents stored locally
+    pub fn list_documents() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::ListDocuments { access: None }),
+        ));
+        (command_id, event)
+    }
+
+    // List the documents stored locally which we have at least `access` to
+    pub fn list_documents_with_access(access: MemberAccess) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::ListDocuments {
+                access: Some(access),
+            }),
+        ));
+        (command_id, event)
+    }
+
+    pub fn create_stream(direction: StreamDirection) -> (CommandId, Event) {
+        Self::create_stream_with_codecs(direction, Vec::new())
+    }
+
+    // Create a stream which offers to compress messages with one of `codecs`,
+    // in order of preference. If the remote doesn't support any of them the
+    // stream falls back to sending uncompressed messages.
+    pub fn create_stream_with_codecs(
+        direction: StreamDirection,
+        codecs: Vec<StreamCodec>,
+    ) -> (CommandId, Event) {
+        Self::create_stream_with_resumption(direction, codecs, None)
+    }
+
+    // Create a stream which attempts to pick up where a previous stream to
+    // the same peer left off, see `StreamResumptionToken`. If the token can't
+    // be used the stream performs a full sync as normal.
+    pub fn create_stream_with_resumption(
+        direction: StreamDirection,
+        codecs: Vec<StreamCodec>,
+        resume_from: Option<StreamResumptionToken>,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::CreateStream {
+                direction,
+                codecs,
+                resume_from,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    pub fn disconnect_stream(stream_id: StreamId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DisconnectStream { stream_id }),
+        ));
+        (command_id, event)
+    }
+
+    /// Close every established stream whose remote authenticated as `peer`
+    ///
+    /// Completes with `CommandResult::DisconnectPeer` holding the number of
+    /// streams which were closed. Streams which are still handshaking aren't
+    /// closed as we don't yet know who is on the other end. Nothing stops the
+    /// peer connecting again afterwards.
+    pub fn disconnect_peer(peer: KeyhiveEntityId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::DisconnectPeer(peer)),
+        ));
+        (command_id, event)
+    }
+
+    pub fn handle_message(stream_id: StreamId, message: Vec<u8>) -> Event {
+        Event(EventInner::StreamMessage(stream_id, message))
+    }
+
+    /// The transport has sent the oldest `num_messages` unacknowledged
+    /// [`StreamEvent::Send`](crate::StreamEvent::Send) messages for a stream
+    ///
+    /// Only needed when a stream congestion threshold has been configured.
+    pub fn stream_messages_sent(stream_id: StreamId, num_messages: usize) -> Event {
+        Event(EventInner::StreamMessagesSent(stream_id, num_messages))
+    }
+
+    /// Replace the inbound rate limit of a stream, `None` removes it
+    ///
+    /// This overrides [`Config::stream_rate_limit`](crate::config::Config::stream_rate_limit)
+    /// for the stream and starts a new one second window.
+    pub fn set_stream_rate_limit(
+        stream_id: StreamId,
+        limit: Option<StreamRateLimit>,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SetStreamRateLimit { stream_id, limit }),
+        ));
+        (command_id, event)
+    }
+
+    pub fn register_endpoint(audience: Audience) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::RegisterEndpoint(audience)),
+        ));
+        (command_id, event)
+    }
+
+    /// Register an endpoint which serves several audiences
+    ///
+    /// A request passed to `handle_request` with a `receive_audience` which is
+    /// any of `audiences` is accepted if it is addressed to any of the others.
+    /// Requests we send to the endpoint are addressed to the first audience.
+    /// Unregistering the endpoint removes all of its audiences at once.
+    pub fn register_endpoint_multi(audiences: Vec<Audience>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::RegisterEndpointMulti(audiences)),
+        ));
+        (command_id, event)
+    }
+
+    /// Register an endpoint whose requests are made on behalf of a group we
+    /// are a transitive member of
+    ///
+    /// Requests sent to the endpoint carry the membership history which proves
+    /// that we belong to `on_behalf_of`, so the receiver can check our access
+    /// through the group. The history is captured at registration, re-register
+    /// the endpoint to pick up later changes to the group. Only groups can be
+    /// delegated from.
+    pub fn register_delegated_endpoint(
+        audience: Audience,
+        on_behalf_of: KeyhiveEntityId,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::RegisterDelegatedEndpoint {
+                audience,
+                on_behalf_of,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    /// Register an endpoint whose requests are authorized by `capability`
+    ///
+    /// `audience` should address the peer who minted the capability. Fetch
+    /// the document with `fetch_with_capability`.
+    pub fn register_capability_endpoint(
+        audience: Audience,
+        capability: Capability,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::RegisterCapabilityEndpoint {
+                audience,
+                capability,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    // Download the document the capability of `endpoint_id` is for, see
+    // `CommandResult::FetchWithCapability`
+    pub fn fetch_with_capability(endpoint_id: EndpointId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::FetchWithCapability(endpoint_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Sync `doc_id` with the peer `endpoint_id` addresses, sending the requests
+    // through the endpoint. See `CommandResult::SyncWithEndpoint`
+    pub fn sync_with_endpoint(endpoint_id: EndpointId, doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SyncWithEndpoint {
+                endpoint_id,
+                doc_id,
+            }),
+        ));
+        (command_id, event)
+    }
+
+    pub fn unregister_endpoint(endpoint_id: EndpointId) -> (CommandId, Event) {
+        Self::unregister_endpoints(vec![endpoint_id])
+    }
+
+    // Unregister several endpoints at once, ids which aren't registered are skipped
+    pub fn unregister_endpoints(endpoint_ids: Vec<EndpointId>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::UnregisterEndpoints(endpoint_ids)),
+        ));
+        (command_id, event)
+    }
+
+    /// Wait for every storage write which is currently outstanding to be
+    /// acknowledged via `Event::io_complete`. Completes immediately if there
+    /// are no outstanding writes.
+    pub fn flush() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Flush),
+        ));
+        (command_id, event)
+    }
+
+    pub fn stop() -> Event {
+        let command_id = CommandId::new();
+        Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Stop),
+        ))
+    }
+
+    pub fn add_member_to_doc(
+        doc_id: DocumentId,
+        member: KeyhiveEntityId,
+        access: MemberAccess,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::AddMemberToDoc(
+                doc_id, member, access, None,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // Add a member to a document, revoking the membership once `expires_at` has passed
+    pub fn add_member_to_doc_until(
+        doc_id: DocumentId,
+        member: KeyhiveEntityId,
+        access: MemberAccess,
+        expires_at: UnixTimestamp,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::AddMemberToDoc(
+                doc_id,
+                member,
+                access,
+                Some(expires_at),
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    pub fn remove_member_from_doc(
+        doc_id: DocumentId,
+        member: KeyhiveEntityId,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::RemoveMemberFromDoc(doc_id, member),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    // Change the access of an existing member of a document without removing
+    // and re-adding them. A member who can still read afterwards never loses
+    // access and keeps their place in the document's key agreement. Completes
+    // with the access the member had before the change.
+    pub fn change_member_access(
+        doc_id: DocumentId,
+        member: KeyhiveEntityId,
+        new_access: MemberAccess,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::ChangeMemberAccess(doc_id, member, new_access),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    // Rotate the key used to encrypt new commits to a document. This is
+    // typically done after removing a member so that they cannot decrypt
+    // future commits. Note that this does not protect commits which have
+    // already been encrypted and distributed, those remain readable by anyone
+    // who had the old key.
+    pub fn rotate_doc_key(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::RotateDocKey(
+                doc_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    pub fn query_access(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::QueryAccess(
+                doc_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // Find the best access `entity` has to a document, including via groups
+    // and public access. Only admins of the document can ask.
+    pub fn query_access_for(doc_id: DocumentId, entity: KeyhiveEntityId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::QueryAccessFor(
+                doc_id, entity,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // Find our own access to each of `doc_ids` in one go. Documents we can't
+    // see at all map to `None` rather than being left out.
+    pub fn query_access_multi(doc_ids: Vec<DocumentId>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::QueryAccessMulti(
+                doc_ids,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // List everyone with access to a document, including members who have
+    // access via a group
+    pub fn list_doc_members(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ListDocMembers(
+                doc_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // The keyhive events which granted, revoked or rekeyed access to a
+    // document, oldest first, for auditing. See `keyhive::AccessLogEntry`.
+    pub fn export_access_log(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ExportAccessLog(
+                doc_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    /// The current key epoch of a document and, if we are an admin of it,
+    /// which epochs each member holds the key for. Useful for working out
+    /// why a member can't decrypt recent commits. See `keyhive::DocKeyEpochs`.
+    pub fn doc_key_epochs(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::DocKeyEpochs(
+                doc_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    /// The rotations of a document's key which will happen on their own,
+    /// soonest first. The key is rotated whenever a member is revoked because
+    /// their membership of the document, or of a group which is a member of
+    /// it, expired.
+    pub fn list_scheduled_rotations(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::ListScheduledRotations(doc_id),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    /// Cancel a rotation listed by `list_scheduled_rotations` before it
+    /// happens. This clears the expiry which causes it, so the membership
+    /// becomes permanent. Fails with `AlreadyApplied` if the rotation has
+    /// already happened.
+    pub fn cancel_scheduled_rotation(
+        doc_id: DocumentId,
+        rotation_id: keyhive::RotationId,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::CancelScheduledRotation(doc_id, rotation_id),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    pub fn create_group(other_owners: Vec<KeyhiveEntityId>) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::CreateGroup(
+                other_owners,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // Create a group owned by us and `other_owners` with each of `members`
+    // given the specified access in a single step. If any owner or member is
+    // a group or document we don't know about no group is created.
+    pub fn create_group_with_members(
+        other_owners: Vec<KeyhiveEntityId>,
+        members: Vec<(KeyhiveEntityId, MemberAccess)>,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::CreateGroupWithMembers(other_owners, members),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    // List every member of a group, including the members of nested groups
+    pub fn expand_group(group_id: PeerId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ExpandGroup(
+                group_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // List every group we are a member of, directly or through other groups,
+    // along with our effective access to each
+    pub fn list_my_groups() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ListMyGroups)),
+        ));
+        (command_id, event)
+    }
+
+    // Replace the display name and key/value metadata of a group
+    pub fn set_group_metadata(
+        group_id: PeerId,
+        metadata: keyhive::GroupMetadata,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::SetGroupMetadata(
+                group_id, metadata,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    pub fn get_group_metadata(group_id: PeerId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::GetGroupMetadata(
+                group_id,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    // Compute who would gain or lose access to a group if `change` were
+    // applied, without applying it
+    pub fn preview_access_change(change: keyhive::AccessChange) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::PreviewAccessChange(change),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    pub fn add_member_to_group(add: AddMemberToGroup) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::AddMemberToGroup(
+                add,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    pub fn remove_member_from_group(remove: RemoveMemberFromGroup) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(
+                keyhive::KeyhiveCommand::RemoveMemberFromGroup(remove),
+            )),
+        ));
+        (command_id, event)
+    }
+
+    pub fn query_status(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::QueryStatus(doc_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Stop syncing a document on every stream until `resume_doc_sync` is
+    // called. We stop offering the document to peers and ignore any commits
+    // they send us for it, changes to who can access the document still sync.
+    // The paused state is not persisted.
+    pub fn pause_doc_sync(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::PauseDocSync(doc_id)),
+        ));
+        (command_id, event)
+    }
+
+    pub fn resume_doc_sync(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::ResumeDocSync(doc_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Change how soon a document is synced relative to the others on every
+    // stream. Higher priority documents are synced and have their changes
+    // forwarded first, and raising the priority starts a sync straight away.
+    // A `SyncPriority::High` document goes back to `SyncPriority::Normal`
+    // once it is in sync on every established stream, other priorities last
+    // until they are changed. Priorities are not persisted.
+    pub fn prioritize_doc_sync(doc_id: DocumentId, priority: SyncPriority) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::PrioritizeDocSync { doc_id, priority }),
+        ));
+        (command_id, event)
+    }
+
+    // Emit a `DocEvent::HeadsChanged` notification whenever the heads of a
+    // document change, whether because of local changes or sync. The
+    // subscription is not persisted.
+    pub fn subscribe_doc(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SubscribeDoc(doc_id)),
+        ));
+        (command_id, event)
+    }
+
+    pub fn unsubscribe_doc(doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::UnsubscribeDoc(doc_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Emit a `DocEvent::AccessChanged` notification whenever the access of
+    // any member to any document changes, whether because of local commands
+    // or sync. Access is compared after each batch of keyhive changes so
+    // rapid changes may be coalesced. The subscription is not persisted.
+    pub fn subscribe_access_changes() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SubscribeAccessChanges),
+        ));
+        (command_id, event)
+    }
+
+    pub fn unsubscribe_access_changes() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::UnsubscribeAccessChanges),
+        ));
+        (command_id, event)
+    }
+
+    /// Replace the policy for automatically bundling loose commits which was
+    /// passed in the `Config`
+    ///
+    /// The new policy applies the next time commits are added to a document.
+    pub fn set_bundling_policy(policy: crate::BundlingPolicy) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SetBundlingPolicy(policy)),
+        ));
+        (command_id, event)
+    }
+
+    /// Replace the document size limit which was passed in the `Config`
+    ///
+    /// Documents which are already over the new limit keep everything they
+    /// have but can't grow any further.
+    pub fn set_doc_size_limit(limit: crate::DocSizeLimit) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::SetDocSizeLimit(limit)),
+        ));
+        (command_id, event)
+    }
+
+    /// Compare our heads for `doc_id` with the heads the peer on the other
+    /// end of `stream_id` had as of the last time we synced it with them
+    pub fn stream_sync_status(stream_id: StreamId, doc_id: DocumentId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::StreamSyncStatus { stream_id, doc_id }),
+        ));
+        (command_id, event)
+    }
+
+    /// A token which a new stream to the same peer can use to skip the sync
+    /// handshake, `None` until the stream has completed a sync. Only the end
+    /// of a stream which drives sync, normally the connecting end, ever
+    /// completes one.
+    pub fn stream_resumption_token(stream_id: StreamId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::StreamResumptionToken(stream_id)),
+        ));
+        (command_id, event)
+    }
+
+    /// Ask when `Event::tick` next needs to be called
+    ///
+    /// The deadline accounts for membership expiries, sync session expiries,
+    /// outbound request timeouts and the periodic re-sync of idle streams. It should be queried again
+    /// after each tick as the work done by a tick will change it.
+    pub fn next_tick_deadline() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::NextTickDeadline),
+        ));
+        (command_id, event)
+    }
+
+    // List the streams which have not been disconnected along with their state
+    pub fn list_streams() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::ListStreams),
+        ));
+        (command_id, event)
+    }
+
+    // The bytes and messages sent and received over a stream and the number
+    // of requests we made on it which were answered. The counters only reset
+    // when the stream is disconnected.
+    pub fn stream_metrics(stream_id: StreamId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::StreamMetrics(stream_id)),
+        ));
+        (command_id, event)
+    }
+
+    // The metrics of every stream which hasn't been disconnected
+    pub fn all_stream_metrics() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::AllStreamMetrics),
+        ));
+        (command_id, event)
+    }
+
+    // Why the handshake on a stream failed. Streams whose handshake fails are
+    // closed, hosts can use this once they see the close to tell a peer
+    // running an incompatible protocol version apart from other failures.
+    pub fn stream_handshake_error(stream_id: StreamId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::StreamHandshakeError(stream_id)),
+        ));
+        (command_id, event)
+    }
+
+    // Forget the sync state negotiated on an established stream and run the
+    // handshake and a full sync with the peer again, without closing the
+    // stream. Completes once the new handshake completes. Whichever side
+    // calls this becomes the connecting side of the stream.
+    pub fn resync_stream(stream_id: StreamId) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::ResyncStream(stream_id)),
+        ));
+        (command_id, event)
+    }
+
+    // List the requests sent to endpoints which haven't been responded to or
+    // timed out yet, along with the endpoint each was sent to and how long it
+    // has been pending. This only reads the requests tracked in memory.
+    pub fn list_outbound_requests() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::ListOutboundRequests),
+        ));
+        (command_id, event)
+    }
+
+    #[cfg(feature = "debug_events")]
+    pub fn log_keyhive_events(
+        nicknames: keyhive_core::debug_events::Nicknames,
+    ) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::DebugEvents(
+                nicknames,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    pub fn create_contact_card() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::CreateContactCard)),
+        ));
+        (command_id, event)
+    }
+
+    /// Fetch the identity of this node without touching storage
+    pub fn local_identity() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::LocalIdentity)),
+        ));
+        (command_id, event)
+    }
+
+    /// List the individuals other than this node which keyhive knows about,
+    /// including contacts which have been revoked from everything they were
+    /// added to
+    pub fn list_contacts() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ListContacts)),
+        ));
+        (command_id, event)
+    }
+
+    /// Export the keyhive state of this node, encrypted with `passphrase`, so
+    /// that it can be moved to another device with [`Self::import_identity`]
+    ///
+    /// The export includes the secret prekeys used to decrypt documents shared
+    /// with us but not the signing key, which is held by the host. The host
+    /// must move the signing key itself and start the new node with it.
+    pub fn export_identity(passphrase: String) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ExportIdentity(
+                passphrase,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    /// Merge an identity exported with [`Self::export_identity`] into this
+    /// node
+    ///
+    /// This node must use the same signing key as the one which made the
+    /// export, otherwise this fails with `ImportIdentity::WrongIdentity`.
+    pub fn import_identity(exported: Vec<u8>, passphrase: String) -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Keyhive(keyhive::KeyhiveCommand::ImportIdentity(
+                exported, passphrase,
+            ))),
+        ));
+        (command_id, event)
+    }
+
+    pub fn tick() -> Event {
+        Event(EventInner::Tick)
+    }
+
+    /// Like `Event::tick` but completes with a
+    /// [`TickSummary`](crate::TickSummary) of the work the tick started
+    pub fn tick_verbose() -> (CommandId, Event) {
+        let command_id = CommandId::new();
+        let event = Event(EventInner::BeginCommand(
+            command_id,
+            Box::new(Command::Tick),
+        ));
+        (command_id, event)
+    }
+
+    /// Cancel a command which is still running
+    ///
+    /// The command will complete with [`CommandResult::Cancelled`](crate::CommandResult::Cancelled)
+    /// and any IO it was waiting on will be discarded when it completes. Cancelling a command
+    /// which has already completed (or which never existed) does nothing.
+    pub fn cancel_command(command_id: CommandId) -> Event {
+        Event(EventInner::CancelCommand(command_id))
+    }
+
+    /// Cancel every command which is still running
+    ///
+    /// This is the same as calling [`Self::cancel_command`] for each running command. Streams,
+    /// endpoints and sync are left alone, and commands which have already completed still
+    /// report their results.
+    pub fn cancel_all_commands() -> Event {
+        Event(EventInner::CancelAllCommands)
     }
 }
 
@@ -283,6 +1604,11 @@ impl Event {
 pub(super) enum EventInner {
     IoComplete(io::IoResult),
     BeginCommand(CommandId, Box<Command>),
+    BeginCorrelatedCommand(CommandId, Box<Command>, String),
+    AppendCommit(DocumentId, Commit),
     StreamMessage(StreamId, Vec<u8>),
+    StreamMessagesSent(StreamId, usize),
     Tick,
+    CancelCommand(CommandId),
+    CancelAllCommands,
 }
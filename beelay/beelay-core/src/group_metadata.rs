@@ -0,0 +1,230 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+};
+
+use crate::{
+    auth::Signed,
+    io::IoHandle,
+    keyhive::{error, GroupMetadata, MemberAccess},
+    parse::{self, Parse},
+    serialization::{leb128, Encode},
+    PeerId, StorageKey, TaskContext,
+};
+
+/// One version of the metadata of a group, signed by the peer which set it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub(crate) struct GroupMetadataRecord {
+    pub(crate) group_id: PeerId,
+    pub(crate) version: u64,
+    pub(crate) metadata: GroupMetadata,
+}
+
+fn metadata_prefix() -> StorageKey {
+    StorageKey::auth().push("group_metadata")
+}
+
+fn metadata_key(group_id: PeerId) -> StorageKey {
+    metadata_prefix().push(group_id.to_string())
+}
+
+// Last writer wins, with ties between concurrent edits at the same version
+// broken by the author so that every peer keeps the same record
+fn supersedes(
+    record: &Signed<GroupMetadataRecord>,
+    existing: &Signed<GroupMetadataRecord>,
+) -> bool {
+    (record.payload.version, record.verifier.as_bytes())
+        > (existing.payload.version, existing.verifier.as_bytes())
+}
+
+pub(crate) async fn load(io: IoHandle) -> HashMap<PeerId, Signed<GroupMetadataRecord>> {
+    let raw = crate::task_context::Storage::new(&io)
+        .load_range(metadata_prefix())
+        .await;
+    let mut records = HashMap::new();
+    for (key, value) in raw {
+        let Some(group_id) = key.name().and_then(|name| PeerId::from_str(name).ok()) else {
+            tracing::warn!(?key, "failed to parse group metadata key");
+            continue;
+        };
+        let Ok((_, record)) = Signed::<GroupMetadataRecord>::parse(parse::Input::new(&value))
+            .inspect_err(|e| tracing::error!(err=?e, "failed to parse stored group metadata"))
+        else {
+            continue;
+        };
+        if record.payload.group_id != group_id || record.verify().is_err() {
+            tracing::error!(?key, "stored group metadata is invalid");
+            continue;
+        }
+        records.insert(group_id, record);
+    }
+    records
+}
+
+/// Replace the metadata of `group_id` with `metadata`
+///
+/// Only members with write access to the group can set its metadata.
+pub(crate) async fn set_metadata<R>(
+    ctx: &TaskContext<R>,
+    group_id: PeerId,
+    metadata: GroupMetadata,
+) -> Result<(), error::SetGroupMetadata>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let keyhive = ctx.state().keyhive();
+    if !keyhive.has_group(group_id).await {
+        return Err(error::SetGroupMetadata::NoSuchGroup);
+    }
+    let our_access = keyhive
+        .group_member_access(group_id, ctx.state().our_peer_id())
+        .await;
+    if our_access < Some(MemberAccess::Write) {
+        return Err(error::SetGroupMetadata::NotAllowed);
+    }
+
+    // Always move past the version we know about so that our edit wins even
+    // if our clock is behind the previous editor's
+    let version = ctx
+        .state()
+        .group_metadata()
+        .get(group_id)
+        .map(|existing| existing.payload.version + 1)
+        .unwrap_or(0)
+        .max(ctx.now().as_secs().0);
+    let record = GroupMetadataRecord {
+        group_id,
+        version,
+        metadata,
+    };
+    let signed = Signed::try_sign(ctx.signer(), record)
+        .await
+        .map_err(|e| error::SetGroupMetadata::Signing(e.to_string()))?;
+    store(ctx, signed).await;
+    Ok(())
+}
+
+/// Apply metadata records received from another peer
+///
+/// Records which are badly signed, whose author doesn't have write access to
+/// the group, or which are older than the record we already have, are
+/// dropped.
+pub(crate) async fn ingest<R>(ctx: &TaskContext<R>, records: Vec<Signed<GroupMetadataRecord>>)
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    for record in records {
+        let group_id = record.payload.group_id;
+        if record.verify().is_err() {
+            tracing::warn!(%group_id, "dropping group metadata with an invalid signature");
+            continue;
+        }
+        let author_access = ctx
+            .state()
+            .keyhive()
+            .group_member_access(group_id, PeerId::from(record.verifier))
+            .await;
+        if author_access < Some(MemberAccess::Write) {
+            tracing::warn!(%group_id, "dropping group metadata from a peer without write access");
+            continue;
+        }
+        if let Some(existing) = ctx.state().group_metadata().get(group_id) {
+            if !supersedes(&record, &existing) {
+                continue;
+            }
+        }
+        store(ctx, record).await;
+    }
+}
+
+/// The metadata records of every group which `peer` is a member of
+pub(crate) async fn records_for_peer<R>(
+    ctx: &TaskContext<R>,
+    peer: PeerId,
+) -> Vec<Signed<GroupMetadataRecord>>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let mut records = Vec::new();
+    for record in ctx.state().group_metadata().all() {
+        if ctx
+            .state()
+            .keyhive()
+            .group_member_access(record.payload.group_id, peer)
+            .await
+            .is_some()
+        {
+            records.push(record);
+        }
+    }
+    records
+}
+
+async fn store<R>(ctx: &TaskContext<R>, record: Signed<GroupMetadataRecord>)
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let key = metadata_key(record.payload.group_id);
+    let encoded = record.encode();
+    ctx.state().group_metadata().insert(record);
+    ctx.storage().put(key, encoded).await;
+}
+
+impl Encode for GroupMetadata {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.name.encode_into(out);
+        leb128::encode_uleb128(out, self.entries.len() as u64);
+        for (key, value) in &self.entries {
+            key.encode_into(out);
+            value.encode_into(out);
+        }
+    }
+}
+
+impl<'a> Parse<'a> for GroupMetadata {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        input.parse_in_ctx("GroupMetadata", |input| {
+            let (mut input, name) = Option::<String>::parse(input)?;
+            let (i, num_entries) = leb128::parse(input)?;
+            input = i;
+            let mut entries = BTreeMap::new();
+            for _ in 0..num_entries {
+                let (i, key) = String::parse(input)?;
+                let (i, value) = String::parse(i)?;
+                input = i;
+                entries.insert(key, value);
+            }
+            Ok((input, GroupMetadata { name, entries }))
+        })
+    }
+}
+
+impl Encode for GroupMetadataRecord {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.group_id.as_bytes());
+        leb128::encode_uleb128(out, self.version);
+        self.metadata.encode_into(out);
+    }
+}
+
+impl<'a> Parse<'a> for GroupMetadataRecord {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        input.parse_in_ctx("GroupMetadataRecord", |input| {
+            let (input, group_id) = parse::arr::<32>(input)?;
+            let group_id = PeerId::try_from(group_id.as_slice())
+                .map_err(|e| input.error(format!("invalid group id: {}", e)))?;
+            let (input, version) = leb128::parse(input)?;
+            let (input, metadata) = GroupMetadata::parse(input)?;
+            Ok((
+                input,
+                GroupMetadataRecord {
+                    group_id,
+                    version,
+                    metadata,
+                },
+            ))
+        })
+    }
+}
@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+use crate::{DocumentId, StorageKey, TaskContext};
+
+// The key is supplied by the application so it is hashed rather than used as a
+// storage key component directly
+fn idempotency_key(key: &str) -> StorageKey {
+    StorageKey::auth()
+        .push("idempotency_keys")
+        .push(blake3::hash(key.as_bytes()).to_hex())
+}
+
+/// The document previously created with `key`, if it still exists
+pub(crate) async fn lookup<R>(ctx: &TaskContext<R>, key: &str) -> Option<DocumentId>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let stored = ctx.storage().load(idempotency_key(key)).await?;
+    let doc_id = std::str::from_utf8(&stored)
+        .ok()
+        .and_then(|s| DocumentId::from_str(s).ok());
+    let Some(doc_id) = doc_id else {
+        tracing::warn!("failed to parse stored idempotency key");
+        return None;
+    };
+    // The document may have been deleted since, in which case the key is free
+    // to be used again
+    if ctx.state().docs().sedimentree(&doc_id).is_some() {
+        Some(doc_id)
+    } else {
+        None
+    }
+}
+
+/// Remember that `key` created `doc_id`
+pub(crate) async fn record<R>(ctx: &TaskContext<R>, key: &str, doc_id: DocumentId)
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    ctx.storage()
+        .put(idempotency_key(key), doc_id.to_string().into_bytes())
+        .await;
+}
@@ -0,0 +1,110 @@
+use chacha20poly1305::{aead::Aead, KeyInit, XChaCha20Poly1305, XNonce};
+
+use crate::{keyhive::error, keyhive_storage, CommitHash, PeerId, TaskContext};
+
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = 1 + SALT_LEN;
+const KDF_ROUNDS: usize = 1 << 16;
+
+/// Export our keyhive state, encrypted with `passphrase`, for
+/// `import_identity` on another node
+///
+/// The blob holds our keyhive archive, which includes the secret prekeys
+/// needed to decrypt documents shared with us.
+pub(crate) async fn export<R>(
+    ctx: TaskContext<R>,
+    passphrase: &str,
+) -> Result<Vec<u8>, error::ExportIdentity>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let archive = ctx.state().keyhive().archive().await;
+    let plaintext =
+        bincode::serialize(&archive).map_err(|e| error::ExportIdentity(e.to_string()))?;
+
+    let salt = ctx.state().random_bytes::<SALT_LEN>();
+    let nonce = ctx.state().random_bytes::<NONCE_LEN>();
+    let mut out = Vec::with_capacity(HEADER_LEN + NONCE_LEN + plaintext.len());
+    out.push(VERSION);
+    out.extend_from_slice(&salt);
+    let ciphertext = cipher(passphrase, &salt)
+        .encrypt(
+            XNonce::from_slice(&nonce),
+            chacha20poly1305::aead::Payload {
+                msg: &plaintext,
+                aad: &out,
+            },
+        )
+        .expect("encrypting into a vec never fails");
+    out.extend_from_slice(&nonce);
+    out.extend(ciphertext);
+    Ok(out)
+}
+
+/// Merge keyhive state exported with `export` into ours
+///
+/// The export must have been made by a node with the same signing key as
+/// this one.
+pub(crate) async fn import<R>(
+    ctx: TaskContext<R>,
+    blob: &[u8],
+    passphrase: &str,
+) -> Result<(), error::ImportIdentity>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    if blob.len() < HEADER_LEN + NONCE_LEN || blob[0] != VERSION {
+        return Err(error::ImportIdentity::Malformed);
+    }
+    let (header, rest) = blob.split_at(HEADER_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let salt: [u8; SALT_LEN] = header[1..].try_into().unwrap();
+    let plaintext = cipher(passphrase, &salt)
+        .decrypt(
+            XNonce::from_slice(nonce),
+            chacha20poly1305::aead::Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map_err(|_| error::ImportIdentity::WrongPassphrase)?;
+
+    let archive: keyhive_core::archive::Archive<CommitHash> =
+        bincode::deserialize(&plaintext).map_err(|_| error::ImportIdentity::Malformed)?;
+    let exported_by = PeerId::try_from(&archive.id().to_bytes()[..])
+        .map_err(|_| error::ImportIdentity::Malformed)?;
+    if exported_by != ctx.state().our_peer_id() {
+        return Err(error::ImportIdentity::WrongIdentity);
+    }
+
+    ctx.state()
+        .keyhive()
+        .ingest_archive(archive)
+        .await
+        .map_err(error::ImportIdentity::Ingest)?;
+    // Ingesting secret prekeys doesn't emit a keyhive event, so the merged
+    // state is stored here rather than waiting for the next one
+    let merged = ctx.state().keyhive().archive().await;
+    keyhive_storage::store_archive(ctx.clone(), merged).await;
+    Ok(())
+}
+
+fn cipher(passphrase: &str, salt: &[u8; SALT_LEN]) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new((&passphrase_key(passphrase, salt)).into())
+}
+
+// We don't have a password hashing function to hand, so the passphrase is
+// stretched by iterating a keyed hash to make each guess cost some work.
+// Hosts wanting more protection can derive the passphrase itself with
+// something like Argon2.
+fn passphrase_key(passphrase: &str, salt: &[u8; SALT_LEN]) -> [u8; 32] {
+    let mut input = salt.to_vec();
+    input.extend_from_slice(passphrase.as_bytes());
+    let mut key = blake3::derive_key("beelay identity export v1", &input);
+    for _ in 0..KDF_ROUNDS {
+        key = *blake3::keyed_hash(&key, &input).as_bytes();
+    }
+    key
+}
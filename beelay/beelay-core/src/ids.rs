@@ -0,0 +1,69 @@
+//! Counters for the ids beelay hands out
+//!
+//! Ids are numbered from process wide counters. With the
+//! `insecure_deterministic` feature each thread numbers ids with its own
+//! counters instead, which [`reset_ids`] restarts from zero, so a test which
+//! replays the same events and IO results on a fresh thread sees the same
+//! `CommandId`s, `StreamId`s and IO task ids every time. Ids from different
+//! threads then collide, so the feature must never be enabled outside of
+//! tests.
+//!
+//! The feature doesn't touch hash map iteration order, which the standard
+//! library randomizes without drawing from the seeded RNG, see [`reset_ids`].
+
+use std::sync::atomic::AtomicU64;
+
+pub(crate) fn next(counter: &'static AtomicU64) -> u64 {
+    #[cfg(feature = "insecure_deterministic")]
+    {
+        deterministic::next(counter)
+    }
+    #[cfg(not(feature = "insecure_deterministic"))]
+    {
+        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Restart every id counter on this thread from zero
+///
+/// Only available with the `insecure_deterministic` feature. With the feature
+/// each thread numbers `CommandId`s, `StreamId`s and IO task ids with its own
+/// counters, so replaying the same events and IO results with the same seeded
+/// RNG, from a fresh thread or after calling this, gives the same ids, keys,
+/// document ids and command results.
+///
+/// It doesn't give byte for byte identical IO. Hash map iteration order is
+/// randomized by the standard library rather than drawn from the seeded RNG,
+/// so the order in which IO tasks for several keys are emitted, and the order
+/// of entries in the keyhive archives written to storage, can differ between
+/// runs. Tests should compare results and stored state, not the sequence of
+/// IO tasks or the bytes of keyhive archives.
+///
+/// Ids handed out on different threads collide, which is why this is for
+/// tests only and the feature must never be enabled in production.
+#[cfg(feature = "insecure_deterministic")]
+pub fn reset_ids() {
+    deterministic::COUNTERS.with(|counters| counters.borrow_mut().clear());
+}
+
+#[cfg(feature = "insecure_deterministic")]
+mod deterministic {
+    use std::{cell::RefCell, collections::HashMap, sync::atomic::AtomicU64};
+
+    thread_local! {
+        // Keyed by the address of the process wide counter each one replaces
+        pub(super) static COUNTERS: RefCell<HashMap<usize, u64>> = RefCell::new(HashMap::new());
+    }
+
+    pub(super) fn next(counter: &'static AtomicU64) -> u64 {
+        COUNTERS.with(|counters| {
+            let mut counters = counters.borrow_mut();
+            let next = counters
+                .entry(counter as *const AtomicU64 as usize)
+                .or_default();
+            let id = *next;
+            *next += 1;
+            id
+        })
+    }
+}
@@ -1,36 +1,66 @@
 use std::{
+    cell::{Ref, RefCell, RefMut},
     collections::HashMap,
-    sync::atomic::{AtomicU64, Ordering},
+    rc::Rc,
+    sync::atomic::AtomicU64,
 };
 
 use futures::channel::{mpsc, oneshot};
 
 use crate::{
-    doc_status::DocEvent, driver, serialization::hex, task_context::JobFuture, DocumentId,
-    StorageKey,
+    doc_status::DocEvent,
+    driver,
+    serialization::hex,
+    task_context::{EphemeralStorage, JobFuture},
+    CommandId, CommandProgress, DocumentId, StorageKey,
 };
 
 #[derive(Clone)]
-pub(crate) struct IoHandle(mpsc::UnboundedSender<driver::DriverOutput>);
+pub(crate) struct IoHandle {
+    tx: mpsc::UnboundedSender<driver::DriverOutput>,
+    ephemeral: Rc<RefCell<EphemeralStorage>>,
+}
 
 impl IoHandle {
     pub(crate) fn new(tx: mpsc::UnboundedSender<driver::DriverOutput>) -> Self {
-        Self(tx)
+        Self {
+            tx,
+            ephemeral: Rc::new(RefCell::new(EphemeralStorage::default())),
+        }
+    }
+
+    pub(crate) fn ephemeral(&self) -> Ref<'_, EphemeralStorage> {
+        self.ephemeral.borrow()
+    }
+
+    pub(crate) fn ephemeral_mut(&self) -> RefMut<'_, EphemeralStorage> {
+        self.ephemeral.borrow_mut()
     }
 
     /// Request a new storage task be executed, the result to be sent to `reply`
     pub(crate) fn new_task(&self, task: IoTask, reply: oneshot::Sender<IoResult>) {
         let _ = self
-            .0
+            .tx
             .unbounded_send(driver::DriverOutput::Task { task, reply });
     }
 
     /// Emit a new document event
     pub(crate) fn new_doc_event(&self, doc_id: DocumentId, event: DocEvent) {
         let _ = self
-            .0
+            .tx
             .unbounded_send(driver::DriverOutput::DocEvent { doc_id, event });
     }
+
+    /// Emit a progress update for a running command
+    pub(crate) fn command_progress(&self, command_id: CommandId, processed: usize, total: usize) {
+        let _ = self
+            .tx
+            .unbounded_send(driver::DriverOutput::CommandProgress(CommandProgress {
+                command_id,
+                processed,
+                total,
+            }));
+    }
 }
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
@@ -40,7 +70,7 @@ static LAST_IO_TASK_ID: AtomicU64 = AtomicU64::new(0);
 
 impl IoTaskId {
     pub(crate) fn new() -> IoTaskId {
-        IoTaskId(LAST_IO_TASK_ID.fetch_add(1, Ordering::Relaxed))
+        IoTaskId(crate::ids::next(&LAST_IO_TASK_ID))
     }
 
     pub fn serialize(&self) -> String {
@@ -109,6 +139,10 @@ impl IoTask {
         &self.action
     }
 
+    pub(crate) fn action_mut(&mut self) -> &mut IoAction {
+        &mut self.action
+    }
+
     pub fn take_action(self) -> IoAction {
         self.action
     }
@@ -128,6 +162,50 @@ pub enum IoAction {
     Sign { payload: Vec<u8> },
 }
 
+/// A description of an IO task which is about to be handed to the
+/// application, see [`Config::io_observer`](crate::config::Config::io_observer)
+#[cfg(feature = "io_observer")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoObservation {
+    pub id: IoTaskId,
+    pub kind: IoKind,
+    /// The key or key prefix the task operates on, `None` for signing
+    pub key: Option<StorageKey>,
+    /// The number of bytes to be written or signed, zero for other tasks
+    pub size: usize,
+}
+
+#[cfg(feature = "io_observer")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoKind {
+    Load,
+    LoadRange,
+    ListOneLevel,
+    Put,
+    Delete,
+    Sign,
+}
+
+#[cfg(feature = "io_observer")]
+impl IoObservation {
+    pub(crate) fn of(task: &IoTask) -> Self {
+        let (kind, key, size) = match &task.action {
+            IoAction::Load { key } => (IoKind::Load, Some(key.clone()), 0),
+            IoAction::LoadRange { prefix } => (IoKind::LoadRange, Some(prefix.clone()), 0),
+            IoAction::ListOneLevel { prefix } => (IoKind::ListOneLevel, Some(prefix.clone()), 0),
+            IoAction::Put { key, data } => (IoKind::Put, Some(key.clone()), data.len()),
+            IoAction::Delete { key } => (IoKind::Delete, Some(key.clone()), 0),
+            IoAction::Sign { payload } => (IoKind::Sign, None, payload.len()),
+        };
+        IoObservation {
+            id: task.id,
+            kind,
+            key,
+            size,
+        }
+    }
+}
+
 pub struct IoResult {
     id: IoTaskId,
     payload: IoResultPayload,
@@ -199,6 +277,10 @@ impl IoResult {
         }
     }
 
+    pub(crate) fn from_payload(id: IoTaskId, payload: IoResultPayload) -> IoResult {
+        IoResult { id, payload }
+    }
+
     pub(crate) fn take_payload(self) -> IoResultPayload {
         self.payload
     }
@@ -260,3 +342,29 @@ impl keyhive_core::crypto::signer::async_signer::AsyncSigner for Signer {
         }
     }
 }
+
+#[cfg(all(test, feature = "io_observer"))]
+mod tests {
+    use super::{IoKind, IoObservation, IoTask};
+    use crate::StorageKey;
+
+    #[test]
+    fn observation_describes_task() {
+        let key = StorageKey::auth().push("a");
+        let put = IoTask::put(key.clone(), vec![1, 2, 3]);
+        let observed = IoObservation::of(&put);
+        assert_eq!(observed.id, put.id());
+        assert_eq!(observed.kind, IoKind::Put);
+        assert_eq!(observed.key, Some(key.clone()));
+        assert_eq!(observed.size, 3);
+
+        let load = IoObservation::of(&IoTask::load(key.clone()));
+        assert_eq!(
+            (load.kind, load.key, load.size),
+            (IoKind::Load, Some(key), 0)
+        );
+
+        let sign = IoObservation::of(&IoTask::sign(vec![0; 10]));
+        assert_eq!((sign.kind, sign.key, sign.size), (IoKind::Sign, None, 10));
+    }
+}
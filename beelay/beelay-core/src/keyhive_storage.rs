@@ -6,7 +6,7 @@ use keyhive_core::{
 use crate::{
     parse::{self, Parse},
     serialization::{hex, Encode},
-    CommitHash, Signer, StorageKey, TaskContext,
+    CommitHash, Signer, StorageKey, TaskContext, UnixTimestamp,
 };
 
 // Abstract over storage so we can use this when loading as well as when saving
@@ -67,15 +67,44 @@ impl Storage for crate::task_context::Storage<'_> {
 pub(crate) async fn store_event<S: Storage>(
     ctx: S,
     event: Event<Signer, CommitHash, crate::keyhive::Listener>,
+    now: UnixTimestamp,
 ) {
     let event = StaticEvent::from(event);
-    let digest = Digest::hash(&event).raw;
-    let storage_key = StorageKey::auth()
-        .push("events")
-        .push(hex::encode(digest.as_bytes()));
+    let digest = hex::encode(Digest::hash(&event).raw.as_bytes());
+    // Keyhive events don't carry a timestamp so we record when we first saw
+    // each one separately, this is only used for auditing
+    let time_key = StorageKey::auth().push("event_times").push(digest.clone());
+    ctx.put(time_key, now.encode()).await;
+    let storage_key = StorageKey::auth().push("events").push(digest);
     ctx.put(storage_key, event.encode()).await;
 }
 
+/// The time at which each stored event was first seen, keyed by the digest
+/// of the event
+pub(crate) async fn load_event_times<S: Storage>(
+    ctx: S,
+) -> std::collections::HashMap<Digest<StaticEvent<CommitHash>>, UnixTimestamp> {
+    let storage_key = StorageKey::auth().push("event_times");
+    let raw = ctx.load_range(storage_key).await;
+    let mut times = std::collections::HashMap::new();
+    for (key, value) in raw {
+        let Some(digest) = key
+            .name()
+            .and_then(|name| hex::decode(name).ok())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        else {
+            tracing::warn!(%key, "invalid keyhive event time key");
+            continue;
+        };
+        let Ok((_, time)) = UnixTimestamp::parse(parse::Input::new(&value)) else {
+            tracing::warn!(%key, "failed to parse stored keyhive event time");
+            continue;
+        };
+        times.insert(Digest::from(digest), time);
+    }
+    times
+}
+
 pub(crate) async fn load_events<S: Storage>(ctx: S) -> Vec<StaticEvent<CommitHash>> {
     let storage_key = StorageKey::auth().push("events");
     let raw = ctx.load_range(storage_key).await;
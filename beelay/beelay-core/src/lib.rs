@@ -42,10 +42,16 @@ use network::{
 use serialization::parse;
 use tracing::Instrument;
 
+mod access_requests;
 mod blob;
+mod bundle_progress;
+mod commit_meta;
+pub use commit_meta::{CommitAuthor, CommitMeta};
 mod config;
 pub mod conn_info;
-pub use config::Config;
+#[cfg(feature = "io_observer")]
+pub use config::IoObserver;
+pub use config::{BundlingPolicy, Config, DocSizeLimit};
 pub mod contact_card;
 mod driver;
 pub use blob::BlobHash;
@@ -56,8 +62,15 @@ mod request_handlers;
 pub use error::{InvalidPeerId, Stopped};
 pub mod io;
 pub use io::IoTaskId;
+mod capabilities;
+pub use capabilities::{Capability, CapabilityId};
 mod commands;
-pub use commands::{keyhive, CommandId, CommandResult};
+pub use commands::{
+    keyhive, AddedBundle, AddedCommits, BundleProblem, CommandId, CommandProgress, CommandResult,
+    CommitsAdded, Compaction, DedupStats, DocDiff, DocGraph, DocItem, DocProblem, DocStats,
+    ExportHandle, GraphBundle, GraphCommit, HandledRequest, PendingOrphan, RequestTarget,
+    RequestedCommits, SyncEstimate, TickSummary, TransferEstimate,
+};
 pub mod auth;
 pub mod doc_status;
 pub(crate) mod riblt;
@@ -75,21 +88,37 @@ pub use documents::{
     BundleBuilder, BundleSpec, Commit, CommitBundle, CommitHash, CommitOrBundle, DocumentHeads,
     DocumentId,
 };
+mod ephemeral_docs;
 mod event;
+mod group_metadata;
+mod idempotency_keys;
+mod ids;
+#[cfg(feature = "insecure_deterministic")]
+pub use ids::reset_ids;
+mod identity_export;
 mod keyhive_storage;
 pub mod loading;
+mod membership_expiry;
 pub use event::Event;
 use event::EventInner;
 mod network;
 use network::streams;
 pub use network::{
-    signed_message::SignedMessage, EndpointId, EndpointResponse, OutboundRequestId,
-    StreamDirection, StreamError, StreamEvent, StreamId,
+    signed_message::{InspectedRequest, SignedMessage},
+    EndpointId, EndpointResponse, HandshakeError, OutboundRequest, OutboundRequestId, StreamCodec,
+    StreamDirection, StreamError, StreamEvent, StreamId, StreamMetrics, StreamQueueDepth,
+    StreamRateLimit, StreamResumptionToken, StreamState, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
+mod seals;
 mod serialization;
 mod stopper;
+mod storage_encryption;
+pub use storage_encryption::StorageEncryptionKey;
 mod sync;
+mod tombstones;
 pub use keyhive_core::contact_card::ContactCard;
+pub use seals::Seal;
+pub use tombstones::Tombstone;
 
 pub const SYNC_INTERVAL: Duration = Duration::from_secs(10);
 
@@ -130,19 +159,45 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> Beelay<R> {
         let (tx_load_complete, rx_load_complete) = oneshot::channel();
         let local_peer_id = PeerId::from(config.verifying_key);
         let run_span = tracing::info_span!("run", %local_peer_id);
-        let driver = driver::Driver::start(config.rng, now, |spawn_args| {
-            driver::run(driver::DriveBeelayArgs {
-                rng: spawn_args.rng,
-                now: spawn_args.now,
-                tx_driver_events: spawn_args.tx_output,
-                rx_input: spawn_args.rx_input,
+        let storage_encrypted = config.storage_encryption_key.is_some();
+        let storage_namespaced = config.storage_namespace.is_some();
+        if let Some(namespace) = &config.storage_namespace {
+            if StorageKey::namespace_root(namespace).is_none() {
+                return loading::Step::Failed(error::Load::InvalidStorageNamespace);
+            }
+        }
+        #[cfg(feature = "io_observer")]
+        let io_observer = config.io_observer;
+        #[allow(unused_mut)]
+        let mut driver = driver::Driver::start(
+            config.rng,
+            now,
+            config.stream_congestion_threshold,
+            config.stream_rate_limit,
+            config.storage_encryption_key,
+            config.storage_namespace,
+            |spawn_args| {
+                driver::run(driver::DriveBeelayArgs {
+                    rng: spawn_args.rng,
+                    now: spawn_args.now,
+                    tx_driver_events: spawn_args.tx_output,
+                    rx_input: spawn_args.rx_input,
 
-                verifying_key: config.verifying_key,
-                load_complete: tx_load_complete,
-                session_duration: config.session_duration,
-            })
-            .instrument(run_span)
-        });
+                    verifying_key: config.verifying_key,
+                    load_complete: tx_load_complete,
+                    storage_encrypted,
+                    storage_namespaced,
+                    session_duration: config.session_duration,
+                    bundling_policy: config.bundling_policy,
+                    doc_size_limit: config.doc_size_limit,
+                    request_timeout: config.request_timeout,
+                    stream_reorder_window: config.stream_reorder_window,
+                })
+                .instrument(run_span)
+            },
+        );
+        #[cfg(feature = "io_observer")]
+        driver.set_io_observer(io_observer);
         loading::Loading::loading(now, driver, rx_load_complete)
     }
 
@@ -169,6 +224,24 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> Beelay<R> {
         self.state().sessions().num_sessions()
     }
 
+    /// The outbound messages for a stream which the transport has not yet
+    /// reported as sent
+    ///
+    /// Returns `None` unless a congestion threshold was set with
+    /// [`Config::stream_congestion_threshold`](config::Config::stream_congestion_threshold).
+    pub fn stream_queue_depth(&self, stream_id: StreamId) -> Option<StreamQueueDepth> {
+        self.driver.stream_queue_depth(stream_id)
+    }
+
+    /// The commits passed to `Event::append_commit` for `doc_id` which have
+    /// not been stored yet
+    ///
+    /// This grows when commits are appended faster than they can be written,
+    /// so callers producing commits in a loop can use it to slow down.
+    pub fn pending_appends(&self, doc_id: DocumentId) -> usize {
+        self.driver.pending_appends(doc_id)
+    }
+
     pub fn connection_info(&self) -> HashMap<StreamId, conn_info::ConnectionInfo> {
         self.state()
             .streams()
@@ -180,6 +253,8 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> Beelay<R> {
                     conn_info::ConnectionInfo {
                         peer_id: established.their_peer_id,
                         state: established.sync_phase.into(),
+                        codec: established.codec,
+                        protocol_version: established.protocol_version,
                     },
                 )
             })
@@ -199,14 +274,34 @@ impl<R: rand::Rng + rand::CryptoRng> Beelay<R> {
                 self.driver.handle_io_complete(io_result);
             }
             EventInner::StreamMessage(stream_id, msg) => {
-                self.driver.handle_stream_message(stream_id, msg);
+                let awaiting_responses =
+                    state::StateAccessor::new(&self.state).awaiting_responses(stream_id);
+                self.driver
+                    .handle_stream_message(now, stream_id, msg, awaiting_responses);
             }
             EventInner::BeginCommand(command_id, command) => {
                 self.driver.dispatch_command(command_id, *command);
             }
+            EventInner::BeginCorrelatedCommand(command_id, command, correlation_id) => {
+                self.driver.correlate(command_id, correlation_id);
+                self.driver.dispatch_command(command_id, *command);
+            }
+            EventInner::AppendCommit(doc_id, commit) => {
+                self.driver.append_commit(doc_id, commit);
+            }
+            EventInner::StreamMessagesSent(stream_id, num_messages) => {
+                self.driver
+                    .handle_stream_messages_sent(stream_id, num_messages);
+            }
             EventInner::Tick => {
                 self.driver.tick();
             }
+            EventInner::CancelCommand(command_id) => {
+                self.driver.cancel_command(command_id);
+            }
+            EventInner::CancelAllCommands => {
+                self.driver.cancel_all_commands();
+            }
         };
 
         Ok(self.driver.step(now))
@@ -220,14 +315,35 @@ pub struct EventResults {
     pub new_tasks: Vec<io::IoTask>,
     /// Commands which have completed
     pub completed_commands: HashMap<CommandId, Result<CommandResult, error::Stopping>>,
+    /// The correlation IDs of the commands in `completed_commands` which were
+    /// given one with [`Event::with_correlation_id`]
+    pub correlation_ids: HashMap<CommandId, String>,
     /// New notifications
     pub notifications: HashMap<DocumentId, Vec<doc_status::DocEvent>>,
     /// Notifications about peer status
     pub peer_status_changes: HashMap<PeerId, conn_info::ConnectionInfo>,
+    /// Progress updates for running commands, in the order they were emitted
+    pub command_progress: Vec<CommandProgress>,
     /// New requests to send
     pub new_requests: HashMap<EndpointId, Vec<NewRequest>>,
     /// New events for streams
     pub new_stream_events: HashMap<streams::StreamId, Vec<streams::StreamEvent>>,
+    /// Streams whose outbound backlog grew past the configured congestion
+    /// threshold. A stream is reported again only after its backlog has
+    /// dropped back under the threshold.
+    pub congested_streams: HashMap<streams::StreamId, streams::StreamQueueDepth>,
+    /// Streams which have started dropping inbound messages because they
+    /// exceeded their rate limit, see
+    /// [`Config::stream_rate_limit`](config::Config::stream_rate_limit)
+    pub rate_limited_streams: Vec<streams::StreamId>,
+    /// Commits passed to `Event::append_commit` which could not be added
+    pub append_failures: Vec<AppendFailure>,
+    /// Bundles which should now be created for documents which commits were
+    /// appended to, as for `add_commits`
+    pub appended_bundles: HashMap<DocumentId, Vec<BundleSpec>>,
+    /// Requests which were given up on because no response arrived within
+    /// [`Config::request_timeout`](config::Config::request_timeout)
+    pub timed_out_requests: Vec<OutboundRequestId>,
     /// Whether the Beelay has stopped
     pub stopped: bool,
 }
@@ -238,12 +354,33 @@ pub struct NewRequest {
     pub request: EndpointRequest,
 }
 
+/// A batch of commits passed to `Event::append_commit` which could not be
+/// added, none of the commits in the batch were stored
+#[derive(Debug)]
+pub struct AppendFailure {
+    pub doc_id: DocumentId,
+    pub commits: Vec<CommitHash>,
+    pub error: error::AddCommits,
+}
+
 pub mod error {
-    pub use crate::commands::error::{AddCommits, Create};
-    pub use crate::documents::error::{InvalidCommitHash, InvalidDocumentId};
+    pub use crate::capabilities::DecodeCapability;
+    pub use crate::commands::error::{
+        AddBundle, AddCommitChunk, AddCommits, BeginExport, CapabilityRejected, CompactDoc, Create,
+        CreateDocFromBundle, CreateDocWithGrants, DeleteNamespace, DenyDocAccess, DiffDocs,
+        DiscoverDocs, EstimateSync, ExportBundle, FetchWithCapability, HandleRequest,
+        HandleResponse, LoadCommitsFrom, LoadDocAt, MergeDocs, MintCapability, NextExportChunk,
+        RegisterEndpointMulti, RequestCommits, RequestDocAccess, RequestFailure, ResyncStream,
+        SealDoc, SyncWithEndpoint, TombstoneDoc, VerifyDoc, WarmDoc,
+    };
+    pub use crate::documents::error::{DecodeExportedBundle, InvalidCommitHash, InvalidDocumentId};
     pub use crate::keyhive::error::{
-        AddMember, CreateContactCard, CreateGroup, QueryAccess, RemoveMember,
+        AddMember, CancelScheduledRotation, ChangeMemberAccess, CreateContactCard, CreateGroup,
+        CreateGroupWithMembers, DocKeyEpochs, ExpandGroup, ExportAccessLog, ExportIdentity,
+        ImportIdentity, InvalidDelegation, ListScheduledRotations, PreviewAccessChange,
+        QueryAccess, QueryAccessFor, RemoveMember, RotateDocKey, SetGroupMetadata,
     };
+    pub use crate::network::streams::resumption::error::DecodeResumptionToken;
     use crate::network::RpcError;
     pub use crate::peer_id::error::InvalidPeerId;
 
@@ -251,6 +388,19 @@ pub mod error {
     #[error("beelay is stopped")]
     pub struct Stopped;
 
+    #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+    pub enum Load {
+        /// Something read from storage couldn't be decrypted with the
+        /// configured storage encryption key. Either the key is wrong or the
+        /// storage was written with a different key or none at all.
+        #[error("storage could not be decrypted with the storage encryption key")]
+        WrongStorageKey,
+        /// The namespace passed to `Config::storage_namespace` is empty,
+        /// contains a `/` or is reserved for beelay's own use
+        #[error("invalid storage namespace")]
+        InvalidStorageNamespace,
+    }
+
     #[derive(Debug, thiserror::Error)]
     #[error("error decoding: {0}")]
     pub struct DecodeResponse(pub(super) String);
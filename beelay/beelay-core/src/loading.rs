@@ -15,7 +15,7 @@ use crate::{io::IoTask, task_context::DocStorage, Beelay, StorageKey, UnixTimest
 
 pub struct Loading<R: rand::Rng + rand::CryptoRng + Clone + 'static> {
     driver: driver::Driver,
-    result: oneshot::Receiver<LoadedParts<R>>,
+    result: oneshot::Receiver<Result<LoadedParts<R>, crate::error::Load>>,
 }
 
 pub(crate) struct LoadedParts<R: rand::Rng + rand::CryptoRng> {
@@ -26,13 +26,15 @@ pub(crate) struct LoadedParts<R: rand::Rng + rand::CryptoRng> {
 pub enum Step<R: rand::Rng + rand::CryptoRng + Clone + 'static> {
     Loading(Loading<R>, Vec<IoTask>),
     Loaded(Beelay<R>, Vec<IoTask>),
+    /// Loading was abandoned, any outstanding IO tasks can be dropped
+    Failed(crate::error::Load),
 }
 
 impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> Loading<R> {
     pub(crate) fn loading(
         now: UnixTimestampMillis,
         driver: driver::Driver,
-        rx_loaded: oneshot::Receiver<LoadedParts<R>>,
+        rx_loaded: oneshot::Receiver<Result<LoadedParts<R>, crate::error::Load>>,
     ) -> Step<R> {
         let loading = Self {
             result: rx_loaded,
@@ -43,10 +45,15 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> Loading<R> {
 
     fn step(mut self, now: UnixTimestampMillis) -> Step<R> {
         let new_events = self.driver.step(now);
-        if let Ok(Some(parts)) = self.result.try_recv() {
-            Step::Loaded(Beelay::loaded(parts, self.driver), new_events.new_tasks)
-        } else {
-            Step::Loading(self, new_events.new_tasks)
+        if self.driver.undecryptable_storage() {
+            return Step::Failed(crate::error::Load::WrongStorageKey);
+        }
+        match self.result.try_recv() {
+            Ok(Some(Ok(parts))) => {
+                Step::Loaded(Beelay::loaded(parts, self.driver), new_events.new_tasks)
+            }
+            Ok(Some(Err(e))) => Step::Failed(e),
+            _ => Step::Loading(self, new_events.new_tasks),
         }
     }
 
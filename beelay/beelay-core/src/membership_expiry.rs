@@ -0,0 +1,196 @@
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use crate::{
+    io::IoHandle,
+    keyhive::{error, KeyhiveEntityId, RotationId, RotationReason, ScheduledRotation},
+    parse::{self, Parse},
+    serialization::Encode,
+    DocumentId, PeerId, StorageKey, TaskContext, UnixTimestamp,
+};
+
+fn expiries_prefix() -> StorageKey {
+    StorageKey::auth().push("membership_expiries")
+}
+
+fn expiry_key(target: PeerId, member: PeerId) -> StorageKey {
+    expiries_prefix()
+        .push(target.to_string())
+        .push(member.to_string())
+}
+
+pub(crate) async fn load(io: IoHandle) -> HashMap<(PeerId, PeerId), UnixTimestamp> {
+    let raw = crate::task_context::Storage::new(&io)
+        .load_range(expiries_prefix())
+        .await;
+    let mut expiries = HashMap::new();
+    for (key, value) in raw {
+        let [_, target, member] = key.remaining() else {
+            tracing::warn!(?key, "unexpected membership expiry key");
+            continue;
+        };
+        let (Ok(target), Ok(member)) = (PeerId::from_str(target), PeerId::from_str(member)) else {
+            tracing::warn!(?key, "failed to parse membership expiry key");
+            continue;
+        };
+        let Ok((_, expires_at)) = UnixTimestamp::parse(parse::Input::new(&value))
+            .inspect_err(|e| tracing::error!(err=?e, "failed to parse stored membership expiry"))
+        else {
+            continue;
+        };
+        expiries.insert((target, member), expires_at);
+    }
+    expiries
+}
+
+/// Record when the membership of `member` in `target` expires
+///
+/// If `expires_at` is `None` then any existing expiry is cleared, making the
+/// membership permanent.
+pub(crate) async fn set_expiry<R>(
+    ctx: &TaskContext<R>,
+    target: PeerId,
+    member: PeerId,
+    expires_at: Option<UnixTimestamp>,
+) where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let key = expiry_key(target, member);
+    match expires_at {
+        Some(expires_at) => {
+            ctx.state()
+                .membership_expiries()
+                .insert(target, member, expires_at);
+            ctx.storage().put(key, expires_at.encode()).await;
+        }
+        None => {
+            if ctx.state().membership_expiries().remove(target, member) {
+                ctx.storage().delete(key).await;
+            }
+        }
+    }
+}
+
+/// Revoke every membership whose expiry has passed
+///
+/// An expiry whose revocation fails is kept, in memory and in storage, so it
+/// is retried the next time expiries are pruned.
+#[tracing::instrument(skip(ctx))]
+pub(crate) async fn prune_expired<R>(ctx: TaskContext<R>)
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let expired = ctx
+        .state()
+        .membership_expiries()
+        .take_expired(ctx.now().as_secs());
+    for (target, member, expires_at) in expired {
+        tracing::debug!(%target, %member, "revoking expired membership");
+        if let Err(e) = ctx.state().keyhive().revoke_member(target, member).await {
+            tracing::error!(err=?e, %target, %member, "failed to revoke expired membership");
+            ctx.state()
+                .membership_expiries()
+                .insert(target, member, expires_at);
+            continue;
+        }
+        ctx.storage().delete(expiry_key(target, member)).await;
+    }
+}
+
+// The memberships whose revocation rotates the key of `doc_id`: those of the
+// document itself and of every group which is a member of it, directly or
+// through other groups. The members `list_doc_members` returns are already
+// transitive, so groups nested in groups are included. Each is mapped to the
+// group it is in, `None` for the document.
+async fn rotation_targets<R>(
+    ctx: &TaskContext<R>,
+    doc_id: DocumentId,
+) -> Option<HashMap<PeerId, Option<PeerId>>>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let members = ctx.state().keyhive().list_doc_members(doc_id).await?;
+    let mut targets = HashMap::from([(PeerId::from(*doc_id.as_key()), None)]);
+    for member in members {
+        if let KeyhiveEntityId::Group(group_id) = member.member {
+            targets.insert(group_id, Some(group_id));
+        }
+    }
+    Some(targets)
+}
+
+/// The key rotations of `doc_id` which will happen when a membership expires,
+/// soonest first
+pub(crate) async fn scheduled_rotations<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Result<Vec<ScheduledRotation>, error::ListScheduledRotations>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    prune_expired(ctx.clone()).await;
+    let targets = rotation_targets(&ctx, doc_id)
+        .await
+        .ok_or(error::ListScheduledRotations::NoSuchDocument)?;
+    let target_ids = targets.keys().copied().collect::<HashSet<_>>();
+    let mut rotations = ctx
+        .state()
+        .membership_expiries()
+        .for_targets(&target_ids)
+        .into_iter()
+        .map(|(target, member, at)| ScheduledRotation {
+            id: RotationId { target, member, at },
+            at,
+            reason: RotationReason::MembershipExpires {
+                member,
+                via_group: targets[&target],
+            },
+        })
+        .collect::<Vec<_>>();
+    rotations.sort_by_key(|rotation| {
+        (
+            rotation.at,
+            rotation.id.target.to_string(),
+            rotation.id.member.to_string(),
+        )
+    });
+    Ok(rotations)
+}
+
+/// Cancel the rotation `id` of `doc_id` by clearing the expiry which causes
+/// it, so the membership no longer expires
+pub(crate) async fn cancel_scheduled_rotation<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+    id: RotationId,
+) -> Result<(), error::CancelScheduledRotation>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    prune_expired(ctx.clone()).await;
+    let targets = rotation_targets(&ctx, doc_id)
+        .await
+        .ok_or(error::CancelScheduledRotation::NoSuchDocument)?;
+    if !targets.contains_key(&id.target) {
+        return Err(error::CancelScheduledRotation::NoSuchRotation);
+    }
+    if !ctx
+        .state()
+        .keyhive()
+        .can_admin(ctx.state().our_peer_id(), &doc_id)
+        .await
+    {
+        return Err(error::CancelScheduledRotation::NotAllowed);
+    }
+    match ctx.state().membership_expiries().get(id.target, id.member) {
+        Some(expires_at) if expires_at == id.at => {
+            set_expiry(&ctx, id.target, id.member, None).await;
+            Ok(())
+        }
+        // Expiries which have passed were applied by `prune_expired` above
+        _ if id.at <= ctx.now().as_secs() => Err(error::CancelScheduledRotation::AlreadyApplied),
+        _ => Err(error::CancelScheduledRotation::NoSuchRotation),
+    }
+}
@@ -1,5 +1,5 @@
 pub(crate) mod endpoint;
-pub use endpoint::{EndpointId, EndpointRequest, EndpointResponse};
+pub use endpoint::{EndpointId, EndpointRequest, EndpointResponse, OutboundRequest};
 pub(crate) mod messages;
 mod outbound_request_id;
 pub use outbound_request_id::OutboundRequestId;
@@ -7,6 +7,10 @@ mod peer_address;
 pub use peer_address::PeerAddress;
 pub(crate) mod signed_message;
 pub(crate) mod streams;
-pub use streams::{StreamDirection, StreamError, StreamEvent, StreamId};
+pub use streams::{
+    HandshakeError, StreamCodec, StreamDirection, StreamError, StreamEvent, StreamId,
+    StreamMetrics, StreamQueueDepth, StreamRateLimit, StreamResumptionToken, StreamState,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
 mod rpc_error;
 pub(crate) use rpc_error::RpcError;
@@ -1,12 +1,14 @@
 use std::{
-    collections::HashMap,
-    sync::atomic::{AtomicU64, Ordering},
+    collections::{HashMap, HashSet, VecDeque},
+    sync::atomic::AtomicU64,
+    time::Duration,
 };
 
 use crate::{
     auth::{self, offset_seconds::OffsetSeconds},
+    commands::error::HandleResponse,
     serialization::Encode,
-    Audience, PeerId, Request, Response, UnixTimestamp,
+    Audience, CommitHash, PeerId, Request, Response, UnixTimestamp, UnixTimestampMillis,
 };
 
 mod endpoint_request;
@@ -14,41 +16,167 @@ pub use endpoint_request::EndpointRequest;
 mod endpoint_response;
 pub use endpoint_response::EndpointResponse;
 use futures::channel::{mpsc, oneshot};
+use keyhive_core::event::static_event::StaticEvent;
+use nonempty::NonEmpty;
 
 use super::OutboundRequestId;
 
+// How many completed requests to remember so that late responses to them can
+// be told apart from responses to requests we never made
+const MAX_COMPLETED_REQUESTS: usize = 1024;
+
 pub(crate) struct Endpoints {
     endpoints: HashMap<EndpointId, Endpoint>,
     // The other end of this channel is polled by the driver and used to send outgoing
     // endpoint requests
     outbox: mpsc::UnboundedSender<(EndpointId, OutboundRequestId, auth::Message)>,
-    pending_requests: HashMap<OutboundRequestId, oneshot::Sender<Option<(PeerId, Response)>>>,
+    pending_requests: HashMap<OutboundRequestId, PendingRequest>,
+    completed_requests: VecDeque<(OutboundRequestId, Completion)>,
+    // How long to wait for a response before giving up on a request, if at all
+    request_timeout: Option<Duration>,
+}
+
+struct PendingRequest {
+    reply: oneshot::Sender<Option<(PeerId, Response)>>,
+    endpoint_id: EndpointId,
+    audience: Audience,
+    sent_at: UnixTimestampMillis,
+    deadline: Option<UnixTimestampMillis>,
+}
+
+/// A request which has been sent to an endpoint and is awaiting a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboundRequest {
+    pub id: OutboundRequestId,
+    pub endpoint_id: EndpointId,
+    /// The audience the request was addressed to
+    pub audience: Audience,
+    /// How long ago the request was sent
+    pub pending_for: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Completion {
+    Responded,
+    TimedOut,
 }
 
 impl Endpoints {
     pub(crate) fn new(
         outbox: mpsc::UnboundedSender<(EndpointId, OutboundRequestId, auth::Message)>,
+        request_timeout: Option<Duration>,
     ) -> Self {
         Self {
             endpoints: HashMap::new(),
             pending_requests: HashMap::new(),
+            completed_requests: VecDeque::new(),
+            request_timeout,
             outbox,
         }
     }
 
-    pub(crate) fn register_endpoint(&mut self, audience: Audience) -> EndpointId {
+    /// Register an endpoint which answers to each of `audiences`
+    ///
+    /// Requests sent to the endpoint are addressed to the first audience.
+    pub(crate) fn register_endpoint(&mut self, audiences: NonEmpty<Audience>) -> EndpointId {
         let id = EndpointId::new();
-        self.endpoints.insert(id, Endpoint { audience });
+        self.endpoints.insert(
+            id,
+            Endpoint {
+                audiences,
+                delegation: None,
+                capability: None,
+            },
+        );
         id
     }
 
-    pub(crate) fn unregister_endpoint(&mut self, endpoint_id: EndpointId) {
-        self.endpoints.remove(&endpoint_id);
+    /// Register an endpoint which answers to `audience` and whose requests
+    /// are made on behalf of the group in `delegation`
+    pub(crate) fn register_delegated_endpoint(
+        &mut self,
+        audience: Audience,
+        delegation: Delegation,
+    ) -> EndpointId {
+        let id = EndpointId::new();
+        self.endpoints.insert(
+            id,
+            Endpoint {
+                audiences: NonEmpty::new(audience),
+                delegation: Some(delegation),
+                capability: None,
+            },
+        );
+        id
+    }
+
+    /// Register an endpoint which answers to `audience` and whose requests
+    /// are authorized by `capability`
+    pub(crate) fn register_capability_endpoint(
+        &mut self,
+        audience: Audience,
+        capability: crate::Capability,
+    ) -> EndpointId {
+        let id = EndpointId::new();
+        self.endpoints.insert(
+            id,
+            Endpoint {
+                audiences: NonEmpty::new(audience),
+                delegation: None,
+                capability: Some(capability),
+            },
+        );
+        id
+    }
+
+    pub(crate) fn capability(&self, endpoint_id: EndpointId) -> Option<crate::Capability> {
+        self.endpoints
+            .get(&endpoint_id)
+            .and_then(|endpoint| endpoint.capability.clone())
+    }
+
+    pub(crate) fn peer(&self, endpoint_id: EndpointId) -> Option<PeerId> {
+        self.endpoints
+            .get(&endpoint_id)?
+            .audiences
+            .iter()
+            .find_map(|audience| match audience {
+                Audience::VerifyingKey(key) => PeerId::try_from(key.as_slice()).ok(),
+                Audience::ServiceName(_) => None,
+            })
+    }
+
+    /// The audiences which a request received for `audience` may be addressed
+    /// to
+    ///
+    /// This is `audience` itself along with every other audience of each
+    /// endpoint which `audience` belongs to.
+    pub(crate) fn audiences_matching(&self, audience: Audience) -> HashSet<Audience> {
+        let mut matching = HashSet::from([audience]);
+        for endpoint in self.endpoints.values() {
+            if endpoint.audiences.contains(&audience) {
+                matching.extend(endpoint.audiences.iter().copied());
+            }
+        }
+        matching
+    }
+
+    /// The endpoint which serves `audience`, if any
+    pub(crate) fn endpoint_for_audience(&self, audience: Audience) -> Option<EndpointId> {
+        self.endpoints
+            .iter()
+            .find(|(_, endpoint)| endpoint.audiences.contains(&audience))
+            .map(|(id, _)| *id)
+    }
+
+    // Returns true if the endpoint was registered
+    pub(crate) fn unregister_endpoint(&mut self, endpoint_id: EndpointId) -> bool {
+        self.endpoints.remove(&endpoint_id).is_some()
     }
 
     pub(crate) fn send_request(
         &mut self,
-        now: UnixTimestamp,
+        now: UnixTimestampMillis,
         endpoint_id: EndpointId,
         request_id: OutboundRequestId,
         request: Request,
@@ -57,32 +185,125 @@ impl Endpoints {
         let Some(endpoint) = self.endpoints.get(&endpoint_id) else {
             return Err(NoSuchEndpoint);
         };
-        let msg = auth::send(now, OffsetSeconds(0), endpoint.audience, request.encode());
-        self.pending_requests.insert(request_id, reply);
+        let request = match &endpoint.delegation {
+            Some(delegation) => Request::Delegated {
+                on_behalf_of: delegation.on_behalf_of,
+                chain: delegation.chain.clone(),
+                request: Box::new(request),
+            },
+            None => request,
+        };
+        let request = match &endpoint.capability {
+            Some(capability) => Request::WithCapability {
+                capability: Box::new(capability.0.clone()),
+                request: Box::new(request),
+            },
+            None => request,
+        };
+        let audience = *endpoint.audiences.first();
+        let msg = auth::send(now.into(), OffsetSeconds(0), audience, request.encode());
+        self.pending_requests.insert(
+            request_id,
+            PendingRequest {
+                reply,
+                endpoint_id,
+                audience,
+                sent_at: now,
+                deadline: self.request_timeout.map(|timeout| now + timeout),
+            },
+        );
         let _ = self.outbox.unbounded_send((endpoint_id, request_id, msg));
         Ok(())
     }
 
+    /// Give up on every request whose timeout has passed, returning their ids
+    ///
+    /// The tasks waiting on these requests see them as having received no
+    /// response and any response which arrives later is rejected with
+    /// `HandleResponse::RequestTimedOut`.
+    pub(crate) fn expire_requests(&mut self, now: UnixTimestampMillis) -> Vec<OutboundRequestId> {
+        let expired = self
+            .pending_requests
+            .iter()
+            .filter(|(_, pending)| pending.deadline.is_some_and(|d| d <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for req_id in &expired {
+            tracing::debug!(?req_id, "request timed out");
+            let pending = self
+                .pending_requests
+                .remove(req_id)
+                .expect("we just found this");
+            let _ = pending.reply.send(None);
+            self.complete(*req_id, Completion::TimedOut);
+        }
+        expired
+    }
+
+    pub(crate) fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+
+    pub(crate) fn next_request_deadline(&self) -> Option<UnixTimestampMillis> {
+        self.pending_requests
+            .values()
+            .filter_map(|pending| pending.deadline)
+            .min()
+    }
+
+    /// The requests which are still awaiting a response, oldest first
+    pub(crate) fn outbound_requests(&self, now: UnixTimestampMillis) -> Vec<OutboundRequest> {
+        let mut requests = self
+            .pending_requests
+            .iter()
+            .map(|(id, pending)| OutboundRequest {
+                id: *id,
+                endpoint_id: pending.endpoint_id,
+                audience: pending.audience,
+                pending_for: if now > pending.sent_at {
+                    now - pending.sent_at
+                } else {
+                    Duration::ZERO
+                },
+            })
+            .collect::<Vec<_>>();
+        requests.sort_by_key(|r| (std::cmp::Reverse(r.pending_for), r.id));
+        requests
+    }
+
+    fn complete(&mut self, req_id: OutboundRequestId, completion: Completion) {
+        if self.completed_requests.len() == MAX_COMPLETED_REQUESTS {
+            self.completed_requests.pop_front();
+        }
+        self.completed_requests.push_back((req_id, completion));
+    }
+
     pub(crate) fn handle_response(
         &mut self,
         now: UnixTimestamp,
         our_peer_id: &PeerId,
         req_id: OutboundRequestId,
         response: auth::Signed<auth::Message>,
-    ) {
+    ) -> Result<(), HandleResponse> {
+        let Some(PendingRequest { reply, .. }) = self.pending_requests.remove(&req_id) else {
+            return match self.completed_requests.iter().find(|(id, _)| *id == req_id) {
+                Some((_, Completion::Responded)) => Err(HandleResponse::AlreadyCompleted),
+                Some((_, Completion::TimedOut)) => Err(HandleResponse::RequestTimedOut),
+                None => Err(HandleResponse::NoSuchRequest),
+            };
+        };
+        self.complete(req_id, Completion::Responded);
+
         match auth::receive::<Response>(now, response, our_peer_id, None) {
             Ok(authenticated) => {
-                if let Some(reply) = self.pending_requests.remove(&req_id) {
-                    let _ = reply.send(Some((authenticated.from.into(), authenticated.content)));
-                }
+                let _ = reply.send(Some((authenticated.from.into(), authenticated.content)));
             }
             Err(e) => {
                 tracing::warn!(err=?e, "failed to authenticate response");
-                if let Some(reply) = self.pending_requests.remove(&req_id) {
-                    let _ = reply.send(None);
-                }
+                let _ = reply.send(None);
             }
         }
+        Ok(())
     }
 }
 
@@ -93,7 +314,7 @@ static LAST_ENDPOINT_ID: AtomicU64 = AtomicU64::new(0);
 
 impl EndpointId {
     fn new() -> Self {
-        Self(LAST_ENDPOINT_ID.fetch_add(1, Ordering::Relaxed))
+        Self(crate::ids::next(&LAST_ENDPOINT_ID))
     }
 
     pub fn serialize(&self) -> u64 {
@@ -107,9 +328,280 @@ impl EndpointId {
 
 pub struct Endpoint {
     // id: EndpointId,
-    audience: Audience,
+    audiences: NonEmpty<Audience>,
+    delegation: Option<Delegation>,
+    capability: Option<crate::Capability>,
+}
+
+/// The group an endpoint makes requests on behalf of, along with the
+/// membership history which proves that we may do so
+pub(crate) struct Delegation {
+    pub(crate) on_behalf_of: PeerId,
+    pub(crate) chain: Vec<StaticEvent<CommitHash>>,
 }
 
 #[derive(Debug, thiserror::Error)]
 #[error("no such endpoint")]
 pub struct NoSuchEndpoint;
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::{collections::HashSet, time::Duration};
+
+    use futures::channel::{mpsc, oneshot};
+    use nonempty::nonempty;
+
+    use super::{Delegation, Endpoints, OutboundRequest};
+    use crate::{
+        auth::{self, message::Message, offset_seconds::OffsetSeconds, signed::Signed},
+        capabilities::CapabilityRecord,
+        commands::error::HandleResponse,
+        keyhive::MemberAccess,
+        network::OutboundRequestId,
+        serialization::Encode,
+        Audience, DocumentId, PeerId, Request, Response, UnixTimestamp, UnixTimestampMillis,
+    };
+
+    #[test]
+    fn unmatched_responses_are_reported() {
+        let remote = SigningKey::from_bytes(&[1; 32]);
+        let us = PeerId::from(SigningKey::from_bytes(&[2; 32]).verifying_key());
+        let now = UnixTimestamp::now();
+        let (outbox, _outbox_rx) = mpsc::unbounded();
+        let mut endpoints = Endpoints::new(outbox, None);
+        let endpoint_id =
+            endpoints.register_endpoint(nonempty![Audience::peer(&remote.verifying_key().into())]);
+
+        let response = || {
+            let payload = auth::send(
+                now,
+                OffsetSeconds(0),
+                Audience::peer(&us),
+                Response::Pong.encode(),
+            );
+            Signed::<Message> {
+                signature: remote.sign(&payload.encode()),
+                verifier: remote.verifying_key(),
+                payload,
+            }
+        };
+
+        let request_id = OutboundRequestId::new();
+        let (reply, mut reply_rx) = oneshot::channel();
+        endpoints
+            .send_request(now.into(), endpoint_id, request_id, Request::Ping, reply)
+            .unwrap();
+        assert_eq!(
+            endpoints.handle_response(now, &us, request_id, response()),
+            Ok(())
+        );
+        assert!(matches!(
+            reply_rx.try_recv(),
+            Ok(Some(Some((_, Response::Pong))))
+        ));
+
+        assert_eq!(
+            endpoints.handle_response(now, &us, request_id, response()),
+            Err(HandleResponse::AlreadyCompleted)
+        );
+        assert_eq!(
+            endpoints.handle_response(now, &us, OutboundRequestId::new(), response()),
+            Err(HandleResponse::NoSuchRequest)
+        );
+    }
+
+    #[test]
+    fn requests_time_out() {
+        let remote = SigningKey::from_bytes(&[1; 32]);
+        let us = PeerId::from(SigningKey::from_bytes(&[2; 32]).verifying_key());
+        let sent_at = UnixTimestampMillis::now();
+        let (outbox, _outbox_rx) = mpsc::unbounded();
+        let mut endpoints = Endpoints::new(outbox, Some(Duration::from_secs(10)));
+        let endpoint_id =
+            endpoints.register_endpoint(nonempty![Audience::peer(&remote.verifying_key().into())]);
+
+        let request_id = OutboundRequestId::new();
+        let (reply, mut reply_rx) = oneshot::channel();
+        endpoints
+            .send_request(sent_at, endpoint_id, request_id, Request::Ping, reply)
+            .unwrap();
+        assert_eq!(
+            endpoints.next_request_deadline(),
+            Some(sent_at + Duration::from_secs(10))
+        );
+
+        assert!(endpoints
+            .expire_requests(sent_at + Duration::from_secs(9))
+            .is_empty());
+        assert_eq!(
+            endpoints.expire_requests(sent_at + Duration::from_secs(10)),
+            vec![request_id]
+        );
+        assert!(matches!(reply_rx.try_recv(), Ok(Some(None))));
+        assert_eq!(endpoints.next_request_deadline(), None);
+
+        let now = UnixTimestamp::from(sent_at);
+        let payload = auth::send(
+            now,
+            OffsetSeconds(0),
+            Audience::peer(&us),
+            Response::Pong.encode(),
+        );
+        let response = Signed::<Message> {
+            signature: remote.sign(&payload.encode()),
+            verifier: remote.verifying_key(),
+            payload,
+        };
+        assert_eq!(
+            endpoints.handle_response(now, &us, request_id, response),
+            Err(HandleResponse::RequestTimedOut)
+        );
+    }
+
+    #[test]
+    fn outbound_requests_are_listed_until_they_complete() {
+        let remote = SigningKey::from_bytes(&[1; 32]);
+        let remote_audience = Audience::peer(&remote.verifying_key().into());
+        let sent_at = UnixTimestampMillis::now();
+        let (outbox, _outbox_rx) = mpsc::unbounded();
+        let mut endpoints = Endpoints::new(outbox, Some(Duration::from_secs(10)));
+        let endpoint_id = endpoints.register_endpoint(nonempty![remote_audience]);
+
+        let first = OutboundRequestId::new();
+        let second = OutboundRequestId::new();
+        let (reply, _first_rx) = oneshot::channel();
+        endpoints
+            .send_request(sent_at, endpoint_id, first, Request::Ping, reply)
+            .unwrap();
+        let (reply, _second_rx) = oneshot::channel();
+        endpoints
+            .send_request(
+                sent_at + Duration::from_secs(5),
+                endpoint_id,
+                second,
+                Request::Ping,
+                reply,
+            )
+            .unwrap();
+
+        let now = sent_at + Duration::from_secs(7);
+        assert_eq!(
+            endpoints.outbound_requests(now),
+            vec![
+                OutboundRequest {
+                    id: first,
+                    endpoint_id,
+                    audience: remote_audience,
+                    pending_for: Duration::from_secs(7),
+                },
+                OutboundRequest {
+                    id: second,
+                    endpoint_id,
+                    audience: remote_audience,
+                    pending_for: Duration::from_secs(2),
+                },
+            ]
+        );
+
+        endpoints.expire_requests(sent_at + Duration::from_secs(10));
+        assert_eq!(
+            endpoints
+                .outbound_requests(now)
+                .into_iter()
+                .map(|r| r.id)
+                .collect::<Vec<_>>(),
+            vec![second]
+        );
+    }
+
+    #[test]
+    fn delegated_endpoints_wrap_requests() {
+        let (outbox, mut outbox_rx) = mpsc::unbounded();
+        let mut endpoints = Endpoints::new(outbox, None);
+        let group = PeerId::from(SigningKey::from_bytes(&[3; 32]).verifying_key());
+        let endpoint_id = endpoints.register_delegated_endpoint(
+            Audience::service_name("a"),
+            Delegation {
+                on_behalf_of: group,
+                chain: Vec::new(),
+            },
+        );
+
+        let (reply, _reply_rx) = oneshot::channel();
+        endpoints
+            .send_request(
+                UnixTimestampMillis::now(),
+                endpoint_id,
+                OutboundRequestId::new(),
+                Request::Ping,
+                reply,
+            )
+            .unwrap();
+        let (_, _, msg) = outbox_rx.try_recv().unwrap();
+        let expected = Request::Delegated {
+            on_behalf_of: group,
+            chain: Vec::new(),
+            request: Box::new(Request::Ping),
+        };
+        assert_eq!(msg.content, expected.encode());
+    }
+
+    #[test]
+    fn capability_endpoints_wrap_requests() {
+        let (outbox, mut outbox_rx) = mpsc::unbounded();
+        let mut endpoints = Endpoints::new(outbox, None);
+        let capability = Signed {
+            payload: CapabilityRecord {
+                id: "000102030405060708090a0b0c0d0e0f".parse().unwrap(),
+                doc_id: DocumentId::from(SigningKey::from_bytes(&[4; 32]).verifying_key()),
+                access: MemberAccess::Read,
+                expires_at: UnixTimestamp::now(),
+            },
+            verifier: SigningKey::from_bytes(&[3; 32]).verifying_key(),
+            signature: ed25519_dalek::Signature::from_bytes(&[0; 64]),
+        };
+        let endpoint_id = endpoints.register_capability_endpoint(
+            Audience::service_name("a"),
+            crate::Capability(capability.clone()),
+        );
+        assert_eq!(
+            endpoints.capability(endpoint_id),
+            Some(crate::Capability(capability.clone()))
+        );
+
+        let (reply, _reply_rx) = oneshot::channel();
+        endpoints
+            .send_request(
+                UnixTimestampMillis::now(),
+                endpoint_id,
+                OutboundRequestId::new(),
+                Request::Ping,
+                reply,
+            )
+            .unwrap();
+        let (_, _, msg) = outbox_rx.try_recv().unwrap();
+        let expected = Request::WithCapability {
+            capability: Box::new(capability),
+            request: Box::new(Request::Ping),
+        };
+        assert_eq!(msg.content, expected.encode());
+    }
+
+    #[test]
+    fn audiences_of_multi_audience_endpoints_match_each_other() {
+        let (outbox, _outbox_rx) = mpsc::unbounded();
+        let mut endpoints = Endpoints::new(outbox, None);
+        let [a, b, c, other] = ["a", "b", "c", "other"].map(Audience::service_name);
+        let endpoint_id = endpoints.register_endpoint(nonempty![a, b, c]);
+
+        assert_eq!(endpoints.audiences_matching(b), HashSet::from([a, b, c]));
+        assert_eq!(endpoints.audiences_matching(other), HashSet::from([other]));
+        assert_eq!(endpoints.endpoint_for_audience(c), Some(endpoint_id));
+        assert_eq!(endpoints.endpoint_for_audience(other), None);
+
+        assert!(endpoints.unregister_endpoint(endpoint_id));
+        assert_eq!(endpoints.audiences_matching(b), HashSet::from([b]));
+        assert_eq!(endpoints.endpoint_for_audience(c), None);
+    }
+}
@@ -3,9 +3,10 @@ use keyhive_core::{
 };
 
 use crate::{
+    keyhive::MemberAccess,
     sedimentree::{self, SedimentreeSummary},
     serialization::{parse, Encode, Parse},
-    CommitHash, DocumentId,
+    CommitHash, DocumentId, PeerId,
 };
 
 mod decode;
@@ -30,6 +31,9 @@ pub(crate) enum Response {
     SyncNeeded,
     UploadMembershipOps,
     UploadCgkaOps,
+    AskForAccess,
+    AccessDecision,
+    DiscoverDocs(Vec<DocumentId>),
 }
 
 impl Parse<'_> for Response {
@@ -67,6 +71,9 @@ impl std::fmt::Display for Response {
             Response::AuthorizationFailed => write!(f, "AuthorizationFailed"),
             Response::UploadMembershipOps => write!(f, "UploadMembershipOps"),
             Response::UploadCgkaOps => write!(f, "UploadCgkaOps"),
+            Response::AskForAccess => write!(f, "AskForAccess"),
+            Response::AccessDecision => write!(f, "AccessDecision"),
+            Response::DiscoverDocs(docs) => write!(f, "DiscoverDocs({} docs)", docs.len()),
         }
     }
 }
@@ -77,6 +84,8 @@ impl std::fmt::Display for Response {
 pub(crate) enum FetchedSedimentree {
     NotFound,
     Found(SedimentreeSummary),
+    /// The remote has paused syncing the document
+    Paused,
 }
 
 impl Parse<'_> for FetchedSedimentree {
@@ -90,6 +99,7 @@ impl Parse<'_> for FetchedSedimentree {
                         input.parse_in_ctx("content", SedimentreeSummary::parse)?;
                     Ok((input, FetchedSedimentree::Found(content_bundles)))
                 }),
+                2 => Ok((input, FetchedSedimentree::Paused)),
                 _ => Err(input.error("unknown tag")),
             }
         })
@@ -106,6 +116,9 @@ impl Encode for FetchedSedimentree {
                 out.push(1);
                 content.encode_into(out);
             }
+            FetchedSedimentree::Paused => {
+                out.push(2);
+            }
         }
     }
 }
@@ -133,6 +146,56 @@ pub(crate) enum Request {
         ops:
             Vec<keyhive_core::crypto::signed::Signed<keyhive_core::cgka::operation::CgkaOperation>>,
     },
+    /// `request` made by the sender on behalf of the group `on_behalf_of`
+    Delegated {
+        on_behalf_of: PeerId,
+        /// The membership history of `on_behalf_of`, which proves that the
+        /// sender is a member of it
+        chain: Vec<StaticEvent<CommitHash>>,
+        request: Box<Request>,
+    },
+    /// Ask an admin of `doc_id` to give the sender `desired` access. The
+    /// sender's encoded contact card is included so the admin can add them.
+    AskForAccess {
+        doc_id: DocumentId,
+        desired: MemberAccess,
+        contact_card: Vec<u8>,
+    },
+    /// An admin's answer to an `AskForAccess`, `None` if it was denied
+    AccessDecision {
+        doc_id: DocumentId,
+        granted: Option<MemberAccess>,
+    },
+    /// Ask for the documents the receiver has which the sender can pull
+    DiscoverDocs,
+    /// `request` authorized by a capability minted by the receiver rather
+    /// than by the sender's membership of the document
+    WithCapability {
+        capability: Box<crate::auth::Signed<crate::capabilities::CapabilityRecord>>,
+        request: Box<Request>,
+    },
+}
+
+impl Request {
+    /// The document this request is about, if any
+    pub(crate) fn doc_id(&self) -> Option<DocumentId> {
+        match self {
+            Request::UploadCommits { doc, .. } => Some(*doc),
+            Request::FetchSedimentree(doc_id) => Some(*doc_id),
+            Request::FetchBlob { doc_id, .. } => Some(*doc_id),
+            Request::Delegated { request, .. } => request.doc_id(),
+            Request::WithCapability { request, .. } => request.doc_id(),
+            Request::AskForAccess { doc_id, .. } => Some(*doc_id),
+            Request::AccessDecision { doc_id, .. } => Some(*doc_id),
+            Request::UploadBlob(_)
+            | Request::Ping
+            | Request::Session(_)
+            | Request::SyncNeeded
+            | Request::DiscoverDocs
+            | Request::UploadMembershipOps { .. }
+            | Request::UploadCgkaOps { .. } => None,
+        }
+    }
 }
 
 impl Parse<'_> for Request {
@@ -171,6 +234,22 @@ impl std::fmt::Display for Request {
             Request::UploadCgkaOps { ops } => {
                 write!(f, "UploadCgkaOps({} ops)", ops.len())
             }
+            Request::Delegated {
+                on_behalf_of,
+                request,
+                ..
+            } => write!(f, "Delegated({}, {})", on_behalf_of, request),
+            Request::AskForAccess {
+                doc_id, desired, ..
+            } => write!(f, "AskForAccess({}, {})", doc_id, desired),
+            Request::AccessDecision { doc_id, granted } => {
+                write!(f, "AccessDecision({}, {:?})", doc_id, granted)
+            }
+            Request::DiscoverDocs => write!(f, "DiscoverDocs"),
+            Request::WithCapability {
+                capability,
+                request,
+            } => write!(f, "WithCapability({}, {})", capability.payload.id, request),
         }
     }
 }
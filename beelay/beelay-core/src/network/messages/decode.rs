@@ -3,8 +3,10 @@ use keyhive_core::{
 };
 
 use crate::{
+    capabilities::CapabilityRecord,
+    keyhive::MemberAccess,
     serialization::{parse, Parse},
-    BlobHash, CommitHash, DocumentId,
+    BlobHash, CommitHash, DocumentId, PeerId,
 };
 
 use super::{
@@ -49,6 +51,52 @@ pub(super) fn parse_request(
             let (input, ops) = Vec::<Signed<CgkaOperation>>::parse(input)?;
             Ok((input, super::Request::UploadCgkaOps { ops }))
         }),
+        RequestType::Delegated => input.parse_in_ctx("Delegated", |input| {
+            let (input, on_behalf_of) = input.parse_in_ctx("on_behalf_of", parse::arr::<32>)?;
+            let on_behalf_of = PeerId::try_from(on_behalf_of.as_slice())
+                .map_err(|_| input.error("invalid peer id"))?;
+            let (input, chain) = Vec::<StaticEvent<CommitHash>>::parse_in_ctx("chain", input)?;
+            let (input, request) = input.parse_in_ctx("request", parse_request)?;
+            Ok((
+                input,
+                super::Request::Delegated {
+                    on_behalf_of,
+                    chain,
+                    request: Box::new(request),
+                },
+            ))
+        }),
+        RequestType::AskForAccess => input.parse_in_ctx("AskForAccess", |input| {
+            let (input, doc_id) = DocumentId::parse_in_ctx("doc_id", input)?;
+            let (input, desired) = MemberAccess::parse_in_ctx("desired", input)?;
+            let (input, contact_card) = input.parse_in_ctx("contact_card", parse::slice)?;
+            Ok((
+                input,
+                super::Request::AskForAccess {
+                    doc_id,
+                    desired,
+                    contact_card: contact_card.to_vec(),
+                },
+            ))
+        }),
+        RequestType::AccessDecision => input.parse_in_ctx("AccessDecision", |input| {
+            let (input, doc_id) = DocumentId::parse_in_ctx("doc_id", input)?;
+            let (input, granted) = Option::<MemberAccess>::parse_in_ctx("granted", input)?;
+            Ok((input, super::Request::AccessDecision { doc_id, granted }))
+        }),
+        RequestType::DiscoverDocs => Ok((input, super::Request::DiscoverDocs)),
+        RequestType::WithCapability => input.parse_in_ctx("WithCapability", |input| {
+            let (input, capability) =
+                crate::auth::Signed::<CapabilityRecord>::parse_in_ctx("capability", input)?;
+            let (input, request) = input.parse_in_ctx("request", parse_request)?;
+            Ok((
+                input,
+                super::Request::WithCapability {
+                    capability: Box::new(capability),
+                    request: Box::new(request),
+                },
+            ))
+        }),
     }
 }
 
@@ -87,6 +135,12 @@ pub(crate) fn parse_response(
         ResponseType::UploadMembershipOps => Ok((input, super::Response::UploadMembershipOps)),
         ResponseType::UploadCgkaOps => Ok((input, super::Response::UploadCgkaOps)),
         ResponseType::UploadBlob => Ok((input, super::Response::UploadBlob)),
+        ResponseType::AskForAccess => Ok((input, super::Response::AskForAccess)),
+        ResponseType::AccessDecision => Ok((input, super::Response::AccessDecision)),
+        ResponseType::DiscoverDocs => input.parse_in_ctx("DiscoverDocs", |input| {
+            let (input, docs) = Vec::<DocumentId>::parse(input)?;
+            Ok((input, super::Response::DiscoverDocs(docs)))
+        }),
     }?;
     Ok((input, resp))
 }
@@ -50,6 +50,41 @@ pub(super) fn encode_request(buf: &mut Vec<u8>, req: &Request) {
             buf.push(RequestType::UploadCgkaOps.into());
             ops.encode_into(buf);
         }
+        Request::Delegated {
+            on_behalf_of,
+            chain,
+            request,
+        } => {
+            buf.push(RequestType::Delegated.into());
+            buf.extend_from_slice(on_behalf_of.as_bytes());
+            chain.encode_into(buf);
+            encode_request(buf, request);
+        }
+        Request::AskForAccess {
+            doc_id,
+            desired,
+            contact_card,
+        } => {
+            buf.push(RequestType::AskForAccess.into());
+            doc_id.encode_into(buf);
+            desired.encode_into(buf);
+            encode_uleb128(buf, contact_card.len() as u64);
+            buf.extend_from_slice(contact_card);
+        }
+        Request::AccessDecision { doc_id, granted } => {
+            buf.push(RequestType::AccessDecision.into());
+            doc_id.encode_into(buf);
+            granted.encode_into(buf);
+        }
+        Request::DiscoverDocs => buf.push(RequestType::DiscoverDocs.into()),
+        Request::WithCapability {
+            capability,
+            request,
+        } => {
+            buf.push(RequestType::WithCapability.into());
+            capability.encode_into(buf);
+            encode_request(buf, request);
+        }
     }
 }
 
@@ -98,5 +133,11 @@ pub(crate) fn encode_response(buf: &mut Vec<u8>, resp: &Response) {
         Response::UploadCgkaOps => {
             buf.push(ResponseType::UploadCgkaOps.into());
         }
+        Response::AskForAccess => buf.push(ResponseType::AskForAccess.into()),
+        Response::AccessDecision => buf.push(ResponseType::AccessDecision.into()),
+        Response::DiscoverDocs(docs) => {
+            buf.push(ResponseType::DiscoverDocs.into());
+            docs.encode_into(buf);
+        }
     }
 }
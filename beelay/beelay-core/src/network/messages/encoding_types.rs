@@ -12,6 +12,11 @@ pub(super) enum RequestType {
     UploadCgkaOps,
     Session,
     SyncNeeded,
+    Delegated,
+    AskForAccess,
+    AccessDecision,
+    DiscoverDocs,
+    WithCapability,
 }
 
 impl RequestType {
@@ -41,6 +46,11 @@ impl TryFrom<u8> for RequestType {
             21 => Ok(Self::UploadCgkaOps),
             24 => Ok(Self::Session),
             25 => Ok(Self::SyncNeeded),
+            26 => Ok(Self::Delegated),
+            27 => Ok(Self::AskForAccess),
+            28 => Ok(Self::AccessDecision),
+            29 => Ok(Self::DiscoverDocs),
+            30 => Ok(Self::WithCapability),
 
             _ => Err(error::InvalidRequestType(value)),
         }
@@ -59,6 +69,11 @@ impl From<RequestType> for u8 {
             RequestType::UploadCgkaOps => 21,
             RequestType::Session => 24,
             RequestType::SyncNeeded => 25,
+            RequestType::Delegated => 26,
+            RequestType::AskForAccess => 27,
+            RequestType::AccessDecision => 28,
+            RequestType::DiscoverDocs => 29,
+            RequestType::WithCapability => 30,
         }
     }
 }
@@ -78,6 +93,9 @@ pub(super) enum ResponseType {
     UploadMembershipOps,
     UploadCgkaOps,
     UploadBlob,
+    AskForAccess,
+    AccessDecision,
+    DiscoverDocs,
 }
 
 impl ResponseType {
@@ -110,6 +128,9 @@ impl TryFrom<u8> for ResponseType {
             25 => Ok(Self::UploadBlob),
             27 => Ok(Self::Session),
             28 => Ok(Self::SyncNeeded),
+            29 => Ok(Self::AskForAccess),
+            30 => Ok(Self::AccessDecision),
+            31 => Ok(Self::DiscoverDocs),
 
             _ => Err(error::InvalidResponseType(value)),
         }
@@ -131,6 +152,9 @@ impl From<ResponseType> for u8 {
             ResponseType::UploadBlob => 25,
             ResponseType::Session => 27,
             ResponseType::SyncNeeded => 28,
+            ResponseType::AskForAccess => 29,
+            ResponseType::AccessDecision => 30,
+            ResponseType::DiscoverDocs => 31,
         }
     }
 }
@@ -6,8 +6,12 @@ use keyhive_core::{
 use message_types::{NextSyncPhaseType, SessionRequestType};
 
 use crate::{
+    auth::Signed as AuthSigned,
+    group_metadata::GroupMetadataRecord,
     riblt::CodedSymbol,
+    seals::SealRecord,
     sync::{server_session::GraphSyncPhase, CgkaSymbol, DocStateHash, MembershipSymbol, SessionId},
+    tombstones::TombstoneRecord,
     CommitHash, DocumentId,
 };
 
@@ -67,6 +71,9 @@ pub(crate) enum SessionMessage {
         count: u32,
     },
     FetchCgkaOps(DocumentId, Vec<Digest<Signed<CgkaOperation>>>),
+    ExchangeGroupMetadata(Vec<AuthSigned<GroupMetadataRecord>>),
+    ExchangeTombstones(Vec<AuthSigned<TombstoneRecord>>),
+    ExchangeSeals(Vec<AuthSigned<SealRecord>>),
 }
 
 impl std::fmt::Display for SessionMessage {
@@ -83,6 +90,15 @@ impl std::fmt::Display for SessionMessage {
                 write!(f, "FetchCgkaSymbols(doc: {}, count: {})", doc, count)
             }
             Self::FetchCgkaOps(doc_id, _) => write!(f, "FetchCgkaOps(doc_id: {})", doc_id),
+            Self::ExchangeGroupMetadata(records) => {
+                write!(f, "ExchangeGroupMetadata(records: {})", records.len())
+            }
+            Self::ExchangeTombstones(records) => {
+                write!(f, "ExchangeTombstones(records: {})", records.len())
+            }
+            Self::ExchangeSeals(records) => {
+                write!(f, "ExchangeSeals(records: {})", records.len())
+            }
         }
     }
 }
@@ -103,6 +119,9 @@ pub(crate) enum SessionResponse {
         Vec<keyhive_core::crypto::signed::Signed<keyhive_core::cgka::operation::CgkaOperation>>,
     ),
     FetchCgkaSymbols(Vec<CodedSymbol<CgkaSymbol>>),
+    ExchangeGroupMetadata(Vec<AuthSigned<GroupMetadataRecord>>),
+    ExchangeTombstones(Vec<AuthSigned<TombstoneRecord>>),
+    ExchangeSeals(Vec<AuthSigned<SealRecord>>),
     Expired,
     Error(String),
 }
@@ -134,6 +153,15 @@ impl std::fmt::Debug for SessionResponse {
             SessionResponse::FetchCgkaSymbols(symbols) => {
                 write!(f, "FetchCgkaSymbols({} symbols)", symbols.len())
             }
+            SessionResponse::ExchangeGroupMetadata(records) => {
+                write!(f, "ExchangeGroupMetadata({} records)", records.len())
+            }
+            SessionResponse::ExchangeTombstones(records) => {
+                write!(f, "ExchangeTombstones({} records)", records.len())
+            }
+            SessionResponse::ExchangeSeals(records) => {
+                write!(f, "ExchangeSeals({} records)", records.len())
+            }
             SessionResponse::Expired => {
                 write!(f, "Expired")
             }
@@ -32,6 +32,9 @@ pub(super) enum SessionSyncMsgType {
     FetchMembershipOps = 4,
     FetchCgkaSymbols = 5,
     FetchCgkaOps = 6,
+    ExchangeGroupMetadata = 7,
+    ExchangeTombstones = 8,
+    ExchangeSeals = 9,
 }
 
 impl From<SessionSyncMsgType> for u8 {
@@ -51,6 +54,9 @@ impl<'a> Parse<'a> for SessionSyncMsgType {
             4 => Ok((input, SessionSyncMsgType::FetchMembershipOps)),
             5 => Ok((input, SessionSyncMsgType::FetchCgkaSymbols)),
             6 => Ok((input, SessionSyncMsgType::FetchCgkaOps)),
+            7 => Ok((input, SessionSyncMsgType::ExchangeGroupMetadata)),
+            8 => Ok((input, SessionSyncMsgType::ExchangeTombstones)),
+            9 => Ok((input, SessionSyncMsgType::ExchangeSeals)),
             other => Err(input.error(format!("unexpected SessionSyncMsgType tag: {}", other))),
         }
     }
@@ -68,6 +74,9 @@ pub(super) enum SessionResponseType {
     FetchCgkaSymbols = 7,
     Expired = 8,
     Error = 9,
+    ExchangeGroupMetadata = 10,
+    ExchangeTombstones = 11,
+    ExchangeSeals = 12,
 }
 
 impl From<SessionResponseType> for u8 {
@@ -90,6 +99,9 @@ impl<'a> Parse<'a> for SessionResponseType {
             7 => Ok((input, SessionResponseType::FetchCgkaSymbols)),
             8 => Ok((input, SessionResponseType::Expired)),
             9 => Ok((input, SessionResponseType::Error)),
+            10 => Ok((input, SessionResponseType::ExchangeGroupMetadata)),
+            11 => Ok((input, SessionResponseType::ExchangeTombstones)),
+            12 => Ok((input, SessionResponseType::ExchangeSeals)),
             other => Err(input.error(format!("unexpected SessionResponseType tag: {}", other))),
         }
     }
@@ -129,6 +141,9 @@ pub(super) enum SessionMsgType {
     FetchMembershipOps,
     FetchCgkaSymbols,
     FetchCgkaOps,
+    ExchangeGroupMetadata,
+    ExchangeTombstones,
+    ExchangeSeals,
 }
 
 impl From<SessionMsgType> for u8 {
@@ -141,6 +156,9 @@ impl From<SessionMsgType> for u8 {
             SessionMsgType::FetchMembershipOps => 4,
             SessionMsgType::FetchCgkaSymbols => 5,
             SessionMsgType::FetchCgkaOps => 6,
+            SessionMsgType::ExchangeGroupMetadata => 7,
+            SessionMsgType::ExchangeTombstones => 8,
+            SessionMsgType::ExchangeSeals => 9,
         }
     }
 }
@@ -156,6 +174,9 @@ impl<'a> Parse<'a> for SessionMsgType {
             4 => Ok((input, SessionMsgType::FetchMembershipOps)),
             5 => Ok((input, SessionMsgType::FetchCgkaSymbols)),
             6 => Ok((input, SessionMsgType::FetchCgkaOps)),
+            7 => Ok((input, SessionMsgType::ExchangeGroupMetadata)),
+            8 => Ok((input, SessionMsgType::ExchangeTombstones)),
+            9 => Ok((input, SessionMsgType::ExchangeSeals)),
             other => Err(input.error(format!("unexpected SessionSyncMsgType tag: {}", other))),
         }
     }
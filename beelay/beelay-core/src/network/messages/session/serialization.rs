@@ -5,10 +5,14 @@ use keyhive_core::{
 };
 
 use crate::{
+    auth::Signed as AuthSigned,
+    group_metadata::GroupMetadataRecord,
     parse::{self, Parse},
     riblt::CodedSymbol,
+    seals::SealRecord,
     serialization::{leb128, Encode},
     sync::{CgkaSymbol, DocStateHash, MembershipSymbol, SessionId},
+    tombstones::TombstoneRecord,
     CommitHash, DocumentId,
 };
 
@@ -137,6 +141,18 @@ impl Encode for SessionMessage {
                 out.push(SessionMsgType::FinishMembership.into());
                 local_membership.encode_into(out);
             }
+            SessionMessage::ExchangeGroupMetadata(records) => {
+                out.push(SessionMsgType::ExchangeGroupMetadata.into());
+                records.encode_into(out);
+            }
+            SessionMessage::ExchangeTombstones(records) => {
+                out.push(SessionMsgType::ExchangeTombstones.into());
+                records.encode_into(out);
+            }
+            SessionMessage::ExchangeSeals(records) => {
+                out.push(SessionMsgType::ExchangeSeals.into());
+                records.encode_into(out);
+            }
         }
     }
 }
@@ -196,6 +212,18 @@ impl<'a> Parse<'a> for SessionMessage {
                     },
                 ))
             }
+            SessionMsgType::ExchangeGroupMetadata => {
+                let (input, records) = Vec::<AuthSigned<GroupMetadataRecord>>::parse(input)?;
+                Ok((input, SessionMessage::ExchangeGroupMetadata(records)))
+            }
+            SessionMsgType::ExchangeTombstones => {
+                let (input, records) = Vec::<AuthSigned<TombstoneRecord>>::parse(input)?;
+                Ok((input, SessionMessage::ExchangeTombstones(records)))
+            }
+            SessionMsgType::ExchangeSeals => {
+                let (input, records) = Vec::<AuthSigned<SealRecord>>::parse(input)?;
+                Ok((input, SessionMessage::ExchangeSeals(records)))
+            }
         }
     }
 }
@@ -235,6 +263,18 @@ impl Encode for SessionResponse {
                 output.push(SessionResponseType::FetchCgkaSymbols.into());
                 symbols.encode_into(output);
             }
+            SessionResponse::ExchangeGroupMetadata(records) => {
+                output.push(SessionResponseType::ExchangeGroupMetadata.into());
+                records.encode_into(output);
+            }
+            SessionResponse::ExchangeTombstones(records) => {
+                output.push(SessionResponseType::ExchangeTombstones.into());
+                records.encode_into(output);
+            }
+            SessionResponse::ExchangeSeals(records) => {
+                output.push(SessionResponseType::ExchangeSeals.into());
+                records.encode_into(output);
+            }
             SessionResponse::Expired => {
                 output.push(SessionResponseType::Expired.into());
             }
@@ -287,6 +327,18 @@ impl<'a> Parse<'a> for SessionResponse {
                     let (input, ops) = Vec::<Signed<CgkaOperation>>::parse(input)?;
                     Ok((input, SessionResponse::FetchCgkaOps(ops)))
                 }
+                SessionResponseType::ExchangeGroupMetadata => {
+                    let (input, records) = Vec::<AuthSigned<GroupMetadataRecord>>::parse(input)?;
+                    Ok((input, SessionResponse::ExchangeGroupMetadata(records)))
+                }
+                SessionResponseType::ExchangeTombstones => {
+                    let (input, records) = Vec::<AuthSigned<TombstoneRecord>>::parse(input)?;
+                    Ok((input, SessionResponse::ExchangeTombstones(records)))
+                }
+                SessionResponseType::ExchangeSeals => {
+                    let (input, records) = Vec::<AuthSigned<SealRecord>>::parse(input)?;
+                    Ok((input, SessionResponse::ExchangeSeals(records)))
+                }
                 SessionResponseType::Expired => Ok((input, SessionResponse::Expired)),
             }
         })
@@ -12,7 +12,7 @@ impl Default for OutboundRequestId {
 
 impl OutboundRequestId {
     pub fn new() -> Self {
-        Self(LAST_OUTBOUND_REQUEST_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+        Self(crate::ids::next(&LAST_OUTBOUND_REQUEST_ID))
     }
 
     pub fn serialize(&self) -> u64 {
@@ -2,6 +2,7 @@
 use crate::{
     auth,
     serialization::{parse, Encode, Parse},
+    Audience, PeerId, UnixTimestamp,
 };
 
 // This is a wrapper type which forms the public API for auth::sign::Signed<auth::message::Message>.
@@ -19,6 +20,26 @@ impl SignedMessage {
     pub fn encode(&self) -> Vec<u8> {
         Encode::encode(self)
     }
+
+    pub(crate) fn inspect(&self) -> InspectedRequest {
+        InspectedRequest {
+            sender: PeerId::from(self.0.verifier),
+            audience: self.0.payload.audience,
+            expires_at: self.0.payload.expires_at,
+            signature_valid: self.0.verify().is_ok(),
+        }
+    }
+}
+
+/// What a `SignedMessage` claims about itself, see `Event::inspect_request`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InspectedRequest {
+    /// The peer whose key signed the message. This is only trustworthy if
+    /// `signature_valid` is true.
+    pub sender: PeerId,
+    pub audience: Audience,
+    pub expires_at: UnixTimestamp,
+    pub signature_valid: bool,
 }
 
 impl Encode for SignedMessage {
@@ -37,3 +58,40 @@ impl<'a> Parse<'a> for SignedMessage {
 #[derive(Debug, thiserror::Error)]
 #[error("error decoding: {0}")]
 pub struct DecodeMessage(pub(super) String);
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::SignedMessage;
+    use crate::{
+        auth::{self, message::Message, offset_seconds::OffsetSeconds, signed::Signed},
+        Audience, PeerId, UnixTimestamp,
+    };
+
+    #[test]
+    fn inspect_reports_claims_and_signature_validity() {
+        let sender = SigningKey::from_bytes(&[1; 32]);
+        let audience = Audience::service_name("sync");
+        let payload = auth::send(
+            UnixTimestamp::now(),
+            OffsetSeconds(0),
+            audience,
+            vec![1, 2, 3],
+        );
+        let mut message = SignedMessage(Signed::<Message> {
+            signature: sender.sign(&crate::serialization::Encode::encode(&payload)),
+            verifier: sender.verifying_key(),
+            payload: payload.clone(),
+        });
+
+        let inspected = message.inspect();
+        assert_eq!(inspected.sender, PeerId::from(sender.verifying_key()));
+        assert_eq!(inspected.audience, audience);
+        assert_eq!(inspected.expires_at, payload.expires_at);
+        assert!(inspected.signature_valid);
+
+        message.0.payload.content = vec![4, 5, 6];
+        assert!(!message.inspect().signature_valid);
+    }
+}
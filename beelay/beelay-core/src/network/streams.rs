@@ -1,24 +1,34 @@
 use std::{
     collections::{HashMap, HashSet},
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::AtomicU64,
 };
 
+pub use compression::StreamCodec;
 pub(crate) use connection::ConnRequestId;
 use connection::Connection;
-pub use error::StreamError;
+pub use error::{HandshakeError, StreamError};
+pub use protocol_version::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+pub use resumption::StreamResumptionToken;
 
+mod compression;
 mod connection;
 mod handshake;
 mod message;
+mod protocol_version;
+mod reorder;
+pub(crate) mod resumption;
 use futures::channel::{mpsc, oneshot};
 use handshake::{Connecting, Handshake, Step};
 pub(crate) use message::StreamMessage;
+use protocol_version::ProtocolVersions;
+use reorder::ReorderBuffer;
 
 use crate::{
     auth::{self, Signed},
     conn_info,
     serialization::Encode,
-    Audience, PeerId, Request, Response, Signer, UnixTimestamp, UnixTimestampMillis,
+    Audience, CommitHash, DocumentId, PeerId, Request, Response, Signer, UnixTimestamp,
+    UnixTimestampMillis,
 };
 
 use super::messages::Envelope;
@@ -30,7 +40,7 @@ static LAST_STREAM_ID: AtomicU64 = AtomicU64::new(0);
 
 impl StreamId {
     pub(crate) fn new() -> Self {
-        Self(LAST_STREAM_ID.fetch_add(1, Ordering::Relaxed))
+        Self(crate::ids::next(&LAST_STREAM_ID))
     }
 
     pub fn serialize(&self) -> u64 {
@@ -52,6 +62,9 @@ pub enum StreamEvent {
 pub(crate) enum UnsignedStreamEvent {
     Close,
     Send(OutboundMessage),
+    // Sent on a stream which negotiated a codec, the encoded envelope should
+    // be compressed with the codec once the message is signed
+    SendCompressed(OutboundMessage, StreamCodec),
 }
 
 impl std::fmt::Debug for StreamEvent {
@@ -74,17 +87,74 @@ pub(crate) struct Streams {
     modified: HashSet<StreamId>,
     our_peer_id: PeerId,
     stopping: bool,
+    reorder_window: usize,
+    // Notified when the handshake restarted by `restart_handshake` completes
+    handshake_waiters: HashMap<StreamId, oneshot::Sender<Result<(), HandshakeError>>>,
 }
 
 struct StreamMeta {
-    state: StreamState,
+    state: ConnectionState,
+    // The direction the stream was created with
+    direction: StreamDirection,
     received_sync_needed: bool,
     sync_phase: SyncPhase,
+    receive_only: bool,
+    // The heads of each document the peer on the other end of this stream
+    // had as of the last time we synced that document with them
+    remote_heads: HashMap<DocumentId, Vec<CommitHash>>,
+    // The token this stream was created with, until it is either checked
+    // against the peer we connected to or consumed by the first sync
+    resume_from: Option<StreamResumptionToken>,
+    // When this stream last completed a sync along with a digest of the local
+    // state the peer could reach at that point
+    agreed_state: Option<(UnixTimestamp, [u8; 32])>,
+    metrics: StreamMetrics,
+    reorder: ReorderBuffer<connection::ConnectionMessage>,
+    // The codecs offered when the stream was created, offered again if the
+    // handshake is restarted
+    codecs: Vec<StreamCodec>,
+    // While a restarted handshake is in progress, the connection it replaces
+    previous_connection: Option<connection::Connection>,
 }
 
-enum StreamState {
+enum ConnectionState {
     Connecting(Option<Handshake>),
     Established(Connection),
+    // The handshake failed and we have asked the host to close the stream
+    Closing(HandshakeError),
+}
+
+/// Outbound messages for a stream which the transport has not yet reported as sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamQueueDepth {
+    pub messages: usize,
+    pub bytes: usize,
+}
+
+/// Traffic on a stream since it was created
+///
+/// Messages include those sent during the handshake. Inbound messages
+/// dropped by the stream's rate limit are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamMetrics {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Requests we sent over the stream which the peer has responded to
+    pub round_trips: u64,
+    /// Times the stream gave up on messages which never arrived and synced
+    /// again from scratch, see
+    /// [`Config::stream_reorder_window`](crate::config::Config::stream_reorder_window)
+    pub resyncs: u64,
+}
+
+/// The most unsolicited inbound traffic a stream will accept each second,
+/// see [`Config::stream_rate_limit`](crate::config::Config::stream_rate_limit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamRateLimit {
+    pub messages_per_second: u32,
+    pub bytes_per_second: u64,
 }
 
 pub(crate) struct EstablishedStream {
@@ -93,12 +163,16 @@ pub(crate) struct EstablishedStream {
     pub(crate) direction: ResolvedDirection,
     pub(crate) sync_phase: SyncPhase,
     pub(crate) received_sync_needed: bool,
+    pub(crate) receive_only: bool,
+    pub(crate) codec: Option<StreamCodec>,
+    pub(crate) protocol_version: u8,
 }
 
 impl Streams {
     pub(crate) fn new(
         outbox: mpsc::UnboundedSender<(StreamId, UnsignedStreamEvent)>,
         verifying_key: ed25519_dalek::VerifyingKey,
+        reorder_window: usize,
     ) -> Self {
         Self {
             our_peer_id: PeerId::from(verifying_key),
@@ -107,6 +181,8 @@ impl Streams {
             streams: HashMap::new(),
             modified: HashSet::new(),
             stopping: false,
+            reorder_window,
+            handshake_waiters: HashMap::new(),
         }
     }
 
@@ -114,6 +190,8 @@ impl Streams {
         &mut self,
         now: UnixTimestamp,
         stream_direction: StreamDirection,
+        codecs: Vec<StreamCodec>,
+        resume_from: Option<StreamResumptionToken>,
     ) -> StreamId {
         let stream_id = StreamId::new();
         if self.stopping {
@@ -122,24 +200,43 @@ impl Streams {
                 .unbounded_send((stream_id, UnsignedStreamEvent::Close));
             return stream_id;
         }
+        let receive_only = matches!(stream_direction, StreamDirection::Observing { .. });
+        let direction = stream_direction.clone();
         let handshake = match stream_direction {
-            StreamDirection::Connecting { remote_audience } => {
-                let (hs, msg) = Handshake::connect(now, remote_audience);
+            StreamDirection::Connecting { remote_audience }
+            | StreamDirection::Observing { remote_audience } => {
+                let (hs, msg) = Handshake::connect(
+                    now,
+                    remote_audience,
+                    codecs.clone(),
+                    ProtocolVersions::ours(),
+                );
                 let _ = self
                     .outbox
                     .unbounded_send((stream_id, UnsignedStreamEvent::Send(msg)));
                 hs
             }
-            StreamDirection::Accepting { receive_audience } => {
-                Handshake::accept(receive_audience.map(Audience::service_name))
-            }
+            StreamDirection::Accepting { receive_audience } => Handshake::accept(
+                receive_audience.map(Audience::service_name),
+                codecs.clone(),
+                ProtocolVersions::ours(),
+            ),
         };
         let stream = StreamMeta {
-            state: StreamState::Connecting(Some(handshake)),
+            state: ConnectionState::Connecting(Some(handshake)),
+            direction,
             received_sync_needed: false,
             sync_phase: SyncPhase::Listening {
                 last_synced_at: None,
             },
+            receive_only,
+            remote_heads: HashMap::new(),
+            resume_from,
+            agreed_state: None,
+            metrics: StreamMetrics::default(),
+            reorder: ReorderBuffer::new(),
+            codecs,
+            previous_connection: None,
         };
         self.streams.insert(stream_id, stream);
         stream_id
@@ -147,13 +244,16 @@ impl Streams {
 
     pub(crate) fn established(&self) -> impl Iterator<Item = EstablishedStream> + '_ {
         self.streams.iter().filter_map(|(id, meta)| {
-            if let StreamState::Established(connection) = &meta.state {
+            if let ConnectionState::Established(connection) = &meta.state {
                 Some(EstablishedStream {
                     id: *id,
                     their_peer_id: connection.their_peer_id(),
                     direction: connection.direction(),
                     received_sync_needed: meta.received_sync_needed,
                     sync_phase: meta.sync_phase,
+                    receive_only: meta.receive_only,
+                    codec: connection.codec(),
+                    protocol_version: connection.protocol_version(),
                 })
             } else {
                 None
@@ -161,9 +261,23 @@ impl Streams {
         })
     }
 
+    pub(crate) fn list(
+        &self,
+    ) -> impl Iterator<Item = (StreamId, StreamDirection, StreamState)> + '_ {
+        self.streams.iter().map(|(id, meta)| {
+            let state = match meta.state {
+                ConnectionState::Connecting(_) => StreamState::Handshaking,
+                ConnectionState::Established(_) => StreamState::Established,
+                ConnectionState::Closing(_) => StreamState::Closing,
+            };
+            (*id, meta.direction.clone(), state)
+        })
+    }
+
     /// Called when the stream is closed by a disconnect command
     pub(crate) fn remove(&mut self, stream: StreamId) {
         self.streams.remove(&stream);
+        self.handshake_waiters.remove(&stream);
         for tx in self
             .pending_requests
             .remove(&stream)
@@ -179,6 +293,7 @@ impl Streams {
             .outbox
             .unbounded_send((stream, UnsignedStreamEvent::Close));
         self.streams.remove(&stream);
+        self.handshake_waiters.remove(&stream);
         for tx in self
             .pending_requests
             .remove(&stream)
@@ -239,17 +354,142 @@ impl Streams {
         }
     }
 
+    pub(crate) fn record_remote_heads(
+        &mut self,
+        stream_id: StreamId,
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    ) {
+        if let Some(meta) = self.streams.get_mut(&stream_id) {
+            meta.remote_heads.insert(doc_id, heads);
+        } else {
+            tracing::warn!(
+                ?stream_id,
+                "attempted to record remote heads for nonexistent stream"
+            );
+        }
+    }
+
+    /// The heads of `doc_id` the peer had as of the last time we synced it
+    /// with them, or `None` if we haven't synced it over this stream yet
+    pub(crate) fn remote_heads(
+        &self,
+        stream_id: StreamId,
+        doc_id: &DocumentId,
+    ) -> Result<Option<Vec<CommitHash>>, StreamError> {
+        let meta = self
+            .streams
+            .get(&stream_id)
+            .ok_or(StreamError::NoSuchStream)?;
+        Ok(meta.remote_heads.get(doc_id).cloned())
+    }
+
+    /// The resumption token the stream was created with, if it was for the
+    /// peer we ended up connected to. Only the first call returns the token.
+    pub(crate) fn take_resumption(&mut self, stream_id: StreamId) -> Option<StreamResumptionToken> {
+        self.streams.get_mut(&stream_id)?.resume_from.take()
+    }
+
+    pub(crate) fn record_agreed_state(
+        &mut self,
+        stream_id: StreamId,
+        synced_at: UnixTimestamp,
+        local_state: [u8; 32],
+    ) {
+        if let Some(meta) = self.streams.get_mut(&stream_id) {
+            meta.agreed_state = Some((synced_at, local_state));
+        } else {
+            tracing::warn!(
+                ?stream_id,
+                "attempted to record agreed state for nonexistent stream"
+            );
+        }
+    }
+
+    /// A token which can be used to resume syncing with the peer on the other
+    /// end of this stream, or `None` if the stream hasn't completed a sync yet
+    pub(crate) fn resumption_token(
+        &self,
+        stream_id: StreamId,
+    ) -> Result<Option<StreamResumptionToken>, StreamError> {
+        let meta = self
+            .streams
+            .get(&stream_id)
+            .ok_or(StreamError::NoSuchStream)?;
+        let ConnectionState::Established(connection) = &meta.state else {
+            return Ok(None);
+        };
+        Ok(meta
+            .agreed_state
+            .map(|(synced_at, local_state)| StreamResumptionToken {
+                peer_id: connection.their_peer_id(),
+                synced_at,
+                local_state,
+                remote_heads: meta.remote_heads.clone(),
+            }))
+    }
+
+    /// Record that a message of `size` bytes was handed to the transport
+    pub(crate) fn record_sent(&mut self, stream_id: StreamId, size: usize) {
+        // The stream may have been disconnected while the message was signed
+        if let Some(meta) = self.streams.get_mut(&stream_id) {
+            meta.metrics.messages_sent += 1;
+            meta.metrics.bytes_sent += size as u64;
+        }
+    }
+
+    /// Why the handshake on this stream failed, or `None` if it is still
+    /// running or completed successfully
+    pub(crate) fn handshake_error(
+        &self,
+        stream_id: StreamId,
+    ) -> Result<Option<HandshakeError>, StreamError> {
+        let meta = self
+            .streams
+            .get(&stream_id)
+            .ok_or(StreamError::NoSuchStream)?;
+        match &meta.state {
+            ConnectionState::Closing(reason) => Ok(Some(reason.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    pub(crate) fn metrics(&self, stream_id: StreamId) -> Result<StreamMetrics, StreamError> {
+        self.streams
+            .get(&stream_id)
+            .map(|meta| meta.metrics)
+            .ok_or(StreamError::NoSuchStream)
+    }
+
+    /// The number of requests we have sent on `stream_id` which are still
+    /// waiting for a response
+    pub(crate) fn awaiting_responses(&self, stream_id: StreamId) -> usize {
+        self.pending_requests
+            .get(&stream_id)
+            .map(|requests| requests.len())
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn all_metrics(&self) -> HashMap<StreamId, StreamMetrics> {
+        self.streams
+            .iter()
+            .map(|(id, meta)| (*id, meta.metrics))
+            .collect()
+    }
+
     pub(crate) fn take_changed(&mut self) -> Vec<conn_info::ConnectionInfo> {
         std::mem::take(&mut self.modified)
             .into_iter()
             .filter_map(|stream_id| {
                 let meta = self.streams.get(&stream_id)?;
-                let StreamState::Established(connection) = &meta.state else {
+                let ConnectionState::Established(connection) = &meta.state else {
                     return None;
                 };
                 Some(conn_info::ConnectionInfo {
                     peer_id: connection.their_peer_id(),
                     state: meta.sync_phase.into(),
+                    codec: connection.codec(),
+                    protocol_version: connection.protocol_version(),
                 })
             })
             .collect()
@@ -260,83 +500,278 @@ impl Streams {
         now: UnixTimestamp,
         stream_id: StreamId,
         msg: Vec<u8>,
-    ) -> Result<Option<HandledMessage>, error::StreamError> {
+    ) -> Result<Vec<HandledMessage>, error::StreamError> {
         let Some(stream) = self.streams.get_mut(&stream_id) else {
             return Err(error::StreamError::NoSuchStream);
         };
+        stream.metrics.messages_received += 1;
+        stream.metrics.bytes_received += msg.len() as u64;
         match &mut stream.state {
-            StreamState::Connecting(handshake) => {
+            ConnectionState::Connecting(handshake) => {
+                if let Some(previous) = &mut stream.previous_connection {
+                    if let Ok((_, connection::ConnectionMessage::Request { .. }))
+                    | Ok((_, connection::ConnectionMessage::Response { .. })) =
+                        previous.receive_message(now, &self.our_peer_id, msg.clone())
+                    {
+                        tracing::trace!(
+                            ?stream_id,
+                            "dropping message sent before the peer saw our new hello"
+                        );
+                        return Ok(Vec::new());
+                    }
+                }
                 let hs = handshake
                     .take()
                     .expect("handshake should never be empty except in this code block");
-                let Step { state, next_msg } = hs.receive_message(now, &self.our_peer_id, msg);
-                if let Some(msg) = next_msg {
-                    let _ = self
-                        .outbox
-                        .unbounded_send((stream_id, UnsignedStreamEvent::Send(msg)));
-                }
-                match state {
-                    Connecting::Complete(connection) => {
-                        tracing::trace!(?stream_id, their_peer_id=%connection.their_peer_id(), "handshake complete");
-                        self.modified.insert(stream_id);
-                        stream.state = StreamState::Established(*connection)
-                    }
-                    Connecting::Handshaking(handshake) => {
-                        stream.state = StreamState::Connecting(Some(handshake))
+                self.advance_handshake(now, stream_id, hs, msg);
+                Ok(Vec::new())
+            }
+            ConnectionState::Closing(_) => {
+                tracing::trace!(?stream_id, "ignoring message on closing stream");
+                Ok(Vec::new())
+            }
+            ConnectionState::Established(connection) => {
+                let their_peer_id = connection.their_peer_id();
+                let received = match connection.receive_message(now, &self.our_peer_id, msg) {
+                    Ok((None, msg)) => vec![msg],
+                    Ok((Some(seq), msg)) => {
+                        let reordered = match stream.reorder.receive(seq, msg, self.reorder_window)
+                        {
+                            Ok(reordered) => reordered,
+                            Err(e) => {
+                                tracing::debug!(
+                                    ?stream_id,
+                                    ?seq,
+                                    err=?e,
+                                    "message out of sequence, disconnecting"
+                                );
+                                let _ = self
+                                    .outbox
+                                    .unbounded_send((stream_id, UnsignedStreamEvent::Close));
+                                self.streams.remove(&stream_id);
+                                return Ok(Vec::new());
+                            }
+                        };
+                        if reordered.skipped {
+                            tracing::debug!(
+                                ?stream_id,
+                                "gap in stream messages is wider than the reorder window, resyncing"
+                            );
+                            self.resync(stream_id);
+                        }
+                        reordered.ready
                     }
-                    Connecting::Failed(reason) => {
-                        tracing::debug!(reason, "handshake failed, disconnecting");
+                    Err(e) => {
+                        tracing::error!(err=?e, "error handling stream message, disconnnecting");
                         let _ = self
                             .outbox
                             .unbounded_send((stream_id, UnsignedStreamEvent::Close));
+                        self.streams.remove(&stream_id);
+                        return Ok(Vec::new());
                     }
-                }
-                Ok(None)
-            }
-            StreamState::Established(connection) => {
-                match connection.receive_message(now, &self.our_peer_id, msg) {
-                    Ok(msg) => match msg {
+                };
+                let mut handled = Vec::new();
+                for msg in received {
+                    match msg {
                         connection::ConnectionMessage::Request { id, req } => {
-                            Ok(Some(HandledMessage::NewRequest {
-                                from: connection.their_peer_id(),
+                            handled.push(HandledMessage::NewRequest {
+                                from: their_peer_id,
                                 id,
                                 req,
-                            }))
+                            });
                         }
                         connection::ConnectionMessage::Response { id, msg } => {
-                            if let Some(request) = self
-                                .pending_requests
-                                .get_mut(&stream_id)
-                                .and_then(|requests| requests.remove(&id))
-                            {
-                                let _ = request.send(Some((connection.their_peer_id(), msg)));
-                            }
-                            if self.stopping
-                                && self
-                                    .pending_requests
-                                    .get(&stream_id)
-                                    .map(|r| r.is_empty())
-                                    .unwrap_or(true)
-                            {
-                                self.pending_requests.remove(&stream_id);
-                                self.disconnect(stream_id);
+                            self.handle_response(stream_id, their_peer_id, id, msg)
+                        }
+                        connection::ConnectionMessage::Restart(hello) => {
+                            tracing::debug!(?stream_id, "peer restarted the handshake");
+                            self.reset_sync_state(stream_id);
+                            if let Some(meta) = self.streams.get_mut(&stream_id) {
+                                meta.reorder = ReorderBuffer::new();
                             }
-                            Ok(None)
+                            let hs = Handshake::accept(
+                                None,
+                                self.codecs(stream_id),
+                                ProtocolVersions::ours(),
+                            );
+                            self.advance_handshake(now, stream_id, hs, hello);
                         }
-                    },
-                    Err(e) => {
-                        tracing::error!(err=?e, "error handling stream message, disconnnecting");
-                        let _ = self
-                            .outbox
-                            .unbounded_send((stream_id, UnsignedStreamEvent::Close));
-                        self.streams.remove(&stream_id);
-                        Ok(None)
                     }
                 }
+                Ok(handled)
+            }
+        }
+    }
+
+    fn advance_handshake(
+        &mut self,
+        now: UnixTimestamp,
+        stream_id: StreamId,
+        hs: Handshake,
+        msg: Vec<u8>,
+    ) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        let Step { state, next_msg } = hs.receive_message(now, &self.our_peer_id, msg);
+        if let Some(msg) = next_msg {
+            let _ = self
+                .outbox
+                .unbounded_send((stream_id, UnsignedStreamEvent::Send(msg)));
+        }
+        if !matches!(state, Connecting::Handshaking(_)) {
+            stream.previous_connection = None;
+        }
+        match state {
+            Connecting::Complete(connection) => {
+                tracing::trace!(?stream_id, their_peer_id=%connection.their_peer_id(), codec=?connection.codec(), "handshake complete");
+                self.modified.insert(stream_id);
+                if let Some(token) = stream.resume_from.take() {
+                    if token.peer_id == connection.their_peer_id() {
+                        stream.remote_heads = token.remote_heads.clone();
+                        stream.resume_from = Some(token);
+                    } else {
+                        tracing::debug!(
+                            token_peer=%token.peer_id,
+                            "resumption token is for a different peer, ignoring it"
+                        );
+                    }
+                }
+                stream.state = ConnectionState::Established(*connection);
+                if let Some(waiter) = self.handshake_waiters.remove(&stream_id) {
+                    let _ = waiter.send(Ok(()));
+                }
+            }
+            Connecting::Handshaking(handshake) => {
+                stream.state = ConnectionState::Connecting(Some(handshake))
+            }
+            Connecting::Failed(reason) => {
+                tracing::debug!(%reason, "handshake failed, disconnecting");
+                let _ = self
+                    .outbox
+                    .unbounded_send((stream_id, UnsignedStreamEvent::Close));
+                if let Some(waiter) = self.handshake_waiters.remove(&stream_id) {
+                    let _ = waiter.send(Err(reason.clone()));
+                }
+                stream.state = ConnectionState::Closing(reason);
             }
         }
     }
 
+    fn codecs(&self, stream_id: StreamId) -> Vec<StreamCodec> {
+        self.streams
+            .get(&stream_id)
+            .map(|meta| meta.codecs.clone())
+            .unwrap_or_default()
+    }
+
+    /// Run the handshake on an established stream again and then sync with
+    /// the peer from scratch, without closing the stream
+    ///
+    /// The returned receiver resolves once the new handshake completes or
+    /// fails, it is dropped if the stream is closed first.
+    pub(crate) fn restart_handshake(
+        &mut self,
+        now: UnixTimestamp,
+        stream_id: StreamId,
+    ) -> Result<oneshot::Receiver<Result<(), HandshakeError>>, StreamError> {
+        let meta = self
+            .streams
+            .get(&stream_id)
+            .ok_or(StreamError::NoSuchStream)?;
+        if !matches!(meta.state, ConnectionState::Established(_)) {
+            return Err(StreamError::InvalidState);
+        }
+        self.reset_sync_state(stream_id);
+        let meta = self
+            .streams
+            .get_mut(&stream_id)
+            .expect("we just looked up the stream");
+        let ConnectionState::Established(connection) =
+            std::mem::replace(&mut meta.state, ConnectionState::Connecting(None))
+        else {
+            unreachable!("we just checked the state");
+        };
+        // The hello is sent over the old connection, so it is compressed with
+        // its codec and addressed to the peer it authenticated as
+        let (hs, hello) = Handshake::restart(
+            now,
+            connection.their_peer_id(),
+            connection.clock_skew(),
+            meta.codecs.clone(),
+            ProtocolVersions::ours(),
+        );
+        let hello = outbound(hello, connection.codec());
+        meta.state = ConnectionState::Connecting(Some(hs));
+        meta.previous_connection = Some(connection);
+        meta.reorder = ReorderBuffer::new();
+        let _ = self.outbox.unbounded_send((stream_id, hello));
+        let (tx, rx) = oneshot::channel();
+        self.handshake_waiters.insert(stream_id, tx);
+        Ok(rx)
+    }
+
+    fn handle_response(
+        &mut self,
+        stream_id: StreamId,
+        their_peer_id: PeerId,
+        id: ConnRequestId,
+        msg: Response,
+    ) {
+        if let Some(request) = self
+            .pending_requests
+            .get_mut(&stream_id)
+            .and_then(|requests| requests.remove(&id))
+        {
+            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                stream.metrics.round_trips += 1;
+            }
+            let _ = request.send(Some((their_peer_id, msg)));
+        }
+        if self.stopping
+            && self
+                .pending_requests
+                .get(&stream_id)
+                .map(|r| r.is_empty())
+                .unwrap_or(true)
+        {
+            self.pending_requests.remove(&stream_id);
+            self.disconnect(stream_id);
+        }
+    }
+
+    /// Forget what we know about the sync state of the peer on the other end
+    /// of `stream_id` and sync with them again from scratch
+    ///
+    /// Requests we sent on the stream which are still waiting for a response
+    /// are failed, the response may have been lost.
+    fn resync(&mut self, stream_id: StreamId) {
+        if let Some(meta) = self.streams.get_mut(&stream_id) {
+            meta.metrics.resyncs += 1;
+        }
+        self.reset_sync_state(stream_id);
+    }
+
+    fn reset_sync_state(&mut self, stream_id: StreamId) {
+        if let Some(meta) = self.streams.get_mut(&stream_id) {
+            meta.remote_heads.clear();
+            meta.agreed_state = None;
+            meta.received_sync_needed = true;
+            meta.sync_phase = SyncPhase::Listening {
+                last_synced_at: None,
+            };
+            self.modified.insert(stream_id);
+        }
+        for tx in self
+            .pending_requests
+            .remove(&stream_id)
+            .unwrap_or_default()
+            .into_values()
+        {
+            let _ = tx.send(None);
+        }
+    }
+
     pub(crate) fn send_request(
         &mut self,
         now: UnixTimestamp,
@@ -347,7 +782,7 @@ impl Streams {
         let Some(stream) = self.streams.get_mut(&stream_id) else {
             return Err(StreamError::NoSuchStream);
         };
-        let StreamState::Established(connection) = &mut stream.state else {
+        let ConnectionState::Established(connection) = &mut stream.state else {
             return Err(StreamError::InvalidState);
         };
         let req_id = connection.next_req_id();
@@ -355,20 +790,19 @@ impl Streams {
             now,
             connection.clock_skew(),
             Audience::peer(&connection.their_peer_id()),
-            StreamMessage::Request {
-                id: req_id,
-                req: Box::new(request),
-            }
-            .encode(),
+            connection
+                .sequence(StreamMessage::Request {
+                    id: req_id,
+                    req: Box::new(request),
+                })
+                .encode(),
         );
-        let msg = OutboundMessage::Signed(msg);
+        let msg = outbound(OutboundMessage::Signed(msg), connection.codec());
         self.pending_requests
             .entry(stream_id)
             .or_default()
             .insert(req_id, reply);
-        let _ = self
-            .outbox
-            .unbounded_send((stream_id, UnsignedStreamEvent::Send(msg)));
+        let _ = self.outbox.unbounded_send((stream_id, msg));
         Ok(req_id)
     }
 
@@ -382,23 +816,22 @@ impl Streams {
         let Some(stream) = self.streams.get_mut(&stream_id) else {
             return Err(StreamError::NoSuchStream);
         };
-        let StreamState::Established(connection) = &mut stream.state else {
+        let ConnectionState::Established(connection) = &mut stream.state else {
             return Err(StreamError::InvalidState);
         };
         let msg = auth::send(
             now,
             connection.clock_skew(),
             Audience::peer(&connection.their_peer_id()),
-            StreamMessage::Response {
-                id: req_id,
-                resp: Box::new(resp),
-            }
-            .encode(),
+            connection
+                .sequence(StreamMessage::Response {
+                    id: req_id,
+                    resp: Box::new(resp),
+                })
+                .encode(),
         );
-        let msg = OutboundMessage::Signed(msg);
-        let _ = self
-            .outbox
-            .unbounded_send((stream_id, UnsignedStreamEvent::Send(msg)));
+        let msg = outbound(OutboundMessage::Signed(msg), connection.codec());
+        let _ = self.outbox.unbounded_send((stream_id, msg));
         Ok(())
     }
 
@@ -414,10 +847,41 @@ impl Streams {
     }
 }
 
+// Messages sent over an established connection are compressed if the
+// connection negotiated a codec
+fn outbound(msg: OutboundMessage, codec: Option<StreamCodec>) -> UnsignedStreamEvent {
+    match codec {
+        Some(codec) => UnsignedStreamEvent::SendCompressed(msg, codec),
+        None => UnsignedStreamEvent::Send(msg),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum StreamDirection {
-    Connecting { remote_audience: Audience },
-    Accepting { receive_audience: Option<String> },
+    Connecting {
+        remote_audience: Audience,
+    },
+    Accepting {
+        receive_audience: Option<String>,
+    },
+    /// Connect to the remote in the same way as `Connecting` but only ever
+    /// receive data on the resulting stream. Local commits, membership ops and
+    /// CGKA ops are never uploaded to the remote over an observing stream.
+    Observing {
+        remote_audience: Audience,
+    },
+}
+
+/// The lifecycle stage of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamState {
+    /// The handshake with the remote peer is still running
+    Handshaking,
+    /// The handshake completed and the stream is being used for sync
+    Established,
+    /// The handshake failed and the stream has been closed but has not yet
+    /// been disconnected
+    Closing,
 }
 
 // Once handshake is complete, which direction is labelled as the "Accepting"
@@ -486,6 +950,34 @@ pub(crate) mod error {
 
     impl std::error::Error for StreamError {}
 
+    /// Why the handshake on a stream failed
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum HandshakeError {
+        /// There is no protocol version both we and the remote support. Each
+        /// side is the newest version that peer speaks, so a remote newer
+        /// than local means this build needs updating.
+        ProtocolVersionMismatch {
+            local: u8,
+            remote: u8,
+        },
+        Other(String),
+    }
+
+    impl std::fmt::Display for HandshakeError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::ProtocolVersionMismatch { local, remote } => write!(
+                    f,
+                    "protocol version mismatch: we speak version {} but the remote speaks version {}",
+                    local, remote
+                ),
+                Self::Other(reason) => write!(f, "{}", reason),
+            }
+        }
+    }
+
+    impl std::error::Error for HandshakeError {}
+
     #[derive(Debug)]
     pub(crate) enum EncodeResponse {
         StreamError(StreamError),
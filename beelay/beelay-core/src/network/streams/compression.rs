@@ -0,0 +1,134 @@
+use crate::{
+    parse::{self, Parse},
+    serialization::Encode,
+};
+
+/// The largest message we will inflate a compressed message to
+///
+/// Messages are decompressed before their signature is checked, so without a
+/// limit any peer could exhaust our memory with a small deflate bomb.
+pub(crate) const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// A compression codec which can be negotiated during the stream handshake
+///
+/// Once both peers have agreed on a codec every message sent over the
+/// established stream is compressed with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub enum StreamCodec {
+    Deflate,
+}
+
+impl StreamCodec {
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Deflate => 0,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn compress(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Deflate => miniz_oxide::deflate::compress_to_vec(data, 6),
+        }
+    }
+
+    pub(crate) fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, error::Decompress> {
+        match self {
+            Self::Deflate => {
+                miniz_oxide::inflate::decompress_to_vec_with_limit(data, MAX_DECOMPRESSED_SIZE)
+                    .map_err(|e| match e.status {
+                        miniz_oxide::inflate::TINFLStatus::HasMoreOutput => {
+                            error::Decompress(format!(
+                                "message inflates to more than {} bytes",
+                                MAX_DECOMPRESSED_SIZE
+                            ))
+                        }
+                        _ => error::Decompress(e.to_string()),
+                    })
+            }
+        }
+    }
+
+    // Parse a list of codecs offered by a peer, skipping any codecs we don't
+    // know about so that newer peers can offer codecs we don't support yet
+    pub(super) fn parse_offered(
+        input: parse::Input<'_>,
+    ) -> Result<(parse::Input<'_>, Vec<Self>), parse::ParseError> {
+        input.parse_in_ctx("offered codecs", |input| {
+            let (input, tags) = Vec::<u8>::parse(input)?;
+            Ok((input, tags.into_iter().filter_map(Self::from_tag).collect()))
+        })
+    }
+}
+
+impl std::fmt::Display for StreamCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Deflate => write!(f, "deflate"),
+        }
+    }
+}
+
+impl Encode for StreamCodec {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push(self.tag());
+    }
+}
+
+impl Parse<'_> for StreamCodec {
+    fn parse(input: parse::Input<'_>) -> Result<(parse::Input<'_>, Self), parse::ParseError> {
+        input.parse_in_ctx("StreamCodec", |input| {
+            let (input, tag) = parse::u8(input)?;
+            let codec =
+                Self::from_tag(tag).ok_or_else(|| input.error(format!("unknown codec {}", tag)))?;
+            Ok((input, codec))
+        })
+    }
+}
+
+pub(crate) mod error {
+    #[derive(Debug)]
+    pub(crate) struct Decompress(pub(super) String);
+
+    impl std::fmt::Display for Decompress {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "failed to decompress message: {}", self.0)
+        }
+    }
+
+    impl std::error::Error for Decompress {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamCodec, MAX_DECOMPRESSED_SIZE};
+
+    #[test]
+    fn deflate_roundtrip() {
+        bolero::check!().with_type::<Vec<u8>>().for_each(|data| {
+            let compressed = StreamCodec::Deflate.compress(data);
+            let decompressed = StreamCodec::Deflate.decompress(&compressed).unwrap();
+            assert_eq!(&decompressed, data);
+        });
+    }
+
+    #[test]
+    fn messages_which_inflate_past_the_limit_are_rejected() {
+        let bomb = StreamCodec::Deflate.compress(&vec![0; MAX_DECOMPRESSED_SIZE + 1]);
+        assert!(bomb.len() < 1024 * 1024);
+        assert!(StreamCodec::Deflate.decompress(&bomb).is_err());
+
+        let largest = StreamCodec::Deflate.compress(&vec![0; MAX_DECOMPRESSED_SIZE]);
+        assert_eq!(
+            StreamCodec::Deflate.decompress(&largest).unwrap().len(),
+            MAX_DECOMPRESSED_SIZE
+        );
+    }
+}
@@ -5,7 +5,7 @@ use crate::{
     PeerId, Request, Response, UnixTimestamp,
 };
 
-use super::{ResolvedDirection, StreamMessage};
+use super::{protocol_version::SEQUENCED, ResolvedDirection, StreamCodec, StreamMessage};
 
 /// A message sent over the channel between two peers
 pub enum ConnectionMessage {
@@ -22,6 +22,9 @@ pub enum ConnectionMessage {
         /// The response itself
         msg: Response,
     },
+    /// The other end started a new handshake, holding the decompressed hello
+    /// to hand to it
+    Restart(Vec<u8>),
 }
 
 /// A completed connection
@@ -31,29 +34,53 @@ pub(crate) struct Connection {
     last_req_id: ConnRequestId,
     direction: ResolvedDirection,
     clock_skew: OffsetSeconds,
+    codec: Option<StreamCodec>,
+    protocol_version: u8,
+    next_seq: u64,
 }
 
 impl Connection {
-    pub(crate) fn new_accepting(their_peer_id: PeerId, clock_skew: OffsetSeconds) -> Self {
+    pub(crate) fn new_accepting(
+        their_peer_id: PeerId,
+        clock_skew: OffsetSeconds,
+        codec: Option<StreamCodec>,
+        protocol_version: u8,
+    ) -> Self {
         Self {
             direction: ResolvedDirection::Accepting,
             their_peer_id,
             last_req_id: ConnRequestId::acceptors_request_id(),
             clock_skew,
+            codec,
+            protocol_version,
+            next_seq: 0,
         }
     }
 
-    pub(crate) fn new_connecting(their_peer_id: PeerId, clock_skew: OffsetSeconds) -> Self {
+    pub(crate) fn new_connecting(
+        their_peer_id: PeerId,
+        clock_skew: OffsetSeconds,
+        codec: Option<StreamCodec>,
+        protocol_version: u8,
+    ) -> Self {
         Self {
             direction: ResolvedDirection::Connecting,
             their_peer_id,
             last_req_id: ConnRequestId::connectors_request_id(),
             clock_skew,
+            codec,
+            protocol_version,
+            next_seq: 0,
         }
     }
 
     /// Receive a message from the other end
     ///
+    /// If a codec was negotiated during the handshake the message is
+    /// decompressed before being parsed. On a connection which sequences its
+    /// messages the sequence number the sender tagged the message with is
+    /// returned alongside it, the message may have overtaken earlier ones.
+    ///
     /// # Errors
     /// This returns an error if unexpected handshake messages are received.
     /// Receiving an error should be considered a fatal error and the connection
@@ -64,8 +91,14 @@ impl Connection {
         now: UnixTimestamp,
         our_peer_id: &PeerId,
         message: Vec<u8>,
-    ) -> Result<ConnectionMessage, error::Receive> {
-        let input = parse::Input::new(&message);
+    ) -> Result<(Option<u64>, ConnectionMessage), error::Receive> {
+        let raw = match self.codec {
+            Some(codec) => codec
+                .decompress(&message)
+                .map_err(|e| error::Receive(e.to_string()))?,
+            None => message,
+        };
+        let input = parse::Input::new(&raw);
         let (_input, message) = Envelope::parse(input)
             .map_err(|e| error::Receive(format!("failed to parse message: {}", e)))?;
         let payload = match message {
@@ -88,11 +121,33 @@ impl Connection {
                 )))
             }
         };
-        match payload.content {
-            StreamMessage::Hello => Err(error::Receive(
+        let (seq, content) = match payload.content {
+            StreamMessage::Sequenced { seq, message } if self.is_sequenced() => {
+                (Some(seq), *message)
+            }
+            StreamMessage::Sequenced { .. } => {
+                return Err(error::Receive(
+                    "received sequenced message on unsequenced connection".to_string(),
+                ))
+            }
+            StreamMessage::Request { .. } | StreamMessage::Response { .. }
+                if self.is_sequenced() =>
+            {
+                return Err(error::Receive(
+                    "received unsequenced message on sequenced connection".to_string(),
+                ))
+            }
+            other => (None, other),
+        };
+        let from = PeerId::from(payload.from);
+        let message = match content {
+            StreamMessage::Hello { .. } if from == self.their_peer_id => {
+                Ok(ConnectionMessage::Restart(raw.clone()))
+            }
+            StreamMessage::Hello { .. } => Err(error::Receive(
                 "received unexpected hello message".to_string(),
             )),
-            StreamMessage::HelloBack => Err(error::Receive(
+            StreamMessage::HelloBack { .. } => Err(error::Receive(
                 "received unexpected helloback message".to_string(),
             )),
             StreamMessage::HandshakeFailure(_) => Err(error::Receive(
@@ -105,9 +160,31 @@ impl Connection {
             StreamMessage::Error(e) => {
                 Err(error::Receive(format!("received error message: {}", e)))
             }
+            StreamMessage::Sequenced { .. } => Err(error::Receive(
+                "received nested sequenced message".to_string(),
+            )),
+        }?;
+        Ok((seq, message))
+    }
+
+    /// Tag `msg` with the next sequence number if this connection sequences
+    /// its messages
+    pub(crate) fn sequence(&mut self, msg: StreamMessage) -> StreamMessage {
+        if !self.is_sequenced() {
+            return msg;
+        }
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        StreamMessage::Sequenced {
+            seq,
+            message: Box::new(msg),
         }
     }
 
+    fn is_sequenced(&self) -> bool {
+        self.protocol_version >= SEQUENCED
+    }
+
     pub(crate) fn next_req_id(&mut self) -> ConnRequestId {
         let id = self.last_req_id.inc();
         self.last_req_id = id;
@@ -130,6 +207,16 @@ impl Connection {
     pub(crate) fn clock_skew(&self) -> OffsetSeconds {
         self.clock_skew
     }
+
+    /// The codec negotiated for this connection, if any
+    pub(crate) fn codec(&self) -> Option<StreamCodec> {
+        self.codec
+    }
+
+    /// The protocol version negotiated for this connection
+    pub(crate) fn protocol_version(&self) -> u8 {
+        self.protocol_version
+    }
 }
 
 // Connection request IDs must be unique to the connection. In order to achieve
@@ -33,6 +33,25 @@
 //! signature of the "hello_back" message) and can address all future messages
 //! to the remote using the peer ID.
 //!
+//! ## Compression
+//!
+//! The connector lists the compression codecs it supports in the "hello"
+//! message. If the acceptor also supports one of them it picks the first one
+//! it supports and names it in the "hello_back" message, after which every
+//! message on the stream is compressed with that codec. Peers which don't
+//! support compression ignore the codec list and reply with a plain
+//! "hello_back", in which case the stream is uncompressed.
+//!
+//! ## Protocol versions
+//!
+//! The "hello" message also carries the newest and oldest protocol versions
+//! the connector supports. The acceptor picks the newest version both peers
+//! support and names it in the "hello_back" message. If there is no such
+//! version the acceptor replies with a handshake failure listing its own
+//! versions and both peers fail the handshake with a
+//! [`HandshakeError::ProtocolVersionMismatch`]. Peers from before versions
+//! were exchanged send neither and are treated as speaking version 1.
+//!
 //! # Usage
 //!
 //! 1. Initialize a handshake using either `Handshake::connect()` or `Handshake::accept()`
@@ -56,7 +75,10 @@ use crate::{
     Audience, PeerId, UnixTimestamp,
 };
 
-use super::{connection::Connection, OutboundMessage, StreamMessage};
+use super::{
+    connection::Connection, error::HandshakeError, protocol_version::ProtocolVersions,
+    OutboundMessage, StreamCodec, StreamMessage,
+};
 
 mod connecting;
 pub(crate) use connecting::Connecting;
@@ -69,9 +91,11 @@ pub(crate) use step::Step;
 pub struct Handshake {
     state: HandshakeState,
     remote_offset: OffsetSeconds,
+    codecs: Vec<StreamCodec>,
+    versions: ProtocolVersions,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub(crate) enum HandshakeState {
     AwaitingHello { receive_audience: Option<Audience> },
     AwaitingHelloBack { remote_audience: Audience },
@@ -81,25 +105,70 @@ impl Handshake {
     pub(crate) fn connect(
         now: UnixTimestamp,
         remote_audience: Audience,
+        codecs: Vec<StreamCodec>,
+        versions: ProtocolVersions,
     ) -> (Handshake, OutboundMessage) {
+        let hello = StreamMessage::Hello {
+            codecs: codecs.clone(),
+            versions: Some(versions),
+        };
         (
             Handshake {
                 state: HandshakeState::AwaitingHelloBack { remote_audience },
                 remote_offset: OffsetSeconds(0),
+                codecs,
+                versions,
             },
             OutboundMessage::Signed(crate::auth::Message::new(
                 now,
                 OffsetSeconds(0),
                 remote_audience,
-                StreamMessage::Hello.encode(),
+                hello.encode(),
+            )),
+        )
+    }
+
+    // Start a new handshake on an established connection, addressed to the
+    // peer on the other end and allowing for the clock skew we already know
+    // about
+    pub(crate) fn restart(
+        now: UnixTimestamp,
+        their_peer_id: PeerId,
+        remote_offset: OffsetSeconds,
+        codecs: Vec<StreamCodec>,
+        versions: ProtocolVersions,
+    ) -> (Handshake, OutboundMessage) {
+        let remote_audience = Audience::peer(&their_peer_id);
+        let hello = StreamMessage::Hello {
+            codecs: codecs.clone(),
+            versions: Some(versions),
+        };
+        (
+            Handshake {
+                state: HandshakeState::AwaitingHelloBack { remote_audience },
+                remote_offset,
+                codecs,
+                versions,
+            },
+            OutboundMessage::Signed(crate::auth::Message::new(
+                now,
+                remote_offset,
+                remote_audience,
+                hello.encode(),
             )),
         )
     }
 
-    pub(crate) fn accept(receive_audience: Option<Audience>) -> Handshake {
+    pub(crate) fn accept(
+        receive_audience: Option<Audience>,
+        codecs: Vec<StreamCodec>,
+        versions: ProtocolVersions,
+    ) -> Handshake {
         Handshake {
             state: HandshakeState::AwaitingHello { receive_audience },
             remote_offset: OffsetSeconds(0),
+            codecs,
+            versions,
         }
     }
 
@@ -123,7 +192,10 @@ impl Handshake {
             Err(e) => {
                 tracing::warn!(err=?e, "invalid message");
                 return Step {
-                    state: Connecting::Failed(format!("invalid message received: {}", e)),
+                    state: Connecting::Failed(HandshakeError::Other(format!(
+                        "invalid message received: {}",
+                        e
+                    ))),
                     next_msg: Some(OutboundMessage::Unsigned("invalid message".encode())),
                 };
             }
@@ -167,66 +239,64 @@ impl Handshake {
                     Err(_) => "invalid message".to_string(),
                 };
                 return Step {
-                    state: Connecting::Failed(error),
+                    state: Connecting::Failed(HandshakeError::Other(error)),
                     next_msg: None,
                 };
             }
         };
 
         match (payload.content, self.state) {
-            (StreamMessage::Hello, HandshakeState::AwaitingHello { .. }) => {
+            (StreamMessage::Hello { codecs, versions }, HandshakeState::AwaitingHello { .. }) => {
                 tracing::trace!("received hello whilst waiting for hello");
-                let their_peer_id = crate::PeerId::from(payload.from);
-                let remote_audience = Audience::peer(&their_peer_id);
-                let msg = auth::send(
-                    now,
-                    self.remote_offset,
-                    remote_audience,
-                    StreamMessage::HelloBack.encode(),
-                );
-                Step {
-                    state: Connecting::Complete(Box::new(Connection::new_accepting(
-                        their_peer_id,
-                        self.remote_offset,
-                    ))),
-                    next_msg: Some(OutboundMessage::Signed(msg)),
-                }
+                self.reply_to_hello(now, payload.from.into(), &codecs, versions)
             }
             (
-                StreamMessage::HelloBack,
+                StreamMessage::HelloBack { codec, version },
                 HandshakeState::AwaitingHelloBack { remote_audience: _ },
             ) => {
                 tracing::trace!("received helloback whilst waiting for helloback");
+                if let Some(codec) = codec {
+                    if !self.codecs.contains(&codec) {
+                        return Step {
+                            state: Connecting::Failed(HandshakeError::Other(format!(
+                                "remote chose unsupported codec {}",
+                                codec
+                            ))),
+                            next_msg: None,
+                        };
+                    }
+                }
+                let version = version.unwrap_or(ProtocolVersions::unversioned().current);
+                if !self.versions.supports(version) {
+                    return Step {
+                        state: Connecting::Failed(HandshakeError::ProtocolVersionMismatch {
+                            local: self.versions.current,
+                            remote: version,
+                        }),
+                        next_msg: None,
+                    };
+                }
                 let their_peer_id = crate::PeerId::from(payload.from);
                 Step {
                     state: Connecting::Complete(Box::new(Connection::new_connecting(
                         their_peer_id,
                         self.remote_offset,
+                        codec,
+                        version,
                     ))),
                     next_msg: None,
                 }
             }
-            (StreamMessage::Hello, HandshakeState::AwaitingHelloBack { remote_audience }) => {
+            (
+                StreamMessage::Hello { codecs, versions },
+                HandshakeState::AwaitingHelloBack { remote_audience },
+            ) => {
                 tracing::warn!("received hello message whilst waiting for hello back");
                 // if we received a hello whilst waiting for a hello back then
                 // probably both peers are attempting to connect. We choose the
                 // peer with the lowest peer id to be the server
                 if our_peer_id.as_key().as_bytes() < payload.from.as_bytes() {
-                    let their_peer_id = payload.from.into();
-                    let remote_audience = Audience::peer(&their_peer_id);
-                    let msg = auth::send(
-                        now,
-                        self.remote_offset,
-                        remote_audience,
-                        StreamMessage::HelloBack.encode(),
-                    );
-                    Step {
-                        state: Connecting::Complete(Box::new(Connection::new_accepting(
-                            their_peer_id,
-                            self.remote_offset,
-                        ))),
-                        next_msg: Some(OutboundMessage::Signed(msg)),
-                    }
+                    self.reply_to_hello(now, payload.from.into(), &codecs, versions)
                 } else {
                     Step {
                         state: Connecting::Handshaking(Self {
@@ -250,7 +320,11 @@ impl Handshake {
                             now,
                             self.remote_offset,
                             remote_audience,
-                            StreamMessage::Hello.encode(),
+                            StreamMessage::Hello {
+                                codecs: self.codecs.clone(),
+                                versions: Some(self.versions),
+                            }
+                            .encode(),
                         );
                         Step {
                             state: Connecting::Handshaking(Handshake {
@@ -260,8 +334,15 @@ impl Handshake {
                             next_msg: Some(OutboundMessage::Signed(msg)),
                         }
                     }
+                    (HandshakeFailure::IncompatibleVersion { receivers_versions }, _) => Step {
+                        state: Connecting::Failed(HandshakeError::ProtocolVersionMismatch {
+                            local: self.versions.current,
+                            remote: receivers_versions.current,
+                        }),
+                        next_msg: None,
+                    },
                     (f, _) => Step {
-                        state: Connecting::Failed(f.to_string()),
+                        state: Connecting::Failed(HandshakeError::Other(f.to_string())),
                         next_msg: None,
                     },
                 }
@@ -269,7 +350,9 @@ impl Handshake {
             (other_msg, other_state) => {
                 tracing::warn!(msg=?other_msg, state=?other_state, "invalid msg for handshake state");
                 Step {
-                    state: Connecting::Failed("invalid msg for handshake state".to_string()),
+                    state: Connecting::Failed(HandshakeError::Other(
+                        "invalid msg for handshake state".to_string(),
+                    )),
                     next_msg: None,
                 }
             }
@@ -277,6 +360,73 @@ impl Handshake {
     }
 }
 
+impl Handshake {
+    // Answer a hello from the remote, becoming the acceptor of the stream
+    fn reply_to_hello(
+        &self,
+        now: UnixTimestamp,
+        their_peer_id: PeerId,
+        codecs: &[StreamCodec],
+        versions: Option<ProtocolVersions>,
+    ) -> Step {
+        let remote_audience = Audience::peer(&their_peer_id);
+        let remote_versions = versions.unwrap_or_else(ProtocolVersions::unversioned);
+        let Some(version) = self.versions.negotiate(&remote_versions) else {
+            tracing::debug!(ours=?self.versions, theirs=?remote_versions, "no common protocol version");
+            // Peers which don't exchange versions wouldn't understand the
+            // failure, so they get a plain error instead
+            let next_msg = if versions.is_some() {
+                OutboundMessage::Signed(auth::send(
+                    now,
+                    self.remote_offset,
+                    remote_audience,
+                    StreamMessage::HandshakeFailure(HandshakeFailure::IncompatibleVersion {
+                        receivers_versions: self.versions,
+                    })
+                    .encode(),
+                ))
+            } else {
+                OutboundMessage::Unsigned("incompatible protocol version".encode())
+            };
+            return Step {
+                state: Connecting::Failed(HandshakeError::ProtocolVersionMismatch {
+                    local: self.versions.current,
+                    remote: remote_versions.current,
+                }),
+                next_msg: Some(next_msg),
+            };
+        };
+        let codec = choose_codec(&self.codecs, codecs);
+        let msg = auth::send(
+            now,
+            self.remote_offset,
+            remote_audience,
+            StreamMessage::HelloBack {
+                codec,
+                version: versions.map(|_| version),
+            }
+            .encode(),
+        );
+        Step {
+            state: Connecting::Complete(Box::new(Connection::new_accepting(
+                their_peer_id,
+                self.remote_offset,
+                codec,
+                version,
+            ))),
+            next_msg: Some(OutboundMessage::Signed(msg)),
+        }
+    }
+}
+
+// The first of the codecs offered by the remote which we also support
+fn choose_codec(supported: &[StreamCodec], offered: &[StreamCodec]) -> Option<StreamCodec> {
+    offered
+        .iter()
+        .find(|codec| supported.contains(codec))
+        .copied()
+}
+
 #[cfg(test)]
 mod tests {
     use std::{collections::VecDeque, future::Future, time::Duration};
@@ -294,12 +444,16 @@ mod tests {
         Audience, PeerId, Signer, UnixTimestamp,
     };
 
-    use super::{Connecting, Connection, Handshake, Step};
+    use super::{
+        Connecting, Connection, Handshake, HandshakeError, ProtocolVersions, Step, StreamCodec,
+        StreamMessage,
+    };
 
     struct TestCtx {
         our_peer_id: PeerId,
         clock: UnixTimestamp,
         signer: Signer,
+        versions: ProtocolVersions,
     }
 
     impl TestCtx {
@@ -308,7 +462,16 @@ mod tests {
         }
 
         fn begin_connect(&mut self, remote_audience: Audience) -> Step {
-            let (state, msg) = Handshake::connect(self.clock, remote_audience);
+            self.begin_connect_with_codecs(remote_audience, Vec::new())
+        }
+
+        fn begin_connect_with_codecs(
+            &mut self,
+            remote_audience: Audience,
+            codecs: Vec<StreamCodec>,
+        ) -> Step {
+            let (state, msg) =
+                Handshake::connect(self.clock, remote_audience, codecs, self.versions);
             Step {
                 state: Connecting::Handshaking(state),
                 next_msg: Some(msg),
@@ -316,7 +479,15 @@ mod tests {
         }
 
         fn begin_accept(&mut self, receive_audience: Option<Audience>) -> Step {
-            let state = Handshake::accept(receive_audience);
+            self.begin_accept_with_codecs(receive_audience, Vec::new())
+        }
+
+        fn begin_accept_with_codecs(
+            &mut self,
+            receive_audience: Option<Audience>,
+            codecs: Vec<StreamCodec>,
+        ) -> Step {
+            let state = Handshake::accept(receive_audience, codecs, self.versions);
             Step {
                 state: Connecting::Handshaking(state),
                 next_msg: None,
@@ -329,6 +500,7 @@ mod tests {
                 our_peer_id,
                 clock,
                 signer,
+                versions: ProtocolVersions::ours(),
             }
         }
     }
@@ -420,7 +592,9 @@ mod tests {
                 .unwrap_err();
             assert_eq!(
                 e,
-                ConnectError::RightFailed("authentication failed".to_string())
+                ConnectError::RightFailed(HandshakeError::Other(
+                    "authentication failed".to_string()
+                ))
             );
         })
     }
@@ -495,6 +669,138 @@ mod tests {
         }
     }
 
+    #[test]
+    fn codec_is_negotiated_when_both_peers_support_it() {
+        init_logging();
+        run(|mut computers| async move {
+            let left = computers
+                .left_state
+                .begin_accept_with_codecs(None, vec![StreamCodec::Deflate]);
+            let right = computers.right_state.begin_connect_with_codecs(
+                Audience::peer(&computers.left_state.our_peer_id),
+                vec![StreamCodec::Deflate],
+            );
+
+            let Connected { left, right } =
+                computers.run_until_connected(left, right).await.unwrap();
+            assert_eq!(left.codec(), Some(StreamCodec::Deflate));
+            assert_eq!(right.codec(), Some(StreamCodec::Deflate));
+        })
+    }
+
+    #[test]
+    fn codec_falls_back_to_uncompressed() {
+        init_logging();
+        run(|mut computers| async move {
+            let left = computers.left_state.begin_accept(None);
+            let right = computers.right_state.begin_connect_with_codecs(
+                Audience::peer(&computers.left_state.our_peer_id),
+                vec![StreamCodec::Deflate],
+            );
+
+            let Connected { left, right } =
+                computers.run_until_connected(left, right).await.unwrap();
+            assert_eq!(left.codec(), None);
+            assert_eq!(right.codec(), None);
+        })
+    }
+
+    #[test]
+    fn older_peer_negotiates_down_to_common_version() {
+        init_logging();
+        run(|mut computers| async move {
+            computers.left_state.versions = ProtocolVersions {
+                current: 3,
+                oldest: 1,
+            };
+            computers.right_state.versions = ProtocolVersions {
+                current: 2,
+                oldest: 1,
+            };
+            let left = computers.left_state.begin_accept(None);
+            let right = computers
+                .right_state
+                .begin_connect(Audience::peer(&computers.left_state.our_peer_id));
+
+            let Connected { left, right } =
+                computers.run_until_connected(left, right).await.unwrap();
+            assert_eq!(left.protocol_version(), 2);
+            assert_eq!(right.protocol_version(), 2);
+        })
+    }
+
+    #[test]
+    fn incompatible_versions_fail_on_both_sides() {
+        init_logging();
+        run(|mut computers| async move {
+            computers.left_state.versions = ProtocolVersions {
+                current: 4,
+                oldest: 3,
+            };
+            computers.right_state.versions = ProtocolVersions {
+                current: 2,
+                oldest: 1,
+            };
+            let left = computers.left_state.begin_accept(None);
+            let right = computers
+                .right_state
+                .begin_connect(Audience::peer(&computers.left_state.our_peer_id));
+
+            let e = computers
+                .run_until_connected(left, right)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                e,
+                ConnectError::BothFailed(
+                    HandshakeError::ProtocolVersionMismatch {
+                        local: 4,
+                        remote: 2
+                    },
+                    HandshakeError::ProtocolVersionMismatch {
+                        local: 2,
+                        remote: 4
+                    },
+                )
+            );
+        })
+    }
+
+    #[test]
+    fn unversioned_peer_speaks_version_one() {
+        init_logging();
+        run(|mut computers| async move {
+            let left = computers.left_state.begin_accept(None);
+
+            // A hello from before versions were exchanged
+            let remote_audience = Audience::peer(&computers.left_state.our_peer_id);
+            let (handshake, _) = Handshake::connect(
+                computers.right_state.clock,
+                remote_audience,
+                Vec::new(),
+                ProtocolVersions::unversioned(),
+            );
+            let hello = StreamMessage::Hello {
+                codecs: Vec::new(),
+                versions: None,
+            };
+            let right = Step {
+                state: Connecting::Handshaking(handshake),
+                next_msg: Some(super::OutboundMessage::Signed(crate::auth::Message::new(
+                    computers.right_state.clock,
+                    crate::auth::offset_seconds::OffsetSeconds(0),
+                    remote_audience,
+                    hello.encode(),
+                ))),
+            };
+
+            let Connected { left, right } =
+                computers.run_until_connected(left, right).await.unwrap();
+            assert_eq!(left.protocol_version(), 1);
+            assert_eq!(right.protocol_version(), 1);
+        })
+    }
+
     fn init_logging() {
         let _ = tracing_subscriber::fmt::fmt()
             // .fmt_fields(GLOBAL_REWRITER.clone())
@@ -507,9 +813,10 @@ mod tests {
     #[derive(Debug, PartialEq, Eq)]
     enum ConnectError {
         LeftErr(crate::network::streams::connection::error::Receive),
-        LeftFailed(String),
+        LeftFailed(HandshakeError),
         RightErr(crate::network::streams::connection::error::Receive),
-        RightFailed(String),
+        RightFailed(HandshakeError),
+        BothFailed(HandshakeError, HandshakeError),
     }
 
     #[derive(Debug)]
@@ -601,9 +908,10 @@ mod tests {
                             right: *right,
                         })
                     }
-                    (Connecting::Failed(f), _) => {
-                        return Err(ConnectError::LeftFailed(f.to_string()))
+                    (Connecting::Failed(l), Connecting::Failed(r)) => {
+                        return Err(ConnectError::BothFailed(l, r))
                     }
+                    (Connecting::Failed(f), _) => return Err(ConnectError::LeftFailed(f)),
                     (_, Connecting::Failed(f)) => return Err(ConnectError::RightFailed(f)),
                     // Annoying thing to make the borrow checker happy
                     (next_left, next_right) => {
@@ -1,4 +1,4 @@
-use crate::streams::connection::Connection;
+use crate::streams::{connection::Connection, error::HandshakeError};
 
 use super::Handshake;
 
@@ -10,5 +10,5 @@ pub(crate) enum Connecting {
     /// Finished successfully
     Complete(Box<Connection>),
     /// Failed for the given reason
-    Failed(String),
+    Failed(HandshakeError),
 }
@@ -1,4 +1,5 @@
 use crate::{
+    network::streams::protocol_version::ProtocolVersions,
     parse::{self, Parse},
     serialization::Encode,
     UnixTimestamp,
@@ -8,7 +9,12 @@ use crate::{
 #[cfg_attr(test, derive(arbitrary::Arbitrary))]
 pub(crate) enum HandshakeFailure {
     AuthFailed,
-    BadTimestamp { receivers_clock: UnixTimestamp },
+    BadTimestamp {
+        receivers_clock: UnixTimestamp,
+    },
+    IncompatibleVersion {
+        receivers_versions: ProtocolVersions,
+    },
 }
 
 impl std::fmt::Display for HandshakeFailure {
@@ -18,6 +24,9 @@ impl std::fmt::Display for HandshakeFailure {
             Self::BadTimestamp { receivers_clock: _ } => {
                 write!(f, "mismatched clocks")
             }
+            Self::IncompatibleVersion {
+                receivers_versions: _,
+            } => write!(f, "incompatible protocol version"),
         }
     }
 }
@@ -32,6 +41,10 @@ impl Encode for HandshakeFailure {
                 FailureType::BadTimestamp.encode_into(out);
                 receivers_clock.encode_into(out);
             }
+            Self::IncompatibleVersion { receivers_versions } => {
+                FailureType::IncompatibleVersion.encode_into(out);
+                receivers_versions.encode_into(out);
+            }
         }
     }
 }
@@ -47,6 +60,11 @@ impl Parse<'_> for HandshakeFailure {
                         UnixTimestamp::parse_in_ctx("receivers_clock", input)?;
                     Ok((input, Self::BadTimestamp { receivers_clock }))
                 }
+                FailureType::IncompatibleVersion => {
+                    let (input, receivers_versions) =
+                        ProtocolVersions::parse_in_ctx("receivers_versions", input)?;
+                    Ok((input, Self::IncompatibleVersion { receivers_versions }))
+                }
             }
         })
     }
@@ -55,6 +73,7 @@ impl Parse<'_> for HandshakeFailure {
 enum FailureType {
     Auth,
     BadTimestamp,
+    IncompatibleVersion,
 }
 
 impl Encode for FailureType {
@@ -62,6 +81,7 @@ impl Encode for FailureType {
         match self {
             Self::Auth => out.push(0),
             Self::BadTimestamp => out.push(1),
+            Self::IncompatibleVersion => out.push(2),
         }
     }
 }
@@ -72,6 +92,7 @@ impl Parse<'_> for FailureType {
         let result = match tag {
             0 => Self::Auth,
             1 => Self::BadTimestamp,
+            2 => Self::IncompatibleVersion,
             other => return Err(input.error(format!("unknown failure type {}", other))),
         };
         Ok((input, result))
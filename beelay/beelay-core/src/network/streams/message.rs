@@ -1,16 +1,31 @@
 use crate::{
     parse::{self, Parse},
-    serialization::Encode,
+    serialization::{leb128, Encode},
     Request, Response,
 };
 
-use super::{connection::ConnRequestId, handshake::HandshakeFailure};
+use super::{
+    connection::ConnRequestId, handshake::HandshakeFailure, protocol_version::ProtocolVersions,
+    StreamCodec,
+};
 
 #[derive(Debug, PartialEq, Eq)]
 #[cfg_attr(test, derive(arbitrary::Arbitrary))]
 pub(crate) enum StreamMessage {
-    Hello,
-    HelloBack,
+    Hello {
+        // The codecs the sender is willing to use, in order of preference
+        codecs: Vec<StreamCodec>,
+        // The protocol versions the sender supports, `None` for peers from
+        // before versions were exchanged
+        versions: Option<ProtocolVersions>,
+    },
+    HelloBack {
+        // The codec chosen by the acceptor, if any
+        codec: Option<StreamCodec>,
+        // The protocol version chosen by the acceptor, only sent to peers
+        // which sent their versions in the hello
+        version: Option<u8>,
+    },
     HandshakeFailure(HandshakeFailure),
     Request {
         id: ConnRequestId,
@@ -21,6 +36,12 @@ pub(crate) enum StreamMessage {
         resp: Box<Response>,
     },
     Error(String),
+    // A request or response on a stream which negotiated a protocol version
+    // with sequence numbers, see `protocol_version::SEQUENCED`
+    Sequenced {
+        seq: u64,
+        message: Box<StreamMessage>,
+    },
 }
 
 enum MessageType {
@@ -30,6 +51,7 @@ enum MessageType {
     Request,
     Response,
     Error,
+    Sequenced,
 }
 
 impl TryFrom<u8> for MessageType {
@@ -42,6 +64,7 @@ impl TryFrom<u8> for MessageType {
             3 => Ok(MessageType::Request),
             4 => Ok(MessageType::Response),
             5 => Ok(MessageType::Error),
+            6 => Ok(MessageType::Sequenced),
             other => Err(other),
         }
     }
@@ -56,6 +79,7 @@ impl From<MessageType> for u8 {
             MessageType::Request => 3,
             MessageType::Response => 4,
             MessageType::Error => 5,
+            MessageType::Sequenced => 6,
         }
     }
 }
@@ -63,11 +87,35 @@ impl From<MessageType> for u8 {
 impl Encode for StreamMessage {
     fn encode_into(&self, out: &mut Vec<u8>) {
         match self {
-            StreamMessage::Hello => {
+            // Peers which don't support compression ignore any trailing
+            // bytes after the hello and hello back tags, so we only write the
+            // codecs when there are any to negotiate. Likewise peers which
+            // don't exchange versions ignore anything after the codecs.
+            StreamMessage::Hello { codecs, versions } => {
                 out.push(MessageType::Hello.into());
+                if !codecs.is_empty() || versions.is_some() {
+                    codecs.encode_into(out);
+                }
+                if let Some(versions) = versions {
+                    versions.encode_into(out);
+                }
             }
-            StreamMessage::HelloBack => {
+            // A hello back without a version is either empty or a single
+            // codec tag. With a version the codec is written as an option so
+            // that the two can be told apart by length.
+            StreamMessage::HelloBack { codec, version } => {
                 out.push(MessageType::HelloBack.into());
+                match version {
+                    Some(version) => {
+                        codec.encode_into(out);
+                        version.encode_into(out);
+                    }
+                    None => {
+                        if let Some(codec) = codec {
+                            codec.encode_into(out);
+                        }
+                    }
+                }
             }
             StreamMessage::HandshakeFailure(handshake_failure) => {
                 out.push(MessageType::HandshakeFailure.into());
@@ -87,6 +135,11 @@ impl Encode for StreamMessage {
                 out.push(MessageType::Error.into());
                 error.encode_into(out);
             }
+            StreamMessage::Sequenced { seq, message } => {
+                out.push(MessageType::Sequenced.into());
+                leb128::encode_uleb128(out, *seq);
+                message.encode_into(out);
+            }
         }
     }
 }
@@ -100,10 +153,67 @@ impl<'a> Parse<'a> for StreamMessage {
             let message_type = MessageType::try_from(tag)
                 .map_err(|e| input.error(format!("unknown message type tag: {}", e)))?;
             match message_type {
-                MessageType::Hello => {
-                    input.parse_in_ctx("Hello", |input| Ok((input, StreamMessage::Hello)))
+                MessageType::Hello => input.parse_in_ctx("Hello", |input| {
+                    if input.is_empty() {
+                        return Ok((
+                            input,
+                            StreamMessage::Hello {
+                                codecs: Vec::new(),
+                                versions: None,
+                            },
+                        ));
+                    }
+                    let (input, codecs) = StreamCodec::parse_offered(input)?;
+                    if input.is_empty() {
+                        return Ok((
+                            input,
+                            StreamMessage::Hello {
+                                codecs,
+                                versions: None,
+                            },
+                        ));
+                    }
+                    let (input, versions) = ProtocolVersions::parse(input)?;
+                    Ok((
+                        input,
+                        StreamMessage::Hello {
+                            codecs,
+                            versions: Some(versions),
+                        },
+                    ))
+                }),
+                MessageType::HelloBack => {
+                    input.parse_in_ctx("HelloBack", |input| match input.len() {
+                        0 => Ok((
+                            input,
+                            StreamMessage::HelloBack {
+                                codec: None,
+                                version: None,
+                            },
+                        )),
+                        1 => {
+                            let (input, codec) = StreamCodec::parse(input)?;
+                            Ok((
+                                input,
+                                StreamMessage::HelloBack {
+                                    codec: Some(codec),
+                                    version: None,
+                                },
+                            ))
+                        }
+                        _ => {
+                            let (input, codec) = Option::<StreamCodec>::parse(input)?;
+                            let (input, version) = parse::u8(input)?;
+                            Ok((
+                                input,
+                                StreamMessage::HelloBack {
+                                    codec,
+                                    version: Some(version),
+                                },
+                            ))
+                        }
+                    })
                 }
-                MessageType::HelloBack => Ok((input, StreamMessage::HelloBack)),
                 MessageType::HandshakeFailure => input.parse_in_ctx("HandshakeFailure", |input| {
                     let (input, failure) = HandshakeFailure::parse(input)?;
                     Ok((input, StreamMessage::HandshakeFailure(failure)))
@@ -134,6 +244,17 @@ impl<'a> Parse<'a> for StreamMessage {
                     let (input, error) = parse::str(input)?;
                     Ok((input, StreamMessage::Error(error.to_string())))
                 }),
+                MessageType::Sequenced => input.parse_in_ctx("Sequenced", |input| {
+                    let (input, seq) = input.parse_in_ctx("sequence number", leb128::parse)?;
+                    let (input, message) = StreamMessage::parse(input)?;
+                    Ok((
+                        input,
+                        StreamMessage::Sequenced {
+                            seq,
+                            message: Box::new(message),
+                        },
+                    ))
+                }),
             }
         })
     }
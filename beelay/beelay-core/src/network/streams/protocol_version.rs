@@ -0,0 +1,72 @@
+use crate::{
+    parse::{self, Parse},
+    serialization::Encode,
+};
+
+/// The version of the stream protocol this build of beelay speaks
+pub const PROTOCOL_VERSION: u8 = 3;
+
+/// The oldest version of the stream protocol this build of beelay can still
+/// talk to. Peers whose newest version is older than this are rejected during
+/// the handshake.
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// The first protocol version in which requests and responses on an
+/// established stream carry sequence numbers, so that the receiver can put
+/// them back in order if the transport reorders them
+pub(crate) const SEQUENCED: u8 = 3;
+
+// Peers from before versions were exchanged in the handshake don't send one,
+// they speak version 1 and nothing else
+const UNVERSIONED: u8 = 1;
+
+/// The range of protocol versions a peer supports, sent in the handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub(crate) struct ProtocolVersions {
+    pub(crate) current: u8,
+    pub(crate) oldest: u8,
+}
+
+impl ProtocolVersions {
+    pub(crate) fn ours() -> Self {
+        Self {
+            current: PROTOCOL_VERSION,
+            oldest: MIN_PROTOCOL_VERSION,
+        }
+    }
+
+    pub(crate) fn unversioned() -> Self {
+        Self {
+            current: UNVERSIONED,
+            oldest: UNVERSIONED,
+        }
+    }
+
+    // The newest version both we and the remote support, if there is one
+    pub(crate) fn negotiate(&self, remote: &ProtocolVersions) -> Option<u8> {
+        let version = self.current.min(remote.current);
+        (version >= self.oldest && version >= remote.oldest).then_some(version)
+    }
+
+    pub(crate) fn supports(&self, version: u8) -> bool {
+        (self.oldest..=self.current).contains(&version)
+    }
+}
+
+impl Encode for ProtocolVersions {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push(self.current);
+        out.push(self.oldest);
+    }
+}
+
+impl Parse<'_> for ProtocolVersions {
+    fn parse(input: parse::Input<'_>) -> Result<(parse::Input<'_>, Self), parse::ParseError> {
+        input.parse_in_ctx("ProtocolVersions", |input| {
+            let (input, current) = parse::u8(input)?;
+            let (input, oldest) = parse::u8(input)?;
+            Ok((input, Self { current, oldest }))
+        })
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+/// Puts the messages received on a stream back into the order they were sent
+///
+/// The sender tags each message with consecutive sequence numbers. A message
+/// which arrives ahead of a gap is held until the gap is filled, unless the
+/// gap is `window` messages or more, in which case the messages in the gap
+/// are given up on and delivery skips ahead to the oldest held message. A
+/// message more than `MAX_SKIP` past the end of the window isn't skipped to,
+/// a sender which claims to be that far ahead is treated as broken.
+#[derive(Debug)]
+pub(crate) struct ReorderBuffer<T> {
+    next: u64,
+    held: BTreeMap<u64, T>,
+}
+
+/// The outcome of receiving a single message
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Reordered<T> {
+    /// The messages which can now be processed, in the order they were sent
+    pub(crate) ready: Vec<T>,
+    /// Whether some messages were given up on in order to deliver `ready`
+    pub(crate) skipped: bool,
+}
+
+/// How far past the end of the window a message may be and still be skipped
+/// to
+const MAX_SKIP: u64 = 1024;
+
+/// A message which can't be placed in the stream, the stream should be
+/// closed
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum OutOfSequence {
+    /// The message is more than `MAX_SKIP` past the end of the window
+    TooFarAhead,
+    /// The message was the last sequence number there is
+    Exhausted,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            next: 0,
+            held: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn receive(
+        &mut self,
+        seq: u64,
+        msg: T,
+        window: usize,
+    ) -> Result<Reordered<T>, OutOfSequence> {
+        // Either a duplicate or a message from a gap we already gave up on
+        if seq < self.next || self.held.contains_key(&seq) {
+            return Ok(Reordered {
+                ready: Vec::new(),
+                skipped: false,
+            });
+        }
+        let gap = seq - self.next;
+        if gap > (window as u64).saturating_add(MAX_SKIP) {
+            return Err(OutOfSequence::TooFarAhead);
+        }
+        self.held.insert(seq, msg);
+        let skipped = gap > 0 && gap >= window as u64;
+        if skipped {
+            self.next = *self.held.keys().next().expect("we just inserted a message");
+        }
+        let mut ready = Vec::new();
+        while let Some(msg) = self.held.remove(&self.next) {
+            ready.push(msg);
+            self.next = self.next.checked_add(1).ok_or(OutOfSequence::Exhausted)?;
+        }
+        Ok(Reordered { ready, skipped })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutOfSequence, ReorderBuffer, Reordered, MAX_SKIP};
+
+    fn ready(msgs: Vec<u64>) -> Result<Reordered<u64>, OutOfSequence> {
+        Ok(Reordered {
+            ready: msgs,
+            skipped: false,
+        })
+    }
+
+    #[test]
+    fn out_of_order_messages_are_held_until_the_gap_fills() {
+        let mut buffer = ReorderBuffer::new();
+        assert_eq!(buffer.receive(2, 2, 4), ready(vec![]));
+        assert_eq!(buffer.receive(1, 1, 4), ready(vec![]));
+        assert_eq!(buffer.receive(0, 0, 4), ready(vec![0, 1, 2]));
+        assert_eq!(buffer.receive(3, 3, 4), ready(vec![3]));
+        // Duplicates are dropped
+        assert_eq!(buffer.receive(3, 3, 4), ready(vec![]));
+        assert_eq!(buffer.receive(5, 5, 4), ready(vec![]));
+        assert_eq!(buffer.receive(5, 5, 4), ready(vec![]));
+        assert_eq!(buffer.receive(4, 4, 4), ready(vec![4, 5]));
+    }
+
+    #[test]
+    fn gaps_as_wide_as_the_window_are_skipped() {
+        let mut buffer = ReorderBuffer::new();
+        assert_eq!(buffer.receive(2, 2, 3), ready(vec![]));
+        assert_eq!(
+            buffer.receive(4, 4, 3),
+            Ok(Reordered {
+                ready: vec![2],
+                skipped: true,
+            })
+        );
+        // The rest of the held messages are still waiting on 3
+        assert_eq!(buffer.receive(3, 3, 3), ready(vec![3, 4]));
+        // Messages from a gap which was skipped are dropped
+        assert_eq!(buffer.receive(0, 0, 3), ready(vec![]));
+    }
+
+    #[test]
+    fn messages_too_far_ahead_are_rejected() {
+        let mut buffer = ReorderBuffer::new();
+        assert_eq!(
+            buffer.receive(u64::MAX, 0, 3),
+            Err(OutOfSequence::TooFarAhead)
+        );
+        assert_eq!(
+            buffer.receive(3 + MAX_SKIP + 1, 0, 3),
+            Err(OutOfSequence::TooFarAhead)
+        );
+        // The rejected messages didn't move the stream on
+        assert_eq!(buffer.receive(0, 0, 3), ready(vec![0]));
+        assert_eq!(buffer.receive(1, 1, 3), ready(vec![1]));
+        // A message as far ahead as is allowed is still skipped to
+        assert_eq!(
+            buffer.receive(2 + 3 + MAX_SKIP, 2, 3),
+            Ok(Reordered {
+                ready: vec![2],
+                skipped: true,
+            })
+        );
+    }
+
+    #[test]
+    fn the_last_sequence_number_exhausts_the_stream() {
+        let mut buffer = ReorderBuffer {
+            next: u64::MAX - 1,
+            held: Default::default(),
+        };
+        assert_eq!(buffer.receive(u64::MAX - 1, 0, 3), ready(vec![0]));
+        assert_eq!(
+            buffer.receive(u64::MAX, 1, 3),
+            Err(OutOfSequence::Exhausted)
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::HashMap;
+
+use crate::{
+    parse::{self, Parse},
+    serialization::{leb128, Encode},
+    CommitHash, DocumentId, PeerId, UnixTimestamp,
+};
+
+const VERSION: u8 = 0;
+
+/// Captures the state a stream had agreed with its peer as of the last sync
+///
+/// Pass this to `Event::create_stream_with_resumption` when reconnecting to
+/// the same peer to skip the initial sync of the new stream if nothing has
+/// changed locally since the token was captured. Tokens which belong to a
+/// different peer, were captured more than `SYNC_INTERVAL` ago, or describe a
+/// local state we no longer have are ignored and the stream syncs as normal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamResumptionToken {
+    pub(crate) peer_id: PeerId,
+    pub(crate) synced_at: UnixTimestamp,
+    // A digest of the local membership and document states which were
+    // reachable by the peer at `synced_at`
+    pub(crate) local_state: [u8; 32],
+    pub(crate) remote_heads: HashMap<DocumentId, Vec<CommitHash>>,
+}
+
+impl StreamResumptionToken {
+    /// The peer on the other end of the stream this token was captured from
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, error::DecodeResumptionToken> {
+        let (_, token) = Self::parse(parse::Input::new(data))
+            .map_err(|e| error::DecodeResumptionToken(e.to_string()))?;
+        Ok(token)
+    }
+}
+
+impl Encode for StreamResumptionToken {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.push(VERSION);
+        out.extend_from_slice(self.peer_id.as_bytes());
+        self.synced_at.encode_into(out);
+        out.extend_from_slice(&self.local_state);
+        let mut remote_heads = self.remote_heads.iter().collect::<Vec<_>>();
+        remote_heads.sort_by_key(|(doc_id, _)| *doc_id.as_bytes());
+        leb128::encode_uleb128(out, remote_heads.len() as u64);
+        for (doc_id, heads) in remote_heads {
+            doc_id.encode_into(out);
+            heads.encode_into(out);
+        }
+    }
+}
+
+impl Parse<'_> for StreamResumptionToken {
+    fn parse(input: parse::Input<'_>) -> Result<(parse::Input<'_>, Self), parse::ParseError> {
+        input.parse_in_ctx("StreamResumptionToken", |input| {
+            let (input, version) = parse::u8(input)?;
+            if version != VERSION {
+                return Err(input.error(format!("unknown token version {}", version)));
+            }
+            let (input, peer_id) = parse::arr::<32>(input)?;
+            let peer_id =
+                PeerId::try_from(peer_id.as_slice()).map_err(|_| input.error("invalid peer id"))?;
+            let (input, synced_at) = UnixTimestamp::parse_in_ctx("synced_at", input)?;
+            let (input, local_state) = parse::arr::<32>(input)?;
+            let (mut input, num_docs) = leb128::parse(input)?;
+            let mut remote_heads = HashMap::new();
+            for _ in 0..num_docs {
+                let (i, doc_id) = DocumentId::parse_in_ctx("doc_id", input)?;
+                let (i, heads) = Vec::<CommitHash>::parse_in_ctx("heads", i)?;
+                remote_heads.insert(doc_id, heads);
+                input = i;
+            }
+            Ok((
+                input,
+                Self {
+                    peer_id,
+                    synced_at,
+                    local_state,
+                    remote_heads,
+                },
+            ))
+        })
+    }
+}
+
+pub(crate) mod error {
+    #[derive(Debug, thiserror::Error)]
+    #[error("invalid resumption token: {0}")]
+    pub struct DecodeResumptionToken(pub(super) String);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamResumptionToken;
+    use crate::{CommitHash, DocumentId, PeerId, UnixTimestamp};
+
+    #[test]
+    fn resumption_token_roundtrip() {
+        bolero::check!()
+            .with_arbitrary::<(PeerId, u64, [u8; 32], Vec<(DocumentId, Vec<CommitHash>)>)>()
+            .for_each(|(peer_id, synced_at, local_state, remote_heads)| {
+                let token = StreamResumptionToken {
+                    peer_id: *peer_id,
+                    synced_at: UnixTimestamp::from(*synced_at),
+                    local_state: *local_state,
+                    remote_heads: remote_heads.iter().cloned().collect(),
+                };
+                let decoded = StreamResumptionToken::decode(&token.encode()).unwrap();
+                assert_eq!(decoded, token);
+            });
+    }
+}
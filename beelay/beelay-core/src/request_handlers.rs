@@ -1,5 +1,8 @@
+use std::collections::HashSet;
+
 use crate::{
     blob::BlobMeta,
+    commit_meta,
     network::messages::{self, FetchedSedimentree, TreePart, UploadItem},
     sedimentree::{self},
     state::DocUpdateBuilder,
@@ -30,8 +33,7 @@ where
                 tracing::trace!("not authorized to write to doc");
                 return Response::AuthorizationFailed;
             }
-            upload_commits(ctx, from, doc, data).await;
-            Response::UploadCommits
+            serve_upload_commits(ctx, from, doc, data).await
         }
         crate::Request::FetchSedimentree(doc_id) => {
             tracing::debug!(doc=%doc_id, "fetch sedimentree");
@@ -40,8 +42,7 @@ where
                 // TODO: Return an empty response rather than an authorization failure?
                 return Response::AuthorizationFailed;
             }
-            let trees = fetch_sedimentree(ctx, doc_id).await;
-            Response::FetchSedimentree(trees)
+            serve_fetch_sedimentree(ctx, doc_id).await
         }
         crate::Request::FetchBlob { doc_id: _, blob } => {
             tracing::debug!("fetch blob");
@@ -105,10 +106,117 @@ where
 
             Response::UploadCgkaOps
         }
+        messages::Request::AskForAccess {
+            doc_id,
+            desired,
+            contact_card,
+        } => {
+            crate::access_requests::handle_request(&ctx, from, doc_id, desired, &contact_card).await
+        }
+        messages::Request::AccessDecision { doc_id, granted } => {
+            crate::access_requests::handle_decision(&ctx, from, doc_id, granted)
+        }
+        messages::Request::DiscoverDocs => {
+            tracing::debug!("discover docs");
+            Response::DiscoverDocs(discover_docs(&ctx, from).await)
+        }
+        messages::Request::Delegated { .. } => {
+            tracing::debug!("delegated request outside of an endpoint");
+            Response::Error("delegated requests are only supported on endpoints".to_string())
+        }
+        messages::Request::WithCapability { .. } => {
+            tracing::debug!("capability request outside of an endpoint");
+            Response::Error("capability requests are only supported on endpoints".to_string())
+        }
     };
     response
 }
 
+/// Handle a request which a capability has already been checked to allow
+///
+/// Uploads and sedimentree fetches skip the keyhive access checks. The blob
+/// requests a capability can make aren't access checked anyway.
+pub(super) async fn handle_capability_request<R>(
+    ctx: TaskContext<R>,
+    request: Request,
+    from: PeerId,
+) -> Response
+where
+    R: rand::Rng + rand::CryptoRng + Clone + 'static,
+{
+    match request {
+        Request::UploadCommits { doc, data } => serve_upload_commits(ctx, from, doc, data).await,
+        Request::FetchSedimentree(doc_id) => serve_fetch_sedimentree(ctx, doc_id).await,
+        other => handle_request(ctx, None, other, from).await,
+    }
+}
+
+async fn serve_upload_commits<R>(
+    ctx: TaskContext<R>,
+    from: PeerId,
+    doc: DocumentId,
+    data: Vec<UploadItem>,
+) -> Response
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    if ctx.state().docs().is_sync_paused(&doc) {
+        tracing::trace!("ignoring upload to paused doc");
+        return Response::Error("sync of this document is paused".to_string());
+    }
+    if ctx.state().tombstones().contains(&doc) {
+        tracing::trace!("ignoring upload to tombstoned doc");
+        return Response::Error("this document has been tombstoned".to_string());
+    }
+    if ctx.state().seals().contains(&doc) {
+        tracing::trace!("ignoring upload to sealed doc");
+        return Response::Error("this document has been sealed".to_string());
+    }
+    let bytes = data.iter().map(|d| d.blob.len() as u64).sum();
+    if crate::commands::exceeds_size_limit(&ctx, doc, data.len(), bytes) {
+        tracing::trace!("ignoring upload which would exceed the document size limit");
+        return Response::Error("document size limit exceeded".to_string());
+    }
+    upload_commits(ctx, from, doc, data).await;
+    Response::UploadCommits
+}
+
+async fn serve_fetch_sedimentree<R>(ctx: TaskContext<R>, doc_id: DocumentId) -> Response
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    // A tombstoned document has no content to sync, so it is treated
+    // as paused to stop the remote from uploading its copy
+    if ctx.state().docs().is_sync_paused(&doc_id) || ctx.state().tombstones().contains(&doc_id) {
+        return Response::FetchSedimentree(FetchedSedimentree::Paused);
+    }
+    let trees = fetch_sedimentree(ctx, doc_id).await;
+    Response::FetchSedimentree(trees)
+}
+
+// The documents we have which `from` can pull. Paused and tombstoned
+// documents are left out as there would be nothing to sync.
+async fn discover_docs<R>(ctx: &TaskContext<R>, from: PeerId) -> Vec<DocumentId>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let accessible = ctx
+        .state()
+        .keyhive()
+        .docs_accessible_to_agent(from)
+        .await
+        .into_iter()
+        .collect::<HashSet<_>>();
+    accessible
+        .into_iter()
+        .filter(|doc_id| {
+            !ctx.state().docs().is_sync_paused(doc_id)
+                && !ctx.state().tombstones().contains(doc_id)
+                && ctx.state().docs().sedimentree(doc_id).is_some()
+        })
+        .collect()
+}
+
 async fn fetch_sedimentree<R>(ctx: TaskContext<R>, doc_id: DocumentId) -> FetchedSedimentree
 where
     R: rand::Rng + rand::CryptoRng,
@@ -135,7 +243,7 @@ async fn upload_commits<R>(
         async move {
             let blob = BlobMeta::new(&d.blob);
             ctx.storage()
-                .put(StorageKey::blob(blob.hash()), d.blob.clone())
+                .put_doc_blob(&doc, blob.hash(), d.blob.clone())
                 .await;
             if let Some(op) = &d.cgka_op {
                 if let Err(err) = ctx
@@ -154,6 +262,7 @@ async fn upload_commits<R>(
                     sedimentree::storage::write_loose_commit(doc_storage, &(&commit).into())
                         .await
                         .unwrap(); // TODO: return an error
+                    commit_meta::record_commit(&ctx, doc, hash, from_peer).await;
                     Some((CommitOrBundle::Commit(commit), d.cgka_op))
                 }
                 TreePart::Stratum {
@@ -167,6 +276,7 @@ async fn upload_commits<R>(
                     sedimentree::storage::write_stratum(doc_storage, stratum)
                         .await
                         .unwrap(); // TODO: return an error
+                    commit_meta::record_bundle(&ctx, doc, start, end).await;
                     let bundle = CommitBundle::builder()
                         .start(start)
                         .end(end)
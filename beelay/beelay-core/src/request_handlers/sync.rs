@@ -1,10 +1,12 @@
 use crate::{
+    group_metadata,
     network::messages::{
         self,
         session::{SessionMessage, SessionRequest, SessionResponse},
     },
+    seals,
     sync::{sessions::SessionError, MembershipState, ReachableDocs},
-    PeerId, Response, TaskContext,
+    tombstones, PeerId, Response, TaskContext,
 };
 
 pub(crate) async fn handle_sync_request<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
@@ -99,6 +101,34 @@ pub(crate) async fn handle_sync_request_inner<R: rand::Rng + rand::CryptoRng + C
                     .cgka_symbols(&session_id, &doc, count)?;
                 Ok(SessionResponse::FetchCgkaSymbols(symbols))
             }
+            SessionMessage::ExchangeGroupMetadata(records) => {
+                group_metadata::ingest(&ctx, records.clone()).await;
+                // Don't echo back the records the remote just sent us
+                let ours = group_metadata::records_for_peer(&ctx, from)
+                    .await
+                    .into_iter()
+                    .filter(|record| !records.contains(record))
+                    .collect();
+                Ok(SessionResponse::ExchangeGroupMetadata(ours))
+            }
+            SessionMessage::ExchangeTombstones(records) => {
+                tombstones::ingest(&ctx, records.clone()).await;
+                let ours = tombstones::records_for_peer(&ctx, from)
+                    .await
+                    .into_iter()
+                    .filter(|record| !records.contains(record))
+                    .collect();
+                Ok(SessionResponse::ExchangeTombstones(ours))
+            }
+            SessionMessage::ExchangeSeals(records) => {
+                seals::ingest(&ctx, records.clone()).await;
+                let ours = seals::records_for_peer(&ctx, from)
+                    .await
+                    .into_iter()
+                    .filter(|record| !records.contains(record))
+                    .collect();
+                Ok(SessionResponse::ExchangeSeals(ours))
+            }
         },
     }
 }
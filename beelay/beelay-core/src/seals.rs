@@ -0,0 +1,214 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    auth::Signed,
+    commands::error,
+    io::IoHandle,
+    parse::{self, Parse},
+    serialization::Encode,
+    CommitHash, DocumentId, PeerId, StorageKey, TaskContext, UnixTimestamp,
+};
+
+/// A record that a document was sealed, signed by the admin of the document
+/// who sealed it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub(crate) struct SealRecord {
+    pub(crate) doc_id: DocumentId,
+    pub(crate) sealed_at: UnixTimestamp,
+    pub(crate) heads: Vec<CommitHash>,
+}
+
+/// Who sealed a document, when, and what its heads were at the time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seal {
+    pub sealed_by: PeerId,
+    pub sealed_at: UnixTimestamp,
+    pub heads: Vec<CommitHash>,
+}
+
+impl From<&Signed<SealRecord>> for Seal {
+    fn from(record: &Signed<SealRecord>) -> Self {
+        Seal {
+            sealed_by: PeerId::from(record.verifier),
+            sealed_at: record.payload.sealed_at,
+            heads: record.payload.heads.clone(),
+        }
+    }
+}
+
+fn seals_prefix() -> StorageKey {
+    StorageKey::auth().push("seals")
+}
+
+fn seal_key(doc_id: DocumentId) -> StorageKey {
+    seals_prefix().push(doc_id.to_string())
+}
+
+pub(crate) async fn load(io: IoHandle) -> HashMap<DocumentId, Signed<SealRecord>> {
+    let raw = crate::task_context::Storage::new(&io)
+        .load_range(seals_prefix())
+        .await;
+    let mut records = HashMap::new();
+    for (key, value) in raw {
+        let Some(doc_id) = key.name().and_then(|name| DocumentId::from_str(name).ok()) else {
+            tracing::warn!(?key, "failed to parse seal key");
+            continue;
+        };
+        let Ok((_, record)) = Signed::<SealRecord>::parse(parse::Input::new(&value))
+            .inspect_err(|e| tracing::error!(err=?e, "failed to parse stored seal"))
+        else {
+            continue;
+        };
+        if record.payload.doc_id != doc_id || record.verify().is_err() {
+            tracing::error!(?key, "stored seal is invalid");
+            continue;
+        }
+        records.insert(doc_id, record);
+    }
+    records
+}
+
+/// Seal `doc_id` so that no more commits are ever added to it
+///
+/// Only admins of the document can seal it. Sealing a document which is
+/// already sealed returns the existing seal.
+pub(crate) async fn seal<R>(ctx: TaskContext<R>, doc_id: DocumentId) -> Result<Seal, error::SealDoc>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    if let Some(record) = ctx.state().seals().get(&doc_id) {
+        return Ok(Seal::from(&record));
+    }
+    let keyhive = ctx.state().keyhive();
+    if !keyhive.has_doc(&doc_id).await {
+        return Err(error::SealDoc::NoSuchDocument);
+    }
+    if !keyhive.can_admin(ctx.state().our_peer_id(), &doc_id).await {
+        return Err(error::SealDoc::NotAllowed);
+    }
+    let mut heads = ctx
+        .state()
+        .docs()
+        .doc_status(doc_id)
+        .local_heads
+        .unwrap_or_default();
+    heads.sort();
+    let record = SealRecord {
+        doc_id,
+        sealed_at: ctx.now().as_secs(),
+        heads,
+    };
+    let signed = Signed::try_sign(ctx.signer(), record)
+        .await
+        .map_err(|e| error::SealDoc::Signing(e.to_string()))?;
+    let seal = Seal::from(&signed);
+    store(&ctx, signed).await;
+    Ok(seal)
+}
+
+/// Apply seals received from another peer
+///
+/// Seals which are badly signed or whose author isn't an admin of the
+/// document are dropped.
+pub(crate) async fn ingest<R>(ctx: &TaskContext<R>, records: Vec<Signed<SealRecord>>)
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    for record in records {
+        let doc_id = record.payload.doc_id;
+        if ctx.state().seals().contains(&doc_id) {
+            continue;
+        }
+        if record.verify().is_err() {
+            tracing::warn!(%doc_id, "dropping seal with an invalid signature");
+            continue;
+        }
+        if !ctx
+            .state()
+            .keyhive()
+            .can_admin(PeerId::from(record.verifier), &doc_id)
+            .await
+        {
+            tracing::warn!(%doc_id, "dropping seal from a peer who isn't an admin");
+            continue;
+        }
+        store(ctx, record).await;
+    }
+}
+
+/// Whether `doc_id` is sealed and we have all of its content up to the seal,
+/// in which case there is nothing more to fetch from peers
+pub(crate) fn has_sealed_content<R>(ctx: &TaskContext<R>, doc_id: DocumentId) -> bool
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let Some(record) = ctx.state().seals().get(&doc_id) else {
+        return false;
+    };
+    let known = ctx
+        .state()
+        .docs()
+        .sedimentree(&doc_id)
+        .map(|tree| crate::commands::known_commits(&tree))
+        .unwrap_or_default();
+    record.payload.heads.iter().all(|head| known.contains(head))
+}
+
+/// The seals of every document `peer` can pull
+pub(crate) async fn records_for_peer<R>(
+    ctx: &TaskContext<R>,
+    peer: PeerId,
+) -> Vec<Signed<SealRecord>>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let mut records = Vec::new();
+    for record in ctx.state().seals().all() {
+        if ctx
+            .state()
+            .keyhive()
+            .can_pull(peer, &record.payload.doc_id)
+            .await
+        {
+            records.push(record);
+        }
+    }
+    records
+}
+
+async fn store<R>(ctx: &TaskContext<R>, record: Signed<SealRecord>)
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let doc_id = record.payload.doc_id;
+    let encoded = record.encode();
+    ctx.state().seals().insert(record);
+    ctx.storage().put(seal_key(doc_id), encoded).await;
+}
+
+impl Encode for SealRecord {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.doc_id.encode_into(out);
+        self.sealed_at.encode_into(out);
+        self.heads.encode_into(out);
+    }
+}
+
+impl<'a> Parse<'a> for SealRecord {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        input.parse_in_ctx("SealRecord", |input| {
+            let (input, doc_id) = DocumentId::parse(input)?;
+            let (input, sealed_at) = UnixTimestamp::parse(input)?;
+            let (input, heads) = Vec::<CommitHash>::parse(input)?;
+            Ok((
+                input,
+                SealRecord {
+                    doc_id,
+                    sealed_at,
+                    heads,
+                },
+            ))
+        })
+    }
+}
@@ -290,6 +290,14 @@ impl StratumMeta {
         Self { start, end, blob }
     }
 
+    pub(crate) fn start(&self) -> CommitHash {
+        self.start
+    }
+
+    pub(crate) fn end(&self) -> CommitHash {
+        self.end
+    }
+
     pub(crate) fn level(&self) -> Level {
         let start_level = trailing_zeros_in_base(&self.start.as_bytes(), 10);
         let end_level = trailing_zeros_in_base(&self.end.as_bytes(), 10);
@@ -338,6 +346,13 @@ impl Sedimentree {
         self.commits.insert(commit)
     }
 
+    // Returns true if the commit was present
+    pub(crate) fn remove_commit(&mut self, hash: CommitHash) -> bool {
+        let before = self.commits.len();
+        self.commits.retain(|c| c.hash() != hash);
+        self.commits.len() != before
+    }
+
     pub(crate) fn diff<'a>(&'a self, other: &'a Sedimentree) -> Diff<'a> {
         let our_strata = HashSet::<&Stratum>::from_iter(self.strata.iter());
         let their_strata = HashSet::from_iter(other.strata.iter());
@@ -386,11 +401,18 @@ impl Sedimentree {
         self.strata.iter()
     }
 
-    #[allow(dead_code)]
     pub(crate) fn loose_commits(&self) -> impl Iterator<Item = &LooseCommit> {
         self.commits.iter()
     }
 
+    /// The blobs holding the contents of every stratum and loose commit
+    pub(crate) fn blobs(&self) -> impl Iterator<Item = &BlobMeta> {
+        self.strata
+            .iter()
+            .map(|s| s.meta().blob())
+            .chain(self.commits.iter().map(|c| c.blob()))
+    }
+
     pub(crate) fn minimize(&self) -> Sedimentree {
         // First sort strata by level, then for each stratum below the lowest
         // level, discard that stratum if it is supported by any of the stratum
@@ -434,25 +456,108 @@ impl Sedimentree {
         }
     }
 
+    /// The part of this tree which is described by `summary`
+    ///
+    /// Strata and commits in the summary which are not present in this tree
+    /// are ignored.
+    pub(crate) fn restrict_to(&self, summary: &SedimentreeSummary) -> Sedimentree {
+        Sedimentree {
+            strata: self
+                .strata
+                .iter()
+                .filter(|s| summary.strata.contains(&s.meta))
+                .cloned()
+                .collect(),
+            commits: self
+                .commits
+                .intersection(&summary.commits)
+                .cloned()
+                .collect(),
+        }
+    }
+
     pub(crate) fn heads(&self) -> Vec<CommitHash> {
         // The heads of a sedimentree are the end hashes of all strata which are
         // not the start of any other stratum or supported by any lower stratum
-        // and which do not appear in the loose commit graph, plus the heads of
-        // the loose commit graph.
+        // and which do not appear in the loose commit graph or as the parent of
+        // a loose commit, plus the heads of the loose commit graph which are
+        // not followed by the commits in a stratum.
         let minimized = self.minimize();
         let dag = commit_dag::CommitDag::from_commits(minimized.commits.iter());
         let mut heads = Vec::<CommitHash>::new();
         for stratum in minimized.strata.iter() {
             if !minimized.strata.iter().any(|s| s.end() == stratum.start())
                 && !dag.contains_commit(&stratum.end())
+                && !minimized
+                    .commits
+                    .iter()
+                    .any(|c| c.parents().contains(&stratum.end()))
             {
                 heads.push(stratum.end());
             }
         }
-        heads.extend(dag.heads());
+        heads.extend(dag.heads().filter(|head| {
+            !minimized
+                .strata
+                .iter()
+                .any(|s| s.start() == *head || s.checkpoints().contains(head))
+        }));
         heads
     }
 
+    /// Return the subset of this tree which is reachable by walking back from `heads`
+    ///
+    /// Loose commits are followed via their parents. When the walk reaches a
+    /// commit which is the end or a checkpoint of a stratum, the stratum is
+    /// included and the walk continues from the start of the stratum. If any
+    /// of the heads are not present in the tree then the missing head is
+    /// returned as an error.
+    pub(crate) fn ancestors_of(&self, heads: &[CommitHash]) -> Result<Sedimentree, CommitHash> {
+        let covering_strata =
+            |hash: CommitHash| self.strata.iter().filter(move |s| s.supports_block(hash));
+        let loose_commit = |hash: CommitHash| self.commits.iter().find(|c| c.hash() == hash);
+
+        let mut strata = BTreeSet::new();
+        let mut commits = BTreeSet::new();
+        let mut frontier = Vec::new();
+        for head in heads {
+            if loose_commit(*head).is_some() || covering_strata(*head).next().is_some() {
+                frontier.push(*head);
+                continue;
+            }
+            // A head which is only the start of a stratum can't be separated
+            // from the commits which follow it in that stratum
+            let starting = self
+                .strata
+                .iter()
+                .filter(|s| s.start() == *head)
+                .collect::<Vec<_>>();
+            if starting.is_empty() {
+                return Err(*head);
+            }
+            strata.extend(starting.into_iter().cloned());
+            frontier.push(*head);
+        }
+
+        let mut visited = HashSet::new();
+        while let Some(hash) = frontier.pop() {
+            if !visited.insert(hash) {
+                continue;
+            }
+            if let Some(commit) = loose_commit(hash) {
+                commits.insert(commit.clone());
+                frontier.extend(commit.parents().iter().copied());
+            }
+            for stratum in covering_strata(hash) {
+                if strata.insert(stratum.clone()) {
+                    frontier.push(stratum.start());
+                }
+            }
+        }
+
+        Ok(Sedimentree { strata, commits }.minimize())
+    }
+
     pub(crate) fn into_items(self) -> impl Iterator<Item = CommitOrStratum> {
         self.strata
             .into_iter()
@@ -693,6 +798,28 @@ mod tests {
         )
     }
 
+    #[test]
+    fn ancestors_of_follows_strata_chain() {
+        let blob = BlobMeta::new(&[1, 2, 3]);
+        let hash = |n: u8| CommitHash::from([n; 32]);
+        let first = Stratum::new(hash(0), hash(1), vec![], blob);
+        let second = Stratum::new(hash(1), hash(2), vec![], blob);
+        let commit = super::LooseCommit::new(hash(3), vec![hash(2)], blob);
+        let tree =
+            super::Sedimentree::new(vec![first.clone(), second.clone()], vec![commit.clone()]);
+
+        let all = tree.ancestors_of(&[hash(3)]).unwrap();
+        assert_eq!(
+            all,
+            super::Sedimentree::new(vec![first.clone(), second], vec![commit])
+        );
+
+        let older = tree.ancestors_of(&[hash(1)]).unwrap();
+        assert_eq!(older, super::Sedimentree::new(vec![first], vec![]));
+
+        assert_eq!(tree.ancestors_of(&[hash(4)]), Err(hash(4)));
+    }
+
     #[test]
     fn loose_commit_encoding_roundtrip() {
         bolero::check!()
@@ -79,30 +79,28 @@ pub(crate) fn data<S: Storage + Clone>(
 ) -> impl futures::Stream<Item = Result<(CommitOrStratum, Vec<u8>), LoadTreeData>> {
     let items = tree.into_items().map(|item| {
         let storage = storage.clone();
-        async move {
-            match item {
-                super::CommitOrStratum::Commit(c) => {
-                    let data = storage
-                        .load_blob(c.blob().hash())
-                        .await
-                        .map_err(|e| LoadTreeData::Storage(e.to_string()))?
-                        .ok_or_else(|| LoadTreeData::MissingBlob(c.blob().hash()))?;
-                    Ok((CommitOrStratum::Commit(c), data))
-                }
-                super::CommitOrStratum::Stratum(s) => {
-                    let data = storage
-                        .load_blob(s.meta().blob().hash())
-                        .await
-                        .map_err(|e| LoadTreeData::Storage(e.to_string()))?
-                        .ok_or_else(|| LoadTreeData::MissingBlob(s.meta().blob().hash()))?;
-                    Ok((CommitOrStratum::Stratum(s), data))
-                }
-            }
-        }
+        async move { item_data(&storage, item).await }
     });
     futures::stream::FuturesUnordered::from_iter(items)
 }
 
+/// Load the blob referenced by a single commit or stratum
+pub(crate) async fn item_data<S: Storage>(
+    storage: &S,
+    item: CommitOrStratum,
+) -> Result<(CommitOrStratum, Vec<u8>), LoadTreeData> {
+    let blob = match &item {
+        CommitOrStratum::Commit(c) => c.blob().hash(),
+        CommitOrStratum::Stratum(s) => s.meta().blob().hash(),
+    };
+    let data = storage
+        .load_blob(blob)
+        .await
+        .map_err(|e| LoadTreeData::Storage(e.to_string()))?
+        .ok_or(LoadTreeData::MissingBlob(blob))?;
+    Ok((item, data))
+}
+
 pub(crate) async fn write_loose_commit<S: Storage>(
     storage: S,
     commit: &LooseCommit,
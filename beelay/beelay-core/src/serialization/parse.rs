@@ -72,7 +72,12 @@ impl<'a> Input<'a> {
     }
 
     pub(crate) fn is_empty(&self) -> bool {
-        self.offset >= self.data.len()
+        self.data.is_empty()
+    }
+
+    /// The number of bytes left to parse
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
     }
 }
 
@@ -13,19 +13,42 @@ use keyhive_core::{
     store::ciphertext::memory::MemoryCiphertextStore,
 };
 
+mod access_changes;
+pub(crate) use access_changes::AccessChanges;
+mod access_requests;
+pub(crate) use access_requests::AccessRequests;
+mod capabilities;
+pub(crate) use capabilities::Capabilities;
+mod commit_chunks;
+pub(crate) use commit_chunks::CommitChunks;
+mod deadlines;
+pub(crate) use deadlines::Deadlines;
 mod docs;
 pub(crate) use docs::{DocUpdateBuilder, Docs};
 mod endpoints;
 pub(crate) use endpoints::Endpoints;
+mod exports;
+pub(crate) use exports::Exports;
+mod group_metadata;
+pub(crate) use group_metadata::GroupMetadataRecords;
 pub(crate) mod keyhive;
+mod membership_expiries;
+pub(crate) use membership_expiries::MembershipExpiries;
+mod seals;
+pub(crate) use seals::Seals;
 mod sessions;
 pub(crate) use sessions::Sessions;
 mod streams;
 pub(crate) use streams::Streams;
+mod tombstones;
+pub(crate) use tombstones::Tombstones;
+mod warm_docs;
+pub(crate) use warm_docs::WarmDocs;
 
 use crate::{
     auth, doc_state::DocState, io::Signer, network::endpoint, streams::UnsignedStreamEvent, sync,
-    CommitHash, DocumentId, EndpointId, OutboundRequestId, PeerId, StreamId,
+    BundlingPolicy, CommitHash, DocSizeLimit, DocumentId, EndpointId, OutboundRequestId, PeerId,
+    StreamId, UnixTimestamp, UnixTimestampMillis,
 };
 
 pub(crate) struct State<R: rand::Rng + rand::CryptoRng> {
@@ -38,6 +61,64 @@ pub(crate) struct State<R: rand::Rng + rand::CryptoRng> {
     rng: Rc<RefCell<R>>,
     sync_sessions: sync::Sessions,
     our_peer_id: PeerId,
+    // Memberships which should be revoked once the given time has passed,
+    // keyed by (group or document, member)
+    membership_expiries: HashMap<(PeerId, PeerId), UnixTimestamp>,
+    // The latest metadata record we know of for each group
+    group_metadata: HashMap<PeerId, auth::Signed<crate::group_metadata::GroupMetadataRecord>>,
+    // Documents whose content has been purged with `tombstone_doc`
+    tombstones: HashMap<DocumentId, auth::Signed<crate::tombstones::TombstoneRecord>>,
+    // Documents which have been sealed with `seal_doc`
+    seals: HashMap<DocumentId, auth::Signed<crate::seals::SealRecord>>,
+    // Capabilities we have minted which haven't been revoked
+    capabilities: HashMap<
+        crate::capabilities::CapabilityId,
+        auth::Signed<crate::capabilities::CapabilityRecord>,
+    >,
+    // Commits which are being added a chunk at a time
+    commit_chunks: HashMap<(DocumentId, CommitHash), commit_chunks::PendingCommit>,
+    // Documents which should not be synced with anyone until they are resumed
+    paused_docs: HashSet<DocumentId>,
+    // Documents whose sync priority isn't `SyncPriority::Normal`
+    sync_priorities: HashMap<DocumentId, crate::doc_status::SyncPriority>,
+    // Ephemeral documents created before we were loaded, whose commits were
+    // never stored
+    vanished_docs: HashSet<DocumentId>,
+    // Documents subscribed to with `subscribe_doc` and the heads we last
+    // reported for them
+    subscribed_docs: HashMap<DocumentId, Option<Vec<CommitHash>>>,
+    // Subscribed documents whose sedimentree has changed since we last
+    // checked their heads
+    subscribed_docs_changed: HashSet<DocumentId>,
+    // Documents with more than one head which we have reported as diverged
+    diverged_docs: HashSet<DocumentId>,
+    // Documents whose sedimentree has changed since we last checked whether
+    // they have diverged
+    divergence_unchecked: HashSet<DocumentId>,
+    // Access requests made by other peers for documents we administer and the
+    // access each requester asked for
+    incoming_access_requests: HashMap<(DocumentId, PeerId), crate::keyhive::MemberAccess>,
+    // Documents we have requested access to and the peers we asked
+    outgoing_access_requests: HashMap<DocumentId, HashSet<PeerId>>,
+    // The access of every member of every document as last reported to
+    // `subscribe_access_changes`, `None` if nobody is subscribed
+    access_snapshot: Option<HashMap<DocumentId, HashMap<PeerId, crate::keyhive::MemberAccess>>>,
+    // Whether keyhive has emitted events since we last compared access
+    // against `access_snapshot`
+    access_unchecked: bool,
+    // Timers which commands are waiting on, fired by `Event::tick`
+    deadlines: Vec<(UnixTimestampMillis, futures::channel::oneshot::Sender<()>)>,
+    // Exports started with `begin_export` which haven't been read to the end
+    exports: HashMap<crate::commands::ExportHandle, exports::Export>,
+    // Decrypted contents of the documents warmed with `warm_docs`
+    warm_docs: HashMap<DocumentId, warm_docs::WarmDoc>,
+    // Held while creating a document with an idempotency key so that two
+    // concurrent creates with the same key can't both miss
+    idempotent_creates: Rc<futures::lock::Mutex<()>>,
+    bundling_policy: BundlingPolicy,
+    doc_size_limit: DocSizeLimit,
+    // Whether `Config::storage_namespace` was set
+    storage_namespaced: bool,
 }
 
 /// The Beelay-visible Keyhive
@@ -51,12 +132,27 @@ type Beehive<R> = Keyhive<
 >;
 
 impl<R: rand::Rng + rand::CryptoRng> State<R> {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         rng: R,
         signer: Signer,
         keyhive: Beehive<R>,
         docs: HashMap<DocumentId, DocState>,
+        membership_expiries: HashMap<(PeerId, PeerId), UnixTimestamp>,
+        group_metadata: HashMap<PeerId, auth::Signed<crate::group_metadata::GroupMetadataRecord>>,
+        tombstones: HashMap<DocumentId, auth::Signed<crate::tombstones::TombstoneRecord>>,
+        seals: HashMap<DocumentId, auth::Signed<crate::seals::SealRecord>>,
+        capabilities: HashMap<
+            crate::capabilities::CapabilityId,
+            auth::Signed<crate::capabilities::CapabilityRecord>,
+        >,
+        vanished_docs: HashSet<DocumentId>,
+        storage_namespaced: bool,
         session_duration: Duration,
+        bundling_policy: BundlingPolicy,
+        doc_size_limit: DocSizeLimit,
+        request_timeout: Option<Duration>,
+        stream_reorder_window: usize,
         tx_outbound_stream_msgs: mpsc::UnboundedSender<(StreamId, UnsignedStreamEvent)>,
         tx_outbound_endpoint_msgs: mpsc::UnboundedSender<(
             EndpointId,
@@ -72,10 +168,38 @@ impl<R: rand::Rng + rand::CryptoRng> State<R> {
             docs,
             docs_with_changes: HashSet::new(),
             keyhive: Rc::new(futures::lock::Mutex::new(keyhive)),
-            streams: crate::streams::Streams::new(tx_outbound_stream_msgs, verifying_key),
-            endpoints: endpoint::Endpoints::new(tx_outbound_endpoint_msgs),
+            streams: crate::streams::Streams::new(
+                tx_outbound_stream_msgs,
+                verifying_key,
+                stream_reorder_window,
+            ),
+            endpoints: endpoint::Endpoints::new(tx_outbound_endpoint_msgs, request_timeout),
             rng: Rc::new(RefCell::new(rng)),
             sync_sessions: sync::Sessions::new(session_duration),
+            membership_expiries,
+            group_metadata,
+            tombstones,
+            seals,
+            capabilities,
+            commit_chunks: HashMap::new(),
+            paused_docs: HashSet::new(),
+            sync_priorities: HashMap::new(),
+            vanished_docs,
+            subscribed_docs: HashMap::new(),
+            subscribed_docs_changed: HashSet::new(),
+            diverged_docs: HashSet::new(),
+            divergence_unchecked: HashSet::new(),
+            incoming_access_requests: HashMap::new(),
+            outgoing_access_requests: HashMap::new(),
+            access_snapshot: None,
+            access_unchecked: false,
+            deadlines: Vec::new(),
+            exports: HashMap::new(),
+            warm_docs: HashMap::new(),
+            idempotent_creates: Rc::new(futures::lock::Mutex::new(())),
+            bundling_policy,
+            doc_size_limit,
+            storage_namespaced,
         }
     }
 
@@ -111,9 +235,88 @@ impl<'a, R: rand::Rng + rand::CryptoRng> StateAccessor<'a, R> {
         Endpoints::new(self.0.clone())
     }
 
+    pub(crate) fn membership_expiries(&self) -> MembershipExpiries<'a, R> {
+        MembershipExpiries::new(self.0.clone())
+    }
+
+    pub(crate) fn group_metadata(&self) -> GroupMetadataRecords<'a, R> {
+        GroupMetadataRecords::new(self.0.clone())
+    }
+
+    pub(crate) fn tombstones(&self) -> Tombstones<'a, R> {
+        Tombstones::new(self.0.clone())
+    }
+
+    pub(crate) fn seals(&self) -> Seals<'a, R> {
+        Seals::new(self.0.clone())
+    }
+
+    pub(crate) fn capabilities(&self) -> Capabilities<'a, R> {
+        Capabilities::new(self.0.clone())
+    }
+
+    pub(crate) fn commit_chunks(&self) -> CommitChunks<'a, R> {
+        CommitChunks::new(self.0.clone())
+    }
+
+    pub(crate) fn access_requests(&self) -> AccessRequests<'a, R> {
+        AccessRequests::new(self.0.clone())
+    }
+
+    pub(crate) fn access_changes(&self) -> AccessChanges<'a, R> {
+        AccessChanges::new(self.0.clone())
+    }
+
+    pub(crate) fn deadlines(&self) -> Deadlines<'a, R> {
+        Deadlines::new(self.0.clone())
+    }
+
+    pub(crate) fn exports(&self) -> Exports<'a, R> {
+        Exports::new(self.0.clone())
+    }
+
+    pub(crate) fn warm_docs(&self) -> WarmDocs<'a, R> {
+        WarmDocs::new(self.0.clone())
+    }
+
     pub(crate) fn our_peer_id(&self) -> PeerId {
         self.0.borrow().our_peer_id
     }
+
+    pub(crate) fn random_bytes<const N: usize>(&self) -> [u8; N] {
+        let rng = RefCell::borrow(&self.0).rng.clone();
+        let mut bytes = [0; N];
+        rng.borrow_mut().fill_bytes(&mut bytes);
+        bytes
+    }
+
+    pub(crate) fn idempotent_creates(&self) -> Rc<futures::lock::Mutex<()>> {
+        self.0.borrow().idempotent_creates.clone()
+    }
+
+    pub(crate) fn bundling_policy(&self) -> BundlingPolicy {
+        self.0.borrow().bundling_policy
+    }
+
+    pub(crate) fn set_bundling_policy(&self, policy: BundlingPolicy) {
+        self.0.borrow_mut().bundling_policy = policy;
+    }
+
+    pub(crate) fn doc_size_limit(&self) -> DocSizeLimit {
+        self.0.borrow().doc_size_limit
+    }
+
+    pub(crate) fn set_doc_size_limit(&self, limit: DocSizeLimit) {
+        self.0.borrow_mut().doc_size_limit = limit;
+    }
+
+    pub(crate) fn storage_namespaced(&self) -> bool {
+        self.0.borrow().storage_namespaced
+    }
+
+    pub(crate) fn awaiting_responses(&self, stream_id: StreamId) -> usize {
+        self.0.borrow().streams.awaiting_responses(stream_id)
+    }
 }
 
 impl<'a, R: rand::Rng + rand::CryptoRng + Clone + 'static> StateAccessor<'a, R> {
@@ -0,0 +1,92 @@
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{doc_status::DocEvent, keyhive::MemberAccess, DocumentId, PeerId};
+
+pub(crate) struct AccessChanges<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> AccessChanges<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        Self(state)
+    }
+
+    /// Start reporting changes to access starting from `current`, returning
+    /// false if we already were
+    pub(crate) fn subscribe(
+        &self,
+        current: HashMap<DocumentId, HashMap<PeerId, MemberAccess>>,
+    ) -> bool {
+        let mut state = RefCell::borrow_mut(&self.0);
+        if state.access_snapshot.is_some() {
+            return false;
+        }
+        state.access_snapshot = Some(current);
+        state.access_unchecked = false;
+        true
+    }
+
+    /// Stop reporting changes to access, returning false if we weren't
+    pub(crate) fn unsubscribe(&self) -> bool {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.access_unchecked = false;
+        state.access_snapshot.take().is_some()
+    }
+
+    /// Record that the keyhive membership graph may have changed
+    pub(crate) fn mark_unchecked(&self) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        if state.access_snapshot.is_some() {
+            state.access_unchecked = true;
+        }
+    }
+
+    /// Whether someone is subscribed and access may have changed since we
+    /// last checked
+    pub(crate) fn is_unchecked(&self) -> bool {
+        RefCell::borrow(&self.0).access_unchecked
+    }
+
+    /// Compare `current` against the access we last reported, returning a
+    /// `DocEvent::AccessChanged` for every member whose access differs
+    pub(crate) fn take_changes(
+        &self,
+        current: HashMap<DocumentId, HashMap<PeerId, MemberAccess>>,
+    ) -> Vec<(DocumentId, DocEvent)> {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.access_unchecked = false;
+        let Some(last) = state.access_snapshot.as_mut() else {
+            return Vec::new();
+        };
+        let empty = HashMap::new();
+        let mut changes = Vec::new();
+        for (doc_id, members) in &current {
+            let before = last.get(doc_id).unwrap_or(&empty);
+            for (entity, new) in members {
+                let old = before.get(entity).copied();
+                if old != Some(*new) {
+                    changes.push((*doc_id, *entity, old, Some(*new)));
+                }
+            }
+            for (entity, old) in before {
+                if !members.contains_key(entity) {
+                    changes.push((*doc_id, *entity, Some(*old), None));
+                }
+            }
+        }
+        for (doc_id, before) in last.iter() {
+            if !current.contains_key(doc_id) {
+                for (entity, old) in before {
+                    changes.push((*doc_id, *entity, Some(*old), None));
+                }
+            }
+        }
+        *last = current;
+        changes
+            .into_iter()
+            .map(|(doc_id, entity, old, new)| {
+                (doc_id, DocEvent::AccessChanged { entity, old, new })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,62 @@
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, rc::Rc};
+
+use crate::{keyhive::MemberAccess, DocumentId, PeerId};
+
+pub(crate) struct AccessRequests<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> AccessRequests<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        AccessRequests(state)
+    }
+
+    /// Record a request from `requester` for access to a document we
+    /// administer, replacing any earlier request of theirs
+    pub(crate) fn insert_incoming(
+        &self,
+        doc_id: DocumentId,
+        requester: PeerId,
+        desired: MemberAccess,
+    ) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state
+            .incoming_access_requests
+            .insert((doc_id, requester), desired);
+    }
+
+    /// Forget the request of `requester` for `doc_id`, returning the access
+    /// they asked for if there was one
+    pub(crate) fn take_incoming(
+        &self,
+        doc_id: DocumentId,
+        requester: PeerId,
+    ) -> Option<MemberAccess> {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.incoming_access_requests.remove(&(doc_id, requester))
+    }
+
+    /// Record that we asked `admins` for access to `doc_id`
+    pub(crate) fn insert_outgoing(&self, doc_id: DocumentId, admins: HashSet<PeerId>) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state
+            .outgoing_access_requests
+            .entry(doc_id)
+            .or_default()
+            .extend(admins);
+    }
+
+    /// Forget our request for access to `doc_id` if `admin` was one of the
+    /// peers we asked, returning whether they were
+    pub(crate) fn take_outgoing(&self, doc_id: DocumentId, admin: PeerId) -> bool {
+        let mut state = RefCell::borrow_mut(&self.0);
+        let asked = state
+            .outgoing_access_requests
+            .get(&doc_id)
+            .is_some_and(|admins| admins.contains(&admin));
+        if asked {
+            state.outgoing_access_requests.remove(&doc_id);
+        }
+        asked
+    }
+}
@@ -0,0 +1,47 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use crate::{
+    auth::Signed,
+    capabilities::{CapabilityId, CapabilityRecord},
+    DocumentId,
+};
+
+pub(crate) struct Capabilities<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> Capabilities<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        Capabilities(state)
+    }
+
+    pub(crate) fn contains(&self, id: &CapabilityId) -> bool {
+        RefCell::borrow(&self.0).capabilities.contains_key(id)
+    }
+
+    pub(crate) fn insert(&self, record: Signed<CapabilityRecord>) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.capabilities.insert(record.payload.id, record);
+    }
+
+    // Returns true if the capability was there to remove
+    pub(crate) fn remove(&self, id: &CapabilityId) -> bool {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.capabilities.remove(id).is_some()
+    }
+
+    // Returns the ids of the capabilities minted for `doc_id` which were removed
+    pub(crate) fn remove_for_doc(&self, doc_id: &DocumentId) -> Vec<CapabilityId> {
+        let mut state = RefCell::borrow_mut(&self.0);
+        let ids = state
+            .capabilities
+            .values()
+            .filter(|record| record.payload.doc_id == *doc_id)
+            .map(|record| record.payload.id)
+            .collect::<Vec<_>>();
+        for id in &ids {
+            state.capabilities.remove(id);
+        }
+        ids
+    }
+}
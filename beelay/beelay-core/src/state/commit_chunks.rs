@@ -0,0 +1,86 @@
+use std::{borrow::Cow, cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use crate::{commands::error::AddCommitChunk, Commit, CommitHash, DocumentId};
+
+/// The chunks received so far for a commit which is being added in pieces
+pub(crate) struct PendingCommit {
+    parents: Vec<CommitHash>,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    last_index: Option<u32>,
+}
+
+pub(crate) struct CommitChunks<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> CommitChunks<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        CommitChunks(state)
+    }
+
+    /// Record a chunk of a commit, returning the reassembled commit once every
+    /// chunk up to and including the last one has been received
+    pub(crate) fn add(
+        &self,
+        doc_id: DocumentId,
+        hash: CommitHash,
+        parents: Vec<CommitHash>,
+        chunk_index: u32,
+        bytes: Vec<u8>,
+        is_last: bool,
+    ) -> Result<Option<Commit>, AddCommitChunk> {
+        let mut state = RefCell::borrow_mut(&self.0);
+        let pending = state
+            .commit_chunks
+            .entry((doc_id, hash))
+            .or_insert_with(|| PendingCommit {
+                parents: parents.clone(),
+                chunks: BTreeMap::new(),
+                last_index: None,
+            });
+
+        if pending.parents != parents {
+            return Err(AddCommitChunk::ConflictingParents);
+        }
+        match pending.last_index {
+            Some(last) if is_last && last != chunk_index => {
+                return Err(AddCommitChunk::ConflictingLastChunk)
+            }
+            Some(last) if chunk_index > last => return Err(AddCommitChunk::ChunkAfterLast),
+            None if is_last && pending.chunks.keys().any(|i| *i > chunk_index) => {
+                return Err(AddCommitChunk::ChunkAfterLast)
+            }
+            _ => {}
+        }
+        match pending.chunks.get(&chunk_index) {
+            // Duplicates are fine as long as they agree with what we already have
+            Some(existing) if *existing != bytes => return Err(AddCommitChunk::ConflictingChunk),
+            Some(_) => {}
+            None => {
+                pending.chunks.insert(chunk_index, bytes);
+            }
+        }
+        if is_last {
+            pending.last_index = Some(chunk_index);
+        }
+
+        let Some(last_index) = pending.last_index else {
+            return Ok(None);
+        };
+        if pending.chunks.len() as u64 != u64::from(last_index) + 1 {
+            return Ok(None);
+        }
+        let pending = state
+            .commit_chunks
+            .remove(&(doc_id, hash))
+            .expect("pending commit should exist");
+        let contents = pending.chunks.into_values().flatten().collect();
+        Ok(Some(Commit::new(pending.parents, contents, hash)))
+    }
+
+    /// Drop any chunks received for a commit, returning false if there were none
+    pub(crate) fn discard(&self, doc_id: DocumentId, hash: CommitHash) -> bool {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.commit_chunks.remove(&(doc_id, hash)).is_some()
+    }
+}
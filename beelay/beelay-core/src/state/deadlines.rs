@@ -0,0 +1,47 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use futures::channel::oneshot;
+
+use crate::UnixTimestampMillis;
+
+pub(crate) struct Deadlines<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> Deadlines<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        Deadlines(state)
+    }
+
+    /// A receiver which completes on the first tick at or after `deadline`
+    pub(crate) fn wait_until(&self, deadline: UnixTimestampMillis) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        RefCell::borrow_mut(&self.0).deadlines.push((deadline, tx));
+        rx
+    }
+
+    /// Complete the receivers of every deadline which has passed and forget
+    /// those whose receiver has been dropped, returning the number completed
+    pub(crate) fn fire_expired(&self, now: UnixTimestampMillis) -> usize {
+        let mut state = RefCell::borrow_mut(&self.0);
+        let (expired, pending) = std::mem::take(&mut state.deadlines)
+            .into_iter()
+            .filter(|(_, tx)| !tx.is_canceled())
+            .partition::<Vec<_>, _>(|(deadline, _)| *deadline <= now);
+        state.deadlines = pending;
+        let fired = expired.len();
+        for (_, tx) in expired {
+            let _ = tx.send(());
+        }
+        fired
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<UnixTimestampMillis> {
+        RefCell::borrow(&self.0)
+            .deadlines
+            .iter()
+            .filter(|(_, tx)| !tx.is_canceled())
+            .map(|(deadline, _)| *deadline)
+            .min()
+    }
+}
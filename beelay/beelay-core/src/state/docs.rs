@@ -1,11 +1,17 @@
-use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    rc::Rc,
+};
 
 use keyhive_core::{cgka::operation::CgkaOperation, crypto::signed::Signed};
 
 use crate::{
+    blob::BlobMeta,
     doc_state::{DocState, NewChange},
-    doc_status::DocStatus,
-    Commit, CommitBundle, DocumentId, PeerId,
+    doc_status::{DocStatus, SyncPriority},
+    BlobHash, Commit, CommitBundle, CommitHash, DocumentId, PeerId,
 };
 
 pub(crate) struct Docs<'a, R: rand::Rng + rand::CryptoRng> {
@@ -23,11 +29,34 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Docs<'a, R> {
         tree: crate::sedimentree::Sedimentree,
         _cgka_op: Option<Signed<CgkaOperation>>,
     ) {
-        self.state
-            .borrow_mut()
-            .docs
-            .insert(doc_id, DocState::new(tree));
-        self.state.borrow_mut().docs_with_changes.insert(doc_id);
+        let mut state = self.state.borrow_mut();
+        state.docs.insert(doc_id, DocState::new(tree));
+        state.docs_with_changes.insert(doc_id);
+        tree_changed(&mut state, doc_id);
+    }
+
+    /// Remove a document from the in memory state, returning its sedimentree if it was present
+    pub(crate) fn remove_doc(
+        &self,
+        doc_id: &DocumentId,
+    ) -> Option<crate::sedimentree::Sedimentree> {
+        let mut state = self.state.borrow_mut();
+        state.docs_with_changes.remove(doc_id);
+        tree_changed(&mut state, *doc_id);
+        state.docs.remove(doc_id).map(|doc| doc.tree().clone())
+    }
+
+    /// Remove loose commits which have been deleted from storage
+    pub(crate) fn remove_commits(&self, doc_id: &DocumentId, hashes: &[CommitHash]) {
+        let mut state = self.state.borrow_mut();
+        if let Some(doc) = state.docs.get_mut(doc_id) {
+            doc.remove_commits(hashes);
+            tree_changed(&mut state, *doc_id);
+        }
+    }
+
+    pub(crate) fn doc_ids(&self) -> Vec<DocumentId> {
+        self.state.borrow().docs.keys().copied().collect()
     }
 
     pub(crate) fn mark_changed(&self, doc_id: &DocumentId) {
@@ -42,12 +71,176 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Docs<'a, R> {
             .map(|summary| summary.tree().clone())
     }
 
+    /// The blobs referenced by every document other than `doc_id`
+    ///
+    /// Blobs are content addressed, so a blob of `doc_id` which is in here
+    /// must not be deleted along with `doc_id`.
+    pub(crate) fn blobs_outside(&self, doc_id: &DocumentId) -> HashSet<BlobHash> {
+        let state = self.state.borrow();
+        state
+            .docs
+            .iter()
+            .filter(|(id, _)| *id != doc_id)
+            .flat_map(|(_, doc)| doc.tree().blobs().map(|b| b.hash()))
+            .collect()
+    }
+
+    /// Every blob referenced by a document, with the number of documents
+    /// which reference it
+    pub(crate) fn blob_references(&self) -> HashMap<BlobMeta, usize> {
+        let state = self.state.borrow();
+        let mut references = HashMap::new();
+        for doc in state.docs.values() {
+            for blob in doc.tree().blobs().copied().collect::<HashSet<_>>() {
+                *references.entry(blob).or_insert(0) += 1;
+            }
+        }
+        references
+    }
+
     pub(crate) fn doc_status(&self, doc_id: DocumentId) -> crate::doc_status::DocStatus {
-        if let Some(summary) = self.state.borrow().docs.get(&doc_id) {
-            summary.status()
+        let state = self.state.borrow();
+        DocStatus {
+            local_heads: state.docs.get(&doc_id).map(|doc| doc.heads()),
+            sync_paused: state.paused_docs.contains(&doc_id),
+        }
+    }
+
+    /// Stop syncing `doc_id`, returning false if it was already paused
+    pub(crate) fn pause_sync(&self, doc_id: DocumentId) -> bool {
+        self.state.borrow_mut().paused_docs.insert(doc_id)
+    }
+
+    /// Start syncing `doc_id` again, returning false if it wasn't paused
+    pub(crate) fn resume_sync(&self, doc_id: DocumentId) -> bool {
+        self.state.borrow_mut().paused_docs.remove(&doc_id)
+    }
+
+    pub(crate) fn is_sync_paused(&self, doc_id: &DocumentId) -> bool {
+        self.state.borrow().paused_docs.contains(doc_id)
+    }
+
+    /// Set the sync priority of `doc_id`, returning its previous priority
+    pub(crate) fn set_sync_priority(
+        &self,
+        doc_id: DocumentId,
+        priority: SyncPriority,
+    ) -> SyncPriority {
+        let mut state = self.state.borrow_mut();
+        let previous = if priority == SyncPriority::Normal {
+            state.sync_priorities.remove(&doc_id)
         } else {
-            DocStatus { local_heads: None }
+            state.sync_priorities.insert(doc_id, priority)
+        };
+        previous.unwrap_or_default()
+    }
+
+    pub(crate) fn sync_priority(&self, doc_id: &DocumentId) -> SyncPriority {
+        self.state
+            .borrow()
+            .sync_priorities
+            .get(doc_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn high_priority_docs(&self) -> Vec<DocumentId> {
+        self.state
+            .borrow()
+            .sync_priorities
+            .iter()
+            .filter(|(_, priority)| **priority == SyncPriority::High)
+            .map(|(doc_id, _)| *doc_id)
+            .collect()
+    }
+
+    /// Whether `doc_id` was an ephemeral document which was lost when we were
+    /// last stopped
+    ///
+    /// This stops being true once some commits for the document arrive.
+    pub(crate) fn is_vanished(&self, doc_id: &DocumentId) -> bool {
+        let state = self.state.borrow();
+        state.vanished_docs.contains(doc_id) && !state.docs.contains_key(doc_id)
+    }
+
+    /// Start reporting changes to the heads of `doc_id`, returning false if
+    /// we already were
+    pub(crate) fn subscribe(&self, doc_id: DocumentId) -> bool {
+        let mut state = self.state.borrow_mut();
+        let heads = state.docs.get(&doc_id).map(|doc| doc.heads());
+        match state.subscribed_docs.entry(doc_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(heads);
+                true
+            }
+        }
+    }
+
+    /// Stop reporting changes to the heads of `doc_id`, returning false if we
+    /// weren't
+    pub(crate) fn unsubscribe(&self, doc_id: DocumentId) -> bool {
+        let mut state = self.state.borrow_mut();
+        state.subscribed_docs_changed.remove(&doc_id);
+        state.subscribed_docs.remove(&doc_id).is_some()
+    }
+
+    /// The new heads of each subscribed document whose heads have changed
+    /// since this was last called
+    pub(crate) fn take_heads_changes(&self) -> Vec<(DocumentId, Vec<CommitHash>)> {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let mut changes = Vec::new();
+        for doc_id in std::mem::take(&mut state.subscribed_docs_changed) {
+            let Some(last_heads) = state.subscribed_docs.get_mut(&doc_id) else {
+                continue;
+            };
+            // A deleted document has no heads to report
+            let Some(heads) = state.docs.get(&doc_id).map(|doc| doc.heads()) else {
+                *last_heads = None;
+                continue;
+            };
+            if last_heads.as_ref() != Some(&heads) {
+                *last_heads = Some(heads.clone());
+                changes.push((doc_id, heads));
+            }
+        }
+        changes
+    }
+
+    /// The heads of each document which has diverged since this was last
+    /// called
+    ///
+    /// A document which stays diverged is only reported once, a document is
+    /// reported again if it is merged back down to one head and then
+    /// diverges again.
+    pub(crate) fn take_divergences(&self) -> Vec<(DocumentId, Vec<CommitHash>)> {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        let mut divergences = Vec::new();
+        for doc_id in std::mem::take(&mut state.divergence_unchecked) {
+            let heads = state
+                .docs
+                .get(&doc_id)
+                .map(|doc| doc.heads())
+                .unwrap_or_default();
+            if heads.len() <= 1 {
+                state.diverged_docs.remove(&doc_id);
+            } else if state.diverged_docs.insert(doc_id) {
+                divergences.push((doc_id, heads));
+            }
         }
+        divergences
+    }
+
+    /// Whether `doc_id` has at most one head, `None` if we have none of its
+    /// commits
+    pub(crate) fn is_merged(&self, doc_id: &DocumentId) -> Option<bool> {
+        self.state
+            .borrow()
+            .docs
+            .get(doc_id)
+            .map(|doc| doc.heads().len() <= 1)
     }
 
     pub(crate) fn apply_doc_update(&self, mut update: DocUpdateBuilder) {
@@ -56,6 +249,7 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Docs<'a, R> {
 
         if !update.is_empty() {
             state.docs_with_changes.insert(doc_id);
+            tree_changed(&mut state, doc_id);
         }
 
         let commits = update.take_commits();
@@ -96,6 +290,15 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Docs<'a, R> {
     }
 }
 
+// Remember to check the heads of `doc_id` for divergence, and for changes if
+// it is subscribed to
+fn tree_changed<R: rand::Rng + rand::CryptoRng>(state: &mut super::State<R>, doc_id: DocumentId) {
+    state.divergence_unchecked.insert(doc_id);
+    if state.subscribed_docs.contains_key(&doc_id) {
+        state.subscribed_docs_changed.insert(doc_id);
+    }
+}
+
 /// Builder for updating document components
 pub(crate) struct DocUpdateBuilder {
     doc_id: DocumentId,
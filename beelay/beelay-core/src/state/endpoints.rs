@@ -1,11 +1,14 @@
-use std::{borrow::Cow, cell::RefCell, rc::Rc};
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, rc::Rc};
 
 use futures::channel::oneshot;
+use nonempty::NonEmpty;
 
 use crate::{
     auth,
+    commands::error::HandleResponse,
     network::endpoint::{self, NoSuchEndpoint},
-    Audience, EndpointId, OutboundRequestId, PeerId, Request, Response, UnixTimestamp,
+    Audience, EndpointId, OutboundRequest, OutboundRequestId, PeerId, Request, Response,
+    UnixTimestamp, UnixTimestampMillis,
 };
 
 pub(crate) struct Endpoints<'a, R: rand::Rng + rand::CryptoRng>(
@@ -17,19 +20,66 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Endpoints<'a, R> {
         Endpoints(state)
     }
 
-    pub(crate) fn register_endpoint(&self, audience: Audience) -> endpoint::EndpointId {
+    pub(crate) fn register_endpoint(&self, audiences: NonEmpty<Audience>) -> endpoint::EndpointId {
         let mut state = RefCell::borrow_mut(&self.0);
-        state.endpoints.register_endpoint(audience)
+        state.endpoints.register_endpoint(audiences)
     }
 
-    pub(crate) fn unregister_endpoint(&self, endpoint_id: endpoint::EndpointId) {
+    pub(crate) fn register_delegated_endpoint(
+        &self,
+        audience: Audience,
+        delegation: endpoint::Delegation,
+    ) -> endpoint::EndpointId {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state
+            .endpoints
+            .register_delegated_endpoint(audience, delegation)
+    }
+
+    pub(crate) fn register_capability_endpoint(
+        &self,
+        audience: Audience,
+        capability: crate::Capability,
+    ) -> endpoint::EndpointId {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state
+            .endpoints
+            .register_capability_endpoint(audience, capability)
+    }
+
+    /// The capability `endpoint_id` was registered with, if any
+    pub(crate) fn capability(&self, endpoint_id: EndpointId) -> Option<crate::Capability> {
+        self.0.borrow().endpoints.capability(endpoint_id)
+    }
+
+    /// The peer `endpoint_id` addresses by its key, if any
+    pub(crate) fn peer(&self, endpoint_id: EndpointId) -> Option<PeerId> {
+        self.0.borrow().endpoints.peer(endpoint_id)
+    }
+
+    pub(crate) fn audiences_matching(&self, audience: Audience) -> HashSet<Audience> {
+        self.0.borrow().endpoints.audiences_matching(audience)
+    }
+
+    pub(crate) fn endpoint_for_audience(&self, audience: Audience) -> Option<EndpointId> {
+        self.0.borrow().endpoints.endpoint_for_audience(audience)
+    }
+
+    /// Unregister each of `endpoint_ids`, returning the ids which were registered
+    pub(crate) fn unregister_endpoints(
+        &self,
+        endpoint_ids: Vec<endpoint::EndpointId>,
+    ) -> Vec<endpoint::EndpointId> {
         let mut state = RefCell::borrow_mut(&self.0);
-        state.endpoints.unregister_endpoint(endpoint_id);
+        endpoint_ids
+            .into_iter()
+            .filter(|endpoint_id| state.endpoints.unregister_endpoint(*endpoint_id))
+            .collect()
     }
 
     pub(crate) fn send_request(
         &self,
-        now: UnixTimestamp,
+        now: UnixTimestampMillis,
         endpoint_id: EndpointId,
         request_id: OutboundRequestId,
         request: Request,
@@ -46,11 +96,27 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Endpoints<'a, R> {
         now: UnixTimestamp,
         req_id: OutboundRequestId,
         response: auth::Signed<auth::Message>,
-    ) {
+    ) -> Result<(), HandleResponse> {
         let mut state = RefCell::borrow_mut(&self.0);
         let our_peer_id = state.our_peer_id;
         state
             .endpoints
-            .handle_response(now, &our_peer_id, req_id, response);
+            .handle_response(now, &our_peer_id, req_id, response)
+    }
+
+    pub(crate) fn expire_requests(&self, now: UnixTimestampMillis) -> Vec<OutboundRequestId> {
+        self.0.borrow_mut().endpoints.expire_requests(now)
+    }
+
+    pub(crate) fn outbound_requests(&self, now: UnixTimestampMillis) -> Vec<OutboundRequest> {
+        self.0.borrow().endpoints.outbound_requests(now)
+    }
+
+    pub(crate) fn request_timeout(&self) -> Option<std::time::Duration> {
+        self.0.borrow().endpoints.request_timeout()
+    }
+
+    pub(crate) fn next_request_deadline(&self) -> Option<UnixTimestampMillis> {
+        self.0.borrow().endpoints.next_request_deadline()
     }
 }
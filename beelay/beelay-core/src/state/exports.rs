@@ -0,0 +1,72 @@
+use std::{borrow::Cow, cell::RefCell, collections::VecDeque, rc::Rc};
+
+use crate::{commands::ExportHandle, sedimentree::CommitOrStratum, DocumentId};
+
+/// An export started with `begin_export` which has not been read to the end
+pub(crate) struct Export {
+    pub(crate) doc_id: DocumentId,
+    // Locked while a chunk is being read so that concurrent reads of the same
+    // export each get whole chunks
+    pub(crate) progress: Rc<futures::lock::Mutex<ExportProgress>>,
+}
+
+pub(crate) struct ExportProgress {
+    /// The commits and strata which have not been loaded yet
+    pub(crate) remaining: VecDeque<CommitOrStratum>,
+    /// Encoded items which have been loaded but not yet returned in a chunk
+    pub(crate) buffer: Vec<u8>,
+}
+
+pub(crate) struct Exports<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> Exports<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        Exports(state)
+    }
+
+    pub(crate) fn begin(
+        &self,
+        doc_id: DocumentId,
+        items: impl IntoIterator<Item = CommitOrStratum>,
+    ) -> ExportHandle {
+        let handle = ExportHandle::new();
+        let progress = ExportProgress {
+            remaining: items.into_iter().collect(),
+            buffer: Vec::new(),
+        };
+        let export = Export {
+            doc_id,
+            progress: Rc::new(futures::lock::Mutex::new(progress)),
+        };
+        RefCell::borrow_mut(&self.0).exports.insert(handle, export);
+        handle
+    }
+
+    pub(crate) fn get(
+        &self,
+        handle: ExportHandle,
+    ) -> Option<(DocumentId, Rc<futures::lock::Mutex<ExportProgress>>)> {
+        self.0
+            .borrow()
+            .exports
+            .get(&handle)
+            .map(|export| (export.doc_id, export.progress.clone()))
+    }
+
+    pub(crate) fn is_active(&self, handle: ExportHandle) -> bool {
+        self.0.borrow().exports.contains_key(&handle)
+    }
+
+    pub(crate) fn finish(&self, handle: ExportHandle) {
+        RefCell::borrow_mut(&self.0).exports.remove(&handle);
+    }
+
+    /// Drop every export of `doc_id`, further reads of them will fail
+    pub(crate) fn invalidate_doc(&self, doc_id: &DocumentId) {
+        RefCell::borrow_mut(&self.0)
+            .exports
+            .retain(|_, export| export.doc_id != *doc_id);
+    }
+}
@@ -0,0 +1,28 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use crate::{auth::Signed, group_metadata::GroupMetadataRecord, PeerId};
+
+pub(crate) struct GroupMetadataRecords<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> GroupMetadataRecords<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        GroupMetadataRecords(state)
+    }
+
+    pub(crate) fn get(&self, group_id: PeerId) -> Option<Signed<GroupMetadataRecord>> {
+        let state = RefCell::borrow(&self.0);
+        state.group_metadata.get(&group_id).cloned()
+    }
+
+    pub(crate) fn all(&self) -> Vec<Signed<GroupMetadataRecord>> {
+        let state = RefCell::borrow(&self.0);
+        state.group_metadata.values().cloned().collect()
+    }
+
+    pub(crate) fn insert(&self, record: Signed<GroupMetadataRecord>) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.group_metadata.insert(record.payload.group_id, record);
+    }
+}
@@ -14,9 +14,14 @@ use keyhive_core::{
         verifiable::Verifiable,
     },
     event::static_event::StaticEvent,
+    listener::cgka::CgkaListener,
     principal::{
+        agent::Agent,
         document::id::DocumentId as KeyhiveDocumentId,
-        group::{membership_operation::MembershipOperation, RevokeMemberError},
+        group::{
+            id::GroupId, membership_operation::MembershipOperation, ChangeMemberAccessError,
+            RevokeMemberError,
+        },
         identifier::Identifier,
         individual::{op::KeyOp, Individual},
         membered::Membered,
@@ -26,13 +31,20 @@ use keyhive_core::{
 use nonempty::NonEmpty;
 
 use crate::{
-    commands::keyhive::{KeyhiveEntityId, MemberAccess},
+    commands::keyhive::{
+        error::{
+            ChangeMemberAccess, CreateGroupWithMembers, InvalidDelegation, PreviewAccessChange,
+            QueryAccessFor,
+        },
+        AccessDiff, AccessLogChange, AccessLogEntry, Contact, CreatedGroup, DocKeyEpochs,
+        DocMember, GroupMember, GroupMembership, KeyhiveEntityId, MemberAccess, MemberKeyEpochs,
+    },
     contact_card::ContactCard,
     io::Signer,
     keyhive::Listener,
     parse::{self, Parse},
     serialization::Encode,
-    CommitHash, DocumentId, PeerId,
+    CommitHash, DocumentId, PeerId, UnixTimestamp,
 };
 
 use super::Beehive;
@@ -102,6 +114,10 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         self.can_do(peer_id, doc_id, KeyhiveAccess::Pull).await
     }
 
+    pub(crate) async fn can_admin(&self, peer_id: PeerId, doc_id: &DocumentId) -> bool {
+        self.can_do(peer_id, doc_id, KeyhiveAccess::Admin).await
+    }
+
     pub(crate) async fn membership_ops_for_peer(
         &self,
         for_peer: PeerId,
@@ -285,16 +301,99 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         other_owners: Vec<KeyhiveEntityId>,
         initial_heads: NonEmpty<CommitHash>,
     ) -> DocumentId {
+        let (doc_id, _) = self
+            .create_keyhive_doc_with_grants(other_owners, Vec::new(), initial_heads)
+            .await
+            .expect("creating a doc without grants should never fail");
+        doc_id
+    }
+
+    /// Create a document and grant each of `grants` access to it before
+    /// anything else can observe the document
+    ///
+    /// Every grantee is resolved before the document is generated so that a
+    /// missing member doesn't leave behind a document which is only partly
+    /// shared. Returns the peer ID of each grantee in the order they were given.
+    pub(crate) async fn create_keyhive_doc_with_grants(
+        &self,
+        other_owners: Vec<KeyhiveEntityId>,
+        grants: Vec<(KeyhiveEntityId, MemberAccess)>,
+        initial_heads: NonEmpty<CommitHash>,
+    ) -> Result<(DocumentId, Vec<(PeerId, MemberAccess)>), GrantError> {
         let k_mutex = self.0.borrow().keyhive.clone();
         let mut keyhive = k_mutex.lock().await;
 
+        let mut grantees = Vec::with_capacity(grants.len());
+        for (entity, access) in grants {
+            let agent = match entity.clone() {
+                KeyhiveEntityId::Doc(d) => keyhive.get_agent(d.as_key().into()),
+                KeyhiveEntityId::Group(g) => keyhive.get_agent(g.as_key().into()),
+                KeyhiveEntityId::Individual(contact_card) => {
+                    let indi = Rc::new(RefCell::new(Individual::from(contact_card.0)));
+                    keyhive.register_individual(indi.clone());
+                    Some(indi.into())
+                }
+                KeyhiveEntityId::Public => Some(Public.individual().into()),
+            };
+            let agent = agent.ok_or(GrantError::MemberNotFound(entity))?;
+            grantees.push((agent, access));
+        }
+
         let parents = other_owners
             .into_iter()
             .filter_map(|parent| get_peer(&mut *keyhive, parent))
             .collect();
         let doc = keyhive.generate_doc(parents, initial_heads).await.unwrap();
+
+        let mut granted = Vec::with_capacity(grantees.len());
+        for (agent, access) in grantees {
+            let member_id = PeerId::from(agent.id().0);
+            keyhive
+                .add_member(agent, &mut doc.clone().into(), access.into(), &[])
+                .await
+                .map_err(|e| GrantError::Grant(e.to_string()))?;
+            granted.push((member_id, access));
+        }
+
         let key = doc.borrow().doc_id().verifying_key();
-        key.into()
+        Ok((key.into(), granted))
+    }
+
+    /// Grant every direct member of `from` at least the access to `into`
+    /// which they have to `from`
+    ///
+    /// Members who already have that much access to `into`, directly or
+    /// through a group, are left alone.
+    pub(crate) async fn grant_members_of(
+        &self,
+        from: DocumentId,
+        into: DocumentId,
+    ) -> Result<(), GrantError> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        let (Some(from_doc), Some(into_doc)) = (
+            keyhive.get_document(from.into()).cloned(),
+            keyhive.get_document(into.into()).cloned(),
+        ) else {
+            return Ok(());
+        };
+        let into_agent = Agent::Document(into_doc.clone());
+        let existing = transitive_members(&into_agent, None);
+        for (member, access) in direct_members(&Agent::Document(from_doc)) {
+            if member.id() == into_agent.id()
+                || existing
+                    .get(&member.id())
+                    .is_some_and(|(_, current)| *current >= access)
+            {
+                continue;
+            }
+            keyhive
+                .add_member(member, &mut into_doc.clone().into(), access, &[])
+                .await
+                .map_err(|e| GrantError::Grant(e.to_string()))?;
+        }
+        Ok(())
     }
 
     pub(crate) async fn get_agent(
@@ -389,6 +488,118 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         }
     }
 
+    /// Change the access of an existing member of `doc_id`, returning the
+    /// access they had before
+    pub(crate) async fn change_member_access(
+        &self,
+        doc_id: DocumentId,
+        member: KeyhiveEntityId,
+        access: MemberAccess,
+    ) -> Result<MemberAccess, ChangeMemberAccess> {
+        let Some(agent) = self.get_agent(member).await else {
+            return Err(ChangeMemberAccess::NotAMember);
+        };
+
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        let Some(doc) = keyhive.get_document(doc_id.into()).cloned() else {
+            return Err(ChangeMemberAccess::NoSuchDocument);
+        };
+        let update = keyhive
+            .change_member_access(agent.id(), access.into(), &mut Membered::from(doc))
+            .await
+            .map_err(|e| match e {
+                ChangeMemberAccessError::NotAMember => ChangeMemberAccess::NotAMember,
+                other => ChangeMemberAccess::Failed(other.to_string()),
+            })?;
+        Ok(update.previous.into())
+    }
+
+    /// Perform a PCS update on the document so that subsequent commits are
+    /// encrypted under a fresh key, returning the number of key epochs the
+    /// document now has
+    pub(crate) async fn rotate_doc_key(&self, doc_id: DocumentId) -> Result<u64, RotateKeyError> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        let Some(doc) = keyhive.get_document(doc_id.into()).cloned() else {
+            return Err(RotateKeyError::NoSuchDocument);
+        };
+
+        let op = keyhive.force_pcs_update(doc.clone()).await?;
+        // force_pcs_update doesn't notify the listener so we do it here, this
+        // is what gets the update persisted and synced to the other members
+        keyhive.event_listener().on_cgka_op(&Rc::new(op)).await;
+
+        let epochs = doc.borrow().cgka_ops()?.len();
+        Ok(epochs as u64)
+    }
+
+    /// The key epochs of the document and the epochs in which each
+    /// individual member held a share of the document key
+    ///
+    /// Epochs are numbered from 1, the last one is the epoch commits are
+    /// currently encrypted under, there are no epochs if we have none of the
+    /// document's key material. Only current members are listed, those who
+    /// never held a share have no epochs.
+    pub(crate) async fn key_epochs(
+        &self,
+        doc_id: DocumentId,
+    ) -> Option<Result<DocKeyEpochs, CgkaError>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let doc = keyhive.get_document(doc_id.into())?.borrow();
+        let epochs = match doc.cgka_ops() {
+            Ok(epochs) => epochs.into_iter().collect::<Vec<_>>(),
+            // We haven't been given any of the document's key material
+            Err(CgkaError::NotInitialized) => Vec::new(),
+            Err(e) => return Some(Err(e)),
+        };
+
+        let mut holders = HashSet::new();
+        let mut held = HashMap::<PeerId, Vec<u64>>::new();
+        for (index, epoch) in epochs.iter().enumerate() {
+            for op in epoch.iter() {
+                match op.payload() {
+                    CgkaOperation::Add { added_id, .. } => {
+                        holders.insert(PeerId::from(added_id.0 .0));
+                    }
+                    CgkaOperation::Remove { id, .. } => {
+                        holders.remove(&PeerId::from(id.0 .0));
+                    }
+                    CgkaOperation::Update { .. } => {}
+                }
+            }
+            for holder in &holders {
+                held.entry(*holder).or_default().push(index as u64 + 1);
+            }
+        }
+
+        let mut members = doc
+            .transitive_members()
+            .into_values()
+            .filter(|(agent, _)| {
+                agent.id() != Public.id()
+                    && matches!(agent, Agent::Active(_) | Agent::Individual(_))
+            })
+            .map(|(agent, access)| {
+                let member = PeerId::from(agent.id().0);
+                MemberKeyEpochs {
+                    member,
+                    access: MemberAccess::from(access),
+                    epochs: held.remove(&member).unwrap_or_default(),
+                }
+            })
+            .collect::<Vec<_>>();
+        members.sort_by(|a, b| a.member.as_bytes().cmp(b.member.as_bytes()));
+        Some(Ok(DocKeyEpochs {
+            current: epochs.len() as u64,
+            members: Some(members),
+        }))
+    }
+
     pub(crate) async fn add_member_to_group(
         &self,
         group_id: PeerId,
@@ -416,6 +627,278 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         }
     }
 
+    /// Whether making `agent` a member of `group_id` would make the group a
+    /// transitive member of itself
+    pub(crate) async fn would_create_cycle(
+        &self,
+        group_id: PeerId,
+        agent: &Agent<Signer, CommitHash, crate::keyhive::Listener>,
+    ) -> bool {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let _keyhive = k_mutex.lock().await;
+
+        let group_id = Identifier::from(group_id.as_key());
+        agent.id() == group_id || transitive_members(agent, None).contains_key(&group_id)
+    }
+
+    /// The members of a group along with the members of any groups or
+    /// documents which are members of it, transitively
+    ///
+    /// A member reachable along several paths gets the best access of any
+    /// path, where the access along a path is the lowest access delegated on
+    /// it. Membership cycles, which can form when concurrent changes are
+    /// merged, are walked once.
+    pub(crate) async fn expand_group(&self, group_id: PeerId) -> Option<Vec<GroupMember>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let group = keyhive
+            .groups()
+            .get(&GroupId::from(Identifier::from(group_id.as_key())))?;
+        let members = transitive_members(&Agent::Group(group.clone()), None);
+        Some(
+            members
+                .into_values()
+                .map(|(agent, access)| GroupMember {
+                    member: entity_id(&agent),
+                    access: MemberAccess::from(access),
+                })
+                .collect(),
+        )
+    }
+
+    /// Every group which our active agent is a transitive member of, with the
+    /// best access we have to it
+    ///
+    /// Membership through a revoked delegation doesn't count, revoked members
+    /// are no longer reachable from the group.
+    pub(crate) async fn groups_of_local_identity(&self) -> Vec<GroupMembership> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let us = Identifier::from(keyhive.active().borrow().id());
+        keyhive
+            .groups()
+            .values()
+            .filter_map(|group| {
+                let group = Agent::Group(group.clone());
+                let (_, access) = transitive_members(&group, None).remove(&us)?;
+                Some(GroupMembership {
+                    group: entity_id(&group),
+                    access: MemberAccess::from(access),
+                })
+            })
+            .collect()
+    }
+
+    /// Every individual other than ourselves which we know about, whether we
+    /// received their contact card directly or learned of them through a
+    /// group or document they were added to
+    pub(crate) async fn contacts(&self) -> Vec<Contact> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let us = keyhive.active().borrow().id();
+        let membered = keyhive
+            .groups()
+            .values()
+            .map(|group| Membered::Group(group.clone()))
+            .chain(
+                keyhive
+                    .documents()
+                    .values()
+                    .map(|doc| Membered::Document(doc.clone())),
+            )
+            .collect::<Vec<_>>();
+        keyhive
+            .individuals()
+            .iter()
+            .filter(|(id, _)| **id != us && id.0 != Public.id())
+            .map(|(id, individual)| {
+                let agent = Agent::Individual(individual.clone());
+                let member_id = id.0;
+                let was_revoked = membered
+                    .iter()
+                    .any(|m| !m.get_agent_revocations(&agent).is_empty());
+                let still_member = membered
+                    .iter()
+                    .any(|m| m.members().contains_key(&member_id));
+                let individual = individual.borrow();
+                Contact {
+                    id: entity_id(&agent),
+                    peer_id: PeerId::from(id.0 .0),
+                    share_keys: individual
+                        .prekeys()
+                        .iter()
+                        .map(|key| key.to_bytes())
+                        .collect(),
+                    revoked: was_revoked && !still_member,
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) async fn has_group(&self, group_id: PeerId) -> bool {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        keyhive
+            .groups()
+            .contains_key(&GroupId::from(Identifier::from(group_id.as_key())))
+    }
+
+    /// The best access `member` has to `group_id` through any path, `None` if
+    /// it isn't a transitive member or the group is unknown
+    pub(crate) async fn group_member_access(
+        &self,
+        group_id: PeerId,
+        member: PeerId,
+    ) -> Option<MemberAccess> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let group = keyhive
+            .groups()
+            .get(&GroupId::from(Identifier::from(group_id.as_key())))?;
+        transitive_members(&Agent::Group(group.clone()), None)
+            .get(&Identifier::from(member.as_key()))
+            .map(|(_, access)| MemberAccess::from(*access))
+    }
+
+    /// The membership history needed to prove to another peer that we are a
+    /// transitive member of `group_id`
+    ///
+    /// This is every membership and prekey operation reachable from us, which
+    /// includes the operations of every group on the path to `group_id`.
+    pub(crate) async fn delegation_chain(
+        &self,
+        group_id: PeerId,
+    ) -> Result<Vec<StaticEvent<CommitHash>>, InvalidDelegation> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let group = keyhive
+            .groups()
+            .get(&GroupId::from(Identifier::from(group_id.as_key())))
+            .ok_or(InvalidDelegation::NoSuchGroup(group_id))?;
+        let us = Agent::Active(keyhive.active().clone());
+        if !transitive_members(&Agent::Group(group.clone()), None).contains_key(&us.id()) {
+            return Err(InvalidDelegation::NotAMember(group_id));
+        }
+
+        let membership = keyhive
+            .membership_ops_for_agent(&us)
+            .into_values()
+            .map(|op| StaticEvent::from(keyhive_core::event::Event::from(op)));
+        let prekeys = keyhive
+            .reachable_prekey_ops_for_agent(&us)
+            .into_values()
+            .flatten()
+            .map(|op| {
+                StaticEvent::from(
+                    keyhive_core::event::Event::<Signer, CommitHash, Listener>::from((*op).clone()),
+                )
+            });
+        Ok(membership.chain(prekeys).collect())
+    }
+
+    /// Check that `from` is a transitive member of `group_id`, returning its
+    /// access in the group
+    ///
+    /// Membership is decided from the operations we already hold, `chain` is
+    /// only ingested once `from` has been found to be a member. A request
+    /// can't vouch for its own sender, so a peer added to the group since we
+    /// last synced it is rejected until we have the operation which added it.
+    pub(crate) async fn verify_delegation(
+        &self,
+        from: PeerId,
+        group_id: PeerId,
+        chain: Vec<StaticEvent<CommitHash>>,
+    ) -> Result<MemberAccess, InvalidDelegation> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        let group = keyhive
+            .groups()
+            .get(&GroupId::from(Identifier::from(group_id.as_key())))
+            .ok_or(InvalidDelegation::NoSuchGroup(group_id))?
+            .clone();
+        // Revoked members are no longer reachable from the group
+        let from_id = Identifier::from(from.as_key());
+        let access = transitive_members(&Agent::Group(group), None)
+            .get(&from_id)
+            .map(|(_, access)| MemberAccess::from(*access))
+            .ok_or(InvalidDelegation::NotAMember(group_id))?;
+        keyhive
+            .ingest_unsorted_static_events(chain)
+            .await
+            .map_err(|e| InvalidDelegation::InvalidChain(format!("{:?}", e)))?;
+        Ok(access)
+    }
+
+    /// The change to the transitive membership of `group_id` which would
+    /// result from giving `member` the access `access`, or removing it from
+    /// the group if `access` is `None`
+    ///
+    /// Nothing is written to keyhive, in particular contact cards for
+    /// individuals we haven't seen before are not registered.
+    pub(crate) async fn preview_access_change(
+        &self,
+        group_id: PeerId,
+        member: KeyhiveEntityId,
+        access: Option<MemberAccess>,
+    ) -> Result<AccessDiff, PreviewAccessChange> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let group_id = Identifier::from(group_id.as_key());
+        let group = keyhive
+            .groups()
+            .get(&GroupId::from(group_id))
+            .ok_or(PreviewAccessChange::NoSuchGroup)?;
+        let group = Agent::Group(group.clone());
+        let member = match member {
+            KeyhiveEntityId::Doc(d) => keyhive.get_agent(d.as_key().into()),
+            KeyhiveEntityId::Group(g) => keyhive.get_agent(g.as_key().into()),
+            KeyhiveEntityId::Individual(contact_card) => {
+                let id = contact_card.0.id().0;
+                keyhive.get_agent(id).or_else(|| {
+                    Some(Rc::new(RefCell::new(Individual::from(contact_card.0))).into())
+                })
+            }
+            KeyhiveEntityId::Public => Some(Public.individual().into()),
+        };
+
+        let before = transitive_members(&group, None);
+        let after = match (member, access) {
+            (Some(member), Some(access)) => {
+                if member.id() == group_id
+                    || transitive_members(&member, None).contains_key(&group_id)
+                {
+                    return Err(PreviewAccessChange::WouldCreateCycle);
+                }
+                let edit = MemberEdit {
+                    group: group_id,
+                    member,
+                    access: Some(access.into()),
+                };
+                transitive_members(&group, Some(&edit))
+            }
+            (Some(member), None) => {
+                let edit = MemberEdit {
+                    group: group_id,
+                    member,
+                    access: None,
+                };
+                transitive_members(&group, Some(&edit))
+            }
+            (None, Some(_)) => return Err(PreviewAccessChange::MemberNotFound),
+            // Removing a member we don't know about does nothing
+            (None, None) => before.clone(),
+        };
+        Ok(access_diff(&before, &after))
+    }
+
     pub(crate) async fn remove_member_from_group(
         &self,
         group_id: PeerId,
@@ -442,6 +925,32 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         }
     }
 
+    /// Revoke `member` from `target`, which may be either a group or a document
+    pub(crate) async fn revoke_member(
+        &self,
+        target: PeerId,
+        member: PeerId,
+    ) -> Result<(), RevokeMemberError> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        let target_id = Identifier::from(target.as_key());
+        let mut membered = if let Some(doc) =
+            keyhive.get_document(KeyhiveDocumentId::from(target_id))
+        {
+            Membered::from(doc.clone())
+        } else if let Some(group) = keyhive.get_group(GroupId::from(target_id)) {
+            Membered::from(group.clone())
+        } else {
+            tracing::warn!("attempting to revoke a member of a group or document we don't have");
+            return Ok(());
+        };
+        keyhive
+            .revoke_member(Identifier::from(member.as_key()), true, &mut membered)
+            .await?;
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self, agent_id))]
     pub(crate) async fn events_for_agent(
         &self,
@@ -491,6 +1000,225 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         Some(result)
     }
 
+    /// The best access `entity` has to `doc_id` through any path, including
+    /// access granted to the public
+    ///
+    /// Only admins of the document can ask about entities other than
+    /// themselves.
+    pub(crate) async fn access_for(
+        &self,
+        doc_id: DocumentId,
+        entity: &KeyhiveEntityId,
+    ) -> Result<Option<MemberAccess>, QueryAccessFor> {
+        let our_peer_id = self.0.borrow().our_peer_id;
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let doc = keyhive
+            .get_document(doc_id.into())
+            .ok_or(QueryAccessFor::NoSuchDocument)?;
+        let transitive = doc.borrow().transitive_members();
+        let access_of = |id: Identifier| transitive.get(&id).map(|(_, access)| *access);
+        let public = access_of(Public.id());
+
+        let ours = access_of(our_peer_id.as_key().into()).max(public);
+        if ours < Some(KeyhiveAccess::Admin) {
+            return Err(QueryAccessFor::NotAllowed);
+        }
+        let entity_id = match entity {
+            KeyhiveEntityId::Individual(card) => card.peer_id(),
+            KeyhiveEntityId::Group(group_id) => *group_id,
+            KeyhiveEntityId::Doc(doc_id) => PeerId::from(*doc_id.as_key()),
+            KeyhiveEntityId::Public => return Ok(public.map(MemberAccess::from)),
+        };
+        Ok(access_of(entity_id.as_key().into())
+            .max(public)
+            .map(MemberAccess::from))
+    }
+
+    /// The best access we have to each of `doc_ids`, including access granted
+    /// to the public, `None` for documents we can't see
+    pub(crate) async fn our_access_multi(
+        &self,
+        doc_ids: Vec<DocumentId>,
+    ) -> HashMap<DocumentId, Option<MemberAccess>> {
+        let our_peer_id = self.0.borrow().our_peer_id;
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        doc_ids
+            .into_iter()
+            .map(|doc_id| {
+                let access = keyhive.get_document(doc_id.into()).and_then(|doc| {
+                    let transitive = doc.borrow().transitive_members();
+                    let access_of = |id: Identifier| transitive.get(&id).map(|(_, access)| *access);
+                    access_of(our_peer_id.as_key().into())
+                        .max(access_of(Public.id()))
+                        .map(MemberAccess::from)
+                });
+                (doc_id, access)
+            })
+            .collect()
+    }
+
+    pub(crate) async fn list_doc_members(&self, doc_id: DocumentId) -> Option<Vec<DocMember>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let doc = keyhive.get_document(doc_id.into())?.borrow();
+        let direct_members = doc.members();
+        let result = doc
+            .transitive_members()
+            .into_iter()
+            .map(|(id, (agent, access))| DocMember {
+                member: entity_id(&agent),
+                access: MemberAccess::from(access),
+                direct: direct_members.contains_key(&id),
+            })
+            .collect();
+        Some(result)
+    }
+
+    /// The entity ID of the local agent and the contact cards it has issued
+    pub(crate) async fn local_identity(&self) -> (KeyhiveEntityId, Vec<ContactCard>) {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let active = keyhive.active().clone();
+        let issued = {
+            let active = active.borrow();
+            // Creating a contact card rotates a prekey to a new private key and
+            // then immediately rotates the private key away so that it is
+            // never picked by anyone else. Each prekey is only ever rotated
+            // once, so following the rotations from a key which was not
+            // itself produced by a rotation alternates between a contact card
+            // and the rotation which retired it.
+            let rotations = active
+                .individual()
+                .prekey_ops()
+                .values()
+                .filter_map(|op| match op.as_ref() {
+                    KeyOp::Rotate(rot) => Some((rot.payload().old, rot.clone())),
+                    KeyOp::Add(_) => None,
+                })
+                .collect::<HashMap<_, _>>();
+            let produced = rotations
+                .values()
+                .map(|rot| rot.payload().new)
+                .collect::<HashSet<_>>();
+            let mut issued = Vec::new();
+            for start in rotations.keys().filter(|old| !produced.contains(old)) {
+                let mut next = rotations.get(start);
+                let mut is_card = true;
+                while let Some(rot) = next {
+                    if is_card {
+                        issued.push(ContactCard(KeyOp::Rotate(rot.clone()).into()));
+                    }
+                    is_card = !is_card;
+                    next = rotations.get(&rot.payload().new);
+                }
+            }
+            issued
+        };
+        (entity_id(&Agent::Active(active)), issued)
+    }
+
+    /// The keyhive events a member of `doc_id` needs in order to decrypt it
+    ///
+    /// This is the membership and prekey ops for every member of the document
+    /// along with the CGKA ops for the document itself.
+    pub(crate) async fn doc_key_material(
+        &self,
+        doc_id: DocumentId,
+    ) -> Option<Result<Vec<StaticEvent<CommitHash>>, CgkaError>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let doc = keyhive.get_document(doc_id.into())?.borrow();
+        let mut events = HashMap::new();
+        for (agent, _) in doc.transitive_members().into_values() {
+            let agent_events = match keyhive.static_events_for_agent(&agent) {
+                Ok(events) => events,
+                Err(e) => return Some(Err(e)),
+            };
+            for (hash, event) in agent_events {
+                if let StaticEvent::CgkaOperation(op) = &event {
+                    if DocumentId::from(*op.payload().doc_id()) != doc_id {
+                        continue;
+                    }
+                }
+                events.insert(hash, event);
+            }
+        }
+        Some(Ok(events.into_values().collect()))
+    }
+
+    /// The membership and CGKA ops of a document, oldest first
+    ///
+    /// Membership ops and CGKA ops are each in causal order. The two are
+    /// interleaved by the time in `recorded`, membership ops go first where
+    /// the time of either is unknown.
+    pub(crate) async fn access_log(
+        &self,
+        doc_id: DocumentId,
+        recorded: &HashMap<Digest<StaticEvent<CommitHash>>, UnixTimestamp>,
+    ) -> Option<Result<Vec<AccessLogEntry>, CgkaError>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+
+        let doc = keyhive.get_document(doc_id.into())?.borrow();
+        // topsort puts the most recent ops first
+        let membership =
+            MembershipOperation::topsort(doc.delegation_heads(), doc.revocation_heads())
+                .into_iter()
+                .rev()
+                .map(|(_, op)| match op {
+                    MembershipOperation::Delegation(dlg) => {
+                        let change = AccessLogChange::Added {
+                            member: PeerId::from(dlg.payload().delegate().id().0),
+                            access: dlg.payload().can().into(),
+                        };
+                        access_log_entry(*dlg.issuer(), change, dlg.into(), recorded)
+                    }
+                    MembershipOperation::Revocation(rev) => {
+                        let revoked = rev.payload().revoked().payload();
+                        let change = AccessLogChange::Removed {
+                            member: PeerId::from(revoked.delegate().id().0),
+                            access: revoked.can().into(),
+                        };
+                        access_log_entry(*rev.issuer(), change, rev.into(), recorded)
+                    }
+                })
+                .collect::<Vec<_>>();
+
+        let epochs = match doc.cgka_ops() {
+            Ok(epochs) => epochs,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut cgka = Vec::new();
+        for epoch in epochs.iter() {
+            for op in epoch.iter() {
+                let change = match op.payload() {
+                    CgkaOperation::Add { added_id, .. } => AccessLogChange::KeyShareAdded {
+                        member: PeerId::from(added_id.0 .0),
+                    },
+                    CgkaOperation::Remove { id, .. } => AccessLogChange::KeyShareRemoved {
+                        member: PeerId::from(id.0 .0),
+                    },
+                    CgkaOperation::Update { .. } => AccessLogChange::KeyRotated,
+                };
+                cgka.push(access_log_entry(
+                    *op.issuer(),
+                    change,
+                    op.clone().into(),
+                    recorded,
+                ));
+            }
+        }
+
+        Some(Ok(merge_by_recorded_time(membership, cgka)))
+    }
+
     pub(crate) async fn to_access_change(
         &self,
         evt: &keyhive_core::event::Event<Signer, CommitHash, crate::keyhive::Listener>,
@@ -603,7 +1331,7 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
             let doc_id = request.doc_id;
             let hash = match &request.payload {
                 crate::CommitOrBundle::Commit(commit) => commit.hash(),
-                crate::CommitOrBundle::Bundle(bundle) => *bundle.hash(),
+                crate::CommitOrBundle::Bundle(bundle) => bundle.end(),
             };
             let ciphertext = match &request.payload {
                 crate::CommitOrBundle::Commit(commit) => commit.contents().to_vec(),
@@ -678,6 +1406,73 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         Ok(id)
     }
 
+    /// Create a group owned by us and `owners` with each of `members`
+    /// delegated the given access
+    ///
+    /// Every owner and member is resolved before the group is generated so an
+    /// unknown group or document leaves keyhive untouched. The keyhive lock is
+    /// held throughout, so nothing observes the group without its members.
+    pub(crate) async fn create_group_with_members(
+        &self,
+        owners: Vec<KeyhiveEntityId>,
+        members: Vec<(KeyhiveEntityId, MemberAccess)>,
+    ) -> Result<CreatedGroup, CreateGroupWithMembers> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        // Looked up without `get_peer` so that nothing is registered if an
+        // entity turns out to be unknown
+        for entity in owners
+            .iter()
+            .chain(members.iter().map(|(member, _)| member))
+        {
+            match entity {
+                KeyhiveEntityId::Doc(d) if keyhive.get_agent(d.as_key().into()).is_none() => {
+                    return Err(CreateGroupWithMembers::NoSuchDocument);
+                }
+                KeyhiveEntityId::Group(g) if keyhive.get_agent(g.as_key().into()).is_none() => {
+                    return Err(CreateGroupWithMembers::NoSuchGroup);
+                }
+                _ => {}
+            }
+        }
+
+        let parents = owners
+            .into_iter()
+            .filter_map(|owner| get_peer(&mut *keyhive, owner))
+            .collect();
+        let group = keyhive
+            .generate_group(parents)
+            .await
+            .map_err(|e| CreateGroupWithMembers::Failed(e.to_string()))?;
+        let group_id: PeerId = group.borrow().id().0.into();
+
+        let mut member_ids = Vec::with_capacity(members.len());
+        for (member, access) in members {
+            let agent =
+                Agent::from(get_peer(&mut *keyhive, member).expect("members were resolved above"));
+            member_ids.push(agent.id());
+            keyhive
+                .add_member(agent, &mut group.clone().into(), access.into(), &[])
+                .await
+                .map_err(|e| CreateGroupWithMembers::Failed(e.to_string()))?;
+        }
+
+        let delegated: HashMap<_, _> = direct_members(&Agent::Group(group))
+            .into_iter()
+            .map(|(agent, access)| (agent.id(), (entity_id(&agent), access)))
+            .collect();
+        let members = member_ids
+            .iter()
+            .filter_map(|id| delegated.get(id))
+            .map(|(member, access)| GroupMember {
+                member: member.clone(),
+                access: MemberAccess::from(*access),
+            })
+            .collect();
+        Ok(CreatedGroup { group_id, members })
+    }
+
     pub(crate) async fn docs_accessible_to_agent(&self, agent_id: PeerId) -> Vec<DocumentId> {
         let k_mutex = self.0.borrow().keyhive.clone();
         let keyhive = k_mutex.lock().await;
@@ -735,6 +1530,35 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
         keyhive.into_archive()
     }
 
+    pub(crate) async fn ingest_archive(
+        &self,
+        archive: keyhive_core::archive::Archive<CommitHash>,
+    ) -> Result<(), String> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let mut keyhive = k_mutex.lock().await;
+
+        keyhive
+            .ingest_archive(archive)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// The access of every transitive member of every document we know of
+    pub(crate) async fn all_access(&self) -> HashMap<DocumentId, HashMap<PeerId, MemberAccess>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.lock().await;
+        all_access(&keyhive)
+    }
+
+    /// As with `all_access`, but `None` if keyhive is in use
+    pub(crate) fn try_all_access(
+        &self,
+    ) -> Option<HashMap<DocumentId, HashMap<PeerId, MemberAccess>>> {
+        let k_mutex = self.0.borrow().keyhive.clone();
+        let keyhive = k_mutex.try_lock()?;
+        Some(all_access(&keyhive))
+    }
+
     pub(crate) fn try_known_docs(&self) -> Option<HashSet<DocumentId>> {
         let k_mutex = self.0.borrow().keyhive.clone();
         let keyhive = k_mutex.try_lock()?;
@@ -757,6 +1581,24 @@ impl<'a, R: rand::Rng + rand::CryptoRng> KeyhiveCtx<'a, R> {
     }
 }
 
+fn all_access<R: rand::Rng + rand::CryptoRng>(
+    keyhive: &Beehive<R>,
+) -> HashMap<DocumentId, HashMap<PeerId, MemberAccess>> {
+    keyhive
+        .documents()
+        .iter()
+        .map(|(doc_id, doc)| {
+            let members = doc
+                .borrow()
+                .transitive_members()
+                .into_iter()
+                .map(|(id, (_, access))| (PeerId::from(id.0), MemberAccess::from(access)))
+                .collect();
+            (DocumentId::from(*doc_id), members)
+        })
+        .collect()
+}
+
 fn get_peer<R: rand::Rng + rand::CryptoRng>(
     keyhive: &mut Beehive<R>,
     agent_id: KeyhiveEntityId,
@@ -779,6 +1621,190 @@ fn get_peer<R: rand::Rng + rand::CryptoRng>(
     }
 }
 
+// The direct members of a group or document with the best access each has
+// been delegated, other agents have no members
+fn direct_members(
+    agent: &Agent<Signer, CommitHash, crate::keyhive::Listener>,
+) -> Vec<(
+    Agent<Signer, CommitHash, crate::keyhive::Listener>,
+    KeyhiveAccess,
+)> {
+    let membered = match agent {
+        Agent::Group(group) => Membered::from(group.clone()),
+        Agent::Document(doc) => Membered::from(doc.clone()),
+        Agent::Active(_) | Agent::Individual(_) => return Vec::new(),
+    };
+    membered
+        .members()
+        .into_values()
+        .map(|delegations| {
+            let delegate = delegations.head.payload().delegate().clone();
+            let access = delegations
+                .iter()
+                .map(|d| d.payload().can())
+                .max()
+                .unwrap_or(delegations.head.payload().can());
+            (delegate, access)
+        })
+        .collect()
+}
+
+// A hypothetical change to the direct members of a group
+struct MemberEdit {
+    group: Identifier,
+    member: Agent<Signer, CommitHash, crate::keyhive::Listener>,
+    // `None` removes the member
+    access: Option<KeyhiveAccess>,
+}
+
+// Every agent reachable from `root` via membership along with the best access
+// of any path to it, after applying `edit`. The root itself is never included.
+fn transitive_members(
+    root: &Agent<Signer, CommitHash, crate::keyhive::Listener>,
+    edit: Option<&MemberEdit>,
+) -> HashMap<
+    Identifier,
+    (
+        Agent<Signer, CommitHash, crate::keyhive::Listener>,
+        KeyhiveAccess,
+    ),
+> {
+    let members_of = |agent: &Agent<Signer, CommitHash, crate::keyhive::Listener>| {
+        let mut members = direct_members(agent);
+        if let Some(edit) = edit.filter(|e| e.group == agent.id()) {
+            let existing = members.iter().position(|(m, _)| m.id() == edit.member.id());
+            match (edit.access, existing) {
+                // Adding a member keeps any better access it already had
+                (Some(access), Some(idx)) => members[idx].1 = members[idx].1.max(access),
+                (Some(access), None) => members.push((edit.member.clone(), access)),
+                (None, Some(idx)) => {
+                    members.swap_remove(idx);
+                }
+                (None, None) => {}
+            }
+        }
+        members
+    };
+
+    let mut best = HashMap::<Identifier, (Agent<_, _, _>, KeyhiveAccess)>::new();
+    let mut explore = members_of(root);
+    while let Some((member, access)) = explore.pop() {
+        if member.id() == root.id() {
+            continue;
+        }
+        match best.entry(member.id()) {
+            Entry::Occupied(mut existing) => {
+                // Only revisit a member if we found a better path to it,
+                // access only ever increases so this terminates
+                if existing.get().1 >= access {
+                    continue;
+                }
+                existing.get_mut().1 = access;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((member.clone(), access));
+            }
+        }
+        explore.extend(
+            members_of(&member)
+                .into_iter()
+                .map(|(sub_member, sub_access)| (sub_member, sub_access.min(access))),
+        );
+    }
+    best
+}
+
+// The levels of access each entity gains or loses going from `before` to
+// `after`
+fn access_diff(
+    before: &HashMap<Identifier, (Agent<Signer, CommitHash, Listener>, KeyhiveAccess)>,
+    after: &HashMap<Identifier, (Agent<Signer, CommitHash, Listener>, KeyhiveAccess)>,
+) -> AccessDiff {
+    let mut diff = AccessDiff::default();
+    let ids = before.keys().chain(after.keys()).collect::<HashSet<_>>();
+    for id in ids {
+        let was = before.get(id).map(|(_, a)| MemberAccess::from(*a));
+        let now = after.get(id).map(|(_, a)| MemberAccess::from(*a));
+        if was == now {
+            continue;
+        }
+        let (agent, _) = after.get(id).or_else(|| before.get(id)).unwrap();
+        for level in MemberAccess::ALL {
+            let had = was.is_some_and(|a| a >= level);
+            let has = now.is_some_and(|a| a >= level);
+            let side = match (had, has) {
+                (false, true) => &mut diff.gained,
+                (true, false) => &mut diff.lost,
+                _ => continue,
+            };
+            side.entry(level).or_default().push(entity_id(agent));
+        }
+    }
+    diff
+}
+
+fn access_log_entry(
+    author: ed25519_dalek::VerifyingKey,
+    change: AccessLogChange,
+    event: keyhive_core::event::Event<Signer, CommitHash, Listener>,
+    recorded: &HashMap<Digest<StaticEvent<CommitHash>>, UnixTimestamp>,
+) -> AccessLogEntry {
+    let event = StaticEvent::from(event);
+    let digest = Digest::hash(&event);
+    AccessLogEntry {
+        digest: digest.into(),
+        author: PeerId::from(author),
+        recorded_at: recorded.get(&digest).copied(),
+        change,
+        event: event.encode(),
+    }
+}
+
+// Merge two lists which are each in causal order without reordering either
+fn merge_by_recorded_time(
+    first: Vec<AccessLogEntry>,
+    second: Vec<AccessLogEntry>,
+) -> Vec<AccessLogEntry> {
+    let mut merged = Vec::with_capacity(first.len() + second.len());
+    let mut first = first.into_iter().peekable();
+    let mut second = second.into_iter().peekable();
+    loop {
+        let take_second = match (first.peek(), second.peek()) {
+            (None, None) => break,
+            (Some(_), None) => false,
+            (None, Some(_)) => true,
+            (Some(a), Some(b)) => matches!(
+                (a.recorded_at, b.recorded_at),
+                (Some(a), Some(b)) if b < a
+            ),
+        };
+        merged.extend(if take_second {
+            second.next()
+        } else {
+            first.next()
+        });
+    }
+    merged
+}
+
+fn entity_id(
+    agent: &keyhive_core::principal::agent::Agent<Signer, CommitHash, crate::keyhive::Listener>,
+) -> KeyhiveEntityId {
+    if agent.id() == Public.id() {
+        return KeyhiveEntityId::Public;
+    }
+    match agent {
+        Agent::Active(active) => {
+            KeyhiveEntityId::Individual(ContactCard(active.borrow().individual().contact_card()))
+        }
+        Agent::Individual(individual) => {
+            KeyhiveEntityId::Individual(ContactCard(individual.borrow().contact_card()))
+        }
+        Agent::Group(group) => KeyhiveEntityId::Group(group.borrow().id().0.into()),
+        Agent::Document(doc) => KeyhiveEntityId::Doc(DocumentId::from(doc.borrow().id().0)),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum EncryptError {
     #[error("No such document")]
@@ -787,6 +1813,24 @@ pub(crate) enum EncryptError {
     Encrypt(#[from] keyhive_core::keyhive::EncryptContentError),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum GrantError {
+    #[error("member {0} not found")]
+    MemberNotFound(KeyhiveEntityId),
+    #[error("error granting access: {0}")]
+    Grant(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum RotateKeyError {
+    #[error("No such document")]
+    NoSuchDocument,
+    #[error(transparent)]
+    Update(#[from] keyhive_core::principal::document::EncryptError),
+    #[error(transparent)]
+    Cgka(#[from] CgkaError),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum DecryptError {
     #[error("No such document")]
@@ -0,0 +1,79 @@
+use std::{borrow::Cow, cell::RefCell, collections::HashSet, rc::Rc};
+
+use crate::{PeerId, UnixTimestamp};
+
+pub(crate) struct MembershipExpiries<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> MembershipExpiries<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        MembershipExpiries(state)
+    }
+
+    pub(crate) fn insert(&self, target: PeerId, member: PeerId, expires_at: UnixTimestamp) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state
+            .membership_expiries
+            .insert((target, member), expires_at);
+    }
+
+    // Returns true if there was an expiry for this membership
+    pub(crate) fn remove(&self, target: PeerId, member: PeerId) -> bool {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state
+            .membership_expiries
+            .remove(&(target, member))
+            .is_some()
+    }
+
+    pub(crate) fn get(&self, target: PeerId, member: PeerId) -> Option<UnixTimestamp> {
+        let state = RefCell::borrow(&self.0);
+        state.membership_expiries.get(&(target, member)).copied()
+    }
+
+    /// The `(target, member, expires_at)` of every expiry whose target is in
+    /// `targets`
+    pub(crate) fn for_targets(
+        &self,
+        targets: &HashSet<PeerId>,
+    ) -> Vec<(PeerId, PeerId, UnixTimestamp)> {
+        let state = RefCell::borrow(&self.0);
+        state
+            .membership_expiries
+            .iter()
+            .filter(|((target, _), _)| targets.contains(target))
+            .map(|((target, member), expires_at)| (*target, *member, *expires_at))
+            .collect()
+    }
+
+    pub(crate) fn num_expired(&self, now: UnixTimestamp) -> usize {
+        let state = RefCell::borrow(&self.0);
+        state
+            .membership_expiries
+            .values()
+            .filter(|expires_at| **expires_at <= now)
+            .count()
+    }
+
+    pub(crate) fn next_expiry(&self) -> Option<UnixTimestamp> {
+        let state = RefCell::borrow(&self.0);
+        state.membership_expiries.values().min().copied()
+    }
+
+    /// Remove and return the `(target, member, expires_at)` of every
+    /// membership which has expired
+    pub(crate) fn take_expired(&self, now: UnixTimestamp) -> Vec<(PeerId, PeerId, UnixTimestamp)> {
+        let mut state = RefCell::borrow_mut(&self.0);
+        let expired = state
+            .membership_expiries
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|((target, member), expires_at)| (*target, *member, *expires_at))
+            .collect::<Vec<_>>();
+        for (target, member, _) in &expired {
+            state.membership_expiries.remove(&(*target, *member));
+        }
+        expired
+    }
+}
@@ -0,0 +1,30 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use crate::{auth::Signed, seals::SealRecord, DocumentId};
+
+pub(crate) struct Seals<'a, R: rand::Rng + rand::CryptoRng>(Cow<'a, Rc<RefCell<super::State<R>>>>);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> Seals<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        Seals(state)
+    }
+
+    pub(crate) fn get(&self, doc_id: &DocumentId) -> Option<Signed<SealRecord>> {
+        let state = RefCell::borrow(&self.0);
+        state.seals.get(doc_id).cloned()
+    }
+
+    pub(crate) fn contains(&self, doc_id: &DocumentId) -> bool {
+        RefCell::borrow(&self.0).seals.contains_key(doc_id)
+    }
+
+    pub(crate) fn all(&self) -> Vec<Signed<SealRecord>> {
+        let state = RefCell::borrow(&self.0);
+        state.seals.values().cloned().collect()
+    }
+
+    pub(crate) fn insert(&self, record: Signed<SealRecord>) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.seals.insert(record.payload.doc_id, record);
+    }
+}
@@ -134,6 +134,10 @@ impl<'a, R: rand::Rng + rand::CryptoRng + Clone + 'static> Sessions<'a, R> {
         self.state.borrow_mut().sync_sessions.expire_sessions(now);
     }
 
+    pub(crate) fn next_expiry(&self) -> Option<UnixTimestampMillis> {
+        self.state.borrow().sync_sessions.next_expiry()
+    }
+
     pub(crate) fn num_sessions(&self) -> usize {
         self.state.borrow().sync_sessions.num_sessions()
     }
@@ -1,12 +1,12 @@
-use std::{borrow::Cow, cell::RefCell, rc::Rc};
+use std::{borrow::Cow, cell::RefCell, collections::HashMap, rc::Rc};
 
 use futures::channel::oneshot;
 
 use crate::{
     conn_info::ConnectionInfo,
     streams::{self, ConnRequestId, EstablishedStream, HandledMessage},
-    PeerId, Request, Response, StreamDirection, StreamError, StreamId, UnixTimestamp,
-    UnixTimestampMillis,
+    CommitHash, DocumentId, PeerId, Request, Response, StreamCodec, StreamDirection, StreamError,
+    StreamId, StreamMetrics, StreamResumptionToken, UnixTimestamp, UnixTimestampMillis,
 };
 
 pub(crate) struct Streams<'a, R: rand::Rng + rand::CryptoRng> {
@@ -22,12 +22,15 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Streams<'a, R> {
         &mut self,
         now: UnixTimestamp,
         stream_direction: StreamDirection,
+        codecs: Vec<StreamCodec>,
+        resume_from: Option<StreamResumptionToken>,
     ) -> StreamId {
-        let stream_id = self
-            .state
-            .borrow_mut()
-            .streams
-            .new_stream(now, stream_direction.clone());
+        let stream_id = self.state.borrow_mut().streams.new_stream(
+            now,
+            stream_direction.clone(),
+            codecs,
+            resume_from,
+        );
         stream_id
     }
 
@@ -36,6 +39,10 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Streams<'a, R> {
         Ok(())
     }
 
+    pub(crate) fn list(&self) -> Vec<(StreamId, StreamDirection, streams::StreamState)> {
+        self.state.borrow().streams.list().collect()
+    }
+
     pub(crate) fn established(&self) -> Vec<EstablishedStream> {
         self.state.borrow().streams.established().collect()
     }
@@ -68,6 +75,79 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Streams<'a, R> {
             .clear_received_sync_needed(stream_id);
     }
 
+    pub(crate) fn record_remote_heads(
+        &mut self,
+        stream_id: StreamId,
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    ) {
+        self.state
+            .borrow_mut()
+            .streams
+            .record_remote_heads(stream_id, doc_id, heads);
+    }
+
+    pub(crate) fn remote_heads(
+        &self,
+        stream_id: StreamId,
+        doc_id: &DocumentId,
+    ) -> Result<Option<Vec<CommitHash>>, StreamError> {
+        self.state.borrow().streams.remote_heads(stream_id, doc_id)
+    }
+
+    pub(crate) fn take_resumption(&mut self, stream_id: StreamId) -> Option<StreamResumptionToken> {
+        self.state.borrow_mut().streams.take_resumption(stream_id)
+    }
+
+    pub(crate) fn record_agreed_state(
+        &mut self,
+        stream_id: StreamId,
+        synced_at: UnixTimestamp,
+        local_state: [u8; 32],
+    ) {
+        self.state
+            .borrow_mut()
+            .streams
+            .record_agreed_state(stream_id, synced_at, local_state);
+    }
+
+    pub(crate) fn resumption_token(
+        &self,
+        stream_id: StreamId,
+    ) -> Result<Option<StreamResumptionToken>, StreamError> {
+        self.state.borrow().streams.resumption_token(stream_id)
+    }
+
+    pub(crate) fn record_sent(&mut self, stream_id: StreamId, size: usize) {
+        self.state.borrow_mut().streams.record_sent(stream_id, size)
+    }
+
+    pub(crate) fn metrics(&self, stream_id: StreamId) -> Result<StreamMetrics, StreamError> {
+        self.state.borrow().streams.metrics(stream_id)
+    }
+
+    pub(crate) fn restart_handshake(
+        &mut self,
+        now: UnixTimestamp,
+        stream_id: StreamId,
+    ) -> Result<oneshot::Receiver<Result<(), streams::HandshakeError>>, StreamError> {
+        self.state
+            .borrow_mut()
+            .streams
+            .restart_handshake(now, stream_id)
+    }
+
+    pub(crate) fn handshake_error(
+        &self,
+        stream_id: StreamId,
+    ) -> Result<Option<streams::HandshakeError>, StreamError> {
+        self.state.borrow().streams.handshake_error(stream_id)
+    }
+
+    pub(crate) fn all_metrics(&self) -> HashMap<StreamId, StreamMetrics> {
+        self.state.borrow().streams.all_metrics()
+    }
+
     pub(crate) fn take_changed(&mut self) -> Vec<ConnectionInfo> {
         self.state.borrow_mut().streams.take_changed()
     }
@@ -77,7 +157,7 @@ impl<'a, R: rand::Rng + rand::CryptoRng> Streams<'a, R> {
         now: UnixTimestampMillis,
         stream_id: StreamId,
         msg: Vec<u8>,
-    ) -> Result<Option<HandledMessage>, streams::error::StreamError> {
+    ) -> Result<Vec<HandledMessage>, streams::error::StreamError> {
         let mut state = self.state.borrow_mut();
         state
             .streams
@@ -0,0 +1,32 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use crate::{auth::Signed, tombstones::TombstoneRecord, DocumentId};
+
+pub(crate) struct Tombstones<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> Tombstones<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        Tombstones(state)
+    }
+
+    pub(crate) fn get(&self, doc_id: &DocumentId) -> Option<Signed<TombstoneRecord>> {
+        let state = RefCell::borrow(&self.0);
+        state.tombstones.get(doc_id).cloned()
+    }
+
+    pub(crate) fn contains(&self, doc_id: &DocumentId) -> bool {
+        RefCell::borrow(&self.0).tombstones.contains_key(doc_id)
+    }
+
+    pub(crate) fn all(&self) -> Vec<Signed<TombstoneRecord>> {
+        let state = RefCell::borrow(&self.0);
+        state.tombstones.values().cloned().collect()
+    }
+
+    pub(crate) fn insert(&self, record: Signed<TombstoneRecord>) {
+        let mut state = RefCell::borrow_mut(&self.0);
+        state.tombstones.insert(record.payload.doc_id, record);
+    }
+}
@@ -0,0 +1,45 @@
+use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+use crate::{sedimentree::Sedimentree, CommitOrBundle, DocumentId};
+
+/// The decrypted contents of a document warmed with `warm_docs`, along with
+/// the tree they were loaded from
+pub(crate) struct WarmDoc {
+    tree: Sedimentree,
+    items: Vec<CommitOrBundle>,
+}
+
+pub(crate) struct WarmDocs<'a, R: rand::Rng + rand::CryptoRng>(
+    Cow<'a, Rc<RefCell<super::State<R>>>>,
+);
+
+impl<'a, R: rand::Rng + rand::CryptoRng> WarmDocs<'a, R> {
+    pub(crate) fn new(state: Cow<'a, Rc<RefCell<super::State<R>>>>) -> Self {
+        WarmDocs(state)
+    }
+
+    pub(crate) fn insert(&self, doc_id: DocumentId, tree: Sedimentree, items: Vec<CommitOrBundle>) {
+        RefCell::borrow_mut(&self.0)
+            .warm_docs
+            .insert(doc_id, WarmDoc { tree, items });
+    }
+
+    pub(crate) fn is_warm(&self, doc_id: &DocumentId) -> bool {
+        RefCell::borrow(&self.0).warm_docs.contains_key(doc_id)
+    }
+
+    /// The cached contents of `doc_id` if they were loaded from `tree`
+    pub(crate) fn get(
+        &self,
+        doc_id: &DocumentId,
+        tree: &Sedimentree,
+    ) -> Option<Vec<CommitOrBundle>> {
+        let state = RefCell::borrow(&self.0);
+        let warm = state.warm_docs.get(doc_id)?;
+        (warm.tree == *tree).then(|| warm.items.clone())
+    }
+
+    pub(crate) fn remove(&self, doc_id: &DocumentId) {
+        RefCell::borrow_mut(&self.0).warm_docs.remove(doc_id);
+    }
+}
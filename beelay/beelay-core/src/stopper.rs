@@ -17,7 +17,7 @@ static LAST_STOP_WAITER_ID: AtomicU64 = AtomicU64::new(0);
 
 impl StopWaiterId {
     fn new() -> Self {
-        Self(LAST_STOP_WAITER_ID.fetch_add(1, Ordering::Relaxed))
+        Self(crate::ids::next(&LAST_STOP_WAITER_ID))
     }
 }
 
@@ -0,0 +1,144 @@
+use chacha20poly1305::{aead::Aead, KeyInit, XChaCha20Poly1305, XNonce};
+
+use crate::{error, io::IoHandle, task_context::Storage, StorageKey};
+
+const NONCE_LEN: usize = 24;
+
+/// A key used to encrypt the values beelay writes to storage, see
+/// [`Config::storage_encryption_key`](crate::Config::storage_encryption_key)
+///
+/// Only values are encrypted, storage keys are written in plaintext and
+/// reveal which documents, commits and blobs are stored. This is independent of the keys keyhive uses to encrypt the content of
+/// documents. To protect storage with a passphrase derive the key from the
+/// passphrase with a password hashing function such as Argon2.
+#[derive(Clone, PartialEq, Eq)]
+pub struct StorageEncryptionKey([u8; 32]);
+
+impl std::fmt::Debug for StorageEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StorageEncryptionKey(..)")
+    }
+}
+
+impl StorageEncryptionKey {
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    // Each value is encrypted with XChaCha20Poly1305 and stored with its
+    // nonce in front. The nonce is a keyed hash of the storage key and the
+    // value so that no randomness is needed and distinct values never share
+    // a nonce. The storage key is also the associated data, so a value moved
+    // to another key fails to decrypt.
+    pub(crate) fn encrypt(&self, key: &StorageKey, data: &[u8]) -> Vec<u8> {
+        let aad = associated_data(key);
+        let mut hasher = blake3::Hasher::new_keyed(&self.0);
+        hasher.update(&(aad.len() as u64).to_be_bytes());
+        hasher.update(&aad);
+        hasher.update(data);
+        let mut nonce = [0; NONCE_LEN];
+        hasher.finalize_xof().fill(&mut nonce);
+
+        let ciphertext = self
+            .cipher()
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                chacha20poly1305::aead::Payload {
+                    msg: data,
+                    aad: &aad,
+                },
+            )
+            .expect("encrypting into a vec never fails");
+        let mut out = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&nonce);
+        out.extend(ciphertext);
+        out
+    }
+
+    /// Decrypt a value written by `encrypt`, `None` if it was written with a
+    /// different key, to a different storage key or not encrypted at all
+    pub(crate) fn decrypt(&self, key: &StorageKey, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        self.cipher()
+            .decrypt(
+                XNonce::from_slice(nonce),
+                chacha20poly1305::aead::Payload {
+                    msg: ciphertext,
+                    aad: &associated_data(key),
+                },
+            )
+            .ok()
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new((&self.0).into())
+    }
+}
+
+// Written when empty storage is first loaded with a key so that loading it
+// without one fails rather than reading ciphertext
+fn marker_key() -> StorageKey {
+    StorageKey::auth().push("storage_encryption")
+}
+
+/// Check that storage is only loaded with a storage encryption key if it was
+/// written with one
+///
+/// Loading encrypted storage with the wrong key is caught by the driver when
+/// the marker fails to decrypt. Storage which already holds plaintext is
+/// rejected without writing the marker, so that it can still be loaded
+/// without a key afterwards.
+pub(crate) async fn check(io: IoHandle, encrypted: bool) -> Result<(), error::Load> {
+    let storage = Storage::new(&io);
+    let marker = storage.load(marker_key()).await;
+    match (encrypted, marker) {
+        (true, None) => {
+            // Listing keys doesn't read any values, so plaintext ones aren't
+            // flagged as undecryptable
+            for prefix in [StorageKey::auth(), StorageKey::sedimentrees()] {
+                if !storage.list_one_level(prefix).await.is_empty() {
+                    return Err(error::Load::WrongStorageKey);
+                }
+            }
+            storage.put(marker_key(), Vec::new()).await;
+            Ok(())
+        }
+        (false, Some(_)) => Err(error::Load::WrongStorageKey),
+        _ => Ok(()),
+    }
+}
+
+fn associated_data(key: &StorageKey) -> Vec<u8> {
+    let mut out = Vec::new();
+    for component in key.components() {
+        out.extend_from_slice(&(component.len() as u64).to_be_bytes());
+        out.extend_from_slice(component.as_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StorageEncryptionKey;
+    use crate::StorageKey;
+
+    #[test]
+    fn values_only_decrypt_with_the_same_key_and_storage_key() {
+        let key = StorageEncryptionKey::from_bytes([1; 32]);
+        let storage_key = StorageKey::auth().push("a");
+        let encrypted = key.encrypt(&storage_key, b"hello");
+        assert_ne!(&encrypted[24..], b"hello");
+        assert_eq!(
+            key.decrypt(&storage_key, &encrypted),
+            Some(b"hello".to_vec())
+        );
+
+        let other = StorageEncryptionKey::from_bytes([2; 32]);
+        assert_eq!(other.decrypt(&storage_key, &encrypted), None);
+        assert_eq!(key.decrypt(&StorageKey::auth().push("b"), &encrypted), None);
+        assert_eq!(key.decrypt(&storage_key, b"hello"), None);
+    }
+}
@@ -148,6 +148,34 @@ impl StorageKey {
         }
     }
 
+    /// The root of a namespace set with `Config::storage_namespace`, `None`
+    /// if the name is empty, contains a slash or is one of the namespaces
+    /// beelay uses itself
+    pub(crate) fn namespace_root(namespace: &str) -> Option<StorageKey> {
+        let key = StorageKey::try_from(vec![namespace.to_string()]).ok()?;
+        match key.namespace {
+            Namespace::Other(ref name) if !name.is_empty() => Some(key),
+            _ => None,
+        }
+    }
+
+    /// This key moved inside `namespace`, so that `sedimentrees/doc123`
+    /// becomes `namespace/sedimentrees/doc123`
+    pub(crate) fn within(&self, namespace: &str) -> StorageKey {
+        StorageKey {
+            namespace: Namespace::Other(namespace.to_string()),
+            remaining: self.components().map(str::to_string).collect(),
+        }
+    }
+
+    /// The inverse of `within`, `None` if this key isn't inside `namespace`
+    pub(crate) fn without(&self, namespace: &str) -> Option<StorageKey> {
+        if self.namespace() != namespace {
+            return None;
+        }
+        StorageKey::try_from(self.remaining.clone()).ok()
+    }
+
     /// Checks if this key is a prefix of another key.
     ///
     /// A key is considered a prefix if:
@@ -1,21 +1,26 @@
 use crate::{
+    doc_status::SyncPriority,
     network::{
         messages::{self},
         PeerAddress, RpcError,
     },
     riblt,
+    serialization::Encode,
     task_context::SessionRpcError,
-    PeerId, TaskContext,
+    PeerId, TaskContext, SYNC_INTERVAL,
 };
 
 pub(crate) mod server_session;
 mod session_id;
 pub(crate) use session_id::SessionId;
 mod sync_doc;
-pub(crate) use sync_doc::CgkaSymbol;
+pub(crate) use sync_doc::{fetch_commits, sync_sedimentree, CgkaSymbol, SyncSedimentreeError};
 mod sync_docs;
 pub(crate) use sync_docs::DocStateHash;
+mod sync_group_metadata;
 mod sync_membership;
+mod sync_seals;
+mod sync_tombstones;
 pub(crate) use sync_membership::MembershipSymbol;
 pub(crate) mod sessions;
 pub(crate) use sessions::Sessions;
@@ -30,10 +35,11 @@ pub(crate) async fn sync_with_peer<R: rand::Rng + rand::CryptoRng + Clone + 'sta
     ctx: TaskContext<R>,
     target: PeerAddress,
     remote_peer_id: PeerId,
+    receive_only: bool,
 ) -> Result<(), Error> {
     let mut retries = 0;
     loop {
-        match sync_inner(ctx.clone(), target, remote_peer_id).await {
+        match sync_inner(ctx.clone(), target, remote_peer_id, receive_only).await {
             Ok(_) => return Ok(()),
             Err(Error::SessionExpired) => {
                 if retries < MAX_RETRIES {
@@ -54,12 +60,79 @@ pub(crate) async fn sync_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>
     ctx: TaskContext<R>,
     peer_address: PeerAddress,
     remote_peer_id: PeerId,
+    receive_only: bool,
 ) -> Result<(), Error> {
     let local_membership = MembershipState::load(ctx.clone(), remote_peer_id).await;
     let local_docs = ReachableDocs::load(ctx.clone(), remote_peer_id)
         .await
         .unwrap();
+    let local_state = state_digest(&local_membership, &local_docs);
 
+    let PeerAddress::Stream(stream_id) = peer_address else {
+        run_session(
+            ctx,
+            peer_address,
+            remote_peer_id,
+            receive_only,
+            local_membership,
+            local_docs,
+        )
+        .await?;
+        return Ok(());
+    };
+
+    // A stream created from a resumption token for a state we still have
+    // doesn't need to offer the remote anything. Anything the remote changed
+    // whilst we were disconnected is picked up by the next periodic sync.
+    if let Some(token) = ctx.state().streams().take_resumption(stream_id) {
+        let fresh = ctx.now().as_secs() <= token.synced_at + SYNC_INTERVAL;
+        if fresh && token.local_state == local_state {
+            tracing::debug!(?stream_id, "resuming stream without syncing");
+            ctx.state()
+                .streams()
+                .record_agreed_state(stream_id, token.synced_at, local_state);
+            return Ok(());
+        }
+        tracing::debug!(
+            ?stream_id,
+            fresh,
+            "resumption token is out of date, falling back to a full sync"
+        );
+    }
+
+    let already_in_sync = run_session(
+        ctx.clone(),
+        peer_address,
+        remote_peer_id,
+        receive_only,
+        local_membership,
+        local_docs,
+    )
+    .await?;
+    let local_state = if already_in_sync {
+        local_state
+    } else {
+        let membership = MembershipState::load(ctx.clone(), remote_peer_id).await;
+        let docs = ReachableDocs::load(ctx.clone(), remote_peer_id).await?;
+        state_digest(&membership, &docs)
+    };
+    ctx.state()
+        .streams()
+        .record_agreed_state(stream_id, ctx.now().as_secs(), local_state);
+    lower_synced_priorities(&ctx);
+    Ok(())
+}
+
+// Run a sync session with the remote, returns true if the remote told us we
+// were already in sync
+async fn run_session<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    peer_address: PeerAddress,
+    remote_peer_id: PeerId,
+    receive_only: bool,
+    local_membership: MembershipState,
+    local_docs: ReachableDocs,
+) -> Result<bool, Error> {
     let mut member_riblt = riblt::Encoder::new();
     for evt in local_membership.clone().into_static_events().values() {
         member_riblt.add_symbol(&MembershipSymbol::from(evt));
@@ -79,10 +152,11 @@ pub(crate) async fn sync_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>
         .await??;
     let mut seq = 0;
 
+    let already_in_sync = matches!(phase, messages::session::NextSyncPhase::Done);
     let remote_doc_symbols = match phase {
-        messages::session::NextSyncPhase::Docs(symbols) => symbols,
+        messages::session::NextSyncPhase::Docs(symbols) => Some(symbols),
         messages::session::NextSyncPhase::Membership(symbols) => {
-            let doc_symbols = sync_membership::sync_membership(
+            sync_membership::sync_membership(
                 ctx.clone(),
                 session_id,
                 &mut seq,
@@ -90,17 +164,45 @@ pub(crate) async fn sync_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>
                 local_membership.into_static_events(),
                 remote_peer_id,
                 peer_address,
+                receive_only,
             )
-            .await?;
-            if let Some(doc_symbols) = doc_symbols {
-                doc_symbols
-            } else {
-                return Ok(());
-            }
-        }
-        messages::session::NextSyncPhase::Done => {
-            return Ok(());
+            .await?
         }
+        messages::session::NextSyncPhase::Done => None,
+    };
+
+    // Group metadata isn't part of the membership or document state so it is
+    // exchanged even if those are already in sync
+    sync_group_metadata::sync_group_metadata(
+        ctx.clone(),
+        session_id,
+        remote_peer_id,
+        peer_address,
+        receive_only,
+    )
+    .await?;
+
+    // Tombstones are exchanged before documents so that neither side sends
+    // the other content which is about to be purged
+    sync_tombstones::sync_tombstones(
+        ctx.clone(),
+        session_id,
+        remote_peer_id,
+        peer_address,
+        receive_only,
+    )
+    .await?;
+    sync_seals::sync_seals(
+        ctx.clone(),
+        session_id,
+        remote_peer_id,
+        peer_address,
+        receive_only,
+    )
+    .await?;
+
+    let Some(remote_doc_symbols) = remote_doc_symbols else {
+        return Ok(already_in_sync);
     };
 
     let out_of_sync = sync_docs::sync_docs(
@@ -110,13 +212,31 @@ pub(crate) async fn sync_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>
         remote_doc_symbols,
         remote_peer_id,
         peer_address,
+        receive_only,
     )
     .await?;
 
     tracing::trace!(?out_of_sync, "completed doc collection state sync");
 
-    // Sync individual documents
-    for doc_id in out_of_sync.out_of_sync {
+    // Documents whose state matched the remote have the same heads on both ends
+    if let PeerAddress::Stream(stream_id) = peer_address {
+        for doc_id in out_of_sync.in_sync {
+            if let Some(heads) = ctx.state().docs().doc_status(doc_id).local_heads {
+                ctx.state()
+                    .streams()
+                    .record_remote_heads(stream_id, doc_id, heads);
+            }
+        }
+    }
+
+    // Sync individual documents, most urgent first
+    let mut out_of_sync_docs = out_of_sync.out_of_sync.into_iter().collect::<Vec<_>>();
+    out_of_sync_docs
+        .sort_by_key(|doc_id| std::cmp::Reverse(ctx.state().docs().sync_priority(doc_id)));
+    for doc_id in out_of_sync_docs {
+        if ctx.state().docs().is_sync_paused(&doc_id) {
+            continue;
+        }
         // TODO: Do this concurrently
         sync_doc::sync_doc(
             ctx.clone(),
@@ -124,11 +244,64 @@ pub(crate) async fn sync_inner<R: rand::Rng + rand::CryptoRng + Clone + 'static>
             remote_peer_id,
             session_id,
             doc_id,
+            receive_only,
         )
         .await?;
     }
 
-    Ok(())
+    Ok(false)
+}
+
+// A high priority document goes back to normal priority once it is in sync
+// on every established stream
+fn lower_synced_priorities<R: rand::Rng + rand::CryptoRng + Clone + 'static>(ctx: &TaskContext<R>) {
+    let streams = ctx.state().streams().established();
+    for doc_id in ctx.state().docs().high_priority_docs() {
+        let Some(mut local_heads) = ctx.state().docs().doc_status(doc_id).local_heads else {
+            continue;
+        };
+        local_heads.sort();
+        let in_sync_everywhere = streams.iter().all(|stream| {
+            match ctx.state().streams().remote_heads(stream.id, &doc_id) {
+                Ok(Some(mut remote_heads)) => {
+                    remote_heads.sort();
+                    remote_heads == local_heads
+                }
+                _ => false,
+            }
+        });
+        if in_sync_everywhere {
+            tracing::trace!(%doc_id, "high priority document is in sync, lowering its priority");
+            ctx.state()
+                .docs()
+                .set_sync_priority(doc_id, SyncPriority::Normal);
+        }
+    }
+}
+
+// A digest of the membership and document states reachable by a peer
+fn state_digest(membership: &MembershipState, docs: &ReachableDocs) -> [u8; 32] {
+    let mut events = membership
+        .clone()
+        .into_static_events()
+        .into_keys()
+        .collect::<Vec<_>>();
+    events.sort();
+    let mut doc_states = docs
+        .doc_states
+        .values()
+        .map(|d| d.hash.encode())
+        .collect::<Vec<_>>();
+    doc_states.sort();
+
+    let mut hasher = blake3::Hasher::new();
+    for event in events {
+        hasher.update(event.as_slice());
+    }
+    for doc_state in doc_states {
+        hasher.update(&doc_state);
+    }
+    *hasher.finalize().as_bytes()
 }
 
 #[derive(Debug, thiserror::Error)]
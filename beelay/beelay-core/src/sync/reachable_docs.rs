@@ -36,7 +36,10 @@ impl ReachableDocs {
             .state()
             .keyhive()
             .docs_accessible_to_agent(for_remote)
-            .await;
+            .await
+            .into_iter()
+            .filter(|doc_id| !ctx.state().docs().is_sync_paused(doc_id))
+            .collect::<Vec<_>>();
         tracing::trace!(num_docs = docs.len(), "loaded accessible docs");
         let doc_states = docs.iter().map(|doc_id| {
             let ctx = ctx.clone();
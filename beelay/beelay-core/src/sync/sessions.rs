@@ -168,6 +168,11 @@ impl Sessions {
         }
     }
 
+    /// When the next active session will expire
+    pub(crate) fn next_expiry(&self) -> Option<UnixTimestampMillis> {
+        self.sessions_by_expiry.peek().map(|key| key.expires_at)
+    }
+
     pub(crate) fn num_sessions(&self) -> usize {
         self.sessions.len()
     }
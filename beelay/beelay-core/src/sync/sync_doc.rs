@@ -5,6 +5,7 @@ use super::SessionId;
 mod sync_cgka;
 pub(crate) use sync_cgka::CgkaSymbol;
 mod sync_sedimentree;
+pub(crate) use sync_sedimentree::{fetch_commits, sync_sedimentree, SyncSedimentreeError};
 
 #[tracing::instrument(skip(ctx))]
 pub(crate) async fn sync_doc<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
@@ -13,16 +14,38 @@ pub(crate) async fn sync_doc<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     peer_id: PeerId,
     session_id: SessionId,
     doc_id: DocumentId,
+    receive_only: bool,
 ) -> Result<(), crate::sync::Error> {
     tracing::trace!("syncing document");
+    if ctx.state().tombstones().contains(&doc_id) {
+        tracing::trace!("not syncing tombstoned document");
+        return Ok(());
+    }
 
-    sync_sedimentree::sync_sedimentree(ctx.clone(), peer_address, peer_id, doc_id)
+    // Once we have everything up to the seal of a sealed document any other
+    // commits the remote has were added after it, so none are fetched. The
+    // remote refuses our uploads itself.
+    if crate::seals::has_sealed_content(&ctx, doc_id) {
+        tracing::trace!("not syncing content of sealed document");
+    } else {
+        let synced = sync_sedimentree::sync_sedimentree(
+            ctx.clone(),
+            peer_address,
+            peer_id,
+            doc_id,
+            receive_only,
+        )
         .await
         .map_err(|e| {
             tracing::error!(err=?e, "syncing sedimentree failed");
             crate::sync::Error::Other(format!("failed to sync sedimentree: {:?}", e))
         })?;
-    sync_cgka::sync_cgka(ctx.clone(), peer_address, session_id, doc_id).await?;
+        // The remote has paused syncing this document
+        if !synced {
+            return Ok(());
+        }
+    }
+    sync_cgka::sync_cgka(ctx.clone(), peer_address, session_id, doc_id, receive_only).await?;
 
     Ok(())
 }
@@ -19,6 +19,7 @@ pub(super) async fn sync_cgka<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     peer_address: PeerAddress,
     session_id: SessionId,
     doc_id: DocumentId,
+    receive_only: bool,
 ) -> Result<(), super::super::Error> {
     let mut decoder = riblt::Decoder::new();
     let local_ops = ctx
@@ -94,7 +95,9 @@ pub(super) async fn sync_cgka<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
         .collect::<Vec<_>>();
 
     let upload = async {
-        if !to_upload.is_empty() {
+        if receive_only {
+            tracing::trace!("not uploading cgka ops on receive only stream");
+        } else if !to_upload.is_empty() {
             tracing::trace!(num_to_upload = to_upload.len(), "uploading cgka ops");
             ctx.requests()
                 .upload_cgka_ops(peer_address, to_upload)
@@ -1,5 +1,8 @@
+use std::collections::HashSet;
+
 use crate::{
     blob::BlobMeta,
+    commit_meta,
     network::{
         messages::{FetchedSedimentree, UploadItem},
         PeerAddress, RpcError,
@@ -7,15 +10,17 @@ use crate::{
     parse::{self, Parse},
     sedimentree,
     state::DocUpdateBuilder,
-    BlobHash, Commit, CommitBundle, DocumentId, PeerId, StorageKey, TaskContext,
+    BlobHash, Commit, CommitBundle, CommitHash, DocumentId, PeerId, StreamId, TaskContext,
 };
 
+// Returns false if the remote has paused syncing the document
 pub(crate) async fn sync_sedimentree<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     ctx: TaskContext<R>,
     peer_address: PeerAddress,
     peer_id: PeerId,
     doc_id: DocumentId,
-) -> Result<(), SyncSedimentreeError> {
+    receive_only: bool,
+) -> Result<bool, SyncSedimentreeError> {
     let storage = ctx.storage().doc_storage(doc_id);
     let local_tree = sedimentree::storage::load(storage)
         .await
@@ -28,6 +33,10 @@ pub(crate) async fn sync_sedimentree<R: rand::Rng + rand::CryptoRng + Clone + 's
     {
         FetchedSedimentree::Found(t) => Some(t),
         FetchedSedimentree::NotFound => None,
+        FetchedSedimentree::Paused => {
+            tracing::trace!("remote has paused syncing the document");
+            return Ok(false);
+        }
     };
 
     tracing::trace!(?local_tree, ?remote_tree, "syncing document");
@@ -41,7 +50,7 @@ pub(crate) async fn sync_sedimentree<R: rand::Rng + rand::CryptoRng + Clone + 's
         (Some(local), Some(remote)) => local.diff_remote(remote),
         (None, Some(remote)) => remote.as_remote_diff(),
         (Some(local), None) => local.as_local_diff(),
-        (None, None) => return Ok(()),
+        (None, None) => return Ok(true),
     };
 
     let download = download_missing(
@@ -52,16 +61,95 @@ pub(crate) async fn sync_sedimentree<R: rand::Rng + rand::CryptoRng + Clone + 's
         remote_strata,
         remote_commits,
     );
-    let upload = upload_missing(
-        ctx.clone(),
-        peer_address,
-        doc_id,
-        local_commits,
-        local_strata,
-    );
+    if receive_only {
+        download.await?;
+    } else {
+        let upload = upload_missing(
+            ctx.clone(),
+            peer_address,
+            doc_id,
+            local_commits,
+            local_strata,
+        );
+        futures::future::try_join(download, upload).await?;
+    }
+
+    if let PeerAddress::Stream(stream_id) = peer_address {
+        record_remote_heads(
+            &ctx,
+            stream_id,
+            doc_id,
+            local_tree.as_ref(),
+            remote_tree.as_ref(),
+            receive_only,
+        )
+        .await?;
+    }
 
-    futures::future::try_join(download, upload).await?;
+    Ok(true)
+}
 
+/// Download the commits and strata of `doc_id` from `peer_address` which
+/// are, or have a boundary which is, one of `wanted`
+pub(crate) async fn fetch_commits<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    peer_address: PeerAddress,
+    peer_id: PeerId,
+    doc_id: DocumentId,
+    wanted: &HashSet<CommitHash>,
+) -> Result<(), SyncSedimentreeError> {
+    let remote_tree = match ctx
+        .requests()
+        .fetch_sedimentrees(peer_address, doc_id)
+        .await?
+    {
+        FetchedSedimentree::Found(t) => t,
+        FetchedSedimentree::NotFound | FetchedSedimentree::Paused => return Ok(()),
+    };
+    let diff = remote_tree.as_remote_diff();
+    let strata = diff
+        .remote_strata
+        .into_iter()
+        .filter(|s| wanted.contains(&s.start()) || wanted.contains(&s.end()))
+        .collect::<Vec<_>>();
+    let commits = diff
+        .remote_commits
+        .into_iter()
+        .filter(|c| wanted.contains(&c.hash()))
+        .collect::<Vec<_>>();
+    download_missing(ctx, peer_address, peer_id, doc_id, strata, commits).await
+}
+
+// Remember what the peer on the other end of `stream_id` now has for this
+// document. Everything the remote had is now stored locally, so the remote
+// tree is the part of our tree which was in their summary, plus everything we
+// uploaded to them.
+async fn record_remote_heads<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: &TaskContext<R>,
+    stream_id: StreamId,
+    doc_id: DocumentId,
+    local_tree: Option<&sedimentree::Sedimentree>,
+    remote_tree: Option<&sedimentree::SedimentreeSummary>,
+    receive_only: bool,
+) -> Result<(), SyncSedimentreeError> {
+    let synced = sedimentree::storage::load(ctx.storage().doc_storage(doc_id))
+        .await
+        .map_err(|e| SyncSedimentreeError::Storage(e.to_string()))?
+        .unwrap_or_default();
+    let mut remote = remote_tree
+        .map(|summary| synced.restrict_to(summary))
+        .unwrap_or_default();
+    if let (false, Some(local)) = (receive_only, local_tree) {
+        for stratum in local.strata() {
+            remote.add_stratum(stratum.clone());
+        }
+        for commit in local.loose_commits() {
+            remote.add_commit(commit.clone());
+        }
+    }
+    ctx.state()
+        .streams()
+        .record_remote_heads(stream_id, doc_id, remote.heads());
     Ok(())
 }
 
@@ -88,11 +176,12 @@ where
             let (_, stratum) = sedimentree::Stratum::parse(parse::Input::new(&blob))
                 .map_err(|_| SyncSedimentreeError::CorruptStratum)?;
             ctx.storage()
-                .put(StorageKey::blob(BlobMeta::new(&blob).hash()), blob.clone())
+                .put_doc_blob(&doc_id, BlobMeta::new(&blob).hash(), blob.clone())
                 .await;
             sedimentree::storage::write_stratum(ctx.storage().doc_storage(doc_id), stratum.clone())
                 .await
                 .map_err(|e| SyncSedimentreeError::Storage(e.to_string()))?;
+            commit_meta::record_bundle(&ctx, doc_id, stratum.start(), stratum.end()).await;
             let bundle = CommitBundle::builder()
                 .start(stratum.start())
                 .end(stratum.end())
@@ -111,11 +200,12 @@ where
                 .await?
                 .ok_or(SyncSedimentreeError::MissingBlob)?;
             ctx.storage()
-                .put(StorageKey::blob(BlobHash::hash_of(&blob)), blob.clone())
+                .put_doc_blob(&doc_id, BlobHash::hash_of(&blob), blob.clone())
                 .await;
             sedimentree::storage::write_loose_commit(ctx.storage().doc_storage(doc_id), c)
                 .await
                 .map_err(|e| SyncSedimentreeError::Storage(e.to_string()))?;
+            commit_meta::record_commit(&ctx, doc_id, c.hash(), peer_id).await;
             let commit = Commit::new(c.parents().to_vec(), blob, c.hash());
             Ok::<_, SyncSedimentreeError>(commit)
         }
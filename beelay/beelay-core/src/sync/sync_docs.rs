@@ -22,7 +22,6 @@ use super::{reachable_docs::DocState, riblt, SessionId};
 #[derive(Debug)]
 pub(super) struct SyncDocsResult {
     pub out_of_sync: HashSet<DocumentId>,
-    #[allow(dead_code)]
     pub in_sync: HashSet<DocumentId>,
 }
 
@@ -34,6 +33,7 @@ pub(super) async fn sync_docs<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     init_remote_symbols: Vec<riblt::CodedSymbol<DocStateHash>>,
     remote: PeerId,
     target: PeerAddress,
+    receive_only: bool,
 ) -> Result<SyncDocsResult, super::Error> {
     tracing::trace!(
         num_local_docs = local_docs.len(),
@@ -83,10 +83,16 @@ pub(super) async fn sync_docs<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
         !only_remote_states.contains_key(doc_id) && !only_local_states.contains_key(doc_id)
     });
 
-    let out_of_sync = local_out_of_sync
-        .union(&only_remote_states.keys().copied().collect())
-        .copied()
-        .collect();
+    // If we're only receiving then documents which the remote doesn't have a
+    // different state for have nothing to give us
+    let out_of_sync = if receive_only {
+        only_remote_states.keys().copied().collect()
+    } else {
+        local_out_of_sync
+            .union(&only_remote_states.keys().copied().collect())
+            .copied()
+            .collect()
+    };
 
     Ok(SyncDocsResult {
         out_of_sync,
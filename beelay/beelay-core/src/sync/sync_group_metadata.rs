@@ -0,0 +1,31 @@
+use crate::{group_metadata, network::PeerAddress, PeerId, TaskContext};
+
+use super::SessionId;
+
+/// Swap the metadata of the groups we and the remote share
+///
+/// This runs once membership has been synced so that both sides know about
+/// the groups, and their writers, that the metadata refers to. On a receive
+/// only sync we still take the remote's metadata but send none of our own.
+#[tracing::instrument(skip(ctx))]
+pub(crate) async fn sync_group_metadata<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    session_id: SessionId,
+    with_remote: PeerId,
+    remote_target: PeerAddress,
+    receive_only: bool,
+) -> Result<(), super::Error> {
+    let ours = if receive_only {
+        Vec::new()
+    } else {
+        group_metadata::records_for_peer(&ctx, with_remote).await
+    };
+    let theirs = ctx
+        .requests()
+        .sessions()
+        .exchange_group_metadata(remote_target, session_id, ours)
+        .await??;
+    tracing::trace!(num_records = theirs.len(), "received group metadata");
+    group_metadata::ingest(&ctx, theirs).await;
+    Ok(())
+}
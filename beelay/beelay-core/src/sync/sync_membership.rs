@@ -77,6 +77,7 @@ impl<'a> From<&'a StaticEvent<CommitHash>> for MembershipSymbol {
 }
 
 #[tracing::instrument(skip(ctx, local_ops, symbols), fields(num_symbols = symbols.len()))]
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn sync_membership<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     ctx: TaskContext<R>,
     session_id: SessionId,
@@ -85,16 +86,21 @@ pub(crate) async fn sync_membership<R: rand::Rng + rand::CryptoRng + Clone + 'st
     local_ops: HashMap<Digest<StaticEvent<CommitHash>>, StaticEvent<CommitHash>>,
     with_remote: PeerId,
     remote_target: PeerAddress,
+    receive_only: bool,
 ) -> Result<Option<Vec<CodedSymbol<DocStateHash>>>, super::Error> {
     tracing::debug!(num_local_ops = local_ops.len(), "running membership sync");
 
-    run_once(
+    // Ops which we have and the remote does not but which we didn't upload
+    // because this is a receive only sync. We leave these out of the symbols
+    // we send to the remote so that the membership phase can complete.
+    let mut withheld = run_once(
         ctx.clone(),
         session_id,
         symbols,
         local_ops,
         with_remote,
         remote_target,
+        receive_only,
     )
     .await?;
 
@@ -104,8 +110,10 @@ pub(crate) async fn sync_membership<R: rand::Rng + rand::CryptoRng + Clone + 'st
         let local_ops = membership.into_static_events();
 
         let mut encoder = riblt::Encoder::new();
-        for op in local_ops.values() {
-            encoder.add_symbol(&MembershipSymbol::from(op));
+        for (hash, op) in &local_ops {
+            if !withheld.contains(hash) {
+                encoder.add_symbol(&MembershipSymbol::from(op));
+            }
         }
         let symbols = encoder.next_n_symbols(10);
 
@@ -116,13 +124,14 @@ pub(crate) async fn sync_membership<R: rand::Rng + rand::CryptoRng + Clone + 'st
             .await??;
         match next_phase {
             NextSyncPhase::Membership(symbols) => {
-                run_once(
+                withheld = run_once(
                     ctx.clone(),
                     session_id,
                     symbols,
                     local_ops,
                     with_remote,
                     remote_target,
+                    receive_only,
                 )
                 .await?;
             }
@@ -144,7 +153,8 @@ pub(crate) async fn run_once<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     local_ops: HashMap<Digest<StaticEvent<CommitHash>>, StaticEvent<CommitHash>>,
     with_remote: PeerId,
     remote_target: PeerAddress,
-) -> Result<(), super::Error> {
+    receive_only: bool,
+) -> Result<HashSet<Digest<StaticEvent<CommitHash>>>, super::Error> {
     let mut decoder = riblt::Decoder::new();
     for op_hash in local_ops.keys() {
         decoder.add_symbol(&MembershipSymbol { hash: *op_hash });
@@ -183,9 +193,12 @@ pub(crate) async fn run_once<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
         .map(|h| local_ops.get(h).unwrap().clone())
         .collect::<Vec<_>>();
     let upload_fut = async {
-        if to_upload.is_empty() {
-            tracing::trace!("no membership ops to upload");
+        if receive_only {
+            tracing::trace!("not uploading membership ops on receive only stream");
             Ok::<_, super::Error>(())
+        } else if to_upload.is_empty() {
+            tracing::trace!("no membership ops to upload");
+            Ok(())
         } else {
             tracing::trace!(num_to_upload = to_upload.len(), "uploading ops");
             if let Err(err) = ctx
@@ -229,5 +242,12 @@ pub(crate) async fn run_once<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     upload_result?;
     download_result?;
 
-    Ok(())
+    if receive_only {
+        Ok(local_op_hashes
+            .difference(&remote_op_hashes)
+            .copied()
+            .collect())
+    } else {
+        Ok(HashSet::new())
+    }
 }
@@ -0,0 +1,30 @@
+use crate::{network::PeerAddress, seals, PeerId, TaskContext};
+
+use super::SessionId;
+
+/// Swap the seals of the documents we and the remote share
+///
+/// This runs alongside the tombstone exchange and, like it, sends none of our
+/// own seals on a receive only sync.
+#[tracing::instrument(skip(ctx))]
+pub(crate) async fn sync_seals<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    session_id: SessionId,
+    with_remote: PeerId,
+    remote_target: PeerAddress,
+    receive_only: bool,
+) -> Result<(), super::Error> {
+    let ours = if receive_only {
+        Vec::new()
+    } else {
+        seals::records_for_peer(&ctx, with_remote).await
+    };
+    let theirs = ctx
+        .requests()
+        .sessions()
+        .exchange_seals(remote_target, session_id, ours)
+        .await??;
+    tracing::trace!(num_records = theirs.len(), "received seals");
+    seals::ingest(&ctx, theirs).await;
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use crate::{network::PeerAddress, tombstones, PeerId, TaskContext};
+
+use super::SessionId;
+
+/// Swap the tombstones of the documents we and the remote share
+///
+/// As with group metadata this runs once membership has been synced so that
+/// both sides can check that the author of each tombstone is an admin of the
+/// document. On a receive only sync we still purge the documents the remote
+/// has tombstoned but send none of our own tombstones.
+#[tracing::instrument(skip(ctx))]
+pub(crate) async fn sync_tombstones<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
+    ctx: TaskContext<R>,
+    session_id: SessionId,
+    with_remote: PeerId,
+    remote_target: PeerAddress,
+    receive_only: bool,
+) -> Result<(), super::Error> {
+    let ours = if receive_only {
+        Vec::new()
+    } else {
+        tombstones::records_for_peer(&ctx, with_remote).await
+    };
+    let theirs = ctx
+        .requests()
+        .sessions()
+        .exchange_tombstones(remote_target, session_id, ours)
+        .await??;
+    tracing::trace!(num_records = theirs.len(), "received tombstones");
+    tombstones::ingest(&ctx, theirs).await;
+    Ok(())
+}
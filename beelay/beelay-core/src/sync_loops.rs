@@ -2,6 +2,7 @@ use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     future::Future,
     pin::Pin,
+    time::Duration,
 };
 
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
@@ -11,9 +12,54 @@ use crate::{
     network::{messages::UploadItem, RpcError},
     state::keyhive::batch,
     streams::{EstablishedStream, ResolvedDirection, SyncPhase},
-    CommitOrBundle, DocumentId, PeerId, StreamId, TaskContext, SYNC_INTERVAL,
+    CommitOrBundle, DocumentId, PeerId, StreamId, TaskContext, UnixTimestampMillis, SYNC_INTERVAL,
 };
 
+/// The earliest time at which `SyncLoops::reconcile` will start a periodic
+/// sync on a stream which is currently idle
+pub(crate) fn next_sync_deadline<R: rand::Rng + rand::CryptoRng>(
+    ctx: &TaskContext<R>,
+) -> Option<UnixTimestampMillis> {
+    sync_deadlines(ctx)
+        .into_iter()
+        .map(|(_, deadline)| deadline)
+        .min()
+}
+
+/// The idle streams on which `SyncLoops::reconcile` will start a periodic
+/// sync at `now`
+pub(crate) fn streams_due_for_sync<R: rand::Rng + rand::CryptoRng>(
+    ctx: &TaskContext<R>,
+    now: UnixTimestampMillis,
+) -> Vec<StreamId> {
+    sync_deadlines(ctx)
+        .into_iter()
+        .filter(|(_, deadline)| *deadline <= now)
+        .map(|(stream_id, _)| stream_id)
+        .collect()
+}
+
+fn sync_deadlines<R: rand::Rng + rand::CryptoRng>(
+    ctx: &TaskContext<R>,
+) -> Vec<(StreamId, UnixTimestampMillis)> {
+    ctx.state()
+        .streams()
+        .established()
+        .into_iter()
+        .filter(|stream| stream.direction != ResolvedDirection::Accepting || stream.receive_only)
+        .filter_map(|stream| match stream.sync_phase {
+            // `reconcile` syncs once strictly more than SYNC_INTERVAL has passed
+            SyncPhase::Listening {
+                last_synced_at: Some(last_synced_at),
+            } => Some((
+                stream.id,
+                last_synced_at + SYNC_INTERVAL + Duration::from_millis(1),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
 pub(crate) struct SyncLoops {
     doc_versions: HashMap<DocumentId, u64>,
     doc_events_pending_decryption: Vec<EventPendingDecryption>,
@@ -58,6 +104,8 @@ impl SyncLoops {
             id: stream_id,
             sync_phase,
             received_sync_needed,
+            receive_only,
+            ..
         } in established
         {
             let has_new_doc = !doc_changes
@@ -67,7 +115,9 @@ impl SyncLoops {
                 .collect::<Vec<_>>()
                 .is_empty();
             let should_start_sync = {
-                if direction == ResolvedDirection::Accepting {
+                // Receive only streams never serve our data to the remote so
+                // we always pull, even if we were resolved as the acceptor
+                if direction == ResolvedDirection::Accepting && !receive_only {
                     false
                 } else if has_new_doc {
                     tracing::trace!(%their_peer_id, "beginning new sync loop as we have new documents");
@@ -91,7 +141,7 @@ impl SyncLoops {
                 }
             };
 
-            if has_new_doc && direction == ResolvedDirection::Accepting {
+            if has_new_doc && direction == ResolvedDirection::Accepting && !receive_only {
                 tracing::trace!(%their_peer_id, "sending them a syncneeded message as we are the acceptor and have new docs");
                 self.running_uploads
                     .push(ctx.requests().sync_needed(stream_id.into()).boxed_local());
@@ -99,15 +149,21 @@ impl SyncLoops {
 
             if should_start_sync {
                 tracing::trace!(?stream_id, ?their_peer_id, "starting sync loop for stream");
-                self.running_sync_loops
-                    .push(sync_loop(ctx.clone(), stream_id, their_peer_id).boxed_local());
+                self.running_sync_loops.push(
+                    sync_loop(ctx.clone(), stream_id, their_peer_id, receive_only).boxed_local(),
+                );
                 ctx.state()
                     .streams()
                     .mark_sync_started(ctx.now(), stream_id);
             }
 
-            // forward everything which has changed and which the remote has access
-            for (doc_id, changes) in &doc_changes {
+            // forward everything which has changed and which the remote has
+            // access to, most urgent first
+            let mut ordered_changes = doc_changes.iter().collect::<Vec<_>>();
+            ordered_changes.sort_by_key(|(doc_id, _)| {
+                std::cmp::Reverse(ctx.state().docs().sync_priority(doc_id))
+            });
+            for (doc_id, changes) in ordered_changes {
                 match self.doc_versions.entry(*doc_id) {
                     Entry::Occupied(mut entry) => {
                         *entry.get_mut() += 1;
@@ -125,6 +181,10 @@ impl SyncLoops {
                         payload: c.payload.clone().into(),
                     }));
 
+                if receive_only || ctx.state().docs().is_sync_paused(doc_id) {
+                    continue;
+                }
+
                 let doc_id = *doc_id;
                 let changes = changes.clone();
                 let ctx = ctx.clone();
@@ -234,8 +294,10 @@ async fn sync_loop<R: rand::Rng + rand::CryptoRng + Clone + 'static>(
     ctx: TaskContext<R>,
     stream_id: StreamId,
     peer_id: PeerId,
+    receive_only: bool,
 ) -> StreamId {
-    if let Err(e) = crate::sync::sync_with_peer(ctx, stream_id.into(), peer_id).await {
+    if let Err(e) = crate::sync::sync_with_peer(ctx, stream_id.into(), peer_id, receive_only).await
+    {
         tracing::error!(err=%e, "sync loop stopping due to error");
     }
     stream_id
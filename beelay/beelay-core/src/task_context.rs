@@ -6,6 +6,7 @@ use crate::{state::State, UnixTimestampMillis};
 mod requests;
 pub(crate) use requests::{Requests, SessionRpcError};
 mod storage;
+pub(crate) use storage::ephemeral::EphemeralStorage;
 pub(crate) use storage::sedimentree::{DocStorage, Error as SedimentreeStorageError};
 pub(crate) use storage::Storage;
 mod job_future;
@@ -8,13 +8,18 @@ use keyhive_core::{
 };
 
 use crate::{
+    auth,
+    group_metadata::GroupMetadataRecord,
+    keyhive::MemberAccess,
     network::{
         messages::{self, session, Response},
         PeerAddress, RpcError,
     },
     riblt::{self, CodedSymbol},
+    seals::SealRecord,
     state::StateAccessor,
     sync::{CgkaSymbol, DocStateHash, MembershipSymbol, SessionId},
+    tombstones::TombstoneRecord,
     CommitHash, DocumentId, OutboundRequestId, UnixTimestampMillis,
 };
 
@@ -140,6 +145,42 @@ where
         }
     }
 
+    pub(crate) fn request_access(
+        &self,
+        to_peer: PeerAddress,
+        doc_id: DocumentId,
+        desired: MemberAccess,
+        contact_card: Vec<u8>,
+    ) -> impl Future<Output = Result<(), RpcError>> + 'static {
+        let request = crate::Request::AskForAccess {
+            doc_id,
+            desired,
+            contact_card,
+        };
+        let task = self.request(to_peer, request);
+        async move {
+            let response = task.await?;
+            match response.content {
+                NonErrorPayload::AskForAccess => Ok(()),
+                _ => Err(RpcError::IncorrectResponseType),
+            }
+        }
+    }
+
+    pub(crate) fn discover_docs(
+        &self,
+        to_peer: PeerAddress,
+    ) -> impl Future<Output = Result<Vec<DocumentId>, RpcError>> + 'static {
+        let task = self.request(to_peer, crate::Request::DiscoverDocs);
+        async move {
+            let response = task.await?;
+            match response.content {
+                NonErrorPayload::DiscoverDocs(docs) => Ok(docs),
+                _ => Err(RpcError::IncorrectResponseType),
+            }
+        }
+    }
+
     pub(crate) fn sessions(&self) -> Sessions<'_, R> {
         Sessions::new(self)
     }
@@ -159,7 +200,7 @@ where
                     let (tx, rx) = oneshot::channel();
                     state
                         .endpoints()
-                        .send_request(now.borrow().as_secs(), endpoint_id, req_id, request, tx)
+                        .send_request(*now.borrow(), endpoint_id, req_id, request, tx)
                         .map_err(|_| RpcError::NoResponse)?;
 
                     JobFuture(rx).await.ok_or(RpcError::NoResponse)
@@ -202,6 +243,9 @@ enum NonErrorPayload {
     SyncNeeded,
     UploadMembershipOps,
     UploadCgkaOps,
+    AskForAccess,
+    AccessDecision,
+    DiscoverDocs(Vec<DocumentId>),
 }
 
 impl TryFrom<Response> for NonErrorPayload {
@@ -223,6 +267,9 @@ impl TryFrom<Response> for NonErrorPayload {
             Response::SyncNeeded => Ok(NonErrorPayload::SyncNeeded),
             Response::UploadMembershipOps => Ok(NonErrorPayload::UploadMembershipOps),
             Response::UploadCgkaOps => Ok(NonErrorPayload::UploadCgkaOps),
+            Response::AskForAccess => Ok(NonErrorPayload::AskForAccess),
+            Response::AccessDecision => Ok(NonErrorPayload::AccessDecision),
+            Response::DiscoverDocs(docs) => Ok(NonErrorPayload::DiscoverDocs(docs)),
         }
     }
 }
@@ -389,6 +436,53 @@ impl<'a, R: rand::Rng + rand::CryptoRng + 'static> Sessions<'a, R> {
         let fut = self.requests.request(peer, request);
         extract_session_response!(fut, session::SessionResponse::FetchCgkaOps(ops) => ops)
     }
+
+    pub(crate) fn exchange_group_metadata(
+        &self,
+        peer: PeerAddress,
+        session_id: SessionId,
+        records: Vec<auth::Signed<GroupMetadataRecord>>,
+    ) -> impl Future<
+        Output = Result<Result<Vec<auth::Signed<GroupMetadataRecord>>, SessionRpcError>, RpcError>,
+    > + 'static {
+        let request = messages::Request::Session(session::SessionRequest::Message {
+            session_id,
+            msg: session::SessionMessage::ExchangeGroupMetadata(records),
+        });
+        let fut = self.requests.request(peer, request);
+        extract_session_response!(fut, session::SessionResponse::ExchangeGroupMetadata(records) => records)
+    }
+
+    pub(crate) fn exchange_tombstones(
+        &self,
+        peer: PeerAddress,
+        session_id: SessionId,
+        records: Vec<auth::Signed<TombstoneRecord>>,
+    ) -> impl Future<
+        Output = Result<Result<Vec<auth::Signed<TombstoneRecord>>, SessionRpcError>, RpcError>,
+    > + 'static {
+        let request = messages::Request::Session(session::SessionRequest::Message {
+            session_id,
+            msg: session::SessionMessage::ExchangeTombstones(records),
+        });
+        let fut = self.requests.request(peer, request);
+        extract_session_response!(fut, session::SessionResponse::ExchangeTombstones(records) => records)
+    }
+
+    pub(crate) fn exchange_seals(
+        &self,
+        peer: PeerAddress,
+        session_id: SessionId,
+        records: Vec<auth::Signed<SealRecord>>,
+    ) -> impl Future<Output = Result<Result<Vec<auth::Signed<SealRecord>>, SessionRpcError>, RpcError>>
+           + 'static {
+        let request = messages::Request::Session(session::SessionRequest::Message {
+            session_id,
+            msg: session::SessionMessage::ExchangeSeals(records),
+        });
+        let fut = self.requests.request(peer, request);
+        extract_session_response!(fut, session::SessionResponse::ExchangeSeals(records) => records)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -4,11 +4,12 @@ use futures::channel::oneshot;
 
 use crate::{
     io::{IoResultPayload, IoTask},
-    DocumentId, StorageKey,
+    BlobHash, DocumentId, StorageKey,
 };
 
 use super::JobFuture;
 
+pub(super) mod ephemeral;
 pub(super) mod sedimentree;
 
 pub(crate) struct Storage<'a> {
@@ -21,6 +22,12 @@ impl<'a> Storage<'a> {
     }
 
     pub(crate) async fn load(&self, key: StorageKey) -> Option<Vec<u8>> {
+        {
+            let ephemeral = self.io_handle.ephemeral();
+            if ephemeral.owns(&key) {
+                return ephemeral.load(&key);
+            }
+        }
         let task = IoTask::load(key);
         match self.io_task(task).await {
             IoResultPayload::Load(result) => result,
@@ -32,6 +39,12 @@ impl<'a> Storage<'a> {
     }
 
     pub(crate) async fn load_range(&self, prefix: StorageKey) -> HashMap<StorageKey, Vec<u8>> {
+        {
+            let ephemeral = self.io_handle.ephemeral();
+            if ephemeral.owns(&prefix) {
+                return ephemeral.load_range(&prefix);
+            }
+        }
         let task = IoTask::load_range(prefix.clone());
         match self.io_task(task).await {
             IoResultPayload::LoadRange(result) => result,
@@ -43,6 +56,12 @@ impl<'a> Storage<'a> {
     }
 
     pub(crate) async fn list_one_level(&self, prefix: StorageKey) -> HashSet<StorageKey> {
+        {
+            let ephemeral = self.io_handle.ephemeral();
+            if ephemeral.owns(&prefix) {
+                return ephemeral.list_one_level(&prefix);
+            }
+        }
         let task = IoTask::list_one_level(prefix);
         match self.io_task(task).await {
             IoResultPayload::ListOneLevel(result) => result.into_iter().collect(),
@@ -54,6 +73,13 @@ impl<'a> Storage<'a> {
     }
 
     pub(crate) async fn put(&self, key: StorageKey, value: Vec<u8>) {
+        {
+            let mut ephemeral = self.io_handle.ephemeral_mut();
+            if ephemeral.owns(&key) {
+                ephemeral.put(key, value);
+                return;
+            }
+        }
         let task = IoTask::put(key, value);
         match self.io_task(task).await {
             IoResultPayload::Put => {}
@@ -65,6 +91,13 @@ impl<'a> Storage<'a> {
 
     #[allow(dead_code)]
     pub(crate) async fn delete(&self, key: StorageKey) {
+        {
+            let mut ephemeral = self.io_handle.ephemeral_mut();
+            if ephemeral.owns(&key) {
+                ephemeral.delete(&key);
+                return;
+            }
+        }
         let task = IoTask::delete(key);
         match self.io_task(task).await {
             IoResultPayload::Delete => {}
@@ -74,6 +107,28 @@ impl<'a> Storage<'a> {
         }
     }
 
+    /// Store the contents of a commit or bundle of `doc_id`
+    ///
+    /// Blobs are not namespaced by document so this is the only way to write
+    /// a blob which is kept in memory if the document is ephemeral.
+    pub(crate) async fn put_doc_blob(&self, doc_id: &DocumentId, blob: BlobHash, data: Vec<u8>) {
+        let key = StorageKey::blob(blob);
+        {
+            let mut ephemeral = self.io_handle.ephemeral_mut();
+            if ephemeral.is_ephemeral(doc_id) {
+                ephemeral.put(key, data);
+                return;
+            }
+        }
+        self.put(key, data).await
+    }
+
+    /// Keep every commit and bundle of `doc_id` written from now on in memory
+    /// rather than in storage
+    pub(crate) fn make_ephemeral(&self, doc_id: DocumentId) {
+        self.io_handle.ephemeral_mut().add_doc(doc_id);
+    }
+
     pub(crate) fn doc_storage(&self, doc_id: DocumentId) -> sedimentree::DocStorage {
         sedimentree::DocStorage::new(self.io_handle.clone(), doc_id)
     }
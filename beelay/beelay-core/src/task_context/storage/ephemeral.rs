@@ -0,0 +1,60 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{DocumentId, StorageKey};
+
+/// In memory storage for the documents which were created as ephemeral
+///
+/// Every key under the sedimentree root of an ephemeral document, as well as
+/// the blobs of those documents, is kept here instead of being handed to the
+/// storage layer. Nothing here survives the `Beelay` being dropped.
+#[derive(Default)]
+pub(crate) struct EphemeralStorage {
+    docs: HashSet<DocumentId>,
+    data: HashMap<StorageKey, Vec<u8>>,
+}
+
+impl EphemeralStorage {
+    pub(crate) fn add_doc(&mut self, doc_id: DocumentId) {
+        self.docs.insert(doc_id);
+    }
+
+    pub(crate) fn is_ephemeral(&self, doc_id: &DocumentId) -> bool {
+        self.docs.contains(doc_id)
+    }
+
+    /// Whether `key` is stored here rather than in the storage layer
+    pub(crate) fn owns(&self, key: &StorageKey) -> bool {
+        self.data.contains_key(key)
+            || self.docs.iter().any(|doc_id| {
+                let root = StorageKey::sedimentree_root(doc_id);
+                root.len() <= key.len() && root.is_prefix_of(key)
+            })
+    }
+
+    pub(crate) fn load(&self, key: &StorageKey) -> Option<Vec<u8>> {
+        self.data.get(key).cloned()
+    }
+
+    pub(crate) fn load_range(&self, prefix: &StorageKey) -> HashMap<StorageKey, Vec<u8>> {
+        self.data
+            .iter()
+            .filter(|(key, _)| prefix.len() <= key.len() && prefix.is_prefix_of(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub(crate) fn list_one_level(&self, prefix: &StorageKey) -> HashSet<StorageKey> {
+        self.data
+            .keys()
+            .filter_map(|key| key.onelevel_deeper(prefix))
+            .collect()
+    }
+
+    pub(crate) fn put(&mut self, key: StorageKey, value: Vec<u8>) {
+        self.data.insert(key, value);
+    }
+
+    pub(crate) fn delete(&mut self, key: &StorageKey) {
+        self.data.remove(key);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{collections::HashMap, str::FromStr};
+
+use crate::{
+    auth::Signed,
+    commands::error,
+    io::IoHandle,
+    parse::{self, Parse},
+    serialization::Encode,
+    DocumentId, PeerId, StorageKey, TaskContext, UnixTimestamp,
+};
+
+/// A record that the content of a document was purged, signed by the admin
+/// of the document who purged it
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(test, derive(arbitrary::Arbitrary))]
+pub(crate) struct TombstoneRecord {
+    pub(crate) doc_id: DocumentId,
+    pub(crate) tombstoned_at: UnixTimestamp,
+}
+
+/// Who tombstoned a document and when
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tombstone {
+    pub tombstoned_by: PeerId,
+    pub tombstoned_at: UnixTimestamp,
+}
+
+impl From<&Signed<TombstoneRecord>> for Tombstone {
+    fn from(record: &Signed<TombstoneRecord>) -> Self {
+        Tombstone {
+            tombstoned_by: PeerId::from(record.verifier),
+            tombstoned_at: record.payload.tombstoned_at,
+        }
+    }
+}
+
+fn tombstones_prefix() -> StorageKey {
+    StorageKey::auth().push("tombstones")
+}
+
+fn tombstone_key(doc_id: DocumentId) -> StorageKey {
+    tombstones_prefix().push(doc_id.to_string())
+}
+
+pub(crate) async fn load(io: IoHandle) -> HashMap<DocumentId, Signed<TombstoneRecord>> {
+    let raw = crate::task_context::Storage::new(&io)
+        .load_range(tombstones_prefix())
+        .await;
+    let mut records = HashMap::new();
+    for (key, value) in raw {
+        let Some(doc_id) = key.name().and_then(|name| DocumentId::from_str(name).ok()) else {
+            tracing::warn!(?key, "failed to parse tombstone key");
+            continue;
+        };
+        let Ok((_, record)) = Signed::<TombstoneRecord>::parse(parse::Input::new(&value))
+            .inspect_err(|e| tracing::error!(err=?e, "failed to parse stored tombstone"))
+        else {
+            continue;
+        };
+        if record.payload.doc_id != doc_id || record.verify().is_err() {
+            tracing::error!(?key, "stored tombstone is invalid");
+            continue;
+        }
+        records.insert(doc_id, record);
+    }
+    records
+}
+
+/// Purge the content of `doc_id` and record that it was purged
+///
+/// Only admins of the document can tombstone it. Returns the number of
+/// storage keys which were deleted.
+pub(crate) async fn tombstone<R>(
+    ctx: TaskContext<R>,
+    doc_id: DocumentId,
+) -> Result<usize, error::TombstoneDoc>
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let keyhive = ctx.state().keyhive();
+    if !keyhive.has_doc(&doc_id).await {
+        return Err(error::TombstoneDoc::NoSuchDocument);
+    }
+    if !keyhive.can_admin(ctx.state().our_peer_id(), &doc_id).await {
+        return Err(error::TombstoneDoc::NotAllowed);
+    }
+    if ctx.state().tombstones().get(&doc_id).is_some() {
+        return Ok(0);
+    }
+    let record = TombstoneRecord {
+        doc_id,
+        tombstoned_at: ctx.now().as_secs(),
+    };
+    let signed = Signed::try_sign(ctx.signer(), record)
+        .await
+        .map_err(|e| error::TombstoneDoc::Signing(e.to_string()))?;
+    Ok(store_and_purge(ctx, signed).await)
+}
+
+/// Apply tombstones received from another peer
+///
+/// Tombstones which are badly signed or whose author isn't an admin of the
+/// document are dropped. The content of every newly tombstoned document is
+/// purged.
+pub(crate) async fn ingest<R>(ctx: &TaskContext<R>, records: Vec<Signed<TombstoneRecord>>)
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    for record in records {
+        let doc_id = record.payload.doc_id;
+        if ctx.state().tombstones().get(&doc_id).is_some() {
+            continue;
+        }
+        if record.verify().is_err() {
+            tracing::warn!(%doc_id, "dropping tombstone with an invalid signature");
+            continue;
+        }
+        if !ctx
+            .state()
+            .keyhive()
+            .can_admin(PeerId::from(record.verifier), &doc_id)
+            .await
+        {
+            tracing::warn!(%doc_id, "dropping tombstone from a peer who isn't an admin");
+            continue;
+        }
+        store_and_purge(ctx.clone(), record).await;
+    }
+}
+
+/// The tombstones of every document `peer` can pull
+pub(crate) async fn records_for_peer<R>(
+    ctx: &TaskContext<R>,
+    peer: PeerId,
+) -> Vec<Signed<TombstoneRecord>>
+where
+    R: rand::Rng + rand::CryptoRng,
+{
+    let mut records = Vec::new();
+    for record in ctx.state().tombstones().all() {
+        if ctx
+            .state()
+            .keyhive()
+            .can_pull(peer, &record.payload.doc_id)
+            .await
+        {
+            records.push(record);
+        }
+    }
+    records
+}
+
+// The tombstone is stored before the content is deleted so that nothing
+// arriving in the meantime is accepted
+async fn store_and_purge<R>(ctx: TaskContext<R>, record: Signed<TombstoneRecord>) -> usize
+where
+    R: rand::Rng + rand::CryptoRng + 'static,
+{
+    let doc_id = record.payload.doc_id;
+    let encoded = record.encode();
+    ctx.state().tombstones().insert(record);
+    ctx.storage().put(tombstone_key(doc_id), encoded).await;
+    crate::commands::delete_doc(ctx, doc_id).await
+}
+
+impl Encode for TombstoneRecord {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.doc_id.encode_into(out);
+        self.tombstoned_at.encode_into(out);
+    }
+}
+
+impl<'a> Parse<'a> for TombstoneRecord {
+    fn parse(input: parse::Input<'a>) -> Result<(parse::Input<'a>, Self), parse::ParseError> {
+        input.parse_in_ctx("TombstoneRecord", |input| {
+            let (input, doc_id) = DocumentId::parse(input)?;
+            let (input, tombstoned_at) = UnixTimestamp::parse(input)?;
+            Ok((
+                input,
+                TombstoneRecord {
+                    doc_id,
+                    tombstoned_at,
+                },
+            ))
+        })
+    }
+}
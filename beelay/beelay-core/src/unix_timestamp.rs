@@ -127,6 +127,12 @@ impl UnixTimestampMillis {
     }
 }
 
+impl From<UnixTimestamp> for UnixTimestampMillis {
+    fn from(secs: UnixTimestamp) -> Self {
+        Self(u128::from(secs.0) * 1000)
+    }
+}
+
 impl From<UnixTimestampMillis> for UnixTimestamp {
     fn from(millis: UnixTimestampMillis) -> Self {
         Self((millis.0 / 1000) as u64)
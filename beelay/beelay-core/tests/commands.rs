@@ -0,0 +1,4102 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use beelay_core::{
+    doc_status::DocEvent,
+    error::InvalidDelegation,
+    keyhive::{
+        AccessChange, AccessLogChange, AddMemberToGroup, GroupMetadata, KeyhiveCommandResult,
+        KeyhiveEntityId, MemberAccess, RemoveMemberFromGroup, RotationReason,
+    },
+    BundleProblem, BundlingPolicy, CommandResult, Commit, CommitBundle, CommitHash, CommitOrBundle,
+    Compaction, DedupStats, DocItem, DocProblem, DocSizeLimit, DocStats, DocumentId, Event,
+    GraphBundle, PeerId, StorageEncryptionKey, UnixTimestamp, UnixTimestampMillis,
+};
+use network::Network;
+use test_utils::init_logging;
+
+mod network;
+
+#[test]
+fn cancel_in_flight_load() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let result = network
+        .beelay(&peer1)
+        .run_cancelled(Event::load_doc(doc_id));
+    assert!(matches!(result, CommandResult::Cancelled));
+
+    // The beelay should still be usable after the cancelled command's IO is discarded
+    let loaded = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    assert_eq!(
+        loaded,
+        vec![beelay_core::CommitOrBundle::Commit(initial_commit)]
+    );
+}
+
+#[test]
+fn cancel_all_in_flight_commands() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let (doc_id, initial_commit) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    network.connect_stream(&peer1, &peer2);
+
+    let results = network.beelay(&peer1).run_all_cancelled(vec![
+        Event::load_doc(doc_id),
+        Event::load_doc_encrypted(doc_id),
+    ]);
+    assert!(results
+        .iter()
+        .all(|r| matches!(r, CommandResult::Cancelled)));
+
+    // The stream is still connected, so new commits keep syncing
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        Some(vec![commit.hash()])
+    );
+}
+
+#[test]
+fn cancel_completed_command_is_noop() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let (command, event) = Event::query_status(doc_id);
+    let result = network.beelay(&peer1).run_command((command, event));
+    assert!(matches!(result, CommandResult::QueryStatus(_)));
+
+    network.beelay(&peer1).cancel_command(command);
+    network
+        .beelay(&peer1)
+        .cancel_command(beelay_core::CommandId::from_serialized(u64::MAX));
+    assert!(network
+        .beelay(&peer1)
+        .doc_status(&doc_id)
+        .local_heads
+        .is_some());
+}
+
+#[test]
+fn add_commits_multi_reports_per_doc_results() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc1, doc1_initial) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let (doc2, doc2_initial) = network
+        .beelay(&peer1)
+        .create_doc_with_contents(vec![1, 2, 3], vec![])
+        .unwrap();
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+
+    let doc1_commit = Commit::new(
+        vec![doc1_initial.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([1; 32]),
+    );
+    let doc2_commit = Commit::new(
+        vec![doc2_initial.hash()],
+        vec![7, 8, 9],
+        CommitHash::from([2; 32]),
+    );
+    let results = network.beelay(&peer1).add_commits_multi(vec![
+        (doc1, vec![doc1_commit.clone()]),
+        (doc2, vec![doc2_commit.clone()]),
+        (
+            missing_doc,
+            vec![Commit::new(vec![], vec![0], CommitHash::from([3; 32]))],
+        ),
+    ]);
+
+    assert_eq!(results.len(), 3);
+    assert!(results[&doc1].is_ok());
+    assert!(results[&doc2].is_ok());
+    assert!(results[&missing_doc].is_err());
+
+    let doc2_loaded = network.beelay(&peer1).load_doc(doc2).unwrap();
+    assert!(doc2_loaded.contains(&CommitOrBundle::Commit(doc2_commit)));
+    let doc1_loaded = network.beelay(&peer1).load_doc(doc1).unwrap();
+    assert!(doc1_loaded.contains(&CommitOrBundle::Commit(doc1_commit)));
+}
+
+#[test]
+fn progress_is_reported_for_loads_and_multi_adds() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc1, doc1_initial) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let (doc2, doc2_initial) = network
+        .beelay(&peer1)
+        .create_doc_with_contents(vec![1, 2, 3], vec![])
+        .unwrap();
+
+    let mut doc1_commits = Vec::new();
+    let mut parent = doc1_initial.hash();
+    for i in 1..=3 {
+        let commit = Commit::new(vec![parent], vec![i], CommitHash::from([i; 32]));
+        parent = commit.hash();
+        doc1_commits.push(commit);
+    }
+    let doc2_commit = Commit::new(
+        vec![doc2_initial.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([4; 32]),
+    );
+
+    let (command, event) =
+        Event::add_commits_multi(vec![(doc1, doc1_commits), (doc2, vec![doc2_commit])]);
+    network.beelay(&peer1).run_command((command, event));
+    let progress = network.beelay(&peer1).take_command_progress(command);
+    assert_eq!(progress.len(), 2);
+    let last = progress.last().unwrap();
+    assert_eq!((last.processed, last.total), (4, 4));
+
+    let (command, event) = Event::load_doc(doc1);
+    let CommandResult::LoadDoc(Some(loaded)) = network.beelay(&peer1).run_command((command, event))
+    else {
+        panic!("expected doc to load");
+    };
+    let progress = network.beelay(&peer1).take_command_progress(command);
+    assert_eq!(progress.len(), loaded.len());
+    assert!(progress.windows(2).all(|w| w[0].processed < w[1].processed));
+    let last = progress.last().unwrap();
+    assert_eq!((last.processed, last.total), (loaded.len(), loaded.len()));
+}
+
+#[test]
+fn exported_bundles_can_be_added_on_another_node() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc, initial_commit) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Individual(bob_contact)])
+        .unwrap();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(doc, vec![commit.clone()])
+        .unwrap();
+    let expected = HashSet::from([
+        CommitOrBundle::Commit(initial_commit.clone()),
+        CommitOrBundle::Commit(commit.clone()),
+    ]);
+
+    let export = |network: &mut Network, include_keys| match network
+        .beelay(&alice)
+        .run_command(Event::export_bundle(doc, vec![commit.hash()], include_keys))
+    {
+        CommandResult::ExportBundle(Ok(bundle)) => bundle,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+
+    let bundle = export(&mut network, false);
+    assert_eq!(bundle.start(), initial_commit.hash());
+    assert_eq!(bundle.end(), commit.hash());
+    assert!(bundle.keyhive_material().is_none());
+    assert_eq!(
+        bundle
+            .exported_items()
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        expected
+    );
+
+    // Bob has never synced the document so he can't add a bundle without keys
+    let CommandResult::AddBundle(result) = network
+        .beelay(&bob)
+        .run_command(Event::add_bundle(doc, bundle))
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(result.is_err());
+
+    let bundle = export(&mut network, true);
+    assert!(bundle.keyhive_material().is_some());
+    let CommandResult::AddBundle(result) = network
+        .beelay(&bob)
+        .run_command(Event::add_bundle(doc, bundle))
+    else {
+        panic!("unexpected command result");
+    };
+    result.unwrap();
+
+    let loaded = network.beelay(&bob).load_doc(doc).unwrap();
+    let [CommitOrBundle::Bundle(loaded)] = loaded.as_slice() else {
+        panic!("expected a single bundle, got {:?}", loaded);
+    };
+    assert_eq!(
+        loaded
+            .exported_items()
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        expected
+    );
+
+    match network.beelay(&alice).run_command(Event::export_bundle(
+        doc,
+        vec![CommitHash::from([9; 32])],
+        false,
+    )) {
+        CommandResult::ExportBundle(Err(beelay_core::error::ExportBundle::MissingHead(_))) => {}
+        other => panic!("unexpected command result: {:?}", other),
+    }
+}
+
+#[test]
+fn add_bundle_checked_rejects_or_repairs_bad_bundles() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc, initial_commit) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Individual(bob_contact)])
+        .unwrap();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(doc, vec![commit.clone()])
+        .unwrap();
+    let CommandResult::ExportBundle(Ok(exported)) = network
+        .beelay(&alice)
+        .run_command(Event::export_bundle(doc, vec![commit.hash()], true))
+    else {
+        panic!("unexpected command result");
+    };
+    let with_checkpoints = |checkpoints: Vec<CommitHash>, keyhive_material: Vec<u8>| {
+        CommitBundle::builder()
+            .start(exported.start())
+            .end(exported.end())
+            .checkpoints(checkpoints)
+            .bundled_commits(exported.bundled_commits().to_vec())
+            .keyhive_material(keyhive_material)
+            .build()
+    };
+    let material = exported.keyhive_material().unwrap().to_vec();
+    let bad = with_checkpoints(vec![commit.hash()], material.clone());
+
+    let add_checked = |network: &mut Network, bundle, strict| match network
+        .beelay(&bob)
+        .run_command(Event::add_bundle_checked(doc, bundle, strict))
+    {
+        CommandResult::AddBundleChecked(result) => result,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+
+    // Garbage keyhive material means Bob can't learn about the document
+    let garbage = with_checkpoints(vec![], vec![0xff; 8]);
+    let Err(beelay_core::error::AddBundle::Invalid(problems)) =
+        add_checked(&mut network, garbage, false)
+    else {
+        panic!("expected the bundle to be rejected");
+    };
+    assert!(matches!(
+        problems.as_slice(),
+        [
+            BundleProblem::UnreadableKeyhiveMaterial(_),
+            BundleProblem::UnknownDocument
+        ]
+    ));
+
+    let Err(beelay_core::error::AddBundle::Invalid(problems)) =
+        add_checked(&mut network, bad.clone(), true)
+    else {
+        panic!("expected the bundle to be rejected");
+    };
+    assert_eq!(
+        problems,
+        vec![BundleProblem::CheckpointOnBoundary(commit.hash())]
+    );
+    assert!(network.beelay(&bob).load_doc(doc).is_none());
+
+    let problems = add_checked(&mut network, bad, false).unwrap();
+    assert_eq!(
+        problems,
+        vec![BundleProblem::CheckpointOnBoundary(commit.hash())]
+    );
+    let loaded = network.beelay(&bob).load_doc(doc).unwrap();
+    let [CommitOrBundle::Bundle(loaded)] = loaded.as_slice() else {
+        panic!("expected a single bundle, got {:?}", loaded);
+    };
+    assert!(loaded.checkpoints().is_empty());
+    assert_eq!(
+        loaded
+            .exported_items()
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        HashSet::from([
+            CommitOrBundle::Commit(initial_commit),
+            CommitOrBundle::Commit(commit),
+        ])
+    );
+}
+
+#[test]
+fn export_doc_in_chunks() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let mut commits = vec![initial_commit];
+    for i in 1..=3 {
+        let commit = Commit::new(
+            vec![commits.last().unwrap().hash()],
+            vec![i; 600_000],
+            CommitHash::from([i; 32]),
+        );
+        network
+            .beelay(&peer1)
+            .add_commits(doc_id, vec![commit.clone()])
+            .unwrap();
+        commits.push(commit);
+    }
+    let head = commits.last().unwrap().hash();
+
+    let begin = |network: &mut Network| match network
+        .beelay(&peer1)
+        .run_command(Event::begin_export(doc_id, vec![head]))
+    {
+        CommandResult::BeginExport(Ok(handle)) => handle,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    let next = |network: &mut Network, handle| match network
+        .beelay(&peer1)
+        .run_command(Event::next_export_chunk(handle))
+    {
+        CommandResult::NextExportChunk(result) => result,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+
+    let handle = begin(&mut network);
+    let mut chunks = Vec::new();
+    while let Some(chunk) = next(&mut network, handle).unwrap() {
+        chunks.push(chunk);
+    }
+    assert!(chunks.len() > 1);
+    let (last, rest) = chunks.split_last().unwrap();
+    assert!(rest.iter().all(|c| c.len() == rest[0].len()));
+    assert!(last.len() <= rest[0].len());
+    let exported = CommitOrBundle::decode_export(&chunks.concat()).unwrap();
+    assert_eq!(
+        exported.into_iter().collect::<HashSet<_>>(),
+        commits
+            .into_iter()
+            .map(CommitOrBundle::Commit)
+            .collect::<HashSet<_>>()
+    );
+    // The handle is gone once the export is exhausted
+    assert!(matches!(
+        next(&mut network, handle),
+        Err(beelay_core::error::NextExportChunk::NoSuchExport)
+    ));
+
+    let handle = begin(&mut network);
+    next(&mut network, handle).unwrap().unwrap();
+    network.beelay(&peer1).delete_doc(doc_id);
+    assert!(matches!(
+        next(&mut network, handle),
+        Err(beelay_core::error::NextExportChunk::NoSuchExport)
+    ));
+}
+
+#[test]
+fn delete_doc_removes_storage() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit])
+        .unwrap();
+    let keys_before = network.beelay(&peer1).storage().len();
+
+    // Two commits, each with a sedimentree entry, a blob and commit metadata
+    let removed = network.beelay(&peer1).delete_doc(doc_id);
+    assert_eq!(removed, 6);
+    assert_eq!(network.beelay(&peer1).storage().len(), keys_before - 6);
+    let doc_prefix = beelay_core::StorageKey::sedimentree_root(&doc_id);
+    assert!(!network
+        .beelay(&peer1)
+        .storage()
+        .keys()
+        .any(|k| doc_prefix.is_prefix_of(k)));
+    assert_eq!(network.beelay(&peer1).doc_status(&doc_id).local_heads, None);
+
+    // Deleting again is a no-op
+    assert_eq!(network.beelay(&peer1).delete_doc(doc_id), 0);
+    let missing = DocumentId::random(&mut rand::thread_rng());
+    assert_eq!(network.beelay(&peer1).delete_doc(missing), 0);
+}
+
+#[test]
+fn delete_doc_keeps_seals_and_tombstones_but_not_capabilities() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (sealed, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let (tombstoned, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let (kept, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    network.beelay(&peer1).seal_doc(sealed).unwrap();
+    network.beelay(&peer1).tombstone_doc(tombstoned).unwrap();
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+    let mut mint = |doc_id| {
+        let CommandResult::MintCapability(Ok(capability)) = network.beelay(&peer1).run_command(
+            Event::mint_capability(doc_id, MemberAccess::Read, expires_at),
+        ) else {
+            panic!("expected a capability");
+        };
+        beelay_core::StorageKey::auth()
+            .push("capabilities")
+            .push(capability.id().to_string())
+    };
+    let deleted_capability = mint(sealed);
+    let kept_capability = mint(kept);
+    let seal = beelay_core::StorageKey::auth()
+        .push("seals")
+        .push(sealed.to_string());
+    let tombstone = beelay_core::StorageKey::auth()
+        .push("tombstones")
+        .push(tombstoned.to_string());
+
+    network.beelay(&peer1).delete_doc(sealed);
+    network.beelay(&peer1).delete_doc(tombstoned);
+    let storage = network.beelay(&peer1).storage().clone();
+    assert!(storage.contains_key(&seal));
+    assert!(storage.contains_key(&tombstone));
+    assert!(!storage.contains_key(&deleted_capability));
+    assert!(storage.contains_key(&kept_capability));
+}
+
+#[test]
+fn delete_doc_keeps_keyhive_membership_and_expiries() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+    network.beelay(&alice).add_member_to_doc_until(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Read,
+        expires_at,
+    );
+    let auth = beelay_core::StorageKey::auth();
+    let keyhive_keys = |network: &mut Network| {
+        network
+            .beelay(&alice)
+            .storage()
+            .keys()
+            .filter(|k| auth.is_prefix_of(k))
+            .cloned()
+            .collect::<HashSet<_>>()
+    };
+    let before = keyhive_keys(&mut network);
+
+    network.beelay(&alice).delete_doc(doc_id);
+    assert_eq!(keyhive_keys(&mut network), before);
+    network.reload_peer(&alice);
+    assert!(network
+        .beelay(&alice)
+        .query_access(doc_id)
+        .unwrap()
+        .contains_key(&bob));
+    let rotations = network
+        .beelay(&alice)
+        .list_scheduled_rotations(doc_id)
+        .unwrap();
+    assert_eq!(rotations.len(), 1);
+    assert_eq!(rotations[0].at, expires_at);
+}
+
+#[test]
+fn compact_doc_removes_commits_covered_by_bundles() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    // Hashes with two trailing zeros in base 10 are bundle boundaries
+    let boundary = |n: u16| {
+        let mut hash = [0; 32];
+        hash[30..].copy_from_slice(&n.to_be_bytes());
+        CommitHash::from(hash)
+    };
+    let a = Commit::new(vec![initial_commit.hash()], vec![1], boundary(100));
+    let b = Commit::new(vec![a.hash()], vec![2], CommitHash::from([2; 32]));
+    let c = Commit::new(vec![b.hash()], vec![3], boundary(300));
+    let d = Commit::new(vec![c.hash()], vec![4], CommitHash::from([4; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![a.clone(), b, c.clone(), d.clone()])
+        .unwrap();
+
+    let compact = |network: &mut Network| match network
+        .beelay(&peer1)
+        .run_command(Event::compact_doc(doc_id))
+    {
+        CommandResult::CompactDoc(result) => result.unwrap(),
+        other => panic!("unexpected command result: {:?}", other),
+    };
+
+    // Nothing is covered by a bundle yet
+    assert_eq!(compact(&mut network).commits_removed, 0);
+
+    let bundle = CommitBundle::builder()
+        .start(a.hash())
+        .end(c.hash())
+        .bundled_commits(vec![1, 2, 3])
+        .build();
+    let CommandResult::AddBundle(result) = network
+        .beelay(&peer1)
+        .run_command(Event::add_bundle(doc_id, bundle.clone()))
+    else {
+        panic!("unexpected command result");
+    };
+    result.unwrap();
+    let keys_before = network.beelay(&peer1).storage().len();
+
+    let compaction = compact(&mut network);
+    // The commits covered by the bundle are removed. `a` also ends the block
+    // after the initial commit, which no bundle covers, so it must stay
+    assert_eq!(compaction.commits_removed, 2);
+    assert!(compaction.bytes_reclaimed > 0);
+    assert!(network.beelay(&peer1).storage().len() < keys_before);
+    assert_eq!(
+        network
+            .beelay(&peer1)
+            .load_doc(doc_id)
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        HashSet::from([
+            CommitOrBundle::Commit(initial_commit),
+            CommitOrBundle::Commit(a),
+            CommitOrBundle::Bundle(bundle),
+            CommitOrBundle::Commit(d.clone()),
+        ])
+    );
+    assert_eq!(
+        network.beelay(&peer1).doc_status(&doc_id).local_heads,
+        Some(vec![d.hash()])
+    );
+
+    // Compacting again finds nothing more to remove
+    assert_eq!(compact(&mut network), Compaction::default());
+
+    // The remaining commits only refer to the boundaries of the bundle
+    let CommandResult::VerifyDoc(problems) = network
+        .beelay(&peer1)
+        .run_command(Event::verify_doc(doc_id))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(problems.unwrap(), vec![]);
+}
+
+#[test]
+fn list_documents_filters_by_access() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+
+    let (shared_doc, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    network
+        .beelay(&peer1)
+        .add_member_to_doc(shared_doc, peer2_contact.into(), MemberAccess::Read);
+    network.connect_stream(&peer2, &peer1);
+
+    let (own_doc, _) = network.beelay(&peer2).create_doc(vec![]).unwrap();
+
+    // Documents should be found in storage after a restart
+    network.reload_peer(&peer2);
+
+    let all = network.beelay(&peer2).list_documents(None);
+    assert_eq!(
+        all.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([shared_doc, own_doc])
+    );
+    let writable = network
+        .beelay(&peer2)
+        .list_documents(Some(MemberAccess::Write));
+    assert_eq!(writable, vec![own_doc]);
+}
+
+#[test]
+fn create_doc_with_idempotency_key_returns_existing_doc() {
+    init_logging();
+    let mut network = Network::new();
+    let peer = network.create_peer("peer").build();
+
+    let doc_id = network
+        .beelay(&peer)
+        .create_doc_idempotent(vec![1, 2, 3], "create-1")
+        .unwrap();
+    let again = network
+        .beelay(&peer)
+        .create_doc_idempotent(vec![4, 5, 6], "create-1")
+        .unwrap();
+    assert_eq!(again, doc_id);
+    let other = network
+        .beelay(&peer)
+        .create_doc_idempotent(vec![1, 2, 3], "create-2")
+        .unwrap();
+    assert_ne!(other, doc_id);
+
+    // The key should still apply after a restart
+    network.reload_peer(&peer);
+    let again = network
+        .beelay(&peer)
+        .create_doc_idempotent(vec![7, 8, 9], "create-1")
+        .unwrap();
+    assert_eq!(again, doc_id);
+    let docs = network.beelay(&peer).list_documents(None);
+    assert_eq!(
+        docs.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([doc_id, other])
+    );
+
+    // Once the document is deleted the key creates a new one
+    network.beelay(&peer).delete_doc(doc_id);
+    let recreated = network
+        .beelay(&peer)
+        .create_doc_idempotent(vec![1, 2, 3], "create-1")
+        .unwrap();
+    assert_ne!(recreated, doc_id);
+}
+
+#[test]
+fn load_commits_from_returns_ancestors_of_heads() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let a = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    let b = Commit::new(vec![a.hash()], vec![2], CommitHash::from([2; 32]));
+    let concurrent = Commit::new(
+        vec![initial_commit.hash()],
+        vec![3],
+        CommitHash::from([3; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![a.clone(), b.clone(), concurrent.clone()])
+        .unwrap();
+
+    let loaded = network
+        .beelay(&peer1)
+        .load_commits_from(doc_id, vec![b.hash()])
+        .unwrap();
+    assert_eq!(
+        loaded.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([
+            CommitOrBundle::Commit(initial_commit.clone()),
+            CommitOrBundle::Commit(a.clone()),
+            CommitOrBundle::Commit(b),
+        ])
+    );
+
+    let loaded = network
+        .beelay(&peer1)
+        .load_commits_from(doc_id, vec![a.hash(), concurrent.hash()])
+        .unwrap();
+    assert_eq!(
+        loaded.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([
+            CommitOrBundle::Commit(initial_commit),
+            CommitOrBundle::Commit(a),
+            CommitOrBundle::Commit(concurrent),
+        ])
+    );
+
+    let missing = CommitHash::from([4; 32]);
+    let result = network
+        .beelay(&peer1)
+        .load_commits_from(doc_id, vec![missing]);
+    assert!(matches!(
+        result,
+        Err(beelay_core::error::LoadCommitsFrom::MissingHead(head)) if head == missing
+    ));
+}
+
+#[test]
+fn load_doc_at_ignores_later_commits() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let a = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    let b = Commit::new(vec![a.hash()], vec![2], CommitHash::from([2; 32]));
+    let concurrent = Commit::new(
+        vec![initial_commit.hash()],
+        vec![3],
+        CommitHash::from([3; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![a.clone(), b.clone(), concurrent.clone()])
+        .unwrap();
+
+    let loaded = network
+        .beelay(&peer1)
+        .load_doc_at(doc_id, vec![a.hash()])
+        .unwrap();
+    assert!(loaded.iter().all(|(_, meta)| meta.is_some()));
+    assert_eq!(
+        loaded
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<HashSet<_>>(),
+        HashSet::from([
+            CommitOrBundle::Commit(initial_commit.clone()),
+            CommitOrBundle::Commit(a.clone()),
+        ])
+    );
+
+    let loaded = network
+        .beelay(&peer1)
+        .load_doc_at(doc_id, vec![b.hash(), concurrent.hash()])
+        .unwrap();
+    assert_eq!(loaded.len(), 4);
+
+    let not_a_commit = CommitHash::from([4; 32]);
+    let result = network
+        .beelay(&peer1)
+        .load_doc_at(doc_id, vec![a.hash(), not_a_commit]);
+    assert!(matches!(
+        result,
+        Err(beelay_core::error::LoadDocAt::NotACommit(head)) if head == not_a_commit
+    ));
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network
+            .beelay(&peer1)
+            .load_doc_at(missing_doc, vec![a.hash()]),
+        Err(beelay_core::error::LoadDocAt::NoSuchDocument)
+    ));
+
+    network.beelay(&peer1).tombstone_doc(doc_id).unwrap();
+    assert!(matches!(
+        network.beelay(&peer1).load_doc_at(doc_id, vec![a.hash()]),
+        Err(beelay_core::error::LoadDocAt::Tombstoned)
+    ));
+}
+
+#[test]
+fn rotate_doc_key_keeps_doc_readable_by_members() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc_id, initial_commit) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Individual(bob_contact)])
+        .unwrap();
+
+    let first_epoch = network.beelay(&alice).rotate_doc_key(doc_id).unwrap();
+    let second_epoch = network.beelay(&alice).rotate_doc_key(doc_id).unwrap();
+    assert!(second_epoch > first_epoch);
+
+    // Commits written after the rotation should be readable by the other members
+    // once they have synced the rotation
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+    network.connect_stream(&bob, &alice);
+    let on_bob = network.beelay(&bob).load_doc(doc_id).unwrap();
+    assert_eq!(
+        on_bob.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([
+            CommitOrBundle::Commit(initial_commit),
+            CommitOrBundle::Commit(commit)
+        ])
+    );
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&alice).rotate_doc_key(missing_doc),
+        Err(beelay_core::error::RotateDocKey::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn doc_key_epochs_shows_which_members_hold_keys() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let carol = network.create_peer("carol").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let carol_contact = network.beelay(&carol).contact_card().unwrap();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Write,
+    );
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(carol_contact),
+        MemberAccess::Pull,
+    );
+    let current = network.beelay(&alice).rotate_doc_key(doc_id).unwrap();
+
+    let epochs = network.beelay(&alice).doc_key_epochs(doc_id).unwrap();
+    assert_eq!(epochs.current, current);
+    let members = epochs.members.unwrap();
+    let member = |peer: PeerId| {
+        members
+            .iter()
+            .find(|m| m.member == peer)
+            .unwrap_or_else(|| panic!("{} not in key epochs", peer))
+    };
+    assert_eq!(member(alice).access, MemberAccess::Admin);
+    assert_eq!(member(alice).epochs.last(), Some(&current));
+    // Bob was given a share after Alice, so holds the keys of fewer epochs
+    assert_eq!(member(bob).epochs.last(), Some(&current));
+    assert!(member(bob).epochs.len() < member(alice).epochs.len());
+    assert!(!member(bob).locked_out());
+    // Pull access doesn't come with a share of the document key
+    assert_eq!(member(carol).access, MemberAccess::Pull);
+    assert!(member(carol).locked_out());
+
+    // Members who aren't admins don't see who holds which keys, and pull
+    // members hold none themselves
+    network.connect_stream(&carol, &alice);
+    let on_carol = network.beelay(&carol).doc_key_epochs(doc_id).unwrap();
+    assert_eq!(on_carol.current, 0);
+    assert!(on_carol.members.is_none());
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&alice).doc_key_epochs(missing_doc),
+        Err(beelay_core::error::DocKeyEpochs::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn list_doc_members_includes_inherited_access() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Read,
+            expires_at: None,
+        })
+        .unwrap();
+    let (doc_id, _) = network
+        .beelay(&alice)
+        .create_doc(vec![
+            KeyhiveEntityId::Group(group),
+            KeyhiveEntityId::Individual(charlie_contact),
+        ])
+        .unwrap();
+
+    let members = network
+        .beelay(&alice)
+        .list_doc_members(doc_id)
+        .unwrap()
+        .into_iter()
+        .map(|m| {
+            let id = match m.member {
+                KeyhiveEntityId::Individual(card) => card.peer_id(),
+                KeyhiveEntityId::Group(id) => id,
+                other => panic!("unexpected member: {}", other),
+            };
+            (id, (m.access, m.direct))
+        })
+        .collect::<HashMap<_, _>>();
+
+    assert_eq!(members.get(&alice), Some(&(MemberAccess::Admin, true)));
+    assert_eq!(members.get(&charlie), Some(&(MemberAccess::Admin, true)));
+    assert_eq!(members.get(&group), Some(&(MemberAccess::Admin, true)));
+    assert_eq!(members.get(&bob), Some(&(MemberAccess::Read, false)));
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&alice).list_doc_members(missing_doc),
+        Err(beelay_core::error::QueryAccess::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn query_access_for_resolves_group_membership() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+    let dave = network.create_peer("dave").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let dave_contact = network.beelay(&dave).contact_card().unwrap();
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact.clone()),
+            access: MemberAccess::Read,
+            expires_at: None,
+        })
+        .unwrap();
+    let (doc_id, _) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Group(group)])
+        .unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(charlie_contact.clone()),
+        MemberAccess::Write,
+    );
+
+    let access_for = |network: &mut Network, entity| {
+        network
+            .beelay(&alice)
+            .query_access_for(doc_id, entity)
+            .unwrap()
+    };
+    assert_eq!(
+        access_for(
+            &mut network,
+            KeyhiveEntityId::Individual(bob_contact.clone())
+        ),
+        Some(MemberAccess::Read)
+    );
+    assert_eq!(
+        access_for(&mut network, KeyhiveEntityId::Individual(charlie_contact)),
+        Some(MemberAccess::Write)
+    );
+    assert_eq!(
+        access_for(&mut network, KeyhiveEntityId::Group(group)),
+        Some(MemberAccess::Admin)
+    );
+    assert_eq!(
+        access_for(
+            &mut network,
+            KeyhiveEntityId::Individual(dave_contact.clone())
+        ),
+        None
+    );
+
+    // Public access applies to everyone
+    network
+        .beelay(&alice)
+        .add_member_to_doc(doc_id, KeyhiveEntityId::Public, MemberAccess::Pull);
+    assert_eq!(
+        access_for(
+            &mut network,
+            KeyhiveEntityId::Individual(dave_contact.clone())
+        ),
+        Some(MemberAccess::Pull)
+    );
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network
+            .beelay(&alice)
+            .query_access_for(missing_doc, KeyhiveEntityId::Public),
+        Err(beelay_core::error::QueryAccessFor::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn query_access_for_requires_admin() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let alice_contact = network.beelay(&alice).contact_card().unwrap();
+
+    // Bob can read the document but isn't an admin so can't ask about anyone
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Read,
+    );
+    network.connect_stream(&alice, &bob);
+    assert!(matches!(
+        network
+            .beelay(&bob)
+            .query_access_for(doc_id, KeyhiveEntityId::Individual(alice_contact)),
+        Err(beelay_core::error::QueryAccessFor::NotAllowed)
+    ));
+}
+
+#[test]
+fn query_access_multi_reports_our_access_to_each_doc() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+
+    let (private_doc, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    let (shared_doc, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        shared_doc,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Read,
+    );
+    let (public_doc, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        public_doc,
+        KeyhiveEntityId::Public,
+        MemberAccess::Write,
+    );
+    network.connect_stream(&alice, &bob);
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    let docs = vec![private_doc, shared_doc, public_doc, missing_doc];
+    assert_eq!(
+        network.beelay(&bob).query_access_multi(docs.clone()),
+        HashMap::from([
+            (private_doc, None),
+            (shared_doc, Some(MemberAccess::Read)),
+            (public_doc, Some(MemberAccess::Write)),
+            (missing_doc, None),
+        ])
+    );
+    assert_eq!(
+        network.beelay(&alice).query_access_multi(docs),
+        HashMap::from([
+            (private_doc, Some(MemberAccess::Admin)),
+            (shared_doc, Some(MemberAccess::Admin)),
+            (public_doc, Some(MemberAccess::Admin)),
+            (missing_doc, None),
+        ])
+    );
+}
+
+#[test]
+fn expand_group_includes_nested_members() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let inner = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: inner,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Admin,
+            expires_at: None,
+        })
+        .unwrap();
+    let outer = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: outer,
+            member: KeyhiveEntityId::Group(inner),
+            access: MemberAccess::Write,
+            expires_at: None,
+        })
+        .unwrap();
+
+    let members = network
+        .beelay(&alice)
+        .expand_group(outer)
+        .unwrap()
+        .into_iter()
+        .map(|m| {
+            let id = match m.member {
+                KeyhiveEntityId::Individual(card) => card.peer_id(),
+                KeyhiveEntityId::Group(id) => id,
+                other => panic!("unexpected member: {}", other),
+            };
+            (id, m.access)
+        })
+        .collect::<HashMap<_, _>>();
+    assert_eq!(members.get(&alice), Some(&MemberAccess::Admin));
+    assert_eq!(members.get(&inner), Some(&MemberAccess::Write));
+    // Bob is an admin of the inner group but that only gives write access to
+    // the outer group
+    assert_eq!(members.get(&bob), Some(&MemberAccess::Write));
+    assert!(!members.contains_key(&outer));
+
+    assert!(matches!(
+        network.beelay(&alice).expand_group(bob),
+        Err(beelay_core::error::ExpandGroup::NoSuchGroup)
+    ));
+}
+
+#[test]
+fn create_group_with_members_adds_every_member_or_none() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let inner = network.beelay(&alice).create_group(vec![]).unwrap();
+    let created = network
+        .beelay(&alice)
+        .create_group_with_members(
+            vec![],
+            vec![
+                (
+                    KeyhiveEntityId::Individual(bob_contact),
+                    MemberAccess::Write,
+                ),
+                (KeyhiveEntityId::Group(inner), MemberAccess::Read),
+            ],
+        )
+        .unwrap();
+    let confirmed = created
+        .members
+        .iter()
+        .map(|m| match &m.member {
+            KeyhiveEntityId::Individual(card) => (card.peer_id(), m.access),
+            KeyhiveEntityId::Group(id) => (*id, m.access),
+            other => panic!("unexpected member: {}", other),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        confirmed,
+        vec![(bob, MemberAccess::Write), (inner, MemberAccess::Read)]
+    );
+    let members = network
+        .beelay(&alice)
+        .expand_group(created.group_id)
+        .unwrap()
+        .into_iter()
+        .filter_map(|m| match m.member {
+            KeyhiveEntityId::Individual(card) => Some((card.peer_id(), m.access)),
+            KeyhiveEntityId::Group(id) => Some((id, m.access)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+    assert_eq!(members.get(&alice), Some(&MemberAccess::Admin));
+    assert_eq!(members.get(&bob), Some(&MemberAccess::Write));
+    assert_eq!(members.get(&inner), Some(&MemberAccess::Read));
+
+    // A group alice has never heard of fails the whole command
+    let groups_before = network.beelay(&alice).list_my_groups().len();
+    let carol = network.create_peer("carol").build();
+    let unknown = network.beelay(&carol).create_group(vec![]).unwrap();
+    let result = network.beelay(&alice).create_group_with_members(
+        vec![],
+        vec![
+            (KeyhiveEntityId::Group(inner), MemberAccess::Write),
+            (KeyhiveEntityId::Group(unknown), MemberAccess::Read),
+        ],
+    );
+    assert!(matches!(
+        result,
+        Err(beelay_core::error::CreateGroupWithMembers::NoSuchGroup)
+    ));
+    assert_eq!(network.beelay(&alice).list_my_groups().len(), groups_before);
+}
+
+#[test]
+fn group_membership_cycles_are_rejected() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+
+    // inner -> middle -> outer, where `a -> b` means a is a member of b
+    let outer = network.beelay(&alice).create_group(vec![]).unwrap();
+    let middle = network
+        .beelay(&alice)
+        .create_group(vec![KeyhiveEntityId::Group(outer)])
+        .unwrap();
+    let inner = network
+        .beelay(&alice)
+        .create_group(vec![KeyhiveEntityId::Group(middle)])
+        .unwrap();
+
+    // Closing the loop, directly and via an intermediate group, is refused
+    for member in [middle, inner] {
+        let result = network
+            .beelay(&alice)
+            .add_member_to_group(AddMemberToGroup {
+                group_id: outer,
+                member: KeyhiveEntityId::Group(member),
+                access: MemberAccess::Read,
+                expires_at: None,
+            });
+        assert!(matches!(
+            result,
+            Err(beelay_core::error::AddMember::WouldCreateCycle)
+        ));
+    }
+    let result = network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: outer,
+            member: KeyhiveEntityId::Group(outer),
+            access: MemberAccess::Read,
+            expires_at: None,
+        });
+    assert!(matches!(
+        result,
+        Err(beelay_core::error::AddMember::WouldCreateCycle)
+    ));
+
+    let members = network
+        .beelay(&alice)
+        .expand_group(inner)
+        .unwrap()
+        .into_iter()
+        .filter_map(|m| match m.member {
+            KeyhiveEntityId::Group(id) => Some(id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(members, HashSet::from([middle, outer]));
+}
+
+#[test]
+fn preview_access_change_reports_diff_without_applying_it() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    let team = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: team,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Write,
+            expires_at: None,
+        })
+        .unwrap();
+
+    fn ids(entities: Option<&Vec<KeyhiveEntityId>>) -> HashSet<PeerId> {
+        entities
+            .into_iter()
+            .flatten()
+            .map(|e| match e {
+                KeyhiveEntityId::Individual(card) => card.peer_id(),
+                KeyhiveEntityId::Group(id) => *id,
+                other => panic!("unexpected member: {}", other),
+            })
+            .collect()
+    }
+
+    // Adding the team with read access gives the team and bob pull and read
+    let diff = network
+        .beelay(&alice)
+        .preview_access_change(AccessChange::AddMember(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Group(team),
+            access: MemberAccess::Read,
+            expires_at: None,
+        }))
+        .unwrap();
+    assert!(diff.lost.is_empty());
+    assert_eq!(
+        ids(diff.gained.get(&MemberAccess::Pull)),
+        HashSet::from([team, bob])
+    );
+    assert_eq!(
+        ids(diff.gained.get(&MemberAccess::Read)),
+        HashSet::from([team, bob])
+    );
+    assert!(!diff.gained.contains_key(&MemberAccess::Write));
+
+    let before = network.beelay(&alice).expand_group(group).unwrap();
+    assert_eq!(before.len(), 1);
+
+    // A peer we have never seen before can be previewed too
+    let storage_before = network.beelay(&alice).storage().clone();
+    let diff = network
+        .beelay(&alice)
+        .preview_access_change(AccessChange::AddMember(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(charlie_contact),
+            access: MemberAccess::Admin,
+            expires_at: None,
+        }))
+        .unwrap();
+    for level in MemberAccess::ALL {
+        assert_eq!(ids(diff.gained.get(&level)), HashSet::from([charlie]));
+    }
+    assert_eq!(network.beelay(&alice).expand_group(group).unwrap().len(), 1);
+    assert_eq!(network.beelay(&alice).storage(), &storage_before);
+
+    // Removing a member reports everything reachable through it as lost
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Group(team),
+            access: MemberAccess::Admin,
+            expires_at: None,
+        })
+        .unwrap();
+    let diff = network
+        .beelay(&alice)
+        .preview_access_change(AccessChange::RemoveMember(RemoveMemberFromGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Group(team),
+        }))
+        .unwrap();
+    assert!(diff.gained.is_empty());
+    assert_eq!(
+        ids(diff.lost.get(&MemberAccess::Write)),
+        HashSet::from([team, bob])
+    );
+    assert_eq!(
+        ids(diff.lost.get(&MemberAccess::Admin)),
+        HashSet::from([team])
+    );
+    assert_eq!(network.beelay(&alice).expand_group(group).unwrap().len(), 3);
+
+    // Downgrading an existing member isn't possible by adding it again
+    let diff = network
+        .beelay(&alice)
+        .preview_access_change(AccessChange::AddMember(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Group(team),
+            access: MemberAccess::Read,
+            expires_at: None,
+        }))
+        .unwrap();
+    assert!(diff.is_empty());
+
+    assert!(matches!(
+        network
+            .beelay(&alice)
+            .preview_access_change(AccessChange::AddMember(AddMemberToGroup {
+                group_id: team,
+                member: KeyhiveEntityId::Group(group),
+                access: MemberAccess::Read,
+                expires_at: None,
+            })),
+        Err(beelay_core::error::PreviewAccessChange::WouldCreateCycle)
+    ));
+}
+
+#[test]
+fn expired_memberships_are_revoked() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+
+    // Bob has access to the doc via a group, charlie is a direct member
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Read,
+            expires_at: Some(expires_at),
+        })
+        .unwrap();
+    let (doc_id, _) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Group(group)])
+        .unwrap();
+    network.beelay(&alice).add_member_to_doc_until(
+        doc_id,
+        KeyhiveEntityId::Individual(charlie_contact),
+        MemberAccess::Read,
+        expires_at,
+    );
+
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert_eq!(access.get(&bob), Some(&MemberAccess::Read));
+    assert_eq!(access.get(&charlie), Some(&MemberAccess::Read));
+
+    // Expiries should survive a restart
+    network.reload_peer(&alice);
+    network.beelay(&alice).advance_time(Duration::from_secs(30));
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert!(access.contains_key(&bob));
+    assert!(access.contains_key(&charlie));
+
+    network.beelay(&alice).advance_time(Duration::from_secs(31));
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert!(!access.contains_key(&bob));
+    assert!(!access.contains_key(&charlie));
+    assert!(access.contains_key(&alice));
+    let members = network.beelay(&alice).list_doc_members(doc_id).unwrap();
+    assert!(members.iter().all(|m| match &m.member {
+        KeyhiveEntityId::Individual(card) => card.peer_id() != bob && card.peer_id() != charlie,
+        _ => true,
+    }));
+}
+
+#[test]
+fn expiries_which_fail_to_revoke_are_retried() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Read,
+    );
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+    network.beelay(&alice).add_member_to_doc_until(
+        doc_id,
+        KeyhiveEntityId::Individual(charlie_contact),
+        MemberAccess::Read,
+        expires_at,
+    );
+    // Bob has to have met charlie to learn about his membership
+    let connected = network.connect_stream(&bob, &charlie);
+    network.beelay(&bob).disconnect(connected.left_to_right);
+    let connected = network.connect_stream(&bob, &alice);
+    network.beelay(&bob).disconnect(connected.left_to_right);
+
+    // Give bob alice's record of charlie's expiry. Bob isn't an admin of the
+    // document so he can't revoke charlie's access.
+    let expiry = network
+        .beelay(&alice)
+        .storage()
+        .iter()
+        .find(|(key, _)| key.to_string().contains("membership_expiries"))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .unwrap();
+    network
+        .beelay(&bob)
+        .storage_mut()
+        .insert(expiry.0.clone(), expiry.1);
+    network.reload_peer(&bob);
+    network.beelay(&bob).advance_time(Duration::from_secs(61));
+    assert!(network
+        .beelay(&bob)
+        .query_access(doc_id)
+        .unwrap()
+        .contains_key(&charlie));
+
+    // The expiry is still due, and still stored
+    assert_eq!(
+        network.beelay(&bob).next_tick_deadline(),
+        Some(UnixTimestampMillis::from(expires_at))
+    );
+    assert!(network.beelay(&bob).storage().contains_key(&expiry.0));
+    network.reload_peer(&bob);
+    assert_eq!(
+        network.beelay(&bob).next_tick_deadline(),
+        Some(UnixTimestampMillis::from(expires_at))
+    );
+}
+
+#[test]
+fn scheduled_rotations_include_groups_nested_in_groups() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+    let outer = network.beelay(&alice).create_group(vec![]).unwrap();
+    let inner = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: outer,
+            member: KeyhiveEntityId::Group(inner),
+            access: MemberAccess::Read,
+            expires_at: None,
+        })
+        .unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: inner,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Read,
+            expires_at: Some(expires_at),
+        })
+        .unwrap();
+    let (doc_id, _) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Group(outer)])
+        .unwrap();
+
+    let reasons = network
+        .beelay(&alice)
+        .list_scheduled_rotations(doc_id)
+        .unwrap()
+        .into_iter()
+        .map(|r| (r.at, r.reason))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        reasons,
+        vec![(
+            expires_at,
+            RotationReason::MembershipExpires {
+                member: bob,
+                via_group: Some(inner),
+            }
+        )]
+    );
+}
+
+#[test]
+fn scheduled_rotations_can_be_listed_and_cancelled() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let now = UnixTimestamp::now();
+
+    // Bob's membership of a group in the doc expires first, then charlie's
+    // membership of the doc itself
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Read,
+            expires_at: Some(now + Duration::from_secs(60)),
+        })
+        .unwrap();
+    let (doc_id, _) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Group(group)])
+        .unwrap();
+    network.beelay(&alice).add_member_to_doc_until(
+        doc_id,
+        KeyhiveEntityId::Individual(charlie_contact),
+        MemberAccess::Read,
+        now + Duration::from_secs(120),
+    );
+
+    let rotations = network
+        .beelay(&alice)
+        .list_scheduled_rotations(doc_id)
+        .unwrap();
+    let reasons = rotations
+        .iter()
+        .map(|r| (r.at, r.reason.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        reasons,
+        vec![
+            (
+                now + Duration::from_secs(60),
+                RotationReason::MembershipExpires {
+                    member: bob,
+                    via_group: Some(group),
+                }
+            ),
+            (
+                now + Duration::from_secs(120),
+                RotationReason::MembershipExpires {
+                    member: charlie,
+                    via_group: None,
+                }
+            ),
+        ]
+    );
+    let (bob_rotation, charlie_rotation) = (rotations[0].id, rotations[1].id);
+
+    network
+        .beelay(&alice)
+        .cancel_scheduled_rotation(doc_id, charlie_rotation)
+        .unwrap();
+    assert!(matches!(
+        network
+            .beelay(&alice)
+            .cancel_scheduled_rotation(doc_id, charlie_rotation),
+        Err(beelay_core::error::CancelScheduledRotation::NoSuchRotation)
+    ));
+    let rotations = network
+        .beelay(&alice)
+        .list_scheduled_rotations(doc_id)
+        .unwrap();
+    assert_eq!(
+        rotations.iter().map(|r| r.id).collect::<Vec<_>>(),
+        vec![bob_rotation]
+    );
+
+    network
+        .beelay(&alice)
+        .advance_time(Duration::from_secs(121));
+    assert!(matches!(
+        network
+            .beelay(&alice)
+            .cancel_scheduled_rotation(doc_id, bob_rotation),
+        Err(beelay_core::error::CancelScheduledRotation::AlreadyApplied)
+    ));
+    assert!(network
+        .beelay(&alice)
+        .list_scheduled_rotations(doc_id)
+        .unwrap()
+        .is_empty());
+    // Charlie's membership no longer expires
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert!(!access.contains_key(&bob));
+    assert_eq!(access.get(&charlie), Some(&MemberAccess::Read));
+
+    let unknown_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&alice).list_scheduled_rotations(unknown_doc),
+        Err(beelay_core::error::ListScheduledRotations::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn correlation_ids_are_returned_with_completions() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let (command_id, event) = Event::query_status(doc_id);
+    let (result, correlation_id) = network
+        .beelay(&peer1)
+        .run_command_correlated((command_id, event.with_correlation_id("request-1")));
+    assert!(matches!(result, CommandResult::QueryStatus(_)));
+    assert_eq!(correlation_id.as_deref(), Some("request-1"));
+
+    // Attaching a second ID replaces the first
+    let (command_id, event) = Event::query_status(doc_id);
+    let event = event
+        .with_correlation_id("request-2")
+        .with_correlation_id("request-3");
+    let (_, correlation_id) = network
+        .beelay(&peer1)
+        .run_command_correlated((command_id, event));
+    assert_eq!(correlation_id.as_deref(), Some("request-3"));
+
+    let (_, correlation_id) = network
+        .beelay(&peer1)
+        .run_command_correlated(Event::query_status(doc_id));
+    assert_eq!(correlation_id, None);
+}
+
+#[test]
+fn unregister_endpoints_skips_unknown_ids() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+
+    let endpoint2 = network.beelay(&peer1).register_endpoint(&peer2);
+    let endpoint3 = network.beelay(&peer1).register_endpoint(&peer3);
+    let unknown = beelay_core::EndpointId::from_serialized(u64::MAX);
+
+    let CommandResult::UnregisterEndpoints(removed) =
+        network
+            .beelay(&peer1)
+            .run_command(Event::unregister_endpoints(vec![
+                endpoint2, unknown, endpoint3,
+            ]))
+    else {
+        panic!("expected UnregisterEndpoints result");
+    };
+    assert_eq!(removed, vec![endpoint2, endpoint3]);
+
+    // Unregistering again is a no-op
+    let CommandResult::UnregisterEndpoints(removed) = network
+        .beelay(&peer1)
+        .run_command(Event::unregister_endpoint(endpoint2))
+    else {
+        panic!("expected UnregisterEndpoints result");
+    };
+    assert!(removed.is_empty());
+}
+
+#[test]
+fn register_endpoint_multi_requires_an_audience() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+
+    let CommandResult::RegisterEndpointMulti(result) = network
+        .beelay(&peer1)
+        .run_command(Event::register_endpoint_multi(Vec::new()))
+    else {
+        panic!("expected RegisterEndpointMulti result");
+    };
+    assert_eq!(
+        result,
+        Err(beelay_core::error::RegisterEndpointMulti::NoAudiences)
+    );
+
+    let audiences = ["a", "b"].map(beelay_core::Audience::service_name).to_vec();
+    let CommandResult::RegisterEndpointMulti(Ok(endpoint)) = network
+        .beelay(&peer1)
+        .run_command(Event::register_endpoint_multi(audiences))
+    else {
+        panic!("expected RegisterEndpointMulti result");
+    };
+    let CommandResult::UnregisterEndpoints(removed) = network
+        .beelay(&peer1)
+        .run_command(Event::unregister_endpoint(endpoint))
+    else {
+        panic!("expected UnregisterEndpoints result");
+    };
+    assert_eq!(removed, vec![endpoint]);
+}
+
+#[test]
+fn local_identity_returns_own_entity_and_issued_cards() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+
+    let cards = (0..5)
+        .map(|_| network.beelay(&peer1).contact_card().unwrap())
+        .collect::<Vec<_>>();
+
+    let CommandResult::Keyhive(KeyhiveCommandResult::LocalIdentity(identity)) =
+        network.beelay(&peer1).run_command(Event::local_identity())
+    else {
+        panic!("expected LocalIdentity result");
+    };
+    let KeyhiveEntityId::Individual(card) = &identity.entity_id else {
+        panic!("expected an individual entity id");
+    };
+    assert_eq!(card.peer_id(), peer1);
+    assert_eq!(PeerId::from(identity.verifying_key), peer1);
+
+    let issued = identity
+        .contact_cards
+        .iter()
+        .map(|c| c.to_bytes())
+        .collect::<HashSet<_>>();
+    let expected = cards.iter().map(|c| c.to_bytes()).collect::<HashSet<_>>();
+    assert_eq!(issued, expected);
+}
+
+#[test]
+fn create_doc_from_exported_bundle() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let (doc, initial_commit) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(doc, vec![commit.clone()])
+        .unwrap();
+    let CommandResult::ExportBundle(Ok(bundle)) = network
+        .beelay(&alice)
+        .run_command(Event::export_bundle(doc, vec![commit.hash()], false))
+    else {
+        panic!("unexpected command result");
+    };
+
+    let CommandResult::CreateDocFromBundle(result) = network
+        .beelay(&bob)
+        .run_command(Event::create_doc_from_bundle(bundle.clone(), vec![]))
+    else {
+        panic!("unexpected command result");
+    };
+    let new_doc = result.unwrap();
+    assert_ne!(new_doc, doc);
+
+    let loaded = network.beelay(&bob).load_doc(new_doc).unwrap();
+    let [CommitOrBundle::Bundle(loaded)] = loaded.as_slice() else {
+        panic!("expected a single bundle, got {:?}", loaded);
+    };
+    assert_eq!(loaded.start(), initial_commit.hash());
+    assert_eq!(loaded.end(), commit.hash());
+    assert_eq!(
+        loaded.exported_items().unwrap(),
+        bundle.exported_items().unwrap()
+    );
+    assert_eq!(
+        network.beelay(&bob).doc_status(&new_doc).local_heads,
+        Some(vec![commit.hash()])
+    );
+}
+
+#[test]
+fn create_doc_from_invalid_bundle_creates_nothing() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let end = CommitHash::from([2; 32]);
+    let bundle = beelay_core::CommitBundle::builder()
+        .start(CommitHash::from([1; 32]))
+        .end(end)
+        .checkpoints(vec![end])
+        .bundled_commits(vec![1, 2, 3])
+        .build();
+
+    let CommandResult::CreateDocFromBundle(result) = network
+        .beelay(&peer1)
+        .run_command(Event::create_doc_from_bundle(bundle, vec![]))
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(matches!(
+        result,
+        Err(beelay_core::error::CreateDocFromBundle::InvalidBundle(_))
+    ));
+    let CommandResult::ListDocuments(docs) =
+        network.beelay(&peer1).run_command(Event::list_documents())
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(docs.is_empty());
+}
+
+#[test]
+fn flush_waits_for_outstanding_writes() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    // Nothing is outstanding so repeated flushes complete straight away
+    for _ in 0..2 {
+        let result = network.beelay(&peer1).run_command(Event::flush());
+        assert!(matches!(result, CommandResult::Flush));
+    }
+
+    network.beelay(&peer1).hold_writes();
+    let contents = vec![1, 2, 3];
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    let commit = Commit::new(vec![initial_commit.hash()], contents, hash);
+    let add = network
+        .beelay(&peer1)
+        .submit(Event::add_commits(doc_id, vec![commit]));
+    let flush = network.beelay(&peer1).submit(Event::flush());
+    let second_flush = network.beelay(&peer1).submit(Event::flush());
+    assert!(network.beelay(&peer1).take_result(flush).is_none());
+    assert!(network.beelay(&peer1).take_result(second_flush).is_none());
+
+    network.beelay(&peer1).release_writes();
+    assert!(matches!(
+        network.beelay(&peer1).take_result(flush),
+        Some(CommandResult::Flush)
+    ));
+    assert!(matches!(
+        network.beelay(&peer1).take_result(second_flush),
+        Some(CommandResult::Flush)
+    ));
+    assert!(matches!(
+        network.beelay(&peer1).take_result(add),
+        Some(CommandResult::AddCommits(Ok(_)))
+    ));
+}
+
+#[test]
+fn appended_commits_are_batched_and_failures_reported() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let mut commits = Vec::new();
+    let mut parent = initial_commit.hash();
+    for i in 0..3u8 {
+        let contents = vec![i; 8];
+        let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+        commits.push(Commit::new(vec![parent], contents, hash));
+        parent = hash;
+    }
+
+    // While storage is stalled appended commits back up
+    network.beelay(&peer1).hold_writes();
+    for commit in &commits {
+        network.beelay(&peer1).append_commit(doc_id, commit.clone());
+    }
+    assert_eq!(
+        network.beelay(&peer1).pending_appends(doc_id),
+        commits.len()
+    );
+
+    network.beelay(&peer1).release_writes();
+    assert_eq!(network.beelay(&peer1).pending_appends(doc_id), 0);
+    assert!(network.beelay(&peer1).pop_append_failures().is_empty());
+    let stored = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    for commit in commits {
+        assert!(stored.contains(&CommitOrBundle::Commit(commit)));
+    }
+
+    network.beelay(&peer1).seal_doc(doc_id).unwrap();
+    let contents = vec![9; 8];
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    let rejected = Commit::new(vec![parent], contents, hash);
+    network.beelay(&peer1).append_commit(doc_id, rejected);
+    let failures = network.beelay(&peer1).pop_append_failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].doc_id, doc_id);
+    assert_eq!(failures[0].commits, vec![hash]);
+    assert!(matches!(
+        failures[0].error,
+        beelay_core::error::AddCommits::Sealed
+    ));
+    assert_eq!(network.beelay(&peer1).pending_appends(doc_id), 0);
+}
+
+#[test]
+fn chunked_commits_are_reassembled() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let contents = (0..=255).collect::<Vec<u8>>();
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    let commit = Commit::new(vec![initial_commit.hash()], contents.clone(), hash);
+    let chunks = contents.chunks(100).collect::<Vec<_>>();
+
+    // Out of order and duplicated chunks are fine
+    let mut beelay = network.beelay(&peer1);
+    assert!(beelay
+        .add_commit_chunk(doc_id, &commit, 2, chunks[2], true)
+        .unwrap()
+        .is_none());
+    assert!(beelay
+        .add_commit_chunk(doc_id, &commit, 0, chunks[0], false)
+        .unwrap()
+        .is_none());
+    assert!(beelay
+        .add_commit_chunk(doc_id, &commit, 0, chunks[0], false)
+        .unwrap()
+        .is_none());
+    assert!(matches!(
+        beelay.add_commit_chunk(doc_id, &commit, 0, chunks[1], false),
+        Err(beelay_core::error::AddCommitChunk::ConflictingChunk)
+    ));
+    assert!(matches!(
+        beelay.add_commit_chunk(doc_id, &commit, 3, chunks[1], false),
+        Err(beelay_core::error::AddCommitChunk::ChunkAfterLast)
+    ));
+    assert!(beelay.load_doc(doc_id).unwrap().len() == 1);
+
+    assert!(beelay
+        .add_commit_chunk(doc_id, &commit, 1, chunks[1], false)
+        .unwrap()
+        .is_some());
+    let loaded = beelay.load_doc(doc_id).unwrap();
+    assert!(loaded.contains(&CommitOrBundle::Commit(commit)));
+}
+
+#[test]
+fn incomplete_chunked_commits_can_be_discarded() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let contents = vec![1, 2, 3, 4];
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    let commit = Commit::new(vec![initial_commit.hash()], contents.clone(), hash);
+
+    let mut beelay = network.beelay(&peer1);
+    beelay
+        .add_commit_chunk(doc_id, &commit, 0, &contents[..2], false)
+        .unwrap();
+    let discard = |beelay: &mut network::BeelayHandle<'_>| match beelay
+        .run_command(Event::discard_commit_chunks(doc_id, hash))
+    {
+        CommandResult::DiscardCommitChunks(discarded) => discarded,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    assert!(discard(&mut beelay));
+    assert!(!discard(&mut beelay));
+
+    // The first chunk is gone so the last chunk alone doesn't complete the commit
+    assert!(beelay
+        .add_commit_chunk(doc_id, &commit, 1, &contents[2..], true)
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        beelay.load_doc(doc_id).unwrap(),
+        vec![CommitOrBundle::Commit(initial_commit)]
+    );
+}
+
+#[test]
+fn next_tick_deadline_tracks_membership_expiries() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    assert_eq!(network.beelay(&alice).next_tick_deadline(), None);
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc_until(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Read,
+        expires_at,
+    );
+    assert_eq!(
+        network.beelay(&alice).next_tick_deadline(),
+        Some(UnixTimestampMillis::from(expires_at))
+    );
+
+    // Once the expiry has been processed there is nothing left to wait for
+    let summary = network
+        .beelay(&alice)
+        .advance_time_verbose(Duration::from_secs(61));
+    assert_eq!(summary.expired_memberships, 1);
+    assert_eq!(network.beelay(&alice).next_tick_deadline(), None);
+}
+
+#[test]
+fn create_doc_with_grants_shares_doc_from_creation() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Read,
+            expires_at: None,
+        })
+        .unwrap();
+
+    let contents = vec![1, 2, 3];
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    let initial_commit = Commit::new(vec![], contents, hash);
+    let result = network
+        .beelay(&alice)
+        .run_command(Event::create_doc_with_grants(
+            initial_commit,
+            vec![],
+            vec![
+                (KeyhiveEntityId::Group(group), MemberAccess::Read),
+                (
+                    KeyhiveEntityId::Individual(charlie_contact),
+                    MemberAccess::Write,
+                ),
+            ],
+        ));
+    let CommandResult::CreateDocWithGrants(Ok((doc_id, granted))) = result else {
+        panic!("unexpected command result: {:?}", result);
+    };
+    assert_eq!(
+        granted,
+        vec![(group, MemberAccess::Read), (charlie, MemberAccess::Write)]
+    );
+
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert_eq!(access.get(&bob), Some(&MemberAccess::Read));
+    assert_eq!(access.get(&charlie), Some(&MemberAccess::Write));
+}
+
+#[test]
+fn create_doc_with_unknown_grantee_creates_nothing() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    // Alice has never heard of bob's group
+    let bobs_group = network.beelay(&bob).create_group(vec![]).unwrap();
+
+    let contents = vec![4, 5, 6];
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    let result = network
+        .beelay(&alice)
+        .run_command(Event::create_doc_with_grants(
+            Commit::new(vec![], contents, hash),
+            vec![],
+            vec![(KeyhiveEntityId::Group(bobs_group), MemberAccess::Read)],
+        ));
+    assert!(matches!(
+        result,
+        CommandResult::CreateDocWithGrants(Err(
+            beelay_core::error::CreateDocWithGrants::MemberNotFound(_)
+        ))
+    ));
+    let CommandResult::ListDocuments(docs) =
+        network.beelay(&alice).run_command(Event::list_documents())
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(docs.is_empty());
+}
+
+#[test]
+fn change_member_access_downgrades_in_place() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let charlie = network.create_peer("charlie").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc_id, initial_commit) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact.clone()),
+        MemberAccess::Write,
+    );
+
+    let previous = network
+        .beelay(&alice)
+        .change_member_access(
+            doc_id,
+            KeyhiveEntityId::Individual(bob_contact.clone()),
+            MemberAccess::Read,
+        )
+        .unwrap();
+    assert_eq!(previous, MemberAccess::Write);
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert_eq!(access.get(&bob), Some(&MemberAccess::Read));
+
+    // Bob can still read the document after the downgrade
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+    network.connect_stream(&bob, &alice);
+    let on_bob = network.beelay(&bob).load_doc(doc_id).unwrap();
+    assert!(on_bob.contains(&CommitOrBundle::Commit(commit)));
+
+    // Changing the access of someone who isn't a member doesn't add them
+    let charlie_contact = network.beelay(&charlie).contact_card().unwrap();
+    assert!(matches!(
+        network.beelay(&alice).change_member_access(
+            doc_id,
+            KeyhiveEntityId::Individual(charlie_contact),
+            MemberAccess::Read,
+        ),
+        Err(beelay_core::error::ChangeMemberAccess::NotAMember)
+    ));
+    let access = network.beelay(&alice).query_access(doc_id).unwrap();
+    assert!(!access.contains_key(&charlie));
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&alice).change_member_access(
+            missing_doc,
+            KeyhiveEntityId::Individual(bob_contact),
+            MemberAccess::Read,
+        ),
+        Err(beelay_core::error::ChangeMemberAccess::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn verify_doc_reports_corruption() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let keys_before = network
+        .beelay(&peer1)
+        .storage()
+        .keys()
+        .cloned()
+        .collect::<HashSet<_>>();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+
+    let verify = |network: &mut Network, doc_id| match network
+        .beelay(&peer1)
+        .run_command(Event::verify_doc(doc_id))
+    {
+        CommandResult::VerifyDoc(result) => result,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    assert_eq!(verify(&mut network, doc_id).unwrap(), vec![]);
+
+    // Overwrite the contents of the new commit
+    let doc_prefix = beelay_core::StorageKey::sedimentree_root(&doc_id);
+    let blob_key = network
+        .beelay(&peer1)
+        .storage()
+        .keys()
+        .find(|k| !keys_before.contains(*k) && !doc_prefix.is_prefix_of(k))
+        .cloned()
+        .unwrap();
+    network
+        .beelay(&peer1)
+        .storage_mut()
+        .insert(blob_key, vec![0; 8]);
+
+    // And lose the initial commit
+    network
+        .beelay(&peer1)
+        .storage_mut()
+        .remove(&beelay_core::StorageKey::sedimentree_commit(
+            &doc_id,
+            initial_commit.hash(),
+        ));
+
+    let problems = verify(&mut network, doc_id).unwrap();
+    assert_eq!(problems.len(), 2);
+    assert!(problems.iter().any(|p| matches!(
+        p,
+        DocProblem::BlobHashMismatch { item: DocItem::Commit(hash), .. } if *hash == commit.hash()
+    )));
+    assert!(problems.contains(&DocProblem::DanglingParent {
+        commit: commit.hash(),
+        parent: initial_commit.hash(),
+    }));
+
+    let missing = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        verify(&mut network, missing),
+        Err(beelay_core::error::VerifyDoc::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn export_access_log_lists_changes_in_order() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact.clone()),
+        MemberAccess::Write,
+    );
+    network.beelay(&alice).rotate_doc_key(doc_id).unwrap();
+    network
+        .beelay(&alice)
+        .remove_member_from_doc(doc_id, KeyhiveEntityId::Individual(bob_contact))
+        .unwrap();
+
+    let log = network.beelay(&alice).export_access_log(doc_id).unwrap();
+    let position = |change: AccessLogChange| {
+        log.iter()
+            .position(|entry| entry.change == change)
+            .unwrap_or_else(|| panic!("{:?} not in access log", change))
+    };
+    let added = position(AccessLogChange::Added {
+        member: bob,
+        access: MemberAccess::Write,
+    });
+    let removed = position(AccessLogChange::Removed {
+        member: bob,
+        access: MemberAccess::Write,
+    });
+    assert!(added < removed);
+    let share_added = position(AccessLogChange::KeyShareAdded { member: bob });
+    let rotated = log
+        .iter()
+        .rposition(|entry| entry.change == AccessLogChange::KeyRotated)
+        .unwrap();
+    assert!(share_added < rotated);
+
+    for entry in [&log[added], &log[removed], &log[rotated]] {
+        assert_eq!(entry.author, alice);
+        assert!(entry.recorded_at.is_some());
+    }
+    let digests = log.iter().map(|entry| entry.digest).collect::<HashSet<_>>();
+    assert_eq!(digests.len(), log.len());
+
+    let missing_doc = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&alice).export_access_log(missing_doc),
+        Err(beelay_core::error::ExportAccessLog::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn identical_blobs_are_shared_between_docs() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_a, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let (doc_b, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let blobs = |network: &mut Network| {
+        network
+            .beelay(&peer1)
+            .storage()
+            .iter()
+            .filter(|(k, _)| k.namespace() == "blobs")
+            .map(|(k, v)| (k.clone(), v.len() as u64))
+            .collect::<HashMap<_, _>>()
+    };
+    let blobs_before = blobs(&mut network);
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1; 100],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_a, vec![commit.clone()])
+        .unwrap();
+    let commit_blob_bytes = blobs(&mut network)
+        .into_iter()
+        .find(|(k, _)| !blobs_before.contains_key(k))
+        .map(|(_, len)| len)
+        .unwrap();
+    let dedup_stats =
+        |network: &mut Network| match network.beelay(&peer1).run_command(Event::dedup_stats()) {
+            CommandResult::DedupStats(stats) => stats,
+            other => panic!("unexpected command result: {:?}", other),
+        };
+    let before = dedup_stats(&mut network);
+    assert_eq!(before.blobs, 3);
+    assert_eq!(before.shared_blobs, 0);
+    assert_eq!(before.bytes_saved, 0);
+
+    // Give doc_b a commit referring to the same blob as the one in doc_a
+    let key = beelay_core::StorageKey::sedimentree_commit(&doc_a, commit.hash());
+    let mut beelay = network.beelay(&peer1);
+    let record = beelay.storage().get(&key).unwrap().clone();
+    beelay.storage_mut().insert(
+        beelay_core::StorageKey::sedimentree_commit(&doc_b, commit.hash()),
+        record,
+    );
+    network.reload_peer(&peer1);
+
+    assert_eq!(
+        dedup_stats(&mut network),
+        DedupStats {
+            blobs: 3,
+            shared_blobs: 1,
+            stored_bytes: before.stored_bytes,
+            bytes_saved: commit_blob_bytes,
+        }
+    );
+
+    // Deleting doc_a leaves the blob doc_b still needs
+    network.beelay(&peer1).delete_doc(doc_a);
+    let CommandResult::VerifyDoc(problems) =
+        network.beelay(&peer1).run_command(Event::verify_doc(doc_b))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(problems.unwrap(), vec![]);
+    assert!(blobs(&mut network)
+        .values()
+        .any(|len| *len == commit_blob_bytes));
+    let after = dedup_stats(&mut network);
+    assert_eq!(after.blobs, 2);
+    assert_eq!(after.shared_blobs, 0);
+    assert_eq!(after.bytes_saved, 0);
+}
+
+#[test]
+fn doc_stats_counts_stored_commits() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let branches = (1..=2)
+        .map(|i| {
+            Commit::new(
+                vec![initial_commit.hash()],
+                vec![i; 10],
+                CommitHash::from([i; 32]),
+            )
+        })
+        .collect::<Vec<_>>();
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, branches)
+        .unwrap();
+
+    let CommandResult::DocStats(stats) =
+        network.beelay(&peer1).run_command(Event::doc_stats(doc_id))
+    else {
+        panic!("unexpected command result");
+    };
+    // This is the only document so every stored blob belongs to it
+    let blob_bytes = network
+        .beelay(&peer1)
+        .storage()
+        .iter()
+        .filter(|(k, _)| k.namespace() == "blobs")
+        .map(|(_, v)| v.len() as u64)
+        .sum::<u64>();
+    assert_eq!(
+        stats,
+        Some(DocStats {
+            commits: 3,
+            bundles: 0,
+            stored_bytes: blob_bytes,
+            heads: 2,
+            complete: true,
+            size_limit: DocSizeLimit::default(),
+        })
+    );
+
+    let CommandResult::DocStats(stats) =
+        network
+            .beelay(&peer1)
+            .run_command(Event::doc_stats(
+                DocumentId::random(&mut rand::thread_rng()),
+            ))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(stats, None);
+}
+
+#[test]
+fn commits_beyond_the_size_limit_are_rejected() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    // A single commit bigger than the byte limit is rejected
+    let CommandResult::SetDocSizeLimit =
+        network
+            .beelay(&peer1)
+            .run_command(Event::set_doc_size_limit(DocSizeLimit {
+                max_bytes: Some(1000),
+                max_commits: None,
+            }))
+    else {
+        panic!("unexpected command result");
+    };
+    let too_big = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1; 1001],
+        CommitHash::from([1; 32]),
+    );
+    assert!(matches!(
+        network.beelay(&peer1).add_commits(doc_id, vec![too_big]),
+        Err(beelay_core::error::AddCommits::SizeLimitExceeded)
+    ));
+
+    let limit = DocSizeLimit {
+        max_bytes: None,
+        max_commits: Some(3),
+    };
+    let CommandResult::SetDocSizeLimit = network
+        .beelay(&peer1)
+        .run_command(Event::set_doc_size_limit(limit))
+    else {
+        panic!("unexpected command result");
+    };
+    let mut commits = vec![initial_commit];
+    for i in 2..=3 {
+        let commit = Commit::new(
+            vec![commits.last().unwrap().hash()],
+            vec![i; 10],
+            CommitHash::from([i; 32]),
+        );
+        network
+            .beelay(&peer1)
+            .add_commits(doc_id, vec![commit.clone()])
+            .unwrap();
+        commits.push(commit);
+    }
+
+    // The document is full, commits we already have can still be re-added
+    let next = Commit::new(
+        vec![commits.last().unwrap().hash()],
+        vec![4; 10],
+        CommitHash::from([4; 32]),
+    );
+    assert!(matches!(
+        network.beelay(&peer1).add_commits(doc_id, vec![next]),
+        Err(beelay_core::error::AddCommits::SizeLimitExceeded)
+    ));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commits[1].clone()])
+        .unwrap();
+
+    let CommandResult::DocStats(Some(stats)) =
+        network.beelay(&peer1).run_command(Event::doc_stats(doc_id))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(stats.commits, 3);
+    assert_eq!(stats.size_limit, limit);
+}
+
+#[test]
+fn delegated_endpoints_require_a_group_we_belong_to() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    let audience = beelay_core::Audience::peer(&bob);
+
+    let register = |network: &mut Network, on_behalf_of| {
+        let CommandResult::RegisterDelegatedEndpoint(result) = network
+            .beelay(&alice)
+            .run_command(Event::register_delegated_endpoint(audience, on_behalf_of))
+        else {
+            panic!("expected RegisterDelegatedEndpoint result");
+        };
+        result.err()
+    };
+
+    assert_eq!(register(&mut network, KeyhiveEntityId::Group(group)), None);
+    assert_eq!(
+        register(&mut network, KeyhiveEntityId::Group(bob)),
+        Some(InvalidDelegation::NoSuchGroup(bob))
+    );
+    assert_eq!(
+        register(&mut network, KeyhiveEntityId::Individual(bob_contact)),
+        Some(InvalidDelegation::NotAGroup)
+    );
+}
+
+// Alice owns a document shared with a group, and each of `members` joins the
+// group with the given access and learns of it from alice
+fn shared_through_group(
+    network: &mut Network,
+    alice: PeerId,
+    members: &[(PeerId, MemberAccess)],
+) -> (PeerId, DocumentId, Commit) {
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    for (member, access) in members {
+        let contact = network.beelay(member).contact_card().unwrap();
+        network
+            .beelay(&alice)
+            .add_member_to_group(AddMemberToGroup {
+                group_id: group,
+                member: KeyhiveEntityId::Individual(contact),
+                access: *access,
+                expires_at: None,
+            })
+            .unwrap();
+    }
+    let (doc_id, initial_commit) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Group(group)])
+        .unwrap();
+    let streams = members
+        .iter()
+        .map(|(member, _)| (*member, network.connect_stream(member, &alice)))
+        .collect::<Vec<_>>();
+    for (member, connected) in streams {
+        network.beelay(&member).disconnect(connected.left_to_right);
+    }
+    (group, doc_id, initial_commit)
+}
+
+fn delegation_failures(network: &mut Network, peer: PeerId) -> Vec<InvalidDelegation> {
+    network
+        .beelay(&peer)
+        .take_request_failures()
+        .into_iter()
+        .filter_map(|failure| match failure {
+            beelay_core::error::RequestFailure::InvalidDelegation { reason, .. } => Some(reason),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn delegated_requests_are_served_for_members() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let (group, doc_id, initial_commit) =
+        shared_through_group(&mut network, alice, &[(bob, MemberAccess::Write)]);
+
+    let bobs_commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&bob)
+        .add_commits(doc_id, vec![bobs_commit.clone()])
+        .unwrap();
+    let endpoint = network
+        .beelay(&bob)
+        .register_delegated_endpoint(&alice, group);
+    network
+        .beelay(&bob)
+        .sync_with_endpoint(endpoint, doc_id)
+        .unwrap();
+    assert!(network
+        .beelay(&alice)
+        .load_doc(doc_id)
+        .unwrap()
+        .contains(&CommitOrBundle::Commit(bobs_commit)));
+
+    assert!(delegation_failures(&mut network, alice).is_empty());
+}
+
+#[test]
+fn delegated_uploads_need_write_access_in_the_group() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let carol = network.create_peer("carol").build();
+    let (group, doc_id, initial_commit) =
+        shared_through_group(&mut network, alice, &[(carol, MemberAccess::Read)]);
+
+    // The group can write to the document but carol can only read through
+    // it, so she can't upload
+    let carols_commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([2; 32]),
+    );
+    network
+        .beelay(&carol)
+        .add_commits(doc_id, vec![carols_commit.clone()])
+        .unwrap();
+    let endpoint = network
+        .beelay(&carol)
+        .register_delegated_endpoint(&alice, group);
+    assert!(matches!(
+        network.beelay(&carol).sync_with_endpoint(endpoint, doc_id),
+        Err(beelay_core::error::SyncWithEndpoint::Rejected)
+    ));
+    assert_eq!(
+        delegation_failures(&mut network, alice),
+        vec![InvalidDelegation::InsufficientAccess {
+            group,
+            access: MemberAccess::Read
+        }]
+    );
+    assert!(!network
+        .beelay(&alice)
+        .load_doc(doc_id)
+        .unwrap()
+        .contains(&CommitOrBundle::Commit(carols_commit)));
+}
+
+#[test]
+fn delegated_requests_from_revoked_members_are_rejected() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let (group, doc_id, _) =
+        shared_through_group(&mut network, alice, &[(bob, MemberAccess::Write)]);
+    let endpoint = network
+        .beelay(&bob)
+        .register_delegated_endpoint(&alice, group);
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    network
+        .beelay(&alice)
+        .remove_member_from_group(RemoveMemberFromGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact),
+        })
+        .unwrap();
+
+    // Bob's chain still shows him being added, our own state shows him removed
+    assert!(matches!(
+        network.beelay(&bob).sync_with_endpoint(endpoint, doc_id),
+        Err(beelay_core::error::SyncWithEndpoint::Rejected)
+    ));
+    assert_eq!(
+        delegation_failures(&mut network, alice),
+        vec![InvalidDelegation::NotAMember(group)]
+    );
+}
+
+#[test]
+fn delegated_requests_from_non_members_are_rejected() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let (group, doc_id, _) =
+        shared_through_group(&mut network, alice, &[(bob, MemberAccess::Write)]);
+    let endpoint = network
+        .beelay(&bob)
+        .register_delegated_endpoint(&alice, group);
+
+    // Eve replays bob's requests, chain and all, under her own key
+    let eve = ed25519_dalek::SigningKey::generate(&mut rand::thread_rng());
+    network.forge_requests_from(&bob, move |request| {
+        network::resign_request(&request, &eve, |_| ())
+    });
+    // Alice answers eve, so bob never sees a response
+    assert!(network
+        .beelay(&bob)
+        .sync_with_endpoint(endpoint, doc_id)
+        .is_err());
+    assert_eq!(
+        delegation_failures(&mut network, alice),
+        vec![InvalidDelegation::NotAMember(group)]
+    );
+}
+
+#[test]
+fn delegated_requests_with_malformed_chains_are_rejected() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let (group, doc_id, _) =
+        shared_through_group(&mut network, alice, &[(bob, MemberAccess::Write)]);
+    let endpoint = network
+        .beelay(&bob)
+        .register_delegated_endpoint(&alice, group);
+
+    // The first request is a fetch, which is encoded in the last 33 bytes. The
+    // byte before those is the end of the signature of the last operation in
+    // the chain.
+    let bob_key = network.beelay(&bob).signing_key();
+    network.forge_requests_from(&bob, move |request| {
+        network::resign_request(&request, &bob_key, |payload| {
+            let last_chain_byte = payload.len() - 34;
+            payload[last_chain_byte] ^= 1;
+        })
+    });
+    assert!(matches!(
+        network.beelay(&bob).sync_with_endpoint(endpoint, doc_id),
+        Err(beelay_core::error::SyncWithEndpoint::Rejected)
+    ));
+    assert!(matches!(
+        delegation_failures(&mut network, alice).as_slice(),
+        [InvalidDelegation::InvalidChain(_)]
+    ));
+}
+
+#[test]
+fn has_commits_reports_stored_commits() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+
+    let hashes = vec![
+        commit.hash(),
+        CommitHash::from([2; 32]),
+        initial_commit.hash(),
+    ];
+    let CommandResult::HasCommits(present) = network
+        .beelay(&peer1)
+        .run_command(Event::has_commits(doc_id, hashes.clone()))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(present, vec![true, false, true]);
+
+    let CommandResult::HasCommits(present) = network.beelay(&peer1).run_command(
+        Event::has_commits(DocumentId::random(&mut rand::thread_rng()), hashes),
+    ) else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(present, vec![false, false, false]);
+}
+
+#[test]
+fn loose_commits_are_bundled_by_policy() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let policy = BundlingPolicy {
+        max_loose_commits: Some(3),
+        max_loose_bytes: None,
+    };
+    let CommandResult::SetBundlingPolicy = network
+        .beelay(&peer1)
+        .run_command(Event::set_bundling_policy(policy))
+    else {
+        panic!("unexpected command result");
+    };
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let mut commits = vec![initial_commit];
+    for i in 1..=3 {
+        let commit = Commit::new(
+            vec![commits.last().unwrap().hash()],
+            vec![i; 10],
+            CommitHash::from([i; 32]),
+        );
+        network
+            .beelay(&peer1)
+            .add_commits(doc_id, vec![commit.clone()])
+            .unwrap();
+        commits.push(commit);
+    }
+    let head = commits.pop().unwrap();
+
+    let bundled = network
+        .beelay(&peer1)
+        .pop_notifications()
+        .remove(&doc_id)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|event| matches!(event, DocEvent::Bundled { .. }))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        bundled,
+        vec![DocEvent::Bundled {
+            start: commits[0].hash(),
+            end: commits[2].hash(),
+            num_commits: 3,
+        }]
+    );
+
+    let loaded = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert!(loaded.contains(&CommitOrBundle::Commit(head)));
+    let bundle = loaded
+        .into_iter()
+        .find_map(|item| match item {
+            CommitOrBundle::Bundle(bundle) => Some(bundle),
+            CommitOrBundle::Commit(_) => None,
+        })
+        .unwrap();
+    assert_eq!(
+        bundle
+            .exported_items()
+            .unwrap()
+            .into_iter()
+            .collect::<HashSet<_>>(),
+        commits
+            .into_iter()
+            .map(CommitOrBundle::Commit)
+            .collect::<HashSet<_>>()
+    );
+}
+
+#[test]
+fn group_metadata_can_be_set_and_replaced() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    assert_eq!(
+        network.beelay(&alice).get_group_metadata(group).unwrap(),
+        GroupMetadata::default()
+    );
+
+    let metadata = GroupMetadata {
+        name: Some("Editors".to_string()),
+        entries: [("team".to_string(), "docs".to_string())].into(),
+    };
+    network
+        .beelay(&alice)
+        .set_group_metadata(group, metadata.clone())
+        .unwrap();
+    assert_eq!(
+        network.beelay(&alice).get_group_metadata(group).unwrap(),
+        metadata
+    );
+
+    // Setting metadata replaces all of it rather than merging
+    let renamed = GroupMetadata {
+        name: Some("Reviewers".to_string()),
+        entries: Default::default(),
+    };
+    network
+        .beelay(&alice)
+        .set_group_metadata(group, renamed.clone())
+        .unwrap();
+    assert_eq!(
+        network.beelay(&alice).get_group_metadata(group).unwrap(),
+        renamed
+    );
+
+    assert!(matches!(
+        network.beelay(&alice).set_group_metadata(bob, metadata),
+        Err(beelay_core::error::SetGroupMetadata::NoSuchGroup)
+    ));
+    assert!(matches!(
+        network.beelay(&alice).get_group_metadata(bob),
+        Err(beelay_core::error::ExpandGroup::NoSuchGroup)
+    ));
+}
+
+#[test]
+fn warmed_docs_load_from_memory() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let keys_before = network
+        .beelay(&peer1)
+        .storage()
+        .keys()
+        .cloned()
+        .collect::<HashSet<_>>();
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+
+    let missing = DocumentId::random(&mut rand::thread_rng());
+    let warmed = match network
+        .beelay(&peer1)
+        .run_command(Event::warm_docs(vec![doc_id, missing]))
+    {
+        CommandResult::WarmDocs(result) => result,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    assert_eq!(warmed.len(), 2);
+    assert!(warmed[&doc_id].is_ok());
+    assert!(matches!(
+        warmed[&missing],
+        Err(beelay_core::error::WarmDoc::NoSuchDocument)
+    ));
+
+    // Corrupting the stored commit doesn't matter as it is no longer read
+    let doc_prefix = beelay_core::StorageKey::sedimentree_root(&doc_id);
+    let blob_key = network
+        .beelay(&peer1)
+        .storage()
+        .keys()
+        .find(|k| !keys_before.contains(*k) && !doc_prefix.is_prefix_of(k))
+        .cloned()
+        .unwrap();
+    network
+        .beelay(&peer1)
+        .storage_mut()
+        .insert(blob_key, vec![0; 8]);
+
+    let loaded = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    assert!(loaded.contains(&CommitOrBundle::Commit(commit)));
+}
+
+#[test]
+fn list_my_groups_includes_nested_groups() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let inner = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: inner,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Write,
+            expires_at: None,
+        })
+        .unwrap();
+    let outer = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: outer,
+            member: KeyhiveEntityId::Group(inner),
+            access: MemberAccess::Read,
+            expires_at: None,
+        })
+        .unwrap();
+
+    let my_groups = |network: &mut Network, peer: &PeerId| {
+        network
+            .beelay(peer)
+            .list_my_groups()
+            .into_iter()
+            .map(|m| match m.group {
+                KeyhiveEntityId::Group(id) => (id, m.access),
+                other => panic!("unexpected group: {}", other),
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    assert_eq!(
+        my_groups(&mut network, &alice),
+        HashMap::from([(inner, MemberAccess::Admin), (outer, MemberAccess::Admin)])
+    );
+
+    network.connect_stream(&bob, &alice);
+    assert_eq!(
+        my_groups(&mut network, &bob),
+        HashMap::from([(inner, MemberAccess::Write), (outer, MemberAccess::Read)])
+    );
+}
+
+#[test]
+fn add_commits_checked_reports_orphans() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let missing = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    let orphan = Commit::new(vec![missing.hash()], vec![2], CommitHash::from([2; 32]));
+    let orphan_child = Commit::new(vec![orphan.hash()], vec![3], CommitHash::from([3; 32]));
+    let attached = Commit::new(
+        vec![initial_commit.hash()],
+        vec![4],
+        CommitHash::from([4; 32]),
+    );
+
+    // In strict mode nothing is stored if any commit is missing its parents
+    let err = network
+        .beelay(&peer1)
+        .add_commits_checked(
+            doc_id,
+            vec![orphan.clone(), orphan_child.clone(), attached.clone()],
+            true,
+        )
+        .unwrap_err();
+    let beelay_core::error::AddCommits::MissingParents(missing_hashes) = err else {
+        panic!("unexpected error: {:?}", err);
+    };
+    assert_eq!(missing_hashes, vec![orphan.hash(), orphan_child.hash()]);
+    let stored = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    assert!(!stored
+        .iter()
+        .any(|c| matches!(c, CommitOrBundle::Commit(c) if c.hash() == attached.hash())));
+
+    let added = network
+        .beelay(&peer1)
+        .add_commits_checked(
+            doc_id,
+            vec![orphan.clone(), orphan_child.clone(), attached.clone()],
+            false,
+        )
+        .unwrap();
+    assert_eq!(added.accepted, vec![attached.hash()]);
+    assert_eq!(added.orphaned, vec![orphan.hash(), orphan_child.hash()]);
+
+    // Once the missing parent arrives nothing is orphaned
+    let added = network
+        .beelay(&peer1)
+        .add_commits_checked(doc_id, vec![missing.clone()], true)
+        .unwrap();
+    assert_eq!(added.accepted, vec![missing.hash()]);
+    assert!(added.orphaned.is_empty());
+}
+
+#[test]
+fn add_commits_reports_whether_the_heads_moved() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let commit1 = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    let commit2 = Commit::new(
+        vec![commit1.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([2; 32]),
+    );
+
+    let added = network
+        .beelay(&peer1)
+        .add_commits_verbose(doc_id, vec![commit1.clone()])
+        .unwrap();
+    assert_eq!(added.new_commits, 1);
+    assert_eq!(added.previous_heads, vec![initial_commit.hash()]);
+    assert_eq!(added.heads, vec![commit1.hash()]);
+    assert!(added.heads_changed());
+
+    // Re-adding a commit changes nothing
+    let added = network
+        .beelay(&peer1)
+        .add_commits_verbose(doc_id, vec![commit1.clone()])
+        .unwrap();
+    assert_eq!(added.new_commits, 0);
+    assert_eq!(added.previous_heads, vec![commit1.hash()]);
+    assert_eq!(added.heads, vec![commit1.hash()]);
+    assert!(!added.heads_changed());
+
+    // Only the commits we didn't have are counted
+    let added = network
+        .beelay(&peer1)
+        .add_commits_verbose(
+            doc_id,
+            vec![commit1.clone(), commit2.clone(), commit2.clone()],
+        )
+        .unwrap();
+    assert_eq!(added.new_commits, 1);
+    assert_eq!(added.heads, vec![commit2.hash()]);
+    assert!(added.heads_changed());
+}
+#[test]
+fn pending_orphans_lists_gaps_until_filled() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    assert!(network.beelay(&peer1).pending_orphans(doc_id).is_empty());
+
+    let missing = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    let other_missing = CommitHash::from([5; 32]);
+    let orphan = Commit::new(
+        vec![missing.hash(), other_missing],
+        vec![2],
+        CommitHash::from([2; 32]),
+    );
+    let orphan_child = Commit::new(vec![orphan.hash()], vec![3], CommitHash::from([3; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits_checked(doc_id, vec![orphan.clone(), orphan_child], false)
+        .unwrap();
+
+    // Only the commit waiting on the gap is listed, not its descendants
+    let pending = network.beelay(&peer1).pending_orphans(doc_id);
+    assert_eq!(
+        pending,
+        vec![beelay_core::PendingOrphan {
+            commit: orphan.hash(),
+            missing_parents: vec![missing.hash(), other_missing],
+        }]
+    );
+
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![missing.clone()])
+        .unwrap();
+    let pending = network.beelay(&peer1).pending_orphans(doc_id);
+    assert_eq!(
+        pending,
+        vec![beelay_core::PendingOrphan {
+            commit: orphan.hash(),
+            missing_parents: vec![other_missing],
+        }]
+    );
+
+    let last = Commit::new(vec![initial_commit.hash()], vec![5], other_missing);
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![last])
+        .unwrap();
+    assert!(network.beelay(&peer1).pending_orphans(doc_id).is_empty());
+}
+
+#[test]
+fn find_unreachable_lists_commits_no_head_reaches() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let a = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    let b = Commit::new(vec![a.hash()], vec![2], CommitHash::from([2; 32]));
+    let c = Commit::new(vec![b.hash()], vec![3], CommitHash::from([3; 32]));
+    let checkpoint = Commit::new(vec![a.hash()], vec![4], CommitHash::from([4; 32]));
+    let orphan = Commit::new(
+        vec![CommitHash::from([5; 32])],
+        vec![6],
+        CommitHash::from([6; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits_checked(
+            doc_id,
+            vec![a.clone(), b, c.clone(), checkpoint.clone(), orphan],
+            false,
+        )
+        .unwrap();
+    assert!(network.beelay(&peer1).find_unreachable(doc_id).is_empty());
+
+    // Once a bundle holds `checkpoint` it is no longer a head, and walking
+    // back from the end of the bundle skips straight to its start
+    let bundle = CommitBundle::builder()
+        .start(a.hash())
+        .end(c.hash())
+        .checkpoints(vec![checkpoint.hash()])
+        .bundled_commits(vec![1, 2, 3, 4])
+        .build();
+    let CommandResult::AddBundle(Ok(_)) = network
+        .beelay(&peer1)
+        .run_command(Event::add_bundle(doc_id, bundle))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(
+        network.beelay(&peer1).find_unreachable(doc_id),
+        vec![checkpoint.hash()]
+    );
+
+    let unknown = DocumentId::random(&mut rand::thread_rng());
+    assert!(network.beelay(&peer1).find_unreachable(unknown).is_empty());
+}
+
+#[test]
+fn doc_graph_reports_parents_heads_and_bundles() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    // Hashes with two trailing zeros in base 10 are bundle boundaries
+    let boundary = |n: u16| {
+        let mut hash = [0; 32];
+        hash[30..].copy_from_slice(&n.to_be_bytes());
+        CommitHash::from(hash)
+    };
+    let a = Commit::new(vec![initial_commit.hash()], vec![1], boundary(100));
+    let b = Commit::new(vec![a.hash()], vec![2], CommitHash::from([2; 32]));
+    let c = Commit::new(vec![b.hash()], vec![3], boundary(300));
+    let d = Commit::new(vec![c.hash()], vec![4], CommitHash::from([4; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![a.clone(), b.clone(), c.clone(), d.clone()])
+        .unwrap();
+    let bundle = CommitBundle::builder()
+        .start(a.hash())
+        .end(c.hash())
+        .bundled_commits(vec![1, 2, 3])
+        .build();
+    let CommandResult::AddBundle(Ok(_)) = network
+        .beelay(&peer1)
+        .run_command(Event::add_bundle(doc_id, bundle))
+    else {
+        panic!("unexpected command result");
+    };
+
+    let CommandResult::DocGraph(Some(graph)) =
+        network.beelay(&peer1).run_command(Event::doc_graph(doc_id))
+    else {
+        panic!("unexpected command result");
+    };
+    let commits = graph
+        .commits
+        .iter()
+        .map(|c| (c.hash, (c.parents.clone(), c.is_head)))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(
+        commits,
+        HashMap::from([
+            (initial_commit.hash(), (vec![], false)),
+            (a.hash(), (vec![initial_commit.hash()], false)),
+            (b.hash(), (vec![a.hash()], false)),
+            (c.hash(), (vec![b.hash()], false)),
+            (d.hash(), (vec![c.hash()], true)),
+        ])
+    );
+    assert_eq!(
+        graph.bundles,
+        vec![GraphBundle {
+            start: a.hash(),
+            end: c.hash(),
+            checkpoints: vec![],
+            is_head: false,
+        }]
+    );
+
+    let CommandResult::DocGraph(graph) =
+        network
+            .beelay(&peer1)
+            .run_command(Event::doc_graph(
+                DocumentId::random(&mut rand::thread_rng()),
+            ))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(graph, None);
+}
+
+#[test]
+fn merge_docs_combines_history_and_members() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+
+    let (into, into_initial) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    let (from, from_initial) = network
+        .beelay(&alice)
+        .create_doc_with_contents(vec![4, 5, 6], vec![])
+        .unwrap();
+    let from_next = Commit::new(
+        vec![from_initial.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(from, vec![from_next.clone()])
+        .unwrap();
+    network.beelay(&alice).add_member_to_doc(
+        from,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Write,
+    );
+
+    let heads = network.beelay(&alice).merge_docs(into, from).unwrap();
+    assert_eq!(
+        heads.into_iter().collect::<HashSet<_>>(),
+        HashSet::from([into_initial.hash(), from_next.hash()])
+    );
+
+    let merged = network.beelay(&alice).load_doc(into).unwrap();
+    let merged_commits = merged
+        .into_iter()
+        .filter_map(|item| match item {
+            CommitOrBundle::Commit(c) => Some(c),
+            CommitOrBundle::Bundle(_) => None,
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(
+        merged_commits,
+        HashSet::from([into_initial, from_initial, from_next])
+    );
+    let access = network.beelay(&alice).query_access(into).unwrap();
+    assert_eq!(access.get(&bob), Some(&MemberAccess::Write));
+
+    // Merging again adds nothing new
+    let heads = network.beelay(&alice).merge_docs(into, from).unwrap();
+    assert_eq!(heads.len(), 2);
+    assert!(matches!(
+        network.beelay(&alice).merge_docs(into, into),
+        Err(beelay_core::error::MergeDocs::SameDocument)
+    ));
+}
+
+#[test]
+fn divergence_is_reported_once_until_merged() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let is_merged = |network: &mut Network, doc_id| match network
+        .beelay(&peer1)
+        .run_command(Event::doc_is_merged(doc_id))
+    {
+        CommandResult::DocIsMerged(merged) => merged,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    let divergences = |network: &mut Network| {
+        network
+            .beelay(&peer1)
+            .pop_notifications()
+            .remove(&doc_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|event| match event {
+                DocEvent::Diverged { heads } => Some(heads.into_iter().collect::<HashSet<_>>()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(is_merged(&mut network, doc_id), Some(true));
+    assert!(divergences(&mut network).is_empty());
+
+    let branch = |parents: Vec<CommitHash>, hash: u8| {
+        Commit::new(parents, vec![hash], CommitHash::from([hash; 32]))
+    };
+    let left = branch(vec![initial_commit.hash()], 1);
+    let right = branch(vec![initial_commit.hash()], 2);
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![left.clone(), right.clone()])
+        .unwrap();
+    assert_eq!(
+        divergences(&mut network),
+        vec![HashSet::from([left.hash(), right.hash()])]
+    );
+    assert_eq!(is_merged(&mut network, doc_id), Some(false));
+
+    // Diverging further isn't a new divergence
+    let third = branch(vec![initial_commit.hash()], 3);
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![third.clone()])
+        .unwrap();
+    assert!(divergences(&mut network).is_empty());
+
+    let merge = branch(vec![left.hash(), right.hash(), third.hash()], 4);
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![merge.clone()])
+        .unwrap();
+    assert_eq!(is_merged(&mut network, doc_id), Some(true));
+    assert!(divergences(&mut network).is_empty());
+
+    let again = [branch(vec![merge.hash()], 5), branch(vec![merge.hash()], 6)];
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, again.to_vec())
+        .unwrap();
+    assert_eq!(divergences(&mut network).len(), 1);
+
+    let unknown = DocumentId::random(&mut rand::thread_rng());
+    assert_eq!(is_merged(&mut network, unknown), None);
+}
+
+#[test]
+fn storage_only_loads_with_its_encryption_key() {
+    init_logging();
+    let signing_key = ed25519_dalek::SigningKey::generate(&mut rand::thread_rng());
+    let key = StorageEncryptionKey::from_bytes([7; 32]);
+    let mut network = Network::new();
+    let alice = network
+        .create_peer("alice")
+        .signing_key(signing_key.clone())
+        .storage_encryption_key(key.clone())
+        .build();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+
+    // Reloading keeps the key
+    network.reload_peer(&alice);
+    assert_eq!(network.beelay(&alice).list_documents(None), vec![doc_id]);
+    let storage = network.beelay(&alice).storage().clone();
+
+    let wrong_key = Network::new()
+        .create_peer("alice")
+        .signing_key(signing_key.clone())
+        .storage(storage.clone())
+        .storage_encryption_key(StorageEncryptionKey::from_bytes([8; 32]))
+        .try_build();
+    assert_eq!(wrong_key, Err(beelay_core::error::Load::WrongStorageKey));
+    let no_key = Network::new()
+        .create_peer("alice")
+        .signing_key(signing_key.clone())
+        .storage(storage.clone())
+        .try_build();
+    assert_eq!(no_key, Err(beelay_core::error::Load::WrongStorageKey));
+
+    let mut network = Network::new();
+    let alice = network
+        .create_peer("alice")
+        .signing_key(signing_key)
+        .storage(storage)
+        .storage_encryption_key(key)
+        .build();
+    assert_eq!(network.beelay(&alice).list_documents(None), vec![doc_id]);
+}
+
+#[test]
+fn plaintext_storage_is_left_usable_after_loading_it_with_a_key() {
+    init_logging();
+    let signing_key = ed25519_dalek::SigningKey::generate(&mut rand::thread_rng());
+    let mut network = Network::new();
+    let alice = network
+        .create_peer("alice")
+        .signing_key(signing_key.clone())
+        .build();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    let storage = network.beelay(&alice).storage().clone();
+
+    let mut failed = Network::new();
+    let with_key = failed
+        .create_peer("alice")
+        .signing_key(signing_key.clone())
+        .storage(storage)
+        .storage_encryption_key(StorageEncryptionKey::from_bytes([7; 32]))
+        .try_build();
+    assert_eq!(with_key, Err(beelay_core::error::Load::WrongStorageKey));
+    let storage = failed.take_failed_load_storage().unwrap();
+
+    let mut network = Network::new();
+    let alice = network
+        .create_peer("alice")
+        .signing_key(signing_key)
+        .storage(storage)
+        .build();
+    assert_eq!(network.beelay(&alice).list_documents(None), vec![doc_id]);
+}
+
+#[test]
+fn storage_namespaces_keep_tenants_apart() {
+    init_logging();
+    let signing_key = ed25519_dalek::SigningKey::generate(&mut rand::thread_rng());
+    let mut network = Network::new();
+    let alice = network
+        .create_peer("alice")
+        .signing_key(signing_key.clone())
+        .storage_namespace("tenant-a")
+        .build();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    let storage = network.beelay(&alice).storage().clone();
+    assert!(!storage.is_empty());
+    assert!(storage.keys().all(|key| key.namespace() == "tenant-a"));
+
+    // The same storage only has the tenant's documents inside its namespace
+    let reloaded = |namespace: Option<&'static str>| {
+        let mut network = Network::new();
+        let mut builder = network
+            .create_peer("alice")
+            .signing_key(signing_key.clone())
+            .storage(storage.clone());
+        if let Some(namespace) = namespace {
+            builder = builder.storage_namespace(namespace);
+        }
+        let alice = builder.build();
+        network.beelay(&alice).list_documents(None)
+    };
+    assert_eq!(reloaded(Some("tenant-a")), vec![doc_id]);
+    assert!(reloaded(Some("tenant-b")).is_empty());
+    assert!(reloaded(None).is_empty());
+
+    let reserved = Network::new()
+        .create_peer("alice")
+        .storage_namespace("auth")
+        .try_build();
+    assert_eq!(
+        reserved,
+        Err(beelay_core::error::Load::InvalidStorageNamespace)
+    );
+
+    // A Beelay without a namespace of its own can wipe a tenant
+    let host = network.create_peer("host").storage(storage).build();
+    let delete = |network: &mut Network, namespace: &str| match network
+        .beelay(&host)
+        .run_command(Event::delete_namespace(namespace.to_string()))
+    {
+        CommandResult::DeleteNamespace(result) => result,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    delete(&mut network, "tenant-a").unwrap();
+    let remaining = network.beelay(&host).storage().clone();
+    assert!(!remaining.is_empty());
+    assert!(remaining.keys().all(|key| key.namespace() != "tenant-a"));
+    assert!(matches!(
+        delete(&mut network, "auth"),
+        Err(beelay_core::error::DeleteNamespace::Invalid)
+    ));
+
+    // A namespaced Beelay can't see other namespaces
+    let tenant = network
+        .create_peer("tenant")
+        .storage_namespace("tenant-b")
+        .build();
+    let CommandResult::DeleteNamespace(result) = network
+        .beelay(&tenant)
+        .run_command(Event::delete_namespace("tenant-a".to_string()))
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(matches!(
+        result,
+        Err(beelay_core::error::DeleteNamespace::Namespaced)
+    ));
+}
+
+#[test]
+fn interrupted_namespace_deletions_resume_on_load() {
+    init_logging();
+    let mut network = Network::new();
+    let tenant = network
+        .create_peer("tenant")
+        .storage_namespace("tenant-a")
+        .build();
+    network.beelay(&tenant).create_doc(vec![]).unwrap();
+    let storage = network.beelay(&tenant).storage().clone();
+    let host = network.create_peer("host").storage(storage).build();
+
+    // Pretend the host crashed after recording the wipe but before deleting
+    // anything
+    let marker = beelay_core::StorageKey::auth()
+        .push("deleting_namespaces")
+        .push("tenant-a");
+    network
+        .beelay(&host)
+        .storage_mut()
+        .insert(marker.clone(), Vec::new());
+    network.reload_peer(&host);
+
+    let remaining = network.beelay(&host).storage().clone();
+    assert!(!remaining.contains_key(&marker));
+    assert!(!remaining.is_empty());
+    assert!(remaining.keys().all(|key| key.namespace() != "tenant-a"));
+}
+
+#[test]
+fn list_contacts_marks_revoked_contacts() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let carol = network.create_peer("carol").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let carol_contact = network.beelay(&carol).contact_card().unwrap();
+    let (doc, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    for contact in [bob_contact.clone(), carol_contact] {
+        network.beelay(&alice).add_member_to_doc(
+            doc,
+            KeyhiveEntityId::Individual(contact),
+            MemberAccess::Write,
+        );
+    }
+
+    let contacts = network.beelay(&alice).list_contacts();
+    assert_eq!(
+        contacts.iter().map(|c| c.peer_id).collect::<HashSet<_>>(),
+        HashSet::from([bob, carol])
+    );
+    assert!(contacts
+        .iter()
+        .all(|c| !c.revoked && !c.share_keys.is_empty()));
+
+    network
+        .beelay(&alice)
+        .remove_member_from_doc(doc, KeyhiveEntityId::Individual(bob_contact))
+        .unwrap();
+    let revoked = network
+        .beelay(&alice)
+        .list_contacts()
+        .into_iter()
+        .map(|c| (c.peer_id, c.revoked))
+        .collect::<HashMap<_, _>>();
+    assert_eq!(revoked, HashMap::from([(bob, true), (carol, false)]));
+}
+
+#[test]
+fn diff_docs_lists_the_commits_each_side_is_missing() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (from, from_initial) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let (into, into_initial) = network
+        .beelay(&peer1)
+        .create_doc_with_contents(vec![9], vec![])
+        .unwrap();
+    let shared = Commit::new(
+        vec![from_initial.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(from, vec![shared.clone()])
+        .unwrap();
+    network.beelay(&peer1).merge_docs(into, from).unwrap();
+
+    // Each copy carries on from the shared history
+    let in_from = Commit::new(vec![shared.hash()], vec![2], CommitHash::from([2; 32]));
+    let in_into = Commit::new(vec![shared.hash()], vec![3], CommitHash::from([3; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(from, vec![in_from.clone()])
+        .unwrap();
+    network
+        .beelay(&peer1)
+        .add_commits(into, vec![in_into.clone()])
+        .unwrap();
+
+    let diff = network.beelay(&peer1).diff_docs(from, into).unwrap();
+    assert_eq!(diff.only_in_a, vec![in_from.hash()]);
+    let mut expected = vec![into_initial.hash(), in_into.hash()];
+    expected.sort();
+    assert_eq!(diff.only_in_b, expected);
+
+    let same = network.beelay(&peer1).diff_docs(from, from).unwrap();
+    assert_eq!(same, beelay_core::DocDiff::default());
+
+    let unknown = DocumentId::random(&mut rand::thread_rng());
+    assert!(matches!(
+        network.beelay(&peer1).diff_docs(from, unknown),
+        Err(beelay_core::error::DiffDocs::NoSuchB)
+    ));
+    assert!(matches!(
+        network.beelay(&peer1).diff_docs(unknown, from),
+        Err(beelay_core::error::DiffDocs::NoSuchA)
+    ));
+}
+
+#[test]
+fn interrupted_bundles_resume_from_their_written_contents() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let bundle = CommitBundle::builder()
+        .start(initial_commit.hash())
+        .end(CommitHash::from([1; 32]))
+        .checkpoints(vec![])
+        .bundled_commits(vec![7; 1024])
+        .build();
+    let is_progress =
+        |key: &beelay_core::StorageKey| key.components().any(|c| c == "pending_bundles");
+    let blobs = |storage: &std::collections::BTreeMap<beelay_core::StorageKey, Vec<u8>>| {
+        storage
+            .keys()
+            .filter(|key| key.namespace() == "blobs")
+            .cloned()
+            .collect::<HashSet<_>>()
+    };
+
+    // Stop once the contents are written, before the bundle is recorded
+    network.beelay(&peer1).hold_writes();
+    let command = network
+        .beelay(&peer1)
+        .submit(Event::add_bundle(doc_id, bundle.clone()));
+    while !network.beelay(&peer1).storage().keys().any(is_progress) {
+        assert!(network.beelay(&peer1).release_next_write());
+    }
+    let crashed = network.beelay(&peer1).storage().clone();
+
+    // Left to finish, the bundle is applied in one go and the progress is
+    // cleared
+    network.beelay(&peer1).release_writes();
+    let Some(CommandResult::AddBundle(Ok(added))) = network.beelay(&peer1).take_result(command)
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(!added.resumed);
+    assert!(!network.beelay(&peer1).storage().keys().any(is_progress));
+
+    // Restarting from the storage at the point of the crash picks the
+    // written contents back up
+    *network.beelay(&peer1).storage_mut() = crashed.clone();
+    network.reload_peer(&peer1);
+    let CommandResult::AddBundle(Ok(added)) = network
+        .beelay(&peer1)
+        .run_command(Event::add_bundle(doc_id, bundle))
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(added.resumed);
+    assert!(!network.beelay(&peer1).storage().keys().any(is_progress));
+    assert_eq!(blobs(network.beelay(&peer1).storage()), blobs(&crashed));
+    let loaded = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    assert!(loaded.iter().any(|item| matches!(
+        item,
+        CommitOrBundle::Bundle(b) if b.bundled_commits() == [7; 1024].as_slice()
+    )));
+}
+
+#[test]
+fn interrupted_bundles_keep_their_cgka_operation() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    // Adding a member means the bundle is encrypted with a new key
+    network.beelay(&peer1).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(bob_contact),
+        MemberAccess::Read,
+    );
+    let bundle = CommitBundle::builder()
+        .start(initial_commit.hash())
+        .end(CommitHash::from([1; 32]))
+        .checkpoints(vec![])
+        .bundled_commits(vec![7; 1024])
+        .build();
+    let is_progress =
+        |key: &beelay_core::StorageKey| key.components().any(|c| c == "pending_bundles");
+    let events = beelay_core::StorageKey::auth().push("events");
+    let new_events =
+        |before: &std::collections::BTreeMap<beelay_core::StorageKey, Vec<u8>>,
+         after: &std::collections::BTreeMap<beelay_core::StorageKey, Vec<u8>>| {
+            after
+                .keys()
+                .filter(|key| events.is_prefix_of(key) && !before.contains_key(key))
+                .count()
+        };
+    let before = network.beelay(&peer1).storage().clone();
+
+    network.beelay(&peer1).hold_writes();
+    network
+        .beelay(&peer1)
+        .submit(Event::add_bundle(doc_id, bundle.clone()));
+    while !network.beelay(&peer1).storage().keys().any(is_progress) {
+        assert!(network.beelay(&peer1).release_next_write());
+    }
+    // Crash before keyhive has stored anything about the new key
+    let auth = beelay_core::StorageKey::auth();
+    let mut crashed = network.beelay(&peer1).storage().clone();
+    crashed.retain(|key, _| !auth.is_prefix_of(key) || before.contains_key(key));
+    network.beelay(&peer1).release_writes();
+    assert_eq!(new_events(&before, network.beelay(&peer1).storage()), 1);
+    *network.beelay(&peer1).storage_mut() = crashed;
+    network.reload_peer(&peer1);
+
+    // Resuming stores the operation the written contents were encrypted with
+    let CommandResult::AddBundle(Ok(added)) = network
+        .beelay(&peer1)
+        .run_command(Event::add_bundle(doc_id, bundle))
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(added.resumed);
+    assert_eq!(new_events(&before, network.beelay(&peer1).storage()), 1);
+}
+
+#[test]
+fn requests_for_unknown_documents_look_like_requests_for_forbidden_ones() {
+    init_logging();
+    let mut network = Network::new();
+    let issuer = network.create_peer("issuer").build();
+    let relay = network.create_peer("relay").build();
+    let holder = network.create_peer("holder").build();
+    network.beelay(&relay).receive_requests_for(Some("sync"));
+    let sync = beelay_core::Audience::service_name("sync");
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+
+    // A document the relay has never heard of
+    let (unknown_doc, _) = network.beelay(&issuer).create_doc(vec![]).unwrap();
+    let CommandResult::MintCapability(Ok(unknown)) = network.beelay(&issuer).run_command(
+        Event::mint_capability(unknown_doc, MemberAccess::Read, expires_at),
+    ) else {
+        panic!("expected a capability");
+    };
+    let endpoint = network
+        .beelay(&holder)
+        .register_capability_endpoint_at(sync, &relay, unknown);
+    let unknown_result = network
+        .beelay(&holder)
+        .run_command(Event::fetch_with_capability(endpoint));
+    assert_eq!(
+        network.beelay(&relay).take_request_failures(),
+        vec![beelay_core::error::RequestFailure::DocumentNotFound(
+            unknown_doc
+        )]
+    );
+
+    // A document the relay has but which the holder may no longer fetch
+    let (forbidden_doc, _) = network.beelay(&relay).create_doc(vec![]).unwrap();
+    let CommandResult::MintCapability(Ok(forbidden)) = network.beelay(&relay).run_command(
+        Event::mint_capability(forbidden_doc, MemberAccess::Read, expires_at),
+    ) else {
+        panic!("expected a capability");
+    };
+    let result = network
+        .beelay(&relay)
+        .run_command(Event::revoke_capability(forbidden.id()));
+    assert!(matches!(result, CommandResult::RevokeCapability(true)));
+    let endpoint =
+        network
+            .beelay(&holder)
+            .register_capability_endpoint_at(sync, &relay, forbidden.clone());
+    let forbidden_result = network
+        .beelay(&holder)
+        .run_command(Event::fetch_with_capability(endpoint));
+    assert_eq!(
+        network.beelay(&relay).take_request_failures(),
+        vec![beelay_core::error::RequestFailure::InvalidCapability {
+            id: forbidden.id(),
+            reason: beelay_core::error::CapabilityRejected::Revoked,
+        }]
+    );
+
+    // The holder can't tell the two apart
+    for result in [unknown_result, forbidden_result] {
+        assert!(matches!(
+            result,
+            CommandResult::FetchWithCapability(Err(
+                beelay_core::error::FetchWithCapability::Rejected
+            ))
+        ));
+    }
+}
+
+#[test]
+fn handled_requests_report_the_audience_they_were_received_for() {
+    init_logging();
+    let mut network = Network::new();
+    let issuer = network.create_peer("issuer").build();
+    let holder = network.create_peer("holder").build();
+    let (doc_id, _) = network.beelay(&issuer).create_doc(vec![]).unwrap();
+    let expires_at = UnixTimestamp::now() + Duration::from_secs(60);
+    let CommandResult::MintCapability(Ok(capability)) = network.beelay(&issuer).run_command(
+        Event::mint_capability(doc_id, MemberAccess::Read, expires_at),
+    ) else {
+        panic!("expected a capability");
+    };
+    let [a, b, other] = ["a", "b", "other"].map(beelay_core::Audience::service_name);
+    let CommandResult::RegisterEndpointMulti(Ok(served)) = network
+        .beelay(&issuer)
+        .run_command(Event::register_endpoint_multi(vec![a, b]))
+    else {
+        panic!("expected RegisterEndpointMulti result");
+    };
+
+    let fetch = |network: &mut Network, audience| {
+        let endpoint = network.beelay(&holder).register_capability_endpoint_at(
+            audience,
+            &issuer,
+            capability.clone(),
+        );
+        let result = network
+            .beelay(&holder)
+            .run_command(Event::fetch_with_capability(endpoint));
+        let handled = network.beelay(&issuer).take_handled_requests();
+        assert!(!handled.is_empty());
+        assert!(handled.windows(2).all(|pair| pair[0] == pair[1]));
+        (result, handled[0].clone())
+    };
+
+    // Without a receive audience requests must be addressed to our peer ID
+    let (result, handled) = fetch(&mut network, beelay_core::Audience::peer(&issuer));
+    assert!(matches!(result, CommandResult::FetchWithCapability(Ok(()))));
+    assert_eq!(handled, (beelay_core::RequestTarget::Peer, None));
+
+    // A request for any audience of an endpoint matches that endpoint
+    network.beelay(&issuer).receive_requests_for(Some("a"));
+    let (result, handled) = fetch(&mut network, b);
+    assert!(matches!(result, CommandResult::FetchWithCapability(Ok(()))));
+    assert_eq!(
+        handled,
+        (
+            beelay_core::RequestTarget::Endpoint {
+                endpoint_id: served,
+                audience: b,
+            },
+            None
+        )
+    );
+
+    // Requests for an audience no endpoint serves are still handled
+    network.beelay(&issuer).receive_requests_for(Some("other"));
+    let (result, handled) = fetch(&mut network, other);
+    assert!(matches!(result, CommandResult::FetchWithCapability(Ok(()))));
+    assert_eq!(
+        handled,
+        (beelay_core::RequestTarget::Unmatched(other), None)
+    );
+
+    // Unless they were addressed to some other audience
+    let (result, handled) = fetch(&mut network, b);
+    assert!(!matches!(
+        result,
+        CommandResult::FetchWithCapability(Ok(()))
+    ));
+    assert_eq!(
+        handled,
+        (
+            beelay_core::RequestTarget::Unmatched(other),
+            Some(beelay_core::error::RequestFailure::AudienceMismatch {
+                expected: other,
+                actual: b,
+            })
+        )
+    );
+}
@@ -0,0 +1,51 @@
+//! Replaying events with a seeded RNG, which needs the
+//! `insecure_deterministic` feature
+//!
+//! Run with `cargo test --features insecure_deterministic --test deterministic`.
+
+use beelay_core::{CommandResult, Commit, CommitHash, Event, UnixTimestampMillis};
+use network::Network;
+use test_utils::init_logging;
+
+mod network;
+
+#[test]
+fn seeded_peers_replay_deterministically() {
+    init_logging();
+    // Each run gets a fresh thread, as a test would
+    let run = || {
+        std::thread::spawn(|| {
+            beelay_core::reset_ids();
+            let mut network = Network::new();
+            let peer1 = network
+                .create_peer("peer1")
+                .signing_key(ed25519_dalek::SigningKey::from_bytes(&[1; 32]))
+                .rng_seed(7)
+                .start_time(UnixTimestampMillis::new(1_700_000_000_000))
+                .build();
+            let (doc_id, initial_commit) = network
+                .beelay(&peer1)
+                .create_doc_with_contents(vec![1, 2, 3], vec![])
+                .unwrap();
+            let commit = Commit::new(
+                vec![initial_commit.hash()],
+                vec![4, 5, 6],
+                CommitHash::from([1; 32]),
+            );
+            network
+                .beelay(&peer1)
+                .add_commits(doc_id, vec![commit])
+                .unwrap();
+            let (command, event) = Event::doc_stats(doc_id);
+            let CommandResult::DocStats(stats) =
+                network.beelay(&peer1).run_command((command, event))
+            else {
+                panic!("unexpected command result");
+            };
+            (doc_id, initial_commit.hash(), command, stats)
+        })
+        .join()
+        .unwrap()
+    };
+    assert_eq!(run(), run());
+}
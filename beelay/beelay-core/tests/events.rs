@@ -110,13 +110,13 @@ fn connect_emits_peer_changes() {
     let peer2_changes = peer_changes.remove(&peer2).unwrap();
     assert!(!peer2_changes.is_empty());
 
-    let ConnectionInfo { peer_id, state } = peer2_changes[0].clone();
+    let ConnectionInfo { peer_id, state, .. } = peer2_changes[0].clone();
     assert_eq!(peer_id, peer2);
     let ConnState::Syncing { started_at } = state else {
         panic!("expected first state to be Syncing");
     };
 
-    let ConnectionInfo { peer_id, state } = peer2_changes[1].clone();
+    let ConnectionInfo { peer_id, state, .. } = peer2_changes[1].clone();
     assert_eq!(peer_id, peer2);
     let ConnState::Listening { last_synced_at } = state else {
         panic!("expected second state to be Listening");
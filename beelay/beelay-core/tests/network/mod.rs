@@ -18,6 +18,7 @@ use beelay_core::{
     UnixTimestampMillis,
 };
 use ed25519_dalek::SigningKey;
+use rand::{rngs::StdRng, SeedableRng};
 use signature::SignerMut;
 
 pub struct BeelayHandle<'a> {
@@ -61,6 +62,23 @@ impl BeelayHandle<'_> {
         }
     }
 
+    pub fn create_doc_idempotent(
+        &mut self,
+        content: Vec<u8>,
+        key: &str,
+    ) -> Result<DocumentId, beelay_core::error::Create> {
+        let hash = CommitHash::from(blake3::hash(&content).as_bytes());
+        let initial_commit = beelay_core::Commit::new(vec![], content, hash);
+        match self.run_command(beelay_core::Event::create_doc_idempotent(
+            initial_commit,
+            vec![],
+            key.to_string(),
+        )) {
+            beelay_core::CommandResult::CreateDoc(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
     pub fn doc_status(&mut self, doc: &DocumentId) -> beelay_core::doc_status::DocStatus {
         let command = {
             let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
@@ -83,6 +101,15 @@ impl BeelayHandle<'_> {
         doc_id: DocumentId,
         commits: Vec<beelay_core::Commit>,
     ) -> Result<Vec<BundleSpec>, beelay_core::error::AddCommits> {
+        self.add_commits_verbose(doc_id, commits)
+            .map(|added| added.new_bundles)
+    }
+
+    pub fn add_commits_verbose(
+        &mut self,
+        doc_id: DocumentId,
+        commits: Vec<beelay_core::Commit>,
+    ) -> Result<beelay_core::CommitsAdded, beelay_core::error::AddCommits> {
         let command = {
             let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
             let (command, event) = beelay_core::Event::add_commits(doc_id, commits);
@@ -92,14 +119,164 @@ impl BeelayHandle<'_> {
         self.network.run_until_quiescent();
         let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
         match beelay.completed_commands.remove(&command) {
-            Some(Ok(beelay_core::CommandResult::AddCommits(new_bundles_needed))) => {
-                new_bundles_needed
-            }
+            Some(Ok(beelay_core::CommandResult::AddCommits(added))) => added,
             Some(other) => panic!("unexpected command result: {:?}", other),
             None => panic!("no command result"),
         }
     }
 
+    pub fn add_commits_checked(
+        &mut self,
+        doc_id: DocumentId,
+        commits: Vec<beelay_core::Commit>,
+        strict: bool,
+    ) -> Result<beelay_core::AddedCommits, beelay_core::error::AddCommits> {
+        match self.run_command(beelay_core::Event::add_commits_checked(
+            doc_id, commits, strict,
+        )) {
+            beelay_core::CommandResult::AddCommitsChecked(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn pending_orphans(&mut self, doc_id: DocumentId) -> Vec<beelay_core::PendingOrphan> {
+        match self.run_command(beelay_core::Event::pending_orphans(doc_id)) {
+            beelay_core::CommandResult::PendingOrphans(orphans) => orphans,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn find_unreachable(&mut self, doc_id: DocumentId) -> Vec<CommitHash> {
+        match self.run_command(beelay_core::Event::find_unreachable(doc_id)) {
+            beelay_core::CommandResult::FindUnreachable(hashes) => hashes,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn diff_docs(
+        &mut self,
+        a: DocumentId,
+        b: DocumentId,
+    ) -> Result<beelay_core::DocDiff, beelay_core::error::DiffDocs> {
+        match self.run_command(beelay_core::Event::diff_docs(a, b)) {
+            beelay_core::CommandResult::DiffDocs(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn request_commits(
+        &mut self,
+        doc_id: DocumentId,
+        hashes: Vec<CommitHash>,
+    ) -> Result<beelay_core::RequestedCommits, beelay_core::error::RequestCommits> {
+        match self.run_command(beelay_core::Event::request_commits(doc_id, hashes)) {
+            beelay_core::CommandResult::RequestCommits(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn request_doc_access(
+        &mut self,
+        doc_id: DocumentId,
+        desired: MemberAccess,
+    ) -> Result<Vec<PeerId>, beelay_core::error::RequestDocAccess> {
+        match self.run_command(beelay_core::Event::request_doc_access(doc_id, desired)) {
+            beelay_core::CommandResult::RequestDocAccess(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn deny_doc_access(
+        &mut self,
+        doc_id: DocumentId,
+        requester: PeerId,
+    ) -> Result<(), beelay_core::error::DenyDocAccess> {
+        match self.run_command(beelay_core::Event::deny_doc_access(doc_id, requester)) {
+            beelay_core::CommandResult::DenyDocAccess(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn add_commits_multi(
+        &mut self,
+        batches: Vec<(DocumentId, Vec<beelay_core::Commit>)>,
+    ) -> HashMap<DocumentId, Result<Vec<BundleSpec>, beelay_core::error::AddCommits>> {
+        match self.run_command(beelay_core::Event::add_commits_multi(batches)) {
+            beelay_core::CommandResult::AddCommitsMulti(results) => results,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn merge_docs(
+        &mut self,
+        into: DocumentId,
+        from: DocumentId,
+    ) -> Result<Vec<beelay_core::CommitHash>, beelay_core::error::MergeDocs> {
+        match self.run_command(beelay_core::Event::merge_docs(into, from)) {
+            beelay_core::CommandResult::MergeDocs(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn delete_doc(&mut self, doc_id: DocumentId) -> usize {
+        match self.run_command(beelay_core::Event::delete_doc(doc_id)) {
+            beelay_core::CommandResult::DeleteDoc(removed) => removed,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn tombstone_doc(
+        &mut self,
+        doc_id: DocumentId,
+    ) -> Result<usize, beelay_core::error::TombstoneDoc> {
+        match self.run_command(Event::tombstone_doc(doc_id)) {
+            CommandResult::TombstoneDoc(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn seal_doc(
+        &mut self,
+        doc_id: DocumentId,
+    ) -> Result<beelay_core::Seal, beelay_core::error::SealDoc> {
+        match self.run_command(Event::seal_doc(doc_id)) {
+            CommandResult::SealDoc(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn list_documents(&mut self, access: Option<MemberAccess>) -> Vec<DocumentId> {
+        let event = match access {
+            Some(access) => beelay_core::Event::list_documents_with_access(access),
+            None => beelay_core::Event::list_documents(),
+        };
+        match self.run_command(event) {
+            beelay_core::CommandResult::ListDocuments(docs) => docs,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn add_commit_chunk(
+        &mut self,
+        doc_id: DocumentId,
+        commit: &beelay_core::Commit,
+        chunk_index: u32,
+        bytes: &[u8],
+        is_last: bool,
+    ) -> Result<Option<Vec<BundleSpec>>, beelay_core::error::AddCommitChunk> {
+        match self.run_command(Event::add_commit_chunk(
+            doc_id,
+            commit.hash(),
+            commit.parents().to_vec(),
+            chunk_index,
+            bytes.to_vec(),
+            is_last,
+        )) {
+            CommandResult::AddCommitChunk(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
     pub fn load_doc(&mut self, doc_id: DocumentId) -> Option<Vec<CommitOrBundle>> {
         let command = {
             let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
@@ -110,12 +287,136 @@ impl BeelayHandle<'_> {
         self.network.run_until_quiescent();
         let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
         match beelay.completed_commands.remove(&command) {
-            Some(Ok(beelay_core::CommandResult::LoadDoc(commits))) => commits,
+            Some(Ok(beelay_core::CommandResult::LoadDoc(commits))) => {
+                commits.map(|c| c.into_iter().map(|(item, _)| item).collect())
+            }
             Some(other) => panic!("unexpected command result: {:?}", other),
             None => panic!("no command result"),
         }
     }
 
+    pub fn load_doc_with_meta(
+        &mut self,
+        doc_id: DocumentId,
+    ) -> Option<Vec<(CommitOrBundle, Option<beelay_core::CommitMeta>)>> {
+        match self.run_command(beelay_core::Event::load_doc(doc_id)) {
+            beelay_core::CommandResult::LoadDoc(commits) => commits,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    /// Submit a command and run the network until it completes
+    pub fn load_doc_at(
+        &mut self,
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    ) -> Result<Vec<(CommitOrBundle, Option<beelay_core::CommitMeta>)>, beelay_core::error::LoadDocAt>
+    {
+        match self.run_command(beelay_core::Event::load_doc_at(doc_id, heads)) {
+            beelay_core::CommandResult::LoadDocAt(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn load_commits_from(
+        &mut self,
+        doc_id: DocumentId,
+        heads: Vec<CommitHash>,
+    ) -> Result<Vec<CommitOrBundle>, beelay_core::error::LoadCommitsFrom> {
+        match self.run_command(beelay_core::Event::load_commits_from(doc_id, heads)) {
+            beelay_core::CommandResult::LoadCommitsFrom(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn run_command(
+        &mut self,
+        (command, event): (beelay_core::CommandId, Event),
+    ) -> beelay_core::CommandResult {
+        {
+            let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+            beelay.inbox.push_back(event);
+        }
+        self.network.run_until_quiescent();
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        match beelay.completed_commands.remove(&command) {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => panic!("unexpected command result: {:?}", e),
+            None => panic!("no command result"),
+        }
+    }
+
+    /// Run a command and return the correlation ID it completed with, if any
+    pub fn run_command_correlated(
+        &mut self,
+        (command, event): (beelay_core::CommandId, Event),
+    ) -> (beelay_core::CommandResult, Option<String>) {
+        let result = self.run_command((command, event));
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        (result, beelay.correlation_ids.remove(&command))
+    }
+
+    /// Submit a command and cancel it before any of the IO it emits has completed
+    pub fn run_cancelled(
+        &mut self,
+        (command, event): (beelay_core::CommandId, Event),
+    ) -> beelay_core::CommandResult {
+        {
+            let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+            beelay.inbox.push_back(event);
+            beelay.inbox.push_back(Event::cancel_command(command));
+        }
+        self.network.run_until_quiescent();
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        match beelay.completed_commands.remove(&command) {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => panic!("unexpected command result: {:?}", e),
+            None => panic!("no command result"),
+        }
+    }
+
+    pub fn take_command_progress(
+        &mut self,
+        command: beelay_core::CommandId,
+    ) -> Vec<beelay_core::CommandProgress> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.command_progress.remove(&command).unwrap_or_default()
+    }
+
+    /// Start each of `events` and then cancel all commands before any of them
+    /// can complete, returning the result of each command
+    pub fn run_all_cancelled(
+        &mut self,
+        events: Vec<(beelay_core::CommandId, Event)>,
+    ) -> Vec<beelay_core::CommandResult> {
+        let commands = {
+            let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+            let mut commands = Vec::new();
+            for (command, event) in events {
+                beelay.inbox.push_back(event);
+                commands.push(command);
+            }
+            beelay.inbox.push_back(Event::cancel_all_commands());
+            commands
+        };
+        self.network.run_until_quiescent();
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        commands
+            .into_iter()
+            .map(|command| match beelay.completed_commands.remove(&command) {
+                Some(Ok(result)) => result,
+                Some(Err(e)) => panic!("unexpected command result: {:?}", e),
+                None => panic!("no command result"),
+            })
+            .collect()
+    }
+
+    pub fn cancel_command(&mut self, command: beelay_core::CommandId) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.inbox.push_back(Event::cancel_command(command));
+        self.network.run_until_quiescent();
+    }
+
     pub fn load_doc_encrypted(&mut self, doc_id: DocumentId) -> Option<Vec<CommitOrBundle>> {
         let command = {
             let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
@@ -126,7 +427,9 @@ impl BeelayHandle<'_> {
         self.network.run_until_quiescent();
         let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
         match beelay.completed_commands.remove(&command) {
-            Some(Ok(beelay_core::CommandResult::LoadDoc(commits))) => commits,
+            Some(Ok(beelay_core::CommandResult::LoadDoc(commits))) => {
+                commits.map(|c| c.into_iter().map(|(item, _)| item).collect())
+            }
             Some(other) => panic!("unexpected command result: {:?}", other),
             None => panic!("no command result"),
         }
@@ -164,6 +467,131 @@ impl BeelayHandle<'_> {
         endpoint_id
     }
 
+    // Register an endpoint for `issuer` whose requests are authorized by `capability`
+    pub fn register_capability_endpoint(
+        &mut self,
+        issuer: &PeerId,
+        capability: beelay_core::Capability,
+    ) -> beelay_core::EndpointId {
+        self.register_capability_endpoint_at(
+            beelay_core::Audience::peer(issuer),
+            issuer,
+            capability,
+        )
+    }
+
+    // Register an endpoint for `audience` whose requests are authorized by
+    // `capability` and delivered to `target`
+    pub fn register_capability_endpoint_at(
+        &mut self,
+        audience: beelay_core::Audience,
+        target: &PeerId,
+        capability: beelay_core::Capability,
+    ) -> beelay_core::EndpointId {
+        let endpoint_id =
+            match self.run_command(Event::register_capability_endpoint(audience, capability)) {
+                beelay_core::CommandResult::RegisterEndpoint(endpoint_id) => endpoint_id,
+                other => panic!("unexpected command result: {:?}", other),
+            };
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.endpoints.insert(endpoint_id, *target);
+        endpoint_id
+    }
+
+    // Register an endpoint for `other` whose requests are made on behalf of
+    // `group`
+    pub fn register_delegated_endpoint(
+        &mut self,
+        other: &PeerId,
+        group: PeerId,
+    ) -> beelay_core::EndpointId {
+        let endpoint_id = match self.run_command(Event::register_delegated_endpoint(
+            beelay_core::Audience::peer(other),
+            KeyhiveEntityId::Group(group),
+        )) {
+            beelay_core::CommandResult::RegisterDelegatedEndpoint(Ok(endpoint_id)) => endpoint_id,
+            other => panic!("unexpected command result: {:?}", other),
+        };
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.endpoints.insert(endpoint_id, *other);
+        endpoint_id
+    }
+
+    pub fn sync_with_endpoint(
+        &mut self,
+        endpoint_id: beelay_core::EndpointId,
+        doc_id: DocumentId,
+    ) -> Result<(), beelay_core::error::SyncWithEndpoint> {
+        match self.run_command(Event::sync_with_endpoint(endpoint_id, doc_id)) {
+            beelay_core::CommandResult::SyncWithEndpoint(r) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn signing_key(&self) -> SigningKey {
+        self.network.beelays[&self.peer_id].signing_key.clone()
+    }
+
+    // Handle incoming requests as though they were received for the service
+    // `audience`, rather than for our peer ID
+    pub fn receive_requests_for(&mut self, audience: Option<&str>) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.receive_audience = audience.map(str::to_string);
+    }
+
+    // Why each of the incoming requests which failed since the last call did so
+    pub fn take_request_failures(&mut self) -> Vec<beelay_core::error::RequestFailure> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        let failed = beelay
+            .completed_commands
+            .iter()
+            .filter_map(|(command, result)| match result {
+                Ok(beelay_core::CommandResult::HandleRequest(Err(e))) => {
+                    Some((*command, e.reason.clone()))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        failed
+            .into_iter()
+            .map(|(command, reason)| {
+                beelay.completed_commands.remove(&command);
+                reason
+            })
+            .collect()
+    }
+
+    // Which of our audiences each of the incoming requests handled since the
+    // last call was received for, along with why it failed if it did
+    pub fn take_handled_requests(
+        &mut self,
+    ) -> Vec<(
+        beelay_core::RequestTarget,
+        Option<beelay_core::error::RequestFailure>,
+    )> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        let handled = beelay
+            .completed_commands
+            .iter()
+            .filter_map(|(command, result)| match result {
+                Ok(beelay_core::CommandResult::HandleRequest(Ok(handled))) => {
+                    Some((*command, (handled.target, None)))
+                }
+                Ok(beelay_core::CommandResult::HandleRequest(Err(e))) => {
+                    Some((*command, (e.target, Some(e.reason.clone()))))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        handled
+            .into_iter()
+            .map(|(command, handled)| {
+                beelay.completed_commands.remove(&command);
+                handled
+            })
+            .collect()
+    }
+
     pub fn dirty_shutdown(&mut self) {
         self.network
             .beelays
@@ -213,6 +641,21 @@ impl BeelayHandle<'_> {
         }
     }
 
+    pub fn add_member_to_doc_until(
+        &mut self,
+        doc: DocumentId,
+        member: KeyhiveEntityId,
+        access: MemberAccess,
+        expires_at: beelay_core::UnixTimestamp,
+    ) {
+        match self.run_command(beelay_core::Event::add_member_to_doc_until(
+            doc, member, access, expires_at,
+        )) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::AddMemberToDoc) => (),
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
     pub fn remove_member_from_doc(
         &mut self,
         doc: DocumentId,
@@ -232,6 +675,192 @@ impl BeelayHandle<'_> {
         }
     }
 
+    pub fn change_member_access(
+        &mut self,
+        doc: DocumentId,
+        member: KeyhiveEntityId,
+        access: MemberAccess,
+    ) -> Result<MemberAccess, beelay_core::error::ChangeMemberAccess> {
+        match self.run_command(beelay_core::Event::change_member_access(
+            doc, member, access,
+        )) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ChangeMemberAccess(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn rotate_doc_key(
+        &mut self,
+        doc: DocumentId,
+    ) -> Result<u64, beelay_core::error::RotateDocKey> {
+        match self.run_command(beelay_core::Event::rotate_doc_key(doc)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::RotateDocKey(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn export_access_log(
+        &mut self,
+        doc: DocumentId,
+    ) -> Result<Vec<beelay_core::keyhive::AccessLogEntry>, beelay_core::error::ExportAccessLog>
+    {
+        match self.run_command(beelay_core::Event::export_access_log(doc)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ExportAccessLog(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn doc_key_epochs(
+        &mut self,
+        doc: DocumentId,
+    ) -> Result<beelay_core::keyhive::DocKeyEpochs, beelay_core::error::DocKeyEpochs> {
+        match self.run_command(beelay_core::Event::doc_key_epochs(doc)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::DocKeyEpochs(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn list_scheduled_rotations(
+        &mut self,
+        doc: DocumentId,
+    ) -> Result<
+        Vec<beelay_core::keyhive::ScheduledRotation>,
+        beelay_core::error::ListScheduledRotations,
+    > {
+        match self.run_command(beelay_core::Event::list_scheduled_rotations(doc)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ListScheduledRotations(
+                r,
+            )) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn cancel_scheduled_rotation(
+        &mut self,
+        doc: DocumentId,
+        rotation_id: beelay_core::keyhive::RotationId,
+    ) -> Result<(), beelay_core::error::CancelScheduledRotation> {
+        match self.run_command(beelay_core::Event::cancel_scheduled_rotation(
+            doc,
+            rotation_id,
+        )) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::CancelScheduledRotation(
+                r,
+            )) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn list_doc_members(
+        &mut self,
+        doc: DocumentId,
+    ) -> Result<Vec<beelay_core::keyhive::DocMember>, beelay_core::error::QueryAccess> {
+        match self.run_command(beelay_core::Event::list_doc_members(doc)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ListDocMembers(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn query_access_for(
+        &mut self,
+        doc: DocumentId,
+        entity: KeyhiveEntityId,
+    ) -> Result<Option<MemberAccess>, beelay_core::error::QueryAccessFor> {
+        match self.run_command(beelay_core::Event::query_access_for(doc, entity)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::QueryAccessFor(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn query_access_multi(
+        &mut self,
+        docs: Vec<DocumentId>,
+    ) -> HashMap<DocumentId, Option<MemberAccess>> {
+        match self.run_command(beelay_core::Event::query_access_multi(docs)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::QueryAccessMulti(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn export_identity(
+        &mut self,
+        passphrase: &str,
+    ) -> Result<Vec<u8>, beelay_core::error::ExportIdentity> {
+        match self.run_command(beelay_core::Event::export_identity(passphrase.to_string())) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ExportIdentity(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn import_identity(
+        &mut self,
+        exported: Vec<u8>,
+        passphrase: &str,
+    ) -> Result<(), beelay_core::error::ImportIdentity> {
+        match self.run_command(beelay_core::Event::import_identity(
+            exported,
+            passphrase.to_string(),
+        )) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ImportIdentity(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn expand_group(
+        &mut self,
+        group_id: beelay_core::PeerId,
+    ) -> Result<Vec<beelay_core::keyhive::GroupMember>, beelay_core::error::ExpandGroup> {
+        match self.run_command(beelay_core::Event::expand_group(group_id)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ExpandGroup(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn list_contacts(&mut self) -> Vec<beelay_core::keyhive::Contact> {
+        match self.run_command(beelay_core::Event::list_contacts()) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ListContacts(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn list_my_groups(&mut self) -> Vec<beelay_core::keyhive::GroupMembership> {
+        match self.run_command(beelay_core::Event::list_my_groups()) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::ListMyGroups(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn set_group_metadata(
+        &mut self,
+        group_id: beelay_core::PeerId,
+        metadata: beelay_core::keyhive::GroupMetadata,
+    ) -> Result<(), beelay_core::error::SetGroupMetadata> {
+        match self.run_command(beelay_core::Event::set_group_metadata(group_id, metadata)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::SetGroupMetadata(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn get_group_metadata(
+        &mut self,
+        group_id: beelay_core::PeerId,
+    ) -> Result<beelay_core::keyhive::GroupMetadata, beelay_core::error::ExpandGroup> {
+        match self.run_command(beelay_core::Event::get_group_metadata(group_id)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::GetGroupMetadata(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn preview_access_change(
+        &mut self,
+        change: beelay_core::keyhive::AccessChange,
+    ) -> Result<beelay_core::keyhive::AccessDiff, beelay_core::error::PreviewAccessChange> {
+        match self.run_command(beelay_core::Event::preview_access_change(change)) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::PreviewAccessChange(r)) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
     pub fn create_group(
         &mut self,
         other_parents: Vec<KeyhiveEntityId>,
@@ -250,6 +879,23 @@ impl BeelayHandle<'_> {
         }
     }
 
+    pub fn create_group_with_members(
+        &mut self,
+        other_owners: Vec<KeyhiveEntityId>,
+        members: Vec<(KeyhiveEntityId, beelay_core::keyhive::MemberAccess)>,
+    ) -> Result<beelay_core::keyhive::CreatedGroup, beelay_core::error::CreateGroupWithMembers>
+    {
+        match self.run_command(beelay_core::Event::create_group_with_members(
+            other_owners,
+            members,
+        )) {
+            beelay_core::CommandResult::Keyhive(KeyhiveCommandResult::CreateGroupWithMembers(
+                r,
+            )) => r,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
     pub fn add_member_to_group(
         &mut self,
         add: beelay_core::keyhive::AddMemberToGroup,
@@ -311,6 +957,10 @@ impl BeelayHandle<'_> {
         &self.network.beelays.get(&self.peer_id).unwrap().storage
     }
 
+    pub fn storage_mut(&mut self) -> &mut BTreeMap<beelay_core::StorageKey, Vec<u8>> {
+        &mut self.network.beelays.get_mut(&self.peer_id).unwrap().storage
+    }
+
     #[cfg(feature = "debug_events")]
     pub fn log_keyhive_events(
         &mut self,
@@ -352,6 +1002,164 @@ impl BeelayHandle<'_> {
         beelay.core.num_sessions()
     }
 
+    pub fn resumption_token(
+        &mut self,
+        stream: StreamId,
+    ) -> Option<beelay_core::StreamResumptionToken> {
+        match self.run_command(Event::stream_resumption_token(stream)) {
+            CommandResult::StreamResumptionToken(token) => token.unwrap(),
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    /// Stop acknowledging storage writes until `release_writes` is called.
+    /// The writes are still applied to storage immediately.
+    pub fn hold_writes(&mut self) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.held_writes.get_or_insert_with(Vec::new);
+    }
+
+    pub fn release_writes(&mut self) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        if let Some(held) = beelay.held_writes.take() {
+            beelay.inbox.extend(held);
+        }
+        self.network.run_until_quiescent();
+    }
+
+    // Acknowledge the oldest held write, returns false if none were held.
+    // Writes issued in response are held as well.
+    pub fn release_next_write(&mut self) -> bool {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        let Some(event) = beelay.held_writes.as_mut().and_then(|held| {
+            if held.is_empty() {
+                None
+            } else {
+                Some(held.remove(0))
+            }
+        }) else {
+            return false;
+        };
+        beelay.inbox.push_back(event);
+        self.network.run_until_quiescent();
+        true
+    }
+
+    pub fn hold_stream_acks(&mut self) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.held_stream_acks.get_or_insert_with(HashMap::new);
+    }
+
+    pub fn release_stream_acks(&mut self) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        if let Some(held) = beelay.held_stream_acks.take() {
+            beelay.inbox.extend(
+                held.into_iter()
+                    .map(|(id, num_sent)| Event::stream_messages_sent(id, num_sent)),
+            );
+        }
+        self.network.run_until_quiescent();
+    }
+
+    pub fn stream_queue_depth(
+        &self,
+        stream_id: beelay_core::StreamId,
+    ) -> Option<beelay_core::StreamQueueDepth> {
+        self.network.beelays[&self.peer_id]
+            .core
+            .stream_queue_depth(stream_id)
+    }
+
+    pub fn pop_congested_streams(
+        &mut self,
+    ) -> Vec<(beelay_core::StreamId, beelay_core::StreamQueueDepth)> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        std::mem::take(&mut beelay.congested_streams)
+    }
+
+    pub fn pop_rate_limited_streams(&mut self) -> Vec<beelay_core::StreamId> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        std::mem::take(&mut beelay.rate_limited_streams)
+    }
+
+    pub fn append_commit(&mut self, doc: DocumentId, commit: beelay_core::Commit) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay
+            .inbox
+            .push_back(beelay_core::Event::append_commit(doc, commit));
+        self.network.run_until_quiescent();
+    }
+
+    pub fn pending_appends(&mut self, doc: DocumentId) -> usize {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.core.pending_appends(doc)
+    }
+
+    pub fn pop_append_failures(&mut self) -> Vec<beelay_core::AppendFailure> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        std::mem::take(&mut beelay.append_failures)
+    }
+
+    pub fn set_stream_rate_limit(
+        &mut self,
+        stream_id: StreamId,
+        limit: Option<beelay_core::StreamRateLimit>,
+    ) {
+        match self.run_command(Event::set_stream_rate_limit(stream_id, limit)) {
+            CommandResult::SetStreamRateLimit => {}
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    /// Deliver `msg` to `stream_id` as if it had been sent by the peer on the
+    /// other end
+    pub fn inject_stream_message(&mut self, stream_id: StreamId, msg: Vec<u8>) {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay
+            .inbox
+            .push_back(Event::handle_message(stream_id, msg));
+        self.network.run_until_quiescent();
+    }
+
+    /// Start a command without waiting for it to complete
+    pub fn submit(
+        &mut self,
+        (command, event): (beelay_core::CommandId, Event),
+    ) -> beelay_core::CommandId {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.inbox.push_back(event);
+        self.network.run_until_quiescent();
+        command
+    }
+
+    /// Queue a command without running the network, so that commands
+    /// queued on several peers are handled in the same round
+    pub fn queue(
+        &mut self,
+        (command, event): (beelay_core::CommandId, Event),
+    ) -> beelay_core::CommandId {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay.inbox.push_back(event);
+        command
+    }
+
+    pub fn take_result(&mut self, command: beelay_core::CommandId) -> Option<CommandResult> {
+        let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
+        beelay
+            .completed_commands
+            .remove(&command)
+            .map(|result| result.expect("command should not be stopped"))
+    }
+
+    // The total number of messages this peer has sent over streams
+    pub fn stream_messages_sent(&self) -> usize {
+        self.network
+            .beelays
+            .get(&self.peer_id)
+            .unwrap()
+            .stream_messages_sent
+    }
+
     pub fn advance_time(&mut self, duration: Duration) {
         let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
         beelay.now += duration;
@@ -359,11 +1167,79 @@ impl BeelayHandle<'_> {
         self.network.run_until_quiescent();
     }
 
+    // Like `advance_time` but returns what the tick did
+    pub fn advance_time_verbose(&mut self, duration: Duration) -> beelay_core::TickSummary {
+        self.network.beelays.get_mut(&self.peer_id).unwrap().now += duration;
+        let summary = match self.run_command(Event::tick_verbose()) {
+            CommandResult::Tick(summary) => summary,
+            other => panic!("unexpected command result: {:?}", other),
+        };
+        self.network.run_until_quiescent();
+        summary
+    }
+
     pub fn conn_info(&mut self) -> HashMap<StreamId, conn_info::ConnectionInfo> {
         let beelay = self.network.beelays.get(&self.peer_id).unwrap();
         beelay.core.connection_info()
     }
 
+    pub fn next_tick_deadline(&mut self) -> Option<UnixTimestampMillis> {
+        match self.run_command(Event::next_tick_deadline()) {
+            CommandResult::NextTickDeadline(deadline) => deadline,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn list_streams(
+        &mut self,
+    ) -> Vec<(
+        StreamId,
+        beelay_core::StreamDirection,
+        beelay_core::StreamState,
+    )> {
+        match self.run_command(Event::list_streams()) {
+            CommandResult::ListStreams(streams) => streams,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn stream_metrics(
+        &mut self,
+        stream_id: StreamId,
+    ) -> Result<beelay_core::StreamMetrics, beelay_core::StreamError> {
+        match self.run_command(Event::stream_metrics(stream_id)) {
+            CommandResult::StreamMetrics(metrics) => metrics,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn stream_handshake_error(
+        &mut self,
+        stream_id: StreamId,
+    ) -> Result<Option<beelay_core::HandshakeError>, beelay_core::StreamError> {
+        match self.run_command(Event::stream_handshake_error(stream_id)) {
+            CommandResult::StreamHandshakeError(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn resync_stream(
+        &mut self,
+        stream_id: StreamId,
+    ) -> Result<(), beelay_core::error::ResyncStream> {
+        match self.run_command(Event::resync_stream(stream_id)) {
+            CommandResult::ResyncStream(result) => result,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
+    pub fn all_stream_metrics(&mut self) -> HashMap<StreamId, beelay_core::StreamMetrics> {
+        match self.run_command(Event::all_stream_metrics()) {
+            CommandResult::AllStreamMetrics(metrics) => metrics,
+            other => panic!("unexpected command result: {:?}", other),
+        }
+    }
+
     pub fn disconnect(&mut self, stream: StreamId) {
         let beelay = self.network.beelays.get_mut(&self.peer_id).unwrap();
         let (command_id, event) = Event::disconnect_stream(stream);
@@ -378,27 +1254,31 @@ impl BeelayHandle<'_> {
         };
         if let Some(StreamState {
             remote_peer: other_peer,
+            remote_stream,
             ..
         }) = other_peer
         {
             let other_beelay = self.network.beelays.get_mut(&other_peer).unwrap();
-            if let Some(other_stream_id) = other_beelay.streams.iter().find_map(
-                |(
-                    other_stream_id,
-                    StreamState {
-                        remote_peer: peer_id,
-                        ..
+            if let Some(other_stream_id) = remote_stream.or_else(|| {
+                other_beelay.streams.iter().find_map(
+                    |(
+                        other_stream_id,
+                        StreamState {
+                            remote_peer: peer_id,
+                            ..
+                        },
+                    )| {
+                        if peer_id == &self.peer_id {
+                            Some(*other_stream_id)
+                        } else {
+                            None
+                        }
                     },
-                )| {
-                    if peer_id == &other_peer {
-                        Some(other_stream_id)
-                    } else {
-                        None
-                    }
-                },
-            ) {
-                let (_, evt) = Event::disconnect_stream(*other_stream_id);
+                )
+            }) {
+                let (_, evt) = Event::disconnect_stream(other_stream_id);
                 other_beelay.inbox.push_back(evt);
+                other_beelay.streams.remove(&other_stream_id);
             }
         }
 
@@ -415,17 +1295,45 @@ impl BeelayHandle<'_> {
     }
 }
 
+type ForgeRequest = Box<dyn FnMut(Vec<u8>) -> Vec<u8>>;
+
 pub struct Network {
-    beelays: HashMap<beelay_core::PeerId, BeelayWrapper<rand::rngs::ThreadRng>>,
+    beelays: HashMap<beelay_core::PeerId, BeelayWrapper<StdRng>>,
+    // Deliver the messages each peer sends in a round in reverse order
+    reorder_messages: bool,
+    // The storage of the last peer which failed to load, as the failed load
+    // left it
+    failed_load_storage: Option<BTreeMap<beelay_core::StorageKey, Vec<u8>>>,
+    // Replaces the encoded requests each peer sends before they are delivered
+    forged_requests: HashMap<beelay_core::PeerId, ForgeRequest>,
 }
 
 impl Network {
     pub fn new() -> Self {
         Self {
             beelays: HashMap::new(),
+            reorder_messages: false,
+            failed_load_storage: None,
+            forged_requests: HashMap::new(),
         }
     }
 
+    // Pass every request `sender` sends through `forge` before delivering it,
+    // see `resign_request`
+    pub fn forge_requests_from(
+        &mut self,
+        sender: &PeerId,
+        forge: impl FnMut(Vec<u8>) -> Vec<u8> + 'static,
+    ) {
+        self.forged_requests.insert(*sender, Box::new(forge));
+    }
+
+    // Deliver the messages sent in each round in the reverse of the order
+    // they were sent in, like a transport which reorders messages
+    pub fn reorder_messages(&mut self, reorder: bool) {
+        self.reorder_messages = reorder;
+    }
+
     pub fn beelay(&mut self, peer: &PeerId) -> BeelayHandle {
         assert!(self.beelays.contains_key(peer));
         BeelayHandle {
@@ -441,19 +1349,50 @@ impl Network {
             nickname,
             session_duration: Duration::from_secs(3600),
             signing_key: SigningKey::generate(&mut rand::thread_rng()),
+            stream_congestion_threshold: None,
+            stream_rate_limit: None,
+            stream_reorder_window: None,
+            storage_encryption_key: None,
+            storage_namespace: None,
+            rng_seed: None,
+            start_time: None,
         }
     }
 
     pub(crate) fn load_peer(
         &mut self,
         nickname: &str,
-        config: beelay_core::Config<rand::rngs::ThreadRng>,
+        config: beelay_core::Config<StdRng>,
+        storage: BTreeMap<beelay_core::StorageKey, Vec<u8>>,
+        signing_key: SigningKey,
+        storage_encryption_key: Option<beelay_core::StorageEncryptionKey>,
+    ) -> PeerId {
+        self.try_load_peer(
+            nickname,
+            config,
+            storage,
+            signing_key,
+            storage_encryption_key,
+            UnixTimestampMillis::now(),
+        )
+        .unwrap()
+    }
+
+    pub(crate) fn try_load_peer(
+        &mut self,
+        nickname: &str,
+        mut config: beelay_core::Config<StdRng>,
         mut storage: BTreeMap<beelay_core::StorageKey, Vec<u8>>,
         mut signing_key: SigningKey,
-    ) -> PeerId {
+        storage_encryption_key: Option<beelay_core::StorageEncryptionKey>,
+        now: UnixTimestampMillis,
+    ) -> Result<PeerId, beelay_core::error::Load> {
+        if let Some(key) = storage_encryption_key.clone() {
+            config = config.storage_encryption_key(key);
+        }
         let peer_id = PeerId::from(signing_key.verifying_key());
         test_utils::add_rewrite(peer_id.to_string(), nickname);
-        let mut step = beelay_core::Beelay::load(config, UnixTimestampMillis::now());
+        let mut step = beelay_core::Beelay::load(config, now);
         let mut completed_tasks = Vec::new();
         let beelay = loop {
             match step {
@@ -463,7 +1402,7 @@ impl Network {
                         completed_tasks.push(result);
                     }
                     if let Some(task_result) = completed_tasks.pop() {
-                        step = loading.handle_io_complete(UnixTimestampMillis::now(), task_result);
+                        step = loading.handle_io_complete(now, task_result);
                         continue;
                     } else {
                         panic!("no tasks completed but still loading");
@@ -476,12 +1415,18 @@ impl Network {
                     }
                     break beelay;
                 }
+                beelay_core::loading::Step::Failed(err) => {
+                    self.failed_load_storage = Some(storage);
+                    return Err(err);
+                }
             }
         };
 
         let peer_id = beelay.peer_id();
         let mut beelay = BeelayWrapper::new(signing_key, nickname, beelay);
+        beelay.now = now;
         beelay.storage = storage;
+        beelay.storage_encryption_key = storage_encryption_key;
         for result in completed_tasks {
             beelay.inbox.push_back(Event::io_complete(result));
         }
@@ -489,7 +1434,15 @@ impl Network {
         self.beelays.insert(peer_id, beelay);
         self.run_until_quiescent();
         tracing::info!("loading complete");
-        peer_id
+        Ok(peer_id)
+    }
+
+    // The storage of the last peer which failed to load, including anything
+    // written before the load failed
+    pub fn take_failed_load_storage(
+        &mut self,
+    ) -> Option<BTreeMap<beelay_core::StorageKey, Vec<u8>>> {
+        self.failed_load_storage.take()
     }
 
     pub fn reload_peer(&mut self, peer: &PeerId) {
@@ -499,12 +1452,90 @@ impl Network {
         }
         let beelay = self.beelays.remove(peer).unwrap();
         let config =
-            beelay_core::Config::new(rand::thread_rng(), beelay.signing_key.verifying_key());
-        self.load_peer(&beelay.nickname, config, beelay.storage, beelay.signing_key);
+            beelay_core::Config::new(StdRng::from_entropy(), beelay.signing_key.verifying_key());
+        self.load_peer(
+            &beelay.nickname,
+            config,
+            beelay.storage,
+            beelay.signing_key,
+            beelay.storage_encryption_key,
+        );
     }
 
     // Create a stream from left to right (i.e. the left peer will send the hello message)
     pub fn connect_stream(&mut self, left: &PeerId, right: &PeerId) -> ConnectedPair {
+        let direction = beelay_core::StreamDirection::Connecting {
+            remote_audience: beelay_core::Audience::peer(right),
+        };
+        self.connect_stream_with_direction(left, right, direction, Vec::new(), Vec::new(), None)
+    }
+
+    // Create a stream from left to right where each side offers the given
+    // compression codecs during the handshake
+    pub fn connect_stream_with_codecs(
+        &mut self,
+        left: &PeerId,
+        right: &PeerId,
+        left_codecs: Vec<beelay_core::StreamCodec>,
+        right_codecs: Vec<beelay_core::StreamCodec>,
+    ) -> ConnectedPair {
+        let direction = beelay_core::StreamDirection::Connecting {
+            remote_audience: beelay_core::Audience::peer(right),
+        };
+        self.connect_stream_with_direction(left, right, direction, left_codecs, right_codecs, None)
+    }
+
+    // Create a stream from left to right which attempts to resume from a
+    // previous stream
+    pub fn resume_stream(
+        &mut self,
+        left: &PeerId,
+        right: &PeerId,
+        token: beelay_core::StreamResumptionToken,
+    ) -> ConnectedPair {
+        let direction = beelay_core::StreamDirection::Connecting {
+            remote_audience: beelay_core::Audience::peer(right),
+        };
+        self.connect_stream_with_direction(
+            left,
+            right,
+            direction,
+            Vec::new(),
+            Vec::new(),
+            Some(token),
+        )
+    }
+
+    // Create a receive only stream from left to right
+    // Connect to `right` but address the handshake to `audience`
+    pub fn connect_stream_with_audience(
+        &mut self,
+        left: &PeerId,
+        right: &PeerId,
+        audience: beelay_core::Audience,
+    ) -> ConnectedPair {
+        let direction = beelay_core::StreamDirection::Connecting {
+            remote_audience: audience,
+        };
+        self.connect_stream_with_direction(left, right, direction, Vec::new(), Vec::new(), None)
+    }
+
+    pub fn connect_observer_stream(&mut self, left: &PeerId, right: &PeerId) -> ConnectedPair {
+        let direction = beelay_core::StreamDirection::Observing {
+            remote_audience: beelay_core::Audience::peer(right),
+        };
+        self.connect_stream_with_direction(left, right, direction, Vec::new(), Vec::new(), None)
+    }
+
+    fn connect_stream_with_direction(
+        &mut self,
+        left: &PeerId,
+        right: &PeerId,
+        direction: beelay_core::StreamDirection,
+        left_codecs: Vec<beelay_core::StreamCodec>,
+        right_codecs: Vec<beelay_core::StreamCodec>,
+        left_resume_from: Option<beelay_core::StreamResumptionToken>,
+    ) -> ConnectedPair {
         let left_closed = Arc::new(AtomicBool::new(false));
         let right_closed = Arc::new(AtomicBool::new(false));
 
@@ -512,9 +1543,9 @@ impl Network {
             let beelay = self.beelays.get_mut(left).unwrap();
             beelay.create_stream(
                 right,
-                beelay_core::StreamDirection::Connecting {
-                    remote_audience: beelay_core::Audience::peer(right),
-                },
+                direction,
+                left_codecs,
+                left_resume_from,
                 left_closed.clone(),
             )
         };
@@ -525,9 +1556,19 @@ impl Network {
                 beelay_core::StreamDirection::Accepting {
                     receive_audience: None,
                 },
+                right_codecs,
+                None,
                 right_closed.clone(),
             )
         };
+        for (peer, stream, remote_stream) in [
+            (left, left_stream_id, right_stream_id),
+            (right, right_stream_id, left_stream_id),
+        ] {
+            if let Some(state) = self.beelays.get_mut(peer).unwrap().streams.get_mut(&stream) {
+                state.remote_stream = Some(remote_stream);
+            }
+        }
         self.run_until_quiescent();
         ConnectedPair {
             left_closed,
@@ -550,7 +1591,10 @@ impl Network {
             if messages_this_round.is_empty() {
                 break;
             }
-            for (sender, outbound) in messages_this_round {
+            for (sender, mut outbound) in messages_this_round {
+                if self.reorder_messages {
+                    outbound.reverse();
+                }
                 for msg in outbound {
                     match msg {
                         Message::Request {
@@ -558,11 +1602,17 @@ impl Network {
                             senders_req_id,
                             request,
                         } => {
+                            let request = match self.forged_requests.get_mut(&sender) {
+                                Some(forge) => forge(request),
+                                None => request,
+                            };
                             let target_beelay = self.beelays.get_mut(&target).unwrap();
                             let signed_message =
                                 beelay_core::SignedMessage::decode(&request).unwrap();
-                            let (command_id, event) =
-                                beelay_core::Event::handle_request(signed_message, None);
+                            let (command_id, event) = beelay_core::Event::handle_request(
+                                signed_message,
+                                target_beelay.receive_audience.clone(),
+                            );
                             target_beelay.inbox.push_back(event);
                             target_beelay
                                 .handling_requests
@@ -580,25 +1630,34 @@ impl Network {
                                 beelay_core::Event::handle_response(id, response);
                             target.inbox.push_back(event);
                         }
-                        Message::Stream { target, msg } => {
-                            let target_beelay = self.beelays.get_mut(&target).unwrap();
-                            let incoming_stream_id = target_beelay
+                        Message::Stream {
+                            target,
+                            from_stream,
+                            msg,
+                        } => {
+                            let remote_stream = self.beelays[&sender]
                                 .streams
-                                .iter()
-                                .find_map(
-                                    |(
-                                        stream,
-                                        StreamState {
-                                            remote_peer: peer, ..
+                                .get(&from_stream)
+                                .and_then(|s| s.remote_stream);
+                            let target_beelay = self.beelays.get_mut(&target).unwrap();
+                            let incoming_stream_id = remote_stream
+                                .as_ref()
+                                .or_else(|| {
+                                    target_beelay.streams.iter().find_map(
+                                        |(
+                                            stream,
+                                            StreamState {
+                                                remote_peer: peer, ..
+                                            },
+                                        )| {
+                                            if *peer == sender {
+                                                Some(stream)
+                                            } else {
+                                                None
+                                            }
                                         },
-                                    )| {
-                                        if *peer == sender {
-                                            Some(stream)
-                                        } else {
-                                            None
-                                        }
-                                    },
-                                )
+                                    )
+                                })
                                 .unwrap();
                             let event =
                                 beelay_core::Event::handle_message(*incoming_stream_id, msg);
@@ -632,6 +1691,7 @@ enum Message {
     },
     Stream {
         target: PeerId,
+        from_stream: beelay_core::StreamId,
         msg: Vec<u8>,
     },
 }
@@ -640,6 +1700,7 @@ pub struct BeelayWrapper<R: rand::Rng + rand::CryptoRng> {
     nickname: String,
     signing_key: SigningKey,
     storage: BTreeMap<beelay_core::StorageKey, Vec<u8>>,
+    storage_encryption_key: Option<beelay_core::StorageEncryptionKey>,
     core: beelay_core::Beelay<R>,
     outbox: Vec<Message>,
     inbox: VecDeque<beelay_core::Event>,
@@ -648,6 +1709,7 @@ pub struct BeelayWrapper<R: rand::Rng + rand::CryptoRng> {
         Result<beelay_core::CommandResult, beelay_core::error::Stopping>,
     >,
     notifications: HashMap<DocumentId, Vec<beelay_core::doc_status::DocEvent>>,
+    command_progress: HashMap<beelay_core::CommandId, Vec<beelay_core::CommandProgress>>,
     peer_changes: HashMap<PeerId, Vec<beelay_core::conn_info::ConnectionInfo>>,
     handling_requests: HashMap<beelay_core::CommandId, (beelay_core::OutboundRequestId, PeerId)>,
     endpoints: HashMap<beelay_core::EndpointId, beelay_core::PeerId>,
@@ -655,6 +1717,17 @@ pub struct BeelayWrapper<R: rand::Rng + rand::CryptoRng> {
     starting_streams: HashMap<beelay_core::CommandId, StreamState>,
     shutdown: bool,
     now: UnixTimestampMillis,
+    stream_messages_sent: usize,
+    // Acknowledgements of storage writes which are being withheld
+    held_writes: Option<Vec<beelay_core::Event>>,
+    // Number of stream messages sent but not yet reported to the core
+    held_stream_acks: Option<HashMap<beelay_core::StreamId, usize>>,
+    congested_streams: Vec<(beelay_core::StreamId, beelay_core::StreamQueueDepth)>,
+    rate_limited_streams: Vec<beelay_core::StreamId>,
+    append_failures: Vec<beelay_core::AppendFailure>,
+    correlation_ids: HashMap<beelay_core::CommandId, String>,
+    // The receive audience incoming requests are handled with
+    receive_audience: Option<String>,
 }
 
 impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
@@ -663,11 +1736,13 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
             nickname: nickname.to_string(),
             signing_key,
             storage: BTreeMap::new(),
+            storage_encryption_key: None,
             core,
             outbox: Vec::new(),
             inbox: VecDeque::new(),
             completed_commands: HashMap::new(),
             notifications: HashMap::new(),
+            command_progress: HashMap::new(),
             peer_changes: HashMap::new(),
             handling_requests: HashMap::new(),
             endpoints: HashMap::new(),
@@ -675,6 +1750,14 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
             starting_streams: HashMap::new(),
             shutdown: false,
             now: UnixTimestampMillis::now(),
+            stream_messages_sent: 0,
+            held_writes: None,
+            held_stream_acks: None,
+            congested_streams: Vec::new(),
+            rate_limited_streams: Vec::new(),
+            append_failures: Vec::new(),
+            correlation_ids: HashMap::new(),
+            receive_audience: None,
         }
     }
 
@@ -682,13 +1765,17 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
         &mut self,
         target: &PeerId,
         direction: beelay_core::StreamDirection,
+        codecs: Vec<beelay_core::StreamCodec>,
+        resume_from: Option<beelay_core::StreamResumptionToken>,
         closed: Arc<AtomicBool>,
     ) -> beelay_core::StreamId {
-        let (command, event) = beelay_core::Event::create_stream(direction);
+        let (command, event) =
+            beelay_core::Event::create_stream_with_resumption(direction, codecs, resume_from);
         self.starting_streams.insert(
             command,
             StreamState {
                 remote_peer: *target,
+                remote_stream: None,
                 closed,
             },
         );
@@ -712,9 +1799,17 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
             self.now += Duration::from_millis(10);
             let results = self.core.handle_event(self.now, event).unwrap();
             for task in results.new_tasks.into_iter() {
+                let is_write = matches!(
+                    task.action(),
+                    IoAction::Put { .. } | IoAction::Delete { .. }
+                );
                 let event = self.handle_task(task);
-                self.inbox.push_back(event);
+                match &mut self.held_writes {
+                    Some(held) if is_write => held.push(event),
+                    _ => self.inbox.push_back(event),
+                }
             }
+            self.correlation_ids.extend(results.correlation_ids);
             for (command, result) in results.completed_commands.into_iter() {
                 if let Ok(beelay_core::CommandResult::CreateStream(stream_id)) = result {
                     let target = self
@@ -724,8 +1819,10 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
                     self.streams.insert(stream_id, target);
                 }
                 if let Ok(beelay_core::CommandResult::HandleRequest(response)) = &result {
-                    let Ok(response) = response else {
-                        continue;
+                    // Failed requests still carry a response for the requestor
+                    let response = match response {
+                        Ok(handled) => &handled.response,
+                        Err(e) => &e.response,
                     };
                     if let Some((sender_req_id, sender)) = self.handling_requests.remove(&command) {
                         self.outbox.push(Message::Response {
@@ -748,26 +1845,54 @@ impl<R: rand::Rng + rand::CryptoRng + Clone + 'static> BeelayWrapper<R> {
                 }
             }
             for (id, events) in results.new_stream_events {
+                let num_sent = events
+                    .iter()
+                    .filter(|e| matches!(e, beelay_core::StreamEvent::Send(_)))
+                    .count();
+                // Only report sent messages if the core is tracking them
+                if num_sent > 0 && self.core.stream_queue_depth(id).is_some() {
+                    match &mut self.held_stream_acks {
+                        Some(held) => *held.entry(id).or_default() += num_sent,
+                        None => self
+                            .inbox
+                            .push_back(Event::stream_messages_sent(id, num_sent)),
+                    }
+                }
                 for event in events {
                     tracing::trace!(?event, "stream event");
                     let StreamState {
                         remote_peer: target,
                         closed,
+                        ..
                     } = self.streams.get(&id).unwrap();
                     match event {
-                        beelay_core::StreamEvent::Send(msg) => self.outbox.push(Message::Stream {
-                            target: *target,
-                            msg,
-                        }),
+                        beelay_core::StreamEvent::Send(msg) => {
+                            self.stream_messages_sent += 1;
+                            self.outbox.push(Message::Stream {
+                                target: *target,
+                                from_stream: id,
+                                msg,
+                            })
+                        }
                         beelay_core::StreamEvent::Close => {
                             closed.store(true, Ordering::SeqCst);
                         }
                     }
                 }
             }
+            self.congested_streams.extend(results.congested_streams);
+            self.rate_limited_streams
+                .extend(results.rate_limited_streams);
+            self.append_failures.extend(results.append_failures);
             for (doc_id, events) in results.notifications.into_iter() {
                 self.notifications.entry(doc_id).or_default().extend(events);
             }
+            for progress in results.command_progress {
+                self.command_progress
+                    .entry(progress.command_id)
+                    .or_default()
+                    .push(progress);
+            }
             for (peer_id, status) in results.peer_status_changes.into_iter() {
                 self.peer_changes.entry(peer_id).or_default().push(status);
             }
@@ -837,6 +1962,28 @@ fn handle_task(
     }
 }
 
+// Sign the payload of the encoded request `request` with `signer` instead,
+// after passing the encoded payload through `edit`. The request content ends
+// the payload so `edit` can change its trailing bytes in place.
+pub fn resign_request(
+    request: &[u8],
+    signer: &SigningKey,
+    edit: impl FnOnce(&mut Vec<u8>),
+) -> Vec<u8> {
+    // The payload is prefixed with its length as a ULEB128 and followed by
+    // the 32 byte verifying key and the 64 byte signature
+    let prefix_len = request.iter().position(|b| b & 0x80 == 0).unwrap() + 1;
+    let mut payload = request[prefix_len..request.len() - 96].to_vec();
+    edit(&mut payload);
+    let mut signer = signer.clone();
+    let signature = signer.sign(&payload);
+    let mut forged = request[..prefix_len].to_vec();
+    forged.extend_from_slice(&payload);
+    forged.extend_from_slice(signer.verifying_key().as_bytes());
+    forged.extend_from_slice(&signature.to_bytes());
+    forged
+}
+
 pub struct ConnectedPair {
     pub left_closed: Arc<AtomicBool>,
     pub left_to_right: beelay_core::StreamId,
@@ -846,6 +1993,9 @@ pub struct ConnectedPair {
 
 pub struct StreamState {
     remote_peer: PeerId,
+    // The other end of the stream, for peers with several streams between
+    // them. Only known for streams made by `connect_stream` and friends.
+    remote_stream: Option<beelay_core::StreamId>,
     closed: Arc<AtomicBool>,
 }
 
@@ -855,9 +2005,54 @@ pub struct PeerBuilder<'a> {
     session_duration: Duration,
     signing_key: SigningKey,
     storage: BTreeMap<beelay_core::StorageKey, Vec<u8>>,
+    stream_congestion_threshold: Option<usize>,
+    stream_rate_limit: Option<beelay_core::StreamRateLimit>,
+    stream_reorder_window: Option<usize>,
+    storage_encryption_key: Option<beelay_core::StorageEncryptionKey>,
+    storage_namespace: Option<&'static str>,
+    rng_seed: Option<u64>,
+    start_time: Option<UnixTimestampMillis>,
 }
 
 impl PeerBuilder<'_> {
+    // Seed the peer's RNG so that, with ids reset using
+    // `beelay_core::reset_ids`, the same events produce the same results
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    // Start the peer's clock at `time` rather than the current time
+    pub fn start_time(mut self, time: UnixTimestampMillis) -> Self {
+        self.start_time = Some(time);
+        self
+    }
+
+    pub fn storage_encryption_key(mut self, key: beelay_core::StorageEncryptionKey) -> Self {
+        self.storage_encryption_key = Some(key);
+        self
+    }
+
+    pub fn storage_namespace(mut self, namespace: &'static str) -> Self {
+        self.storage_namespace = Some(namespace);
+        self
+    }
+
+    pub fn stream_rate_limit(mut self, limit: beelay_core::StreamRateLimit) -> Self {
+        self.stream_rate_limit = Some(limit);
+        self
+    }
+
+    pub fn stream_reorder_window(mut self, messages: usize) -> Self {
+        self.stream_reorder_window = Some(messages);
+        self
+    }
+
+    pub fn stream_congestion_threshold(mut self, bytes: usize) -> Self {
+        self.stream_congestion_threshold = Some(bytes);
+        self
+    }
+
     pub fn session_duration(mut self, duration: Duration) -> Self {
         self.session_duration = duration;
         self
@@ -874,9 +2069,35 @@ impl PeerBuilder<'_> {
     }
 
     pub fn build(self) -> PeerId {
-        let config = beelay_core::Config::new(rand::thread_rng(), self.signing_key.verifying_key())
+        self.try_build().unwrap()
+    }
+
+    pub fn try_build(self) -> Result<PeerId, beelay_core::error::Load> {
+        let rng = match self.rng_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut config = beelay_core::Config::new(rng, self.signing_key.verifying_key())
             .session_duration(self.session_duration);
-        self.network
-            .load_peer(self.nickname, config, self.storage, self.signing_key)
+        if let Some(threshold) = self.stream_congestion_threshold {
+            config = config.stream_congestion_threshold(threshold);
+        }
+        if let Some(limit) = self.stream_rate_limit {
+            config = config.stream_rate_limit(limit);
+        }
+        if let Some(window) = self.stream_reorder_window {
+            config = config.stream_reorder_window(window);
+        }
+        if let Some(namespace) = self.storage_namespace {
+            config = config.storage_namespace(namespace);
+        }
+        self.network.try_load_peer(
+            self.nickname,
+            config,
+            self.storage,
+            self.signing_key,
+            self.storage_encryption_key,
+            self.start_time.unwrap_or_else(UnixTimestampMillis::now),
+        )
     }
 }
@@ -1,10 +1,15 @@
-use std::{collections::HashSet, sync::atomic::Ordering, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::atomic::Ordering,
+    time::Duration,
+};
 
 use beelay_core::{
     conn_info,
-    doc_status::DocStatus,
-    keyhive::{AddMemberToGroup, KeyhiveEntityId, MemberAccess},
-    CommitHash, CommitOrBundle,
+    doc_status::{DocEvent, DocStatus, StreamSyncStatus, SyncPriority},
+    keyhive::{AddMemberToGroup, GroupMetadata, KeyhiveEntityId, MemberAccess},
+    Audience, CommandResult, Commit, CommitAuthor, CommitBundle, CommitHash, CommitOrBundle, Event,
+    StreamCodec, StreamDirection, StreamError, StreamState,
 };
 use ed25519_dalek::SigningKey;
 use keyhive_core::principal::public::Public;
@@ -61,6 +66,7 @@ fn create_and_sync_via_stream() {
 
     let DocStatus {
         local_heads: Some(start_heads),
+        ..
     } = network.beelay(&peer1).doc_status(&doc1_id)
     else {
         panic!("no local heads on peer1");
@@ -86,7 +92,10 @@ fn create_and_sync_via_stream() {
     let status = network.beelay(&peer2).doc_status(&doc1_id);
     assert_eq!(
         status,
-        beelay_core::doc_status::DocStatus { local_heads: None }
+        beelay_core::doc_status::DocStatus {
+            local_heads: None,
+            sync_paused: false
+        }
     );
 
     // Now connect the other peer
@@ -96,7 +105,8 @@ fn create_and_sync_via_stream() {
     assert_eq!(
         network.beelay(&peer2).doc_status(&doc1_id),
         DocStatus {
-            local_heads: Some(vec![CommitHash::from([1; 32])])
+            local_heads: Some(vec![CommitHash::from([1; 32])]),
+            sync_paused: false
         }
     );
 
@@ -149,7 +159,8 @@ fn changes_published_after_sync() {
     assert_eq!(
         network.beelay(&peer2).doc_status(&doc1_id),
         DocStatus {
-            local_heads: Some(vec![initial_commit.hash()])
+            local_heads: Some(vec![initial_commit.hash()]),
+            sync_paused: false
         }
     );
 
@@ -169,7 +180,8 @@ fn changes_published_after_sync() {
     assert_eq!(
         network.beelay(&peer2).doc_status(&doc1_id),
         DocStatus {
-            local_heads: Some(vec![commit1.hash()])
+            local_heads: Some(vec![commit1.hash()]),
+            sync_paused: false
         }
     );
 
@@ -330,6 +342,7 @@ fn newly_accessible_documents_are_synced() {
             group_id: group,
             member: KeyhiveEntityId::Individual(charlie_contact),
             access: MemberAccess::Admin,
+            expires_at: None,
         })
         .unwrap();
 
@@ -353,6 +366,7 @@ fn newly_accessible_documents_are_synced() {
             group_id: group,
             member: KeyhiveEntityId::Individual(bob_contact),
             access: MemberAccess::Admin,
+            expires_at: None,
         })
         .unwrap();
 
@@ -416,6 +430,7 @@ fn public_docs_are_synced() {
 
     let DocStatus {
         local_heads: Some(start_heads),
+        ..
     } = network.beelay(&peer1).doc_status(&doc1_id)
     else {
         panic!("no local heads on peer1");
@@ -432,7 +447,10 @@ fn public_docs_are_synced() {
     let status = network.beelay(&peer2).doc_status(&doc1_id);
     assert_eq!(
         status,
-        beelay_core::doc_status::DocStatus { local_heads: None }
+        beelay_core::doc_status::DocStatus {
+            local_heads: None,
+            sync_paused: false
+        }
     );
 
     // Now connect the other peer
@@ -442,7 +460,8 @@ fn public_docs_are_synced() {
     assert_eq!(
         network.beelay(&peer2).doc_status(&doc1_id),
         DocStatus {
-            local_heads: Some(vec![CommitHash::from([1; 32])])
+            local_heads: Some(vec![CommitHash::from([1; 32])]),
+            sync_paused: false
         }
     );
 
@@ -474,6 +493,239 @@ fn public_docs_are_synced() {
     assert_eq!(commits_on_2, expected_commits);
 }
 
+#[test]
+fn observer_streams_only_receive() {
+    init_logging();
+    let mut network = Network::new();
+    let server = network.create_peer("server").build();
+    let observer = network.create_peer("observer").build();
+
+    let (server_doc, server_commit) = network
+        .beelay(&server)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+    let (observer_doc, _) = network
+        .beelay(&observer)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+
+    let ConnectedPair {
+        left_to_right: observer_to_server,
+        ..
+    } = network.connect_observer_stream(&observer, &server);
+
+    // The observer should have pulled the server's document but not pushed its own
+    assert_eq!(
+        network.beelay(&observer).load_doc(server_doc),
+        Some(vec![CommitOrBundle::Commit(server_commit.clone())])
+    );
+    assert_eq!(network.beelay(&server).load_doc(observer_doc), None);
+
+    // Changes on the server are forwarded to the observer
+    let server_change = beelay_core::Commit::new(
+        vec![server_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&server)
+        .add_commits(server_doc, vec![server_change.clone()])
+        .unwrap();
+    assert_eq!(
+        network.beelay(&observer).doc_status(&server_doc),
+        DocStatus {
+            local_heads: Some(vec![server_change.hash()]),
+            sync_paused: false
+        }
+    );
+
+    // Changes on the observer are never uploaded, even after a resync
+    let observer_change = beelay_core::Commit::new(
+        vec![server_change.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([2; 32]),
+    );
+    network
+        .beelay(&observer)
+        .add_commits(server_doc, vec![observer_change])
+        .unwrap();
+    network.advance_time(Duration::from_secs(60));
+    assert_eq!(
+        network.beelay(&server).doc_status(&server_doc),
+        DocStatus {
+            local_heads: Some(vec![server_change.hash()]),
+            sync_paused: false
+        }
+    );
+    assert_eq!(network.beelay(&server).load_doc(observer_doc), None);
+
+    network.beelay(&observer).disconnect(observer_to_server);
+    assert!(network.beelay(&observer).conn_info().is_empty());
+    assert!(network.beelay(&server).conn_info().is_empty());
+}
+
+#[test]
+fn compressed_streams_sync() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+
+    let (doc_id, initial_commit) = network
+        .beelay(&peer1)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+
+    // Both sides offer deflate so the stream is compressed
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream_with_codecs(
+        &peer2,
+        &peer1,
+        vec![StreamCodec::Deflate],
+        vec![StreamCodec::Deflate],
+    );
+    assert_eq!(
+        network.beelay(&peer2).conn_info()[&left_to_right].codec,
+        Some(StreamCodec::Deflate)
+    );
+    assert_eq!(
+        network.beelay(&peer1).conn_info()[&right_to_left].codec,
+        Some(StreamCodec::Deflate)
+    );
+    assert_eq!(
+        network.beelay(&peer2).load_doc(doc_id),
+        Some(vec![CommitOrBundle::Commit(initial_commit.clone())])
+    );
+
+    // Only one side offers deflate so the stream falls back to uncompressed
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream_with_codecs(&peer3, &peer1, vec![StreamCodec::Deflate], vec![]);
+    assert_eq!(
+        network.beelay(&peer3).conn_info()[&left_to_right].codec,
+        None
+    );
+    assert_eq!(
+        network.beelay(&peer1).conn_info()[&right_to_left].codec,
+        None
+    );
+    assert_eq!(
+        network.beelay(&peer3).load_doc(doc_id),
+        Some(vec![CommitOrBundle::Commit(initial_commit)])
+    );
+}
+
+#[test]
+fn stream_sync_status_tracks_remote_heads() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+
+    let (shared, initial_commit) = network
+        .beelay(&peer1)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+    let (private, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let ConnectedPair { left_to_right, .. } = network.connect_stream(&peer2, &peer1);
+
+    let CommandResult::StreamSyncStatus(status) = network
+        .beelay(&peer2)
+        .run_command(Event::stream_sync_status(left_to_right, shared))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(
+        status.unwrap(),
+        StreamSyncStatus::Known {
+            local_heads: vec![initial_commit.hash()],
+            remote_heads: vec![initial_commit.hash()],
+            in_sync: true,
+        }
+    );
+
+    // peer2 can't see the private document so the stream never discussed it
+    let CommandResult::StreamSyncStatus(status) = network
+        .beelay(&peer2)
+        .run_command(Event::stream_sync_status(left_to_right, private))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(status.unwrap(), StreamSyncStatus::Unknown);
+
+    network.beelay(&peer2).disconnect(left_to_right);
+    let CommandResult::StreamSyncStatus(status) = network
+        .beelay(&peer2)
+        .run_command(Event::stream_sync_status(left_to_right, shared))
+    else {
+        panic!("unexpected command result");
+    };
+    assert!(matches!(status, Err(StreamError::NoSuchStream)));
+}
+
+#[test]
+fn commit_authors_are_recorded() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+
+    let (doc_id, initial_commit) = network
+        .beelay(&alice)
+        .create_doc(vec![KeyhiveEntityId::Individual(bob_contact)])
+        .unwrap();
+    network.connect_stream(&bob, &alice);
+
+    let bob_commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&bob)
+        .add_commits(doc_id, vec![bob_commit.clone()])
+        .unwrap();
+
+    let bundle = CommitBundle::builder()
+        .start(initial_commit.hash())
+        .end(bob_commit.hash())
+        .bundled_commits(vec![4, 5, 6])
+        .build();
+    let CommandResult::AddBundle(result) = network
+        .beelay(&alice)
+        .run_command(Event::add_bundle(doc_id, bundle.clone()))
+    else {
+        panic!("unexpected command result");
+    };
+    result.unwrap();
+
+    let authors = network
+        .beelay(&alice)
+        .load_doc_with_meta(doc_id)
+        .unwrap()
+        .into_iter()
+        .map(|(item, meta)| (item, meta.unwrap().author))
+        .collect::<Vec<_>>();
+    assert_eq!(authors.len(), 3);
+    for (item, author) in authors {
+        let expected = match item {
+            CommitOrBundle::Commit(c) if c == initial_commit => CommitAuthor::Peer(alice),
+            CommitOrBundle::Commit(c) if c == bob_commit => CommitAuthor::Peer(bob),
+            CommitOrBundle::Bundle(b) if b == bundle => CommitAuthor::Unknown,
+            other => panic!("unexpected item {:?}", other),
+        };
+        assert_eq!(author, expected);
+    }
+}
+
 #[test]
 fn stream_close_emitted_on_shutdown() {
     init_logging();
@@ -491,3 +743,1583 @@ fn stream_close_emitted_on_shutdown() {
     network.beelay(&peer2).shutdown();
     assert!(left_closed.load(Ordering::SeqCst));
 }
+
+#[test]
+fn resumed_stream_skips_initial_sync() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+
+    let (doc_id, initial_commit) = network
+        .beelay(&peer1)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+    for _ in 0..3 {
+        network
+            .beelay(&peer1)
+            .create_doc(vec![Public.into()])
+            .unwrap();
+    }
+
+    let ConnectedPair { left_to_right, .. } = network.connect_stream(&peer2, &peer1);
+    let token = network
+        .beelay(&peer2)
+        .resumption_token(left_to_right)
+        .unwrap();
+    assert_eq!(token.peer_id(), peer1);
+    network.beelay(&peer2).disconnect(left_to_right);
+
+    // Tokens survive being persisted by the application
+    let token = beelay_core::StreamResumptionToken::decode(&token.encode()).unwrap();
+
+    let sent_before = network.beelay(&peer2).stream_messages_sent();
+    let ConnectedPair { left_to_right, .. } = network.connect_stream(&peer2, &peer1);
+    let full_sync_messages = network.beelay(&peer2).stream_messages_sent() - sent_before;
+    network.beelay(&peer2).disconnect(left_to_right);
+
+    let sent_before = network.beelay(&peer2).stream_messages_sent();
+    let ConnectedPair { left_to_right, .. } = network.resume_stream(&peer2, &peer1, token);
+    let resumed_messages = network.beelay(&peer2).stream_messages_sent() - sent_before;
+    assert!(
+        resumed_messages < full_sync_messages,
+        "resumed stream sent {} messages, a full sync sent {}",
+        resumed_messages,
+        full_sync_messages
+    );
+
+    // What we knew about the remote is restored from the token
+    let CommandResult::StreamSyncStatus(status) = network
+        .beelay(&peer2)
+        .run_command(Event::stream_sync_status(left_to_right, doc_id))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(
+        status.unwrap(),
+        StreamSyncStatus::Known {
+            local_heads: vec![initial_commit.hash()],
+            remote_heads: vec![initial_commit.hash()],
+            in_sync: true,
+        }
+    );
+    assert!(network
+        .beelay(&peer2)
+        .resumption_token(left_to_right)
+        .is_some());
+}
+
+#[test]
+fn stale_resumption_tokens_fall_back_to_full_sync() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+
+    let (doc_id, initial_commit) = network
+        .beelay(&peer1)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+    let ConnectedPair { left_to_right, .. } = network.connect_stream(&peer2, &peer1);
+    let token = network
+        .beelay(&peer2)
+        .resumption_token(left_to_right)
+        .unwrap();
+    network.beelay(&peer2).disconnect(left_to_right);
+
+    // A token for a different peer is ignored
+    let (peer3_doc, _) = network
+        .beelay(&peer3)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+    let ConnectedPair { left_to_right, .. } = network.resume_stream(&peer2, &peer3, token.clone());
+    assert!(network.beelay(&peer2).load_doc(peer3_doc).is_some());
+    network.beelay(&peer2).disconnect(left_to_right);
+
+    // Local changes since the token was captured are offered to the remote,
+    // note that peer2 has also picked up peer3's document since then
+    let (peer2_doc, _) = network
+        .beelay(&peer2)
+        .create_doc(vec![Public.into()])
+        .unwrap();
+    let ConnectedPair { left_to_right, .. } = network.resume_stream(&peer2, &peer1, token.clone());
+    assert!(network.beelay(&peer1).load_doc(peer2_doc).is_some());
+    let token = network
+        .beelay(&peer2)
+        .resumption_token(left_to_right)
+        .unwrap();
+    network.beelay(&peer2).disconnect(left_to_right);
+
+    // Once a periodic sync would have been due the token is stale, so remote
+    // changes made whilst we were disconnected are fetched immediately
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([7; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+    network.advance_time(beelay_core::SYNC_INTERVAL + Duration::from_secs(1));
+    network.resume_stream(&peer2, &peer1, token);
+    let loaded = network.beelay(&peer2).load_doc(doc_id).unwrap();
+    assert!(loaded.contains(&CommitOrBundle::Commit(commit)));
+}
+
+#[test]
+fn list_streams_reports_direction_and_state() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    let streams = network.beelay(&peer1).list_streams();
+    assert_eq!(streams.len(), 1);
+    assert!(matches!(
+        &streams[0],
+        (id, StreamDirection::Connecting { remote_audience }, StreamState::Established)
+            if *id == left_to_right && *remote_audience == Audience::peer(&peer2)
+    ));
+    let streams = network.beelay(&peer2).list_streams();
+    assert!(matches!(
+        &streams[..],
+        [(id, StreamDirection::Accepting { .. }, StreamState::Established)] if *id == right_to_left
+    ));
+
+    // A handshake addressed to the wrong audience fails on the connecting
+    // side and leaves the accepting side waiting for a valid hello
+    let ConnectedPair {
+        left_to_right: failed,
+        right_to_left: waiting,
+        ..
+    } = network.connect_stream_with_audience(&peer1, &peer3, Audience::service_name("nope"));
+    let streams = network.beelay(&peer1).list_streams();
+    assert!(streams
+        .iter()
+        .any(|(id, _, state)| *id == failed && *state == StreamState::Closing));
+    let streams = network.beelay(&peer3).list_streams();
+    assert!(streams
+        .iter()
+        .any(|(id, _, state)| *id == waiting && *state == StreamState::Handshaking));
+
+    network.beelay(&peer1).disconnect(left_to_right);
+    network.beelay(&peer1).disconnect(failed);
+    assert!(network.beelay(&peer1).list_streams().is_empty());
+}
+
+#[test]
+fn disconnect_peer_closes_every_stream_to_the_peer() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+
+    network.connect_stream(&peer1, &peer2);
+    network.connect_stream(&peer2, &peer1);
+    let ConnectedPair {
+        left_to_right: to_peer3,
+        ..
+    } = network.connect_stream(&peer1, &peer3);
+
+    let result = network
+        .beelay(&peer1)
+        .run_command(Event::disconnect_peer(peer2_contact.clone().into()));
+    assert!(matches!(result, CommandResult::DisconnectPeer(2)));
+    let streams = network.beelay(&peer1).list_streams();
+    assert!(matches!(&streams[..], [(id, _, _)] if *id == to_peer3));
+
+    let result = network
+        .beelay(&peer1)
+        .run_command(Event::disconnect_peer(peer2_contact.into()));
+    assert!(matches!(result, CommandResult::DisconnectPeer(0)));
+    let result = network
+        .beelay(&peer1)
+        .run_command(Event::disconnect_peer(KeyhiveEntityId::Public));
+    assert!(matches!(result, CommandResult::DisconnectPeer(0)));
+}
+
+#[test]
+fn failed_handshakes_report_why_they_failed() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    assert_eq!(
+        network.beelay(&peer1).conn_info()[&left_to_right].protocol_version,
+        beelay_core::PROTOCOL_VERSION
+    );
+    assert_eq!(
+        network
+            .beelay(&peer2)
+            .stream_handshake_error(right_to_left)
+            .unwrap(),
+        None
+    );
+
+    let ConnectedPair {
+        left_to_right: failed,
+        ..
+    } = network.connect_stream_with_audience(&peer1, &peer3, Audience::service_name("nope"));
+    assert_eq!(
+        network
+            .beelay(&peer1)
+            .stream_handshake_error(failed)
+            .unwrap(),
+        Some(beelay_core::HandshakeError::Other(
+            "authentication failed".to_string()
+        ))
+    );
+
+    network.beelay(&peer1).disconnect(failed);
+    assert!(matches!(
+        network.beelay(&peer1).stream_handshake_error(failed),
+        Err(StreamError::NoSuchStream)
+    ));
+}
+
+#[test]
+fn stream_metrics_count_traffic_until_disconnect() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let (doc_id, initial_commit) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    let sent = network
+        .beelay(&peer1)
+        .stream_metrics(left_to_right)
+        .unwrap();
+    let received = network
+        .beelay(&peer2)
+        .stream_metrics(right_to_left)
+        .unwrap();
+    assert!(sent.messages_sent > 0);
+    assert_eq!(sent.bytes_sent, received.bytes_received);
+    assert_eq!(sent.messages_sent, received.messages_received);
+    assert_eq!(sent.bytes_received, received.bytes_sent);
+    assert_eq!(sent.messages_received, received.messages_sent);
+    // Only the connecting end drives sync
+    assert!(sent.round_trips > 0);
+    assert_eq!(received.round_trips, 0);
+
+    // Counters keep growing across syncs
+    let commit = Commit::new(
+        vec![initial_commit.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([7; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit])
+        .unwrap();
+    let later = network
+        .beelay(&peer1)
+        .stream_metrics(left_to_right)
+        .unwrap();
+    assert!(later.bytes_sent > sent.bytes_sent);
+    assert_eq!(
+        network.beelay(&peer1).all_stream_metrics(),
+        HashMap::from([(left_to_right, later)])
+    );
+
+    network.beelay(&peer1).disconnect(left_to_right);
+    assert!(matches!(
+        network.beelay(&peer1).stream_metrics(left_to_right),
+        Err(StreamError::NoSuchStream)
+    ));
+    assert!(network.beelay(&peer1).all_stream_metrics().is_empty());
+}
+
+#[test]
+fn verbose_tick_reports_periodic_syncs() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let ConnectedPair { left_to_right, .. } = network.connect_stream(&peer1, &peer2);
+
+    // An idle tick still reports a summary
+    assert_eq!(
+        network
+            .beelay(&peer1)
+            .advance_time_verbose(Duration::from_secs(1)),
+        beelay_core::TickSummary::default()
+    );
+
+    let summary = network
+        .beelay(&peer1)
+        .advance_time_verbose(beelay_core::SYNC_INTERVAL);
+    assert_eq!(summary.resynced_streams, vec![left_to_right]);
+    assert_eq!(summary.expired_memberships, 0);
+    assert!(summary.timed_out_requests.is_empty());
+}
+
+#[test]
+fn next_tick_deadline_recomputes_after_periodic_sync() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    network.beelay(&peer1).create_doc(vec![]).unwrap();
+
+    let ConnectedPair { left_to_right, .. } = network.connect_stream(&peer1, &peer2);
+    let first = network
+        .beelay(&peer1)
+        .next_tick_deadline()
+        .expect("the connecting side should schedule a periodic sync");
+
+    network
+        .beelay(&peer1)
+        .advance_time(beelay_core::SYNC_INTERVAL + Duration::from_secs(1));
+    let second = network.beelay(&peer1).next_tick_deadline().unwrap();
+    assert!(second > first);
+
+    network.beelay(&peer1).disconnect(left_to_right);
+    assert_eq!(network.beelay(&peer1).next_tick_deadline(), None);
+}
+
+#[test]
+fn stream_rate_limit_drops_unsolicited_messages() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network
+        .create_peer("peer1")
+        .stream_rate_limit(beelay_core::StreamRateLimit {
+            messages_per_second: 3,
+            bytes_per_second: 1024,
+        })
+        .build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer1_contact = network.beelay(&peer1).contact_card().unwrap();
+
+    let mut docs = Vec::new();
+    for i in 0..5 {
+        let (doc_id, _) = network
+            .beelay(&peer2)
+            .create_doc_with_contents(vec![i], vec![peer1_contact.clone().into()])
+            .unwrap();
+        docs.push(doc_id);
+    }
+
+    // The initial catch-up is mostly replies to our own requests, which
+    // don't count against the limit, so it isn't throttled
+    let ConnectedPair {
+        left_to_right,
+        left_closed,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    for doc_id in &docs {
+        assert!(network.beelay(&peer1).load_doc(*doc_id).is_some());
+    }
+    assert!(network.beelay(&peer1).pop_rate_limited_streams().is_empty());
+
+    // A flood of messages we didn't ask for is dropped before it is parsed,
+    // so the junk doesn't close the stream
+    network.beelay(&peer1).set_stream_rate_limit(
+        left_to_right,
+        Some(beelay_core::StreamRateLimit {
+            messages_per_second: 0,
+            bytes_per_second: 0,
+        }),
+    );
+    for _ in 0..3 {
+        network
+            .beelay(&peer1)
+            .inject_stream_message(left_to_right, vec![1, 2, 3]);
+    }
+    assert!(!left_closed.load(Ordering::SeqCst));
+    assert_eq!(
+        network.beelay(&peer1).pop_rate_limited_streams(),
+        vec![left_to_right]
+    );
+
+    // Each second the stream is limited is reported again
+    network.beelay(&peer1).advance_time(Duration::from_secs(2));
+    network
+        .beelay(&peer1)
+        .inject_stream_message(left_to_right, vec![1, 2, 3]);
+    assert_eq!(
+        network.beelay(&peer1).pop_rate_limited_streams(),
+        vec![left_to_right]
+    );
+
+    // Without a limit the junk reaches the stream, which closes it
+    network
+        .beelay(&peer1)
+        .set_stream_rate_limit(left_to_right, None);
+    network
+        .beelay(&peer1)
+        .inject_stream_message(left_to_right, vec![1, 2, 3]);
+    assert!(left_closed.load(Ordering::SeqCst));
+    assert!(network.beelay(&peer1).pop_rate_limited_streams().is_empty());
+}
+
+#[test]
+fn stream_rate_limit_drops_requests_sent_alongside_responses() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer1_contact = network.beelay(&peer1).contact_card().unwrap();
+
+    let (doc_id, initial_commit) = network
+        .beelay(&peer2)
+        .create_doc(vec![peer1_contact.into()])
+        .unwrap();
+    let ConnectedPair {
+        left_to_right,
+        left_closed,
+        right_to_left,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    assert!(network.beelay(&peer1).load_doc(doc_id).is_some());
+    let answered = network
+        .beelay(&peer2)
+        .stream_metrics(right_to_left)
+        .unwrap()
+        .round_trips;
+
+    network.beelay(&peer1).set_stream_rate_limit(
+        left_to_right,
+        Some(beelay_core::StreamRateLimit {
+            messages_per_second: 0,
+            bytes_per_second: 0,
+        }),
+    );
+
+    // Both ends send requests at once, so peer2's request arrives while
+    // peer1 is waiting for a response. Only a response would have been let
+    // through over the limit.
+    let ours = beelay_core::Commit::new(
+        vec![initial_commit.hash()],
+        vec![1],
+        CommitHash::from([1; 32]),
+    );
+    let theirs = beelay_core::Commit::new(
+        vec![initial_commit.hash()],
+        vec![2],
+        CommitHash::from([2; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .queue(Event::add_commits(doc_id, vec![ours.clone()]));
+    network
+        .beelay(&peer2)
+        .queue(Event::add_commits(doc_id, vec![theirs]));
+    network.run_until_quiescent();
+
+    assert_eq!(
+        network.beelay(&peer1).pop_rate_limited_streams(),
+        vec![left_to_right]
+    );
+    assert!(!left_closed.load(Ordering::SeqCst));
+    assert_eq!(
+        network
+            .beelay(&peer2)
+            .stream_metrics(right_to_left)
+            .unwrap()
+            .round_trips,
+        answered
+    );
+    let on_peer2 = network.beelay(&peer2).load_doc(doc_id).unwrap();
+    assert!(on_peer2.contains(&CommitOrBundle::Commit(ours)));
+}
+
+#[test]
+fn stream_queue_depth_tracks_unsent_messages() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network
+        .create_peer("peer1")
+        .stream_congestion_threshold(1024)
+        .build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+
+    let (doc_id, initial_commit) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    let contents = vec![7; 4096];
+    let hash = CommitHash::from(blake3::hash(&contents).as_bytes());
+    network
+        .beelay(&peer1)
+        .add_commits(
+            doc_id,
+            vec![Commit::new(vec![initial_commit.hash()], contents, hash)],
+        )
+        .unwrap();
+
+    // Pretend peer1's transport can't keep up so nothing is acknowledged
+    network.beelay(&peer1).hold_stream_acks();
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    let depth = network
+        .beelay(&peer1)
+        .stream_queue_depth(left_to_right)
+        .unwrap();
+    assert!(depth.messages > 0);
+    assert!(depth.bytes > 1024);
+
+    // Congestion is only reported once while the backlog stays high
+    let congested = network.beelay(&peer1).pop_congested_streams();
+    assert_eq!(congested.len(), 1);
+    assert_eq!(congested[0].0, left_to_right);
+    assert!(congested[0].1.bytes > 1024);
+
+    network.beelay(&peer1).release_stream_acks();
+    let depth = network
+        .beelay(&peer1)
+        .stream_queue_depth(left_to_right)
+        .unwrap();
+    assert_eq!(depth, beelay_core::StreamQueueDepth::default());
+    assert!(network.beelay(&peer1).pop_congested_streams().is_empty());
+
+    // Peers without a threshold don't track their queues at all
+    assert_eq!(
+        network.beelay(&peer2).stream_queue_depth(right_to_left),
+        None
+    );
+}
+
+#[test]
+fn request_commits_fetches_from_connected_peers() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+
+    let (doc_id, initial) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    network.connect_stream(&peer2, &peer1);
+
+    // Pausing stops the commits reaching peer2 by way of sync
+    network
+        .beelay(&peer2)
+        .run_command(Event::pause_doc_sync(doc_id));
+    let commit1 = Commit::new(vec![initial.hash()], vec![1], CommitHash::from([1; 32]));
+    let commit2 = Commit::new(vec![commit1.hash()], vec![2], CommitHash::from([2; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit1.clone(), commit2.clone()])
+        .unwrap();
+    network.run_until_quiescent();
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        Some(vec![initial.hash()])
+    );
+
+    let missing = CommitHash::from([3; 32]);
+    let requested = network
+        .beelay(&peer2)
+        .request_commits(doc_id, vec![commit2.hash(), missing, commit1.hash()])
+        .unwrap();
+    assert_eq!(
+        requested,
+        beelay_core::RequestedCommits {
+            fetched: vec![commit2.hash(), commit1.hash()],
+            unfetchable: vec![missing],
+            timed_out: vec![],
+        }
+    );
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        Some(vec![commit2.hash()])
+    );
+
+    assert!(matches!(
+        network.beelay(&peer2).request_commits(
+            beelay_core::DocumentId::random(&mut rand::thread_rng()),
+            vec![commit1.hash()]
+        ),
+        Err(beelay_core::error::RequestCommits::NoSuchDocument)
+    ));
+}
+
+#[test]
+fn paused_docs_are_not_synced() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+
+    let (big_doc, big_initial) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.clone().into()])
+        .unwrap();
+    let (small_doc, small_initial) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    network.connect_stream(&peer2, &peer1);
+
+    let result = network
+        .beelay(&peer2)
+        .run_command(Event::pause_doc_sync(big_doc));
+    assert!(matches!(result, CommandResult::PauseDocSync(true)));
+    assert!(network.beelay(&peer2).doc_status(&big_doc).sync_paused);
+    assert!(!network.beelay(&peer2).doc_status(&small_doc).sync_paused);
+
+    let big_commit = Commit::new(
+        vec![big_initial.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    let small_commit = Commit::new(
+        vec![small_initial.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([2; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(big_doc, vec![big_commit.clone()])
+        .unwrap();
+    network
+        .beelay(&peer1)
+        .add_commits(small_doc, vec![small_commit.clone()])
+        .unwrap();
+    network.run_until_quiescent();
+    // A periodic sync doesn't pick up the paused document either
+    network.advance_time(beelay_core::SYNC_INTERVAL + Duration::from_millis(10));
+
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&small_doc).local_heads,
+        Some(vec![small_commit.hash()])
+    );
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&big_doc).local_heads,
+        Some(vec![big_initial.hash()])
+    );
+
+    let result = network
+        .beelay(&peer2)
+        .run_command(Event::resume_doc_sync(big_doc));
+    assert!(matches!(result, CommandResult::ResumeDocSync(true)));
+    network.run_until_quiescent();
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&big_doc),
+        DocStatus {
+            local_heads: Some(vec![big_commit.hash()]),
+            sync_paused: false
+        }
+    );
+}
+
+#[test]
+fn ephemeral_docs_sync_but_are_not_stored() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let blobs_before = stored_blobs(&mut network, &peer1);
+
+    let initial = Commit::new(vec![], vec![1, 2, 3], CommitHash::from([7; 32]));
+    let result = network
+        .beelay(&peer1)
+        .run_command(Event::create_ephemeral_doc(
+            initial.clone(),
+            vec![peer2_contact.into()],
+        ));
+    let CommandResult::CreateDoc(Ok(doc_id)) = result else {
+        panic!("unexpected result: {:?}", result);
+    };
+    let next = Commit::new(
+        vec![initial.hash()],
+        vec![4, 5, 6],
+        CommitHash::from([8; 32]),
+    );
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![next.clone()])
+        .unwrap();
+
+    let doc_root = beelay_core::StorageKey::sedimentree_root(&doc_id);
+    assert!(!network
+        .beelay(&peer1)
+        .storage()
+        .keys()
+        .any(|k| doc_root.is_prefix_of(k)));
+    assert_eq!(stored_blobs(&mut network, &peer1), blobs_before);
+    let loaded = network.beelay(&peer1).load_doc(doc_id).unwrap();
+    assert_eq!(loaded.len(), 2);
+
+    network.connect_stream(&peer1, &peer2);
+    network.run_until_quiescent();
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        Some(vec![next.hash()])
+    );
+
+    network.reload_peer(&peer1);
+    assert_eq!(network.beelay(&peer1).load_doc(doc_id), None);
+}
+
+fn stored_blobs(network: &mut Network, peer: &beelay_core::PeerId) -> usize {
+    network
+        .beelay(peer)
+        .storage()
+        .keys()
+        .filter(|k| k.namespace() == "blobs")
+        .count()
+}
+
+#[test]
+fn subscribed_docs_notify_when_heads_change() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let (doc_id, initial) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    network.connect_stream(&peer1, &peer2);
+
+    let result = network
+        .beelay(&peer2)
+        .run_command(Event::subscribe_doc(doc_id));
+    assert!(matches!(result, CommandResult::SubscribeDoc(true)));
+    network.beelay(&peer2).pop_notifications();
+
+    let heads_changes = |network: &mut Network| {
+        network
+            .beelay(&peer2)
+            .pop_notifications()
+            .remove(&doc_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|event| match event {
+                DocEvent::HeadsChanged { new_heads } => Some(new_heads),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let first = Commit::new(vec![initial.hash()], vec![1], CommitHash::from([1; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![first.clone()])
+        .unwrap();
+    network.run_until_quiescent();
+    assert_eq!(heads_changes(&mut network), vec![vec![first.hash()]]);
+
+    let result = network
+        .beelay(&peer2)
+        .run_command(Event::unsubscribe_doc(doc_id));
+    assert!(matches!(result, CommandResult::UnsubscribeDoc(true)));
+
+    let second = Commit::new(vec![first.hash()], vec![2], CommitHash::from([2; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![second.clone()])
+        .unwrap();
+    network.run_until_quiescent();
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        Some(vec![second.hash()])
+    );
+    assert!(heads_changes(&mut network).is_empty());
+}
+
+#[test]
+fn group_metadata_is_synced() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let group = network.beelay(&alice).create_group(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_group(AddMemberToGroup {
+            group_id: group,
+            member: KeyhiveEntityId::Individual(bob_contact),
+            access: MemberAccess::Write,
+            expires_at: None,
+        })
+        .unwrap();
+    let metadata = GroupMetadata {
+        name: Some("Editors".to_string()),
+        entries: [("colour".to_string(), "blue".to_string())].into(),
+    };
+    network
+        .beelay(&alice)
+        .set_group_metadata(group, metadata.clone())
+        .unwrap();
+
+    let ConnectedPair {
+        left_to_right: alice_to_bob,
+        ..
+    } = network.connect_stream(&alice, &bob);
+    network.beelay(&alice).disconnect(alice_to_bob);
+    assert_eq!(
+        network.beelay(&bob).get_group_metadata(group).unwrap(),
+        metadata
+    );
+
+    // Bob's edit is made after seeing Alice's so it wins when they next sync
+    let renamed = GroupMetadata {
+        name: Some("Reviewers".to_string()),
+        ..metadata
+    };
+    network
+        .beelay(&bob)
+        .set_group_metadata(group, renamed.clone())
+        .unwrap();
+    network.connect_stream(&alice, &bob);
+    assert_eq!(
+        network.beelay(&alice).get_group_metadata(group).unwrap(),
+        renamed
+    );
+}
+
+#[test]
+fn tombstones_purge_content_on_every_peer() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_doc(doc, bob_contact.into(), MemberAccess::Write);
+
+    let ConnectedPair {
+        left_to_right: bob_to_alice,
+        ..
+    } = network.connect_stream(&bob, &alice);
+    network.beelay(&bob).disconnect(bob_to_alice);
+    assert!(network.beelay(&bob).doc_status(&doc).local_heads.is_some());
+
+    // Only admins can tombstone a document
+    assert!(matches!(
+        network.beelay(&bob).tombstone_doc(doc),
+        Err(beelay_core::error::TombstoneDoc::NotAllowed)
+    ));
+
+    assert!(network.beelay(&alice).tombstone_doc(doc).unwrap() > 0);
+    let load_tombstone = |network: &mut Network, peer| match network
+        .beelay(peer)
+        .run_command(Event::load_doc(doc))
+    {
+        CommandResult::Tombstoned(tombstone) => tombstone,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    let tombstone = load_tombstone(&mut network, &alice);
+    assert_eq!(tombstone.tombstoned_by, alice);
+    assert_eq!(network.beelay(&alice).tombstone_doc(doc).unwrap(), 0);
+    let commit = Commit::new(vec![], vec![1, 2, 3], CommitHash::from([1; 32]));
+    assert!(matches!(
+        network.beelay(&alice).add_commits(doc, vec![commit]),
+        Err(beelay_core::error::AddCommits::Tombstoned)
+    ));
+
+    // The access history of the document is kept
+    let members = network.beelay(&alice).list_doc_members(doc).unwrap();
+    assert!(members.iter().any(|m| match &m.member {
+        KeyhiveEntityId::Individual(card) => card.peer_id() == bob,
+        _ => false,
+    }));
+
+    network.connect_stream(&bob, &alice);
+    assert_eq!(load_tombstone(&mut network, &bob), tombstone);
+    assert_eq!(network.beelay(&bob).doc_status(&doc).local_heads, None);
+    network.reload_peer(&bob);
+    assert_eq!(load_tombstone(&mut network, &bob), tombstone);
+}
+
+#[test]
+fn access_requests_are_granted_or_denied_by_admins() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer3 = network.create_peer("peer3").build();
+
+    let (doc_id, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    network.connect_stream(&peer2, &peer1);
+    network.connect_stream(&peer3, &peer1);
+    network.run_until_quiescent();
+    network.beelay(&peer1).pop_notifications();
+
+    let asked = network
+        .beelay(&peer2)
+        .request_doc_access(doc_id, MemberAccess::Read)
+        .unwrap();
+    assert_eq!(asked, vec![peer1]);
+    let asked = network
+        .beelay(&peer3)
+        .request_doc_access(doc_id, MemberAccess::Write)
+        .unwrap();
+    assert_eq!(asked, vec![peer1]);
+
+    let mut requests = network
+        .beelay(&peer1)
+        .pop_notifications()
+        .remove(&doc_id)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|event| match event {
+            DocEvent::AccessRequested { requester, desired } => Some((requester, desired)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    requests.sort_by_key(|(_, desired)| *desired);
+    assert_eq!(requests.len(), 2);
+    let (peer2_card, peer2_desired) = requests.remove(0);
+    let (peer3_card, peer3_desired) = requests.remove(0);
+    assert_eq!(peer2_card.peer_id(), peer2);
+    assert_eq!(peer2_desired, MemberAccess::Read);
+    assert_eq!(peer3_card.peer_id(), peer3);
+    assert_eq!(peer3_desired, MemberAccess::Write);
+
+    network.beelay(&peer1).add_member_to_doc(
+        doc_id,
+        KeyhiveEntityId::Individual(peer2_card),
+        MemberAccess::Read,
+    );
+    network
+        .beelay(&peer1)
+        .deny_doc_access(doc_id, peer3)
+        .unwrap();
+    network.run_until_quiescent();
+
+    let peer2_events = network
+        .beelay(&peer2)
+        .pop_notifications()
+        .remove(&doc_id)
+        .unwrap_or_default();
+    assert!(peer2_events.contains(&DocEvent::AccessRequestGranted {
+        by: peer1,
+        access: MemberAccess::Read
+    }));
+    let peer3_events = network
+        .beelay(&peer3)
+        .pop_notifications()
+        .remove(&doc_id)
+        .unwrap_or_default();
+    assert_eq!(
+        peer3_events,
+        vec![DocEvent::AccessRequestDenied { by: peer1 }]
+    );
+
+    // Each request is only decided once
+    assert_eq!(
+        network.beelay(&peer1).deny_doc_access(doc_id, peer3),
+        Err(beelay_core::error::DenyDocAccess::NoSuchRequest)
+    );
+    assert!(matches!(
+        network
+            .beelay(&peer1)
+            .request_doc_access(doc_id, MemberAccess::Admin),
+        Err(beelay_core::error::RequestDocAccess::AlreadyHasAccess)
+    ));
+}
+
+#[test]
+fn sealed_docs_accept_no_more_commits_on_any_peer() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc, initial) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_doc(doc, bob_contact.into(), MemberAccess::Write);
+
+    let ConnectedPair {
+        left_to_right: bob_to_alice,
+        ..
+    } = network.connect_stream(&bob, &alice);
+    network.beelay(&bob).disconnect(bob_to_alice);
+
+    // Only admins can seal a document
+    assert!(matches!(
+        network.beelay(&bob).seal_doc(doc),
+        Err(beelay_core::error::SealDoc::NotAllowed)
+    ));
+
+    // A commit bob makes while disconnected never reaches alice
+    let bobs_commit = Commit::new(vec![initial.hash()], vec![1], CommitHash::from([1; 32]));
+    network
+        .beelay(&bob)
+        .add_commits(doc, vec![bobs_commit])
+        .unwrap();
+
+    let seal = network.beelay(&alice).seal_doc(doc).unwrap();
+    assert_eq!(seal.sealed_by, alice);
+    assert_eq!(seal.heads, vec![initial.hash()]);
+    assert_eq!(network.beelay(&alice).seal_doc(doc).unwrap(), seal);
+    let commit = Commit::new(vec![initial.hash()], vec![2], CommitHash::from([2; 32]));
+    assert!(matches!(
+        network
+            .beelay(&alice)
+            .add_commits(doc, vec![commit.clone()]),
+        Err(beelay_core::error::AddCommits::Sealed)
+    ));
+
+    // Alice connecting to bob would fetch his commit were it not sealed
+    network.connect_stream(&alice, &bob);
+    assert_eq!(
+        network.beelay(&alice).doc_status(&doc).local_heads,
+        Some(vec![initial.hash()])
+    );
+    assert!(matches!(
+        network.beelay(&bob).add_commits(doc, vec![commit.clone()]),
+        Err(beelay_core::error::AddCommits::Sealed)
+    ));
+
+    // The document can still be read
+    let loaded = network.beelay(&alice).load_doc(doc).unwrap();
+    assert_eq!(loaded.len(), 1);
+
+    network.reload_peer(&bob);
+    assert!(matches!(
+        network.beelay(&bob).add_commits(doc, vec![commit]),
+        Err(beelay_core::error::AddCommits::Sealed)
+    ));
+}
+
+#[test]
+fn high_sync_priority_lasts_until_the_doc_is_in_sync() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let (doc_id, _) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    network.connect_stream(&peer2, &peer1);
+
+    let prioritize = |network: &mut Network, priority| match network
+        .beelay(&peer2)
+        .run_command(Event::prioritize_doc_sync(doc_id, priority))
+    {
+        CommandResult::PrioritizeDocSync(previous) => previous,
+        other => panic!("unexpected command result: {:?}", other),
+    };
+    assert_eq!(
+        prioritize(&mut network, SyncPriority::High),
+        SyncPriority::Normal
+    );
+    // Raising the priority synced the document, which was already up to date
+    // so it went straight back to normal priority
+    assert_eq!(
+        prioritize(&mut network, SyncPriority::Low),
+        SyncPriority::Normal
+    );
+    network.advance_time(beelay_core::SYNC_INTERVAL + Duration::from_secs(1));
+    assert_eq!(
+        prioritize(&mut network, SyncPriority::Normal),
+        SyncPriority::Low
+    );
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        network.beelay(&peer1).doc_status(&doc_id).local_heads
+    );
+}
+
+#[test]
+fn an_exported_identity_decrypts_docs_on_a_new_device() {
+    init_logging();
+    let alice_key = SigningKey::generate(&mut rand::thread_rng());
+    let bob_key = SigningKey::generate(&mut rand::thread_rng());
+    let mut network = Network::new();
+    let alice = network
+        .create_peer("alice")
+        .signing_key(alice_key.clone())
+        .build();
+    let bob = network
+        .create_peer("bob")
+        .signing_key(bob_key.clone())
+        .build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc, initial) = network.beelay(&alice).create_doc(vec![]).unwrap();
+    network
+        .beelay(&alice)
+        .add_member_to_doc(doc, bob_contact.into(), MemberAccess::Pull);
+    let commit = Commit::new(
+        vec![initial.hash()],
+        vec![1, 2, 3],
+        CommitHash::from([1; 32]),
+    );
+    network
+        .beelay(&alice)
+        .add_commits(doc, vec![commit.clone()])
+        .unwrap();
+    network.connect_stream(&alice, &bob);
+
+    let exported = network.beelay(&alice).export_identity("hunter2").unwrap();
+
+    // A new device with alice's signing key but none of her storage
+    let mut network2 = Network::new();
+    let new_device = network2
+        .create_peer("new_device")
+        .signing_key(alice_key)
+        .build();
+    let bob = network2
+        .create_peer("bob")
+        .storage(network.beelay(&bob).storage().clone())
+        .signing_key(bob_key)
+        .build();
+    let stranger = network2.create_peer("stranger").build();
+
+    assert!(matches!(
+        network2
+            .beelay(&new_device)
+            .import_identity(exported.clone(), "hunter3"),
+        Err(beelay_core::error::ImportIdentity::WrongPassphrase)
+    ));
+    assert!(matches!(
+        network2
+            .beelay(&stranger)
+            .import_identity(exported.clone(), "hunter2"),
+        Err(beelay_core::error::ImportIdentity::WrongIdentity)
+    ));
+    network2
+        .beelay(&new_device)
+        .import_identity(exported, "hunter2")
+        .unwrap();
+
+    network2.connect_stream(&new_device, &bob);
+    let loaded = network2.beelay(&new_device).load_doc(doc).unwrap();
+    assert!(loaded
+        .iter()
+        .any(|c| matches!(c, CommitOrBundle::Commit(c) if c.contents() == [1, 2, 3])));
+
+    // The identity survives a restart
+    network2.reload_peer(&new_device);
+    let loaded = network2.beelay(&new_device).load_doc(doc).unwrap();
+    assert!(loaded
+        .iter()
+        .any(|c| matches!(c, CommitOrBundle::Commit(c) if c.contents() == [1, 2, 3])));
+}
+
+#[test]
+fn access_changes_are_reported_to_subscribers() {
+    init_logging();
+    let mut network = Network::new();
+    let alice = network.create_peer("alice").build();
+    let bob = network.create_peer("bob").build();
+    let bob_contact = network.beelay(&bob).contact_card().unwrap();
+    let (doc_id, _) = network.beelay(&alice).create_doc(vec![]).unwrap();
+
+    let access_changes = |network: &mut Network, peer| {
+        network
+            .beelay(peer)
+            .pop_notifications()
+            .remove(&doc_id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|event| match event {
+                DocEvent::AccessChanged { entity, old, new } => Some((entity, old, new)),
+                _ => None,
+            })
+            .collect::<HashSet<_>>()
+    };
+    for peer in [&alice, &bob] {
+        let result = network
+            .beelay(peer)
+            .run_command(Event::subscribe_access_changes());
+        assert!(matches!(
+            result,
+            CommandResult::SubscribeAccessChanges(true)
+        ));
+        network.beelay(peer).pop_notifications();
+    }
+    let result = network
+        .beelay(&alice)
+        .run_command(Event::subscribe_access_changes());
+    assert!(matches!(
+        result,
+        CommandResult::SubscribeAccessChanges(false)
+    ));
+
+    // A local change is reported straight away
+    network.beelay(&alice).add_member_to_doc(
+        doc_id,
+        bob_contact.clone().into(),
+        MemberAccess::Write,
+    );
+    assert_eq!(
+        access_changes(&mut network, &alice),
+        HashSet::from([(bob, None, Some(MemberAccess::Write))])
+    );
+
+    // Changes which are synced in are reported once they arrive
+    network.connect_stream(&bob, &alice);
+    assert_eq!(
+        access_changes(&mut network, &bob),
+        HashSet::from([
+            (alice, None, Some(MemberAccess::Admin)),
+            (bob, None, Some(MemberAccess::Write)),
+        ])
+    );
+
+    network
+        .beelay(&alice)
+        .change_member_access(doc_id, bob_contact.clone().into(), MemberAccess::Read)
+        .unwrap();
+    assert_eq!(
+        access_changes(&mut network, &alice),
+        HashSet::from([(bob, Some(MemberAccess::Write), Some(MemberAccess::Read))])
+    );
+
+    let result = network
+        .beelay(&alice)
+        .run_command(Event::unsubscribe_access_changes());
+    assert!(matches!(
+        result,
+        CommandResult::UnsubscribeAccessChanges(true)
+    ));
+    network
+        .beelay(&alice)
+        .remove_member_from_doc(doc_id, bob_contact.into())
+        .unwrap();
+    assert_eq!(access_changes(&mut network, &alice), HashSet::new());
+}
+
+#[test]
+fn estimate_sync_counts_what_each_side_is_missing() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let (doc_id, initial) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    let ConnectedPair { right_to_left, .. } = network.connect_stream(&peer1, &peer2);
+    network
+        .beelay(&peer2)
+        .run_command(Event::pause_doc_sync(doc_id));
+
+    // Commits each side makes while sync is paused stay where they are
+    let commit1 = Commit::new(vec![initial.hash()], vec![1; 10], CommitHash::from([1; 32]));
+    let commit2 = Commit::new(vec![commit1.hash()], vec![2; 10], CommitHash::from([2; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit1, commit2])
+        .unwrap();
+    let commit3 = Commit::new(vec![initial.hash()], vec![3; 10], CommitHash::from([3; 32]));
+    network
+        .beelay(&peer2)
+        .add_commits(doc_id, vec![commit3.clone()])
+        .unwrap();
+    network.run_until_quiescent();
+    let heads_before = network.beelay(&peer2).doc_status(&doc_id).local_heads;
+
+    let unknown_doc = beelay_core::DocumentId::random(&mut rand::thread_rng());
+    let CommandResult::EstimateSync(estimate) = network.beelay(&peer2).run_command(
+        Event::estimate_sync(right_to_left, vec![doc_id, unknown_doc]),
+    ) else {
+        panic!("unexpected command result");
+    };
+    let estimate = estimate.unwrap();
+    assert_eq!(estimate.download.commits, 2);
+    assert_eq!(estimate.download.bundles, 0);
+    assert!(estimate.download.bytes > 20);
+    assert_eq!(estimate.upload.commits, 1);
+    assert!(estimate.upload.bytes > 10);
+    assert!(estimate.upload.bytes < estimate.download.bytes);
+    assert_eq!(estimate.unavailable, vec![unknown_doc]);
+
+    // Nothing was transferred
+    assert_eq!(
+        network.beelay(&peer2).doc_status(&doc_id).local_heads,
+        heads_before
+    );
+    assert!(!network
+        .beelay(&peer1)
+        .load_doc(doc_id)
+        .unwrap()
+        .iter()
+        .any(|c| matches!(c, CommitOrBundle::Commit(c) if c.hash() == commit3.hash())));
+
+    network.beelay(&peer2).disconnect(right_to_left);
+    let result = network
+        .beelay(&peer2)
+        .run_command(Event::estimate_sync(right_to_left, vec![doc_id]));
+    assert!(matches!(
+        result,
+        CommandResult::EstimateSync(Err(beelay_core::error::EstimateSync::NoSuchStream))
+    ));
+}
+
+#[test]
+fn discover_docs_lists_only_docs_we_can_pull() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let (shared, _) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.clone().into()])
+        .unwrap();
+    let (paused, _) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    network
+        .beelay(&peer1)
+        .run_command(Event::pause_doc_sync(paused));
+    let (_private, _) = network.beelay(&peer1).create_doc(vec![]).unwrap();
+    let ConnectedPair { right_to_left, .. } = network.connect_stream(&peer1, &peer2);
+
+    let CommandResult::DiscoverDocs(docs) = network
+        .beelay(&peer2)
+        .run_command(Event::discover_docs(right_to_left))
+    else {
+        panic!("unexpected command result");
+    };
+    assert_eq!(docs.unwrap(), vec![shared]);
+
+    let result = network
+        .beelay(&peer2)
+        .run_cancelled(Event::discover_docs(right_to_left));
+    assert!(matches!(result, CommandResult::Cancelled));
+
+    network.beelay(&peer2).disconnect(right_to_left);
+    let result = network
+        .beelay(&peer2)
+        .run_command(Event::discover_docs(right_to_left));
+    assert!(matches!(
+        result,
+        CommandResult::DiscoverDocs(Err(beelay_core::error::DiscoverDocs::NoSuchStream))
+    ));
+}
+
+#[test]
+fn capabilities_authorize_fetches_until_revoked_or_expired() {
+    init_logging();
+    let mut network = Network::new();
+    let issuer = network.create_peer("issuer").build();
+    let holder = network.create_peer("holder").build();
+    let (doc_id, initial_commit) = network.beelay(&issuer).create_doc(vec![]).unwrap();
+
+    let expires_at = beelay_core::UnixTimestamp::now() + Duration::from_secs(60);
+    let CommandResult::MintCapability(Ok(capability)) = network.beelay(&issuer).run_command(
+        Event::mint_capability(doc_id, MemberAccess::Read, expires_at),
+    ) else {
+        panic!("expected a capability");
+    };
+    // The holder receives the capability as bytes
+    let capability = beelay_core::Capability::from_bytes(&capability.to_bytes()).unwrap();
+    assert_eq!(capability.doc_id(), doc_id);
+    assert_eq!(capability.issued_by(), issuer);
+    let endpoint = network
+        .beelay(&holder)
+        .register_capability_endpoint(&issuer, capability.clone());
+
+    let result = network
+        .beelay(&holder)
+        .run_command(Event::fetch_with_capability(endpoint));
+    assert!(matches!(result, CommandResult::FetchWithCapability(Ok(()))));
+    let CommandResult::LoadDoc(Some(items)) = network
+        .beelay(&holder)
+        .run_command(Event::load_doc_encrypted(doc_id))
+    else {
+        panic!("expected the fetched commits");
+    };
+    assert!(items.iter().any(|(item, _)| matches!(
+        item,
+        CommitOrBundle::Commit(c) if c.hash() == initial_commit.hash()
+    )));
+
+    let result = network
+        .beelay(&issuer)
+        .run_command(Event::revoke_capability(capability.id()));
+    assert!(matches!(result, CommandResult::RevokeCapability(true)));
+    let result = network
+        .beelay(&issuer)
+        .run_command(Event::revoke_capability(capability.id()));
+    assert!(matches!(result, CommandResult::RevokeCapability(false)));
+    network.beelay(&issuer).take_request_failures();
+    let result = network
+        .beelay(&holder)
+        .run_command(Event::fetch_with_capability(endpoint));
+    assert!(matches!(
+        result,
+        CommandResult::FetchWithCapability(Err(beelay_core::error::FetchWithCapability::Rejected))
+    ));
+    assert_eq!(
+        network.beelay(&issuer).take_request_failures(),
+        vec![beelay_core::error::RequestFailure::InvalidCapability {
+            id: capability.id(),
+            reason: beelay_core::error::CapabilityRejected::Revoked,
+        }]
+    );
+
+    let CommandResult::MintCapability(Ok(short_lived)) = network.beelay(&issuer).run_command(
+        Event::mint_capability(doc_id, MemberAccess::Read, expires_at),
+    ) else {
+        panic!("expected a capability");
+    };
+    let endpoint = network
+        .beelay(&holder)
+        .register_capability_endpoint(&issuer, short_lived.clone());
+    for peer in [&issuer, &holder] {
+        network.beelay(peer).advance_time(Duration::from_secs(61));
+    }
+    let result = network
+        .beelay(&holder)
+        .run_command(Event::fetch_with_capability(endpoint));
+    assert!(matches!(
+        result,
+        CommandResult::FetchWithCapability(Err(beelay_core::error::FetchWithCapability::Rejected))
+    ));
+    assert_eq!(
+        network.beelay(&issuer).take_request_failures(),
+        vec![beelay_core::error::RequestFailure::InvalidCapability {
+            id: short_lived.id(),
+            reason: beelay_core::error::CapabilityRejected::Expired,
+        }]
+    );
+
+    let result = network.beelay(&holder).run_command(Event::mint_capability(
+        doc_id,
+        MemberAccess::Read,
+        expires_at,
+    ));
+    assert!(matches!(
+        result,
+        CommandResult::MintCapability(Err(beelay_core::error::MintCapability::NoSuchDocument))
+    ));
+}
+
+#[test]
+fn reordered_stream_messages_are_processed_in_order() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    // Gives up on any message which is overtaken by a later one
+    let peer3 = network
+        .create_peer("peer3")
+        .stream_reorder_window(0)
+        .build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let peer3_contact = network.beelay(&peer3).contact_card().unwrap();
+    let mut expected = HashMap::<_, Vec<_>>::new();
+    for (peer, contact) in [(peer2, peer2_contact), (peer3, peer3_contact)] {
+        for _ in 0..3 {
+            let (doc_id, initial_commit) = network
+                .beelay(&peer1)
+                .create_doc(vec![contact.clone().into()])
+                .unwrap();
+            expected
+                .entry(peer)
+                .or_default()
+                .push((doc_id, initial_commit));
+        }
+    }
+
+    // Syncing several documents at once puts several requests in flight, which
+    // then arrive in the reverse of the order they were sent in
+    network.reorder_messages(true);
+    let ConnectedPair {
+        left_to_right: peer2_stream,
+        ..
+    } = network.connect_stream(&peer2, &peer1);
+    let ConnectedPair {
+        left_to_right: peer3_stream,
+        ..
+    } = network.connect_stream(&peer3, &peer1);
+
+    for (peer, docs) in expected {
+        for (doc_id, initial_commit) in docs {
+            let loaded = network.beelay(&peer).load_doc(doc_id).unwrap();
+            assert_eq!(loaded, vec![CommitOrBundle::Commit(initial_commit)]);
+        }
+    }
+    let metrics = network.beelay(&peer2).stream_metrics(peer2_stream).unwrap();
+    assert_eq!(metrics.resyncs, 0);
+    // Every gap made peer3 start over, but it still caught up
+    let metrics = network.beelay(&peer3).stream_metrics(peer3_stream).unwrap();
+    assert!(metrics.resyncs > 0);
+}
+
+#[test]
+fn resync_stream_redoes_the_handshake_without_closing_the_stream() {
+    init_logging();
+    let mut network = Network::new();
+    let peer1 = network.create_peer("peer1").build();
+    let peer2 = network.create_peer("peer2").build();
+    let peer2_contact = network.beelay(&peer2).contact_card().unwrap();
+    let (doc_id, initial) = network
+        .beelay(&peer1)
+        .create_doc(vec![peer2_contact.into()])
+        .unwrap();
+    let ConnectedPair {
+        left_to_right,
+        right_to_left,
+        ..
+    } = network.connect_stream(&peer1, &peer2);
+    assert!(network.beelay(&peer2).load_doc(doc_id).is_some());
+
+    network.beelay(&peer2).resync_stream(right_to_left).unwrap();
+    for (peer, stream_id) in [(&peer1, left_to_right), (&peer2, right_to_left)] {
+        let streams = network.beelay(peer).list_streams();
+        let (_, _, state) = streams.iter().find(|(id, _, _)| *id == stream_id).unwrap();
+        assert_eq!(*state, beelay_core::StreamState::Established);
+    }
+
+    // The stream keeps syncing after the new handshake
+    let commit = Commit::new(vec![initial.hash()], vec![1; 10], CommitHash::from([1; 32]));
+    network
+        .beelay(&peer1)
+        .add_commits(doc_id, vec![commit.clone()])
+        .unwrap();
+    network.run_until_quiescent();
+    assert!(network
+        .beelay(&peer2)
+        .load_doc(doc_id)
+        .unwrap()
+        .iter()
+        .any(|c| matches!(c, CommitOrBundle::Commit(c) if c.hash() == commit.hash())));
+
+    network.beelay(&peer2).disconnect(right_to_left);
+    assert!(matches!(
+        network.beelay(&peer2).resync_stream(right_to_left),
+        Err(beelay_core::error::ResyncStream::NoSuchStream)
+    ));
+}
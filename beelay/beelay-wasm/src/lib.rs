@@ -229,6 +229,7 @@ impl Beelay {
                     }
                     loading
                 }
+                loading::Step::Failed(err) => return Err(JsError::new(&err.to_string())),
             };
             let next_result = running_tasks.select_next_some().await?;
             step = loading.handle_io_complete(now(), next_result);
@@ -434,6 +435,7 @@ impl Beelay {
                     access: args.access.into(),
                     group_id,
                     member: args.member.into(),
+                    expires_at: None,
                 });
                 let (tx, rx) = oneshot::channel();
                 self.inner
@@ -585,9 +587,9 @@ impl Beelay {
         match rx.await {
             Ok(result) => match result {
                 CommandResult::AddCommits(bundle_specs) => match bundle_specs {
-                    Ok(bundle_specs) => {
+                    Ok(added) => {
                         let result = Array::new();
-                        for bundle_spec in bundle_specs {
+                        for bundle_spec in added.new_bundles {
                             let bundle =
                                 serde_wasm_bindgen::to_value(&JsBundleSpec::from(bundle_spec))
                                     .unwrap();
@@ -636,7 +638,7 @@ impl Beelay {
         match rx.await {
             Ok(result) => match result {
                 CommandResult::AddBundle(result) => match result {
-                    Ok(()) => Ok(()),
+                    Ok(_) => Ok(()),
                     Err(err) => Err(JsError::new(&format!("Failed to add bundle: {}", err))),
                 },
                 _ => Err(JsError::new("Unexpected command result")),
@@ -703,7 +705,7 @@ impl Beelay {
             CommandResult::LoadDoc(result) => {
                 if let Some(result) = result {
                     let arr = js_sys::Array::new();
-                    for item in result {
+                    for (item, _) in result {
                         let item =
                             serde_wasm_bindgen::to_value(&JsCommitOrBundle::from(item)).unwrap();
                         arr.push(&item);
@@ -946,7 +948,13 @@ impl Beelay {
                                     Reflect::set(&js_event, &"type".into(), &"discovered".into());
                                 let _ = Reflect::set(&js_arg, &"event".into(), &js_event);
                             }
-                            DocEvent::AccessChanged { .. } => {
+                            DocEvent::AccessChanged { .. }
+                            | DocEvent::HeadsChanged { .. }
+                            | DocEvent::Diverged { .. }
+                            | DocEvent::Bundled { .. }
+                            | DocEvent::AccessRequested { .. }
+                            | DocEvent::AccessRequestGranted { .. }
+                            | DocEvent::AccessRequestDenied { .. } => {
                                 continue;
                             }
                         }
@@ -170,7 +170,16 @@ impl Cgka {
             op = Some(update_op);
             pcs_key
         } else {
-            self.pcs_key_from_tree_root()?
+            let pcs_key = self.pcs_key_from_tree_root()?;
+            if !self.pcs_key_ops.contains_key(&Digest::hash(&pcs_key)) {
+                // We can derive the root key from operations we have received
+                // but haven't used it yet, so associate it with the head
+                // operation in the same way as `rebuild_pcs_key` does.
+                if let Some(head) = self.ops_graph.cgka_op_heads.iter().next().copied() {
+                    self.insert_pcs_key(&pcs_key, head);
+                }
+            }
+            pcs_key
         };
         let pcs_key_hash = Digest::hash(&current_pcs_key);
         let nonce = Siv::new(&current_pcs_key.into(), content, self.doc_id)
@@ -22,9 +22,10 @@ use crate::{
         active::Active,
         agent::{id::AgentId, Agent},
         document::{
-            id::DocumentId, AddMemberError, AddMemberUpdate, DecryptError,
-            DocCausalDecryptionError, Document, EncryptError, EncryptedContentWithUpdate,
-            GenerateDocError, MissingIndividualError, RevokeMemberUpdate,
+            id::DocumentId, AddMemberError, AddMemberUpdate, ChangeMemberAccessUpdate,
+            DecryptError, DocCausalDecryptionError, Document, EncryptError,
+            EncryptedContentWithUpdate, GenerateDocError, MissingIndividualError,
+            RevokeMemberUpdate,
         },
         group::{
             delegation::{Delegation, StaticDelegation},
@@ -32,7 +33,7 @@ use crate::{
             id::GroupId,
             membership_operation::{MembershipOperation, StaticMembershipOperation},
             revocation::{Revocation, StaticRevocation},
-            Group, IdOrIndividual, RevokeMemberError,
+            ChangeMemberAccessError, Group, IdOrIndividual, RevokeMemberError,
         },
         identifier::Identifier,
         individual::{
@@ -176,6 +177,12 @@ impl<
         self.active.borrow().individual().clone()
     }
 
+    /// Every [`Individual`] this Keyhive knows about, including [`Public`].
+    #[instrument(skip(self), fields(khid = %self.id()))]
+    pub fn individuals(&self) -> &HashMap<IndividualId, Rc<RefCell<Individual>>> {
+        &self.individuals
+    }
+
     #[allow(clippy::type_complexity)]
     #[instrument(skip(self), fields(khid = %self.id()))]
     pub fn groups(&self) -> &HashMap<GroupId, Rc<RefCell<Group<S, T, L>>>> {
@@ -434,6 +441,27 @@ impl<
             .await
     }
 
+    /// Change the access of an existing member of `resource` without
+    /// revoking and re-adding them
+    #[allow(clippy::type_complexity)]
+    #[instrument(level = "debug", skip(self), fields(khid = %self.id()))]
+    pub async fn change_member_access(
+        &mut self,
+        member_id: Identifier,
+        can: Access,
+        resource: &mut Membered<S, T, L>,
+    ) -> Result<ChangeMemberAccessUpdate<S, T, L>, ChangeMemberAccessError> {
+        let mut relevant_docs = BTreeMap::new();
+        for (doc_id, Ability { doc, .. }) in self.reachable_docs() {
+            relevant_docs.insert(doc_id, doc.borrow().content_heads.iter().cloned().collect());
+        }
+
+        let signer = self.active.borrow().signer.clone();
+        resource
+            .change_member_access(member_id, can, &signer, &relevant_docs)
+            .await
+    }
+
     #[allow(clippy::await_holding_refcell_ref)] // FIXME
     #[instrument(skip_all, fields(khid = %self.id(), doc_id = %doc.borrow().id(), content_ref))]
     pub async fn try_encrypt_content(
@@ -2093,6 +2121,53 @@ mod tests {
         assert_eq!(dlg.delegation.subject_id(), doc.borrow().doc_id().into());
     }
 
+    #[allow(clippy::await_holding_refcell_ref)] // FIXME
+    #[tokio::test]
+    async fn test_change_member_access() {
+        test_utils::init_logging();
+
+        let mut alice = make_keyhive().await;
+        let bob = make_keyhive().await;
+        let bob_on_alice = Rc::new(RefCell::new(bob.active().borrow().individual.clone()));
+        assert!(alice.register_individual(bob_on_alice.dupe()));
+        let bob_id = bob.active().borrow().id().into();
+
+        let doc = alice
+            .generate_doc(vec![], nonempty![[0u8; 32]])
+            .await
+            .unwrap();
+        alice
+            .add_member(
+                bob_on_alice.dupe().into(),
+                &mut doc.dupe().into(),
+                Access::Write,
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let update = alice
+            .change_member_access(bob_id, Access::Read, &mut doc.dupe().into())
+            .await
+            .unwrap();
+        assert_eq!(update.previous, Access::Write);
+        assert_eq!(update.revocations.len(), 1);
+        // Bob is still a reader so his place in the CGKA is untouched
+        assert!(update.cgka_ops.is_empty());
+        let dlgs = doc.borrow().members().get(&bob_id).cloned().unwrap();
+        assert!(dlgs.iter().all(|dlg| dlg.payload().can == Access::Read));
+
+        let charlie = make_keyhive().await;
+        let result = alice
+            .change_member_access(
+                charlie.active().borrow().id().into(),
+                Access::Read,
+                &mut doc.dupe().into(),
+            )
+            .await;
+        assert!(matches!(result, Err(ChangeMemberAccessError::NotAMember)));
+    }
+
     #[allow(clippy::await_holding_refcell_ref)] // FIXME
     #[tokio::test]
     async fn receiving_an_event_with_added_or_rotated_prekeys_works() {
@@ -31,7 +31,7 @@ use crate::{
             delegation::{Delegation, DelegationError},
             error::AddError,
             revocation::Revocation,
-            Group, RevokeMemberError,
+            ChangeMemberAccessError, Group, RevokeMemberError,
         },
         identifier::Identifier,
     },
@@ -322,6 +322,39 @@ impl<S: AsyncSigner, T: ContentRef, L: MembershipListener<S, T>> Document<S, T,
         })
     }
 
+    /// Change the access `member_id` has to this document in place, see
+    /// [`Group::change_member_access`]
+    #[instrument(skip(self, signer, after_other_doc_content), fields(doc_id = ?self.doc_id()))]
+    pub async fn change_member_access(
+        &mut self,
+        member_id: Identifier,
+        can: Access,
+        signer: &S,
+        after_other_doc_content: &BTreeMap<DocumentId, Vec<T>>,
+    ) -> Result<ChangeMemberAccessUpdate<S, T, L>, ChangeMemberAccessError> {
+        let mut after_content = after_other_doc_content.clone();
+        after_content.insert(self.doc_id(), self.content_state.iter().cloned().collect());
+
+        let mut update = self
+            .group
+            .change_member_access(member_id, can, signer, &after_content)
+            .await?;
+
+        // As with `add_member` the group only manages the CGKAs of its
+        // transitive documents so we handle our own CGKA here
+        if can.is_reader() {
+            let ops = self.add_cgka_member(&update.delegation, signer).await?;
+            update.cgka_ops.extend(ops);
+        } else if update.previous.is_reader() {
+            for id in update.delegation.payload.delegate.individual_ids() {
+                if let Some(op) = self.cgka_mut()?.remove(id, signer).await? {
+                    update.cgka_ops.push(op);
+                }
+            }
+        }
+        Ok(update)
+    }
+
     #[instrument(skip(self, signer), fields(doc_id = ?self.doc_id()))]
     pub async fn remove_cgka_member(
         &mut self,
@@ -588,6 +621,19 @@ pub struct AddMemberUpdate<
     pub cgka_ops: Vec<Signed<CgkaOperation>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeMemberAccessUpdate<
+    S: AsyncSigner,
+    T: ContentRef = [u8; 32],
+    L: MembershipListener<S, T> = NoListener,
+> {
+    /// The highest access the member had before the change
+    pub previous: Access,
+    pub delegation: Rc<Signed<Delegation<S, T, L>>>,
+    pub revocations: Vec<Rc<Signed<Revocation<S, T, L>>>>,
+    pub cgka_ops: Vec<Signed<CgkaOperation>>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Error)]
 #[error("Missing individual: {0}")]
 pub struct MissingIndividualError(pub Box<IndividualId>);
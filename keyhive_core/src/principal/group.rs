@@ -16,7 +16,9 @@ use self::{
 };
 use super::{
     agent::{id::AgentId, Agent},
-    document::{id::DocumentId, AddMemberUpdate, Document, RevokeMemberUpdate},
+    document::{
+        id::DocumentId, AddMemberUpdate, ChangeMemberAccessUpdate, Document, RevokeMemberUpdate,
+    },
     identifier::Identifier,
     individual::{id::IndividualId, Individual},
     membered::Membered,
@@ -522,7 +524,6 @@ impl<S: AsyncSigner, T: ContentRef, L: MembershipListener<S, T>> Group<S, T, L>
         signer: &S,
         after_content: &BTreeMap<DocumentId, Vec<T>>,
     ) -> Result<RevokeMemberUpdate<S, T, L>, RevokeMemberError> {
-        let vk = signer.verifying_key();
         let mut revocations = vec![];
         let og_dlgs: Vec<_> = self.members.values().flatten().cloned().collect();
 
@@ -537,85 +538,11 @@ impl<S: AsyncSigner, T: ContentRef, L: MembershipListener<S, T>> Group<S, T, L>
             return Ok(RevokeMemberUpdate::default());
         }
 
-        if vk == self.verifying_key() {
-            // In the (unlikely) case that the group signing key still exists and is doing the revocation.
-            // Arguably this could be made impossible, but it would likely be surprising behaviour.
-            for to_revoke in all_to_revoke.iter() {
-                let r = self
-                    .build_revocation(signer, to_revoke.dupe(), None, after_content.clone())
-                    .await?;
-                self.receive_revocation(r.dupe()).await?;
-                revocations.push(r);
-            }
-        } else {
-            for to_revoke in all_to_revoke.iter() {
-                let mut found = false;
-
-                if let Some(member_dlgs) = self.members.get(&vk.into()) {
-                    // "Double up" if you're an admin in case you get concurrently demoted.
-                    // We include the admin proofs as well since those could also get revoked.
-                    for mem_dlg in member_dlgs.clone().iter() {
-                        if mem_dlg.payload.delegate.id() != member_to_remove {
-                            continue;
-                        }
-
-                        if mem_dlg.payload().can == Access::Admin {
-                            // Use your awesome & terrible admin powers!
-                            //
-                            // NOTE we don't do admin revocation cycle checking here for a few reasons:
-                            // 1. Unknown to you, the cycle may be broken with some other revocation
-                            // 2. It all gets resolved at materialization time
-                            let r = self
-                                .build_revocation(
-                                    signer,
-                                    to_revoke.dupe(),
-                                    Some(mem_dlg.dupe()), // Admin proof
-                                    after_content.clone(),
-                                )
-                                .await?;
-                            self.receive_revocation(r.dupe()).await?;
-                            revocations.push(r);
-                            found = true;
-                        }
-                    }
-                }
-
-                if to_revoke.issuer == vk {
-                    let r = self
-                        .build_revocation(
-                            signer,
-                            to_revoke.dupe(),
-                            Some(to_revoke.dupe()), // You issued it!
-                            after_content.clone(),
-                        )
-                        .await?;
-                    self.receive_revocation(r.dupe()).await?;
-                    revocations.push(r);
-                    found = true;
-                } else {
-                    // Look for proof of any ancestor
-                    for ancestor in to_revoke.payload().proof_lineage() {
-                        if ancestor.issuer == vk {
-                            found = true;
-                            let r = self
-                                .build_revocation(
-                                    signer,
-                                    to_revoke.dupe(),
-                                    Some(ancestor.dupe()),
-                                    after_content.clone(),
-                                )
-                                .await?;
-                            revocations.push(r.dupe());
-                            self.receive_revocation(r).await?;
-                            break;
-                        }
-                    }
-                }
-
-                if !found {
-                    return Err(RevokeMemberError::NoProof);
-                }
-            }
+        for to_revoke in all_to_revoke.iter() {
+            let rs = self
+                .revoke_delegation(to_revoke.dupe(), signer, after_content)
+                .await?;
+            revocations.extend(rs);
         }
 
         for r in revocations.iter() {
@@ -684,6 +611,188 @@ impl<S: AsyncSigner, T: ContentRef, L: MembershipListener<S, T>> Group<S, T, L>
         })
     }
 
+    /// Change the access `member_id` has to this group in place
+    ///
+    /// The new delegation is issued before the member's existing delegations
+    /// are revoked, so a member who stays a reader keeps their place in the
+    /// CGKA of any transitive documents. Members who drop below
+    /// [`Access::Read`] are removed from those CGKAs.
+    #[allow(clippy::type_complexity)]
+    #[instrument(skip(self, signer, after_content), fields(group_id = %self.group_id()))]
+    pub async fn change_member_access(
+        &mut self,
+        member_id: Identifier,
+        can: Access,
+        signer: &S,
+        after_content: &BTreeMap<DocumentId, Vec<T>>,
+    ) -> Result<ChangeMemberAccessUpdate<S, T, L>, ChangeMemberAccessError> {
+        let old_dlgs: Vec<Rc<Signed<Delegation<S, T, L>>>> = self
+            .members()
+            .get(&member_id)
+            .map(|ne| Vec::<_>::from(ne.clone()))
+            .ok_or(ChangeMemberAccessError::NotAMember)?;
+
+        let previous = old_dlgs
+            .iter()
+            .map(|dlg| dlg.payload.can)
+            .max()
+            .expect("members always have at least one delegation");
+        let member = old_dlgs[0].payload.delegate.dupe();
+
+        let AddMemberUpdate {
+            delegation,
+            mut cgka_ops,
+        } = self
+            .add_member_with_manual_content(member.dupe(), can, signer, after_content.clone())
+            .await?;
+
+        let mut revocations = vec![];
+        for dlg in old_dlgs {
+            let rs = self.revoke_delegation(dlg, signer, after_content).await?;
+            revocations.extend(rs);
+        }
+        for r in revocations.iter() {
+            self.listener.on_revocation(r).await
+        }
+
+        if previous.is_reader() && !can.is_reader() {
+            let ops = self.remove_cgka_member(&member, signer).await?;
+            cgka_ops.extend(ops);
+        }
+
+        Ok(ChangeMemberAccessUpdate {
+            previous,
+            delegation,
+            revocations,
+            cgka_ops,
+        })
+    }
+
+    /// Remove every individual in `member` from the CGKA of each transitive
+    /// document member
+    #[allow(clippy::await_holding_refcell_ref)] // FIXME
+    async fn remove_cgka_member(
+        &mut self,
+        member: &Agent<S, T, L>,
+        signer: &S,
+    ) -> Result<Vec<Signed<CgkaOperation>>, CgkaError> {
+        let docs: Vec<Rc<RefCell<Document<S, T, L>>>> = self
+            .transitive_members()
+            .values()
+            .filter_map(|(agent, _)| {
+                if let Agent::Document(doc) = agent {
+                    Some(doc.dupe())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let mut cgka_ops = Vec::new();
+        for id in member.individual_ids() {
+            for doc in &docs {
+                if let Some(op) = doc.borrow_mut().remove_cgka_member(id, signer).await? {
+                    cgka_ops.push(op);
+                }
+            }
+        }
+        Ok(cgka_ops)
+    }
+
+    /// Revoke a single delegation using whichever proof `signer` has
+    /// to authorize it, returning the revocations which were issued
+    #[allow(clippy::type_complexity)]
+    async fn revoke_delegation(
+        &mut self,
+        to_revoke: Rc<Signed<Delegation<S, T, L>>>,
+        signer: &S,
+        after_content: &BTreeMap<DocumentId, Vec<T>>,
+    ) -> Result<Vec<Rc<Signed<Revocation<S, T, L>>>>, RevokeMemberError> {
+        let vk = signer.verifying_key();
+        let member_to_remove = to_revoke.payload.delegate.id();
+        let mut revocations = vec![];
+
+        if vk == self.verifying_key() {
+            // In the (unlikely) case that the group signing key still exists and is doing the revocation.
+            // Arguably this could be made impossible, but it would likely be surprising behaviour.
+            let r = self
+                .build_revocation(signer, to_revoke.dupe(), None, after_content.clone())
+                .await?;
+            self.receive_revocation(r.dupe()).await?;
+            revocations.push(r);
+            return Ok(revocations);
+        }
+
+        let mut found = false;
+
+        if let Some(member_dlgs) = self.members.get(&vk.into()) {
+            // "Double up" if you're an admin in case you get concurrently demoted.
+            // We include the admin proofs as well since those could also get revoked.
+            for mem_dlg in member_dlgs.clone().iter() {
+                if mem_dlg.payload.delegate.id() != member_to_remove {
+                    continue;
+                }
+
+                if mem_dlg.payload().can == Access::Admin {
+                    // Use your awesome & terrible admin powers!
+                    //
+                    // NOTE we don't do admin revocation cycle checking here for a few reasons:
+                    // 1. Unknown to you, the cycle may be broken with some other revocation
+                    // 2. It all gets resolved at materialization time
+                    let r = self
+                        .build_revocation(
+                            signer,
+                            to_revoke.dupe(),
+                            Some(mem_dlg.dupe()), // Admin proof
+                            after_content.clone(),
+                        )
+                        .await?;
+                    self.receive_revocation(r.dupe()).await?;
+                    revocations.push(r);
+                    found = true;
+                }
+            }
+        }
+
+        if to_revoke.issuer == vk {
+            let r = self
+                .build_revocation(
+                    signer,
+                    to_revoke.dupe(),
+                    Some(to_revoke.dupe()), // You issued it!
+                    after_content.clone(),
+                )
+                .await?;
+            self.receive_revocation(r.dupe()).await?;
+            revocations.push(r);
+            found = true;
+        } else {
+            // Look for proof of any ancestor
+            for ancestor in to_revoke.payload().proof_lineage() {
+                if ancestor.issuer == vk {
+                    found = true;
+                    let r = self
+                        .build_revocation(
+                            signer,
+                            to_revoke.dupe(),
+                            Some(ancestor.dupe()),
+                            after_content.clone(),
+                        )
+                        .await?;
+                    revocations.push(r.dupe());
+                    self.receive_revocation(r).await?;
+                    break;
+                }
+            }
+        }
+
+        if !found {
+            return Err(RevokeMemberError::NoProof);
+        }
+
+        Ok(revocations)
+    }
+
     async fn build_revocation(
         &mut self,
         signer: &S,
@@ -931,6 +1040,21 @@ pub enum RevokeMemberError {
     RedelegationError(#[from] AddGroupMemberError),
 }
 
+#[derive(Debug, Error)]
+pub enum ChangeMemberAccessError {
+    #[error("Agent is not a member")]
+    NotAMember,
+
+    #[error(transparent)]
+    AddMemberError(#[from] AddGroupMemberError),
+
+    #[error(transparent)]
+    RevokeMemberError(#[from] RevokeMemberError),
+
+    #[error(transparent)]
+    CgkaError(#[from] CgkaError),
+}
+
 #[cfg(test)]
 mod tests {
     use super::delegation::Delegation;
@@ -124,6 +124,11 @@ impl Individual {
         self.prekeys.iter().nth(idx).expect("index to be in range")
     }
 
+    /// The [`ShareKey`] pre-keys currently available to invite this `Individual` with.
+    pub fn prekeys(&self) -> &HashSet<ShareKey> {
+        &self.prekeys
+    }
+
     pub fn prekey_ops(&self) -> &CaMap<KeyOp> {
         self.prekey_state.ops()
     }
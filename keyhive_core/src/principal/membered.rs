@@ -2,9 +2,13 @@ pub mod id;
 
 use super::{
     agent::{id::AgentId, Agent},
-    document::{id::DocumentId, AddMemberError, AddMemberUpdate, Document, RevokeMemberUpdate},
+    document::{
+        id::DocumentId, AddMemberError, AddMemberUpdate, ChangeMemberAccessUpdate, Document,
+        RevokeMemberUpdate,
+    },
     group::{
-        delegation::Delegation, error::AddError, revocation::Revocation, Group, RevokeMemberError,
+        delegation::Delegation, error::AddError, revocation::Revocation, ChangeMemberAccessError,
+        Group, RevokeMemberError,
     },
     identifier::Identifier,
 };
@@ -131,6 +135,31 @@ impl<S: AsyncSigner, T: ContentRef, L: MembershipListener<S, T>> Membered<S, T,
         }
     }
 
+    #[allow(clippy::await_holding_refcell_ref)] // FIXME
+    #[allow(clippy::type_complexity)]
+    pub async fn change_member_access(
+        &mut self,
+        member_id: Identifier,
+        can: Access,
+        signer: &S,
+        relevant_docs: &BTreeMap<DocumentId, Vec<T>>,
+    ) -> Result<ChangeMemberAccessUpdate<S, T, L>, ChangeMemberAccessError> {
+        match self {
+            Membered::Group(group) => {
+                group
+                    .borrow_mut()
+                    .change_member_access(member_id, can, signer, relevant_docs)
+                    .await
+            }
+            Membered::Document(document) => {
+                document
+                    .borrow_mut()
+                    .change_member_access(member_id, can, signer, relevant_docs)
+                    .await
+            }
+        }
+    }
+
     pub fn get_agent_revocations(
         &self,
         agent: &Agent<S, T, L>,